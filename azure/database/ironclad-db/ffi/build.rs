@@ -0,0 +1,11 @@
+//! Regenerate include/ironclad.h from src/lib.rs (see cbindgen.toml)
+
+fn main() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).expect("reading cbindgen.toml");
+    cbindgen::generate_with_config(&crate_dir, config)
+        .expect("generating the C header")
+        .write_to_file(format!("{}/include/ironclad.h", crate_dir));
+}
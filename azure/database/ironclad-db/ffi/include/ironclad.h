@@ -0,0 +1,145 @@
+/*
+ * IronClad C API (see src/lib.rs)
+ *
+ * Build the library with `cargo build --release` in ffi/, then link against
+ * libironclad (.so / .dylib / .a).
+ *
+ * Every call returns an IroncladStatus; on failure, ironclad_last_error()
+ * describes it (per thread, valid until the thread's next call). Strings are
+ * NUL-terminated UTF-8; free strings the library returns with
+ * ironclad_free_string(). Calls block; a handle may be shared between threads.
+ */
+
+#ifndef IRONCLAD_H
+#define IRONCLAD_H
+
+/* Generated by cbindgen from src/lib.rs; don't edit by hand */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * An open store
+ */
+typedef struct IroncladStore IroncladStore;
+
+/**
+ * Status code returned by every call
+ */
+typedef int IroncladStatus;
+
+/**
+ * Called once per entry by `ironclad_scan`; return nonzero to stop
+ */
+typedef int (*IroncladScanFn)(void *context, const char *key, const char *value);
+
+#define IRONCLAD_OK 0
+
+/**
+ * The key doesn't exist
+ */
+#define IRONCLAD_NOT_FOUND 1
+
+/**
+ * A null pointer or a string that isn't UTF-8
+ */
+#define IRONCLAD_INVALID_ARGUMENT 2
+
+/**
+ * A write conflicted with a concurrent one; retry
+ */
+#define IRONCLAD_CONFLICT 3
+
+#define IRONCLAD_QUOTA_EXCEEDED 4
+
+/**
+ * Another process holds the store's lock
+ */
+#define IRONCLAD_LOCKED 5
+
+/**
+ * Any other failure (storage, network, corruption)
+ */
+#define IRONCLAD_ERROR 6
+
+#define IRONCLAD_PANIC 7
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Open the store at `connection_string`, writing its handle to `out`
+ *
+ * # Safety
+ * `connection_string` is a NUL-terminated string; `out` is writable
+ */
+IroncladStatus ironclad_open(const char *connection_string, IroncladStore **out);
+
+/**
+ * Flush and close a store; the handle is freed even if the flush fails
+ * (null is ignored)
+ *
+ * # Safety
+ * `store` is null or a handle from `ironclad_open`, not used afterwards
+ */
+IroncladStatus ironclad_close(IroncladStore *store);
+
+/**
+ * Read `key` into `value_out` (free it with `ironclad_free_string`);
+ * `IRONCLAD_NOT_FOUND` if it doesn't exist
+ *
+ * # Safety
+ * `store` is a handle from `ironclad_open`; `key` is a NUL-terminated
+ * string; `value_out` is writable
+ */
+IroncladStatus ironclad_get(const IroncladStore *store, const char *key, char **value_out);
+
+/**
+ * Write `value` under `key`
+ *
+ * # Safety
+ * `store` is a handle from `ironclad_open`; `key` and `value` are
+ * NUL-terminated strings
+ */
+IroncladStatus ironclad_set(const IroncladStore *store, const char *key, const char *value);
+
+/**
+ * Delete `key`; `IRONCLAD_NOT_FOUND` if it didn't exist
+ *
+ * # Safety
+ * `store` is a handle from `ironclad_open`; `key` is a NUL-terminated
+ * string
+ */
+IroncladStatus ironclad_delete(const IroncladStore *store, const char *key);
+
+/**
+ * Call `callback` with each entry in key order, until it returns nonzero.
+ * The strings are only valid during the callback.
+ *
+ * # Safety
+ * `store` is a handle from `ironclad_open`; `callback` is safe to call
+ * with `context`
+ */
+IroncladStatus ironclad_scan(const IroncladStore *store, IroncladScanFn callback, void *context);
+
+/**
+ * Free a string returned by the library (null is ignored)
+ *
+ * # Safety
+ * `s` is null or a string from this library, not used afterwards
+ */
+void ironclad_free_string(char *s);
+
+/**
+ * The calling thread's last error message ("" after a successful call)
+ */
+const char *ironclad_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* IRONCLAD_H */
@@ -0,0 +1,296 @@
+//! FFI: A C ABI for Embedding the Store
+//!
+//! This crate builds `libironclad` as a `cdylib` and a `staticlib` that
+//! export these functions, declared in `include/ironclad.h`, so C, C++, Go
+//! (cgo) or Python (ctypes) services can embed a store in-process. It is
+//! kept apart from `ironclad-db` so Rust users of the store don't build
+//! C libraries they never link:
+//!
+//! ```c
+//! IroncladStore *store;
+//! if (ironclad_open(connection_string, &store) != IRONCLAD_OK) {
+//!     fprintf(stderr, "%s\n", ironclad_last_error());
+//! }
+//! ironclad_set(store, "user:1", "alice");
+//! char *value;
+//! if (ironclad_get(store, "user:1", &value) == IRONCLAD_OK) {
+//!     ironclad_free_string(value);
+//! }
+//! ironclad_close(store);
+//! ```
+//!
+//! Every call returns an `IroncladStatus`. On failure, `ironclad_last_error`
+//! describes it; the message belongs to the calling thread and stays valid
+//! until its next call. Strings are NUL-terminated UTF-8. Strings the
+//! library returns are freed with `ironclad_free_string`.
+//!
+//! A handle wraps the blocking facade (see blocking.rs), so calls block the
+//! calling thread; a handle may be shared between threads. Panics are
+//! caught at the boundary and reported as `IRONCLAD_PANIC`.
+//!
+//! The ABI is stable: functions and status codes are only ever added.
+//! The header is generated by cbindgen on every build (see build.rs and
+//! cbindgen.toml), from the declarations and doc comments below.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::ptr;
+
+use ironclad_db::blocking::KVStore;
+use ironclad_db::IronCladError;
+
+/// Status code returned by every call
+pub type IroncladStatus = c_int;
+
+pub const IRONCLAD_OK: IroncladStatus = 0;
+/// The key doesn't exist
+pub const IRONCLAD_NOT_FOUND: IroncladStatus = 1;
+/// A null pointer or a string that isn't UTF-8
+pub const IRONCLAD_INVALID_ARGUMENT: IroncladStatus = 2;
+/// A write conflicted with a concurrent one; retry
+pub const IRONCLAD_CONFLICT: IroncladStatus = 3;
+pub const IRONCLAD_QUOTA_EXCEEDED: IroncladStatus = 4;
+/// Another process holds the store's lock
+pub const IRONCLAD_LOCKED: IroncladStatus = 5;
+/// Any other failure (storage, network, corruption)
+pub const IRONCLAD_ERROR: IroncladStatus = 6;
+pub const IRONCLAD_PANIC: IroncladStatus = 7;
+
+/// Called once per entry by `ironclad_scan`; return nonzero to stop
+pub type IroncladScanFn = Option<extern "C" fn(context: *mut c_void, key: *const c_char, value: *const c_char) -> c_int>;
+
+/// An open store
+pub struct IroncladStore {
+    store: KVStore,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn set_last_error(message: &str) {
+    let message = CString::new(message.replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+/// The status for a failed call
+fn status_of(error: &anyhow::Error) -> IroncladStatus {
+    match error.downcast_ref::<IronCladError>() {
+        Some(IronCladError::WriteConflict { .. } | IronCladError::SnapshotTooOld { .. }) => IRONCLAD_CONFLICT,
+        Some(IronCladError::QuotaExceeded { .. }) => IRONCLAD_QUOTA_EXCEEDED,
+        Some(IronCladError::LockHeld { .. }) => IRONCLAD_LOCKED,
+        _ => IRONCLAD_ERROR,
+    }
+}
+
+/// Run `call` at the boundary: record failures and catch panics
+fn guard(call: impl FnOnce() -> Result<(), IroncladStatus>) -> IroncladStatus {
+    set_last_error("");
+    match catch_unwind(AssertUnwindSafe(call)) {
+        Ok(Ok(())) => IRONCLAD_OK,
+        Ok(Err(status)) => status,
+        Err(_) => {
+            set_last_error("panic inside ironclad");
+            IRONCLAD_PANIC
+        }
+    }
+}
+
+fn fail(error: anyhow::Error) -> IroncladStatus {
+    set_last_error(&format!("{:#}", error));
+    status_of(&error)
+}
+
+fn invalid(message: &str) -> IroncladStatus {
+    set_last_error(message);
+    IRONCLAD_INVALID_ARGUMENT
+}
+
+/// # Safety
+/// `s` is null or a NUL-terminated string
+unsafe fn str_arg<'a>(s: *const c_char, name: &str) -> Result<&'a str, IroncladStatus> {
+    if s.is_null() {
+        return Err(invalid(&format!("{} is null", name)));
+    }
+    CStr::from_ptr(s).to_str().map_err(|_| invalid(&format!("{} isn't UTF-8", name)))
+}
+
+/// # Safety
+/// `store` is null or a handle from `ironclad_open`
+unsafe fn store_arg<'a>(store: *const IroncladStore) -> Result<&'a KVStore, IroncladStatus> {
+    store.as_ref().map(|handle| &handle.store).ok_or_else(|| invalid("store is null"))
+}
+
+fn c_string(s: &str) -> Result<CString, IroncladStatus> {
+    CString::new(s).map_err(|_| fail(anyhow::anyhow!("value contains a NUL byte")))
+}
+
+/// Open the store at `connection_string`, writing its handle to `out`
+///
+/// # Safety
+/// `connection_string` is a NUL-terminated string; `out` is writable
+#[no_mangle]
+pub unsafe extern "C" fn ironclad_open(connection_string: *const c_char, out: *mut *mut IroncladStore) -> IroncladStatus {
+    guard(|| {
+        if out.is_null() {
+            return Err(invalid("out is null"));
+        }
+        let connection_string = str_arg(connection_string, "connection_string")?;
+        let store = KVStore::new(connection_string).map_err(fail)?;
+        *out = Box::into_raw(Box::new(IroncladStore { store }));
+        Ok(())
+    })
+}
+
+/// Flush and close a store; the handle is freed even if the flush fails
+/// (null is ignored)
+///
+/// # Safety
+/// `store` is null or a handle from `ironclad_open`, not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn ironclad_close(store: *mut IroncladStore) -> IroncladStatus {
+    guard(|| {
+        if store.is_null() {
+            return Ok(());
+        }
+        let handle = Box::from_raw(store);
+        handle.store.flush().map_err(fail)
+    })
+}
+
+/// Read `key` into `value_out` (free it with `ironclad_free_string`);
+/// `IRONCLAD_NOT_FOUND` if it doesn't exist
+///
+/// # Safety
+/// `store` is a handle from `ironclad_open`; `key` is a NUL-terminated
+/// string; `value_out` is writable
+#[no_mangle]
+pub unsafe extern "C" fn ironclad_get(store: *const IroncladStore, key: *const c_char, value_out: *mut *mut c_char) -> IroncladStatus {
+    guard(|| {
+        let store = store_arg(store)?;
+        let key = str_arg(key, "key")?;
+        if value_out.is_null() {
+            return Err(invalid("value_out is null"));
+        }
+        *value_out = ptr::null_mut();
+        match store.get(key).map_err(fail)? {
+            Some(value) => {
+                *value_out = c_string(&value)?.into_raw();
+                Ok(())
+            }
+            None => Err(IRONCLAD_NOT_FOUND),
+        }
+    })
+}
+
+/// Write `value` under `key`
+///
+/// # Safety
+/// `store` is a handle from `ironclad_open`; `key` and `value` are
+/// NUL-terminated strings
+#[no_mangle]
+pub unsafe extern "C" fn ironclad_set(store: *const IroncladStore, key: *const c_char, value: *const c_char) -> IroncladStatus {
+    guard(|| {
+        let store = store_arg(store)?;
+        let (key, value) = (str_arg(key, "key")?, str_arg(value, "value")?);
+        store.set(key, value).map_err(fail)
+    })
+}
+
+/// Delete `key`; `IRONCLAD_NOT_FOUND` if it didn't exist
+///
+/// # Safety
+/// `store` is a handle from `ironclad_open`; `key` is a NUL-terminated
+/// string
+#[no_mangle]
+pub unsafe extern "C" fn ironclad_delete(store: *const IroncladStore, key: *const c_char) -> IroncladStatus {
+    guard(|| {
+        let store = store_arg(store)?;
+        let key = str_arg(key, "key")?;
+        match store.delete(key).map_err(fail)? {
+            true => Ok(()),
+            false => Err(IRONCLAD_NOT_FOUND),
+        }
+    })
+}
+
+/// Call `callback` with each entry in key order, until it returns nonzero.
+/// The strings are only valid during the callback.
+///
+/// # Safety
+/// `store` is a handle from `ironclad_open`; `callback` is safe to call
+/// with `context`
+#[no_mangle]
+pub unsafe extern "C" fn ironclad_scan(store: *const IroncladStore, callback: IroncladScanFn, context: *mut c_void) -> IroncladStatus {
+    guard(|| {
+        let store = store_arg(store)?;
+        let callback = callback.ok_or_else(|| invalid("callback is null"))?;
+        for (key, value) in store.scan().map_err(fail)? {
+            let (key, value) = (c_string(&key)?, c_string(&value)?);
+            if callback(context, key.as_ptr(), value.as_ptr()) != 0 {
+                break;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Free a string returned by the library (null is ignored)
+///
+/// # Safety
+/// `s` is null or a string from this library, not used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn ironclad_free_string(s: *mut c_char) {
+    if !s.is_null() {
+        drop(CString::from_raw(s));
+    }
+}
+
+/// The calling thread's last error message ("" after a successful call)
+#[no_mangle]
+pub extern "C" fn ironclad_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| last.borrow().as_ptr())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(ironclad_last_error()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_failures_set_status_and_message() {
+        let mut store = ptr::null_mut();
+        let status = unsafe { ironclad_open(c"not a connection string".as_ptr(), &mut store) };
+        assert_eq!(status, IRONCLAD_ERROR);
+        assert!(store.is_null());
+        assert!(!last_error().is_empty());
+
+        let status = unsafe { ironclad_set(ptr::null(), c"k".as_ptr(), c"v".as_ptr()) };
+        assert_eq!(status, IRONCLAD_INVALID_ARGUMENT);
+        assert_eq!(last_error(), "store is null");
+    }
+
+    #[test]
+    fn test_error_kinds_map_to_status_codes() {
+        let conflict = anyhow::Error::from(IronCladError::WriteConflict { key: "k".to_string() });
+        assert_eq!(status_of(&conflict), IRONCLAD_CONFLICT);
+        assert_eq!(status_of(&anyhow::anyhow!("network")), IRONCLAD_ERROR);
+    }
+
+    #[test]
+    fn test_header_declares_every_export() {
+        let header = include_str!("../include/ironclad.h");
+        let source = include_str!("lib.rs");
+        for line in source.lines().filter(|line| line.starts_with("pub unsafe extern \"C\" fn") || line.starts_with("pub extern \"C\" fn")) {
+            let name = line.split("fn ").nth(1).and_then(|rest| rest.split('(').next()).unwrap();
+            assert!(header.contains(&format!("{}(", name)), "include/ironclad.h doesn't declare {}", name);
+        }
+        for status in ["IRONCLAD_OK", "IRONCLAD_NOT_FOUND", "IRONCLAD_PANIC"] {
+            assert!(header.contains(status));
+        }
+    }
+}
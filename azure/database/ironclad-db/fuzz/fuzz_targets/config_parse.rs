@@ -0,0 +1,18 @@
+//! Fuzz the text parsers fed from configuration and clients:
+//! connection strings and session tokens.
+
+#![no_main]
+
+use ironclad_db::{ConnectionConfig, SessionToken};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &str| {
+    if let Ok(config) = ConnectionConfig::parse(data) {
+        assert!(!config.account_name.is_empty());
+        assert!(!config.account_key.is_empty());
+    }
+
+    if let Ok(token) = data.parse::<SessionToken>() {
+        assert_eq!(token.to_string().parse::<SessionToken>().unwrap(), token);
+    }
+});
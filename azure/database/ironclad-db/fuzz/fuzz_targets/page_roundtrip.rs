@@ -0,0 +1,16 @@
+//! Fuzz the page encoder with arbitrary keys and values.
+//!
+//! Every pair the encoder accepts must decode back to itself.
+
+#![no_main]
+
+use ironclad_db::page::{decode_kv_page, encode_kv_page};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (String, String)| {
+    let (key, value) = input;
+
+    if let Ok(page) = encode_kv_page(&key, &value) {
+        assert_eq!(decode_kv_page(&page).unwrap(), (key, value));
+    }
+});
@@ -0,0 +1,10 @@
+//! Fuzz the WAL decoder with arbitrary log blob contents.
+
+#![no_main]
+
+use ironclad_db::wal::decode_log;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = decode_log(data);
+});
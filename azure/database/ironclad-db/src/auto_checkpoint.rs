@@ -0,0 +1,333 @@
+//! Auto Checkpoint: Checkpoint and Flush Pacing From the Workload
+//!
+//! Rather than hand-tuning checkpoint thresholds per workload, set
+//! `StoreOptions::auto_checkpoint` and start the tuner with
+//! `KVStore::start_auto_checkpoint`. Every `tick` it measures the write rate
+//! (WAL entries per second) and read rate, and picks:
+//!
+//! - the checkpoint interval: the time the WAL takes to grow to
+//!   `target_wal_entries` at the current write rate, within
+//!   `[min_interval, max_interval]`. A quiet store checkpoints rarely; a
+//!   busy one often enough to keep recovery (and the WAL) bounded. A WAL
+//!   reaching the target checkpoints at once.
+//! - the flush lag: between checkpoints, each tick flushes the pages
+//!   holding changes older than this many entries. It scales with the
+//!   write share of the traffic: a read-mostly store flushes nearly
+//!   everything, keeping dirty pages (and their write-back) off the read
+//!   path; a write-mostly one lets pages absorb repeated writes for up to
+//!   half a target's worth of entries.
+//!
+//! Rates are exponentially weighted, so one burst doesn't swing the
+//! choices. The values in effect are in `KVStoreStats::auto_checkpoint`.
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::kvstore::KVStore;
+use crate::runtime::TaskHandle;
+
+/// Weight of the newest rate sample
+const RATE_WEIGHT: f64 = 0.3;
+
+/// Auto checkpoint bounds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AutoCheckpointOptions {
+    /// WAL entries a checkpoint should keep the log under (default: 100,000)
+    pub target_wal_entries: u64,
+    /// Never checkpoint more often than this (default: 10s)
+    pub min_interval: Duration,
+    /// Always checkpoint a written-to store this often (default: 10min)
+    pub max_interval: Duration,
+    /// How often rates are sampled and pages flushed (default: 1s)
+    pub tick: Duration,
+}
+
+impl Default for AutoCheckpointOptions {
+    fn default() -> Self {
+        Self {
+            target_wal_entries: 100_000,
+            min_interval: Duration::from_secs(10),
+            max_interval: Duration::from_secs(600),
+            tick: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Auto checkpoint choices in effect and counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct AutoCheckpointStats {
+    /// Smoothed WAL entries per second
+    pub write_rate: u64,
+    /// Smoothed reads per second
+    pub read_rate: u64,
+    /// Checkpoint interval chosen
+    pub interval: Duration,
+    /// Flush lag chosen, in WAL entries
+    pub flush_lag_entries: u64,
+    /// Checkpoints taken by the tuner
+    pub checkpoints: u64,
+}
+
+/// What a tick should do
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Action {
+    Checkpoint,
+    /// Flush pages with changes logged at or before this LSN
+    FlushUpTo(u64),
+    Nothing,
+}
+
+/// Picks the interval and flush lag from observed rates
+pub(crate) struct CheckpointTuner {
+    options: AutoCheckpointOptions,
+    /// (time, LSN, reads) at the last sample
+    last_sample: Option<(Instant, u64, u64)>,
+    write_rate: f64,
+    read_rate: f64,
+    last_checkpoint: Instant,
+    stats: AutoCheckpointStats,
+}
+
+impl CheckpointTuner {
+    pub fn new(options: AutoCheckpointOptions, now: Instant) -> Self {
+        Self {
+            options,
+            last_sample: None,
+            write_rate: 0.0,
+            read_rate: 0.0,
+            last_checkpoint: now,
+            stats: AutoCheckpointStats { interval: options.max_interval, ..Default::default() },
+        }
+    }
+
+    /// Sample the rates (from the current LSN and read count) and decide
+    pub fn tick(&mut self, now: Instant, lsn: u64, reads: u64, wal_entries: u64) -> Action {
+        if let Some((at, last_lsn, last_reads)) = self.last_sample {
+            let secs = now.duration_since(at).as_secs_f64();
+            if secs > 0.0 {
+                let smooth = |rate: f64, delta: u64| rate + RATE_WEIGHT * (delta as f64 / secs - rate);
+                self.write_rate = smooth(self.write_rate, lsn.saturating_sub(last_lsn));
+                self.read_rate = smooth(self.read_rate, reads.saturating_sub(last_reads));
+            }
+        }
+        self.last_sample = Some((now, lsn, reads));
+
+        let options = &self.options;
+        let interval = match self.write_rate {
+            rate if rate > 0.0 => Duration::from_secs_f64(options.target_wal_entries as f64 / rate),
+            _ => options.max_interval,
+        }
+        .clamp(options.min_interval, options.max_interval);
+        let total = self.write_rate + self.read_rate;
+        let write_share = if total > 0.0 { self.write_rate / total } else { 1.0 };
+        let flush_lag = (options.target_wal_entries as f64 * write_share / 2.0) as u64;
+
+        self.stats.write_rate = self.write_rate.round() as u64;
+        self.stats.read_rate = self.read_rate.round() as u64;
+        self.stats.interval = interval;
+        self.stats.flush_lag_entries = flush_lag;
+
+        let since_checkpoint = now.duration_since(self.last_checkpoint);
+        let due = since_checkpoint >= interval
+            || (wal_entries >= options.target_wal_entries && since_checkpoint >= options.min_interval);
+        if wal_entries == 0 {
+            Action::Nothing
+        } else if due {
+            Action::Checkpoint
+        } else if lsn > flush_lag {
+            Action::FlushUpTo(lsn - flush_lag)
+        } else {
+            Action::Nothing
+        }
+    }
+
+    pub fn checkpointed(&mut self, now: Instant) {
+        self.last_checkpoint = now;
+        self.stats.checkpoints += 1;
+    }
+
+    pub fn stats(&self) -> AutoCheckpointStats {
+        self.stats
+    }
+}
+
+/// Auto checkpoint state of a store
+#[derive(Default)]
+pub(crate) struct AutoCheckpoint {
+    /// Reads served, for the read rate
+    reads: AtomicU64,
+    /// The running tuner's choices (None = not started)
+    stats: Mutex<Option<AutoCheckpointStats>>,
+}
+
+impl AutoCheckpoint {
+    pub fn note_read(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> Option<AutoCheckpointStats> {
+        *self.stats.lock()
+    }
+}
+
+/// Running auto checkpoint tuner; dropping it stops the tuner
+pub struct AutoCheckpointer {
+    task: TaskHandle,
+}
+
+impl Drop for AutoCheckpointer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl KVStore {
+    /// Checkpoint and flush on the pace the workload calls for (see the
+    /// module docs)
+    ///
+    /// Returns None when `auto_checkpoint` isn't set in the store options.
+    /// Failed checkpoints and flushes are logged (and reported to event
+    /// listeners); the tuner carries on.
+    pub fn start_auto_checkpoint(self: &Arc<Self>) -> Result<Option<AutoCheckpointer>> {
+        let Some(options) = self.options().auto_checkpoint else {
+            return Ok(None);
+        };
+        if options.tick.is_zero() || options.min_interval > options.max_interval {
+            anyhow::bail!("Invalid auto checkpoint options: {:?}", options);
+        }
+
+        let store = Arc::clone(self);
+        let runtime = self.options().runtime.clone();
+        let task = runtime.clone().spawn(async move {
+            let mut tuner = CheckpointTuner::new(options, Instant::now());
+            loop {
+                runtime.sleep(options.tick).await;
+                let state = store.auto_checkpoint();
+                let action = tuner.tick(
+                    Instant::now(),
+                    store.wal().current_lsn(),
+                    state.reads.load(Ordering::Relaxed),
+                    store.wal().entry_count() as u64,
+                );
+                *state.stats.lock() = Some(tuner.stats());
+
+                match action {
+                    Action::Checkpoint => match store.checkpoint().await {
+                        Ok(()) => {
+                            tuner.checkpointed(Instant::now());
+                            debug!("AUTO CHECKPOINT: checkpointed ({:?})", tuner.stats());
+                        }
+                        Err(e) => warn!("AUTO CHECKPOINT: checkpoint failed: {:#}", e),
+                    },
+                    Action::FlushUpTo(lsn) => {
+                        if let Err(e) = store.flush_up_to(lsn).await {
+                            warn!("AUTO CHECKPOINT: flush failed: {:#}", e);
+                        }
+                    }
+                    Action::Nothing => {}
+                }
+            }
+        });
+        Ok(Some(AutoCheckpointer { task }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_store::MemoryLog;
+    use crate::options::StoreOptions;
+    use crate::page_store::MemoryDisk;
+    use crate::testing::{memory_options, open_store, CONNECTION};
+
+    fn options() -> AutoCheckpointOptions {
+        AutoCheckpointOptions {
+            target_wal_entries: 1_000,
+            min_interval: Duration::from_secs(10),
+            max_interval: Duration::from_secs(100),
+            tick: Duration::from_secs(1),
+        }
+    }
+
+    /// Run `ticks` one-second ticks at `writes` and `reads` per second
+    fn run(tuner: &mut CheckpointTuner, start: Instant, ticks: u64, writes: u64, reads: u64) -> Action {
+        let mut action = Action::Nothing;
+        for tick in 0..=ticks {
+            action = tuner.tick(start + Duration::from_secs(tick), tick * writes, tick * reads, tick * writes);
+        }
+        action
+    }
+
+    #[test]
+    fn test_interval_follows_write_rate() {
+        let start = Instant::now();
+        let mut busy = CheckpointTuner::new(options(), start);
+        run(&mut busy, start, 30, 200, 0);
+        // 1,000 entries at ~200/s is 5s, held to the 10s minimum
+        assert_eq!(busy.stats().interval, Duration::from_secs(10));
+
+        let mut quiet = CheckpointTuner::new(options(), start);
+        run(&mut quiet, start, 5, 1, 0);
+        assert_eq!(quiet.stats().interval, Duration::from_secs(100));
+    }
+
+    #[test]
+    fn test_read_heavy_flushes_closer_to_the_head() {
+        let start = Instant::now();
+        let mut writes = CheckpointTuner::new(options(), start);
+        let mut reads = CheckpointTuner::new(options(), start);
+        run(&mut writes, start, 5, 10, 0);
+        run(&mut reads, start, 5, 10, 990);
+        assert_eq!(writes.stats().flush_lag_entries, 500);
+        assert!(reads.stats().flush_lag_entries < 10);
+    }
+
+    #[tokio::test]
+    async fn test_auto_checkpointed_store_reopens() {
+        let (disk, log) = (MemoryDisk::new(), MemoryLog::new());
+        let auto_checkpoint = AutoCheckpointOptions {
+            target_wal_entries: 20,
+            min_interval: Duration::ZERO,
+            max_interval: Duration::from_secs(60),
+            tick: Duration::from_millis(10),
+        };
+        let options = StoreOptions { auto_checkpoint: Some(auto_checkpoint), ..memory_options(&disk, &log) };
+        let store = Arc::new(KVStore::open(CONNECTION, options.clone()).await.unwrap());
+        let checkpointer = store.start_auto_checkpoint().unwrap().unwrap();
+        for i in 0..50 {
+            store.set(&format!("k{}", i), &i.to_string()).await.unwrap();
+        }
+
+        // The WAL is past the target: the next tick checkpoints
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while store.stats().auto_checkpoint.map_or(0, |stats| stats.checkpoints) == 0 {
+            assert!(Instant::now() < deadline, "no auto checkpoint");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(checkpointer);
+        assert!(store.stats().wal_entries < 50);
+        drop(store);
+
+        let store = open_store(options).await;
+        for i in 0..50 {
+            assert_eq!(store.get(&format!("k{}", i)).await.unwrap(), Some(i.to_string()));
+        }
+    }
+
+    #[test]
+    fn test_full_wal_checkpoints_after_the_minimum_interval() {
+        let start = Instant::now();
+        let mut tuner = CheckpointTuner::new(options(), start);
+        assert_eq!(tuner.tick(start, 0, 0, 0), Action::Nothing);
+        assert!(matches!(tuner.tick(start + Duration::from_secs(5), 2_000, 0, 2_000), Action::FlushUpTo(_)));
+        assert_eq!(tuner.tick(start + Duration::from_secs(10), 2_000, 0, 2_000), Action::Checkpoint);
+
+        tuner.checkpointed(start + Duration::from_secs(10));
+        assert_eq!(tuner.tick(start + Duration::from_secs(11), 2_000, 0, 0), Action::Nothing);
+        assert_eq!(tuner.stats().checkpoints, 1);
+    }
+}
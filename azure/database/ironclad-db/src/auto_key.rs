@@ -0,0 +1,218 @@
+//! Auto Keys: Generated Keys for Event-Log Style Writes
+//!
+//! `insert_auto(value)` writes a value under a key the store makes up and
+//! returns the key. `StoreOptions::key_generator` picks how keys are made;
+//! `insert_auto_with` picks per call:
+//!
+//! ```ignore
+//! let id = store.insert_auto(&event_json).await?; // UUIDv7 by default
+//! let seq = store.insert_auto_with(&KeyGenerator::counter("orders/"), &order_json).await?;
+//! ```
+//!
+//! - `UuidV7`: a version 7 UUID (RFC 9562), 48 bits of Unix milliseconds
+//!   then 74 random bits. Unique without coordination; keys sort by
+//!   millisecond.
+//! - `Snowflake { node_id }`: a 64-bit ID, milliseconds since 2020 (41
+//!   bits), node (10 bits), sequence within the millisecond (12 bits),
+//!   written as 20 zero-padded digits so keys sort like the IDs. Unique
+//!   across writers as long as each has its own node ID; increasing on
+//!   one node, even if its clock steps back.
+//! - `Counter { bucket }`: `bucket` followed by a 20-digit counter that
+//!   only grows, across restarts too. Counters are handed out from blocks
+//!   reserved in the superblock, so a restart skips the rest of a block:
+//!   values never repeat, but may have gaps. The superblock fits in a
+//!   page, so this suits tens of buckets, not one per tenant.
+//! - `Custom`: any function returning a key. Unlike the generators above,
+//!   its keys aren't unique by construction: an insert whose key already
+//!   exists fails instead of overwriting it.
+
+use anyhow::Result;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::kvstore::KVStore;
+
+/// Counter values reserved in the superblock at a time
+const COUNTER_BLOCK: u64 = 1_000;
+
+/// Snowflake time origin: 2020-01-01T00:00:00Z, in Unix milliseconds
+const SNOWFLAKE_EPOCH_MS: u64 = 1_577_836_800_000;
+const SNOWFLAKE_NODE_BITS: u32 = 10;
+const SNOWFLAKE_SEQUENCE_BITS: u32 = 12;
+
+/// How `insert_auto` makes keys
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum KeyGenerator {
+    /// Time-ordered random UUID
+    #[default]
+    UuidV7,
+    /// Time, node and sequence packed in 64 bits; `node_id` below 1024
+    Snowflake { node_id: u16 },
+    /// `bucket` followed by a persisted counter
+    Counter { bucket: String },
+    /// Keys from a function
+    Custom(CustomKeyGenerator),
+}
+
+impl KeyGenerator {
+    /// Counter keys under `bucket`
+    pub fn counter(bucket: &str) -> Self {
+        Self::Counter { bucket: bucket.to_string() }
+    }
+}
+
+/// A function making keys for `KeyGenerator::Custom`
+#[derive(Clone)]
+pub struct CustomKeyGenerator(Arc<dyn Fn() -> String + Send + Sync>);
+
+impl CustomKeyGenerator {
+    pub fn new<F: Fn() -> String + Send + Sync + 'static>(generate: F) -> Self {
+        Self(Arc::new(generate))
+    }
+}
+
+impl std::fmt::Debug for CustomKeyGenerator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CustomKeyGenerator")
+    }
+}
+
+/// Generators are equal when they share the function
+impl PartialEq for CustomKeyGenerator {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CustomKeyGenerator {}
+
+/// Generator state of a store
+#[derive(Debug, Default)]
+pub(crate) struct AutoKeys {
+    /// Millisecond and sequence of the last Snowflake ID
+    snowflake: parking_lot::Mutex<(u64, u64)>,
+    /// Counter values reserved but not yet handed out, by bucket
+    counters: tokio::sync::Mutex<HashMap<String, Range<u64>>>,
+}
+
+impl AutoKeys {
+    fn next_snowflake(&self, node_id: u16) -> Result<u64> {
+        if u64::from(node_id) >= 1 << SNOWFLAKE_NODE_BITS {
+            anyhow::bail!("Snowflake node ID {} is over 1023", node_id);
+        }
+        let now = unix_millis().saturating_sub(SNOWFLAKE_EPOCH_MS);
+        let mut last = self.snowflake.lock();
+        let (millis, sequence) = &mut *last;
+        if now > *millis {
+            (*millis, *sequence) = (now, 0);
+        } else if *sequence + 1 < 1 << SNOWFLAKE_SEQUENCE_BITS {
+            *sequence += 1;
+        } else {
+            // Sequence used up (or the clock stepped back): borrow the next millisecond
+            (*millis, *sequence) = (*millis + 1, 0);
+        }
+        Ok(*millis << (SNOWFLAKE_NODE_BITS + SNOWFLAKE_SEQUENCE_BITS)
+            | u64::from(node_id) << SNOWFLAKE_SEQUENCE_BITS
+            | *sequence)
+    }
+}
+
+/// Reserve the next block of `bucket`'s counter in the superblock's
+/// `counters`; returns the values reserved
+pub(crate) fn reserve_block(counters: &mut BTreeMap<String, u64>, bucket: &str) -> Range<u64> {
+    let ceiling = counters.entry(bucket.to_string()).or_default();
+    *ceiling += COUNTER_BLOCK;
+    *ceiling - COUNTER_BLOCK..*ceiling
+}
+
+/// A version 7 UUID at `millis`, in its hyphenated form
+fn uuid_v7(millis: u64, rng: &mut impl Rng) -> String {
+    let mut bytes: [u8; 16] = rng.gen();
+    bytes[..6].copy_from_slice(&millis.to_be_bytes()[2..]);
+    bytes[6] = 0x70 | (bytes[6] & 0x0F);
+    bytes[8] = 0x80 | (bytes[8] & 0x3F);
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+impl KVStore {
+    /// Write `value` under a key from `StoreOptions::key_generator`;
+    /// returns the key (see auto_key.rs)
+    pub async fn insert_auto(&self, value: &str) -> Result<String> {
+        let generator = self.options().key_generator.clone();
+        self.insert_auto_with(&generator, value).await
+    }
+
+    /// Write `value` under a key from `generator`; returns the key
+    pub async fn insert_auto_with(&self, generator: &KeyGenerator, value: &str) -> Result<String> {
+        let key = match generator {
+            KeyGenerator::UuidV7 => uuid_v7(unix_millis(), &mut rand::thread_rng()),
+            KeyGenerator::Snowflake { node_id } => format!("{:020}", self.auto_keys().next_snowflake(*node_id)?),
+            KeyGenerator::Counter { bucket } => format!("{}{:020}", bucket, self.next_count(bucket).await?),
+            KeyGenerator::Custom(custom) => {
+                let key = (custom.0)();
+                if !self.set_nx(&key, value).await? {
+                    anyhow::bail!("Generated key {} already exists", key);
+                }
+                return Ok(key);
+            }
+        };
+        self.set(&key, value).await?;
+        Ok(key)
+    }
+
+    /// Next value of `bucket`'s counter, reserving a block when the last
+    /// one is used up
+    async fn next_count(&self, bucket: &str) -> Result<u64> {
+        let mut reserved = self.auto_keys().counters.lock().await;
+        let values = reserved.entry(bucket.to_string()).or_default();
+        if values.is_empty() {
+            let mut block = 0..0;
+            self.update_superblock(|sb| block = reserve_block(&mut sb.counters, bucket)).await?;
+            *values = block;
+        }
+        Ok(values.next().expect("block is not empty"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_uuid_v7_layout_and_order() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let earlier = uuid_v7(1_700_000_000_000, &mut rng);
+        let later = uuid_v7(1_700_000_000_001, &mut rng);
+        assert_eq!(earlier.len(), 36);
+        assert!(earlier.starts_with("018bcfe5-6800-7"));
+        assert!(matches!(&earlier[19..20], "8" | "9" | "a" | "b"));
+        assert!(earlier < later);
+    }
+
+    #[test]
+    fn test_snowflake_ids_increase_and_carry_the_node() {
+        let auto_keys = AutoKeys::default();
+        let ids: Vec<u64> = (0..10_000).map(|_| auto_keys.next_snowflake(5).unwrap()).collect();
+        assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(ids.iter().all(|id| id >> SNOWFLAKE_SEQUENCE_BITS & 0x3FF == 5));
+        assert!(auto_keys.next_snowflake(1024).is_err());
+    }
+
+    #[test]
+    fn test_counter_blocks_never_overlap() {
+        let mut counters = BTreeMap::new();
+        assert_eq!(reserve_block(&mut counters, "orders/"), 0..COUNTER_BLOCK);
+        assert_eq!(reserve_block(&mut counters, "orders/"), COUNTER_BLOCK..2 * COUNTER_BLOCK);
+        assert_eq!(reserve_block(&mut counters, "events/"), 0..COUNTER_BLOCK);
+    }
+}
@@ -1,25 +1,45 @@
-/// AzureDisk: Pager Layer - Treats Azure Page Blobs as raw block devices
-/// 
-/// This layer provides a block device abstraction over Azure Page Blobs.
-/// Each page is 4KB (4096 bytes) - standard database page size.
-/// Operations are async due to network I/O.
+//! AzureDisk: Pager Layer - Treats Azure Page Blobs as raw block devices
+//! 
+//! This layer provides a block device abstraction over Azure Page Blobs.
+//! Each page is 4KB (4096 bytes) - standard database page size.
+//! Operations are async due to network I/O.
+//!
+//! Access tiers (Hot, Cool, Cold, Archive) only apply to block blobs, so
+//! data pages are never archived and a page read never waits on
+//! rehydration. Backup sets (backup_set.rs) are block blobs: one moved to
+//! Archive must be rehydrated with Azure's Set Blob Tier before it can be
+//! restored.
+//!
+//! With `StoreOptions::secondary_reads`, page reads may be served by the
+//! account's RA-GRS secondary region instead (see geo_reads.rs).
 
 use anyhow::Result;
-use azure_storage::prelude::*;
 use azure_storage_blobs::prelude::*;
 use futures::StreamExt;
 use std::sync::Arc;
 use tracing::{debug, info};
 use bytes::Bytes;
 
+use crate::backup::{delete_blob_snapshot, snapshot_blob};
+use crate::bootstrap::ensure_container;
+use crate::buffer_pool::SECTOR_SIZE;
+use crate::config::ConnectionConfig;
+use crate::geo_reads::{GeoReadStats, GeoReads, SecondaryReads};
+use crate::retry_budget;
+
 const PAGE_SIZE: usize = 4096; // 4KB pages - standard database page size
 const BLOB_SIZE: usize = 1024 * 1024 * 1024; // 1GB total capacity
 
 /// AzureDisk provides a block device abstraction over Azure Page Blobs
 pub struct AzureDisk {
     blob_client: Arc<BlobClient>,
+    container_client: ContainerClient,
+    #[allow(dead_code)]
     container_name: String,
+    #[allow(dead_code)]
     blob_name: String,
+    /// Routes reads to the secondary region, if configured
+    geo: Option<GeoReads>,
 }
 
 impl AzureDisk {
@@ -33,36 +53,24 @@ impl AzureDisk {
         connection_string: &str,
         container_name: &str,
         blob_name: &str,
+    ) -> Result<Self> {
+        Self::open(connection_string, container_name, blob_name, None).await
+    }
+
+    /// Create an AzureDisk whose reads may go to the secondary region
+    pub async fn open(
+        connection_string: &str,
+        container_name: &str,
+        blob_name: &str,
+        secondary_reads: Option<&SecondaryReads>,
     ) -> Result<Self> {
         info!("Initializing AzureDisk: container={}, blob={}", container_name, blob_name);
         
-        // Manual connection string parsing
-        let mut account_name = String::new();
-        let mut account_key = String::new();
-        
-        for part in connection_string.split(';') {
-            if let Some((key, value)) = part.split_once('=') {
-                match key {
-                    "AccountName" => account_name = value.to_string(),
-                    "AccountKey" => account_key = value.to_string(),
-                    _ => {}
-                }
-            }
-        }
-        
-        if account_name.is_empty() || account_key.is_empty() {
-             anyhow::bail!("Invalid connection string: missing AccountName or AccountKey");
-        }
-        
-        let creds = StorageCredentials::access_key(account_name.clone(), account_key);
-        let blob_service_client = BlobServiceClient::new(account_name, creds);
-        let container_client = blob_service_client.container_client(container_name);
+        let config = ConnectionConfig::parse(connection_string)?;
+        let container_client = config.container_client(container_name);
         
         // Ensure container exists
-        if !container_client.exists().await? {
-            info!("Creating container {}", container_name);
-            container_client.create().await?;
-        }
+        ensure_container(&container_client).await?;
         
         let blob_client = container_client.blob_client(blob_name);
         
@@ -73,10 +81,19 @@ impl AzureDisk {
             blob_client.put_page_blob(BLOB_SIZE as u128).await?;
         }
         
+        let geo = secondary_reads.map(|reads| {
+            let endpoint = config.secondary_endpoint(reads.endpoint.as_deref());
+            info!("Reading pages from {} within {:?} staleness", endpoint, reads.max_staleness);
+            let secondary = config.container_client_at(&endpoint, container_name).blob_client(blob_name);
+            GeoReads::new(secondary, reads.clone())
+        });
+        
         Ok(Self {
             blob_client: Arc::new(blob_client),
+            container_client,
             container_name: container_name.to_string(),
             blob_name: blob_name.to_string(),
+            geo,
         })
     }
     
@@ -88,35 +105,10 @@ impl AzureDisk {
     /// # Returns
     /// A 4KB byte array containing the page data
     pub async fn read_page(&self, page_id: u64) -> Result<Vec<u8>> {
-        let offset = page_id * PAGE_SIZE as u64;
-        
-        debug!("Reading page {} from offset {}", page_id, offset);
-        
-        // We need to read PAGE_SIZE bytes at the calculated offset
-        let range = offset..offset + PAGE_SIZE as u64;
-        
-        // Execute the request using into_stream()
-        let mut stream = self.blob_client.get().range(range).into_stream();
-        let mut data = Vec::with_capacity(PAGE_SIZE);
-        
-        while let Some(response_res) = stream.next().await {
-            let response = response_res?;
-            let mut body = response.data;
-            while let Some(chunk_res) = body.next().await {
-                let chunk: Bytes = chunk_res?;
-                data.extend_from_slice(&chunk);
-            }
+        match &self.geo {
+            Some(geo) => geo.read_page(&self.blob_client, page_id).await,
+            None => fetch_page(&self.blob_client, page_id).await,
         }
-
-        if data.len() < PAGE_SIZE {
-             data.resize(PAGE_SIZE, 0);
-        }
-        
-        if data.len() > PAGE_SIZE {
-            data.truncate(PAGE_SIZE);
-        }
-        
-        Ok(data)
     }
     
     /// Write a page to the blob storage
@@ -125,22 +117,35 @@ impl AzureDisk {
     /// * `page_id` - The page ID (0-indexed)
     /// * `data` - The 4KB data to write
     pub async fn write_page(&self, page_id: u64, data: &[u8]) -> Result<()> {
+        self.write_page_range(page_id, data, 0..PAGE_SIZE).await
+    }
+    
+    /// Write only bytes `range` of a page (512-byte aligned, see
+    /// `DirtyPage::dirty_range`); `data` is the whole page
+    pub async fn write_page_range(&self, page_id: u64, data: &[u8], range: std::ops::Range<usize>) -> Result<()> {
         if data.len() != PAGE_SIZE {
             anyhow::bail!("Invalid page size: expected {}, got {}", PAGE_SIZE, data.len());
         }
+        if range.is_empty() || range.end > PAGE_SIZE || !range.start.is_multiple_of(SECTOR_SIZE) || !range.end.is_multiple_of(SECTOR_SIZE) {
+            anyhow::bail!("Invalid page range {:?}: must be whole 512-byte sectors of the page", range);
+        }
         
-        let offset = page_id * PAGE_SIZE as u64;
+        let offset = page_id * PAGE_SIZE as u64 + range.start as u64;
         
-        debug!("Writing page {} at offset {}", page_id, offset);
+        debug!("Writing page {} bytes {:?} at offset {}", page_id, range, offset);
         
-        let bytes = Bytes::copy_from_slice(data);
+        let bytes = Bytes::copy_from_slice(&data[range.clone()]);
         
-        let range = BA512Range::new(offset, offset + (PAGE_SIZE as u64) - 1)?;
+        let range = BA512Range::new(offset, offset + range.len() as u64 - 1)?;
         
         // update_pages takes u64 offset
-        self.blob_client
+        let response = self.blob_client
             .put_page(range, bytes)
-            .await?;
+            .await
+            .map_err(retry_budget::surface)?;
+        if let Some(geo) = &self.geo {
+            geo.record_write(page_id, response.last_modified.into());
+        }
             
         Ok(())
     }
@@ -153,6 +158,26 @@ impl AzureDisk {
         Ok(())
     }
     
+    /// Take a blob snapshot of the data blob; returns its snapshot ID
+    pub async fn snapshot(&self) -> Result<String> {
+        snapshot_blob(&self.blob_client).await
+    }
+    
+    /// Delete a snapshot taken with `snapshot`
+    pub async fn delete_snapshot(&self, snapshot: &str) -> Result<()> {
+        delete_blob_snapshot(&self.blob_client, snapshot).await
+    }
+    
+    /// Client for the container holding the data blob
+    pub(crate) fn container_client(&self) -> &ContainerClient {
+        &self.container_client
+    }
+    
+    /// Where page reads went, if some may go to the secondary region
+    pub fn geo_read_stats(&self) -> Option<GeoReadStats> {
+        self.geo.as_ref().map(GeoReads::stats)
+    }
+    
     /// Get the page size (4KB)
     pub fn page_size(&self) -> usize {
         PAGE_SIZE
@@ -164,6 +189,39 @@ impl AzureDisk {
     }
 }
 
+/// Read page `page_id` of the page blob behind `blob_client`
+pub(crate) async fn fetch_page(blob_client: &BlobClient, page_id: u64) -> Result<Vec<u8>> {
+    let offset = page_id * PAGE_SIZE as u64;
+    
+    debug!("Reading page {} from offset {}", page_id, offset);
+    
+    // We need to read PAGE_SIZE bytes at the calculated offset
+    let range = offset..offset + PAGE_SIZE as u64;
+    
+    // Execute the request using into_stream()
+    let mut stream = blob_client.get().range(range).into_stream();
+    let mut data = Vec::with_capacity(PAGE_SIZE);
+    
+    while let Some(response_res) = stream.next().await {
+        let response = response_res.map_err(retry_budget::surface)?;
+        let mut body = response.data;
+        while let Some(chunk_res) = body.next().await {
+            let chunk: Bytes = chunk_res?;
+            data.extend_from_slice(&chunk);
+        }
+    }
+
+    if data.len() < PAGE_SIZE {
+         data.resize(PAGE_SIZE, 0);
+    }
+    
+    if data.len() > PAGE_SIZE {
+        data.truncate(PAGE_SIZE);
+    }
+    
+    Ok(data)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -174,6 +232,7 @@ mod tests {
     }
     
     #[test]
+    #[allow(clippy::erasing_op, clippy::identity_op)]
     fn test_page_calculations() {
         // Test offset calculations
         let page_0_offset = 0 * PAGE_SIZE as u64;
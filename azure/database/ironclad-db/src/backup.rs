@@ -0,0 +1,289 @@
+//! Backup: Snapshot Backups, Retention and Scheduling
+//!
+//! A backup checkpoints the store (bounding the WAL), then takes an Azure
+//! blob snapshot of the data blob followed by one of the WAL. Pages written
+//! after the checkpoint may or may not be in the data snapshot, but every
+//! such change is in the WAL snapshot, so replaying it on restore yields the
+//! state as of the WAL snapshot. Snapshots are copy-on-write on the Azure
+//! side, so a backup costs only the pages changed afterwards.
+//!
+//! The catalog of backups lives in the store under `__backup/<id>`, written
+//! after the snapshots (a backup never contains its own catalog entry).
+//!
+//! With `StoreOptions::backup_schedule` set, `start_backup_scheduler` runs
+//! backups on that cron schedule and prunes old ones by the retention policy.
+
+use anyhow::Result;
+use azure_storage_blobs::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::cron::CronSchedule;
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+use crate::runtime::TaskHandle;
+
+/// Key prefix for backup catalog entries
+const BACKUP_PREFIX: &str = "__backup/";
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+/// One backup in the catalog
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupInfo {
+    /// Catalog ID (sortable: later backups have larger IDs)
+    pub id: String,
+    /// When the backup was taken (Unix ms)
+    pub taken_at_ms: u64,
+    /// Snapshot of the data blob
+    pub data_snapshot: String,
+    /// Snapshot of the WAL blob
+    pub wal_snapshot: String,
+    /// WAL LSN when the WAL was snapshotted
+    pub lsn: u64,
+}
+
+/// Which backups to keep when pruning
+///
+/// A backup is kept if it is one of the `keep_last` newest, or the newest
+/// backup of one of the `keep_daily` most recent days (UTC) or
+/// `keep_weekly` most recent weeks (starting Monday) that have backups.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub keep_last: usize,
+    pub keep_daily: usize,
+    pub keep_weekly: usize,
+}
+
+impl Default for RetentionPolicy {
+    fn default() -> Self {
+        Self {
+            keep_last: 1,
+            keep_daily: 7,
+            keep_weekly: 4,
+        }
+    }
+}
+
+impl RetentionPolicy {
+    /// Backups the policy no longer keeps
+    pub fn prune<'b>(&self, backups: &'b [BackupInfo]) -> Vec<&'b BackupInfo> {
+        let mut newest_first: Vec<&BackupInfo> = backups.iter().collect();
+        newest_first.sort_by(|a, b| b.taken_at_ms.cmp(&a.taken_at_ms).then_with(|| b.id.cmp(&a.id)));
+
+        let mut keep: HashSet<&str> = newest_first.iter().take(self.keep_last).map(|b| b.id.as_str()).collect();
+        keep_newest_per_bucket(&newest_first, self.keep_daily, |ms| ms / DAY_MS, &mut keep);
+        // Day 4 of the epoch was the first Monday
+        keep_newest_per_bucket(&newest_first, self.keep_weekly, |ms| (ms / DAY_MS + 3) / 7, &mut keep);
+
+        newest_first.into_iter().filter(|b| !keep.contains(b.id.as_str())).collect()
+    }
+}
+
+/// Keep the newest backup in each of the `count` most recent buckets
+fn keep_newest_per_bucket<'b, F: Fn(u64) -> u64>(
+    newest_first: &[&'b BackupInfo],
+    count: usize,
+    bucket_of: F,
+    keep: &mut HashSet<&'b str>,
+) {
+    let mut last_bucket = None;
+    let mut buckets = 0;
+
+    for backup in newest_first {
+        if buckets >= count {
+            break;
+        }
+        let bucket = bucket_of(backup.taken_at_ms);
+        if last_bucket != Some(bucket) {
+            keep.insert(backup.id.as_str());
+            last_bucket = Some(bucket);
+            buckets += 1;
+        }
+    }
+}
+
+/// Running backup schedule; dropping it stops the scheduler
+pub struct BackupScheduler {
+    task: TaskHandle,
+}
+
+impl Drop for BackupScheduler {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl KVStore {
+    /// Take a backup now and record it in the catalog
+    pub async fn backup(&self) -> Result<BackupInfo> {
+        self.checkpoint().await?;
+
+        let data_snapshot = self.disk().snapshot().await?;
+        let lsn = self.wal().current_lsn();
+        let wal_snapshot = self.wal().snapshot().await?;
+
+        let taken_at_ms = now_ms();
+        let info = BackupInfo {
+            id: format!("{:013}", taken_at_ms),
+            taken_at_ms,
+            data_snapshot,
+            wal_snapshot,
+            lsn,
+        };
+
+        self.set(&catalog_key(&info.id), &serde_json::to_string(&info)?).await?;
+        info!("BACKUP: {} taken (LSN {})", info.id, lsn);
+        Ok(info)
+    }
+
+    /// All backups in the catalog, oldest first
+    pub async fn list_backups(&self) -> Result<Vec<BackupInfo>> {
+        let mut keys = self.keys_with_prefix(BACKUP_PREFIX);
+        keys.sort();
+
+        let mut backups = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.get(&key).await? {
+                backups.push(serde_json::from_str(&value)?);
+            }
+        }
+        Ok(backups)
+    }
+
+    /// Delete a backup's snapshots and catalog entry; returns whether it existed
+    pub async fn delete_backup(&self, id: &str) -> Result<bool> {
+        let info: BackupInfo = match self.get(&catalog_key(id)).await? {
+            Some(value) => serde_json::from_str(&value)?,
+            None => return Ok(false),
+        };
+
+        self.disk().delete_snapshot(&info.data_snapshot).await?;
+        self.wal().delete_snapshot(&info.wal_snapshot).await?;
+        self.delete(&catalog_key(id)).await?;
+
+        info!("BACKUP: {} deleted", id);
+        Ok(true)
+    }
+
+    /// Delete the backups `policy` doesn't keep; returns the IDs deleted
+    pub async fn prune_backups(&self, policy: &RetentionPolicy) -> Result<Vec<String>> {
+        let backups = self.list_backups().await?;
+        let mut deleted = Vec::new();
+
+        for backup in policy.prune(&backups) {
+            self.delete_backup(&backup.id).await?;
+            deleted.push(backup.id.clone());
+        }
+        Ok(deleted)
+    }
+
+    /// Run backups on the `backup_schedule` from the store options
+    ///
+    /// Returns None when no schedule is configured. Each run takes a backup
+    /// and then prunes by `backup_retention`; failures are logged and the
+    /// next run goes ahead as scheduled.
+    pub fn start_backup_scheduler(self: &Arc<Self>) -> Result<Option<BackupScheduler>> {
+        let schedule = match &self.options().backup_schedule {
+            Some(expr) => CronSchedule::parse(expr)?,
+            None => return Ok(None),
+        };
+
+        let store = Arc::clone(self);
+        let runtime = self.options().runtime.clone();
+        let task = runtime.clone().spawn(async move {
+            loop {
+                let now_secs = now_ms() / 1000;
+                let Some(next) = schedule.next_after(now_secs) else {
+                    warn!("BACKUP: schedule never fires again, stopping");
+                    return;
+                };
+                runtime.sleep(Duration::from_secs(next - now_secs)).await;
+
+                if let Err(e) = store.backup().await {
+                    warn!("BACKUP: scheduled backup failed: {}", e);
+                    store.options().listeners.error("backup", &e);
+                    continue;
+                }
+                let retention = store.options().backup_retention;
+                match store.prune_backups(&retention).await {
+                    Ok(deleted) if !deleted.is_empty() => info!("BACKUP: pruned {} old backups", deleted.len()),
+                    Ok(_) => {}
+                    Err(e) => warn!("BACKUP: pruning failed: {}", e),
+                }
+            }
+        });
+
+        Ok(Some(BackupScheduler { task }))
+    }
+}
+
+/// Snapshot a blob, returning the opaque snapshot ID
+pub(crate) async fn snapshot_blob(blob_client: &BlobClient) -> Result<String> {
+    let response = blob_client.snapshot().await?;
+    match serde_json::to_value(&response.snapshot)? {
+        serde_json::Value::String(id) => Ok(id),
+        other => anyhow::bail!("Unexpected snapshot ID {}", other),
+    }
+}
+
+/// Delete one snapshot of a blob
+pub(crate) async fn delete_blob_snapshot(blob_client: &BlobClient, snapshot: &str) -> Result<()> {
+    blob_client.delete_snapshot(Snapshot::new(snapshot.to_string())).await?;
+    Ok(())
+}
+
+fn catalog_key(id: &str) -> String {
+    format!("{}{}", BACKUP_PREFIX, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn backup_at(ms: u64) -> BackupInfo {
+        BackupInfo {
+            id: format!("{:013}", ms),
+            taken_at_ms: ms,
+            data_snapshot: String::new(),
+            wal_snapshot: String::new(),
+            lsn: 0,
+        }
+    }
+
+    #[test]
+    fn test_prune_keeps_daily_and_weekly() {
+        // Four backups a day for 60 days
+        let backups: Vec<BackupInfo> = (0..60 * 4).map(|i| backup_at(i * DAY_MS / 4)).collect();
+        let policy = RetentionPolicy { keep_last: 2, keep_daily: 7, keep_weekly: 4 };
+
+        let pruned = policy.prune(&backups);
+        let kept: Vec<&BackupInfo> = backups
+            .iter()
+            .filter(|b| !pruned.iter().any(|p| p.id == b.id))
+            .collect();
+
+        // 2 newest (the second is also the newest of its day), 6 more days,
+        // plus weeks not already covered by a daily backup
+        assert!(kept.len() >= 8 && kept.len() <= 2 + 7 + 4);
+        assert!(kept.iter().any(|b| b.taken_at_ms == 239 * DAY_MS / 4));
+        assert!(!kept.iter().any(|b| b.taken_at_ms < 30 * DAY_MS));
+    }
+
+    #[test]
+    fn test_prune_keep_last_only() {
+        let backups: Vec<BackupInfo> = (0..5).map(backup_at).collect();
+        let policy = RetentionPolicy { keep_last: 3, keep_daily: 0, keep_weekly: 0 };
+
+        let pruned: Vec<u64> = policy.prune(&backups).iter().map(|b| b.taken_at_ms).collect();
+        assert_eq!(pruned, vec![1, 0]);
+    }
+
+    #[test]
+    fn test_prune_empty_catalog() {
+        assert!(RetentionPolicy::default().prune(&[]).is_empty());
+    }
+}
@@ -0,0 +1,335 @@
+//! Backup Sets: Full and Incremental Page-Level Backups
+//!
+//! Unlike snapshot backups (backup.rs), a backup set is a standalone copy of
+//! the store's pages in a block blob under `backup-sets/<id>`, so it survives
+//! deletion of the data blob and can be restored into another container.
+//!
+//! A full set holds every allocated page; an incremental set holds only the
+//! pages written to the data blob since the previous set, plus the
+//! superblock. Both carry a copy of the WAL, so restoring replays the
+//! changes that hadn't reached the data pages yet. Restore layers a chain
+//! of incrementals onto its full set, newest page version winning.
+//!
+//! The changed-page set lives in memory only: after a restart the store
+//! doesn't know what changed since the last set, and the next incremental
+//! is taken as a full set instead.
+//!
+//! Set blob layout:
+//!
+//! ```text
+//! ["ICBKSET1"][page_count: u64 LE][wal_len: u64 LE]
+//! [page_id: u64 LE][page: 4096 bytes] x page_count
+//! [WAL bytes: wal_len]
+//! ```
+
+use anyhow::Result;
+use azure_storage_blobs::prelude::BlobClient;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use tracing::info;
+
+use crate::azure_disk::AzureDisk;
+use crate::error::IronCladError;
+use crate::kvstore::{KVStore, DATA_BLOB, WAL_BLOB};
+use crate::metadata::now_ms;
+use crate::page::PAGE_SIZE;
+use crate::superblock::{Superblock, FIRST_DATA_PAGE, SUPERBLOCK_PAGE};
+use crate::wal::WAL;
+
+/// Key prefix for backup set catalog entries
+const BACKUP_SET_PREFIX: &str = "__backupset/";
+
+/// Blob name prefix for backup set contents
+const BACKUP_SET_BLOB_PREFIX: &str = "backup-sets/";
+
+const MAGIC: &[u8; 8] = b"ICBKSET1";
+const HEADER_SIZE: usize = 8 + 8 + 8;
+const RECORD_SIZE: usize = 8 + PAGE_SIZE;
+
+/// Largest block accepted by a single append
+const MAX_APPEND_BLOCK: usize = 4 * 1024 * 1024;
+
+/// Whether a set stands alone or builds on an earlier one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackupSetKind {
+    Full,
+    /// Pages changed since the `parent` set
+    Incremental { parent: String },
+}
+
+/// Catalog entry for a backup set
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BackupSetInfo {
+    /// Catalog ID (sortable: later sets have larger IDs)
+    pub id: String,
+    pub kind: BackupSetKind,
+    pub taken_at_ms: u64,
+    /// Pages copied into this set
+    pub pages: usize,
+    /// WAL LSN when the WAL was copied
+    pub lsn: u64,
+}
+
+/// Decoded contents of a set blob
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BackupSet {
+    pub pages: BTreeMap<u64, Vec<u8>>,
+    pub wal: Vec<u8>,
+}
+
+impl BackupSet {
+    /// Serialize into the set blob layout
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(HEADER_SIZE + self.pages.len() * RECORD_SIZE + self.wal.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&(self.pages.len() as u64).to_le_bytes());
+        data.extend_from_slice(&(self.wal.len() as u64).to_le_bytes());
+        for (page_id, page) in &self.pages {
+            data.extend_from_slice(&page_id.to_le_bytes());
+            data.extend_from_slice(page);
+        }
+        data.extend_from_slice(&self.wal);
+        data
+    }
+
+    /// Parse a set blob, rejecting truncated or foreign data
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE || &data[0..8] != MAGIC {
+            anyhow::bail!("Not a backup set (bad magic)");
+        }
+
+        let page_count = read_u64(data, 8) as usize;
+        let wal_len = read_u64(data, 16) as usize;
+        let expected = page_count
+            .checked_mul(RECORD_SIZE)
+            .and_then(|pages| pages.checked_add(HEADER_SIZE))
+            .and_then(|len| len.checked_add(wal_len));
+        if expected != Some(data.len()) {
+            anyhow::bail!("Corrupt backup set: header doesn't match length {}", data.len());
+        }
+
+        let mut pages = BTreeMap::new();
+        for i in 0..page_count {
+            let offset = HEADER_SIZE + i * RECORD_SIZE;
+            let page_id = read_u64(data, offset);
+            pages.insert(page_id, data[offset + 8..offset + RECORD_SIZE].to_vec());
+        }
+        let wal = data[data.len() - wal_len..].to_vec();
+
+        Ok(Self { pages, wal })
+    }
+}
+
+/// Combine a chain of sets, oldest (the full set) first
+///
+/// Later sets' pages replace earlier ones; the WAL comes from the newest set.
+pub fn layer(chain: Vec<BackupSet>) -> BackupSet {
+    let mut combined = BackupSet::default();
+    for set in chain {
+        combined.pages.extend(set.pages);
+        combined.wal = set.wal;
+    }
+    combined
+}
+
+impl KVStore {
+    /// Take a full backup set
+    pub async fn full_backup(&self) -> Result<BackupSetInfo> {
+        self.take_backup_set(None).await
+    }
+
+    /// Take an incremental backup set on top of the newest set
+    ///
+    /// Falls back to a full set when there is no earlier set, or when the
+    /// changed pages aren't known (the store restarted since the last set).
+    pub async fn incremental_backup(&self) -> Result<BackupSetInfo> {
+        let parent = self.list_backup_sets().await?.pop();
+        match parent {
+            Some(parent) if self.changed_pages_known() => self.take_backup_set(Some(parent.id)).await,
+            _ => self.take_backup_set(None).await,
+        }
+    }
+
+    /// All backup sets in the catalog, oldest first
+    pub async fn list_backup_sets(&self) -> Result<Vec<BackupSetInfo>> {
+        let mut keys = self.keys_with_prefix(BACKUP_SET_PREFIX);
+        keys.sort();
+
+        let mut sets = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.get(&key).await? {
+                sets.push(serde_json::from_str(&value)?);
+            }
+        }
+        Ok(sets)
+    }
+
+    /// Restore a backup set (with its chain) into the store location `container`
+    ///
+    /// The target must not hold a store yet. Open it afterwards with
+    /// `StoreOptions { container, .. }`; recovery replays the restored WAL.
+    pub async fn restore_backup_set(&self, id: &str, connection_string: &str, container: &str) -> Result<usize> {
+        let mut chain = Vec::new();
+        let mut next = Some(id.to_string());
+        while let Some(id) = next {
+            let info = self
+                .backup_set_info(&id)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Backup set {} not found", id))?;
+            let blob = self.backup_set_blob(&id)?;
+            chain.push(BackupSet::decode(&blob.get_content().await?)?);
+            next = match info.kind {
+                BackupSetKind::Full => None,
+                BackupSetKind::Incremental { parent } => Some(parent),
+            };
+        }
+        chain.reverse();
+        let restored = layer(chain);
+
+        let disk = AzureDisk::new(connection_string, container, DATA_BLOB).await?;
+        let wal = WAL::new(connection_string, container, WAL_BLOB).await?;
+        if Superblock::decode(&disk.read_page(SUPERBLOCK_PAGE).await?)?.is_some() || !wal.is_empty().await? {
+            anyhow::bail!("Refusing to restore into container {}: it already holds a store", container);
+        }
+
+        // Pages first, the superblock last: until it lands the target reads as empty
+        for (page_id, page) in restored.pages.iter().filter(|(id, _)| **id != SUPERBLOCK_PAGE) {
+            disk.write_page(*page_id, page).await?;
+        }
+        for chunk in restored.wal.chunks(MAX_APPEND_BLOCK) {
+            wal.append_raw(chunk).await?;
+        }
+        if let Some(superblock) = restored.pages.get(&SUPERBLOCK_PAGE) {
+            disk.write_page(SUPERBLOCK_PAGE, superblock).await?;
+        }
+        disk.flush().await?;
+
+        info!("BACKUP SET: restored {} into {} ({} pages)", id, container, restored.pages.len());
+        Ok(restored.pages.len())
+    }
+
+    /// The blob backup set `id` is kept in, next to the data blob
+    fn backup_set_blob(&self, id: &str) -> Result<BlobClient> {
+        match self.disk().container_client() {
+            Some(container_client) => Ok(container_client.blob_client(blob_name(id))),
+            None => {
+                let reason = "backup sets are kept next to a page blob, and this store's pages aren't in one".to_string();
+                Err(IronCladError::NotSupported { operation: "backup_set", reason }.into())
+            }
+        }
+    }
+
+    async fn backup_set_info(&self, id: &str) -> Result<Option<BackupSetInfo>> {
+        match self.get(&catalog_key(id)).await? {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Copy pages (all, or those changed since `parent`) and the WAL into a new set
+    async fn take_backup_set(&self, parent: Option<String>) -> Result<BackupSetInfo> {
+        self.checkpoint().await?;
+
+        // Start tracking afresh: pages written while we copy land in the next set
+        let changed = self.take_changed_pages();
+        let page_ids: BTreeSet<u64> = match (&parent, changed.clone()) {
+            (Some(_), Some(changed)) => changed,
+            _ => (FIRST_DATA_PAGE..self.next_page_id()).collect(),
+        };
+
+        let result = self.copy_backup_set(parent, page_ids).await;
+        if result.is_err() {
+            // Keep the pages for the next attempt
+            if let Some(changed) = changed {
+                self.restore_changed_pages(changed);
+            }
+        }
+        result
+    }
+
+    async fn copy_backup_set(&self, parent: Option<String>, page_ids: BTreeSet<u64>) -> Result<BackupSetInfo> {
+        let mut set = BackupSet::default();
+        set.pages.insert(SUPERBLOCK_PAGE, self.disk().read_page(SUPERBLOCK_PAGE).await?);
+        for page_id in page_ids {
+            set.pages.insert(page_id, self.disk().read_page(page_id).await?);
+        }
+        let (lsn, wal) = self.wal().read_raw().await?;
+        set.wal = wal;
+
+        let taken_at_ms = now_ms();
+        let id = format!("{:013}", taken_at_ms);
+        let blob = self.backup_set_blob(&id)?;
+        blob.put_block_blob(Bytes::from(set.encode())).await?;
+
+        let info = BackupSetInfo {
+            id,
+            kind: match parent {
+                Some(parent) => BackupSetKind::Incremental { parent },
+                None => BackupSetKind::Full,
+            },
+            taken_at_ms,
+            pages: set.pages.len(),
+            lsn,
+        };
+        self.set(&catalog_key(&info.id), &serde_json::to_string(&info)?).await?;
+
+        info!("BACKUP SET: {} ({:?}, {} pages, LSN {})", info.id, info.kind, info.pages, lsn);
+        Ok(info)
+    }
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn catalog_key(id: &str) -> String {
+    format!("{}{}", BACKUP_SET_PREFIX, id)
+}
+
+fn blob_name(id: &str) -> String {
+    format!("{}{}", BACKUP_SET_BLOB_PREFIX, id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set_of(pages: &[(u64, u8)], wal: &[u8]) -> BackupSet {
+        BackupSet {
+            pages: pages.iter().map(|(id, fill)| (*id, vec![*fill; PAGE_SIZE])).collect(),
+            wal: wal.to_vec(),
+        }
+    }
+
+    #[test]
+    fn test_backup_set_round_trip() {
+        let set = set_of(&[(0, 1), (5, 2)], b"{\"Delete\":{\"key\":\"k\"}}\n");
+        assert_eq!(BackupSet::decode(&set.encode()).unwrap(), set);
+    }
+
+    #[test]
+    fn test_corrupt_backup_set() {
+        let encoded = set_of(&[(1, 1)], b"").encode();
+        assert!(BackupSet::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(BackupSet::decode(b"not a set").is_err());
+
+        let mut huge_count = encoded.clone();
+        huge_count[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(BackupSet::decode(&huge_count).is_err());
+    }
+
+    #[test]
+    fn test_layer_incrementals_onto_full() {
+        let full = set_of(&[(0, 0), (1, 1), (2, 1)], b"old");
+        let first = set_of(&[(0, 2), (2, 2)], b"mid");
+        let second = set_of(&[(0, 3), (3, 3)], b"new");
+
+        let restored = layer(vec![full, first, second]);
+        let fills: Vec<(u64, u8)> = restored.pages.iter().map(|(id, page)| (*id, page[0])).collect();
+        assert_eq!(fills, vec![(0, 3), (1, 1), (2, 2), (3, 3)]);
+        assert_eq!(restored.wal, b"new");
+    }
+}
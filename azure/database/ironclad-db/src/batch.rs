@@ -0,0 +1,126 @@
+//! Batch: Multi-Key Writes, All or Nothing or With a Per-Key Report
+//!
+//! `set_many` writes pairs as one atomic batch: all of them or, on the
+//! first pair that can't be written, none. Bulk pipelines that would
+//! rather log a bad row and go on use `set_many_report`, which writes what
+//! it can in one batch and reports what happened to each pair, in order:
+//!
+//! ```ignore
+//! let outcomes = store.set_many_report(&rows).await?;
+//! for ((key, _), outcome) in rows.iter().zip(&outcomes) {
+//!     if !outcome.is_written() {
+//!         warn!("{} not written: {:?}", key, outcome);
+//!     }
+//! }
+//! ```
+//!
+//! - `Inserted` / `Updated`: written; the key was new / already held a value
+//! - `Conflicted`: not written, because another writer changed the key
+//!   after the call started. The batch is optimistic: it never clobbers a
+//!   value newer than itself.
+//! - `TooLarge`: not written; key and value (as stored, after middleware
+//!   and encryption) don't fit in a page
+//! - `Invalid`: not written; the value breaks a rule of its bucket's
+//!   validator (see validation.rs)
+//!
+//! Anything else (the WAL append failing, a quota refusing the batch)
+//! still fails the whole call with nothing written. Imports can report
+//! the same way (see `ImportJob::with_conflict_report`).
+
+use anyhow::Result;
+use std::collections::HashSet;
+use tracing::debug;
+
+use crate::kvstore::KVStore;
+use crate::page;
+use crate::wal::WalEntry;
+
+/// What `set_many_report` did with a pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteOutcome {
+    Inserted,
+    Updated,
+    Conflicted,
+    TooLarge,
+    Invalid,
+}
+
+impl WriteOutcome {
+    /// Was the pair written?
+    pub fn is_written(&self) -> bool {
+        matches!(self, WriteOutcome::Inserted | WriteOutcome::Updated)
+    }
+}
+
+impl KVStore {
+    /// Write `pairs` as one atomic batch
+    pub async fn set_many(&self, pairs: &[(String, String)]) -> Result<()> {
+        let writes = pairs.iter().map(|(key, value)| WalEntry::set(key, value)).collect();
+        self.commit_batch(self.new_txn_id(), writes, None).await?;
+        Ok(())
+    }
+
+    /// Write the pairs of `pairs` that can be written, as one batch, and
+    /// report each pair's outcome (see batch.rs)
+    pub async fn set_many_report(&self, pairs: &[(String, String)]) -> Result<Vec<WriteOutcome>> {
+        let since_seq = self.commit_seq();
+        self.commit_reported(pairs, since_seq, Vec::new()).await
+    }
+
+    /// Write the pairs not written by others after `since_seq` that are
+    /// valid and fit in a page, plus `extra` unconditionally, as one batch
+    pub(crate) async fn commit_reported(
+        &self,
+        pairs: &[(String, String)],
+        since_seq: u64,
+        extra: Vec<WalEntry>,
+    ) -> Result<Vec<WriteOutcome>> {
+        let _commit_guard = self.lock_commits().await;
+
+        let mut outcomes = Vec::with_capacity(pairs.len());
+        let mut writes = Vec::with_capacity(pairs.len() + extra.len());
+        let mut batch_keys = HashSet::new();
+        for (key, value) in pairs {
+            if self.key_version(key) > since_seq {
+                outcomes.push(WriteOutcome::Conflicted);
+                continue;
+            }
+            if self.validate(key, value).is_err() {
+                outcomes.push(WriteOutcome::Invalid);
+                continue;
+            }
+            let stored = self.stored_value(key, value).await?;
+            if page::encode_kv_page(key, &stored).is_err() {
+                outcomes.push(WriteOutcome::TooLarge);
+                continue;
+            }
+            let existed = self.index().get(key).is_some() || !batch_keys.insert(key.as_str());
+            outcomes.push(if existed { WriteOutcome::Updated } else { WriteOutcome::Inserted });
+            writes.push(WalEntry::set(key, &stored));
+        }
+        let skipped = pairs.len() - writes.len();
+        writes.extend(self.transform_writes(extra).await?);
+
+        if !writes.is_empty() {
+            self.commit_stored_locked(self.new_txn_id(), writes, None).await?;
+        }
+        if skipped > 0 {
+            debug!("BATCH: {} of {} pairs not written", skipped, pairs.len());
+        }
+        Ok(outcomes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_inserts_and_updates_are_written() {
+        assert!(WriteOutcome::Inserted.is_written());
+        assert!(WriteOutcome::Updated.is_written());
+        assert!(!WriteOutcome::Conflicted.is_written());
+        assert!(!WriteOutcome::TooLarge.is_written());
+        assert!(!WriteOutcome::Invalid.is_written());
+    }
+}
@@ -0,0 +1,80 @@
+use ironclad_db::BufferPool;
+use rand::Rng;
+use std::env;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PAGE_SIZE: usize = 4096;
+
+/// Buffer pool contention benchmark: reader threads hammer `get_page`
+/// while one writer puts pages, timing each put. Writer latency under
+/// read load shows whether readers starve writers. Needs no Azure account.
+fn main() {
+    let args: Vec<usize> = env::args().skip(1).map(|arg| arg.parse().expect("arguments are numbers")).collect();
+    let readers = args.first().copied().unwrap_or(8);
+    let seconds = args.get(1).copied().unwrap_or(5) as u64;
+    let frames = args.get(2).copied().unwrap_or(4_096);
+
+    println!("\n⚔️  BUFFER POOL CONTENTION ({} readers, 1 writer, {} frames, {}s)", readers, frames, seconds);
+    println!("==================================================");
+
+    let pool = Arc::new(BufferPool::with_admission(frames, false));
+    // A quarter of the pages the writer touches don't fit, so puts also evict
+    let pages = frames as u64 * 5 / 4;
+    for page_id in 0..frames as u64 {
+        pool.put_page_at(page_id, vec![0u8; PAGE_SIZE], page_id).expect("pool has room");
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let reads = Arc::new(AtomicU64::new(0));
+    let reader_threads: Vec<_> = (0..readers)
+        .map(|_| {
+            let (pool, stop, reads) = (pool.clone(), stop.clone(), reads.clone());
+            thread::spawn(move || {
+                let mut rng = rand::thread_rng();
+                while !stop.load(Ordering::Relaxed) {
+                    pool.get_page(rng.gen_range(0..pages));
+                    reads.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+        })
+        .collect();
+
+    let writer = {
+        let (pool, stop) = (pool.clone(), stop.clone());
+        thread::spawn(move || {
+            let mut rng = rand::thread_rng();
+            let mut latencies = Vec::new();
+            let mut lsn = pages;
+            while !stop.load(Ordering::Relaxed) {
+                lsn += 1;
+                let started = Instant::now();
+                pool.put_page_at(rng.gen_range(0..pages), vec![1u8; PAGE_SIZE], lsn).expect("steal policy evicts");
+                latencies.push(started.elapsed());
+                // Write-back is the store's job; keep the set from growing
+                for page in pool.pending_writebacks() {
+                    pool.complete_writeback(page.page_id, page.page_lsn);
+                }
+            }
+            latencies
+        })
+    };
+
+    thread::sleep(Duration::from_secs(seconds));
+    stop.store(true, Ordering::Relaxed);
+    let mut latencies = writer.join().expect("writer finished");
+    for reader in reader_threads {
+        reader.join().expect("reader finished");
+    }
+
+    latencies.sort();
+    let percentile = |p: f64| latencies.get(((latencies.len() as f64 * p) as usize).min(latencies.len().saturating_sub(1)));
+    println!("  Reads:          {:>12.0}/s", reads.load(Ordering::Relaxed) as f64 / seconds as f64);
+    println!("  Writes:         {:>12.0}/s", latencies.len() as f64 / seconds as f64);
+    for (label, p) in [("p50", 0.5), ("p99", 0.99), ("p99.9", 0.999)] {
+        println!("  Write {:<6}    {:>12.1?}", label, percentile(p).copied().unwrap_or_default());
+    }
+    println!("  Write max       {:>12.1?}", latencies.last().copied().unwrap_or_default());
+}
@@ -0,0 +1,52 @@
+use futures::StreamExt;
+use ironclad_db::{DiffEntry, KVStore, StoreOptions};
+use std::env;
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 3 {
+        eprintln!("Usage: {} <container-a> <container-b>", args[0]);
+        eprintln!("Compares two stores key by key (A -> B).");
+        eprintln!("Opening a store fences out its current writer: run against stopped stores or restored copies.");
+        std::process::exit(2);
+    }
+
+    let connection_string = match env::var("AZURE_STORAGE_CONNECTION_STRING") {
+        Ok(val) => val,
+        Err(_) => {
+            eprintln!("❌ AZURE_STORAGE_CONNECTION_STRING not set. Source the .env file first.");
+            std::process::exit(2);
+        }
+    };
+
+    let open = |container: &str| {
+        let options = StoreOptions {
+            container: container.to_string(),
+            ..StoreOptions::default()
+        };
+        KVStore::open(&connection_string, options)
+    };
+    let a = open(&args[1]).await?;
+    let b = open(&args[2]).await?;
+
+    let mut differences = 0;
+    let mut diff = Box::pin(a.diff(&b));
+    while let Some(entry) = diff.next().await {
+        match entry? {
+            DiffEntry::Added { key, value } => println!("+ {} = {}", key, value),
+            DiffEntry::Removed { key, value } => println!("- {} = {}", key, value),
+            DiffEntry::Changed { key, old, new } => println!("~ {}: {} -> {}", key, old, new),
+        }
+        differences += 1;
+    }
+
+    println!("{} difference(s)", differences);
+    // Like diff(1): exit status 1 when the stores differ
+    if differences > 0 {
+        std::process::exit(1);
+    }
+    Ok(())
+}
@@ -2,6 +2,7 @@ use ironclad_db::KVStore;
 use std::env;
 use std::time::Instant;
 use rand::Rng;
+use base64::Engine;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
@@ -38,7 +39,7 @@ async fn main() -> anyhow::Result<()> {
         let key = format!("drop:{}", i);
         let mut value = vec![0u8; drop_size];
         rng.fill(&mut value[..]);
-        let value_str = base64::encode(&value); // Store as string for simplicity
+        let value_str = base64::engine::general_purpose::STANDARD.encode(&value); // Store as string for simplicity
 
         store.set(&key, &value_str).await?;
 
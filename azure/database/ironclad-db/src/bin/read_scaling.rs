@@ -0,0 +1,61 @@
+use ironclad_db::BufferPool;
+use rand::Rng;
+use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+const PAGE_SIZE: usize = 4096;
+
+/// Buffer pool read scaling benchmark: all-hit `get_page` storms with 1,
+/// 2, 4, ... reader threads up to the core count. With no lock or shared
+/// counter written on every read, reads/s grows with the readers. Needs no
+/// Azure account.
+fn main() {
+    let args: Vec<usize> = env::args().skip(1).map(|arg| arg.parse().expect("arguments are numbers")).collect();
+    let seconds = args.first().copied().unwrap_or(2) as u64;
+    let frames = args.get(1).copied().unwrap_or(4_096);
+    let cores = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let max_readers = args.get(2).copied().unwrap_or(cores);
+
+    println!("\n📈 BUFFER POOL READ SCALING ({} frames, {}s per step, {} cores)", frames, seconds, cores);
+    println!("==================================================");
+
+    // Default pool: TinyLFU admission and heat tracking on, as in the store
+    let pool = Arc::new(BufferPool::with_capacity(frames));
+    for page_id in 0..frames as u64 {
+        pool.put_page_at(page_id, vec![0u8; PAGE_SIZE], page_id).expect("pool has room");
+    }
+
+    let mut baseline = None;
+    let mut readers = 1;
+    while readers <= max_readers {
+        let reads = storm(&pool, readers, frames as u64, Duration::from_secs(seconds)) as f64 / seconds as f64;
+        let baseline = *baseline.get_or_insert(reads);
+        println!("  {:>3} readers: {:>12.0} reads/s  ({:.2}x)", readers, reads, reads / baseline);
+        readers *= 2;
+    }
+}
+
+/// Reads done by `readers` threads over `duration`, each counting its own
+fn storm(pool: &Arc<BufferPool>, readers: usize, pages: u64, duration: Duration) -> u64 {
+    let stop = Arc::new(AtomicBool::new(false));
+    let threads: Vec<_> = (0..readers)
+        .map(|_| {
+            let (pool, stop) = (pool.clone(), stop.clone());
+            thread::spawn(move || {
+                let mut rng = rand::thread_rng();
+                let mut reads = 0u64;
+                while !stop.load(Ordering::Relaxed) {
+                    pool.get_page(rng.gen_range(0..pages));
+                    reads += 1;
+                }
+                reads
+            })
+        })
+        .collect();
+    thread::sleep(duration);
+    stop.store(true, Ordering::Relaxed);
+    threads.into_iter().map(|reader| reader.join().expect("reader finished")).sum()
+}
@@ -0,0 +1,116 @@
+//! Blocking: A Synchronous Facade Over the Store
+//!
+//! For CLI tools and sync codebases that only need to read or write a few
+//! keys without adopting async:
+//!
+//! ```ignore
+//! let store = ironclad_db::blocking::KVStore::new(&connection_string)?;
+//! store.set("user:1", "alice")?;
+//! assert_eq!(store.get("user:1")?, Some("alice".to_string()));
+//! ```
+//!
+//! The facade owns a Tokio runtime and blocks the calling thread on each
+//! call. Background work (lock renewal, group commit, key refreshes) runs
+//! on the runtime's worker threads, so it keeps going between calls.
+//!
+//! Don't use it from async code: blocking inside a Tokio runtime panics.
+//! Call the async store instead, or reach it with `as_async`. Clones share
+//! the store and the runtime.
+//!
+//! Only the common operations are wrapped; for anything else, use
+//! `block_on` with the async store:
+//!
+//! ```ignore
+//! let rows = store.block_on(store.as_async().scan())?;
+//! ```
+
+use anyhow::Result;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::kvstore::{KVStore as AsyncKVStore, KVStoreStats};
+use crate::options::StoreOptions;
+
+/// A store whose operations block the calling thread
+#[derive(Clone)]
+pub struct KVStore {
+    inner: AsyncKVStore,
+    runtime: Arc<tokio::runtime::Runtime>,
+}
+
+impl KVStore {
+    /// Open with default options (see `crate::KVStore::new`)
+    pub fn new(connection_string: &str) -> Result<Self> {
+        Self::open(connection_string, StoreOptions::default())
+    }
+
+    /// Open with `options` (see `crate::KVStore::open`)
+    pub fn open(connection_string: &str, options: StoreOptions) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("ironclad-blocking")
+            .build()?;
+        let inner = runtime.block_on(AsyncKVStore::open(connection_string, options))?;
+        Ok(Self { inner, runtime: Arc::new(runtime) })
+    }
+
+    /// The async store underneath
+    pub fn as_async(&self) -> &AsyncKVStore {
+        &self.inner
+    }
+
+    /// Run `future` to completion on the facade's runtime
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.runtime.block_on(future)
+    }
+
+    pub fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.block_on(self.inner.set(key, value))
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<String>> {
+        self.block_on(self.inner.get(key))
+    }
+
+    pub fn delete(&self, key: &str) -> Result<bool> {
+        self.block_on(self.inner.delete(key))
+    }
+
+    pub fn set_nx(&self, key: &str, value: &str) -> Result<bool> {
+        self.block_on(self.inner.set_nx(key, value))
+    }
+
+    pub fn compare_and_set(&self, key: &str, expected: Option<&str>, value: &str) -> Result<bool> {
+        self.block_on(self.inner.compare_and_set(key, expected, value))
+    }
+
+    pub fn compare_and_delete(&self, key: &str, expected: &str) -> Result<bool> {
+        self.block_on(self.inner.compare_and_delete(key, expected))
+    }
+
+    pub fn scan(&self) -> Result<Vec<(String, String)>> {
+        self.block_on(self.inner.scan())
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.block_on(self.inner.flush())
+    }
+
+    pub fn checkpoint(&self) -> Result<()> {
+        self.block_on(self.inner.checkpoint())
+    }
+
+    pub fn stats(&self) -> KVStoreStats {
+        self.inner.stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_errors_are_returned_from_sync_code() {
+        assert!(KVStore::new("not a connection string").is_err());
+    }
+}
@@ -0,0 +1,249 @@
+//! Bootstrap: Crash-Safe, Idempotent Store Creation
+//!
+//! Creating a store takes several independent steps (container, data blob,
+//! WAL blob, superblock). Each step is create-if-missing and tolerates a
+//! concurrent creator, and the superblock doubles as a creation marker
+//! written in two phases:
+//!
+//! 1. A superblock in state `Creating` is written once the blobs exist.
+//! 2. When every other step has finished, it is rewritten as `Ready`.
+//!
+//! On open:
+//!
+//! | Superblock | WAL                  | Meaning                          | Action                          |
+//! |------------|----------------------|----------------------------------|---------------------------------|
+//! | none       | empty                | new store, or crashed before (1) | run the full bootstrap          |
+//! | `Creating` | any                  | crashed between (1) and (2)      | clean the WAL, finish bootstrap |
+//! | `Ready`    | present              | fully created                    | open normally                   |
+//! | none       | never cleared        | data blob deleted (or page 0)    | rebuild the data from the WAL   |
+//! | none       | cleared              | data blob deleted (or page 0)    | fail: `ComponentLost`           |
+//! | `Ready`    | blob deleted         | WAL deleted                      | fail: `ComponentLost`           |
+//!
+//! No writes are accepted before the store is `Ready`, so a `Creating` store
+//! never holds user data and its WAL can safely be reset.
+//!
+//! Losing one blob of a store but not the other (a partial container
+//! cleanup, a mistaken delete) is told apart from a new store:
+//!
+//! - A WAL never cleared by a checkpoint holds every write since the store
+//!   was created, so a lost data blob is rebuilt from it: the superblock
+//!   is written afresh and recovery redoes every write. Once a checkpoint
+//!   has cleared it, writes before the checkpoint are only in the data
+//!   blob, and the open fails; restore a backup set instead.
+//! - Writes since the last checkpoint are only in the WAL (unless flushed),
+//!   so a lost WAL fails the open too. With `StoreOptions::accept_lost_wal`
+//!   the store opens on a new, empty WAL instead, with the data blob as of
+//!   its last flush. The WAL only counts as lost when its backend can tell
+//!   (an append blob or local file that wasn't there before the open).
+//!
+//! Page blob creation can't be made conditional with the SDK, so two
+//! processes creating the *same new* store at the same instant can still
+//! race; opens of existing stores are unaffected.
+
+use anyhow::Result;
+use azure_core::prelude::IfMatchCondition;
+use azure_core::StatusCode;
+use azure_storage_blobs::prelude::*;
+use tracing::{info, warn};
+
+use crate::page_store::PageStore;
+use crate::error::IronCladError;
+use crate::shadow::Durability;
+use crate::superblock::{StoreState, Superblock, SUPERBLOCK_PAGE};
+use crate::wal::WAL;
+
+/// What an open has to do, judging by the superblock and WAL found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BootstrapAction {
+    /// No superblock and an empty WAL: create the store from scratch
+    Create,
+    /// Bootstrap was interrupted: clean up and finish it
+    Complete,
+    /// Store is ready
+    Open,
+    /// The data blob is gone but the WAL holds every write: rebuild from it
+    RebuildData,
+    /// The WAL is gone: carry on from the data blob with a new one
+    /// (only with `accept_lost_wal`)
+    ReplaceWal,
+}
+
+/// State of the WAL found on open
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalState {
+    Empty,
+    /// Has records and was never cleared: every write since creation
+    FullHistory,
+    /// Has records, opening with a checkpoint that cleared older ones
+    Cleared,
+    /// The log's blob (or file) didn't exist before this open
+    Lost,
+}
+
+/// Decide what to do with a store, or refuse one that can't be recovered
+pub fn classify(superblock: Option<&Superblock>, wal: WalState, accept_lost_wal: bool) -> Result<BootstrapAction> {
+    match (superblock.map(|sb| sb.state), wal) {
+        (Some(StoreState::Creating), _) => Ok(BootstrapAction::Complete),
+        (Some(StoreState::Ready), WalState::Lost) if accept_lost_wal => Ok(BootstrapAction::ReplaceWal),
+        (Some(StoreState::Ready), WalState::Lost) => Err(IronCladError::ComponentLost {
+            component: "WAL",
+            reason: "writes since the last checkpoint may be lost with it; restore a backup set, or open with \
+                     StoreOptions::accept_lost_wal to carry on from the data blob as of its last flush"
+                .to_string(),
+        }
+        .into()),
+        (Some(StoreState::Ready), _) => Ok(BootstrapAction::Open),
+        // Nothing to lose yet
+        (None, WalState::Empty | WalState::Lost) => Ok(BootstrapAction::Create),
+        (None, WalState::FullHistory) => Ok(BootstrapAction::RebuildData),
+        (None, WalState::Cleared) => Err(IronCladError::ComponentLost {
+            component: "data blob",
+            reason: "it has no superblock and a checkpoint has cleared the WAL of the writes before it; \
+                     restore a backup set (see backup_set.rs)"
+                .to_string(),
+        }
+        .into()),
+    }
+}
+
+/// Bring the store to the `Ready` state and return its superblock; a new
+/// store records `durability`. `wal_lost`: the WAL's blob was missing
+/// before the open
+pub(crate) async fn run(
+    disk: &dyn PageStore,
+    wal: &WAL,
+    durability: Durability,
+    wal_lost: bool,
+    accept_lost_wal: bool,
+) -> Result<Superblock> {
+    let page = disk.read_page(SUPERBLOCK_PAGE).await?;
+    let existing = Superblock::decode(&page)?;
+
+    let wal_state = if wal_lost {
+        WalState::Lost
+    } else if wal.is_empty().await? {
+        WalState::Empty
+    } else if existing.is_some() {
+        // Only needed to tell a lost data blob apart; spares reading the log
+        WalState::FullHistory
+    } else if wal.was_cleared().await? {
+        WalState::Cleared
+    } else {
+        WalState::FullHistory
+    };
+
+    match classify(existing.as_ref(), wal_state, accept_lost_wal)? {
+        BootstrapAction::Open => return Ok(existing.unwrap_or_default()),
+        BootstrapAction::ReplaceWal => {
+            warn!("Bootstrap: the WAL is gone; carrying on from the data blob with a new one");
+            return Ok(existing.unwrap_or_default());
+        }
+        BootstrapAction::Create => {
+            info!("Bootstrap: creating new store");
+            let creating = Superblock { state: StoreState::Creating, durability, ..Default::default() };
+            write_superblock(disk, &creating).await?;
+        }
+        BootstrapAction::RebuildData => {
+            // Recovery redoes every write in the log onto fresh pages
+            warn!("Bootstrap: the data blob has no superblock; rebuilding it from the WAL");
+        }
+        BootstrapAction::Complete => {
+            warn!("Bootstrap: previous store creation was interrupted, completing it");
+            // Nothing can have been committed before the store was ready
+            if !wal.is_empty().await? {
+                wal.clear().await?;
+            }
+        }
+    }
+
+    let ready = Superblock {
+        state: StoreState::Ready,
+        ..existing.unwrap_or_default()
+    };
+    write_superblock(disk, &ready).await?;
+
+    info!("Bootstrap: store ready");
+    Ok(ready)
+}
+
+async fn write_superblock(disk: &dyn PageStore, superblock: &Superblock) -> Result<()> {
+    disk.write_page(SUPERBLOCK_PAGE, &superblock.encode()?).await?;
+    disk.flush().await
+}
+
+/// Create the container unless it exists (possibly created concurrently)
+pub(crate) async fn ensure_container(container_client: &ContainerClient) -> Result<()> {
+    if container_client.exists().await? {
+        return Ok(());
+    }
+
+    info!("Creating container {}", container_client.container_name());
+    match container_client.create().await {
+        Err(e) if is_already_exists(&e) => Ok(()),
+        result => Ok(result.map(|_| ())?),
+    }
+}
+
+/// Create an empty append blob unless one exists, never replacing an existing log
+pub(crate) async fn ensure_append_blob(blob_client: &BlobClient) -> Result<()> {
+    if blob_client.exists().await? {
+        return Ok(());
+    }
+
+    info!("Creating Append Blob: {}", blob_client.blob_name());
+    let result = blob_client
+        .put_append_blob()
+        .if_match(IfMatchCondition::NotMatch("*".to_string()))
+        .await;
+    match result {
+        Err(e) if is_already_exists(&e) => Ok(()),
+        result => Ok(result.map(|_| ())?),
+    }
+}
+
+/// Did a create fail only because someone else created the resource first?
+fn is_already_exists(error: &azure_core::Error) -> bool {
+    error.as_http_error().is_some_and(|http| {
+        matches!(http.status(), StatusCode::Conflict | StatusCode::PreconditionFailed)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify() {
+        let creating = Superblock { state: StoreState::Creating, ..Default::default() };
+        let ready = Superblock::default();
+
+        assert_eq!(classify(None, WalState::Empty, false).unwrap(), BootstrapAction::Create);
+        assert_eq!(classify(Some(&creating), WalState::Empty, false).unwrap(), BootstrapAction::Complete);
+        assert_eq!(classify(Some(&creating), WalState::FullHistory, false).unwrap(), BootstrapAction::Complete);
+        assert_eq!(classify(Some(&ready), WalState::FullHistory, false).unwrap(), BootstrapAction::Open);
+        assert_eq!(classify(Some(&ready), WalState::Cleared, false).unwrap(), BootstrapAction::Open);
+    }
+
+    #[test]
+    fn test_refuses_log_without_superblock_once_cleared() {
+        let err = classify(None, WalState::Cleared, false).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IronCladError>(),
+            Some(IronCladError::ComponentLost { component: "data blob", .. })
+        ));
+        assert_eq!(classify(None, WalState::FullHistory, false).unwrap(), BootstrapAction::RebuildData);
+    }
+
+    #[test]
+    fn test_lost_wal_fails_unless_accepted() {
+        let ready = Superblock::default();
+        let err = classify(Some(&ready), WalState::Lost, false).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IronCladError>(),
+            Some(IronCladError::ComponentLost { component: "WAL", .. })
+        ));
+        assert_eq!(classify(Some(&ready), WalState::Lost, true).unwrap(), BootstrapAction::ReplaceWal);
+        // A store whose blobs are both gone is a new one
+        assert_eq!(classify(None, WalState::Lost, false).unwrap(), BootstrapAction::Create);
+    }
+}
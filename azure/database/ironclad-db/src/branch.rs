@@ -0,0 +1,476 @@
+//! Branch: Copy-on-Write Views of the Store
+//!
+//! A branch reads the main store's keys but keeps its writes to itself, so
+//! a migration or a risky batch job can run against production data and be
+//! thrown away afterwards:
+//!
+//! ```ignore
+//! let branch = store.branch("experiment-1").await?;
+//! branch.set("user:1", &migrated).await?;    // main still has the old value
+//! assert_eq!(branch.get("user:1").await?, Some(migrated));
+//! store.drop_branch("experiment-1").await?;  // gone without a trace
+//! ```
+//!
+//! Nothing is copied when a branch is created. A branch's writes (its
+//! delta) are stored under `__brd/<id>/<key>`; a key the branch hasn't
+//! written reads through to the main store, sharing its pages. The branch
+//! is therefore not a frozen snapshot: changes made to main after the
+//! branch was created show through for keys the branch hasn't written.
+//!
+//! Each delta entry records a hash of the main value it replaced (taken
+//! when the branch first wrote the key), so merging the branch back can
+//! tell which keys main changed in the meantime.
+//!
+//! `merge_branch` applies a branch's changes to main in one transaction
+//! and empties the branch. A key main also changed since the branch first
+//! wrote it is a conflict, settled by the `ConflictPolicy`:
+//!
+//! - `LastWriterWins`: whichever side wrote the key last (by LSN) wins; a
+//!   deletion in main counts as older than any branch write
+//! - `Fail`: nothing is merged; the error lists every conflicting key
+//! - `Resolve`: a callback picks the merged value
+//!
+//! Definitions live under `__branch/<name>`, and like column families a
+//! branch's data is keyed by an ID chosen at creation, so a dropped branch
+//! never leaks keys into a new one of the same name.
+
+use anyhow::Result;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::info;
+
+use crate::collections::validate_name;
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::metadata::{self, KeyMetadata};
+use crate::superblock::checksum;
+use crate::trash;
+use crate::wal::WalEntry;
+
+/// Key prefix for branch definitions
+const DEFINITION_PREFIX: &str = "__branch/";
+
+/// Key prefix for branch deltas
+const DATA_PREFIX: &str = "__brd/";
+
+/// Persisted branch definition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Definition {
+    id: String,
+    created_at_ms: u64,
+}
+
+/// Stored form of a delta entry
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Delta {
+    /// The branch's value (None = deleted in the branch)
+    value: Option<String>,
+    /// Hash of main's value when the branch first wrote the key (None = absent)
+    base: Option<u64>,
+}
+
+/// A key the branch changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BranchChange {
+    pub key: String,
+    /// The branch's value (None = deleted in the branch)
+    pub value: Option<String>,
+    /// Hash of the main value the change was based on
+    pub(crate) base: Option<u64>,
+}
+
+/// Picks the merged value of a conflicting key (None = delete it)
+pub type ConflictResolver = Box<dyn Fn(&MergeConflict) -> Option<String> + Send + Sync>;
+
+/// How `merge_branch` settles keys changed in both main and the branch
+pub enum ConflictPolicy {
+    LastWriterWins,
+    Fail,
+    Resolve(ConflictResolver),
+}
+
+impl std::fmt::Debug for ConflictPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConflictPolicy::LastWriterWins => f.write_str("LastWriterWins"),
+            ConflictPolicy::Fail => f.write_str("Fail"),
+            ConflictPolicy::Resolve(_) => f.write_str("Resolve"),
+        }
+    }
+}
+
+/// A key changed in both main and the branch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    pub key: String,
+    /// Main's current value (None = deleted)
+    pub main: Option<String>,
+    /// The branch's value (None = deleted)
+    pub branch: Option<String>,
+}
+
+/// Outcome of a merge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct MergeReport {
+    /// Keys of main the merge changed
+    pub applied: usize,
+    /// Keys changed on both sides
+    pub conflicts: usize,
+    /// Conflicts settled in main's favour
+    pub kept_main: usize,
+}
+
+/// Hash of a main value, as recorded in delta entries
+pub(crate) fn value_hash(value: Option<&str>) -> Option<u64> {
+    value.map(|value| checksum(value.as_bytes()))
+}
+
+/// Handle to a branch
+pub struct StoreBranch<'a> {
+    store: &'a KVStore,
+    name: String,
+    prefix: String,
+    created_at_ms: u64,
+}
+
+impl<'a> StoreBranch<'a> {
+    fn new(store: &'a KVStore, name: &str, definition: Definition) -> Self {
+        Self {
+            store,
+            name: name.to_string(),
+            prefix: format!("{}{}/", DATA_PREFIX, definition.id),
+            created_at_ms: definition.created_at_ms,
+        }
+    }
+
+    /// Branch name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// When the branch was created (Unix ms)
+    pub fn created_at_ms(&self) -> u64 {
+        self.created_at_ms
+    }
+
+    /// A key's value as the branch sees it
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        match self.delta(key).await? {
+            Some(delta) => Ok(delta.value),
+            None => self.store.get(key).await,
+        }
+    }
+
+    /// Set a key in the branch only
+    pub async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.write(key, Some(value)).await
+    }
+
+    /// Delete a key in the branch only; returns whether the branch had it
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        let existed = self.get(key).await?.is_some();
+        if existed {
+            self.write(key, None).await?;
+        }
+        Ok(existed)
+    }
+
+    /// All key-value pairs as the branch sees them (reserved keys excluded)
+    pub async fn scan(&self) -> Result<Vec<(String, String)>> {
+        let mut entries: BTreeMap<String, String> = self
+            .store
+            .scan()
+            .await?
+            .into_iter()
+            .filter(|(key, _)| !trash::is_reserved(key))
+            .collect();
+        for change in self.changes().await? {
+            match change.value {
+                Some(value) => entries.insert(change.key, value),
+                None => entries.remove(&change.key),
+            };
+        }
+        Ok(entries.into_iter().collect())
+    }
+
+    /// The keys the branch changed, in key order
+    pub async fn changes(&self) -> Result<Vec<BranchChange>> {
+        let mut changes = Vec::new();
+        for data_key in self.store.keys_with_prefix(&self.prefix) {
+            let key = &data_key[self.prefix.len()..];
+            if let Some(delta) = self.delta(key).await? {
+                changes.push(BranchChange { key: key.to_string(), value: delta.value, base: delta.base });
+            }
+        }
+        changes.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(changes)
+    }
+
+    /// Undo the branch's change to `key`, so it reads through to main
+    /// again; returns whether the branch had changed it
+    pub async fn discard(&self, key: &str) -> Result<bool> {
+        self.store.delete(&self.data_key(key)).await
+    }
+
+    async fn write(&self, key: &str, value: Option<&str>) -> Result<()> {
+        // The base is the main value the branch's first write replaced
+        let base = match self.delta(key).await? {
+            Some(delta) => delta.base,
+            None => value_hash(self.store.get(key).await?.as_deref()),
+        };
+        let delta = Delta { value: value.map(str::to_string), base };
+        self.store.set(&self.data_key(key), &serde_json::to_string(&delta)?).await
+    }
+
+    async fn delta(&self, key: &str) -> Result<Option<Delta>> {
+        match self.store.get(&self.data_key(key)).await? {
+            Some(stored) => Ok(Some(serde_json::from_str(&stored)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn data_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+impl KVStore {
+    /// Create a branch; fails with `BranchExists` if the name is taken
+    pub async fn branch(&self, name: &str) -> Result<StoreBranch<'_>> {
+        validate_name(name)?;
+
+        let definition = Definition {
+            id: format!("{:016x}", rand::thread_rng().gen::<u64>()),
+            created_at_ms: metadata::now_ms(),
+        };
+
+        if !self.set_nx(&definition_key(name), &serde_json::to_string(&definition)?).await? {
+            return Err(IronCladError::BranchExists { name: name.to_string() }.into());
+        }
+
+        info!("Created branch {} (id {})", name, definition.id);
+        Ok(StoreBranch::new(self, name, definition))
+    }
+
+    /// Open an existing branch
+    pub async fn open_branch(&self, name: &str) -> Result<Option<StoreBranch<'_>>> {
+        validate_name(name)?;
+
+        match self.get(&definition_key(name)).await? {
+            Some(stored) => {
+                let definition: Definition = serde_json::from_str(&stored)?;
+                Ok(Some(StoreBranch::new(self, name, definition)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Names of all branches
+    pub fn list_branches(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .keys_with_prefix(DEFINITION_PREFIX)
+            .into_iter()
+            .map(|key| key[DEFINITION_PREFIX.len()..].to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Apply a branch's changes to main, then empty the branch (see the
+    /// module docs); fails with `BranchConflict` under `ConflictPolicy::Fail`
+    pub async fn merge_branch(&self, name: &str, policy: ConflictPolicy) -> Result<MergeReport> {
+        let Some(branch) = self.open_branch(name).await? else {
+            anyhow::bail!("No branch named {}", name);
+        };
+        // Main can't change between the conflict checks and the commit
+        let _commit_guard = self.lock_commits().await;
+
+        let mut report = MergeReport::default();
+        let mut writes = Vec::new();
+        let mut conflicting = Vec::new();
+        for change in branch.changes().await? {
+            let main = self.get(&change.key).await?;
+            let merged = if value_hash(main.as_deref()) == change.base || main == change.value {
+                change.value.clone()
+            } else {
+                report.conflicts += 1;
+                let conflict = MergeConflict { key: change.key.clone(), main: main.clone(), branch: change.value.clone() };
+                match &policy {
+                    ConflictPolicy::Fail => {
+                        conflicting.push(change.key.clone());
+                        continue;
+                    }
+                    ConflictPolicy::LastWriterWins => {
+                        let last_lsn = |metadata: Option<KeyMetadata>| metadata.map_or(0, |metadata| metadata.last_lsn);
+                        let main_lsn = last_lsn(self.metadata(&change.key).await?);
+                        let branch_lsn = last_lsn(self.metadata(&branch.data_key(&change.key)).await?);
+                        if branch_lsn > main_lsn {
+                            conflict.branch
+                        } else {
+                            conflict.main
+                        }
+                    }
+                    ConflictPolicy::Resolve(resolve) => resolve(&conflict),
+                }
+            };
+
+            if merged != main {
+                report.applied += 1;
+                writes.push(match &merged {
+                    Some(value) => WalEntry::set(&change.key, value),
+                    None => WalEntry::Delete { key: change.key.clone() },
+                });
+            } else if main != change.value {
+                report.kept_main += 1;
+            }
+            writes.push(WalEntry::Delete { key: branch.data_key(&change.key) });
+        }
+
+        if !conflicting.is_empty() {
+            return Err(IronCladError::BranchConflict { name: name.to_string(), keys: conflicting }.into());
+        }
+        if !writes.is_empty() {
+            self.commit_batch_locked(self.new_txn_id(), writes, None).await?;
+        }
+
+        info!(
+            "Merged branch {}: {} keys applied, {} conflicts ({} kept main's value)",
+            name, report.applied, report.conflicts, report.kept_main
+        );
+        Ok(report)
+    }
+
+    /// Drop a branch and its changes; returns whether it existed
+    pub async fn drop_branch(&self, name: &str) -> Result<bool> {
+        let branch = match self.open_branch(name).await? {
+            Some(branch) => branch,
+            None => return Ok(false),
+        };
+
+        // Unlink the definition first: leftovers from an interrupted drop are unreachable
+        self.delete(&definition_key(name)).await?;
+
+        let keys = self.keys_with_prefix(&branch.prefix);
+        for key in &keys {
+            self.delete(key).await?;
+        }
+
+        info!("Dropped branch {} ({} changed keys)", name, keys.len());
+        Ok(true)
+    }
+}
+
+fn definition_key(name: &str) -> String {
+    format!("{}{}", DEFINITION_PREFIX, name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::memory_store;
+
+    #[test]
+    fn test_conflict_policy_debug() {
+        assert_eq!(format!("{:?}", ConflictPolicy::Resolve(Box::new(|conflict| conflict.branch.clone()))), "Resolve");
+    }
+
+    #[test]
+    fn test_delta_round_trip() {
+        let delta = Delta { value: None, base: value_hash(Some("old")) };
+        let stored = serde_json::to_string(&delta).unwrap();
+        assert_eq!(serde_json::from_str::<Delta>(&stored).unwrap(), delta);
+        assert_ne!(value_hash(Some("old")), value_hash(Some("new")));
+        assert_eq!(value_hash(None), None);
+    }
+
+    #[tokio::test]
+    async fn test_branch_writes_stay_out_of_main() {
+        let store = memory_store().await;
+        store.set("user:1", "v1").await.unwrap();
+        store.set("user:2", "v1").await.unwrap();
+        let branch = store.branch("migration").await.unwrap();
+        assert!(store.branch("migration").await.is_err());
+
+        branch.set("user:1", "v2").await.unwrap();
+        assert!(branch.delete("user:2").await.unwrap());
+        branch.set("user:3", "new").await.unwrap();
+        assert_eq!(store.get("user:1").await.unwrap().as_deref(), Some("v1"));
+        assert_eq!(store.get("user:2").await.unwrap().as_deref(), Some("v1"));
+        assert_eq!(store.get("user:3").await.unwrap(), None);
+
+        // Keys the branch hasn't written read through, main's later writes included
+        store.set("user:4", "main").await.unwrap();
+        let scan = branch.scan().await.unwrap();
+        let expected = [("user:1", "v2"), ("user:3", "new"), ("user:4", "main")];
+        assert_eq!(scan, expected.map(|(k, v)| (k.to_string(), v.to_string())));
+
+        // A discarded change reads through again
+        assert!(branch.discard("user:1").await.unwrap());
+        assert_eq!(branch.get("user:1").await.unwrap().as_deref(), Some("v1"));
+
+        // Dropping leaves nothing behind; a new branch of the same name starts clean
+        assert!(store.drop_branch("migration").await.unwrap());
+        assert!(store.list_branches().is_empty());
+        assert!(store.keys_with_prefix(DATA_PREFIX).is_empty());
+        let fresh = store.branch("migration").await.unwrap();
+        assert!(fresh.changes().await.unwrap().is_empty());
+        assert_eq!(fresh.get("user:2").await.unwrap().as_deref(), Some("v1"));
+    }
+
+    /// A branch "b" that changed `clean` (untouched in main since) and
+    /// `contested` (which main then changed too)
+    async fn diverged(store: &KVStore) {
+        store.set("clean", "main-0").await.unwrap();
+        store.set("contested", "main-0").await.unwrap();
+        let branch = store.branch("b").await.unwrap();
+        branch.set("clean", "branch").await.unwrap();
+        branch.set("contested", "branch").await.unwrap();
+        store.set("contested", "main-1").await.unwrap();
+    }
+
+    async fn value(store: &KVStore, key: &str) -> Option<String> {
+        store.get(key).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_merge_policies() {
+        // Fail: nothing merges and the branch keeps its changes
+        let store = memory_store().await;
+        diverged(&store).await;
+        let err = store.merge_branch("b", ConflictPolicy::Fail).await.unwrap_err();
+        match err.downcast_ref::<IronCladError>() {
+            Some(IronCladError::BranchConflict { keys, .. }) => assert_eq!(keys, &["contested"]),
+            other => panic!("expected BranchConflict, got {:?}", other),
+        }
+        assert_eq!(value(&store, "clean").await.as_deref(), Some("main-0"));
+        assert_eq!(store.open_branch("b").await.unwrap().unwrap().changes().await.unwrap().len(), 2);
+
+        // Last writer wins: main wrote "contested" after the branch did
+        let store = memory_store().await;
+        diverged(&store).await;
+        let report = store.merge_branch("b", ConflictPolicy::LastWriterWins).await.unwrap();
+        assert_eq!(report, MergeReport { applied: 1, conflicts: 1, kept_main: 1 });
+        assert_eq!(value(&store, "clean").await.as_deref(), Some("branch"));
+        assert_eq!(value(&store, "contested").await.as_deref(), Some("main-1"));
+        assert!(store.open_branch("b").await.unwrap().unwrap().changes().await.unwrap().is_empty());
+
+        // ... and the branch wins once it writes the key again
+        let store = memory_store().await;
+        diverged(&store).await;
+        store.open_branch("b").await.unwrap().unwrap().set("contested", "branch-2").await.unwrap();
+        let report = store.merge_branch("b", ConflictPolicy::LastWriterWins).await.unwrap();
+        assert_eq!(report, MergeReport { applied: 2, conflicts: 1, kept_main: 0 });
+        assert_eq!(value(&store, "contested").await.as_deref(), Some("branch-2"));
+
+        // Resolve: the callback sees both sides
+        let store = memory_store().await;
+        diverged(&store).await;
+        let resolve = ConflictPolicy::Resolve(Box::new(|conflict| {
+            Some(format!("{}+{}", conflict.main.as_deref()?, conflict.branch.as_deref()?))
+        }));
+        store.merge_branch("b", resolve).await.unwrap();
+        assert_eq!(value(&store, "contested").await.as_deref(), Some("main-1+branch"));
+        assert_eq!(value(&store, "clean").await.as_deref(), Some("branch"));
+    }
+}
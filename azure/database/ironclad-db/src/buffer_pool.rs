@@ -1,19 +1,73 @@
-/// BufferPool: Memory Manager with LRU Eviction
-/// 
-/// This layer manages a fixed-size buffer pool (50MB) in memory.
-/// It uses LRU (Least Recently Used) eviction policy when the cache is full.
-/// The buffer pool reduces latency by caching frequently accessed pages in RAM.
+//! BufferPool: Memory Manager with LRU Eviction
+//! 
+//! This layer manages a fixed-size buffer pool (50MB) in memory.
+//! It uses LRU (Least Recently Used) eviction policy when the cache is full.
+//! The buffer pool reduces latency by caching frequently accessed pages in RAM.
+//! 
+//! Every frame remembers the LSN of the last WAL record that modified it.
+//! Under the steal policy a dirty page may be evicted: it moves to a
+//! write-back set (still served by `get_page`) until the store has written
+//! it to disk, which it only does once the WAL is durable up to the page's
+//! LSN. Under no-steal, dirty pages stay resident until flushed.
+//! 
+//! Frames also remember the LSN that first dirtied them (`rec_lsn`), so
+//! `flush_up_to` can write out just the pages holding old changes. The
+//! smallest rec_lsn is where crash recovery has to start redoing the WAL.
+//! 
+//! Pages can be tagged with a cache group (the store uses one per column
+//! family). Groups get their own hit/miss counters and an optional quota:
+//! a group at its quota evicts its own least recently used page to make
+//! room, so one dataset can't push another out of the cache.
+//! 
+//! Readers and writers don't share one big lock: each frame has its own
+//! latch, recency is an atomic stamp per frame (so a hit takes no write
+//! lock at all), and the page table is only write-locked to insert or
+//! evict. Locks are always taken in the order page groups, page table,
+//! frame latch; nothing holding a frame latch waits on another lock.
+//! The `contention` binary measures put latency under a read storm.
+//! 
+//! Frames track which of their 512-byte sectors differ from the disk copy.
+//! A counter bump or a flag flip usually changes one sector, so the store
+//! writes back just the span of dirty sectors (see `DirtyPage::dirty_range`)
+//! instead of the whole page. Pages put without a disk copy resident are
+//! all dirty.
 
 use anyhow::Result;
-use parking_lot::RwLock;
-use std::collections::{HashMap, VecDeque};
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tracing::{debug, info, warn};
 
+use crate::admission::TinyLfu;
+use crate::events::{EventListeners, EvictionEvent};
+use crate::heat::{HeatMap, HeatTracker, HotPinning, DEFAULT_HEAT_WINDOW};
+
 const BUFFER_SIZE: usize = 50 * 1024 * 1024; // 50MB
 const PAGE_SIZE: usize = 4096; // 4KB per page
 const NUM_FRAMES: usize = BUFFER_SIZE / PAGE_SIZE; // 12,800 frames
 
+/// Azure page blobs are written in 512-byte sectors
+pub const SECTOR_SIZE: usize = 512;
+
+/// Dirty sector bitmap with every sector of a page set
+const ALL_SECTORS: u8 = u8::MAX;
+
+/// Bitmap of the sectors in which `old` and `new` differ
+fn changed_sectors(old: &[u8], new: &[u8]) -> u8 {
+    if old.len() != new.len() {
+        return ALL_SECTORS;
+    }
+    old.chunks(SECTOR_SIZE)
+        .zip(new.chunks(SECTOR_SIZE))
+        .enumerate()
+        .filter(|(_, (old, new))| old != new)
+        .fold(0, |sectors, (sector, _)| sectors | 1 << sector)
+}
+
 /// Represents a single frame in the buffer pool
 #[derive(Debug, Clone)]
 struct Frame {
@@ -21,6 +75,59 @@ struct Frame {
     data: Vec<u8>,
     dirty: bool,      // Has this page been modified?
     pin_count: u32,   // Number of users currently accessing this page
+    page_lsn: u64,    // LSN of the last WAL record applied to this page
+    rec_lsn: u64,     // LSN of the first WAL record since the page was last clean
+    dirty_sectors: u8, // Sectors differing from the disk copy, bit i = sector i
+}
+
+impl Frame {
+    fn to_dirty_page(&self) -> DirtyPage {
+        DirtyPage {
+            page_id: self.page_id,
+            data: self.data.clone(),
+            page_lsn: self.page_lsn,
+            rec_lsn: self.rec_lsn,
+            dirty_sectors: self.dirty_sectors,
+        }
+    }
+}
+
+/// How eagerly pages read from disk are cached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum CachePriority {
+    /// Never cached on read (bulk or rarely read data)
+    Low,
+    /// Cached subject to the admission filter
+    #[default]
+    Normal,
+    /// Always cached on read, bypassing the admission filter
+    High,
+}
+
+/// A dirty page waiting to be written to disk
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirtyPage {
+    pub page_id: u64,
+    pub data: Vec<u8>,
+    /// The WAL must be durable up to this LSN before the page is written
+    pub page_lsn: u64,
+    /// Oldest WAL record whose change the page holds but the disk doesn't
+    pub rec_lsn: u64,
+    /// Sectors differing from the disk copy, bit i = sector i
+    pub dirty_sectors: u8,
+}
+
+impl DirtyPage {
+    /// Bytes of the page that need writing: from the first dirty sector
+    /// to the end of the last (the whole page if the bitmap is empty)
+    pub fn dirty_range(&self) -> std::ops::Range<usize> {
+        if self.dirty_sectors == 0 {
+            return 0..self.data.len();
+        }
+        let first = self.dirty_sectors.trailing_zeros() as usize;
+        let last = 7 - self.dirty_sectors.leading_zeros() as usize;
+        first * SECTOR_SIZE..((last + 1) * SECTOR_SIZE).min(self.data.len())
+    }
 }
 
 /// BufferPool manages in-memory page caching with LRU eviction
@@ -28,109 +135,527 @@ pub struct BufferPool {
     /// Page table: Maps page_id -> frame_index in buffer
     page_table: Arc<RwLock<HashMap<u64, usize>>>,
     
-    /// The actual buffer frames (50MB of 4KB pages)
-    frames: Arc<RwLock<Vec<Option<Frame>>>>,
+    /// The actual buffer frames (50MB of 4KB pages), each with its own latch
+    frames: Arc<Vec<RwLock<Option<Frame>>>>,
     
-    /// LRU queue: Most recently used at back, least recently used at front
-    lru_queue: Arc<RwLock<VecDeque<u64>>>,
+    /// When each frame was last used, on `clock`; the least recently used
+    /// page is the resident one with the smallest stamp
+    last_used: Arc<Vec<AtomicU64>>,
+    
+    /// Ticks once per page install or write; reads only stamp its value,
+    /// so pages read since the last tick tie (see `touch`)
+    clock: Arc<AtomicU64>,
     
     /// Free frames available for allocation
     free_frames: Arc<RwLock<VecDeque<usize>>>,
+    
+    /// Number of frames in this pool
+    capacity: usize,
+    
+    /// Frequency sketch deciding whether clean pages loaded from disk may
+    /// displace resident ones (None = admit everything, plain LRU)
+    admission: Option<Arc<Mutex<TinyLfu>>>,
+    
+    /// Loads turned away by the admission filter
+    rejected_admissions: Arc<AtomicU64>,
+    
+    /// May dirty pages be evicted (and written back) before they're flushed?
+    steal: bool,
+    
+    /// Dirty pages evicted under the steal policy, not yet written to disk
+    writeback: Arc<Mutex<HashMap<u64, DirtyPage>>>,
+    
+    /// Cache group of each tagged page
+    page_groups: Arc<RwLock<HashMap<u64, Arc<str>>>>,
+    
+    /// Usage, counters and quota per cache group
+    groups: Arc<Mutex<HashMap<Arc<str>, GroupStats>>>,
+    
+    /// Recent accesses per page (see heat.rs)
+    heat: Arc<HeatTracker>,
+    
+    /// Pin hot pages automatically (None = never)
+    hot_pinning: Option<HotPinning>,
+    
+    /// Page accesses since the last hot page check
+    accesses_since_check: Arc<AtomicU64>,
+    
+    /// Pages currently pinned for being hot
+    hot_pages: Arc<Mutex<HashSet<u64>>>,
+    
+    /// Told about evictions (see events.rs)
+    listeners: EventListeners,
 }
 
 impl BufferPool {
     /// Create a new BufferPool
     pub fn new() -> Self {
-        info!("Initializing BufferPool: {}MB ({} frames)", 
-              BUFFER_SIZE / (1024 * 1024), NUM_FRAMES);
+        Self::with_capacity(NUM_FRAMES)
+    }
+    
+    /// Create a BufferPool with `num_frames` 4KB frames and TinyLFU admission
+    pub fn with_capacity(num_frames: usize) -> Self {
+        Self::with_admission(num_frames, true)
+    }
+    
+    /// Create a BufferPool, choosing whether the TinyLFU admission filter is used
+    pub fn with_admission(num_frames: usize, tiny_lfu: bool) -> Self {
+        info!("Initializing BufferPool: {}MB ({} frames, admission: {})", 
+              num_frames * PAGE_SIZE / (1024 * 1024), num_frames,
+              if tiny_lfu { "TinyLFU" } else { "LRU" });
         
-        let frames = vec![None; NUM_FRAMES];
-        let free_frames: VecDeque<usize> = (0..NUM_FRAMES).collect();
+        let frames = (0..num_frames).map(|_| RwLock::new(None)).collect();
+        let last_used = (0..num_frames).map(|_| AtomicU64::new(0)).collect();
+        let free_frames: VecDeque<usize> = (0..num_frames).collect();
         
         Self {
             page_table: Arc::new(RwLock::new(HashMap::new())),
-            frames: Arc::new(RwLock::new(frames)),
-            lru_queue: Arc::new(RwLock::new(VecDeque::new())),
+            frames: Arc::new(frames),
+            last_used: Arc::new(last_used),
+            clock: Arc::new(AtomicU64::new(0)),
             free_frames: Arc::new(RwLock::new(free_frames)),
+            capacity: num_frames,
+            admission: tiny_lfu.then(|| Arc::new(Mutex::new(TinyLfu::new(num_frames)))),
+            rejected_admissions: Arc::new(AtomicU64::new(0)),
+            steal: true,
+            writeback: Arc::new(Mutex::new(HashMap::new())),
+            page_groups: Arc::new(RwLock::new(HashMap::new())),
+            groups: Arc::new(Mutex::new(HashMap::new())),
+            heat: Arc::new(HeatTracker::new(DEFAULT_HEAT_WINDOW)),
+            hot_pinning: None,
+            accesses_since_check: Arc::new(AtomicU64::new(0)),
+            hot_pages: Arc::new(Mutex::new(HashSet::new())),
+            listeners: EventListeners::default(),
+        }
+    }
+    
+    /// Choose the eviction policy for dirty pages (steal is the default)
+    /// 
+    /// With `steal = false` dirty pages are never evicted; once every frame
+    /// holds a dirty page, inserts fail until the store flushes.
+    pub fn with_steal(mut self, steal: bool) -> Self {
+        self.steal = steal;
+        self
+    }
+    
+    /// Count page accesses over `window` instead of the default minute
+    pub fn with_heat_window(mut self, window: Duration) -> Self {
+        self.heat = Arc::new(HeatTracker::new(window));
+        self
+    }
+    
+    /// Pin the hottest pages so they're never evicted (see `HotPinning`)
+    pub fn with_hot_pinning(mut self, hot_pinning: Option<HotPinning>) -> Self {
+        self.hot_pinning = hot_pinning;
+        self
+    }
+    
+    /// Report evictions to `listeners`
+    pub fn with_listeners(mut self, listeners: EventListeners) -> Self {
+        self.listeners = listeners;
+        self
+    }
+    
+    /// Pages currently pinned for being hot
+    pub fn hot_pages(&self) -> Vec<u64> {
+        let mut pages: Vec<u64> = self.hot_pages.lock().iter().copied().collect();
+        pages.sort_unstable();
+        pages
+    }
+    
+    /// Re-pick the hot pages every `check_every` accesses
+    fn maybe_pin_hot_pages(&self) {
+        let Some(hot_pinning) = &self.hot_pinning else {
+            return;
+        };
+        let accesses = self.accesses_since_check.fetch_add(1, Ordering::Relaxed) + 1;
+        if accesses < hot_pinning.check_every {
+            return;
+        }
+        self.accesses_since_check.store(0, Ordering::Relaxed);
+        
+        let wanted: HashSet<u64> = hot_pinning.hot_pages(&self.heat.heat_map(), self.capacity).into_iter().collect();
+        let mut hot_pages = self.hot_pages.lock();
+        let set_pin = |page_id: u64, pinned: bool| {
+            self.with_frame_mut(page_id, |_, frame| {
+                if pinned {
+                    frame.pin_count += 1;
+                } else {
+                    frame.pin_count = frame.pin_count.saturating_sub(1);
+                }
+            })
+            .is_some()
+        };
+        
+        hot_pages.retain(|page_id| {
+            let keep = wanted.contains(page_id);
+            if !keep {
+                set_pin(*page_id, false);
+            }
+            keep
+        });
+        for page_id in wanted {
+            // Pages not resident right now get another chance next check
+            if !hot_pages.contains(&page_id) && set_pin(page_id, true) {
+                hot_pages.insert(page_id);
+            }
         }
+        debug!("Hot pages pinned: {}", hot_pages.len());
+    }
+    
+    /// Page access counts over the heat window, hottest first
+    pub fn heat_map(&self) -> HeatMap {
+        self.heat.heat_map()
+    }
+    
+    /// Is the steal policy in effect?
+    pub fn steal(&self) -> bool {
+        self.steal
     }
     
     /// Fetch a page from the buffer pool
     /// If not in cache, returns None (caller should load from disk)
     pub fn get_page(&self, page_id: u64) -> Option<Vec<u8>> {
-        let page_table = self.page_table.read();
+        self.sample_access(page_id);
+        self.heat.record(page_id);
+        self.maybe_pin_hot_pages();
         
-        if let Some(&frame_idx) = page_table.get(&page_id) {
-            // Page is in cache - update LRU
-            self.update_lru(page_id);
-            
-            let frames = self.frames.read();
-            if let Some(Some(frame)) = frames.get(frame_idx) {
-                debug!("Cache HIT: page {} in frame {}", page_id, frame_idx);
-                return Some(frame.data.clone());
-            }
+        if let Some(data) = self.with_frame(page_id, |frame_idx, frame| {
+            self.touch(frame_idx);
+            debug!("Cache HIT: page {} in frame {}", page_id, frame_idx);
+            frame.data.clone()
+        }) {
+            self.with_group(page_id, |group| group.hits += 1);
+            return Some(data);
+        }
+        
+        // Evicted but not yet written back: the disk copy is stale
+        if let Some(pending) = self.writeback.lock().get(&page_id) {
+            debug!("Cache HIT: page {} awaiting write-back", page_id);
+            self.with_group(page_id, |group| group.hits += 1);
+            return Some(pending.data.clone());
         }
         
         debug!("Cache MISS: page {}", page_id);
+        self.with_group(page_id, |group| group.misses += 1);
         None
     }
     
-    /// Put a page into the buffer pool
-    /// Returns the frame index, and optionally a dirty page that was evicted
-    pub fn put_page(&self, page_id: u64, data: Vec<u8>) -> Result<Option<(u64, Vec<u8>)>> {
-        if data.len() != PAGE_SIZE {
-            anyhow::bail!("Invalid page size: expected {}, got {}", PAGE_SIZE, data.len());
+    /// Run `read` on the frame holding `page_id`, if it's resident
+    /// 
+    /// The page table lock is released before the frame latch is taken, so
+    /// the frame may have been reused for another page meanwhile; the
+    /// lookup is retried then.
+    fn with_frame<T>(&self, page_id: u64, read: impl FnOnce(usize, &Frame) -> T) -> Option<T> {
+        loop {
+            let frame_idx = *self.page_table.read().get(&page_id)?;
+            let slot = self.frames[frame_idx].read();
+            if let Some(frame) = slot.as_ref().filter(|frame| frame.page_id == page_id) {
+                return Some(read(frame_idx, frame));
+            }
+        }
+    }
+    
+    /// Run `update` on the frame holding `page_id`, if it's resident
+    fn with_frame_mut<T>(&self, page_id: u64, update: impl FnOnce(usize, &mut Frame) -> T) -> Option<T> {
+        loop {
+            let frame_idx = *self.page_table.read().get(&page_id)?;
+            let mut slot = self.frames[frame_idx].write();
+            if let Some(frame) = slot.as_mut().filter(|frame| frame.page_id == page_id) {
+                return Some(update(frame_idx, frame));
+            }
+        }
+    }
+    
+    /// Mark a frame as just read
+    /// 
+    /// Reads don't tick the clock: a shared counter bumped on every hit
+    /// would have every reader fight over its cache line. A read stamps the
+    /// current time, writing the stamp only if it changed, so a storm of
+    /// reads between two writes only reads shared memory. Pages read
+    /// between the same two ticks tie, which leaves LRU approximate
+    /// within that span; any of them may go first.
+    fn touch(&self, frame_idx: usize) {
+        let now = self.clock.load(Ordering::Relaxed);
+        let last_used = &self.last_used[frame_idx];
+        if last_used.load(Ordering::Relaxed) != now {
+            last_used.store(now, Ordering::Relaxed);
+        }
+    }
+    
+    /// Mark a frame as just installed or written, ticking the clock
+    fn touch_write(&self, frame_idx: usize) {
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        self.last_used[frame_idx].store(now, Ordering::Relaxed);
+    }
+    
+    /// Assign a page to a cache group (pages start out in no group)
+    pub fn tag_page(&self, page_id: u64, group: &str) {
+        // Looked up first: the page table is never locked under page groups
+        let resident = self.is_resident(page_id);
+        let mut page_groups = self.page_groups.write();
+        if page_groups.get(&page_id).is_some_and(|current| &**current == group) {
+            return;
         }
         
-        // Check if page is already in buffer
-        {
-            let page_table = self.page_table.read();
-            if page_table.contains_key(&page_id) {
-                // Update existing page
-                return self.update_existing_page(page_id, data);
+        let mut groups = self.groups.lock();
+        
+        if resident {
+            if let Some(previous) = page_groups.get(&page_id).and_then(|g| groups.get_mut(g)) {
+                previous.resident_frames = previous.resident_frames.saturating_sub(1);
             }
         }
         
-        // Need to allocate a new frame
-        let frame_idx = self.allocate_frame()?;
+        let key = groups
+            .get_key_value(group)
+            .map(|(key, _)| Arc::clone(key))
+            .unwrap_or_else(|| Arc::from(group));
+        let stats = groups.entry(Arc::clone(&key)).or_default();
+        if resident {
+            stats.resident_frames += 1;
+        }
+        page_groups.insert(page_id, key);
+    }
+    
+    /// Cap a cache group at `fraction` of the pool (None = no cap)
+    pub fn set_group_quota(&self, group: &str, fraction: Option<f64>) {
+        let quota = fraction.map(|f| ((self.capacity as f64 * f.clamp(0.0, 1.0)) as usize).max(1));
+        let mut groups = self.groups.lock();
         
-        let mut frames = self.frames.write();
-        let mut page_table = self.page_table.write();
+        match groups.get_mut(group) {
+            Some(stats) => stats.quota_frames = quota,
+            None => {
+                groups.insert(Arc::from(group), GroupStats { quota_frames: quota, ..Default::default() });
+            }
+        }
+        debug!("Cache group {} quota: {:?} frames", group, quota);
+    }
+    
+    /// Usage and counters of one cache group
+    pub fn group_stats(&self, group: &str) -> Option<GroupStats> {
+        self.groups.lock().get(group).cloned()
+    }
+    
+    /// Apply `update` to the stats of the page's group, if it has one
+    fn with_group<F: FnOnce(&mut GroupStats)>(&self, page_id: u64, update: F) {
+        let page_groups = self.page_groups.read();
+        if let Some(group) = page_groups.get(&page_id) {
+            if let Some(stats) = self.groups.lock().get_mut(group) {
+                update(stats);
+            }
+        }
+    }
+    
+    /// Cache group of a page that is at (or over) its quota
+    fn group_at_quota(&self, page_id: u64) -> Option<Arc<str>> {
+        let page_groups = self.page_groups.read();
+        let group = page_groups.get(&page_id)?;
+        let groups = self.groups.lock();
+        let stats = groups.get(group)?;
+        
+        match stats.quota_frames {
+            Some(quota) if stats.resident_frames >= quota => Some(Arc::clone(group)),
+            _ => None,
+        }
+    }
+    
+    /// Put a page into the buffer pool (marked dirty, with no WAL record)
+    pub fn put_page(&self, page_id: u64, data: Vec<u8>) -> Result<()> {
+        self.put_page_at(page_id, data, 0)
+    }
+    
+    /// Put a page modified by the WAL record at `lsn` into the buffer pool
+    /// 
+    /// If this evicts a dirty page (steal policy), the victim is queued for
+    /// write-back; see `pending_writebacks`.
+    pub fn put_page_at(&self, page_id: u64, data: Vec<u8>, lsn: u64) -> Result<()> {
+        if data.len() != PAGE_SIZE {
+            anyhow::bail!("Invalid page size: expected {}, got {}", PAGE_SIZE, data.len());
+        }
+        self.heat.record(page_id);
+        
+        self.record_access(page_id);
+        
+        // The new version supersedes any evicted copy still waiting to be written
+        self.writeback.lock().remove(&page_id);
+        
+        // Check if page is already in buffer
+        let data = match self.update_existing_page(page_id, data, lsn) {
+            None => return Ok(()),
+            Some(data) => data,
+        };
         
-        frames[frame_idx] = Some(Frame {
+        // Need to allocate a new frame
+        let frame_idx = self.allocate_frame(page_id)?;
+        let frame = Frame {
             page_id,
             data,
             dirty: true,
             pin_count: 0,
-        });
+            page_lsn: lsn,
+            rec_lsn: lsn,
+            dirty_sectors: ALL_SECTORS,
+        };
+        if let Err(frame) = self.install(frame_idx, frame) {
+            // Put concurrently by another writer; ours is the newer version
+            self.update_existing_page(page_id, frame.data, lsn);
+            return Ok(());
+        }
         
+        debug!("Inserted page {} into frame {} (LSN {})", page_id, frame_idx, lsn);
+        Ok(())
+    }
+    
+    /// Put `frame` into the free frame `frame_idx` and the page table
+    /// 
+    /// Hands the frame back (and frees `frame_idx`) if another thread made
+    /// the page resident meanwhile.
+    fn install(&self, frame_idx: usize, frame: Frame) -> Result<(), Frame> {
+        let page_id = frame.page_id;
+        let mut page_table = self.page_table.write();
+        if page_table.contains_key(&page_id) {
+            drop(page_table);
+            self.free_frames.write().push_back(frame_idx);
+            return Err(frame);
+        }
+        *self.frames[frame_idx].write() = Some(frame);
         page_table.insert(page_id, frame_idx);
-        self.update_lru(page_id);
+        self.touch_write(frame_idx);
+        drop(page_table);
         
-        debug!("Inserted page {} into frame {}", page_id, frame_idx);
-        Ok(None)
+        self.with_group(page_id, |group| group.resident_frames += 1);
+        Ok(())
     }
     
-    /// Update an existing page in the buffer
-    fn update_existing_page(&self, page_id: u64, data: Vec<u8>) -> Result<Option<(u64, Vec<u8>)>> {
-        let page_table = self.page_table.read();
+    /// Is `page_id` in a frame? (evicted pages awaiting write-back aren't)
+    pub fn is_resident(&self, page_id: u64) -> bool {
+        self.page_table.read().contains_key(&page_id)
+    }
+    
+    /// Make sure a frame is free for the next new page, evicting the LRU page if needed
+    /// 
+    /// Lets a writer make room (and write back a dirty victim, see
+    /// `pending_writebacks`) ahead of time, e.g. while its WAL append is in
+    /// flight. Returns whether a page was evicted.
+    pub fn reserve_frame(&self) -> Result<bool> {
+        if !self.free_frames.read().is_empty() {
+            return Ok(false);
+        }
+        match self.evict_lru_page_in(None)? {
+            Some(frame_idx) => {
+                self.free_frames.write().push_back(frame_idx);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+    
+    /// Offer a clean page just loaded from disk to the cache
+    /// 
+    /// With TinyLFU enabled and no free frame, the page is only cached if it
+    /// has been accessed more often than the LRU page it would evict; one-off
+    /// reads are turned away instead of polluting the cache. Returns whether
+    /// the page was cached.
+    pub fn admit_page(&self, page_id: u64, data: Vec<u8>) -> Result<bool> {
+        self.admit_page_with(page_id, data, CachePriority::Normal)
+    }
+    
+    /// Offer a clean page to the cache at the given priority
+    /// 
+    /// `Low` pages are never cached on read, `High` pages skip the admission
+    /// filter, `Normal` behaves like `admit_page`.
+    pub fn admit_page_with(&self, page_id: u64, data: Vec<u8>, priority: CachePriority) -> Result<bool> {
+        if data.len() != PAGE_SIZE {
+            anyhow::bail!("Invalid page size: expected {}, got {}", PAGE_SIZE, data.len());
+        }
+        
+        if self.page_table.read().contains_key(&page_id) || self.writeback.lock().contains_key(&page_id) {
+            // Already resident or awaiting write-back (e.g. written meanwhile):
+            // keep the newer copy
+            return Ok(true);
+        }
+        
+        if priority == CachePriority::Low {
+            debug!("Not caching low-priority page {}", page_id);
+            return Ok(false);
+        }
         
-        if let Some(&frame_idx) = page_table.get(&page_id) {
-            let mut frames = self.frames.write();
+        if let (Some(admission), CachePriority::Normal) = (&self.admission, priority) {
+            let pool_full = self.free_frames.read().is_empty();
+            let victim = self.lru_frame(|_| true).map(|(_, page_id)| page_id);
             
-            if let Some(Some(frame)) = frames.get_mut(frame_idx) {
-                frame.data = data;
-                frame.dirty = true;
-                self.update_lru(page_id);
-                debug!("Updated page {} in frame {} (marked dirty)", page_id, frame_idx);
+            if let (true, Some(victim)) = (pool_full, victim) {
+                if !admission.lock().admit(page_id, victim) {
+                    self.rejected_admissions.fetch_add(1, Ordering::Relaxed);
+                    debug!("Admission rejected page {} (victim page {} is hotter)", page_id, victim);
+                    return Ok(false);
+                }
             }
         }
         
-        Ok(None)
+        let frame_idx = self.allocate_frame(page_id)?;
+        let frame = Frame {
+            page_id,
+            data,
+            dirty: false,
+            pin_count: 0,
+            page_lsn: 0,
+            rec_lsn: 0,
+            dirty_sectors: 0,
+        };
+        if self.install(frame_idx, frame).is_err() {
+            // Written meanwhile: keep the newer copy
+            return Ok(true);
+        }
+        
+        debug!("Admitted clean page {} into frame {}", page_id, frame_idx);
+        Ok(true)
+    }
+    
+    /// Feed an access into the admission sketch
+    fn record_access(&self, page_id: u64) {
+        if let Some(admission) = &self.admission {
+            admission.lock().record(page_id);
+        }
     }
     
-    /// Allocate a frame (either from free list or evict LRU page)
-    fn allocate_frame(&self) -> Result<usize> {
+    /// Feed a read into the admission sketch unless another thread is
+    /// updating it; under a read storm the sketch samples reads instead of
+    /// making them queue (its counts are estimates anyway)
+    fn sample_access(&self, page_id: u64) {
+        if let Some(mut admission) = self.admission.as_ref().and_then(|admission| admission.try_lock()) {
+            admission.record(page_id);
+        }
+    }
+    
+    /// Update a page if it's in the buffer, handing `data` back if it isn't
+    fn update_existing_page(&self, page_id: u64, data: Vec<u8>, lsn: u64) -> Option<Vec<u8>> {
+        let mut data = Some(data);
+        self.with_frame_mut(page_id, |frame_idx, frame| {
+            if !frame.dirty {
+                frame.rec_lsn = lsn;
+                frame.dirty_sectors = 0;
+            }
+            let data = data.take().unwrap_or_default();
+            frame.dirty_sectors |= changed_sectors(&frame.data, &data);
+            frame.data = data;
+            frame.dirty = true;
+            frame.page_lsn = frame.page_lsn.max(lsn);
+            self.touch_write(frame_idx);
+            debug!("Updated page {} in frame {} (marked dirty, LSN {})", page_id, frame_idx, lsn);
+        });
+        data
+    }
+    
+    /// Allocate a frame for `page_id` (either from free list or evict LRU page)
+    fn allocate_frame(&self, page_id: u64) -> Result<usize> {
+        // A group at its quota makes room among its own pages
+        if let Some(group) = self.group_at_quota(page_id) {
+            if let Some(frame_idx) = self.evict_lru_page_in(Some(&group))? {
+                return Ok(frame_idx);
+            }
+        }
+        
         // Try to get a free frame first
         {
             let mut free_frames = self.free_frames.write();
@@ -141,83 +666,228 @@ impl BufferPool {
         }
         
         // No free frames - must evict LRU page
-        self.evict_lru_page()
+        match self.evict_lru_page_in(None)? {
+            Some(frame_idx) => Ok(frame_idx),
+            None if self.steal => anyhow::bail!("No pages available for eviction (all pinned)"),
+            None => anyhow::bail!("No clean pages available for eviction (no-steal): flush dirty pages first"),
+        }
     }
     
-    /// Evict the least recently used page
-    fn evict_lru_page(&self) -> Result<usize> {
-        let mut lru_queue = self.lru_queue.write();
-        
-        // Find an unpinned page to evict
-        while let Some(candidate_page_id) = lru_queue.pop_front() {
-            let page_table = self.page_table.read();
-            
-            if let Some(&frame_idx) = page_table.get(&candidate_page_id) {
-                let frames = self.frames.read();
-                
-                if let Some(Some(frame)) = frames.get(frame_idx) {
-                    if frame.pin_count == 0 {
-                        // Found a page we can evict
-                        drop(frames);
-                        drop(page_table);
-                        
-                        warn!("Evicting LRU page {} from frame {}", candidate_page_id, frame_idx);
-                        
-                        // Remove from page table
-                        let mut page_table = self.page_table.write();
-                        page_table.remove(&candidate_page_id);
-                        
-                        return Ok(frame_idx);
-                    }
+    /// Least recently used resident frame (and its page) passing `eligible`
+    /// 
+    /// Reads the recency stamps without locking; only frames that would
+    /// beat the best candidate so far are latched to be checked.
+    fn lru_frame(&self, eligible: impl Fn(&Frame) -> bool) -> Option<(usize, u64)> {
+        let mut best: Option<(u64, usize, u64)> = None;
+        for (frame_idx, slot) in self.frames.iter().enumerate() {
+            let stamp = self.last_used[frame_idx].load(Ordering::Relaxed);
+            if best.is_some_and(|(best_stamp, _, _)| stamp >= best_stamp) {
+                continue;
+            }
+            let page_id = match slot.read().as_ref() {
+                Some(frame) if eligible(frame) => frame.page_id,
+                _ => continue,
+            };
+            best = Some((stamp, frame_idx, page_id));
+        }
+        best.map(|(_, frame_idx, page_id)| (frame_idx, page_id))
+    }
+    
+    /// Evict the least recently used page (of `group`, if given)
+    /// 
+    /// Dirty victims are only taken under the steal policy and are moved to
+    /// the write-back set rather than dropped. Returns the emptied frame, or
+    /// None if no page qualifies.
+    fn evict_lru_page_in(&self, group: Option<&Arc<str>>) -> Result<Option<usize>> {
+        let evictable = |frame: &Frame| frame.pin_count == 0 && (self.steal || !frame.dirty);
+        loop {
+            // Group membership is checked with no frame latched
+            let candidate = match group {
+                None => self.lru_frame(evictable),
+                Some(group) => {
+                    let members: HashSet<u64> = self
+                        .page_groups
+                        .read()
+                        .iter()
+                        .filter(|(_, g)| *g == group)
+                        .map(|(page_id, _)| *page_id)
+                        .collect();
+                    self.lru_frame(|frame| members.contains(&frame.page_id) && evictable(frame))
                 }
+            };
+            let Some((frame_idx, page_id)) = candidate else {
+                return Ok(None);
+            };
+            
+            let mut page_table = self.page_table.write();
+            let mut slot = self.frames[frame_idx].write();
+            // Pinned, dirtied or replaced since it was picked: look again
+            if !slot.as_ref().is_some_and(|frame| frame.page_id == page_id && evictable(frame)) {
+                continue;
             }
+            let Some(frame) = slot.take() else { continue };
+            
+            warn!("Evicting LRU page {} from frame {}", page_id, frame_idx);
+            
+            // Remove from page table; a dirty victim waits for write-back
+            let dirty = frame.dirty;
+            if dirty {
+                self.writeback.lock().insert(page_id, frame.to_dirty_page());
+            }
+            page_table.remove(&page_id);
+            drop(slot);
+            drop(page_table);
+            
+            self.with_group(page_id, |group| {
+                group.resident_frames = group.resident_frames.saturating_sub(1);
+                group.evictions += 1;
+            });
+            let event = EvictionEvent { page_id, dirty };
+            self.listeners.emit(|listener| listener.on_eviction(&event));
             
-            // This page is pinned, put it back at the end
-            lru_queue.push_back(candidate_page_id);
+            return Ok(Some(frame_idx));
         }
-        
-        anyhow::bail!("No pages available for eviction (all pinned)")
     }
     
-    /// Update the LRU queue when a page is accessed
-    fn update_lru(&self, page_id: u64) {
-        let mut lru_queue = self.lru_queue.write();
+    /// Mark a page as dirty (modified)
+    pub fn mark_dirty(&self, page_id: u64) -> Result<()> {
+        self.with_frame_mut(page_id, |_, frame| {
+            frame.dirty = true;
+            frame.dirty_sectors = ALL_SECTORS;
+            debug!("Marked page {} as dirty", page_id);
+        });
         
-        // Remove page if it's already in the queue
-        if let Some(pos) = lru_queue.iter().position(|&id| id == page_id) {
-            lru_queue.remove(pos);
+        Ok(())
+    }
+    
+    /// Dirty pages evicted under the steal policy that still need writing
+    /// 
+    /// Call `complete_writeback` once each one is on disk.
+    pub fn pending_writebacks(&self) -> Vec<DirtyPage> {
+        self.writeback.lock().values().cloned().collect()
+    }
+    
+    /// Forget an evicted page once it's on disk, unless it changed meanwhile
+    pub fn complete_writeback(&self, page_id: u64, page_lsn: u64) {
+        let mut writeback = self.writeback.lock();
+        if writeback.get(&page_id).is_some_and(|pending| pending.page_lsn == page_lsn) {
+            writeback.remove(&page_id);
         }
+    }
+    
+    /// Resident dirty pages with the LSN each must wait for before being written
+    pub fn dirty_pages(&self) -> Vec<DirtyPage> {
+        self.frames
+            .iter()
+            .filter_map(|slot| slot.read().as_ref().filter(|frame| frame.dirty).map(Frame::to_dirty_page))
+            .collect()
+    }
+    
+    /// Oldest LSN whose change is in memory but not yet on disk
+    /// 
+    /// Recovery must redo the WAL from here; None when nothing is dirty.
+    pub fn oldest_dirty_lsn(&self) -> Option<u64> {
+        let resident = self
+            .frames
+            .iter()
+            .filter_map(|slot| slot.read().as_ref().filter(|frame| frame.dirty).map(|frame| frame.rec_lsn))
+            .min();
+        let evicted = self.writeback.lock().values().map(|page| page.rec_lsn).min();
         
-        // Add to back (most recently used)
-        lru_queue.push_back(page_id);
+        resident.into_iter().chain(evicted).min()
     }
     
-    /// Mark a page as dirty (modified)
-    pub fn mark_dirty(&self, page_id: u64) -> Result<()> {
-        let page_table = self.page_table.read();
+    /// Write every dirty page first modified at or before `lsn` with `write`
+    /// 
+    /// Pages dirtied only by later records are left alone, so a checkpoint
+    /// can bound recovery time without writing the whole dirty set. Pages
+    /// are marked clean as they're written (unless modified again
+    /// meanwhile). Returns the number of pages written.
+    pub async fn flush_up_to<F, Fut>(&self, lsn: u64, write: F) -> Result<usize>
+    where
+        F: Fn(DirtyPage) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut written = 0;
         
-        if let Some(&frame_idx) = page_table.get(&page_id) {
-            let mut frames = self.frames.write();
-            
-            if let Some(Some(frame)) = frames.get_mut(frame_idx) {
-                frame.dirty = true;
-                debug!("Marked page {} as dirty", page_id);
-            }
+        let evicted: Vec<DirtyPage> = self
+            .pending_writebacks()
+            .into_iter()
+            .filter(|page| page.rec_lsn <= lsn)
+            .collect();
+        for page in evicted {
+            let (page_id, page_lsn) = (page.page_id, page.page_lsn);
+            write(page).await?;
+            self.complete_writeback(page_id, page_lsn);
+            written += 1;
         }
         
-        Ok(())
+        let resident: Vec<DirtyPage> = self
+            .dirty_pages()
+            .into_iter()
+            .filter(|page| page.rec_lsn <= lsn)
+            .collect();
+        for page in resident {
+            let (page_id, page_lsn) = (page.page_id, page.page_lsn);
+            write(page).await?;
+            self.mark_clean(page_id, page_lsn);
+            written += 1;
+        }
+        
+        debug!("Flushed {} pages dirtied at or before LSN {}", written, lsn);
+        Ok(written)
+    }
+    
+    /// `flush_up_to` for just the pages `page_ids` (e.g. the ones a write
+    /// changed under the force policy); pages that aren't dirty are skipped
+    pub async fn flush_pages<F, Fut>(&self, page_ids: &[u64], write: F) -> Result<usize>
+    where
+        F: Fn(DirtyPage) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut written = 0;
+        for &page_id in page_ids {
+            let evicted = self.writeback.lock().get(&page_id).cloned();
+            if let Some(page) = evicted {
+                let page_lsn = page.page_lsn;
+                write(page).await?;
+                self.complete_writeback(page_id, page_lsn);
+                written += 1;
+            }
+            if let Some(page) = self.with_frame(page_id, |_, frame| frame.dirty.then(|| frame.to_dirty_page())).flatten() {
+                let page_lsn = page.page_lsn;
+                write(page).await?;
+                self.mark_clean(page_id, page_lsn);
+                written += 1;
+            }
+        }
+        Ok(written)
     }
     
+    /// Resident clean pages (copies of what's on disk), least recently used
+    /// first, e.g. to warm another pool with (see warm_cache.rs)
+    pub fn clean_pages(&self) -> Vec<(u64, Vec<u8>)> {
+        let mut pages: Vec<(u64, u64, Vec<u8>)> = self
+            .frames
+            .iter()
+            .enumerate()
+            .filter_map(|(frame_idx, slot)| {
+                let slot = slot.read();
+                let frame = slot.as_ref().filter(|frame| !frame.dirty)?;
+                Some((self.last_used[frame_idx].load(Ordering::Relaxed), frame.page_id, frame.data.clone()))
+            })
+            .collect();
+        pages.sort_unstable_by_key(|(last_used, _, _)| *last_used);
+        pages.into_iter().map(|(_, page_id, data)| (page_id, data)).collect()
+    }
+
     /// Get all dirty pages that need to be flushed
     pub fn get_dirty_pages(&self) -> Vec<(u64, Vec<u8>)> {
-        let frames = self.frames.read();
         let mut dirty_pages = Vec::new();
         
-        for frame_opt in frames.iter() {
-            if let Some(frame) = frame_opt {
-                if frame.dirty {
-                    dirty_pages.push((frame.page_id, frame.data.clone()));
-                }
+        for slot in self.frames.iter() {
+            if let Some(frame) = slot.read().as_ref().filter(|frame| frame.dirty) {
+                dirty_pages.push((frame.page_id, frame.data.clone()));
             }
         }
         
@@ -227,18 +897,25 @@ impl BufferPool {
     
     /// Clear dirty flag for a page after it's been flushed
     pub fn clear_dirty(&self, page_id: u64) -> Result<()> {
-        let page_table = self.page_table.read();
+        self.with_frame_mut(page_id, |_, frame| {
+            frame.dirty = false;
+            frame.dirty_sectors = 0;
+            debug!("Cleared dirty flag for page {}", page_id);
+        });
         
-        if let Some(&frame_idx) = page_table.get(&page_id) {
-            let mut frames = self.frames.write();
-            
-            if let Some(Some(frame)) = frames.get_mut(frame_idx) {
+        Ok(())
+    }
+    
+    /// Clear the dirty flag after a flush, unless the page was modified by a
+    /// WAL record after `flushed_lsn` (that newer version isn't on disk yet)
+    pub fn mark_clean(&self, page_id: u64, flushed_lsn: u64) {
+        self.with_frame_mut(page_id, |_, frame| {
+            if frame.page_lsn <= flushed_lsn {
                 frame.dirty = false;
-                debug!("Cleared dirty flag for page {}", page_id);
+                frame.dirty_sectors = 0;
+                debug!("Cleared dirty flag for page {} (LSN {})", page_id, flushed_lsn);
             }
-        }
-        
-        Ok(())
+        });
     }
     
     /// Get buffer pool statistics
@@ -247,14 +924,35 @@ impl BufferPool {
         let free_frames = self.free_frames.read();
         
         BufferPoolStats {
-            total_frames: NUM_FRAMES,
+            total_frames: self.capacity,
             used_frames: page_table.len(),
             free_frames: free_frames.len(),
-            buffer_size_mb: BUFFER_SIZE / (1024 * 1024),
+            buffer_size_mb: self.capacity * PAGE_SIZE / (1024 * 1024),
+            rejected_admissions: self.rejected_admissions.load(Ordering::Relaxed),
+            hot_pages: self.hot_pages.lock().len(),
         }
     }
 }
 
+impl Default for BufferPool {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Per-cache-group usage and counters
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupStats {
+    /// Frames currently holding the group's pages
+    pub resident_frames: usize,
+    /// Most frames the group may hold (None = uncapped)
+    pub quota_frames: Option<usize>,
+    pub hits: u64,
+    pub misses: u64,
+    /// Group pages evicted to make room
+    pub evictions: u64,
+}
+
 /// Buffer pool statistics
 #[derive(Debug, Clone)]
 pub struct BufferPoolStats {
@@ -262,6 +960,10 @@ pub struct BufferPoolStats {
     pub used_frames: usize,
     pub free_frames: usize,
     pub buffer_size_mb: usize,
+    /// Clean page loads the admission filter declined to cache
+    pub rejected_admissions: u64,
+    /// Pages pinned for being hot (see `HotPinning`)
+    pub hot_pages: usize,
 }
 
 #[cfg(test)]
@@ -340,6 +1042,335 @@ mod tests {
         assert_eq!(dirty.len(), 1);
     }
     
+    #[test]
+    fn test_admission_keeps_hot_pages() {
+        let bp = BufferPool::with_capacity(4);
+        
+        // Fill the pool, then make every resident page hot
+        for i in 0..4 {
+            bp.admit_page(i, vec![i as u8; PAGE_SIZE]).unwrap();
+        }
+        for _ in 0..3 {
+            for i in 0..4 {
+                bp.get_page(i);
+            }
+        }
+        
+        // A one-off page is turned away instead of evicting a hot one
+        assert!(!bp.admit_page(100, vec![0u8; PAGE_SIZE]).unwrap());
+        assert!(bp.get_page(100).is_none());
+        assert_eq!(bp.stats().rejected_admissions, 1);
+        for i in 0..4 {
+            assert!(bp.get_page(i).is_some());
+        }
+    }
+    
+    #[test]
+    fn test_admitted_pages_are_clean() {
+        let bp = BufferPool::with_capacity(4);
+        bp.admit_page(0, vec![1u8; PAGE_SIZE]).unwrap();
+        assert!(bp.get_dirty_pages().is_empty());
+    }
+    
+    #[test]
+    fn test_lru_admission_admits_everything() {
+        let bp = BufferPool::with_admission(2, false);
+        for i in 0..4 {
+            assert!(bp.admit_page(i, vec![0u8; PAGE_SIZE]).unwrap());
+        }
+        assert_eq!(bp.stats().rejected_admissions, 0);
+    }
+    
+    #[test]
+    fn test_steal_queues_dirty_victim_for_writeback() {
+        let bp = BufferPool::with_admission(2, false);
+        bp.put_page_at(0, vec![0u8; PAGE_SIZE], 5).unwrap();
+        bp.put_page_at(1, vec![1u8; PAGE_SIZE], 6).unwrap();
+        bp.put_page_at(2, vec![2u8; PAGE_SIZE], 7).unwrap();
+        
+        // Page 0 was evicted dirty: it's still readable and awaits write-back
+        let pending = bp.pending_writebacks();
+        assert_eq!(pending.len(), 1);
+        assert_eq!((pending[0].page_id, pending[0].page_lsn), (0, 5));
+        assert_eq!(bp.get_page(0), Some(vec![0u8; PAGE_SIZE]));
+        
+        bp.complete_writeback(0, 5);
+        assert!(bp.pending_writebacks().is_empty());
+        assert!(bp.get_page(0).is_none());
+    }
+    
+    #[test]
+    fn test_evictions_reach_listeners() {
+        struct Evictions(Mutex<Vec<EvictionEvent>>);
+        impl crate::events::EventListener for Evictions {
+            fn on_eviction(&self, event: &EvictionEvent) {
+                self.0.lock().push(*event);
+            }
+        }
+
+        let evictions = Arc::new(Evictions(Mutex::new(Vec::new())));
+        let bp = BufferPool::with_admission(1, false).with_listeners(EventListeners::new().with(evictions.clone()));
+        bp.put_page_at(0, vec![0u8; PAGE_SIZE], 1).unwrap();
+        bp.put_page_at(1, vec![1u8; PAGE_SIZE], 2).unwrap();
+
+        assert_eq!(*evictions.0.lock(), vec![EvictionEvent { page_id: 0, dirty: true }]);
+    }
+
+    #[test]
+    fn test_reserve_frame_evicts_ahead_of_put() {
+        let bp = BufferPool::with_admission(2, false);
+        assert!(!bp.reserve_frame().unwrap());
+        bp.put_page_at(0, vec![0u8; PAGE_SIZE], 1).unwrap();
+        bp.put_page_at(1, vec![1u8; PAGE_SIZE], 2).unwrap();
+        
+        assert!(bp.reserve_frame().unwrap());
+        assert!(!bp.is_resident(0));
+        assert_eq!(bp.pending_writebacks().len(), 1);
+        
+        // The put takes the reserved frame instead of evicting page 1
+        bp.put_page_at(2, vec![2u8; PAGE_SIZE], 3).unwrap();
+        assert!(bp.is_resident(1) && bp.is_resident(2));
+        assert_eq!(bp.pending_writebacks().len(), 1);
+    }
+    
+    #[test]
+    fn test_no_steal_keeps_dirty_pages() {
+        let bp = BufferPool::with_admission(2, false).with_steal(false);
+        bp.put_page_at(0, vec![0u8; PAGE_SIZE], 1).unwrap();
+        bp.put_page_at(1, vec![1u8; PAGE_SIZE], 2).unwrap();
+        
+        // Every frame is dirty: nothing may be evicted
+        assert!(bp.put_page_at(2, vec![2u8; PAGE_SIZE], 3).is_err());
+        
+        // Once a page is flushed it becomes the victim, and is simply dropped
+        bp.mark_clean(0, 1);
+        bp.put_page_at(2, vec![2u8; PAGE_SIZE], 3).unwrap();
+        assert!(bp.get_page(0).is_none());
+        assert!(bp.pending_writebacks().is_empty());
+    }
+    
+    #[test]
+    fn test_mark_clean_respects_newer_lsn() {
+        let bp = BufferPool::new();
+        bp.put_page_at(0, vec![0u8; PAGE_SIZE], 3).unwrap();
+        let flushed = bp.dirty_pages();
+        assert_eq!(flushed[0].page_lsn, 3);
+        
+        // Modified again while the flush was in flight
+        bp.put_page_at(0, vec![1u8; PAGE_SIZE], 4).unwrap();
+        bp.mark_clean(0, 3);
+        assert_eq!(bp.dirty_pages().len(), 1);
+        
+        bp.mark_clean(0, 4);
+        assert!(bp.dirty_pages().is_empty());
+    }
+    
+    #[test]
+    fn test_rec_lsn_tracks_first_dirtying_record() {
+        let bp = BufferPool::new();
+        bp.put_page_at(0, vec![0u8; PAGE_SIZE], 3).unwrap();
+        bp.put_page_at(0, vec![1u8; PAGE_SIZE], 8).unwrap();
+        bp.put_page_at(1, vec![1u8; PAGE_SIZE], 5).unwrap();
+        assert_eq!(bp.oldest_dirty_lsn(), Some(3));
+        
+        // Once clean, the next change starts a new recovery point
+        bp.mark_clean(0, 8);
+        bp.put_page_at(0, vec![2u8; PAGE_SIZE], 9).unwrap();
+        assert_eq!(bp.oldest_dirty_lsn(), Some(5));
+    }
+    
+    #[tokio::test]
+    async fn test_flush_up_to_leaves_newer_pages_dirty() {
+        let bp = BufferPool::new();
+        bp.put_page_at(0, vec![0u8; PAGE_SIZE], 2).unwrap();
+        bp.put_page_at(1, vec![1u8; PAGE_SIZE], 4).unwrap();
+        bp.put_page_at(2, vec![2u8; PAGE_SIZE], 6).unwrap();
+        
+        let written = Mutex::new(Vec::new());
+        let count = bp
+            .flush_up_to(4, |page| {
+                written.lock().push(page.page_id);
+                async { Ok(()) }
+            })
+            .await
+            .unwrap();
+        
+        assert_eq!(count, 2);
+        let mut written = written.into_inner();
+        written.sort();
+        assert_eq!(written, vec![0, 1]);
+        assert_eq!(bp.oldest_dirty_lsn(), Some(6));
+    }
+    
+    #[tokio::test]
+    async fn test_flush_pages_writes_only_the_pages_given() {
+        let bp = BufferPool::new();
+        bp.put_page_at(0, vec![0u8; PAGE_SIZE], 2).unwrap();
+        bp.put_page_at(1, vec![1u8; PAGE_SIZE], 4).unwrap();
+        
+        let written = Mutex::new(Vec::new());
+        let write = |page: DirtyPage| {
+            written.lock().push(page.page_id);
+            async { Ok(()) }
+        };
+        assert_eq!(bp.flush_pages(&[1, 7], write).await.unwrap(), 1);
+        assert_eq!(bp.flush_pages(&[1], write).await.unwrap(), 0);
+        assert_eq!(written.into_inner(), vec![1]);
+        assert_eq!(bp.oldest_dirty_lsn(), Some(2));
+    }
+    
+    #[tokio::test]
+    async fn test_flush_up_to_stops_on_write_failure() {
+        let bp = BufferPool::new();
+        bp.put_page_at(0, vec![0u8; PAGE_SIZE], 1).unwrap();
+        
+        let result = bp
+            .flush_up_to(10, |_| async { anyhow::bail!("disk unavailable") })
+            .await;
+        assert!(result.is_err());
+        assert_eq!(bp.dirty_pages().len(), 1);
+    }
+    
+    #[test]
+    fn test_cache_priority_overrides_admission() {
+        let bp = BufferPool::with_capacity(2);
+        for i in 0..2 {
+            bp.admit_page(i, vec![0u8; PAGE_SIZE]).unwrap();
+            bp.get_page(i);
+            bp.get_page(i);
+        }
+        
+        assert!(!bp.admit_page_with(10, vec![0u8; PAGE_SIZE], CachePriority::Low).unwrap());
+        assert!(!bp.admit_page_with(11, vec![0u8; PAGE_SIZE], CachePriority::Normal).unwrap());
+        assert!(bp.admit_page_with(12, vec![0u8; PAGE_SIZE], CachePriority::High).unwrap());
+        assert!(bp.get_page(12).is_some());
+    }
+    
+    #[test]
+    fn test_group_quota_evicts_within_group() {
+        let bp = BufferPool::with_admission(4, false);
+        bp.set_group_quota("bulk", Some(0.5));
+        
+        // A latency-critical page outside the group
+        bp.put_page(0, vec![0u8; PAGE_SIZE]).unwrap();
+        
+        // The bulk group churns through many pages but stays within 2 frames
+        for page_id in 10..20 {
+            bp.tag_page(page_id, "bulk");
+            bp.admit_page(page_id, vec![1u8; PAGE_SIZE]).unwrap();
+        }
+        
+        let stats = bp.group_stats("bulk").unwrap();
+        assert_eq!(stats.quota_frames, Some(2));
+        assert_eq!(stats.resident_frames, 2);
+        assert_eq!(stats.evictions, 8);
+        assert!(bp.get_page(0).is_some());
+    }
+    
+    #[test]
+    fn test_group_hit_miss_counters() {
+        let bp = BufferPool::new();
+        bp.tag_page(1, "users");
+        assert!(bp.get_page(1).is_none());
+        bp.admit_page(1, vec![0u8; PAGE_SIZE]).unwrap();
+        assert!(bp.get_page(1).is_some());
+        
+        let stats = bp.group_stats("users").unwrap();
+        assert_eq!((stats.hits, stats.misses, stats.resident_frames), (1, 1, 1));
+    }
+    
+    #[test]
+    fn test_hot_pages_are_pinned() {
+        let pinning = HotPinning { min_accesses_per_sec: 0, max_pinned: 1, check_every: 4 };
+        let bp = BufferPool::with_capacity(2).with_hot_pinning(Some(pinning));
+        bp.put_page(1, vec![1u8; PAGE_SIZE]).unwrap();
+        bp.put_page(2, vec![2u8; PAGE_SIZE]).unwrap();
+        for _ in 0..4 {
+            bp.get_page(1);
+        }
+        assert_eq!(bp.hot_pages(), vec![1]);
+        
+        // Page 1 is least recently used after this, but pinned
+        bp.get_page(2);
+        bp.put_page(3, vec![3u8; PAGE_SIZE]).unwrap();
+        assert!(bp.is_resident(1));
+        assert!(!bp.is_resident(2));
+    }
+    
+    #[test]
+    fn test_updates_track_dirty_sectors() {
+        let bp = BufferPool::with_admission(4, false);
+        bp.admit_page(1, vec![0u8; PAGE_SIZE]).unwrap();
+
+        let mut data = vec![0u8; PAGE_SIZE];
+        data[10] = 1;
+        bp.put_page_at(1, data.clone(), 1).unwrap();
+        assert_eq!(bp.dirty_pages()[0].dirty_sectors, 0b0000_0001);
+        assert_eq!(bp.dirty_pages()[0].dirty_range(), 0..SECTOR_SIZE);
+
+        // Changes add up until the page is flushed
+        data[3 * SECTOR_SIZE] = 1;
+        bp.put_page_at(1, data.clone(), 2).unwrap();
+        assert_eq!(bp.dirty_pages()[0].dirty_range(), 0..4 * SECTOR_SIZE);
+        bp.mark_clean(1, 2);
+        data[PAGE_SIZE - 1] = 1;
+        bp.put_page_at(1, data, 3).unwrap();
+        assert_eq!(bp.dirty_pages()[0].dirty_range(), 7 * SECTOR_SIZE..PAGE_SIZE);
+
+        // No disk copy to compare with: all of it
+        bp.put_page_at(2, vec![0u8; PAGE_SIZE], 4).unwrap();
+        let page = bp.dirty_pages().into_iter().find(|page| page.page_id == 2).unwrap();
+        assert_eq!(page.dirty_range(), 0..PAGE_SIZE);
+    }
+
+    #[test]
+    fn test_reads_stamp_without_ticking_the_clock() {
+        let bp = BufferPool::with_admission(3, false);
+        for page_id in 1..=3 {
+            bp.put_page(page_id, vec![page_id as u8; PAGE_SIZE]).unwrap();
+        }
+        let clock = bp.clock.load(Ordering::Relaxed);
+        for _ in 0..100 {
+            bp.get_page(1);
+        }
+        assert_eq!(bp.clock.load(Ordering::Relaxed), clock);
+
+        // Still newer than every page installed before the reads
+        bp.put_page(4, vec![4u8; PAGE_SIZE]).unwrap();
+        assert!(bp.is_resident(1));
+        assert!(!bp.is_resident(2));
+    }
+
+    #[test]
+    fn test_puts_make_progress_under_concurrent_reads() {
+        let bp = Arc::new(BufferPool::with_admission(8, false));
+        let stop = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let (bp, stop) = (bp.clone(), stop.clone());
+                std::thread::spawn(move || {
+                    let mut page_id = 0;
+                    while !stop.load(Ordering::Relaxed) {
+                        bp.get_page(page_id % 12);
+                        page_id += 1;
+                    }
+                })
+            })
+            .collect();
+        
+        // Puts evict (and replace) pages the readers are hitting
+        for lsn in 1..=2_000u64 {
+            bp.put_page_at(lsn % 12, vec![lsn as u8; PAGE_SIZE], lsn).unwrap();
+        }
+        stop.store(true, Ordering::Relaxed);
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        
+        assert_eq!(bp.stats().used_frames, 8);
+        assert_eq!(bp.get_page(2_000 % 12), Some(vec![2_000u64 as u8; PAGE_SIZE]));
+    }
+    
     #[test]
     fn test_invalid_page_size() {
         let bp = BufferPool::new();
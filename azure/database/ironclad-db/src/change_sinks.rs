@@ -0,0 +1,287 @@
+//! Change Sinks: Event Grid and Service Bus Destinations for the Change Feed
+//!
+//! `ChangeSink`s that put the change feed (see changefeed.rs) into Azure's
+//! messaging services, so event-driven architectures can react to writes:
+//!
+//! - `EventGridSink`: an Event Grid topic, one Event Grid schema event per
+//!   change. `eventType` is `IronClad.<Op>` ("IronClad.Set", ...),
+//!   `subject` is `<source>/<key>` and `data` the `ChangeEvent`. Subscribers
+//!   can filter on either.
+//! - `ServiceBusSink`: a Service Bus topic (or queue), one message per
+//!   change, its body the `ChangeEvent` as JSON. The message ID is
+//!   `<source>:<lsn>`, so a topic with duplicate detection on drops the
+//!   resends at-least-once delivery can make; `op` and `key` are also
+//!   user properties, for subscription rules.
+//!
+//! Both post a whole batch in one request over the REST APIs, and
+//! authenticate with a key (a topic access key; a shared access policy's
+//! key, from its connection string). A batch the service doesn't accept
+//! with a 2xx fails, and the publisher sends it again.
+
+use anyhow::{Context, Result};
+use azure_core::{HttpClient, Method, Request, Url};
+use base64::{engine::general_purpose, Engine as _};
+use futures::future::BoxFuture;
+use hmac::{Hmac, Mac};
+use serde_json::json;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::changefeed::{ChangeEvent, ChangeSink};
+use crate::cron::civil_from_days;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Lifetime of the Service Bus SAS tokens we sign
+const SAS_TOKEN_SECS: u64 = 3_600;
+
+/// Default `source` naming the store in event IDs and subjects
+const DEFAULT_SOURCE: &str = "ironclad";
+
+/// Publishes changes to an Event Grid topic
+pub struct EventGridSink {
+    http: Arc<dyn HttpClient>,
+    endpoint: Url,
+    key: String,
+    source: String,
+}
+
+impl EventGridSink {
+    /// `endpoint` is the topic endpoint
+    /// (`https://<topic>.<region>-1.eventgrid.azure.net/api/events`), `key`
+    /// one of its access keys
+    pub fn new(endpoint: &str, key: &str) -> Result<Self> {
+        Ok(Self {
+            http: azure_core::new_http_client(),
+            endpoint: Url::parse(endpoint).with_context(|| format!("Invalid Event Grid endpoint {:?}", endpoint))?,
+            key: key.to_string(),
+            source: DEFAULT_SOURCE.to_string(),
+        })
+    }
+
+    /// Name the store in event IDs and subjects (default: "ironclad");
+    /// stores sharing a topic need distinct sources
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = source.to_string();
+        self
+    }
+
+    fn body(&self, events: &[ChangeEvent]) -> serde_json::Value {
+        let events: Vec<_> = events
+            .iter()
+            .map(|event| {
+                json!({
+                    "id": format!("{}:{}", self.source, event.lsn),
+                    "subject": format!("{}/{}", self.source, event.key),
+                    "eventType": event_type(event),
+                    // Deletes don't record when they were issued
+                    "eventTime": rfc3339(if event.at_ms > 0 { event.at_ms } else { now_secs() * 1000 }),
+                    "dataVersion": "1.0",
+                    "data": event,
+                })
+            })
+            .collect();
+        serde_json::Value::Array(events)
+    }
+}
+
+impl ChangeSink for EventGridSink {
+    fn name(&self) -> &str {
+        "event grid"
+    }
+
+    fn publish<'a>(&'a self, events: &'a [ChangeEvent]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let mut request = Request::new(self.endpoint.clone(), Method::Post);
+            request.insert_header("aeg-sas-key", self.key.clone());
+            request.insert_header("content-type", "application/json");
+            request.set_body(serde_json::to_vec(&self.body(events))?);
+            send(self.http.as_ref(), &request, "Event Grid").await
+        })
+    }
+}
+
+/// Publishes changes to a Service Bus topic or queue
+pub struct ServiceBusSink {
+    http: Arc<dyn HttpClient>,
+    /// `https://<namespace>.servicebus.windows.net/<topic>`
+    entity: String,
+    key_name: String,
+    key: String,
+    source: String,
+}
+
+impl ServiceBusSink {
+    /// `connection_string` is a shared access policy's
+    /// (`Endpoint=sb://...;SharedAccessKeyName=...;SharedAccessKey=...`),
+    /// `topic` the topic or queue to send to
+    pub fn new(connection_string: &str, topic: &str) -> Result<Self> {
+        let (host, key_name, key) = parse_sas_connection_string(connection_string)?;
+        Ok(Self {
+            http: azure_core::new_http_client(),
+            entity: format!("https://{}/{}", host, topic),
+            key_name,
+            key,
+            source: DEFAULT_SOURCE.to_string(),
+        })
+    }
+
+    /// Name the store in message IDs (default: "ironclad"); stores sharing
+    /// a topic need distinct sources
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = source.to_string();
+        self
+    }
+
+    fn body(&self, events: &[ChangeEvent]) -> Result<serde_json::Value> {
+        let messages = events
+            .iter()
+            .map(|event| {
+                Ok(json!({
+                    "Body": serde_json::to_string(event)?,
+                    "BrokerProperties": { "MessageId": format!("{}:{}", self.source, event.lsn) },
+                    "UserProperties": { "op": event.change.op(), "key": event.key },
+                }))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(serde_json::Value::Array(messages))
+    }
+}
+
+impl ChangeSink for ServiceBusSink {
+    fn name(&self) -> &str {
+        "service bus"
+    }
+
+    fn publish<'a>(&'a self, events: &'a [ChangeEvent]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let url = Url::parse(&format!("{}/messages", self.entity))?;
+            let mut request = Request::new(url, Method::Post);
+            request.insert_header("authorization", sas_token(&self.entity, &self.key_name, &self.key, now_secs() + SAS_TOKEN_SECS));
+            request.insert_header("content-type", "application/vnd.microsoft.servicebus.json");
+            request.set_body(serde_json::to_vec(&self.body(events)?)?);
+            send(self.http.as_ref(), &request, "Service Bus").await
+        })
+    }
+}
+
+/// (host, key name, key) of a Service Bus or Event Hubs namespace
+/// connection string
+pub(crate) fn parse_sas_connection_string(connection_string: &str) -> Result<(String, String, String)> {
+    let field = |name: &str| {
+        connection_string
+            .split(';')
+            .find_map(|part| part.trim().strip_prefix(name).and_then(|rest| rest.strip_prefix('=')))
+            .map(str::to_string)
+            .ok_or_else(|| anyhow::anyhow!("Connection string has no {}", name))
+    };
+    let endpoint = field("Endpoint")?;
+    let host = endpoint
+        .strip_prefix("sb://")
+        .ok_or_else(|| anyhow::anyhow!("Endpoint {:?} isn't sb://", endpoint))?
+        .trim_end_matches('/');
+    Ok((host.to_string(), field("SharedAccessKeyName")?, field("SharedAccessKey")?))
+}
+
+/// Send `request`, failing unless the service answers with a 2xx
+pub(crate) async fn send(http: &dyn HttpClient, request: &Request, service: &str) -> Result<()> {
+    let response = http.execute_request(request).await?;
+    let status = response.status();
+    if !status.is_success() {
+        let body = response.into_body().collect_string().await.unwrap_or_default();
+        anyhow::bail!("{} rejected the batch: {} {}", service, status, body);
+    }
+    Ok(())
+}
+
+/// "IronClad.Set", "IronClad.Delete", ...
+fn event_type(event: &ChangeEvent) -> String {
+    let op = event.change.op();
+    format!("IronClad.{}{}", op[..1].to_uppercase(), &op[1..])
+}
+
+/// A Service Bus SAS token for `resource`, valid until `expiry` (s since the epoch)
+pub(crate) fn sas_token(resource: &str, key_name: &str, key: &str, expiry: u64) -> String {
+    let resource = percent_encode(resource);
+    let mut mac = HmacSha256::new_from_slice(key.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(format!("{}\n{}", resource, expiry).as_bytes());
+    let signature = general_purpose::STANDARD.encode(mac.finalize().into_bytes());
+    format!(
+        "SharedAccessSignature sr={}&sig={}&se={}&skn={}",
+        resource,
+        percent_encode(&signature),
+        expiry,
+        percent_encode(key_name)
+    )
+}
+
+/// Percent-encode everything but RFC 3986 unreserved characters
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// RFC 3339 UTC time of `ms` since the epoch
+fn rfc3339(ms: u64) -> String {
+    let secs = ms / 1000;
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    let time = secs % 86_400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        time / 3_600,
+        time % 3_600 / 60,
+        time % 60,
+        ms % 1000
+    )
+}
+
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changefeed::Change;
+
+    #[test]
+    fn test_event_grid_events() {
+        let sink = EventGridSink::new("https://orders.westus-1.eventgrid.azure.net/api/events", "key")
+            .unwrap()
+            .with_source("orders");
+        let event = ChangeEvent { lsn: 42, key: "o/1".to_string(), change: Change::Delete, at_ms: 1_710_504_000_250 };
+        let body = sink.body(&[event]);
+        assert_eq!(body[0]["id"], "orders:42");
+        assert_eq!(body[0]["subject"], "orders/o/1");
+        assert_eq!(body[0]["eventType"], "IronClad.Delete");
+        assert_eq!(body[0]["eventTime"], "2024-03-15T12:00:00.250Z");
+        assert_eq!(body[0]["data"]["op"], "delete");
+    }
+
+    #[test]
+    fn test_service_bus_connection_string_and_token() {
+        let sink = ServiceBusSink::new(
+            "Endpoint=sb://shop.servicebus.windows.net/;SharedAccessKeyName=Send;SharedAccessKey=c2VjcmV0",
+            "changes",
+        )
+        .unwrap();
+        assert_eq!(sink.entity, "https://shop.servicebus.windows.net/changes");
+        assert_eq!((sink.key_name.as_str(), sink.key.as_str()), ("Send", "c2VjcmV0"));
+        assert!(ServiceBusSink::new("Endpoint=sb://shop.servicebus.windows.net/", "changes").is_err());
+
+        let token = sas_token(&sink.entity, "Send", "c2VjcmV0", 1_700_000_000);
+        assert!(token.starts_with("SharedAccessSignature sr=https%3A%2F%2Fshop.servicebus.windows.net%2Fchanges&sig="));
+        assert!(token.ends_with("&se=1700000000&skn=Send"));
+    }
+}
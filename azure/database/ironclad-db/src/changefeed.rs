@@ -0,0 +1,350 @@
+//! Change Feed: Committed Writes as a Stream of Events
+//!
+//! Every committed write is in the WAL with its LSN, so the change feed is
+//! read straight from it: `KVStore::changes_since(lsn)` returns the writes
+//! committed after `lsn` as `ChangeEvent`s, in commit order. Reserved
+//! (`__`) keys are left out; values are as readers see them (decrypted,
+//! through the middleware).
+//!
+//! A `ChangePublisher` forwards the feed to a `ChangeSink` (Event Grid,
+//! Service Bus, see change_sinks.rs; Kafka, Event Hubs, see kafka.rs) in
+//! the background:
+//!
+//! ```ignore
+//! let sink = Arc::new(EventGridSink::new(topic_endpoint, topic_key));
+//! let publisher = store.start_change_publisher(sink, ChangeFeedOptions::new("orders-to-grid"))?;
+//! ```
+//!
+//! Delivery is at least once. The LSN a publisher has delivered up to is
+//! kept under `__changefeed/<name>`, written after each batch the sink
+//! accepted, so a restarted publisher carries on from there, resending at
+//! most the batch that was in flight. A batch the sink rejects is retried
+//! (after `retry_interval`) until it goes through. Events carry their LSN,
+//! for consumers that want to drop duplicates.
+//!
+//! The feed only reaches back as far as the WAL does: a checkpoint clears
+//! it. Changes a publisher hadn't delivered when the log was cleared are
+//! lost to it; the publisher reports the skipped LSN range (to the event
+//! listeners, operation "changefeed", and in its stats) and carries on.
+//! Keep `poll_interval` well below the checkpoint interval.
+//!
+//! Each publisher's stats hold its lag behind the WAL as of its last poll;
+//! `KVStore::consumer_lag` reports every feed's (see lag.rs).
+//!
+//! In-process subscribers can `watch` the feed instead, with filters and
+//! a bounded buffer (see watch.rs).
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+use crate::kvstore::KVStore;
+use crate::lag::{lag_of, ConsumerLag};
+use crate::metadata::now_ms;
+use crate::runtime::TaskHandle;
+use crate::trash::is_reserved;
+use crate::wal::WalEntry;
+
+pub(crate) const CHANGEFEED_PREFIX: &str = "__changefeed/";
+
+/// What a write did to its key
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Change {
+    Set { value: String },
+    Delete,
+    /// JSON Pointer location set (see patch.rs)
+    Patch { pointer: String, value: serde_json::Value },
+}
+
+impl Change {
+    /// "set", "delete" or "patch"
+    pub fn op(&self) -> &'static str {
+        match self {
+            Change::Set { .. } => "set",
+            Change::Delete => "delete",
+            Change::Patch { .. } => "patch",
+        }
+    }
+}
+
+/// One committed write
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ChangeEvent {
+    pub lsn: u64,
+    pub key: String,
+    #[serde(flatten)]
+    pub change: Change,
+    /// When the write was issued (ms since the epoch; 0 if unknown)
+    pub at_ms: u64,
+}
+
+/// Changes read from the feed
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ChangeBatch {
+    pub events: Vec<ChangeEvent>,
+    /// Highest LSN read, the position to continue after
+    pub last_lsn: u64,
+    /// LSNs asked for but already cleared from the WAL by a checkpoint
+    pub missed: Option<RangeInclusive<u64>>,
+}
+
+/// Where a `ChangePublisher` sends changes
+pub trait ChangeSink: Send + Sync {
+    /// Name used in logs
+    fn name(&self) -> &str;
+
+    /// Deliver `events` (in commit order), returning once the destination
+    /// accepted them all
+    fn publish<'a>(&'a self, events: &'a [ChangeEvent]) -> BoxFuture<'a, Result<()>>;
+}
+
+/// Change publisher settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeFeedOptions {
+    /// Names the delivered-LSN marker; one per destination
+    pub name: String,
+    /// How often the WAL is checked for new changes (default: 1s)
+    pub poll_interval: Duration,
+    /// Most events handed to the sink at once (default: 100)
+    pub max_batch: usize,
+    /// Wait before resending a rejected batch (default: 5s)
+    pub retry_interval: Duration,
+}
+
+impl ChangeFeedOptions {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            poll_interval: Duration::from_secs(1),
+            max_batch: 100,
+            retry_interval: Duration::from_secs(5),
+        }
+    }
+
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    pub fn with_max_batch(mut self, events: usize) -> Self {
+        self.max_batch = events.max(1);
+        self
+    }
+
+    pub fn with_retry_interval(mut self, interval: Duration) -> Self {
+        self.retry_interval = interval;
+        self
+    }
+}
+
+/// Change publisher counters
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ChangePublisherStats {
+    /// Events the sink accepted
+    pub published: u64,
+    pub batches: u64,
+    /// Batches the sink rejected (and were retried)
+    pub failures: u64,
+    /// LSN delivered up to
+    pub lsn: u64,
+    /// LSN ranges cleared by checkpoints before they were delivered
+    pub missed: Vec<RangeInclusive<u64>>,
+    /// How far behind the WAL the publisher was at its last poll, before
+    /// delivering (see lag.rs)
+    pub lag: Option<ConsumerLag>,
+}
+
+/// Delivered-LSN marker, persisted under `__changefeed/<name>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct FeedMarker {
+    pub(crate) lsn: u64,
+}
+
+pub(crate) fn marker_key(name: &str) -> String {
+    format!("{}{}", CHANGEFEED_PREFIX, name)
+}
+
+/// LSNs of writes a cleared log no longer holds: those after `after_lsn`
+/// and before the checkpoint record at `checkpoint_lsn` that opens it
+fn cleared_range(after_lsn: u64, checkpoint_lsn: u64) -> Option<RangeInclusive<u64>> {
+    (after_lsn + 1 < checkpoint_lsn).then(|| after_lsn + 1..=checkpoint_lsn - 1)
+}
+
+/// Running change publisher; dropping it stops the publisher
+pub struct ChangePublisher {
+    task: TaskHandle,
+    stats: Arc<Mutex<ChangePublisherStats>>,
+}
+
+impl ChangePublisher {
+    pub fn stats(&self) -> ChangePublisherStats {
+        self.stats.lock().clone()
+    }
+}
+
+impl Drop for ChangePublisher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl KVStore {
+    /// Writes committed after `after_lsn`, in commit order
+    pub async fn changes_since(&self, after_lsn: u64) -> Result<ChangeBatch> {
+        let entries = self.wal().replay_since(after_lsn).await?;
+        let mut batch = ChangeBatch { last_lsn: after_lsn, ..Default::default() };
+        for (i, (lsn, entry)) in entries.into_iter().enumerate() {
+            batch.last_lsn = lsn;
+            let change = match entry {
+                // A cleared log opens with the LSN it was cleared at; LSNs
+                // are contiguous, so anything missing before it is gone
+                WalEntry::Checkpoint { lsn: checkpoint_lsn } => {
+                    if i == 0 {
+                        batch.missed = cleared_range(after_lsn, checkpoint_lsn);
+                    }
+                    continue;
+                }
+                WalEntry::Set { key, value, at_ms } => {
+                    let value = self.read_value(&key, value).await?;
+                    (key, Change::Set { value }, at_ms)
+                }
+                WalEntry::Delete { key } => (key, Change::Delete, 0),
+                WalEntry::Patch { key, pointer, value, at_ms } => (key, Change::Patch { pointer, value }, at_ms),
+                WalEntry::Begin { .. } | WalEntry::Commit { .. } | WalEntry::Unknown { .. } => continue,
+            };
+            let (key, change, at_ms) = change;
+            if !is_reserved(&key) {
+                batch.events.push(ChangeEvent { lsn, key, change, at_ms });
+            }
+        }
+        Ok(batch)
+    }
+
+    /// Forward committed changes to `sink` in the background (see the
+    /// module docs)
+    pub fn start_change_publisher(
+        self: &Arc<Self>,
+        sink: Arc<dyn ChangeSink>,
+        options: ChangeFeedOptions,
+    ) -> Result<ChangePublisher> {
+        if options.poll_interval.is_zero() {
+            anyhow::bail!("Change feed {} needs a non-zero poll interval", options.name);
+        }
+        let stats = Arc::new(Mutex::new(ChangePublisherStats::default()));
+        let store = Arc::clone(self);
+        let task_stats = stats.clone();
+        let runtime = self.options().runtime.clone();
+        let task = runtime.clone().spawn(async move {
+            let mut lsn = None;
+            loop {
+                match store.publish_changes(sink.as_ref(), &options, &mut lsn, &task_stats).await {
+                    Ok(()) => runtime.sleep(options.poll_interval).await,
+                    Err(e) => {
+                        task_stats.lock().failures += 1;
+                        warn!("CHANGEFEED: {} to {} failed: {:#}", options.name, sink.name(), e);
+                        store.options().listeners.error("changefeed", &e);
+                        runtime.sleep(options.retry_interval).await;
+                    }
+                }
+            }
+        });
+        Ok(ChangePublisher { task, stats })
+    }
+
+    /// Deliver the changes after `lsn` (loaded from the marker when None)
+    async fn publish_changes(
+        &self,
+        sink: &dyn ChangeSink,
+        options: &ChangeFeedOptions,
+        lsn: &mut Option<u64>,
+        stats: &Mutex<ChangePublisherStats>,
+    ) -> Result<()> {
+        let key = marker_key(&options.name);
+        // A new feed starts at the oldest change the WAL still holds
+        let (after, resumed) = match *lsn {
+            Some(after) => (after, true),
+            None => match self.get(&key).await? {
+                Some(json) => (serde_json::from_str::<FeedMarker>(&json)?.lsn, true),
+                None => (0, false),
+            },
+        };
+        *lsn = Some(after);
+
+        let batch = self.changes_since(after).await?;
+        let writes = batch.events.iter().map(|event| (event.lsn, event.at_ms));
+        let lag = lag_of(&options.name, after, self.wal().current_lsn(), writes, now_ms());
+        self.check_lag(&lag);
+        stats.lock().lag = Some(lag);
+        if let Some(missed) = batch.missed.clone().filter(|_| resumed) {
+            let error = anyhow::anyhow!(
+                "changes at LSN {}..={} were cleared by a checkpoint before {} delivered them",
+                missed.start(),
+                missed.end(),
+                options.name
+            );
+            warn!("CHANGEFEED: {}", error);
+            self.options().listeners.error("changefeed", &error);
+            stats.lock().missed.push(missed);
+        }
+
+        for chunk in batch.events.chunks(options.max_batch) {
+            sink.publish(chunk).await?;
+            let delivered = chunk.last().map(|event| event.lsn).unwrap_or(after);
+            self.set(&key, &serde_json::to_string(&FeedMarker { lsn: delivered })?).await?;
+            *lsn = Some(delivered);
+
+            let mut stats = stats.lock();
+            stats.published += chunk.len() as u64;
+            stats.batches += 1;
+            stats.lsn = delivered;
+            debug!("CHANGEFEED: {} delivered {} changes to {}", options.name, chunk.len(), sink.name());
+        }
+
+        // Past everything read, markers and reserved keys included; the
+        // persisted marker catches up with the next delivery
+        *lsn = Some(batch.last_lsn);
+        stats.lock().lsn = batch.last_lsn;
+        Ok(())
+    }
+
+    /// Forget change feed `name`'s position, so it starts over at the
+    /// oldest change the WAL holds
+    pub async fn reset_change_feed(&self, name: &str) -> Result<bool> {
+        self.delete(&marker_key(name)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cleared_range() {
+        // Log cleared at LSN 10 (writes up to 9 gone)
+        assert_eq!(cleared_range(0, 10), Some(1..=9));
+        assert_eq!(cleared_range(8, 10), Some(9..=9));
+        assert_eq!(cleared_range(9, 10), None);
+        assert_eq!(cleared_range(12, 10), None);
+    }
+
+    #[test]
+    fn test_events_serialize_flat() {
+        let event = ChangeEvent {
+            lsn: 7,
+            key: "orders/1".to_string(),
+            change: Change::Set { value: "shipped".to_string() },
+            at_ms: 1_000,
+        };
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({"lsn": 7, "key": "orders/1", "op": "set", "value": "shipped", "at_ms": 1_000})
+        );
+        assert_eq!(Change::Delete.op(), "delete");
+    }
+}
@@ -0,0 +1,87 @@
+//! Checkpoint History: The Last Checkpoints, Kept in the Superblock
+//!
+//! A latency spike that lines up with a checkpoint, or a WAL that keeps
+//! growing because the checkpointer stopped, is easy to spot with a record
+//! of when checkpoints ran. Each successful `checkpoint` appends one to a
+//! ring of the last `CHECKPOINT_HISTORY`, persisted in the superblock so it
+//! survives restarts and failovers:
+//!
+//! ```ignore
+//! for checkpoint in store.checkpoint_history().await {
+//!     println!("LSN {} at {}: {} pages in {}ms",
+//!              checkpoint.lsn, checkpoint.at_ms, checkpoint.pages_flushed, checkpoint.duration_ms);
+//! }
+//! ```
+//!
+//! Failed checkpoints aren't recorded; listeners hear about them (see
+//! events.rs). Recording costs one more superblock write per checkpoint.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+
+/// Checkpoints kept; older ones are dropped
+pub const CHECKPOINT_HISTORY: usize = 16;
+
+/// A completed checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointRecord {
+    /// WAL position the checkpoint covers
+    pub lsn: u64,
+    /// When it finished (ms since the epoch)
+    pub at_ms: u64,
+    /// Dirty pages written to disk
+    pub pages_flushed: usize,
+    pub duration_ms: u64,
+}
+
+/// Append `record`, dropping the oldest beyond `CHECKPOINT_HISTORY`
+fn push_record(history: &mut VecDeque<CheckpointRecord>, record: CheckpointRecord) {
+    history.push_back(record);
+    while history.len() > CHECKPOINT_HISTORY {
+        history.pop_front();
+    }
+}
+
+impl KVStore {
+    /// The last checkpoints, oldest first (see checkpoint_history.rs)
+    pub async fn checkpoint_history(&self) -> Vec<CheckpointRecord> {
+        self.superblock().lock().await.checkpoints.iter().copied().collect()
+    }
+
+    /// Persist a checkpoint that just completed
+    pub(crate) async fn record_checkpoint(&self, pages_flushed: usize, duration: Duration) -> Result<()> {
+        let record = CheckpointRecord {
+            lsn: self.wal().current_lsn(),
+            at_ms: now_ms(),
+            pages_flushed,
+            duration_ms: duration.as_millis() as u64,
+        };
+        self.update_superblock(|sb| push_record(&mut sb.checkpoints, record)).await?;
+        debug!("CHECKPOINT: recorded {:?}", record);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::superblock::Superblock;
+
+    #[test]
+    fn test_history_keeps_the_last_checkpoints_and_fits_the_superblock() {
+        let mut superblock = Superblock::default();
+        for lsn in 0..CHECKPOINT_HISTORY as u64 + 5 {
+            let record = CheckpointRecord { lsn: u64::MAX - lsn, at_ms: u64::MAX, pages_flushed: usize::MAX, duration_ms: u64::MAX };
+            push_record(&mut superblock.checkpoints, record);
+        }
+        assert_eq!(superblock.checkpoints.len(), CHECKPOINT_HISTORY);
+        assert_eq!(superblock.checkpoints.front().unwrap().lsn, u64::MAX - 5);
+        assert!(superblock.encode().is_ok());
+    }
+}
@@ -0,0 +1,221 @@
+//! Codec: Pluggable Value Encodings
+//!
+//! The page layer stores raw value bytes; a `ValueCodec` decides how a typed
+//! value becomes those bytes. Codecs compose, so compression (or any other
+//! byte-level transform) wraps whichever codec produces the bytes:
+//!
+//! ```ignore
+//! let codec = CompressedZstd::new(Json::<Order>::new());
+//! let page = page::encode_kv_page_with(&codec, "order:1", &order)?;
+//! ```
+//!
+//! `Bincode` and `CompressedZstd` are behind the `bincode` and `zstd` features.
+
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::marker::PhantomData;
+
+use crate::error::IronCladError;
+
+/// Converts values to and from the bytes stored in a page
+pub trait ValueCodec {
+    /// The in-memory value type
+    type Value;
+
+    /// Serialize a value into bytes
+    fn encode(&self, value: &Self::Value) -> Result<Vec<u8>>;
+
+    /// Deserialize bytes produced by `encode`
+    fn decode(&self, bytes: &[u8]) -> Result<Self::Value>;
+}
+
+/// UTF-8 text (the store's native string values)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Utf8String;
+
+impl ValueCodec for Utf8String {
+    type Value = String;
+
+    fn encode(&self, value: &String) -> Result<Vec<u8>> {
+        Ok(value.as_bytes().to_vec())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<String> {
+        String::from_utf8(bytes.to_vec())
+            .map_err(|e| IronCladError::InvalidPageFormat(format!("value is not UTF-8: {}", e)).into())
+    }
+}
+
+/// Raw bytes, stored as-is
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Bytes;
+
+impl ValueCodec for Bytes {
+    type Value = Vec<u8>;
+
+    fn encode(&self, value: &Vec<u8>) -> Result<Vec<u8>> {
+        Ok(value.clone())
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>> {
+        Ok(bytes.to_vec())
+    }
+}
+
+/// Any serde type, stored as JSON
+#[derive(Debug, Clone, Copy)]
+pub struct Json<T>(PhantomData<T>);
+
+impl<T> Json<T> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T> Default for Json<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> ValueCodec for Json<T> {
+    type Value = T;
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// Any serde type, stored in bincode's compact binary format
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy)]
+pub struct Bincode<T>(PhantomData<T>);
+
+#[cfg(feature = "bincode")]
+impl<T> Bincode<T> {
+    pub fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl<T> Default for Bincode<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "bincode")]
+impl<T: Serialize + DeserializeOwned> ValueCodec for Bincode<T> {
+    type Value = T;
+
+    fn encode(&self, value: &T) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// Upper bound on decompressed size, so a corrupt frame can't balloon memory
+#[cfg(feature = "zstd")]
+const MAX_DECOMPRESSED_SIZE: usize = 16 * 1024 * 1024;
+
+/// Zstd compression around another codec
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy)]
+pub struct CompressedZstd<C> {
+    inner: C,
+    level: i32,
+}
+
+#[cfg(feature = "zstd")]
+impl<C: ValueCodec> CompressedZstd<C> {
+    /// Wrap `inner` using zstd's default compression level
+    pub fn new(inner: C) -> Self {
+        Self::with_level(inner, zstd::DEFAULT_COMPRESSION_LEVEL)
+    }
+
+    /// Wrap `inner` with an explicit compression level
+    pub fn with_level(inner: C, level: i32) -> Self {
+        Self { inner, level }
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl<C: ValueCodec> ValueCodec for CompressedZstd<C> {
+    type Value = C::Value;
+
+    fn encode(&self, value: &C::Value) -> Result<Vec<u8>> {
+        let raw = self.inner.encode(value)?;
+        Ok(zstd::bulk::compress(&raw, self.level)?)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<C::Value> {
+        let raw = zstd::bulk::decompress(bytes, MAX_DECOMPRESSED_SIZE)?;
+        self.inner.decode(&raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Order {
+        id: u64,
+        items: Vec<String>,
+    }
+
+    fn sample_order() -> Order {
+        Order { id: 7, items: vec!["apple".to_string(), "pear".to_string()] }
+    }
+
+    #[test]
+    fn test_utf8_round_trip() {
+        let codec = Utf8String;
+        let bytes = codec.encode(&"héllo".to_string()).unwrap();
+        assert_eq!(codec.decode(&bytes).unwrap(), "héllo");
+        assert!(codec.decode(&[0xFF, 0xFE]).is_err());
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let codec = Bytes;
+        let value = vec![0u8, 1, 2, 255];
+        assert_eq!(codec.decode(&codec.encode(&value).unwrap()).unwrap(), value);
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let codec = Json::<Order>::new();
+        let bytes = codec.encode(&sample_order()).unwrap();
+        assert_eq!(codec.decode(&bytes).unwrap(), sample_order());
+        assert!(codec.decode(b"not json").is_err());
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_round_trip() {
+        let codec = Bincode::<Order>::new();
+        let bytes = codec.encode(&sample_order()).unwrap();
+        assert_eq!(codec.decode(&bytes).unwrap(), sample_order());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_zstd_wraps_inner_codec() {
+        let codec = CompressedZstd::new(Utf8String);
+        let value = "abc".repeat(1000);
+        let bytes = codec.encode(&value).unwrap();
+        assert!(bytes.len() < value.len());
+        assert_eq!(codec.decode(&bytes).unwrap(), value);
+    }
+}
@@ -0,0 +1,172 @@
+//! Collation: How Keys Are Ordered in Range Scans
+//!
+//! Keys compare byte by byte unless their bucket (key prefix) says
+//! otherwise, so `item10` sorts before `item2`. A bucket's collation
+//! changes how `scan_range` orders and bounds its keys:
+//!
+//! ```ignore
+//! let options = StoreOptions {
+//!     collations: vec![
+//!         BucketCollation::new("items/", Collation::Numeric),
+//!         BucketCollation::new("users/", Collation::CaseInsensitiveAscii),
+//!     ],
+//!     ..Default::default()
+//! };
+//! // items/item2, items/item10, then items/item100
+//! let items = store.scan_range("items/item2", Some("items/item200")).await?;
+//! ```
+//!
+//! - `Binary`: byte order (the default)
+//! - `CaseInsensitiveAscii`: ASCII letters compare without case
+//! - `Numeric`: runs of digits compare by value, so `item2 < item10`
+//!
+//! Keys equal under a collation (`User` and `user`, `a01` and `a1`) are
+//! still distinct keys; they are ordered byte-wise among themselves. A key
+//! under several buckets uses the longest matching prefix. Collation only
+//! affects ordering: lookups still match keys exactly.
+
+use anyhow::Result;
+use std::cmp::Ordering;
+use tracing::info;
+
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::store_meta;
+
+/// How keys compare (see collation.rs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Collation {
+    #[default]
+    Binary,
+    CaseInsensitiveAscii,
+    /// Digit runs compare by value
+    Numeric,
+}
+
+impl Collation {
+    /// Compare two keys; only byte-identical keys are Equal
+    pub fn compare(&self, a: &str, b: &str) -> Ordering {
+        let collated = match self {
+            Collation::Binary => Ordering::Equal,
+            Collation::CaseInsensitiveAscii => {
+                a.bytes().map(|c| c.to_ascii_lowercase()).cmp(b.bytes().map(|c| c.to_ascii_lowercase()))
+            }
+            Collation::Numeric => compare_numeric(a.as_bytes(), b.as_bytes()),
+        };
+        collated.then_with(|| a.cmp(b))
+    }
+}
+
+/// Compare with digit runs ordered by value (leading zeros ignored)
+fn compare_numeric(a: &[u8], b: &[u8]) -> Ordering {
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if a[i].is_ascii_digit() && b[j].is_ascii_digit() {
+            let run = |s: &[u8], start: usize| start + s[start..].iter().take_while(|c| c.is_ascii_digit()).count();
+            let (end_a, end_b) = (run(a, i), run(b, j));
+            let trim = |digits: &[u8]| digits.iter().take_while(|&&c| c == b'0').count();
+            let (value_a, value_b) = (&a[i + trim(&a[i..end_a])..end_a], &b[j + trim(&b[j..end_b])..end_b]);
+            let order = value_a.len().cmp(&value_b.len()).then_with(|| value_a.cmp(value_b));
+            if order != Ordering::Equal {
+                return order;
+            }
+            (i, j) = (end_a, end_b);
+        } else {
+            if a[i] != b[j] {
+                return a[i].cmp(&b[j]);
+            }
+            (i, j) = (i + 1, j + 1);
+        }
+    }
+    (a.len() - i).cmp(&(b.len() - j))
+}
+
+/// The collation of the keys starting with `prefix`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BucketCollation {
+    pub prefix: String,
+    pub collation: Collation,
+}
+
+impl BucketCollation {
+    pub fn new(prefix: &str, collation: Collation) -> Self {
+        Self { prefix: prefix.to_string(), collation }
+    }
+}
+
+/// The bucket covering `key` (the longest matching prefix), if any
+fn bucket_for<'c>(collations: &'c [BucketCollation], key: &str) -> Option<&'c BucketCollation> {
+    collations.iter().filter(|bucket| key.starts_with(&bucket.prefix)).max_by_key(|bucket| bucket.prefix.len())
+}
+
+impl KVStore {
+    /// Entries from `start` up to (not including) `end`, or to the end of
+    /// the bucket, ordered by the collation of `start`'s bucket
+    ///
+    /// The scan covers that bucket only; `end` must fall in it too. Store
+    /// metadata (`__meta/`) is skipped, as in `scan`.
+    pub async fn scan_range(&self, start: &str, end: Option<&str>) -> Result<Vec<(String, String)>> {
+        let (keys, collation) = self.range_keys(start, end).await?;
+        let mut results = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.get(&key).await? {
+                results.push((key, value));
+            }
+        }
+        info!("SCAN: {} entries from {} ({:?})", results.len(), start, collation);
+        Ok(results)
+    }
+
+    /// The keys `scan_range` covers, in order, and the collation ordering them
+    pub(crate) async fn range_keys(&self, start: &str, end: Option<&str>) -> Result<(Vec<String>, Collation)> {
+        let bucket = bucket_for(&self.options().collations, start);
+        let (prefix, collation) = bucket.map_or(("", Collation::Binary), |bucket| (bucket.prefix.as_str(), bucket.collation));
+        if let Some(end) = end {
+            if bucket_for(&self.options().collations, end) != bucket {
+                let reason = format!("{} and {} are in different collation buckets", start, end);
+                return Err(IronCladError::NotSupported { operation: "scan_range", reason }.into());
+            }
+        }
+
+        let mut keys: Vec<String> = self
+            .all_keys()
+            .await?
+            .into_iter()
+            .filter(|key| key.starts_with(prefix) && bucket_for(&self.options().collations, key) == bucket)
+            .filter(|key| collation.compare(key, start) != Ordering::Less)
+            .filter(|key| end.is_none_or(|end| collation.compare(key, end) == Ordering::Less))
+            .filter(|key| !store_meta::is_meta(key))
+            .collect();
+        keys.sort_by(|a, b| collation.compare(a, b));
+        Ok((keys, collation))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(collation: Collation, keys: &[&str]) -> Vec<String> {
+        let mut keys: Vec<String> = keys.iter().map(|key| key.to_string()).collect();
+        keys.sort_by(|a, b| collation.compare(a, b));
+        keys
+    }
+
+    #[test]
+    fn test_collations_order_keys() {
+        let keys = ["item10", "Item3", "item2", "item02", "item", "itemb"];
+        assert_eq!(sorted(Collation::Binary, &keys), ["Item3", "item", "item02", "item10", "item2", "itemb"]);
+        assert_eq!(sorted(Collation::CaseInsensitiveAscii, &keys), ["item", "item02", "item10", "item2", "Item3", "itemb"]);
+        assert_eq!(sorted(Collation::Numeric, &keys), ["Item3", "item", "item02", "item2", "item10", "itemb"]);
+        assert_eq!(Collation::Numeric.compare("v1.10", "v1.9"), Ordering::Greater);
+        assert_eq!(Collation::Numeric.compare("a007", "a7"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_longest_bucket_wins() {
+        let collations = vec![BucketCollation::new("a/", Collation::Numeric), BucketCollation::new("a/b/", Collation::Binary)];
+        assert_eq!(bucket_for(&collations, "a/b/1").unwrap().prefix, "a/b/");
+        assert_eq!(bucket_for(&collations, "a/c").unwrap().collation, Collation::Numeric);
+        assert!(bucket_for(&collations, "b").is_none());
+    }
+}
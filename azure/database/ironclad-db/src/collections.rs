@@ -0,0 +1,335 @@
+//! Collections: Lists and Sets on Top of Keys
+//!
+//! `StoreList` is a double-ended list and `StoreSet` an unordered set of
+//! strings, each stored as a family of keys plus a metadata record:
+//!
+//! ```text
+//! __list/<name>            {"head": .., "tail": ..}   positions head..tail are live
+//! __list/<name>/<pos:016x> element at that position
+//! __set/<name>             {"len": ..}
+//! __set/<name>/<member>    (empty value)
+//! ```
+//!
+//! List positions start in the middle of the `u64` range so both ends can
+//! grow; hex padding keeps element keys in list order.
+//!
+//! Every operation reads the metadata and commits the element writes and
+//! the updated metadata as one WAL batch under the commit lock, so
+//! concurrent pushes can't claim the same position and a crash never
+//! leaves a count that disagrees with the members.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::kvstore::KVStore;
+use crate::wal::WalEntry;
+
+/// Key prefix for lists
+const LIST_PREFIX: &str = "__list/";
+
+/// Key prefix for sets
+const SET_PREFIX: &str = "__set/";
+
+/// Position of the first element pushed onto an empty list
+const MIDDLE: u64 = 1 << 63;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+struct ListMeta {
+    head: u64,
+    tail: u64,
+}
+
+impl Default for ListMeta {
+    fn default() -> Self {
+        Self { head: MIDDLE, tail: MIDDLE }
+    }
+}
+
+impl ListMeta {
+    fn len(&self) -> u64 {
+        self.tail - self.head
+    }
+
+    /// Positions of elements `start..end` (clamped to the list)
+    fn positions(&self, start: u64, end: u64) -> std::ops::Range<u64> {
+        let len = self.len();
+        self.head + start.min(len)..self.head + end.min(len)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+struct SetMeta {
+    len: u64,
+}
+
+/// Handle to a list
+pub struct StoreList<'a> {
+    store: &'a KVStore,
+    meta_key: String,
+}
+
+impl<'a> StoreList<'a> {
+    /// Number of elements
+    pub async fn len(&self) -> Result<u64> {
+        Ok(self.meta().await?.len())
+    }
+
+    pub async fn is_empty(&self) -> Result<bool> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// Add to the back; returns the new length
+    pub async fn push(&self, value: &str) -> Result<u64> {
+        let _commit_guard = self.store.lock_commits().await;
+        let mut meta = self.meta().await?;
+        let position = meta.tail;
+        meta.tail += 1;
+        self.commit(position, Some(value), meta).await?;
+        Ok(meta.len())
+    }
+
+    /// Add to the front; returns the new length
+    pub async fn push_front(&self, value: &str) -> Result<u64> {
+        let _commit_guard = self.store.lock_commits().await;
+        let mut meta = self.meta().await?;
+        meta.head -= 1;
+        self.commit(meta.head, Some(value), meta).await?;
+        Ok(meta.len())
+    }
+
+    /// Remove from the back
+    pub async fn pop(&self) -> Result<Option<String>> {
+        let _commit_guard = self.store.lock_commits().await;
+        let mut meta = self.meta().await?;
+        if meta.len() == 0 {
+            return Ok(None);
+        }
+        meta.tail -= 1;
+        let value = self.store.get(&self.element_key(meta.tail)).await?;
+        self.commit(meta.tail, None, meta).await?;
+        Ok(value)
+    }
+
+    /// Remove from the front
+    pub async fn pop_front(&self) -> Result<Option<String>> {
+        let _commit_guard = self.store.lock_commits().await;
+        let mut meta = self.meta().await?;
+        if meta.len() == 0 {
+            return Ok(None);
+        }
+        let position = meta.head;
+        meta.head += 1;
+        let value = self.store.get(&self.element_key(position)).await?;
+        self.commit(position, None, meta).await?;
+        Ok(value)
+    }
+
+    /// Element at `index` (0 = front)
+    pub async fn get(&self, index: u64) -> Result<Option<String>> {
+        let meta = self.meta().await?;
+        match meta.positions(index, index.saturating_add(1)).next() {
+            Some(position) => self.store.get(&self.element_key(position)).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Elements `start..end` (0 = front; clamped to the list)
+    pub async fn range(&self, start: u64, end: u64) -> Result<Vec<String>> {
+        let meta = self.meta().await?;
+        let mut values = Vec::new();
+        for position in meta.positions(start, end) {
+            values.extend(self.store.get(&self.element_key(position)).await?);
+        }
+        Ok(values)
+    }
+
+    async fn meta(&self) -> Result<ListMeta> {
+        match self.store.get(&self.meta_key).await? {
+            Some(stored) => Ok(serde_json::from_str(&stored)?),
+            None => Ok(ListMeta::default()),
+        }
+    }
+
+    /// Write (or delete) the element at `position` together with `meta`
+    async fn commit(&self, position: u64, value: Option<&str>, meta: ListMeta) -> Result<()> {
+        let key = self.element_key(position);
+        let element = match value {
+            Some(value) => WalEntry::set(&key, value),
+            None => WalEntry::Delete { key },
+        };
+        let meta = match meta.len() {
+            0 => WalEntry::Delete { key: self.meta_key.clone() },
+            _ => WalEntry::set(&self.meta_key, &serde_json::to_string(&meta)?),
+        };
+        self.store.commit_batch_locked(self.store.new_txn_id(), vec![element, meta], None).await?;
+        Ok(())
+    }
+
+    fn element_key(&self, position: u64) -> String {
+        format!("{}/{:016x}", self.meta_key, position)
+    }
+}
+
+/// Handle to a set
+pub struct StoreSet<'a> {
+    store: &'a KVStore,
+    meta_key: String,
+}
+
+impl<'a> StoreSet<'a> {
+    /// Number of members
+    pub async fn len(&self) -> Result<u64> {
+        Ok(self.meta().await?.len)
+    }
+
+    pub async fn is_empty(&self) -> Result<bool> {
+        Ok(self.len().await? == 0)
+    }
+
+    /// Add a member; returns false if it was already present
+    pub async fn add(&self, member: &str) -> Result<bool> {
+        let _commit_guard = self.store.lock_commits().await;
+        if self.contains(member).await? {
+            return Ok(false);
+        }
+        let mut meta = self.meta().await?;
+        meta.len += 1;
+        self.commit(WalEntry::set(&self.member_key(member), ""), meta).await?;
+        Ok(true)
+    }
+
+    /// Remove a member; returns false if it wasn't present
+    pub async fn remove(&self, member: &str) -> Result<bool> {
+        let _commit_guard = self.store.lock_commits().await;
+        if !self.contains(member).await? {
+            return Ok(false);
+        }
+        let mut meta = self.meta().await?;
+        meta.len = meta.len.saturating_sub(1);
+        self.commit(WalEntry::Delete { key: self.member_key(member) }, meta).await?;
+        Ok(true)
+    }
+
+    pub async fn contains(&self, member: &str) -> Result<bool> {
+        Ok(self.store.get(&self.member_key(member)).await?.is_some())
+    }
+
+    /// All members, sorted
+    pub fn members(&self) -> Vec<String> {
+        let prefix = self.member_key("");
+        let mut members: Vec<String> = self
+            .store
+            .keys_with_prefix(&prefix)
+            .into_iter()
+            .map(|key| key[prefix.len()..].to_string())
+            .collect();
+        members.sort();
+        members
+    }
+
+    async fn meta(&self) -> Result<SetMeta> {
+        match self.store.get(&self.meta_key).await? {
+            Some(stored) => Ok(serde_json::from_str(&stored)?),
+            None => Ok(SetMeta::default()),
+        }
+    }
+
+    async fn commit(&self, member: WalEntry, meta: SetMeta) -> Result<()> {
+        let meta = match meta.len {
+            0 => WalEntry::Delete { key: self.meta_key.clone() },
+            _ => WalEntry::set(&self.meta_key, &serde_json::to_string(&meta)?),
+        };
+        self.store.commit_batch_locked(self.store.new_txn_id(), vec![member, meta], None).await?;
+        Ok(())
+    }
+
+    fn member_key(&self, member: &str) -> String {
+        format!("{}/{}", self.meta_key, member)
+    }
+}
+
+impl KVStore {
+    /// Handle to the list `name` (empty until pushed to)
+    pub fn list(&self, name: &str) -> Result<StoreList<'_>> {
+        validate_name(name)?;
+        debug!("Opened list {}", name);
+        Ok(StoreList { store: self, meta_key: format!("{}{}", LIST_PREFIX, name) })
+    }
+
+    /// Handle to the set `name` (empty until added to)
+    pub fn set_collection(&self, name: &str) -> Result<StoreSet<'_>> {
+        validate_name(name)?;
+        debug!("Opened set {}", name);
+        Ok(StoreSet { store: self, meta_key: format!("{}{}", SET_PREFIX, name) })
+    }
+}
+
+/// Names become key prefixes, so a '/' would let one collection's keys
+/// show up in another's
+pub(crate) fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') {
+        anyhow::bail!("Invalid collection name {:?}: must be non-empty and contain no '/'", name);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fresh_options, open_store};
+
+    #[test]
+    fn test_list_positions_are_clamped() {
+        let meta = ListMeta { head: MIDDLE - 2, tail: MIDDLE + 3 };
+        assert_eq!(meta.len(), 5);
+        assert_eq!(meta.positions(1, 3), MIDDLE - 1..MIDDLE + 1);
+        assert_eq!(meta.positions(4, 100), MIDDLE + 2..MIDDLE + 3);
+        assert!(meta.positions(7, 9).is_empty());
+        assert_eq!(ListMeta::default().len(), 0);
+    }
+
+    #[test]
+    fn test_collection_names() {
+        assert!(validate_name("queue").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("a/b").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_collections_survive_reopen() {
+        let options = fresh_options();
+        {
+            let store = open_store(options.clone()).await;
+            let list = store.list("jobs").unwrap();
+            for job in ["b", "c", "d"] {
+                list.push(job).await.unwrap();
+            }
+            assert_eq!(list.push_front("a").await.unwrap(), 4);
+            assert_eq!(list.pop().await.unwrap().as_deref(), Some("d"));
+            assert_eq!(list.range(0, 10).await.unwrap(), vec!["a", "b", "c"]);
+            assert_eq!(list.get(1).await.unwrap().as_deref(), Some("b"));
+
+            let set = store.set_collection("tags").unwrap();
+            assert!(set.add("red").await.unwrap());
+            assert!(set.add("blue").await.unwrap());
+            assert!(!set.add("red").await.unwrap());
+            assert!(set.remove("blue").await.unwrap());
+            assert!(!set.remove("blue").await.unwrap());
+            // A set whose name extends this one's doesn't leak into it
+            store.set_collection("tagsx").unwrap().add("green").await.unwrap();
+            assert_eq!(set.members(), vec!["red"]);
+            store.flush().await.unwrap();
+        }
+
+        let store = open_store(options).await;
+        let list = store.list("jobs").unwrap();
+        assert_eq!(list.len().await.unwrap(), 3);
+        assert_eq!(list.pop_front().await.unwrap().as_deref(), Some("a"));
+        assert_eq!(list.range(0, 10).await.unwrap(), vec!["b", "c"]);
+        let set = store.set_collection("tags").unwrap();
+        assert_eq!(set.len().await.unwrap(), 1);
+        assert!(set.contains("red").await.unwrap());
+        assert_eq!(set.members(), vec!["red"]);
+    }
+}
@@ -0,0 +1,403 @@
+//! Column Families: Named Key Namespaces Within One Store
+//!
+//! A column family is an independent key space with its own options,
+//! sharing the store's WAL, buffer pool and data blob. Families are created
+//! and dropped at runtime:
+//!
+//! ```ignore
+//! let sessions = store
+//!     .create_column_family("sessions", ColumnFamilyOptions {
+//!         default_ttl: Some(Duration::from_secs(3600)),
+//!         ..Default::default()
+//!     })
+//!     .await?;
+//! sessions.set("user:1", "token").await?;
+//! ```
+//!
+//! Definitions live under `__cf/<name>`; a family's keys are stored as
+//! `__cfd/<id>/<key>`, where `id` is chosen when the family is created. A
+//! dropped family's definition is removed first, so a drop interrupted by a
+//! crash leaves only unreachable keys behind, never keys that show up in a
+//! new family of the same name.
+//!
+//! Each family's pages form their own buffer pool cache group, with hit/miss
+//! counters (`ColumnFamily::stats`) and an optional quota on how much of the
+//! pool the family may occupy.
+//!
+//! Values are stored in a small JSON envelope carrying the expiry time and
+//! whether the value is compressed. Expired values read as absent; they are
+//! not removed until overwritten, deleted, reclaimed by `enforce_retention`
+//! (see retention.rs) or the family is dropped.
+
+use anyhow::Result;
+use base64::{engine::general_purpose, Engine as _};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::info;
+
+use crate::buffer_pool::{CachePriority, GroupStats};
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+
+/// Key prefix for column family definitions
+const DEFINITION_PREFIX: &str = "__cf/";
+
+/// Key prefix for column family data
+pub(crate) const DATA_PREFIX: &str = "__cfd/";
+
+/// Value compression for a column family
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Compression {
+    #[default]
+    None,
+    /// zstd (requires the `zstd` feature)
+    Zstd,
+}
+
+/// Per-family options, fixed when the family is created
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct ColumnFamilyOptions {
+    /// Expiry applied by `set` (None = values never expire)
+    pub default_ttl: Option<Duration>,
+
+    /// How values are compressed
+    pub compression: Compression,
+
+    /// How eagerly the family's pages are cached on read
+    pub cache_priority: CachePriority,
+
+    /// Most of the buffer pool the family may occupy, in percent (None = uncapped)
+    #[serde(default)]
+    pub cache_quota_percent: Option<u8>,
+}
+
+/// Usage of one column family
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnFamilyStats {
+    pub name: String,
+    /// Keys stored in the family (expired ones included until removed)
+    pub num_keys: usize,
+    /// Buffer pool usage and counters of the family's pages
+    pub cache: GroupStats,
+}
+
+/// Persisted family definition
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Definition {
+    id: String,
+    options: ColumnFamilyOptions,
+}
+
+/// Stored form of a family value
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Envelope {
+    value: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    zstd: bool,
+}
+
+fn is_false(flag: &bool) -> bool {
+    !*flag
+}
+
+/// Handle to a column family
+pub struct ColumnFamily<'a> {
+    store: &'a KVStore,
+    name: String,
+    prefix: String,
+    options: ColumnFamilyOptions,
+}
+
+impl<'a> ColumnFamily<'a> {
+    fn new(store: &'a KVStore, name: &str, definition: Definition) -> Self {
+        let quota = definition.options.cache_quota_percent.map(|percent| percent as f64 / 100.0);
+        store.buffer_pool().set_group_quota(&definition.id, quota);
+
+        Self {
+            store,
+            name: name.to_string(),
+            prefix: format!("{}{}/", DATA_PREFIX, definition.id),
+            options: definition.options,
+        }
+    }
+
+    /// Key count and cache usage of the family
+    pub fn stats(&self) -> ColumnFamilyStats {
+        let group = &self.prefix[DATA_PREFIX.len()..self.prefix.len() - 1];
+        ColumnFamilyStats {
+            name: self.name.clone(),
+            num_keys: self.store.keys_with_prefix(&self.prefix).len(),
+            cache: self.store.buffer_pool().group_stats(group).unwrap_or_default(),
+        }
+    }
+
+    /// Prefix of the family's keys in the store (e.g. for an encryption scope)
+    pub fn key_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Family name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Options the family was created with
+    pub fn options(&self) -> &ColumnFamilyOptions {
+        &self.options
+    }
+
+    /// Set a key, expiring after the family's default TTL (if any)
+    pub async fn set(&self, key: &str, value: &str) -> Result<()> {
+        self.write(key, value, self.options.default_ttl).await
+    }
+
+    /// Set a key with an explicit TTL, overriding the family default
+    pub async fn set_with_ttl(&self, key: &str, value: &str, ttl: Duration) -> Result<()> {
+        self.write(key, value, Some(ttl)).await
+    }
+
+    /// Get a key's value (None if absent or expired)
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        let stored = self
+            .store
+            .get_with_priority(&self.data_key(key), self.options.cache_priority)
+            .await?;
+
+        match stored {
+            Some(stored) => open_envelope(&stored, now_ms()),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete a key; returns whether it existed
+    pub async fn delete(&self, key: &str) -> Result<bool> {
+        self.store.delete(&self.data_key(key)).await
+    }
+
+    /// All live key-value pairs in the family
+    pub async fn scan(&self) -> Result<Vec<(String, String)>> {
+        let mut results = Vec::new();
+
+        for data_key in self.store.keys_with_prefix(&self.prefix) {
+            let key = &data_key[self.prefix.len()..];
+            if let Some(value) = self.get(key).await? {
+                results.push((key.to_string(), value));
+            }
+        }
+
+        Ok(results)
+    }
+
+    async fn write(&self, key: &str, value: &str, ttl: Option<Duration>) -> Result<()> {
+        let expires_at_ms = ttl.map(|ttl| now_ms() + ttl.as_millis() as u64);
+        let stored = seal_envelope(value, expires_at_ms, self.options.compression)?;
+        self.store.set(&self.data_key(key), &stored).await
+    }
+
+    fn data_key(&self, key: &str) -> String {
+        format!("{}{}", self.prefix, key)
+    }
+}
+
+impl KVStore {
+    /// Create a column family; fails with `ColumnFamilyExists` if the name is taken
+    pub async fn create_column_family(
+        &self,
+        name: &str,
+        options: ColumnFamilyOptions,
+    ) -> Result<ColumnFamily<'_>> {
+        validate_name(name)?;
+
+        if options.compression == Compression::Zstd && !cfg!(feature = "zstd") {
+            anyhow::bail!("Column family {} requests zstd compression, but the zstd feature is disabled", name);
+        }
+
+        let definition = Definition {
+            id: format!("{:016x}", rand::thread_rng().gen::<u64>()),
+            options,
+        };
+
+        if !self.set_nx(&definition_key(name), &serde_json::to_string(&definition)?).await? {
+            return Err(IronCladError::ColumnFamilyExists { name: name.to_string() }.into());
+        }
+
+        info!("Created column family {} (id {})", name, definition.id);
+        Ok(ColumnFamily::new(self, name, definition))
+    }
+
+    /// Open an existing column family
+    pub async fn column_family(&self, name: &str) -> Result<Option<ColumnFamily<'_>>> {
+        validate_name(name)?;
+
+        match self.get(&definition_key(name)).await? {
+            Some(stored) => {
+                let definition: Definition = serde_json::from_str(&stored)?;
+                Ok(Some(ColumnFamily::new(self, name, definition)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Names of all column families
+    pub fn list_column_families(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .keys_with_prefix(DEFINITION_PREFIX)
+            .into_iter()
+            .map(|key| key[DEFINITION_PREFIX.len()..].to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Stats for every column family
+    pub async fn column_family_stats(&self) -> Result<Vec<ColumnFamilyStats>> {
+        let mut stats = Vec::new();
+        for name in self.list_column_families() {
+            if let Some(family) = self.column_family(&name).await? {
+                stats.push(family.stats());
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Apply every family's cache quota to the buffer pool (on open)
+    pub(crate) async fn register_column_families(&self) -> Result<()> {
+        for name in self.list_column_families() {
+            self.column_family(&name).await?;
+        }
+        Ok(())
+    }
+
+    /// Drop a column family and all its keys; returns whether it existed
+    pub async fn drop_column_family(&self, name: &str) -> Result<bool> {
+        let family = match self.column_family(name).await? {
+            Some(family) => family,
+            None => return Ok(false),
+        };
+
+        // Unlink the definition first: leftovers from an interrupted drop are unreachable
+        self.delete(&definition_key(name)).await?;
+
+        let keys = self.keys_with_prefix(&family.prefix);
+        for key in &keys {
+            self.delete(key).await?;
+        }
+
+        info!("Dropped column family {} ({} keys)", name, keys.len());
+        Ok(true)
+    }
+}
+
+/// Buffer pool cache group of a store key (the family ID for family data)
+pub(crate) fn cache_group(key: &str) -> Option<&str> {
+    key.strip_prefix(DATA_PREFIX)?.split_once('/').map(|(id, _)| id)
+}
+
+fn definition_key(name: &str) -> String {
+    format!("{}{}", DEFINITION_PREFIX, name)
+}
+
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains('/') {
+        anyhow::bail!("Invalid column family name {:?}: must be non-empty and contain no '/'", name);
+    }
+    Ok(())
+}
+
+fn seal_envelope(value: &str, expires_at_ms: Option<u64>, compression: Compression) -> Result<String> {
+    let envelope = match compression {
+        Compression::None => Envelope { value: value.to_string(), expires_at_ms, zstd: false },
+        Compression::Zstd => Envelope {
+            value: general_purpose::STANDARD.encode(compress(value.as_bytes())?),
+            expires_at_ms,
+            zstd: true,
+        },
+    };
+    Ok(serde_json::to_string(&envelope)?)
+}
+
+/// Has the family value stored as `stored` expired?
+pub(crate) fn envelope_expired(stored: &str, now_ms: u64) -> bool {
+    serde_json::from_str::<Envelope>(stored).is_ok_and(|envelope| envelope.expires_at_ms.is_some_and(|expires| now_ms >= expires))
+}
+
+fn open_envelope(stored: &str, now_ms: u64) -> Result<Option<String>> {
+    let envelope: Envelope = serde_json::from_str(stored)?;
+
+    if envelope.expires_at_ms.is_some_and(|expires| now_ms >= expires) {
+        return Ok(None);
+    }
+
+    if !envelope.zstd {
+        return Ok(Some(envelope.value));
+    }
+
+    let compressed = general_purpose::STANDARD.decode(envelope.value)?;
+    Ok(Some(String::from_utf8(decompress(&compressed)?)?))
+}
+
+#[cfg(feature = "zstd")]
+fn compress(bytes: &[u8]) -> Result<Vec<u8>> {
+    Ok(zstd::encode_all(bytes, 0)?)
+}
+
+#[cfg(feature = "zstd")]
+fn decompress(bytes: &[u8]) -> Result<Vec<u8>> {
+    Ok(zstd::decode_all(bytes)?)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compress(_bytes: &[u8]) -> Result<Vec<u8>> {
+    anyhow::bail!("zstd compression requires the zstd feature")
+}
+
+#[cfg(not(feature = "zstd"))]
+fn decompress(_bytes: &[u8]) -> Result<Vec<u8>> {
+    anyhow::bail!("zstd compression requires the zstd feature")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let stored = seal_envelope("hello", None, Compression::None).unwrap();
+        assert_eq!(open_envelope(&stored, 0).unwrap().as_deref(), Some("hello"));
+    }
+
+    #[test]
+    fn test_envelope_expiry() {
+        let stored = seal_envelope("hello", Some(1_000), Compression::None).unwrap();
+        assert_eq!(open_envelope(&stored, 999).unwrap().as_deref(), Some("hello"));
+        assert_eq!(open_envelope(&stored, 1_000).unwrap(), None);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn test_compressed_envelope() {
+        let value = "abc".repeat(200);
+        let stored = seal_envelope(&value, None, Compression::Zstd).unwrap();
+        assert!(stored.len() < value.len());
+        assert_eq!(open_envelope(&stored, 0).unwrap(), Some(value));
+    }
+
+    #[test]
+    fn test_cache_group() {
+        assert_eq!(cache_group("__cfd/00ab/user:1"), Some("00ab"));
+        assert_eq!(cache_group("__cfd/00ab/a/b"), Some("00ab"));
+        assert_eq!(cache_group("user:1"), None);
+        assert_eq!(cache_group("__cf/users"), None);
+    }
+
+    #[test]
+    fn test_name_validation() {
+        assert!(validate_name("users").is_ok());
+        assert!(validate_name("").is_err());
+        assert!(validate_name("a/b").is_err());
+    }
+}
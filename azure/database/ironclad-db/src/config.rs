@@ -0,0 +1,136 @@
+//! Config: Storage Account Connection Settings
+//!
+//! Parses Azure Storage connection strings
+//! (`DefaultEndpointsProtocol=https;AccountName=...;AccountKey=...;EndpointSuffix=...`)
+//! into the pieces the pager and WAL need to build their blob clients.
+
+use anyhow::Result;
+use azure_core::ClientOptions;
+use azure_storage::prelude::*;
+use azure_storage::CloudLocation;
+use azure_storage_blobs::prelude::*;
+use std::sync::Arc;
+
+use crate::deadline::DeadlinePolicy;
+use crate::request_tags::{RequestTagPolicy, ServerRequestIdPolicy};
+use crate::retry_budget::RetryBudgetPolicy;
+use crate::storage_metrics::{AttemptMetricsPolicy, CallMetricsPolicy};
+
+/// Credentials extracted from an Azure Storage connection string
+#[derive(Clone, PartialEq, Eq)]
+pub struct ConnectionConfig {
+    pub account_name: String,
+    pub account_key: String,
+}
+
+impl ConnectionConfig {
+    /// Parse a connection string
+    /// 
+    /// Unknown settings are ignored; AccountName and AccountKey are required.
+    pub fn parse(connection_string: &str) -> Result<Self> {
+        // Manual connection string parsing
+        let mut account_name = String::new();
+        let mut account_key = String::new();
+
+        for part in connection_string.split(';') {
+            if let Some((key, value)) = part.split_once('=') {
+                match key.trim() {
+                    "AccountName" => account_name = value.trim().to_string(),
+                    "AccountKey" => account_key = value.trim().to_string(),
+                    _ => {}
+                }
+            }
+        }
+
+        if account_name.is_empty() || account_key.is_empty() {
+            anyhow::bail!("Invalid connection string: missing AccountName or AccountKey");
+        }
+
+        Ok(Self { account_name, account_key })
+    }
+
+    /// Build a container client for this account
+    /// 
+    /// Its requests are tagged for Azure diagnostics (see request_tags.rs) and
+    /// feed the throttling and retry counters (see storage_metrics.rs); each
+    /// call is bounded by the caller's deadline, if any (see deadline.rs),
+    /// and retries draw on the account's retry budget (see retry_budget.rs).
+    pub fn container_client(&self, container_name: &str) -> ContainerClient {
+        let creds = StorageCredentials::access_key(self.account_name.clone(), self.account_key.clone());
+        ClientBuilder::new(self.account_name.clone(), creds)
+            .client_options(self.client_options(container_name))
+            .container_client(container_name)
+    }
+
+    /// Build a container client for this account at another blob endpoint,
+    /// e.g. its RA-GRS secondary (see geo_reads.rs)
+    pub fn container_client_at(&self, endpoint: &str, container_name: &str) -> ContainerClient {
+        let creds = StorageCredentials::access_key(self.account_name.clone(), self.account_key.clone());
+        let location = CloudLocation::Custom { account: self.account_name.clone(), uri: endpoint.to_string() };
+        ClientBuilder::with_location(location, creds)
+            .client_options(self.client_options(container_name))
+            .container_client(container_name)
+    }
+
+    /// `endpoint`, or the account's default secondary blob endpoint
+    pub fn secondary_endpoint(&self, endpoint: Option<&str>) -> String {
+        endpoint.map_or_else(|| format!("https://{}-secondary.blob.core.windows.net", self.account_name), str::to_string)
+    }
+
+    fn client_options(&self, container_name: &str) -> ClientOptions {
+        let mut options = ClientOptions::default();
+        options.per_call_policies_mut().push(Arc::new(RequestTagPolicy::new(container_name)));
+        options.per_call_policies_mut().push(Arc::new(CallMetricsPolicy));
+        options.per_call_policies_mut().push(Arc::new(DeadlinePolicy));
+        options.per_retry_policies_mut().push(Arc::new(AttemptMetricsPolicy));
+        options.per_retry_policies_mut().push(Arc::new(ServerRequestIdPolicy));
+        options.per_retry_policies_mut().push(Arc::new(RetryBudgetPolicy::new(&self.account_name)));
+        options
+    }
+}
+
+// Keep the account key out of logs
+impl std::fmt::Debug for ConnectionConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ConnectionConfig")
+            .field("account_name", &self.account_name)
+            .field("account_key", &"<redacted>")
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_connection_string() {
+        let config = ConnectionConfig::parse(
+            "DefaultEndpointsProtocol=https;AccountName=ironcladstor;AccountKey=abc/def==;EndpointSuffix=core.windows.net",
+        )
+        .unwrap();
+
+        assert_eq!(config.account_name, "ironcladstor");
+        assert_eq!(config.account_key, "abc/def==");
+    }
+
+    #[test]
+    fn test_missing_settings() {
+        assert!(ConnectionConfig::parse("test-connection").is_err());
+        assert!(ConnectionConfig::parse("AccountName=foo").is_err());
+        assert!(ConnectionConfig::parse("AccountKey=bar;;=;").is_err());
+    }
+
+    #[test]
+    fn test_secondary_endpoint() {
+        let config = ConnectionConfig::parse("AccountName=foo;AccountKey=bar").unwrap();
+        assert_eq!(config.secondary_endpoint(None), "https://foo-secondary.blob.core.windows.net");
+        assert_eq!(config.secondary_endpoint(Some("https://example.test")), "https://example.test");
+    }
+
+    #[test]
+    fn test_debug_redacts_key() {
+        let config = ConnectionConfig::parse("AccountName=foo;AccountKey=secret").unwrap();
+        assert!(!format!("{:?}", config).contains("secret"));
+    }
+}
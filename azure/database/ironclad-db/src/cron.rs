@@ -0,0 +1,197 @@
+//! Cron: Minimal Cron Expressions for Scheduled Maintenance
+//!
+//! Standard five-field expressions evaluated in UTC:
+//!
+//! ```text
+//! minute (0-59)  hour (0-23)  day-of-month (1-31)  month (1-12)  day-of-week (0-6, 0 or 7 = Sunday)
+//! ```
+//!
+//! Each field accepts `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`,
+//! or a comma-separated list of those. As in classic cron, when both
+//! day-of-month and day-of-week are restricted a day matching either runs.
+
+use anyhow::Result;
+
+/// A parsed cron expression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    /// Day-of-month / day-of-week restricted (not `*`)
+    dom_restricted: bool,
+    dow_restricted: bool,
+}
+
+/// Longest gap searched for a matching minute (covers leap days)
+const MAX_SEARCH_MINUTES: u64 = 4 * 366 * 24 * 60;
+
+impl CronSchedule {
+    /// Parse a five-field expression such as `"30 2 * * *"` (02:30 UTC daily)
+    pub fn parse(expr: &str) -> Result<Self> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            anyhow::bail!("Invalid cron expression {:?}: expected 5 fields, got {}", expr, fields.len());
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7)?;
+        // 7 is an alias for Sunday
+        if days_of_week & (1 << 7) != 0 {
+            days_of_week = (days_of_week | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days_of_month: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            days_of_week,
+            dom_restricted: fields[2] != "*",
+            dow_restricted: fields[4] != "*",
+        })
+    }
+
+    /// First matching minute strictly after `unix_secs`, as Unix seconds
+    pub fn next_after(&self, unix_secs: u64) -> Option<u64> {
+        let first = unix_secs / 60 + 1;
+
+        (first..first + MAX_SEARCH_MINUTES)
+            .find(|&minute| self.matches(minute))
+            .map(|minute| minute * 60)
+    }
+
+    /// Does the minute `unix_minute` (minutes since the epoch) match?
+    fn matches(&self, unix_minute: u64) -> bool {
+        let days = (unix_minute / (24 * 60)) as i64;
+        let minute_of_day = unix_minute % (24 * 60);
+        let (_, month, day) = civil_from_days(days);
+        let weekday = (days + 4).rem_euclid(7) as u32; // 1970-01-01 was a Thursday
+
+        let dom = bit(self.days_of_month, day);
+        let dow = bit(self.days_of_week, weekday);
+        let day_matches = match (self.dom_restricted, self.dow_restricted) {
+            (true, true) => dom || dow,
+            _ => dom && dow,
+        };
+
+        bit(self.minutes, (minute_of_day % 60) as u32)
+            && bit(self.hours, (minute_of_day / 60) as u32)
+            && bit(self.months, month)
+            && day_matches
+    }
+}
+
+fn bit(mask: u64, n: u32) -> bool {
+    mask & (1 << n) != 0
+}
+
+/// Parse one field into a bitmask of allowed values in `min..=max`
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64> {
+    let mut mask = 0u64;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, parse_number(step, 1, max.max(1))?),
+            None => (part, 1),
+        };
+
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (parse_number(start, min, max)?, parse_number(end, min, max)?)
+        } else {
+            let value = parse_number(range, min, max)?;
+            // "a/n" means from a to the end of the range
+            (value, if step > 1 { max } else { value })
+        };
+
+        if start > end {
+            anyhow::bail!("Invalid cron range {:?}", part);
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Ok(mask)
+}
+
+fn parse_number(text: &str, min: u32, max: u32) -> Result<u32> {
+    let value: u32 = text
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid cron value {:?}", text))?;
+    if value < min || value > max {
+        anyhow::bail!("Cron value {} out of range {}-{}", value, min, max);
+    }
+    Ok(value)
+}
+
+/// (year, month, day) of a day count since 1970-01-01 (proleptic Gregorian)
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 2024-03-15 12:00:00 UTC, a Friday
+    const FRI_NOON: u64 = 1_710_504_000;
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_797), (2024, 3, 15));
+        assert_eq!(civil_from_days(19_782), (2024, 2, 29));
+    }
+
+    #[test]
+    fn test_daily_schedule() {
+        let schedule = CronSchedule::parse("30 2 * * *").unwrap();
+        // Next 02:30 is the following day
+        assert_eq!(schedule.next_after(FRI_NOON), Some(FRI_NOON + 14 * 3600 + 30 * 60));
+    }
+
+    #[test]
+    fn test_steps_and_lists() {
+        let schedule = CronSchedule::parse("*/15 * * * *").unwrap();
+        assert_eq!(schedule.next_after(FRI_NOON), Some(FRI_NOON + 15 * 60));
+
+        let schedule = CronSchedule::parse("0 0,12 * * *").unwrap();
+        assert_eq!(schedule.next_after(FRI_NOON), Some(FRI_NOON + 12 * 3600));
+    }
+
+    #[test]
+    fn test_day_of_week() {
+        // Sundays at midnight: 2024-03-17
+        let sunday = FRI_NOON + 36 * 3600;
+        assert_eq!(CronSchedule::parse("0 0 * * 0").unwrap().next_after(FRI_NOON), Some(sunday));
+        assert_eq!(CronSchedule::parse("0 0 * * 7").unwrap().next_after(FRI_NOON), Some(sunday));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert!(CronSchedule::parse("* * * *").is_err());
+        assert!(CronSchedule::parse("60 * * * *").is_err());
+        assert!(CronSchedule::parse("5-1 * * * *").is_err());
+        assert!(CronSchedule::parse("x * * * *").is_err());
+    }
+
+    #[test]
+    fn test_impossible_date_never_fires() {
+        let schedule = CronSchedule::parse("0 0 31 2 *").unwrap();
+        assert_eq!(schedule.next_after(FRI_NOON), None);
+    }
+}
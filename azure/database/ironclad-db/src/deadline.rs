@@ -0,0 +1,163 @@
+//! Deadline: Capping an Operation's Total Time
+//!
+//! A store inside a request path with an SLA can't wait out the SDK's full
+//! retry schedule. `KVStore::with_deadline` runs an operation under a
+//! deadline:
+//!
+//! ```ignore
+//! let value = store.with_deadline(Deadline::after(Duration::from_millis(200)), store.get("user:1")).await?;
+//! ```
+//!
+//! The deadline covers every blob call the operation makes, WAL and data
+//! pages alike: each call, retries and backoff included, gets only the time
+//! left (see `DeadlinePolicy`), and a call started after the deadline fails
+//! at once. When the operation fails with the deadline passed, the error is
+//! `IronCladError::DeadlineExceeded`, with the storage error as its cause.
+//!
+//! A blob call cut short fails like any other storage error, so writes keep
+//! their usual guarantees: a write that fails may or may not have been
+//! logged, and a retry with the same value is safe. Group commit delays are
+//! shortened to fit the deadline too. What isn't bounded: waits on the
+//! store's in-memory locks, and I/O done on another task on this
+//! operation's behalf (a group commit led by another writer, a `LocalFile`
+//! WAL's file I/O).
+//!
+//! The deadline travels with the task (a Tokio task-local), so it needs no
+//! extra parameters through the layers.
+
+use anyhow::Result;
+use azure_core::error::ErrorKind;
+use azure_core::{Context, Policy, PolicyResult, Request};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+
+tokio::task_local! {
+    static DEADLINE: Deadline;
+}
+
+/// When an operation must be done by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Deadline(Instant);
+
+impl Deadline {
+    /// A budget of `budget` from now
+    pub fn after(budget: Duration) -> Self {
+        Self(Instant::now() + budget)
+    }
+
+    pub fn at(instant: Instant) -> Self {
+        Self(instant)
+    }
+
+    pub fn instant(&self) -> Instant {
+        self.0
+    }
+
+    /// Time left (zero once passed)
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.remaining().is_zero()
+    }
+}
+
+/// The deadline of the operation running on this task, if any
+pub(crate) fn current() -> Option<Deadline> {
+    DEADLINE.try_with(|deadline| *deadline).ok()
+}
+
+/// `wait` shortened to the current deadline
+pub(crate) fn clamp(wait: Duration) -> Duration {
+    match current() {
+        Some(deadline) => wait.min(deadline.remaining()),
+        None => wait,
+    }
+}
+
+impl KVStore {
+    /// Run `operation` under `deadline` (see the module docs)
+    ///
+    /// Fails with `IronCladError::DeadlineExceeded` without starting if the
+    /// deadline has already passed. A tighter deadline already in force on
+    /// this task wins.
+    pub async fn with_deadline<T, F>(&self, deadline: Deadline, operation: F) -> Result<T>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        let deadline = current().map_or(deadline, |outer| outer.min(deadline));
+        let budget = deadline.remaining();
+        if budget.is_zero() {
+            return Err(IronCladError::DeadlineExceeded { budget }.into());
+        }
+        match DEADLINE.scope(deadline, operation).await {
+            Err(e) if deadline.is_expired() => Err(e.context(IronCladError::DeadlineExceeded { budget })),
+            result => result,
+        }
+    }
+}
+
+/// Per-call policy: bounds each blob call, retries included, by the
+/// current deadline
+#[derive(Debug)]
+pub(crate) struct DeadlinePolicy;
+
+impl Policy for DeadlinePolicy {
+    fn send<'life0, 'life1, 'life2, 'life3, 'async_trait>(
+        &'life0 self,
+        ctx: &'life1 Context,
+        request: &'life2 mut Request,
+        next: &'life3 [Arc<dyn Policy>],
+    ) -> Pin<Box<dyn Future<Output = PolicyResult> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        'life3: 'async_trait,
+        Self: 'async_trait,
+    {
+        let deadline = current();
+        Box::pin(async move {
+            let Some(deadline) = deadline else {
+                return next[0].send(ctx, request, &next[1..]).await;
+            };
+            let exceeded = || azure_core::Error::message(ErrorKind::Other, "deadline exceeded before the call completed");
+            if deadline.is_expired() {
+                return Err(exceeded());
+            }
+            match tokio::time::timeout(deadline.remaining(), next[0].send(ctx, request, &next[1..])).await {
+                Ok(result) => result,
+                Err(_) => Err(exceeded()),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_deadline_travels_with_the_task() {
+        assert_eq!(current(), None);
+        assert_eq!(clamp(Duration::from_secs(5)), Duration::from_secs(5));
+
+        let deadline = Deadline::after(Duration::from_secs(1));
+        DEADLINE
+            .scope(deadline, async {
+                assert_eq!(current(), Some(deadline));
+                assert!(clamp(Duration::from_secs(5)) <= Duration::from_secs(1));
+            })
+            .await;
+
+        let passed = Deadline::at(Instant::now() - Duration::from_millis(1));
+        assert!(passed.is_expired());
+        assert_eq!(passed.remaining(), Duration::ZERO);
+    }
+}
@@ -0,0 +1,134 @@
+//! Diff: Key-by-Key Comparison of Two Stores
+//!
+//! `KVStore::diff` walks the union of both stores' keys in sorted order and
+//! reports each key that was added, removed or changed going from `self` to
+//! `other`. Useful for validating a migration or checking a replica for
+//! drift. To compare against a backup set, restore it into its own
+//! container first and open that (see backup_set.rs).
+//!
+//! The key lists are captured when the stream is first polled (with a
+//! hashed index that reads every page; see index.rs); values are read as
+//! the stream is polled, so writes made meanwhile may or may not show up.
+
+use anyhow::Result;
+use futures::stream::{self, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+use crate::kvstore::KVStore;
+
+/// One difference between two stores
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiffEntry {
+    /// Only in the other store
+    Added { key: String, value: String },
+    /// Only in this store
+    Removed { key: String, value: String },
+    /// In both, with different values
+    Changed { key: String, old: String, new: String },
+}
+
+impl DiffEntry {
+    pub fn key(&self) -> &str {
+        match self {
+            DiffEntry::Added { key, .. } | DiffEntry::Removed { key, .. } | DiffEntry::Changed { key, .. } => key,
+        }
+    }
+
+    /// Classify one key's values on either side (None = absent)
+    pub fn between(key: &str, old: Option<String>, new: Option<String>) -> Option<Self> {
+        let key = key.to_string();
+        match (old, new) {
+            (None, None) => None,
+            (None, Some(value)) => Some(DiffEntry::Added { key, value }),
+            (Some(value), None) => Some(DiffEntry::Removed { key, value }),
+            (Some(old), Some(new)) if old == new => None,
+            (Some(old), Some(new)) => Some(DiffEntry::Changed { key, old, new }),
+        }
+    }
+}
+
+/// Merge two sorted key lists into their sorted, deduplicated union
+fn union_sorted(left: Vec<String>, right: Vec<String>) -> Vec<String> {
+    let mut union = Vec::with_capacity(left.len().max(right.len()));
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+
+    loop {
+        let next = match (left.peek(), right.peek()) {
+            (Some(l), Some(r)) => match l.cmp(r) {
+                Ordering::Less => left.next(),
+                Ordering::Greater => right.next(),
+                Ordering::Equal => {
+                    right.next();
+                    left.next()
+                }
+            },
+            (Some(_), None) => left.next(),
+            (None, Some(_)) => right.next(),
+            (None, None) => break,
+        };
+        union.extend(next);
+    }
+    union
+}
+
+impl KVStore {
+    /// Stream the differences from this store to `other`, in key order
+    pub fn diff<'a>(&'a self, other: &'a KVStore) -> impl Stream<Item = Result<DiffEntry>> + 'a {
+        let keys = async move {
+            let mut ours = self.all_keys().await?;
+            let mut theirs = other.all_keys().await?;
+            ours.sort();
+            theirs.sort();
+            Ok::<_, anyhow::Error>(union_sorted(ours, theirs))
+        };
+
+        stream::once(keys)
+            .flat_map(|keys: Result<Vec<String>>| match keys {
+                Ok(keys) => stream::iter(keys.into_iter().map(Ok)).left_stream(),
+                Err(e) => stream::once(async move { Err(e) }).right_stream(),
+            })
+            .then(move |key: Result<String>| async move {
+                let key = key?;
+                let old = self.get(&key).await?;
+                let new = other.get(&key).await?;
+                Ok(DiffEntry::between(&key, old, new))
+            })
+            .filter_map(|result: Result<Option<DiffEntry>>| async move { result.transpose() })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(keys: &[&str]) -> Vec<String> {
+        keys.iter().map(|k| k.to_string()).collect()
+    }
+
+    #[test]
+    fn test_union_sorted() {
+        let union = union_sorted(keys(&["a", "c", "d"]), keys(&["b", "c", "e"]));
+        assert_eq!(union, keys(&["a", "b", "c", "d", "e"]));
+        assert_eq!(union_sorted(Vec::new(), keys(&["x"])), keys(&["x"]));
+    }
+
+    #[test]
+    fn test_classify_differences() {
+        let s = |v: &str| Some(v.to_string());
+        assert_eq!(DiffEntry::between("k", None, None), None);
+        assert_eq!(DiffEntry::between("k", s("v"), s("v")), None);
+        assert_eq!(
+            DiffEntry::between("k", None, s("v")),
+            Some(DiffEntry::Added { key: "k".into(), value: "v".into() })
+        );
+        assert_eq!(
+            DiffEntry::between("k", s("v"), None),
+            Some(DiffEntry::Removed { key: "k".into(), value: "v".into() })
+        );
+        let changed = DiffEntry::between("k", s("a"), s("b")).unwrap();
+        assert_eq!(changed, DiffEntry::Changed { key: "k".into(), old: "a".into(), new: "b".into() });
+        assert_eq!(changed.key(), "k");
+    }
+}
@@ -0,0 +1,118 @@
+//! Dir: Directory-Style Listing of `/`-Separated Keys
+//!
+//! Keys like `photos/2024/june/beach.jpg` form a tree. `list_dir` returns
+//! one level of it, the keys directly in a directory and the
+//! subdirectories below it, instead of every key under the prefix:
+//!
+//! ```ignore
+//! for entry in store.list_dir("photos/2024/").await? {
+//!     match entry {
+//!         DirEntry::Key(key) => println!("{}", key),        // photos/2024/cover.jpg
+//!         DirEntry::Dir(prefix) => println!("{} ...", prefix), // photos/2024/june/
+//!     }
+//! }
+//! ```
+//!
+//! Directories aren't stored: a subdirectory is listed while any key lies
+//! below it. Entries carry full keys and prefixes, sorted, so a prefix can
+//! be listed in turn. `""` lists the root; a directory given without its
+//! trailing `/` gets one.
+//!
+//! The index is a hash map, not a sorted one, so a listing is one pass
+//! over the index, costing about as much as `keys_with_prefix` and reading
+//! no values; each subdirectory is copied out once however many keys lie
+//! below it. It needs
+//! the full keys: with `IndexMode::Hashed` only reserved (`__`) directories
+//! can be listed. Reserved keys are left out unless the directory is
+//! itself reserved.
+
+use anyhow::Result;
+use std::collections::BTreeSet;
+
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::trash::is_reserved;
+
+/// Key separator
+const SEPARATOR: char = '/';
+
+/// An entry of a directory listing
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum DirEntry {
+    /// A key directly in the directory
+    Key(String),
+    /// A subdirectory: the prefix of the keys below it, ending in `/`
+    Dir(String),
+}
+
+impl DirEntry {
+    /// The key or prefix
+    pub fn path(&self) -> &str {
+        match self {
+            DirEntry::Key(path) | DirEntry::Dir(path) => path,
+        }
+    }
+}
+
+/// The path in `dir` (ending in `/`, or empty) that `key` lies at or
+/// below, and whether it is a subdirectory
+fn child_of<'a>(dir: &str, key: &'a str) -> Option<(&'a str, bool)> {
+    let name = key.strip_prefix(dir)?;
+    match name.find(SEPARATOR) {
+        _ if name.is_empty() => None,
+        Some(end) => Some((&key[..dir.len() + end + 1], true)),
+        None => Some((key, false)),
+    }
+}
+
+impl KVStore {
+    /// Keys and subdirectories directly in `dir`, sorted by path (see
+    /// dir.rs)
+    pub async fn list_dir(&self, dir: &str) -> Result<Vec<DirEntry>> {
+        let dir = if dir.is_empty() || dir.ends_with(SEPARATOR) { dir.to_string() } else { format!("{}{}", dir, SEPARATOR) };
+        if !self.index().can_list(&dir) {
+            return Err(IronCladError::NotSupported {
+                operation: "list_dir",
+                reason: "a hashed index doesn't hold the keys to list".to_string(),
+            }
+            .into());
+        }
+
+        let skip_reserved = !is_reserved(&dir);
+        let (mut keys, mut dirs) = (Vec::new(), BTreeSet::new());
+        self.index().for_each_key_with_prefix(&dir, |key| {
+            if skip_reserved && is_reserved(key) {
+                return;
+            }
+            match child_of(&dir, key) {
+                // Copied out once, not once per key below it
+                Some((prefix, true)) if !dirs.contains(prefix) => {
+                    dirs.insert(prefix.to_string());
+                }
+                Some((key, false)) => keys.push(key.to_string()),
+                _ => {}
+            }
+        });
+
+        let mut entries: Vec<DirEntry> =
+            keys.into_iter().map(DirEntry::Key).chain(dirs.into_iter().map(DirEntry::Dir)).collect();
+        entries.sort_by(|a, b| a.path().cmp(b.path()));
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_child_of() {
+        assert_eq!(child_of("a/", "a/x"), Some(("a/x", false)));
+        assert_eq!(child_of("a/", "a/b/c/d"), Some(("a/b/", true)));
+        assert_eq!(child_of("", "top"), Some(("top", false)));
+        assert_eq!(child_of("", "a/x"), Some(("a/", true)));
+        // The directory's own key, and keys outside it
+        assert_eq!(child_of("a/", "a/"), None);
+        assert_eq!(child_of("a/", "ab/x"), None);
+    }
+}
@@ -0,0 +1,151 @@
+//! Dry Run: Replaying Another Store's WAL into a Scratch Store
+//!
+//! To validate a change (a new page format, new options) against real
+//! traffic, open a store on a scratch container and feed it a copy of
+//! production's WAL:
+//!
+//! ```ignore
+//! let options = StoreOptions { container: "staging-scratch".to_string(), ..Default::default() };
+//! let staging = KVStore::open(&connection_string, options).await?;
+//! let report = staging.ingest_store_wal(&connection_string, "ironclad-db", 0).await?;
+//! // ...later, pick up where the last ingest stopped
+//! staging.ingest_store_wal(&connection_string, "ironclad-db", report.last_source_lsn).await?;
+//! ```
+//!
+//! The source log is only read: every write it holds is re-applied through
+//! the scratch store's own write path, so its WAL, pages and superblock all
+//! live in the scratch container. Transactions are re-committed as one
+//! batch; writes that fail are counted in the report rather than stopping
+//! the run, since finding them is the point. Incomplete transactions and
+//! checkpoint records in the source are skipped.
+
+use anyhow::Result;
+use tracing::{info, warn};
+
+use crate::config::ConnectionConfig;
+use crate::kvstore::{KVStore, WAL_BLOB};
+use crate::log_store::{AppendBlobLog, LogStore};
+use crate::wal::{assign_lsns, decode_log, discard_incomplete_transactions, WalEntry};
+
+/// Most failures kept in an `IngestReport` (the count is exact)
+const MAX_REPORTED_FAILURES: usize = 100;
+
+/// Outcome of an ingest
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct IngestReport {
+    /// Writes applied (transaction members included)
+    pub writes_applied: u64,
+    pub transactions_applied: u64,
+    /// Writes (or whole transactions) that failed
+    pub failures: u64,
+    /// (source LSN, error) of the first failures
+    pub failure_samples: Vec<(u64, String)>,
+    /// Last source LSN read; pass it to the next ingest to continue
+    pub last_source_lsn: u64,
+}
+
+impl IngestReport {
+    fn record_failure(&mut self, lsn: u64, error: anyhow::Error) {
+        warn!("INGEST: source LSN {} failed: {}", lsn, error);
+        self.failures += 1;
+        if self.failure_samples.len() < MAX_REPORTED_FAILURES {
+            self.failure_samples.push((lsn, error.to_string()));
+        }
+    }
+}
+
+impl AppendBlobLog {
+    /// The WAL of the store in `container`, for reading: creates nothing
+    pub fn for_store(connection_string: &str, container: &str) -> Result<Self> {
+        let container_client = ConnectionConfig::parse(connection_string)?.container_client(container);
+        Ok(Self::new(container_client.blob_client(WAL_BLOB)))
+    }
+}
+
+impl KVStore {
+    /// Apply the WAL of the store in `source_container` (see the module docs)
+    pub async fn ingest_store_wal(&self, connection_string: &str, source_container: &str, after_lsn: u64) -> Result<IngestReport> {
+        if source_container == self.options().container {
+            anyhow::bail!("Can't ingest {}'s own WAL; open the store on a scratch container", source_container);
+        }
+        self.ingest_wal(&AppendBlobLog::for_store(connection_string, source_container)?, after_lsn).await
+    }
+
+    /// Apply the writes in `source` logged after `after_lsn`
+    pub async fn ingest_wal(&self, source: &dyn LogStore, after_lsn: u64) -> Result<IngestReport> {
+        let len = source.stat().await?.len;
+        // A transaction still open at the end is picked up by the next ingest
+        let committed = discard_incomplete_transactions(assign_lsns(decode_log(&source.read(0..len).await?)?));
+
+        let mut report = IngestReport {
+            last_source_lsn: committed.last().map_or(after_lsn, |(lsn, _)| (*lsn).max(after_lsn)),
+            ..Default::default()
+        };
+        let mut transaction: Option<(u64, Vec<WalEntry>)> = None;
+
+        for (lsn, entry) in committed {
+            if lsn <= after_lsn {
+                continue;
+            }
+            match entry {
+                WalEntry::Begin { .. } => transaction = Some((lsn, Vec::new())),
+                WalEntry::Commit { .. } => {
+                    let Some((begin_lsn, writes)) = transaction.take() else {
+                        continue;
+                    };
+                    let count = writes.len() as u64;
+                    match self.commit_batch(self.new_txn_id(), writes, None).await {
+                        Ok(_) => {
+                            report.writes_applied += count;
+                            report.transactions_applied += 1;
+                        }
+                        Err(e) => report.record_failure(begin_lsn, e),
+                    }
+                }
+                WalEntry::Checkpoint { .. } => {}
+                write => match transaction.as_mut() {
+                    Some((_, writes)) => writes.push(write),
+                    None => match self.apply_ingested(write).await {
+                        Ok(()) => report.writes_applied += 1,
+                        Err(e) => report.record_failure(lsn, e),
+                    },
+                },
+            }
+        }
+
+        info!(
+            "INGEST: applied {} writes ({} transactions), {} failed, through source LSN {}",
+            report.writes_applied, report.transactions_applied, report.failures, report.last_source_lsn
+        );
+        Ok(report)
+    }
+
+    /// Re-issue a single source write through the public write path
+    async fn apply_ingested(&self, write: WalEntry) -> Result<()> {
+        match write {
+            WalEntry::Set { key, value, .. } => self.set(&key, &value).await,
+            WalEntry::Delete { key } => self.delete(&key).await.map(|_| ()),
+            WalEntry::Patch { key, pointer, value, .. } => self.patch_json(&key, &pointer, value).await,
+            WalEntry::Checkpoint { .. }
+            | WalEntry::Begin { .. }
+            | WalEntry::Commit { .. }
+            | WalEntry::Unknown { .. } => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failure_samples_are_capped() {
+        let mut report = IngestReport::default();
+        for lsn in 0..(MAX_REPORTED_FAILURES as u64 + 5) {
+            report.record_failure(lsn, anyhow::anyhow!("bad write"));
+        }
+        assert_eq!(report.failures, MAX_REPORTED_FAILURES as u64 + 5);
+        assert_eq!(report.failure_samples.len(), MAX_REPORTED_FAILURES);
+        assert_eq!(report.failure_samples[0], (0, "bad write".to_string()));
+    }
+}
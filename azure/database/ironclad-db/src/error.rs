@@ -0,0 +1,97 @@
+//! Error: Typed Errors for Conditions Callers Need to Match On
+//!
+//! Most of the crate returns `anyhow::Result`. Errors that callers are
+//! expected to handle programmatically are raised as `IronCladError` inside
+//! the `anyhow::Error`, so they can be recovered with `downcast_ref`.
+
+use thiserror::Error;
+
+/// Errors with a stable, matchable meaning
+#[derive(Debug, Error)]
+pub enum IronCladError {
+    /// A page read from storage doesn't follow the page layout (corrupt or torn write)
+    #[error("Invalid page format: {0}")]
+    InvalidPageFormat(String),
+
+    /// A composite key doesn't decode to the tuple asked for (see composite.rs)
+    #[error("Invalid composite key {key}: {reason}")]
+    InvalidKey { key: String, reason: String },
+
+    /// A snapshot transaction read a key that changed after its snapshot was taken
+    #[error("Snapshot too old: {key} was modified after the transaction's snapshot")]
+    SnapshotTooOld { key: String },
+
+    /// A snapshot transaction wrote a key another writer committed after its snapshot
+    #[error("Write conflict: {key} was modified after the transaction's snapshot")]
+    WriteConflict { key: String },
+
+    /// A distributed lock is held by someone else and its lease hasn't expired
+    #[error("Lock {name} is held by {owner}")]
+    LockHeld { name: String, owner: String },
+
+    /// The storage holds a partially created store that can't be completed safely
+    #[error("Incomplete store bootstrap: {0}")]
+    IncompleteBootstrap(String),
+
+    /// A column family with this name already exists
+    #[error("Column family {name} already exists")]
+    ColumnFamilyExists { name: String },
+
+    /// A branch with this name already exists
+    #[error("Branch {name} already exists")]
+    BranchExists { name: String },
+
+    /// `merge_branch` with `ConflictPolicy::Fail` found keys changed on both sides
+    #[error("Merge of branch {name} conflicts on {}", keys.join(", "))]
+    BranchConflict { name: String, keys: Vec<String> },
+
+    /// A JSON patch couldn't be applied to a key's value
+    #[error("Patch of {key} failed: {reason}")]
+    PatchFailed { key: String, reason: String },
+
+    /// A write would take a bucket over one of its limits (see quota.rs)
+    #[error("Quota exceeded: {prefix} is limited to {limit} {resource}")]
+    QuotaExceeded { prefix: String, resource: &'static str, limit: u64 },
+
+    /// The WAL holds a record this version can't read and mustn't skip
+    /// (see wal_record.rs)
+    #[error("Unsupported WAL record {record_type} (envelope v{version}): upgrade to a version that reads it")]
+    UnsupportedWalRecord { record_type: String, version: u32 },
+
+    /// An encrypted value couldn't be decrypted (see encryption.rs)
+    #[error("Can't decrypt {key} with data key {key_id}: {reason}")]
+    DecryptionFailed { key: String, key_id: String, reason: String },
+
+    /// `open_with_verification` found the store damaged (see verify.rs)
+    #[error("Store verification failed: {}", problems.join("; "))]
+    VerificationFailed { problems: Vec<String> },
+
+    /// An operation run with `with_deadline` ran out of time (see deadline.rs)
+    #[error("Deadline exceeded (budget {budget:?})")]
+    DeadlineExceeded { budget: std::time::Duration },
+
+    /// The storage account's retry budget is spent, so a failed call wasn't
+    /// retried (see retry_budget.rs)
+    #[error("Retry budget exhausted: {operation} to account {account} not retried")]
+    RetryBudgetExhausted { account: String, operation: String },
+
+    /// One of the store's blobs is gone and can't be rebuilt from the
+    /// other (see bootstrap.rs)
+    #[error("Store {component} is missing: {reason}")]
+    ComponentLost { component: &'static str, reason: String },
+
+    /// A value broke a rule of its bucket's validator (see validation.rs)
+    #[error("Validation of {key} failed ({rule}): {reason}")]
+    ValidationFailed { key: String, rule: String, reason: String },
+
+    /// A watch with `OverflowPolicy::Error` buffered more changes than its
+    /// subscriber took (see watch.rs)
+    #[error("Watch buffer of {capacity} events overflowed")]
+    WatchOverflow { capacity: usize },
+
+    /// An operation the store won't carry out: with `strict_durability`, one
+    /// that would otherwise lose or hide data without failing (see
+    /// options.rs), or one the key or the store's setup rules out
+    #[error("{operation} not supported: {reason}")]
+    NotSupported { operation: &'static str, reason: String },
+}
@@ -0,0 +1,186 @@
+//! Events: Structured Hooks Into the Store's Lifecycle
+//!
+//! An `EventListener` registered in `StoreOptions::listeners` is told about
+//! opens, checkpoints, flushes, buffer pool evictions, recovery progress
+//! and errors, so embedders can feed their own metrics and alerting
+//! without parsing logs:
+//!
+//! ```ignore
+//! struct Alerts;
+//!
+//! impl EventListener for Alerts {
+//!     fn on_error(&self, event: &ErrorEvent) {
+//!         pager::page(&format!("{}: {}", event.operation, event.message));
+//!     }
+//! }
+//!
+//! let options = StoreOptions { listeners: EventListeners::new().with(Arc::new(Alerts)), ..Default::default() };
+//! ```
+//!
+//! Every method has an empty default, so a listener implements only what
+//! it needs. Listeners are called inline, on the thread doing the work and
+//! sometimes under internal locks (evictions): keep them quick, and never
+//! call back into the store from one. Hand slow work to a channel.
+//!
+//! `on_lag` fires when a change consumer falls behind (see lag.rs).
+//! `on_recovery_progress` receives what `StoreOptions::replay_progress`
+//! does (see replay.rs).
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::lag::ConsumerLag;
+use crate::replay::ReplayProgress;
+
+/// A store finished opening (recovery included)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenEvent {
+    pub container: String,
+    /// Writer epoch this instance took
+    pub epoch: u64,
+    pub keys: usize,
+    pub duration: Duration,
+}
+
+/// A checkpoint finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointEvent {
+    pub duration: Duration,
+    /// False when it failed (`on_error` has the reason)
+    pub succeeded: bool,
+}
+
+/// The buffer pool evicted a page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvictionEvent {
+    pub page_id: u64,
+    /// A dirty page is written back later (steal policy)
+    pub dirty: bool,
+}
+
+/// Dirty pages were written to disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlushEvent {
+    pub pages: usize,
+    /// Pages holding changes up to this LSN were flushed (u64::MAX = all)
+    pub up_to_lsn: u64,
+    pub duration: Duration,
+}
+
+/// An operation failed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorEvent {
+    /// What failed: "open", "checkpoint", "flush", "backup", "readback"
+    pub operation: &'static str,
+    pub message: String,
+}
+
+/// Receives store events; every method defaults to doing nothing
+pub trait EventListener: Send + Sync {
+    fn on_open(&self, _event: &OpenEvent) {}
+
+    fn on_checkpoint_start(&self) {}
+
+    fn on_checkpoint_end(&self, _event: &CheckpointEvent) {}
+
+    fn on_eviction(&self, _event: &EvictionEvent) {}
+
+    fn on_recovery_progress(&self, _progress: &ReplayProgress) {}
+
+    fn on_flush(&self, _event: &FlushEvent) {}
+
+    fn on_error(&self, _event: &ErrorEvent) {}
+
+    /// A change consumer went over `StoreOptions::lag_alerts` (`exceeded`)
+    /// or came back under (see lag.rs)
+    fn on_lag(&self, _lag: &ConsumerLag, _exceeded: bool) {}
+}
+
+/// The listeners registered with a store
+#[derive(Clone, Default)]
+pub struct EventListeners(Vec<Arc<dyn EventListener>>);
+
+impl EventListeners {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Also notify `listener`
+    pub fn with(mut self, listener: Arc<dyn EventListener>) -> Self {
+        self.0.push(listener);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Call `notify` on every listener
+    pub(crate) fn emit(&self, notify: impl Fn(&dyn EventListener)) {
+        for listener in &self.0 {
+            notify(listener.as_ref());
+        }
+    }
+
+    /// Report a failure of `operation`
+    pub(crate) fn error(&self, operation: &'static str, error: &anyhow::Error) {
+        if self.is_empty() {
+            return;
+        }
+        let event = ErrorEvent { operation, message: format!("{:#}", error) };
+        self.emit(|listener| listener.on_error(&event));
+    }
+}
+
+impl std::fmt::Debug for EventListeners {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "EventListeners({})", self.0.len())
+    }
+}
+
+/// Equal when they hold the same listeners, in order
+impl PartialEq for EventListeners {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.len() == other.0.len()
+            && self.0.iter().zip(&other.0).all(|(a, b)| std::ptr::addr_eq(Arc::as_ptr(a), Arc::as_ptr(b)))
+    }
+}
+
+impl Eq for EventListeners {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    #[derive(Default)]
+    struct Recorder {
+        seen: Mutex<Vec<String>>,
+    }
+
+    impl EventListener for Recorder {
+        fn on_eviction(&self, event: &EvictionEvent) {
+            self.seen.lock().push(format!("evict {}", event.page_id));
+        }
+
+        fn on_error(&self, event: &ErrorEvent) {
+            self.seen.lock().push(format!("{}: {}", event.operation, event.message));
+        }
+    }
+
+    #[test]
+    fn test_events_reach_every_listener() {
+        let (first, second) = (Arc::new(Recorder::default()), Arc::new(Recorder::default()));
+        let listeners = EventListeners::new().with(first.clone()).with(second.clone());
+
+        listeners.emit(|listener| listener.on_eviction(&EvictionEvent { page_id: 7, dirty: false }));
+        listeners.emit(|listener| listener.on_checkpoint_start());
+        listeners.error("flush", &anyhow::anyhow!("disk gone"));
+
+        for recorder in [first, second] {
+            assert_eq!(*recorder.seen.lock(), vec!["evict 7", "flush: disk gone"]);
+        }
+        assert_eq!(listeners, listeners.clone());
+        assert_ne!(listeners, EventListeners::new());
+    }
+}
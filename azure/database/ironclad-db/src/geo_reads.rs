@@ -0,0 +1,366 @@
+//! Geo Reads: Serving Page Reads From an RA-GRS Secondary
+//!
+//! With read-access geo-redundant storage, the account's secondary region
+//! serves reads at `<account>-secondary.blob.core.windows.net`. It may be
+//! closer than the primary, but it lags: writes reach it asynchronously,
+//! usually within minutes, with no bound. `secondary_reads` sends page
+//! reads to whichever endpoint has been answering faster, as long as the
+//! secondary is fresh enough:
+//!
+//! ```ignore
+//! let options = StoreOptions {
+//!     secondary_reads: Some(SecondaryReads::new(Duration::from_secs(60))),
+//!     ..Default::default()
+//! };
+//! let store = KVStore::open(&connection_string, options).await?;
+//! println!("{:?}", store.geo_read_stats());
+//! ```
+//!
+//! A page goes to the secondary only when all of these hold:
+//!
+//! - Replication lag is known to be under `max_staleness`. Every
+//!   `probe_interval`, the data blob's properties are read from both
+//!   endpoints: matching ETags mean the secondary has caught up; otherwise
+//!   the lag is at most the time since the secondary's Last-Modified.
+//! - The secondary holds the page's latest write from this store: pages
+//!   written since the last sync come from the primary, so a page evicted
+//!   and read back is never older than the copy the store wrote.
+//! - The secondary has been answering faster (a moving average of page
+//!   read latency per endpoint; every `EXPLORE_EVERY`th read tries the
+//!   slower one, to keep both estimates current).
+//!
+//! A read that fails on one endpoint is retried on the other (the
+//! secondary only when the rules above allow it), and a failed secondary
+//! is left out until the next probe. Writes always go to the primary.
+
+use anyhow::Result;
+use azure_storage_blobs::prelude::*;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{debug, warn};
+
+use crate::azure_disk::fetch_page;
+use crate::kvstore::KVStore;
+
+/// Every this many reads the secondary may serve, the slower endpoint
+/// serves one, to keep its latency estimate current
+const EXPLORE_EVERY: u64 = 50;
+
+/// Weight of the newest sample in an endpoint's latency average
+const LATENCY_WEIGHT: f64 = 0.2;
+
+/// Options for reading from the secondary region (see geo_reads.rs)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecondaryReads {
+    /// Secondary blob endpoint (None = `https://<account>-secondary.blob.core.windows.net`)
+    pub endpoint: Option<String>,
+    /// Oldest the secondary's data may be
+    pub max_staleness: Duration,
+    /// How often replication lag is checked (default: 30 seconds)
+    pub probe_interval: Duration,
+}
+
+impl SecondaryReads {
+    pub fn new(max_staleness: Duration) -> Self {
+        Self { endpoint: None, max_staleness, probe_interval: Duration::from_secs(30) }
+    }
+
+    pub fn endpoint(mut self, endpoint: &str) -> Self {
+        self.endpoint = Some(endpoint.to_string());
+        self
+    }
+
+    pub fn probe_interval(mut self, interval: Duration) -> Self {
+        self.probe_interval = interval;
+        self
+    }
+}
+
+/// Where page reads went
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GeoReadStats {
+    pub primary_reads: u64,
+    pub secondary_reads: u64,
+    /// Reads retried on the other endpoint after a failure
+    pub failovers: u64,
+    /// Replication lag at the last probe (None = unknown or unreachable)
+    pub lag: Option<Duration>,
+    /// Average page read latency, per endpoint
+    pub primary_latency: Option<Duration>,
+    pub secondary_latency: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Endpoint {
+    Primary,
+    Secondary,
+}
+
+/// Routing state: what the secondary is known to hold, and how fast each
+/// endpoint answers
+#[derive(Debug, Default)]
+struct Router {
+    /// Writes of this store, numbered from 1
+    write_seq: u64,
+    /// Each page written but not known to be on the secondary: its write
+    /// number and Last-Modified
+    written: HashMap<u64, (u64, SystemTime)>,
+    /// Writes up to this number are on the secondary
+    synced_seq: Option<u64>,
+    /// Writes last modified before this are on the secondary
+    synced_before: Option<SystemTime>,
+    /// Last-Modified of the data blob when pages not in `written` were
+    /// last written (as of the first probe)
+    baseline: Option<SystemTime>,
+    /// The secondary may serve reads until then
+    fresh_until: Option<Instant>,
+    lag: Option<Duration>,
+    last_probe: Option<Instant>,
+    primary_latency: Option<f64>,
+    secondary_latency: Option<f64>,
+    /// Reads the secondary could have served
+    eligible_reads: u64,
+    stats: GeoReadStats,
+}
+
+impl Router {
+    fn record_write(&mut self, page_id: u64, last_modified: SystemTime) {
+        self.write_seq += 1;
+        self.written.insert(page_id, (self.write_seq, last_modified));
+    }
+
+    /// Does the secondary hold this store's latest write of the page?
+    fn replicated(&self, page_id: u64) -> bool {
+        let (seq, last_modified) = match self.written.get(&page_id) {
+            Some(&(seq, last_modified)) => (seq, Some(last_modified)),
+            None => (0, self.baseline),
+        };
+        self.synced_seq.is_some_and(|synced| seq <= synced)
+            || last_modified.zip(self.synced_before).is_some_and(|(modified, synced)| modified < synced)
+    }
+
+    /// Record a probe that started at `started`, when `write_seq` was
+    /// `seq`: the blob's ETag and Last-Modified on each endpoint, and the
+    /// primary's clock when it answered
+    fn record_probe(&mut self, started: Instant, seq: u64, primary: (&str, SystemTime, SystemTime), secondary: (&str, SystemTime), max_staleness: Duration) {
+        let (primary_etag, primary_modified, primary_date) = primary;
+        let (secondary_etag, secondary_modified) = secondary;
+        self.baseline.get_or_insert(primary_modified);
+        let lag = if primary_etag == secondary_etag {
+            // Caught up: everything written before the probe is there
+            self.synced_seq = Some(seq);
+            self.written.retain(|_, (written, _)| *written > seq);
+            Duration::ZERO
+        } else {
+            self.synced_before = Some(secondary_modified);
+            primary_date.duration_since(secondary_modified).unwrap_or_default()
+        };
+        self.lag = Some(lag);
+        self.fresh_until = max_staleness.checked_sub(lag).map(|left| started + left);
+        self.last_probe = Some(started);
+    }
+
+    /// The secondary is unreachable: keep reads on the primary until the next probe
+    fn secondary_down(&mut self, now: Instant) {
+        self.fresh_until = None;
+        self.lag = None;
+        self.last_probe = Some(now);
+    }
+
+    fn probe_due(&self, now: Instant, interval: Duration) -> bool {
+        self.last_probe.is_none_or(|last| now.duration_since(last) >= interval)
+    }
+
+    fn secondary_allowed(&self, page_id: u64, now: Instant) -> bool {
+        self.fresh_until.is_some_and(|until| now < until) && self.replicated(page_id)
+    }
+
+    /// Which endpoint should serve a read of `page_id`?
+    fn route(&mut self, page_id: u64, now: Instant) -> Endpoint {
+        if !self.secondary_allowed(page_id, now) {
+            return Endpoint::Primary;
+        }
+        self.eligible_reads += 1;
+        // An endpoint not measured yet is tried first
+        let faster = match (self.primary_latency, self.secondary_latency) {
+            (Some(primary), Some(secondary)) if primary <= secondary => Endpoint::Primary,
+            (Some(_), Some(_)) | (Some(_), None) => Endpoint::Secondary,
+            (None, _) => Endpoint::Primary,
+        };
+        match (faster, self.eligible_reads.is_multiple_of(EXPLORE_EVERY)) {
+            (Endpoint::Primary, true) => Endpoint::Secondary,
+            (Endpoint::Secondary, true) => Endpoint::Primary,
+            (faster, false) => faster,
+        }
+    }
+
+    fn record_read(&mut self, endpoint: Endpoint, latency: Duration) {
+        let (average, reads) = match endpoint {
+            Endpoint::Primary => (&mut self.primary_latency, &mut self.stats.primary_reads),
+            Endpoint::Secondary => (&mut self.secondary_latency, &mut self.stats.secondary_reads),
+        };
+        let sample = latency.as_secs_f64();
+        *average = Some(average.map_or(sample, |average| average + LATENCY_WEIGHT * (sample - average)));
+        *reads += 1;
+    }
+
+    fn stats(&self) -> GeoReadStats {
+        GeoReadStats {
+            lag: self.lag,
+            primary_latency: self.primary_latency.map(Duration::from_secs_f64),
+            secondary_latency: self.secondary_latency.map(Duration::from_secs_f64),
+            ..self.stats
+        }
+    }
+}
+
+/// Page reads routed between the primary and the secondary
+pub(crate) struct GeoReads {
+    secondary: BlobClient,
+    options: SecondaryReads,
+    router: Mutex<Router>,
+    probing: AtomicBool,
+}
+
+impl GeoReads {
+    pub(crate) fn new(secondary: BlobClient, options: SecondaryReads) -> Self {
+        Self { secondary, options, router: Mutex::new(Router::default()), probing: AtomicBool::new(false) }
+    }
+
+    /// Note a write of `page_id` the primary last modified at `last_modified`
+    pub(crate) fn record_write(&self, page_id: u64, last_modified: SystemTime) {
+        self.router.lock().record_write(page_id, last_modified);
+    }
+
+    /// Read `page_id` from the endpoint chosen for it, failing over to the other
+    pub(crate) async fn read_page(&self, primary: &BlobClient, page_id: u64) -> Result<Vec<u8>> {
+        if self.router.lock().probe_due(Instant::now(), self.options.probe_interval) {
+            self.probe(primary).await;
+        }
+
+        let endpoint = self.router.lock().route(page_id, Instant::now());
+        match self.read_from(endpoint, primary, page_id).await {
+            Ok(data) => Ok(data),
+            Err(e) => {
+                let other = match endpoint {
+                    Endpoint::Secondary => {
+                        self.router.lock().secondary_down(Instant::now());
+                        Endpoint::Primary
+                    }
+                    Endpoint::Primary if self.router.lock().secondary_allowed(page_id, Instant::now()) => Endpoint::Secondary,
+                    Endpoint::Primary => return Err(e),
+                };
+                warn!("GEO: reading page {} from {:?} failed ({}), trying {:?}", page_id, endpoint, e, other);
+                self.router.lock().stats.failovers += 1;
+                self.read_from(other, primary, page_id).await
+            }
+        }
+    }
+
+    async fn read_from(&self, endpoint: Endpoint, primary: &BlobClient, page_id: u64) -> Result<Vec<u8>> {
+        let client = match endpoint {
+            Endpoint::Primary => primary,
+            Endpoint::Secondary => &self.secondary,
+        };
+        let started = Instant::now();
+        let data = fetch_page(client, page_id).await?;
+        self.router.lock().record_read(endpoint, started.elapsed());
+        Ok(data)
+    }
+
+    /// Compare the data blob on both endpoints to bound replication lag
+    async fn probe(&self, primary: &BlobClient) {
+        if self.probing.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let started = Instant::now();
+        let seq = self.router.lock().write_seq;
+        let result = async {
+            let primary = primary.get_properties().await?;
+            let secondary = self.secondary.get_properties().await?;
+            anyhow::Ok((primary, secondary))
+        }
+        .await;
+        match result {
+            Ok((primary, secondary)) => {
+                let (primary_etag, secondary_etag) = (primary.blob.properties.etag.to_string(), secondary.blob.properties.etag.to_string());
+                let primary_side = (primary_etag.as_str(), primary.blob.properties.last_modified.into(), primary.date.into());
+                let secondary_side = (secondary_etag.as_str(), secondary.blob.properties.last_modified.into());
+                let mut router = self.router.lock();
+                router.record_probe(started, seq, primary_side, secondary_side, self.options.max_staleness);
+                debug!("GEO: secondary lag {:?}", router.lag);
+            }
+            Err(e) => {
+                warn!("GEO: lag probe failed, reading from the primary: {}", e);
+                self.router.lock().secondary_down(started);
+            }
+        }
+        self.probing.store(false, Ordering::Release);
+    }
+
+    pub(crate) fn stats(&self) -> GeoReadStats {
+        self.router.lock().stats()
+    }
+}
+
+impl KVStore {
+    /// Where page reads went (None without `secondary_reads`)
+    pub fn geo_read_stats(&self) -> Option<GeoReadStats> {
+        self.disk().geo_read_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STALENESS: Duration = Duration::from_secs(60);
+
+    fn at(secs: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_secondary_serves_only_fresh_replicated_pages() {
+        let mut router = Router::default();
+        let now = Instant::now();
+        assert_eq!(router.route(1, now), Endpoint::Primary, "no probe yet");
+
+        // Behind by 20s: pages last written before the secondary's Last-Modified qualify
+        router.record_write(1, at(1_000));
+        router.record_write(2, at(1_090));
+        router.record_probe(now, 2, ("b", at(1_090), at(1_100)), ("a", at(1_080)), STALENESS);
+        assert_eq!(router.lag, Some(Duration::from_secs(20)));
+        assert!(router.secondary_allowed(1, now) && !router.secondary_allowed(2, now));
+        assert!(!router.secondary_allowed(3, now), "unwritten pages may date from the latest write");
+        assert!(!router.secondary_allowed(1, now + Duration::from_secs(40)), "lag reaches the bound");
+
+        // Caught up: every page written before the probe qualifies, later ones don't
+        router.record_probe(now, 2, ("b", at(1_090), at(1_200)), ("b", at(1_090)), STALENESS);
+        router.record_write(2, at(1_210));
+        assert!(router.secondary_allowed(1, now) && router.secondary_allowed(3, now));
+        assert!(!router.secondary_allowed(2, now));
+
+        // Too far behind: nothing
+        router.record_probe(now, 3, ("c", at(1_210), at(1_300)), ("b", at(1_090)), STALENESS);
+        assert!(!router.secondary_allowed(1, now));
+    }
+
+    #[test]
+    fn test_routes_to_the_faster_endpoint() {
+        let mut router = Router::default();
+        let now = Instant::now();
+        router.record_probe(now, 0, ("a", at(10), at(20)), ("a", at(10)), STALENESS);
+        assert_eq!(router.route(1, now), Endpoint::Primary);
+        router.record_read(Endpoint::Primary, Duration::from_millis(80));
+        assert_eq!(router.route(1, now), Endpoint::Secondary);
+        router.record_read(Endpoint::Secondary, Duration::from_millis(10));
+
+        let routes: Vec<Endpoint> = (0..EXPLORE_EVERY).map(|_| router.route(1, now)).collect();
+        assert_eq!(routes.iter().filter(|&&endpoint| endpoint == Endpoint::Primary).count(), 1);
+        router.secondary_down(now);
+        assert_eq!(router.route(1, now), Endpoint::Primary);
+    }
+}
@@ -0,0 +1,159 @@
+//! Group Commit: Batching Concurrent WAL Appends
+//!
+//! Every WAL record has to reach the log before its write returns, and an
+//! Azure append costs milliseconds however small it is. With group commit,
+//! writers queue their records and whichever writer holds the append lock
+//! next writes everything queued so far in one append (up to
+//! `max_batch_bytes`), handing each writer its LSN span.
+//!
+//! Knobs (`GroupCommitOptions`):
+//!
+//! - `max_batch_bytes`: most bytes per append (Azure caps a block at 4MB).
+//! - `max_delay`: how long the writer doing an append waits for more
+//!   records first. Zero (the default) adds no latency: batches form only
+//!   from writers that queued while the previous append was in flight.
+//! - `max_outstanding`: most writes queued or in flight; further writers
+//!   wait, bounding the memory held by queued records.
+//! - `adaptive`: pick the delay from observed append latency instead, up to
+//!   `max_delay`: a quarter of the average append time, so waiting stays
+//!   small next to the append it saves.
+//!
+//! The values in effect are reported by `WAL::group_commit_stats`.
+
+use parking_lot::Mutex;
+use std::time::Duration;
+
+/// Group commit tuning
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GroupCommitOptions {
+    /// Most bytes written per append (default: 1MB)
+    pub max_batch_bytes: usize,
+    /// Wait for more records before appending (default: none)
+    pub max_delay: Duration,
+    /// Most writes queued or in flight (default: 1024)
+    pub max_outstanding: usize,
+    /// Derive the delay from append latency, capped at `max_delay` (default: false)
+    pub adaptive: bool,
+}
+
+impl Default for GroupCommitOptions {
+    fn default() -> Self {
+        Self {
+            max_batch_bytes: 1024 * 1024,
+            max_delay: Duration::ZERO,
+            max_outstanding: 1024,
+            adaptive: false,
+        }
+    }
+}
+
+/// Group commit settings in effect and counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct GroupCommitStats {
+    pub max_batch_bytes: usize,
+    pub max_outstanding: usize,
+    /// Delay currently applied before each append
+    pub delay_us: u64,
+    /// Moving average of append latency
+    pub avg_append_latency_us: u64,
+    /// Appends made
+    pub appends: u64,
+    /// Writes those appends carried
+    pub writes: u64,
+}
+
+/// Append counters and the adaptive delay
+#[derive(Debug)]
+pub(crate) struct GroupCommitTuner {
+    options: GroupCommitOptions,
+    state: Mutex<TunerState>,
+}
+
+#[derive(Debug, Default)]
+struct TunerState {
+    avg_latency_us: Option<f64>,
+    appends: u64,
+    writes: u64,
+}
+
+/// Weight of the newest sample in the latency average
+const LATENCY_ALPHA: f64 = 0.2;
+
+impl GroupCommitTuner {
+    pub fn new(options: GroupCommitOptions) -> Self {
+        Self { options, state: Mutex::new(TunerState::default()) }
+    }
+
+    pub fn options(&self) -> &GroupCommitOptions {
+        &self.options
+    }
+
+    /// How long to wait for more records before the next append
+    pub fn delay(&self) -> Duration {
+        if !self.options.adaptive {
+            return self.options.max_delay;
+        }
+        let avg = self.state.lock().avg_latency_us.unwrap_or(0.0);
+        Duration::from_micros((avg / 4.0) as u64).min(self.options.max_delay)
+    }
+
+    /// Record an append of `writes` writes that took `latency`
+    pub fn record_append(&self, writes: usize, latency: Duration) {
+        let sample = latency.as_micros() as f64;
+        let mut state = self.state.lock();
+        state.avg_latency_us = Some(match state.avg_latency_us {
+            Some(avg) => avg + LATENCY_ALPHA * (sample - avg),
+            None => sample,
+        });
+        state.appends += 1;
+        state.writes += writes as u64;
+    }
+
+    pub fn stats(&self) -> GroupCommitStats {
+        let delay_us = self.delay().as_micros() as u64;
+        let state = self.state.lock();
+        GroupCommitStats {
+            max_batch_bytes: self.options.max_batch_bytes,
+            max_outstanding: self.options.max_outstanding,
+            delay_us,
+            avg_append_latency_us: state.avg_latency_us.unwrap_or(0.0) as u64,
+            appends: state.appends,
+            writes: state.writes,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fixed_delay() {
+        let tuner = GroupCommitTuner::new(GroupCommitOptions { max_delay: Duration::from_millis(2), ..Default::default() });
+        tuner.record_append(1, Duration::from_millis(40));
+        assert_eq!(tuner.delay(), Duration::from_millis(2));
+    }
+
+    #[test]
+    fn test_adaptive_delay_follows_latency() {
+        let tuner = GroupCommitTuner::new(GroupCommitOptions {
+            max_delay: Duration::from_millis(5),
+            adaptive: true,
+            ..Default::default()
+        });
+        assert_eq!(tuner.delay(), Duration::ZERO);
+
+        tuner.record_append(3, Duration::from_millis(8));
+        assert_eq!(tuner.delay(), Duration::from_millis(2));
+
+        // Capped at max_delay however slow appends get
+        for _ in 0..50 {
+            tuner.record_append(1, Duration::from_millis(100));
+        }
+        assert_eq!(tuner.delay(), Duration::from_millis(5));
+
+        let stats = tuner.stats();
+        assert_eq!((stats.appends, stats.writes), (51, 53));
+        assert_eq!(stats.delay_us, 5_000);
+    }
+}
@@ -0,0 +1,271 @@
+//! Heat: Page Access Counts over a Sliding Window
+//!
+//! The buffer pool counts every read and write of each page. Counts are
+//! kept in a few time slots (by default six of ten seconds); slots older
+//! than the window are dropped, so `BufferPool::heat_map` reports roughly
+//! the last minute's accesses:
+//!
+//! ```ignore
+//! let heat = buffer_pool.heat_map();
+//! for page in heat.hottest(10) { println!("page {}: {} accesses", page.page_id, page.accesses); }
+//! ```
+//!
+//! Each key has its own page, so a hot page is a hot key; `KVStore::heat_map`
+//! names the keys. `HeatMap::by_range` sums pages into ranges of page IDs
+//! for a coarser picture.
+//!
+//! Counts are split over independently locked shards by page ID, so
+//! concurrent readers of different pages don't queue on one lock.
+//!
+//! With `HotPinning` set, the pool also uses the heat map to pin its
+//! hottest pages (see `BufferPool::with_hot_pinning`).
+
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use crate::index::IndexEntry;
+use crate::kvstore::KVStore;
+use crate::page;
+
+/// Default window the heat map covers
+pub const DEFAULT_HEAT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Slots a window is divided into
+const SLOTS: u32 = 6;
+
+/// Independently locked parts of a tracker; a page is counted in one, so
+/// readers of different pages rarely wait for each other
+const SHARDS: usize = 16;
+
+/// Access counts of one page
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PageHeat {
+    pub page_id: u64,
+    pub accesses: u64,
+}
+
+/// Page access counts over the last `window`, hottest first
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HeatMap {
+    pub window: Duration,
+    pub pages: Vec<PageHeat>,
+}
+
+impl HeatMap {
+    /// The `n` most accessed pages
+    pub fn hottest(&self, n: usize) -> &[PageHeat] {
+        &self.pages[..n.min(self.pages.len())]
+    }
+
+    /// Total accesses in the window
+    pub fn total_accesses(&self) -> u64 {
+        self.pages.iter().map(|page| page.accesses).sum()
+    }
+
+    /// Accesses summed over ranges of `pages_per_range` page IDs, in page order
+    pub fn by_range(&self, pages_per_range: u64) -> Vec<(Range<u64>, u64)> {
+        let pages_per_range = pages_per_range.max(1);
+        let mut ranges: Vec<(Range<u64>, u64)> = Vec::new();
+        let mut pages = self.pages.clone();
+        pages.sort_by_key(|page| page.page_id);
+        for page in pages {
+            let start = page.page_id - page.page_id % pages_per_range;
+            match ranges.last_mut() {
+                Some((range, accesses)) if range.start == start => *accesses += page.accesses,
+                _ => ranges.push((start..start + pages_per_range, page.accesses)),
+            }
+        }
+        ranges
+    }
+}
+
+/// Automatic pinning of hot pages (see `BufferPool::with_hot_pinning`)
+///
+/// Every `check_every` page accesses the pool re-reads its heat map and
+/// pins the (at most `max_pinned`) resident pages read at least
+/// `min_accesses_per_sec` times a second over the heat window, so a
+/// skewed workload's hottest keys are never evicted by a scan or a burst
+/// of cold reads. Pages that cool down are unpinned at the next check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HotPinning {
+    pub min_accesses_per_sec: u64,
+    /// At most this many pages pinned (and never more than half the pool)
+    pub max_pinned: usize,
+    pub check_every: u64,
+}
+
+impl Default for HotPinning {
+    fn default() -> Self {
+        Self { min_accesses_per_sec: 100, max_pinned: 64, check_every: 10_000 }
+    }
+}
+
+impl HotPinning {
+    /// Pages of `heat` hot enough to pin, hottest first
+    pub(crate) fn hot_pages(&self, heat: &HeatMap, capacity: usize) -> Vec<u64> {
+        let min_accesses = self.min_accesses_per_sec.saturating_mul(heat.window.as_secs().max(1));
+        heat.pages
+            .iter()
+            .take_while(|page| page.accesses >= min_accesses)
+            .take(self.max_pinned.min(capacity / 2))
+            .map(|page| page.page_id)
+            .collect()
+    }
+}
+
+/// Access counts of one key (see `KVStore::heat_map`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyHeat {
+    pub key: String,
+    pub page_id: u64,
+    pub accesses: u64,
+}
+
+/// (slot start, accesses per page), oldest first
+type Slots = VecDeque<(Instant, HashMap<u64, u64>)>;
+
+/// Sliding-window access counter per page
+pub struct HeatTracker {
+    slot_len: Duration,
+    /// Slots of the pages whose ID is the shard's index, modulo `SHARDS`
+    shards: Vec<Mutex<Slots>>,
+}
+
+impl HeatTracker {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            slot_len: (window / SLOTS).max(Duration::from_millis(1)),
+            shards: (0..SHARDS).map(|_| Mutex::new(VecDeque::new())).collect(),
+        }
+    }
+
+    /// Window covered
+    pub fn window(&self) -> Duration {
+        self.slot_len * SLOTS
+    }
+
+    pub fn record(&self, page_id: u64) {
+        self.record_at(page_id, Instant::now());
+    }
+
+    fn record_at(&self, page_id: u64, now: Instant) {
+        let mut slots = self.shards[page_id as usize % SHARDS].lock();
+        self.rotate(&mut slots, now);
+        if let Some((_, counts)) = slots.back_mut() {
+            *counts.entry(page_id).or_default() += 1;
+        }
+    }
+
+    /// Start a new slot if the current one is over, dropping those out of the window
+    fn rotate(&self, slots: &mut Slots, now: Instant) {
+        if slots.back().is_none_or(|(start, _)| now.duration_since(*start) >= self.slot_len) {
+            slots.push_back((now, HashMap::new()));
+        }
+        let window = self.window();
+        while slots.front().is_some_and(|(start, _)| now.duration_since(*start) >= window) {
+            slots.pop_front();
+        }
+    }
+
+    pub fn heat_map(&self) -> HeatMap {
+        self.heat_map_at(Instant::now())
+    }
+
+    fn heat_map_at(&self, now: Instant) -> HeatMap {
+        let mut totals: HashMap<u64, u64> = HashMap::new();
+        for shard in &self.shards {
+            let mut slots = shard.lock();
+            self.rotate(&mut slots, now);
+            for (_, counts) in slots.iter() {
+                for (page_id, accesses) in counts {
+                    *totals.entry(*page_id).or_default() += accesses;
+                }
+            }
+        }
+
+        let mut pages: Vec<PageHeat> =
+            totals.into_iter().map(|(page_id, accesses)| PageHeat { page_id, accesses }).collect();
+        pages.sort_by(|a, b| b.accesses.cmp(&a.accesses).then(a.page_id.cmp(&b.page_id)));
+        HeatMap { window: self.window(), pages }
+    }
+}
+
+impl KVStore {
+    /// The `n` most accessed keys over the heat window
+    ///
+    /// Hash-indexed keys are named from their pages, read straight from
+    /// the data blob so naming them doesn't count as an access.
+    pub async fn heat_map(&self, n: usize) -> anyhow::Result<Vec<KeyHeat>> {
+        let heat = self.buffer_pool().heat_map();
+        let keys: HashMap<u64, Option<String>> =
+            self.index().pages().into_iter().map(|(key, page_id)| (page_id, key)).collect();
+
+        let mut hottest = Vec::new();
+        for page in heat.pages {
+            if hottest.len() == n {
+                break;
+            }
+            let key = match keys.get(&page.page_id) {
+                Some(Some(key)) => key.clone(),
+                // Hashed: the key is only in the page
+                Some(None) => match self.disk().read_page(page.page_id).await {
+                    Ok(data) => page::decode_kv_page(&data)?.0,
+                    Err(_) => continue,
+                },
+                // No longer a key's page
+                None => continue,
+            };
+            // A stale page of a re-allocated hashed key
+            if !matches!(self.index().get(&key), Some(IndexEntry::Page(id)) if id == page.page_id) {
+                continue;
+            }
+            hottest.push(KeyHeat { key, page_id: page.page_id, accesses: page.accesses });
+        }
+        Ok(hottest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts_cover_the_window() {
+        let tracker = HeatTracker::new(Duration::from_secs(60));
+        let start = Instant::now();
+        for _ in 0..3 {
+            tracker.record_at(1, start);
+        }
+        tracker.record_at(2, start + Duration::from_secs(30));
+
+        let heat = tracker.heat_map_at(start + Duration::from_secs(40));
+        assert_eq!(heat.pages, vec![PageHeat { page_id: 1, accesses: 3 }, PageHeat { page_id: 2, accesses: 1 }]);
+
+        // Page 1's slot has left the window
+        let heat = tracker.heat_map_at(start + Duration::from_secs(65));
+        assert_eq!(heat.pages, vec![PageHeat { page_id: 2, accesses: 1 }]);
+        assert_eq!(tracker.heat_map_at(start + Duration::from_secs(200)).total_accesses(), 0);
+    }
+
+    #[test]
+    fn test_ranges() {
+        let heat = HeatMap {
+            window: DEFAULT_HEAT_WINDOW,
+            pages: vec![
+                PageHeat { page_id: 12, accesses: 5 },
+                PageHeat { page_id: 3, accesses: 2 },
+                PageHeat { page_id: 7, accesses: 1 },
+            ],
+        };
+        assert_eq!(heat.by_range(10), vec![(0..10, 3), (10..20, 5)]);
+
+        let pinning = HotPinning { min_accesses_per_sec: 2, max_pinned: 1, check_every: 1 };
+        let heat = HeatMap { window: Duration::from_secs(1), ..heat };
+        assert_eq!(pinning.hot_pages(&heat, 100), vec![12]);
+        assert_eq!(HotPinning { max_pinned: 5, ..pinning }.hot_pages(&heat, 100), vec![12, 3]);
+        assert!(pinning.hot_pages(&heat, 1).is_empty());
+        assert_eq!(heat.hottest(1), &[PageHeat { page_id: 12, accesses: 5 }]);
+    }
+}
@@ -0,0 +1,181 @@
+//! Idempotency: Retry-Safe Writes with Client Tokens
+//!
+//! A client that times out can't tell whether its write was applied. If it
+//! passes the same idempotency token on every attempt, the store applies
+//! the write once and turns the retries into no-ops.
+//!
+//! Each token is recorded under `__idem/<token>` in the same WAL batch as
+//! the write it guards, so both are applied (or recovered) together. The
+//! check and the commit run under the commit lock, so concurrent attempts
+//! with the same token can't both apply. Tokens expire after
+//! `StoreOptions::idempotency_window`; `prune_idempotency_tokens` deletes
+//! expired records.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+use crate::retention::ReclaimKind;
+use crate::txn::Transaction;
+use crate::wal::WalEntry;
+
+/// Key prefix for idempotency token records
+pub(crate) const TOKEN_PREFIX: &str = "__idem/";
+
+/// Value stored for a token
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TokenRecord {
+    seen_at_ms: u64,
+}
+
+impl TokenRecord {
+    fn is_expired(&self, now_ms: u64, window_ms: u64) -> bool {
+        now_ms.saturating_sub(self.seen_at_ms) >= window_ms
+    }
+}
+
+impl KVStore {
+    /// Set a key unless a write with `token` was already applied
+    ///
+    /// Returns false when this is a retry of an applied write.
+    pub async fn set_with_token(&self, key: &str, value: &str, token: &str) -> Result<bool> {
+        let mut txn = self.begin();
+        txn.set(key, value);
+        Ok(txn.commit_with_token(token).await?.is_some())
+    }
+
+    /// Delete a key unless a write with `token` was already applied
+    ///
+    /// Returns false when this is a retry of an applied write.
+    pub async fn delete_with_token(&self, key: &str, token: &str) -> Result<bool> {
+        let mut txn = self.begin();
+        txn.delete(key);
+        Ok(txn.commit_with_token(token).await?.is_some())
+    }
+
+    /// Delete token records older than the idempotency window; returns how many
+    pub async fn prune_idempotency_tokens(&self) -> Result<usize> {
+        Ok(self.reclaim(&[ReclaimKind::IdempotencyToken]).await?.len())
+    }
+
+    /// Is the token record stored as `value` past the idempotency window?
+    /// An unreadable one can't prove a write happened, so it is too
+    pub(crate) fn token_expired(&self, value: &str, now_ms: u64) -> bool {
+        serde_json::from_str::<TokenRecord>(value).map_or(true, |record| record.is_expired(now_ms, self.window_ms()))
+    }
+
+    /// Commit `writes` plus the token record, unless the token is live
+    ///
+    /// Returns the Commit LSN, or None if the token was already used.
+    pub(crate) async fn commit_once(
+        &self,
+        token: &str,
+        txn_id: u64,
+        mut writes: Vec<WalEntry>,
+        snapshot_seq: Option<u64>,
+    ) -> Result<Option<u64>> {
+        let _commit_guard = self.lock_commits().await;
+
+        let key = token_key(token);
+        let now = now_ms();
+        if let Some(record) = self.token_record(&key).await? {
+            if !record.is_expired(now, self.window_ms()) {
+                debug!("COMMIT: token {} already applied, skipping transaction {}", token, txn_id);
+                return Ok(None);
+            }
+        }
+
+        writes.push(WalEntry::set(&key, &serde_json::to_string(&TokenRecord { seen_at_ms: now })?));
+        self.commit_batch_locked(txn_id, writes, snapshot_seq).await.map(Some)
+    }
+
+    async fn token_record(&self, key: &str) -> Result<Option<TokenRecord>> {
+        // An unreadable record can't prove the write happened
+        Ok(self.get(key).await?.and_then(|value| serde_json::from_str(&value).ok()))
+    }
+
+    fn window_ms(&self) -> u64 {
+        self.options().idempotency_window.as_millis() as u64
+    }
+}
+
+impl Transaction<'_> {
+    /// Commit unless a write with `token` was already applied
+    ///
+    /// Returns the LSN of the Commit marker, or None for a retry.
+    pub async fn commit_with_token(self, token: &str) -> Result<Option<u64>> {
+        let (store, txn_id, writes, snapshot_seq) = self.into_parts();
+        store.commit_once(token, txn_id, writes, snapshot_seq).await
+    }
+}
+
+fn token_key(token: &str) -> String {
+    format!("{}{}", TOKEN_PREFIX, token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::StoreOptions;
+    use crate::testing::{fresh_options, open_store};
+    use std::time::Duration;
+
+    /// Add one to `counter` in a transaction guarded by `token`
+    async fn increment(store: &KVStore, token: &str) -> Option<u64> {
+        let mut txn = store.begin();
+        let counter: u64 = txn.get("counter").await.unwrap().map_or(0, |value| value.parse().unwrap());
+        txn.set("counter", &(counter + 1).to_string());
+        txn.commit_with_token(token).await.unwrap()
+    }
+
+    #[test]
+    fn test_token_expiry() {
+        let record = TokenRecord { seen_at_ms: 1_000 };
+        assert!(!record.is_expired(1_500, 1_000));
+        assert!(record.is_expired(2_000, 1_000));
+        // Clock going backwards keeps the token live
+        assert!(!record.is_expired(500, 1_000));
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_no_ops_across_reopen() {
+        let options = fresh_options();
+        {
+            let store = open_store(options.clone()).await;
+            assert!(increment(&store, "inc-1").await.is_some());
+            assert!(increment(&store, "inc-1").await.is_none());
+            assert!(increment(&store, "inc-2").await.is_some());
+
+            assert!(store.set_with_token("name", "first", "set-1").await.unwrap());
+            store.set("name", "changed").await.unwrap();
+            assert!(!store.set_with_token("name", "first", "set-1").await.unwrap());
+            assert!(store.delete_with_token("name", "del-1").await.unwrap());
+            store.set("name", "back").await.unwrap();
+            assert!(!store.delete_with_token("name", "del-1").await.unwrap());
+        }
+
+        // Tokens are recovered from the log with the writes they guarded
+        let store = open_store(options).await;
+        assert!(increment(&store, "inc-1").await.is_none());
+        assert!(increment(&store, "inc-2").await.is_none());
+        assert_eq!(store.get("counter").await.unwrap().as_deref(), Some("2"));
+        assert!(!store.set_with_token("name", "first", "set-1").await.unwrap());
+        assert_eq!(store.get("name").await.unwrap().as_deref(), Some("back"));
+    }
+
+    #[tokio::test]
+    async fn test_expired_tokens_apply_again_and_are_pruned() {
+        let options = StoreOptions { idempotency_window: Duration::from_millis(50), ..fresh_options() };
+        let store = open_store(options).await;
+        assert!(increment(&store, "inc-1").await.is_some());
+        assert!(increment(&store, "inc-1").await.is_none());
+
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(store.prune_idempotency_tokens().await.unwrap(), 1);
+        assert!(store.keys_with_prefix(TOKEN_PREFIX).is_empty());
+        assert!(increment(&store, "inc-1").await.is_some());
+        assert_eq!(store.get("counter").await.unwrap().as_deref(), Some("2"));
+    }
+}
@@ -0,0 +1,444 @@
+//! Import: Seeding the Store From a CSV or JSON Lines Blob
+//!
+//! `KVStore::import_blob` streams a dataset blob from any container into
+//! the store:
+//!
+//! ```ignore
+//! let job = ImportJob::new("users-2024", ImportFormat::JsonLines { key_field: "id".to_string() })
+//!     .with_key_prefix("user:");
+//! let report = store.import_blob(&connection_string, "datasets", "users.jsonl", &job).await?;
+//! println!("{} rows at {:.0} rows/s", report.rows_imported, report.rows_per_sec());
+//! ```
+//!
+//! - CSV: each record's `key_column` becomes the key and `value_column` the
+//!   value (columns counted from 0). Quoted fields may hold commas, quotes
+//!   (doubled) and newlines.
+//! - JSON Lines: each line's `key_field` (a string or number) becomes the
+//!   key and the whole line the value.
+//!
+//! The blob is read `chunk_bytes` at a time, so memory doesn't grow with
+//! its size. Rows are written `batch_rows` at a time, each batch as one
+//! atomic batch together with the job's progress marker (`__import/<job>`),
+//! which records the byte offset reached. Running a job again resumes after
+//! the last batch written; a job that finished does nothing. A marker is
+//! tied to the blob it was made for: if the blob is replaced, resuming
+//! fails until `reset_import` clears the marker.
+//!
+//! Records that can't be parsed (or lack the key) are skipped and counted,
+//! with the first few kept in the report.
+//!
+//! By default a row too large for a page fails the import, and rows
+//! overwrite whatever the keys hold. `with_conflict_report` writes batches
+//! the way `set_many_report` does (see batch.rs) instead: rows too large
+//! or refused by a validator, and rows whose key another writer changed
+//! after the run started, are left out and counted, and the import goes
+//! on. Live writes made during a
+//! long import aren't overwritten by older data from the dataset.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{info, warn};
+
+use crate::batch::WriteOutcome;
+use crate::config::ConnectionConfig;
+use crate::kvstore::KVStore;
+use crate::log_store::{AppendBlobLog, LogStore};
+use crate::wal::WalEntry;
+
+const IMPORT_PREFIX: &str = "__import/";
+
+/// Called with the report so far after every batch
+pub type ImportProgress = Arc<dyn Fn(&ImportReport) + Send + Sync>;
+
+/// Most skipped records kept in an `ImportReport` (the count is exact)
+const MAX_REPORTED_SKIPS: usize = 100;
+
+/// How the source is laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportFormat {
+    Csv { key_column: usize, value_column: usize, has_header: bool },
+    JsonLines { key_field: String },
+}
+
+/// A resumable import
+#[derive(Clone)]
+pub struct ImportJob {
+    /// Names the progress marker
+    pub name: String,
+    pub format: ImportFormat,
+    /// Prepended to every key
+    pub key_prefix: String,
+    /// Rows per write batch (default: 500)
+    pub batch_rows: usize,
+    /// Bytes per blob read (default: 4MB)
+    pub chunk_bytes: u64,
+    /// Leave out and count rows too large or conflicting instead of
+    /// failing (default: false)
+    pub conflict_report: bool,
+    progress: Option<ImportProgress>,
+}
+
+impl ImportJob {
+    pub fn new(name: &str, format: ImportFormat) -> Self {
+        Self {
+            name: name.to_string(),
+            format,
+            key_prefix: String::new(),
+            batch_rows: 500,
+            chunk_bytes: 4 * 1024 * 1024,
+            conflict_report: false,
+            progress: None,
+        }
+    }
+
+    pub fn with_key_prefix(mut self, prefix: &str) -> Self {
+        self.key_prefix = prefix.to_string();
+        self
+    }
+
+    pub fn with_batch_rows(mut self, rows: usize) -> Self {
+        self.batch_rows = rows.max(1);
+        self
+    }
+
+    pub fn with_chunk_bytes(mut self, bytes: u64) -> Self {
+        self.chunk_bytes = bytes.max(1);
+        self
+    }
+
+    /// Count rows that are too large or conflict instead of failing
+    pub fn with_conflict_report(mut self) -> Self {
+        self.conflict_report = true;
+        self
+    }
+
+    /// Call `progress` after every batch
+    pub fn with_progress<F: Fn(&ImportReport) + Send + Sync + 'static>(mut self, progress: F) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+}
+
+impl std::fmt::Debug for ImportJob {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImportJob")
+            .field("name", &self.name)
+            .field("format", &self.format)
+            .field("key_prefix", &self.key_prefix)
+            .field("batch_rows", &self.batch_rows)
+            .field("chunk_bytes", &self.chunk_bytes)
+            .field("conflict_report", &self.conflict_report)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Progress of an import (this run's counts, the job's offset)
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ImportReport {
+    pub rows_imported: u64,
+    /// Records skipped as unparseable
+    pub rows_skipped: u64,
+    /// (byte offset, reason) of the first skipped records
+    pub skip_samples: Vec<(u64, String)>,
+    /// Rows left out because another writer changed their key during the
+    /// run (only `with_conflict_report`)
+    pub rows_conflicted: u64,
+    /// Rows left out as too large for a page (only `with_conflict_report`)
+    pub rows_too_large: u64,
+    /// Rows left out as refused by a validator (only `with_conflict_report`)
+    pub rows_invalid: u64,
+    /// (key, outcome) of the first rows left out
+    pub rejected_samples: Vec<(String, WriteOutcome)>,
+    /// Source bytes consumed by this run
+    pub bytes_read: u64,
+    /// Byte offset the job has reached, out of `total_bytes`
+    pub offset: u64,
+    pub total_bytes: u64,
+    pub elapsed: Duration,
+    pub completed: bool,
+}
+
+impl ImportReport {
+    pub fn rows_per_sec(&self) -> f64 {
+        self.rows_imported as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.bytes_read as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+
+    fn record_skip(&mut self, offset: u64, reason: String) {
+        self.rows_skipped += 1;
+        if self.skip_samples.len() < MAX_REPORTED_SKIPS {
+            self.skip_samples.push((offset, reason));
+        }
+    }
+
+    fn record_outcome(&mut self, key: &str, outcome: WriteOutcome) {
+        match outcome {
+            WriteOutcome::Inserted | WriteOutcome::Updated => {
+                self.rows_imported += 1;
+                return;
+            }
+            WriteOutcome::Conflicted => self.rows_conflicted += 1,
+            WriteOutcome::TooLarge => self.rows_too_large += 1,
+            WriteOutcome::Invalid => self.rows_invalid += 1,
+        }
+        if self.rejected_samples.len() < MAX_REPORTED_SKIPS {
+            self.rejected_samples.push((key.to_string(), outcome));
+        }
+    }
+}
+
+/// Progress marker, persisted under `__import/<job>`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ImportMarker {
+    /// Identity of the source blob (its creation time)
+    source: Option<String>,
+    offset: u64,
+    rows: u64,
+    completed: bool,
+}
+
+fn marker_key(job: &str) -> String {
+    format!("{}{}", IMPORT_PREFIX, job)
+}
+
+/// Length of the first complete record in `buf` (newline included), if
+/// any; CSV newlines inside quotes don't end a record
+fn record_end(buf: &[u8], csv: bool) -> Option<usize> {
+    let mut quoted = false;
+    for (i, byte) in buf.iter().enumerate() {
+        match byte {
+            b'"' if csv => quoted = !quoted,
+            b'\n' if !quoted => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Split one CSV record into fields
+fn csv_fields(record: &str) -> Result<Vec<String>> {
+    let mut fields = Vec::new();
+    let mut field = String::new();
+    let mut chars = record.chars().peekable();
+    let mut quoted = false;
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if field.is_empty() => quoted = true,
+            (',', false) => fields.push(std::mem::take(&mut field)),
+            (c, _) => field.push(c),
+        }
+    }
+    if quoted {
+        anyhow::bail!("unterminated quote");
+    }
+    fields.push(field);
+    Ok(fields)
+}
+
+/// The (key, value) of one record
+fn parse_record(record: &str, format: &ImportFormat) -> Result<(String, String)> {
+    match format {
+        ImportFormat::Csv { key_column, value_column, .. } => {
+            let fields = csv_fields(record)?;
+            let column = |index: usize| fields.get(index).cloned().ok_or_else(|| anyhow::anyhow!("no column {}", index));
+            Ok((column(*key_column)?, column(*value_column)?))
+        }
+        ImportFormat::JsonLines { key_field } => {
+            let value: Value = serde_json::from_str(record)?;
+            let key = match value.get(key_field) {
+                Some(Value::String(key)) => key.clone(),
+                Some(Value::Number(key)) => key.to_string(),
+                _ => anyhow::bail!("no string or number {:?} field", key_field),
+            };
+            Ok((key, record.to_string()))
+        }
+    }
+}
+
+impl KVStore {
+    /// Import `blob` in `container` (see the module docs)
+    pub async fn import_blob(&self, connection_string: &str, container: &str, blob: &str, job: &ImportJob) -> Result<ImportReport> {
+        let container_client = ConnectionConfig::parse(connection_string)?.container_client(container);
+        self.import_from(&AppendBlobLog::new(container_client.blob_client(blob)), job).await
+    }
+
+    /// Import from any byte source (a `LogStore` is read as plain bytes)
+    pub async fn import_from(&self, source: &dyn LogStore, job: &ImportJob) -> Result<ImportReport> {
+        let started = Instant::now();
+        // Keys written after this are conflicts (with `conflict_report`)
+        let since_seq = self.commit_seq();
+        let stat = source.stat().await?;
+        let mut marker = match self.get(&marker_key(&job.name)).await? {
+            Some(json) => serde_json::from_str::<ImportMarker>(&json)?,
+            None => ImportMarker { source: stat.identity.clone(), offset: 0, rows: 0, completed: false },
+        };
+        if marker.source != stat.identity {
+            anyhow::bail!("Import {} was made for a different source blob; reset_import to start over", job.name);
+        }
+
+        let mut report = ImportReport { offset: marker.offset, total_bytes: stat.len, completed: marker.completed, ..Default::default() };
+        if marker.completed {
+            info!("IMPORT: {} already completed", job.name);
+            return Ok(report);
+        }
+        if marker.offset > 0 {
+            info!("IMPORT: resuming {} at byte {} of {}", job.name, marker.offset, stat.len);
+        }
+
+        let csv = matches!(job.format, ImportFormat::Csv { .. });
+        let skip_header = matches!(job.format, ImportFormat::Csv { has_header: true, .. }) && marker.offset == 0;
+        let mut header_pending = skip_header;
+        // Bytes read but not yet made into a record start at `buffered_at`
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut buffered_at = marker.offset;
+        let mut read_to = marker.offset;
+        let mut batch: Vec<(String, String)> = Vec::new();
+        let mut batch_end = marker.offset;
+
+        loop {
+            let at_end = read_to >= stat.len;
+            if !at_end {
+                let end = (read_to + job.chunk_bytes).min(stat.len);
+                buffer.extend(source.read(read_to..end).await?);
+                report.bytes_read += end - read_to;
+                read_to = end;
+            }
+
+            // Whole records, then (at the end) a last one without a newline
+            let mut consumed = 0;
+            loop {
+                let rest = &buffer[consumed..];
+                let len = match record_end(rest, csv) {
+                    Some(len) => len,
+                    None if at_end && !rest.is_empty() => rest.len(),
+                    None => break,
+                };
+                let record_at = buffered_at + consumed as u64;
+                let record = String::from_utf8_lossy(&rest[..len]);
+                let record = record.trim_end_matches(['\n', '\r']);
+                consumed += len;
+                batch_end = buffered_at + consumed as u64;
+
+                if std::mem::take(&mut header_pending) || record.trim().is_empty() {
+                    continue;
+                }
+                match parse_record(record, &job.format) {
+                    Ok((key, value)) => batch.push((format!("{}{}", job.key_prefix, key), value)),
+                    Err(e) => report.record_skip(record_at, e.to_string()),
+                }
+                if batch.len() >= job.batch_rows {
+                    let rows = std::mem::take(&mut batch);
+                    self.write_import_batch(job, &mut marker, rows, batch_end, false, since_seq, &mut report, started)
+                        .await?;
+                }
+            }
+            buffer.drain(..consumed);
+            buffered_at += consumed as u64;
+
+            if at_end {
+                break;
+            }
+        }
+
+        self.write_import_batch(job, &mut marker, batch, batch_end.max(stat.len), true, since_seq, &mut report, started)
+            .await?;
+        if report.rows_skipped > 0 {
+            warn!("IMPORT: {} skipped {} unparseable records", job.name, report.rows_skipped);
+        }
+        if report.rows_conflicted + report.rows_too_large + report.rows_invalid > 0 {
+            warn!(
+                "IMPORT: {} left out {} conflicting, {} too large and {} invalid rows",
+                job.name, report.rows_conflicted, report.rows_too_large, report.rows_invalid
+            );
+        }
+        info!(
+            "IMPORT: {} done, {} rows ({:.0} rows/s, {:.1} MB/s)",
+            job.name,
+            report.rows_imported,
+            report.rows_per_sec(),
+            report.bytes_per_sec() / (1024.0 * 1024.0)
+        );
+        Ok(report)
+    }
+
+    /// Write `rows` and the marker at `offset` as one batch
+    #[allow(clippy::too_many_arguments)]
+    async fn write_import_batch(
+        &self,
+        job: &ImportJob,
+        marker: &mut ImportMarker,
+        rows: Vec<(String, String)>,
+        offset: u64,
+        completed: bool,
+        since_seq: u64,
+        report: &mut ImportReport,
+        started: Instant,
+    ) -> Result<()> {
+        // Rows left out count as done too: a resumed run doesn't retry them
+        let next = ImportMarker { offset, rows: marker.rows + rows.len() as u64, completed, ..marker.clone() };
+        let marker_write = WalEntry::set(&marker_key(&job.name), &serde_json::to_string(&next)?);
+        if job.conflict_report {
+            let outcomes = self.commit_reported(&rows, since_seq, vec![marker_write]).await?;
+            for ((key, _), outcome) in rows.iter().zip(outcomes) {
+                report.record_outcome(key, outcome);
+            }
+        } else {
+            let mut writes: Vec<WalEntry> = rows.iter().map(|(key, value)| WalEntry::set(key, value)).collect();
+            writes.push(marker_write);
+            self.commit_batch(self.new_txn_id(), writes, None).await?;
+            report.rows_imported += rows.len() as u64;
+        }
+        *marker = next;
+
+        report.offset = offset;
+        report.completed = completed;
+        report.elapsed = started.elapsed();
+        if let Some(progress) = &job.progress {
+            progress(report);
+        }
+        Ok(())
+    }
+
+    /// Forget import `job`'s progress, so it runs from the start again
+    pub async fn reset_import(&self, job: &str) -> Result<bool> {
+        self.delete(&marker_key(job)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_records_may_quote_commas_quotes_and_newlines() {
+        assert_eq!(record_end(b"a,\"b\nc\"\nnext", true), Some(8));
+        assert_eq!(record_end(b"a,\"b\nc\"\nnext", false), Some(5));
+        assert_eq!(record_end(b"partial", true), None);
+
+        assert_eq!(csv_fields(r#"k1,"hello, ""world""",3"#).unwrap(), vec!["k1", r#"hello, "world""#, "3"]);
+        assert!(csv_fields(r#"k1,"open"#).is_err());
+    }
+
+    #[test]
+    fn test_records_map_to_keys_and_values() {
+        let csv = ImportFormat::Csv { key_column: 1, value_column: 0, has_header: false };
+        assert_eq!(parse_record("v,k", &csv).unwrap(), ("k".to_string(), "v".to_string()));
+        assert!(parse_record("only", &csv).is_err());
+
+        let json = ImportFormat::JsonLines { key_field: "id".to_string() };
+        let line = r#"{"id": 7, "name": "Ada"}"#;
+        assert_eq!(parse_record(line, &json).unwrap(), ("7".to_string(), line.to_string()));
+        assert!(parse_record(r#"{"name": "Ada"}"#, &json).is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Index: In-Memory Map from Keys to Pages
+//!
+//! By default the index holds every key in full. For huge keyspaces of long
+//! keys (URLs, paths) that dominates memory, so `IndexMode::Hashed` keeps a
+//! 128-bit hash of each key instead; the full key is still in its page, and
+//! reads check it, so a hash collision reads as "not found" rather than
+//! returning another key's value.
+//!
+//! Trade-offs of hashed mode:
+//!
+//! - Keys can't be listed from memory. `scan` and `diff` read every page;
+//!   keys in the reserved `__` namespace (locks, catalogs, column family
+//!   data) stay in full so prefix listings keep working.
+//! - Writes don't check for collisions: on a collision (about 2^-64 odds at
+//!   four billion keys) the newer key takes over the older one's entry.
+//! - Values can't be inlined (see `IndexEntry::Inline`), since persisting
+//!   them needs the key.
+//!
+//! Each checkpoint persists the page-backed entries to packed pages the
+//! superblock names (`IndexSnapshot`), since it clears the WAL they were
+//! rebuilt from; recovery loads them before replaying the log.
+
+use anyhow::Result;
+use dashmap::DashMap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::sample::reservoir;
+
+/// How the index stores keys
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IndexMode {
+    /// Full keys (listing by prefix is free)
+    #[default]
+    Full,
+    /// 128-bit key hashes, for keys outside the reserved `__` namespace
+    Hashed,
+}
+
+/// Where the last checkpoint persisted the index (see `KeyIndex::page_snapshot`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexSnapshot {
+    /// `count` packed pages from `start`
+    pub start: u64,
+    pub count: u64,
+    /// The snapshot reflects every WAL record up to this LSN
+    pub lsn: u64,
+    /// No page from here on was in use
+    pub next_page_id: u64,
+}
+
+/// Keys with this prefix are always indexed in full
+const RESERVED_PREFIX: &str = "__";
+
+/// Where a key's value lives
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum IndexEntry {
+    /// In its own page
+    Page(u64),
+    /// In the index itself (small values; persisted at checkpoint)
+    Inline(String),
+}
+
+/// A key picked by `KeyIndex::sample`: in full, or the page holding a
+/// hash-indexed key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum SampledKey {
+    Full(String),
+    Hashed(u64),
+}
+
+/// Key -> entry map
+pub(crate) struct KeyIndex {
+    mode: IndexMode,
+    full: DashMap<String, IndexEntry>,
+    hashed: DashMap<u128, u64>,
+}
+
+impl KeyIndex {
+    pub fn new(mode: IndexMode) -> Self {
+        Self {
+            mode,
+            full: DashMap::new(),
+            hashed: DashMap::new(),
+        }
+    }
+
+    fn is_hashed(&self, key: &str) -> bool {
+        self.mode == IndexMode::Hashed && !key.starts_with(RESERVED_PREFIX)
+    }
+
+    /// Can `key`'s value be stored inline?
+    pub fn can_inline(&self, key: &str) -> bool {
+        !self.is_hashed(key)
+    }
+
+    /// Are all keys starting with `prefix` indexed in full?
+    pub fn can_list(&self, prefix: &str) -> bool {
+        !self.is_hashed(prefix)
+    }
+
+    pub fn get(&self, key: &str) -> Option<IndexEntry> {
+        if self.is_hashed(key) {
+            self.hashed.get(&key_hash(key)).map(|entry| IndexEntry::Page(*entry.value()))
+        } else {
+            self.full.get(key).map(|entry| entry.value().clone())
+        }
+    }
+
+    /// Index a key; an `Inline` entry for a hashed key is ignored (check
+    /// `can_inline` first)
+    pub fn insert(&self, key: &str, entry: IndexEntry) {
+        if !self.is_hashed(key) {
+            self.full.insert(key.to_string(), entry);
+        } else if let IndexEntry::Page(page_id) = entry {
+            self.hashed.insert(key_hash(key), page_id);
+        }
+    }
+
+    /// Remove a key; returns whether it was indexed
+    pub fn remove(&self, key: &str) -> bool {
+        if self.is_hashed(key) {
+            self.hashed.remove(&key_hash(key)).is_some()
+        } else {
+            self.full.remove(key).is_some()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.full.len() + self.hashed.len()
+    }
+
+    /// Fully indexed keys starting with `prefix` (every matching key, unless
+    /// hashed mode is on and `prefix` isn't reserved)
+    pub fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.full
+            .iter()
+            .filter(|entry| entry.key().starts_with(prefix))
+            .map(|entry| entry.key().clone())
+            .collect()
+    }
+
+    /// Call `visit` with each fully indexed key starting with `prefix`,
+    /// without copying the keys
+    pub fn for_each_key_with_prefix(&self, prefix: &str, mut visit: impl FnMut(&str)) {
+        for entry in self.full.iter().filter(|entry| entry.key().starts_with(prefix)) {
+            visit(entry.key());
+        }
+    }
+
+    /// Number of inline entries
+    pub fn inline_len(&self) -> usize {
+        self.full.iter().filter(|entry| matches!(entry.value(), IndexEntry::Inline(_))).count()
+    }
+
+    /// Inline key-value pairs, sorted by key
+    pub fn inline_entries(&self) -> Vec<(String, String)> {
+        let mut entries: Vec<(String, String)> = self
+            .full
+            .iter()
+            .filter_map(|entry| match entry.value() {
+                IndexEntry::Inline(value) => Some((entry.key().clone(), value.clone())),
+                IndexEntry::Page(_) => None,
+            })
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    /// Every fully indexed key's entry
+    pub fn entries(&self) -> Vec<(String, IndexEntry)> {
+        self.full.iter().map(|entry| (entry.key().clone(), entry.value().clone())).collect()
+    }
+
+    /// Every page-backed entry: (key, page), with no key for hash-indexed keys
+    pub fn pages(&self) -> Vec<(Option<String>, u64)> {
+        let full = self.full.iter().filter_map(|entry| match entry.value() {
+            IndexEntry::Page(page_id) => Some((Some(entry.key().clone()), *page_id)),
+            IndexEntry::Inline(_) => None,
+        });
+        full.chain(self.hashed.iter().map(|entry| (None, *entry.value()))).collect()
+    }
+
+    /// Every page-backed entry as a packed pair, sorted: (key, "@page"), or
+    /// (hex hash, "#page") for hash-indexed keys
+    pub fn page_snapshot(&self) -> Vec<(String, String)> {
+        let full = self.full.iter().filter_map(|entry| match entry.value() {
+            IndexEntry::Page(page_id) => Some((entry.key().clone(), format!("@{}", page_id))),
+            IndexEntry::Inline(_) => None,
+        });
+        let hashed = self.hashed.iter().map(|entry| (format!("{:032x}", entry.key()), format!("#{}", entry.value())));
+        let mut pairs: Vec<(String, String)> = full.chain(hashed).collect();
+        pairs.sort();
+        pairs
+    }
+
+    /// Index a pair from `page_snapshot`; returns the page it points at
+    pub fn restore_page(&self, key: &str, encoded: &str) -> Result<u64> {
+        if let Some(page_id) = encoded.strip_prefix('@') {
+            let page_id = page_id.parse()?;
+            self.insert(key, IndexEntry::Page(page_id));
+            return Ok(page_id);
+        }
+        let Some(page_id) = encoded.strip_prefix('#') else {
+            anyhow::bail!("Unreadable index snapshot entry {:?}", encoded);
+        };
+        if self.mode != IndexMode::Hashed {
+            anyhow::bail!("Index snapshot holds hashed keys: open the store with IndexMode::Hashed");
+        }
+        let page_id = page_id.parse()?;
+        self.hashed.insert(u128::from_str_radix(key, 16)?, page_id);
+        Ok(page_id)
+    }
+
+    /// Up to `n` keys outside the reserved namespace, each equally likely
+    /// (see sample.rs)
+    pub fn sample(&self, n: usize, rng: &mut impl Rng) -> Vec<SampledKey> {
+        // Keys are only copied once picked
+        let full = self.full.iter().filter(|entry| !entry.key().starts_with(RESERVED_PREFIX)).map(Ok);
+        let hashed = self.hashed.iter().map(|entry| Err(*entry.value()));
+        reservoir(full.chain(hashed), n, rng, |candidate| match candidate {
+            Ok(entry) => SampledKey::Full(entry.key().clone()),
+            Err(page_id) => SampledKey::Hashed(*page_id),
+        })
+    }
+
+    /// Pages of hash-indexed keys (their keys are only in the pages)
+    pub fn hashed_pages(&self) -> Vec<u64> {
+        self.hashed.iter().map(|entry| *entry.value()).collect()
+    }
+}
+
+/// 128-bit FNV-1a hash of a key
+pub fn key_hash(key: &str) -> u128 {
+    const OFFSET: u128 = 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d;
+    const PRIME: u128 = 0x0000_0000_0100_0000_0000_0000_0000_013b;
+
+    let mut hash = OFFSET;
+    for byte in key.as_bytes() {
+        hash ^= *byte as u128;
+        hash = hash.wrapping_mul(PRIME);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hashed_mode_keeps_reserved_keys_in_full() {
+        let index = KeyIndex::new(IndexMode::Hashed);
+        index.insert("https://example.com/a/very/long/url", IndexEntry::Page(7));
+        index.insert("__lock/leader", IndexEntry::Page(8));
+
+        assert_eq!(index.get("https://example.com/a/very/long/url"), Some(IndexEntry::Page(7)));
+        assert_eq!(index.get("https://example.com/other"), None);
+        assert_eq!(index.keys_with_prefix(""), vec!["__lock/leader".to_string()]);
+        assert_eq!(index.hashed_pages(), vec![7]);
+        assert_eq!(index.len(), 2);
+
+        assert!(index.remove("https://example.com/a/very/long/url"));
+        assert!(!index.remove("https://example.com/a/very/long/url"));
+    }
+
+    #[test]
+    fn test_full_mode_lists_every_key() {
+        let index = KeyIndex::new(IndexMode::Full);
+        index.insert("a", IndexEntry::Page(1));
+        index.insert("b", IndexEntry::Inline("2".to_string()));
+
+        let mut keys = index.keys_with_prefix("");
+        keys.sort();
+        assert_eq!(keys, vec!["a".to_string(), "b".to_string()]);
+        assert!(index.hashed_pages().is_empty());
+        assert_eq!(index.inline_entries(), vec![("b".to_string(), "2".to_string())]);
+    }
+
+    #[test]
+    fn test_hashed_keys_are_never_inline() {
+        let index = KeyIndex::new(IndexMode::Hashed);
+        assert!(!index.can_inline("flag"));
+        assert!(index.can_inline("__cf/users"));
+
+        index.insert("flag", IndexEntry::Inline("on".to_string()));
+        assert_eq!(index.get("flag"), None);
+    }
+
+    #[test]
+    fn test_page_snapshot_round_trip() {
+        let index = KeyIndex::new(IndexMode::Hashed);
+        index.insert("https://example.com/a", IndexEntry::Page(7));
+        index.insert("__lock/leader", IndexEntry::Page(8));
+        index.insert("__cf/flag", IndexEntry::Inline("on".to_string()));
+
+        let restored = KeyIndex::new(IndexMode::Hashed);
+        for (key, encoded) in index.page_snapshot() {
+            restored.restore_page(&key, &encoded).unwrap();
+        }
+        assert_eq!(restored.get("https://example.com/a"), Some(IndexEntry::Page(7)));
+        assert_eq!(restored.get("__lock/leader"), Some(IndexEntry::Page(8)));
+        assert_eq!(restored.get("__cf/flag"), None);
+
+        // Hashes can't be turned back into keys
+        let full = KeyIndex::new(IndexMode::Full);
+        let errors = index.page_snapshot().iter().filter(|(key, encoded)| full.restore_page(key, encoded).is_err()).count();
+        assert_eq!(errors, 1);
+    }
+
+    #[test]
+    fn test_key_hash_known_values() {
+        // Reference FNV-1a 128-bit values
+        assert_eq!(key_hash(""), 0x6c62_272e_07bb_0142_62b8_2175_6295_c58d);
+        assert_eq!(key_hash("a"), 0xd228_cb69_6f1a_8caf_7891_2b70_4e4a_8964);
+        assert_ne!(key_hash("ab"), key_hash("ba"));
+    }
+}
@@ -0,0 +1,369 @@
+//! IndexedDB: Pager and WAL Layers in a Browser
+//!
+//! The browser counterparts of `LocalDisk` and `LocalFileLog` (see
+//! local_disk.rs and log_store.rs), built with the `wasm` feature. Both
+//! keep their data in one IndexedDB database, named by the caller:
+//!
+//! ```ignore
+//! let store = KVStore::new_indexed_db("ironclad").await?;
+//! store.set("k", "v").await?;
+//! ```
+//!
+//! To combine IndexedDB with other options, set `StoreOptions::page_backend`
+//! to `PageBackend::IndexedDb` and `StoreOptions::wal_backend` to
+//! `WalBackend::IndexedDb`.
+//!
+//! The database has two object stores:
+//!
+//! - `pages`: one 4KB record per written page, keyed by page ID. A page
+//!   never written reads as zeros, as on the other backends.
+//! - `wal`: the log in 64KB blocks keyed by block number, plus its length
+//!   under the key `len`. An append puts the blocks it touches and the new
+//!   length in one transaction, so a log is never longer than the bytes
+//!   written to it. The length and the last, partial block are also kept
+//!   in memory, so an append doesn't read the block it extends.
+//!
+//! Writes return once their transaction completes. IndexedDB commits a
+//! transaction atomically, but browsers may report it complete before
+//! it's flushed to disk, so a power loss (not a closed tab) can lose the
+//! last writes. Snapshots, and so backups, aren't supported.
+//!
+//! Browser handles (`JsValue`) aren't `Send`, while `PageStore` and
+//! `LogStore` are: the handles and futures are held in a `SendWrapper`,
+//! which panics if they're used off the thread that made them. A browser
+//! has one thread, so that only catches misuse.
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use js_sys::{Array, Promise, Uint8Array};
+use send_wrapper::SendWrapper;
+use std::ops::Range;
+use tracing::{debug, info};
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{IdbDatabase, IdbFactory, IdbKeyRange, IdbObjectStore, IdbRequest, IdbTransaction, IdbTransactionMode};
+
+use crate::kvstore::KVStore;
+use crate::log_store::{LogStat, LogStore, WalBackend};
+use crate::options::StoreOptions;
+use crate::page::PAGE_SIZE;
+use crate::page_store::{PageBackend, PageStore};
+
+/// Schema version; bumped when the object stores change
+const VERSION: u32 = 1;
+
+/// Object stores in the database
+const PAGES: &str = "pages";
+const WAL: &str = "wal";
+
+/// Key of the log length in `WAL`
+const LEN_KEY: &str = "len";
+
+/// Bytes per WAL block
+const BLOCK_SIZE: usize = 64 * 1024;
+
+/// `open` parses a connection string even when nothing is in Azure
+const BROWSER_CONNECTION_STRING: &str = "AccountName=browser;AccountKey=browser";
+
+/// Pages in IndexedDB (see indexed_db.rs)
+pub struct IndexedDbDisk {
+    db: SendWrapper<IdbDatabase>,
+}
+
+impl IndexedDbDisk {
+    /// Use (or create) the pages in database `name`
+    pub async fn open(name: &str) -> Result<Self> {
+        let db = open_database(name).await?;
+        Ok(Self { db: SendWrapper::new(db) })
+    }
+}
+
+impl PageStore for IndexedDbDisk {
+    fn read_page(&self, page_id: u64) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(SendWrapper::new(async move {
+            debug!("Reading page {} from IndexedDB", page_id);
+            let (_, pages) = transaction(&self.db, PAGES, IdbTransactionMode::Readonly)?;
+            let value = request(&pages.get(&key(page_id)).map_err(js_error)?).await?;
+            if value.is_undefined() {
+                return Ok(vec![0u8; PAGE_SIZE]);
+            }
+            Ok(Uint8Array::new(&value).to_vec())
+        }))
+    }
+
+    fn write_page<'a>(&'a self, page_id: u64, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(SendWrapper::new(async move {
+            if data.len() != PAGE_SIZE {
+                anyhow::bail!("Invalid page size: expected {}, got {}", PAGE_SIZE, data.len());
+            }
+            debug!("Writing page {} to IndexedDB", page_id);
+            let (transaction, pages) = transaction(&self.db, PAGES, IdbTransactionMode::Readwrite)?;
+            pages.put_with_key(&Uint8Array::from(data), &key(page_id)).map_err(js_error)?;
+            complete(&transaction).await
+        }))
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<()>> {
+        // Each write's transaction completed before it returned
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// Length and last partial block of an `IndexedDbLog`
+struct LogTail {
+    len: u64,
+    /// The last `len % BLOCK_SIZE` bytes of the log
+    block: Vec<u8>,
+}
+
+/// Log in IndexedDB, in fixed-size blocks (see indexed_db.rs)
+pub struct IndexedDbLog {
+    db: SendWrapper<IdbDatabase>,
+    /// Serializes appends, truncates and resets
+    tail: tokio::sync::Mutex<LogTail>,
+}
+
+impl IndexedDbLog {
+    /// Use (or create) the log in database `name`
+    pub async fn open(name: &str) -> Result<Self> {
+        let db = open_database(name).await?;
+        let (_, wal) = transaction(&db, WAL, IdbTransactionMode::Readonly)?;
+        let len = request(&wal.get(&JsValue::from_str(LEN_KEY)).map_err(js_error)?).await?;
+        let len = len.as_f64().unwrap_or(0.0) as u64;
+        let mut block = Vec::new();
+        let partial = len as usize % BLOCK_SIZE;
+        if partial > 0 {
+            // A new transaction: one may commit once nothing is pending in it
+            let (_, wal) = transaction(&db, WAL, IdbTransactionMode::Readonly)?;
+            let value = request(&wal.get(&key(len / BLOCK_SIZE as u64)).map_err(js_error)?).await?;
+            if value.is_undefined() {
+                anyhow::bail!("Log block {} is missing ({} bytes logged)", len / BLOCK_SIZE as u64, len);
+            }
+            block = Uint8Array::new(&value).to_vec();
+            block.truncate(partial);
+        }
+        Ok(Self { db: SendWrapper::new(db), tail: tokio::sync::Mutex::new(LogTail { len, block }) })
+    }
+
+    /// In one transaction, drop the blocks from `drop_from` on, then put
+    /// `blocks` and the log length `len`
+    async fn write_blocks(&self, blocks: &[(u64, Vec<u8>)], drop_from: Option<u64>, len: u64) -> Result<()> {
+        let (transaction, wal) = transaction(&self.db, WAL, IdbTransactionMode::Readwrite)?;
+        if let Some(first) = drop_from {
+            // Bounded above, as string keys (`len`) sort after numbers
+            let range = IdbKeyRange::bound(&key(first), &JsValue::from_f64(f64::INFINITY)).map_err(js_error)?;
+            wal.delete(&range).map_err(js_error)?;
+        }
+        for (block, data) in blocks {
+            wal.put_with_key(&Uint8Array::from(data.as_slice()), &key(*block)).map_err(js_error)?;
+        }
+        wal.put_with_key(&JsValue::from_f64(len as f64), &JsValue::from_str(LEN_KEY)).map_err(js_error)?;
+        complete(&transaction).await
+    }
+
+    /// Read `range` of a log of `len` bytes
+    async fn read_blocks(&self, range: Range<u64>, len: u64) -> Result<Vec<u8>> {
+        if range.start > range.end || range.end > len {
+            anyhow::bail!("Log range {}..{} is past the end ({} bytes)", range.start, range.end, len);
+        }
+        if range.is_empty() {
+            return Ok(Vec::new());
+        }
+        let blocks = blocks_for_read(&range);
+        let (_, wal) = transaction(&self.db, WAL, IdbTransactionMode::Readonly)?;
+        let bounds = IdbKeyRange::bound(&key(blocks.start), &key(blocks.end - 1)).map_err(js_error)?;
+        let values: Array = request(&wal.get_all_with_key(&bounds).map_err(js_error)?).await?.unchecked_into();
+        let mut data = Vec::with_capacity(values.length() as usize * BLOCK_SIZE);
+        for value in values.iter() {
+            data.extend_from_slice(&Uint8Array::new(&value).to_vec());
+        }
+        let start = (range.start - blocks.start * BLOCK_SIZE as u64) as usize;
+        let end = start + (range.end - range.start) as usize;
+        data.get(start..end)
+            .map(<[u8]>::to_vec)
+            .ok_or_else(|| anyhow!("Log blocks {}..{} are missing ({} bytes logged)", blocks.start, blocks.end, len))
+    }
+}
+
+impl LogStore for IndexedDbLog {
+    fn append(&self, data: Bytes) -> BoxFuture<'_, Result<()>> {
+        Box::pin(SendWrapper::new(async move {
+            let mut tail = self.tail.lock().await;
+            let blocks = blocks_for_append(tail.len, &tail.block, &data);
+            let len = tail.len + data.len() as u64;
+            self.write_blocks(&blocks, None, len).await?;
+            tail.block = match blocks.last() {
+                Some((_, block)) if block.len() < BLOCK_SIZE => block.clone(),
+                _ => Vec::new(),
+            };
+            tail.len = len;
+            Ok(())
+        }))
+    }
+
+    fn stat(&self) -> BoxFuture<'_, Result<LogStat>> {
+        Box::pin(async move { Ok(LogStat { len: self.tail.lock().await.len, identity: None }) })
+    }
+
+    fn read(&self, range: Range<u64>) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(SendWrapper::new(async move {
+            let len = self.tail.lock().await.len;
+            self.read_blocks(range, len).await
+        }))
+    }
+
+    fn reset(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(SendWrapper::new(async move {
+            let mut tail = self.tail.lock().await;
+            self.write_blocks(&[], Some(0), 0).await?;
+            *tail = LogTail { len: 0, block: Vec::new() };
+            Ok(())
+        }))
+    }
+
+    fn truncate(&self, len: u64) -> BoxFuture<'_, Result<()>> {
+        Box::pin(SendWrapper::new(async move {
+            let mut tail = self.tail.lock().await;
+            if len > tail.len {
+                anyhow::bail!("Can't truncate a log of {} bytes to {} bytes", tail.len, len);
+            }
+            let first = len / BLOCK_SIZE as u64;
+            let block = self.read_blocks(first * BLOCK_SIZE as u64..len, tail.len).await?;
+            let blocks = if block.is_empty() { Vec::new() } else { vec![(first, block.clone())] };
+            self.write_blocks(&blocks, Some(first), len).await?;
+            *tail = LogTail { len, block };
+            Ok(())
+        }))
+    }
+}
+
+impl KVStore {
+    /// Open (or create) a store kept entirely in IndexedDB database `name`
+    /// (see indexed_db.rs)
+    pub async fn new_indexed_db(name: &str) -> Result<Self> {
+        let options = StoreOptions {
+            page_backend: PageBackend::IndexedDb(name.to_string()),
+            wal_backend: WalBackend::IndexedDb(name.to_string()),
+            ..Default::default()
+        };
+        Self::open(BROWSER_CONNECTION_STRING, options).await
+    }
+}
+
+/// Blocks to put to append `data` to a log of `len` bytes whose last
+/// `len % BLOCK_SIZE` bytes are `tail`
+fn blocks_for_append(len: u64, tail: &[u8], data: &[u8]) -> Vec<(u64, Vec<u8>)> {
+    let first = len / BLOCK_SIZE as u64;
+    let mut bytes = Vec::with_capacity(tail.len() + data.len());
+    bytes.extend_from_slice(tail);
+    bytes.extend_from_slice(data);
+    bytes.chunks(BLOCK_SIZE).enumerate().map(|(i, chunk)| (first + i as u64, chunk.to_vec())).collect()
+}
+
+/// Blocks holding the (non-empty) byte range `range`
+fn blocks_for_read(range: &Range<u64>) -> Range<u64> {
+    range.start / BLOCK_SIZE as u64..(range.end - 1) / BLOCK_SIZE as u64 + 1
+}
+
+/// IndexedDB key of page or block `id`
+fn key(id: u64) -> JsValue {
+    JsValue::from_f64(id as f64)
+}
+
+/// Open (or create) database `name` with both object stores
+async fn open_database(name: &str) -> Result<IdbDatabase> {
+    let factory: IdbFactory = js_sys::Reflect::get(&js_sys::global(), &JsValue::from_str("indexedDB"))
+        .map_err(js_error)?
+        .dyn_into()
+        .map_err(|_| anyhow!("IndexedDB isn't available here"))?;
+    let open = factory.open_with_u32(name, VERSION).map_err(js_error)?;
+    let opening = open.clone();
+    let upgrade = Closure::once(move |_event: JsValue| {
+        // A failure aborts the upgrade, which fails the open below
+        if let Ok(db) = opening.result().and_then(|db| db.dyn_into::<IdbDatabase>()) {
+            for store in [PAGES, WAL] {
+                if !db.object_store_names().contains(store) {
+                    let _ = db.create_object_store(store);
+                }
+            }
+        }
+    });
+    open.set_onupgradeneeded(Some(upgrade.as_ref().unchecked_ref()));
+    let db = request(&open).await;
+    open.set_onupgradeneeded(None);
+    info!("Opened IndexedDB database {}", name);
+    db?.dyn_into().map_err(|_| anyhow!("IndexedDB open of {} didn't return a database", name))
+}
+
+/// A transaction on object store `store`, and the store
+fn transaction(db: &IdbDatabase, store: &str, mode: IdbTransactionMode) -> Result<(IdbTransaction, IdbObjectStore)> {
+    let transaction = db.transaction_with_str_and_mode(store, mode).map_err(js_error)?;
+    let store = transaction.object_store(store).map_err(js_error)?;
+    Ok((transaction, store))
+}
+
+/// Wait for `request` to succeed; returns its result
+async fn request(request: &IdbRequest) -> Result<JsValue> {
+    let done = Promise::new(&mut |resolve, reject| {
+        request.set_onsuccess(Some(&resolve));
+        request.set_onerror(Some(&reject));
+    });
+    let outcome = JsFuture::from(done).await;
+    request.set_onsuccess(None);
+    request.set_onerror(None);
+    if outcome.is_err() {
+        let error = request.error().ok().flatten().map(|error| error.message());
+        anyhow::bail!("IndexedDB request failed: {}", error.unwrap_or_else(|| "unknown error".to_string()));
+    }
+    request.result().map_err(js_error)
+}
+
+/// Wait for `transaction` to commit
+async fn complete(transaction: &IdbTransaction) -> Result<()> {
+    let done = Promise::new(&mut |resolve, reject| {
+        transaction.set_oncomplete(Some(&resolve));
+        transaction.set_onerror(Some(&reject));
+        transaction.set_onabort(Some(&reject));
+    });
+    let outcome = JsFuture::from(done).await;
+    if outcome.is_err() {
+        let error = transaction.error().map(|error| error.message());
+        anyhow::bail!("IndexedDB transaction failed: {}", error.unwrap_or_else(|| "aborted".to_string()));
+    }
+    Ok(())
+}
+
+fn js_error(error: JsValue) -> anyhow::Error {
+    anyhow!("IndexedDB error: {:?}", error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_appends_fill_the_tail_block_first() {
+        assert!(blocks_for_append(0, &[], &[]).is_empty());
+        assert_eq!(blocks_for_append(0, &[], b"abc"), vec![(0, b"abc".to_vec())]);
+        assert_eq!(blocks_for_append(3, b"abc", b"de"), vec![(0, b"abcde".to_vec())]);
+
+        // Crossing a block boundary rewrites the tail and starts the next
+        let len = BLOCK_SIZE as u64 + 2;
+        let blocks = blocks_for_append(len, b"xy", &vec![7u8; BLOCK_SIZE]);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!((blocks[0].0, blocks[0].1.len()), (1, BLOCK_SIZE));
+        assert_eq!(&blocks[0].1[..3], b"xy\x07");
+        assert_eq!(blocks[1], (2, vec![7u8; 2]));
+    }
+
+    #[test]
+    fn test_reads_cover_every_block_in_the_range() {
+        let block = BLOCK_SIZE as u64;
+        assert_eq!(blocks_for_read(&(0..1)), 0..1);
+        assert_eq!(blocks_for_read(&(0..block)), 0..1);
+        assert_eq!(blocks_for_read(&(block - 1..block + 1)), 0..2);
+        assert_eq!(blocks_for_read(&(2 * block..2 * block + 5)), 2..3);
+    }
+}
@@ -0,0 +1,322 @@
+//! Kafka: Change Feed Sinks for Kafka and Event Hubs
+//!
+//! Behind the `kafka` feature, two `ChangeSink`s (see changefeed.rs) put
+//! the change stream where streaming pipelines already read:
+//!
+//! - `KafkaSink`: a Kafka topic, through librdkafka (the `rdkafka`
+//!   crate). Each change is a record keyed by the store key, its value the
+//!   `ChangeEvent` as JSON and an `op` header. The producer is idempotent
+//!   and waits for all in-sync replicas (`acks=all`), so retries neither
+//!   reorder nor duplicate a partition's records.
+//! - `EventHubsSink`: an Event Hub, over its REST API. Kafka consumers
+//!   can read it through the namespace's Kafka endpoint.
+//!
+//! Both pick a change's partition from its key the way Kafka's default
+//! partitioner does (murmur2, modulo the partition count), so every
+//! change to a key lands on one partition, in commit order, and a
+//! consumer keyed the same way sees them where it expects to.
+//!
+//! ```ignore
+//! let sink = Arc::new(KafkaSink::new("broker-1:9092", "ironclad-changes")?);
+//! let publisher = store.start_change_publisher(sink, ChangeFeedOptions::new("changes-to-kafka"))?;
+//! ```
+//!
+//! `KafkaSink::with_config` takes any librdkafka settings, which is how
+//! a sink gets TLS or SASL (`security.protocol`, `ssl.*`, `sasl.*`).
+//! librdkafka keeps a connection per broker, follows moved partition
+//! leaders, and bounds responses (`receive.message.max.bytes`); the sink
+//! adds default socket and delivery timeouts so a dead broker fails a
+//! batch (which the publisher retries) instead of hanging it.
+
+use anyhow::{Context, Result};
+use azure_core::{HttpClient, Method, Request, Url};
+use futures::future::BoxFuture;
+use rdkafka::config::ClientConfig;
+use rdkafka::error::KafkaError;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::types::RDKafkaErrorCode;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::OnceCell;
+
+use crate::change_sinks::{now_secs, parse_sas_connection_string, sas_token, send};
+use crate::changefeed::{ChangeEvent, ChangeSink};
+
+const CLIENT_ID: &str = "ironclad";
+
+/// How long a broker may take to answer a request (a produce included)
+const REQUEST_TIMEOUT_MS: u32 = 30_000;
+
+/// How long a record may take to be delivered, retries included
+const DELIVERY_TIMEOUT_MS: u32 = 120_000;
+
+/// How long a broker connection may sit on an unanswered request
+const SOCKET_TIMEOUT_MS: u32 = 60_000;
+
+/// Wait before enqueueing again when librdkafka's local queue is full
+const QUEUE_FULL_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Lifetime of the Event Hubs SAS tokens we sign
+const SAS_TOKEN_SECS: u64 = 3_600;
+
+/// Partition of `key` among `partitions`, as Kafka's default partitioner
+/// picks it
+pub fn partition_for(key: &str, partitions: u32) -> u32 {
+    (murmur2(key.as_bytes()) & 0x7fff_ffff) as u32 % partitions.max(1)
+}
+
+/// Kafka's murmur2 (seed 0x9747b28c)
+fn murmur2(data: &[u8]) -> i32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h: u32 = 0x9747_b28c ^ data.len() as u32;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    if tail.len() >= 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= tail[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
+}
+
+/// Events grouped by partition, each group in commit order
+fn by_partition(events: &[ChangeEvent], partitions: u32) -> BTreeMap<u32, Vec<&ChangeEvent>> {
+    let mut grouped: BTreeMap<u32, Vec<&ChangeEvent>> = BTreeMap::new();
+    for event in events {
+        grouped.entry(partition_for(&event.key, partitions)).or_default().push(event);
+    }
+    grouped
+}
+
+/// `config` with the settings the sink relies on
+///
+/// Delivery guarantees and partitioning are always set; client ID and
+/// timeouts only when `config` leaves them out.
+fn producer_config(config: &ClientConfig) -> ClientConfig {
+    let mut config = config.clone();
+    config
+        .set("enable.idempotence", "true")
+        .set("acks", "all")
+        .set("partitioner", "murmur2_random");
+    for (key, value) in [
+        ("client.id", CLIENT_ID.to_string()),
+        ("request.timeout.ms", REQUEST_TIMEOUT_MS.to_string()),
+        ("message.timeout.ms", DELIVERY_TIMEOUT_MS.to_string()),
+        ("socket.timeout.ms", SOCKET_TIMEOUT_MS.to_string()),
+    ] {
+        if config.get(key).is_none() {
+            config.set(key, value);
+        }
+    }
+    config
+}
+
+/// Publishes changes to a Kafka topic
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: String,
+}
+
+impl KafkaSink {
+    /// `bootstrap` is a comma-separated list of host:port brokers, reached
+    /// in plaintext
+    pub fn new(bootstrap: &str, topic: &str) -> Result<Self> {
+        Self::with_config(ClientConfig::new().set("bootstrap.servers", bootstrap), topic)
+    }
+
+    /// Produce with `config`, which names the brokers and may set up TLS
+    /// or SASL
+    pub fn with_config(config: &ClientConfig, topic: &str) -> Result<Self> {
+        let producer = producer_config(config).create().context("Creating the Kafka producer")?;
+        Ok(Self { producer, topic: topic.to_string() })
+    }
+}
+
+impl ChangeSink for KafkaSink {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    fn publish<'a>(&'a self, events: &'a [ChangeEvent]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            // Enqueue everything in commit order, then wait for the acks
+            let mut deliveries = Vec::with_capacity(events.len());
+            for event in events {
+                let payload = serde_json::to_vec(event)?;
+                let headers = OwnedHeaders::new().insert(Header { key: "op", value: Some(event.change.op()) });
+                let mut record = FutureRecord::to(&self.topic).key(&event.key).payload(&payload).headers(headers);
+                if event.at_ms > 0 {
+                    record = record.timestamp(event.at_ms as i64);
+                }
+                loop {
+                    match self.producer.send_result(record) {
+                        Ok(delivery) => break deliveries.push(delivery),
+                        Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                            record = returned;
+                            tokio::time::sleep(QUEUE_FULL_BACKOFF).await;
+                        }
+                        Err((e, _)) => return Err(e).with_context(|| format!("Producing to Kafka topic {}", self.topic)),
+                    }
+                }
+            }
+            for delivery in deliveries {
+                delivery
+                    .await
+                    .context("The Kafka producer dropped a delivery")?
+                    .map_err(|(e, _)| e)
+                    .with_context(|| format!("Delivering to Kafka topic {}", self.topic))?;
+            }
+            Ok(())
+        })
+    }
+}
+
+/// Publishes changes to an Event Hub
+pub struct EventHubsSink {
+    http: Arc<dyn HttpClient>,
+    /// `https://<namespace>.servicebus.windows.net/<hub>`
+    entity: String,
+    key_name: String,
+    key: String,
+    partitions: OnceCell<u32>,
+}
+
+impl EventHubsSink {
+    /// `connection_string` is a namespace (or hub) shared access policy's,
+    /// `hub` the Event Hub to send to
+    pub fn new(connection_string: &str, hub: &str) -> Result<Self> {
+        let (host, key_name, key) = parse_sas_connection_string(connection_string)?;
+        Ok(Self {
+            http: azure_core::new_http_client(),
+            entity: format!("https://{}/{}", host, hub),
+            key_name,
+            key,
+            partitions: OnceCell::new(),
+        })
+    }
+
+    /// Skip looking up the hub's partition count
+    pub fn with_partitions(self, partitions: u32) -> Self {
+        let _ = self.partitions.set(partitions.max(1));
+        self
+    }
+
+    fn authorize(&self, request: &mut Request) {
+        request.insert_header("authorization", sas_token(&self.entity, &self.key_name, &self.key, now_secs() + SAS_TOKEN_SECS));
+    }
+
+    async fn partition_count(&self) -> Result<u32> {
+        let count = self
+            .partitions
+            .get_or_try_init(|| async {
+                let mut request = Request::new(Url::parse(&format!("{}?api-version=2014-01", self.entity))?, Method::Get);
+                self.authorize(&mut request);
+                let response = self.http.execute_request(&request).await?;
+                if !response.status().is_success() {
+                    anyhow::bail!("Event Hubs answered {} describing {}", response.status(), self.entity);
+                }
+                let description = response.into_body().collect_string().await?;
+                description
+                    .split("<PartitionCount>")
+                    .nth(1)
+                    .and_then(|rest| rest.split('<').next())
+                    .and_then(|count| count.trim().parse().ok())
+                    .ok_or_else(|| anyhow::anyhow!("Event Hub {} description has no partition count", self.entity))
+            })
+            .await?;
+        Ok(*count)
+    }
+}
+
+impl ChangeSink for EventHubsSink {
+    fn name(&self) -> &str {
+        "event hubs"
+    }
+
+    fn publish<'a>(&'a self, events: &'a [ChangeEvent]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            for (partition, events) in by_partition(events, self.partition_count().await?) {
+                let messages = events
+                    .iter()
+                    .map(|event| {
+                        Ok(serde_json::json!({
+                            "Body": serde_json::to_string(event)?,
+                            "UserProperties": { "op": event.change.op(), "key": event.key },
+                        }))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                let url = Url::parse(&format!("{}/partitions/{}/messages", self.entity, partition))?;
+                let mut request = Request::new(url, Method::Post);
+                self.authorize(&mut request);
+                request.insert_header("content-type", "application/vnd.microsoft.servicebus.json");
+                request.set_body(serde_json::to_vec(&messages)?);
+                send(self.http.as_ref(), &request, "Event Hubs").await?;
+            }
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::changefeed::Change;
+
+    #[test]
+    fn test_partitioning_matches_kafka() {
+        // Vectors from Kafka's own murmur2 tests
+        assert_eq!(murmur2(b"21"), -973_932_308);
+        assert_eq!(murmur2(b"foobar"), -790_332_482);
+        assert_eq!(murmur2(b"a-little-bit-long-string"), -985_981_536);
+        assert_eq!(murmur2(b"a-little-bit-longer-string"), -1_486_304_829);
+        assert_eq!(partition_for("21", 1), 0);
+        assert_eq!(partition_for("foobar", 8), (-790_332_482i32 & 0x7fff_ffff) as u32 % 8);
+    }
+
+    #[test]
+    fn test_producer_config_keeps_security_and_forces_delivery() {
+        let mut config = ClientConfig::new();
+        config
+            .set("bootstrap.servers", "broker-1:9093")
+            .set("security.protocol", "SASL_SSL")
+            .set("sasl.mechanism", "PLAIN")
+            .set("acks", "1")
+            .set("message.timeout.ms", "5000");
+        let config = producer_config(&config);
+        assert_eq!(config.get("security.protocol"), Some("SASL_SSL"));
+        assert_eq!(config.get("sasl.mechanism"), Some("PLAIN"));
+        assert_eq!(config.get("acks"), Some("all"));
+        assert_eq!(config.get("enable.idempotence"), Some("true"));
+        assert_eq!(config.get("partitioner"), Some("murmur2_random"));
+        assert_eq!(config.get("message.timeout.ms"), Some("5000"));
+        assert_eq!(config.get("socket.timeout.ms"), Some("60000"));
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_broker_fails_the_batch() {
+        // Nothing listens on port 1: the delivery timeout has to end the wait
+        let mut config = ClientConfig::new();
+        config.set("bootstrap.servers", "127.0.0.1:1").set("message.timeout.ms", "1000");
+        let sink = KafkaSink::with_config(&config, "changes").unwrap();
+        let event = ChangeEvent { lsn: 1, key: "k".to_string(), change: Change::Delete, at_ms: 1_000 };
+        let result = tokio::time::timeout(Duration::from_secs(30), sink.publish(&[event])).await;
+        assert!(result.expect("publish hung").is_err());
+    }
+}
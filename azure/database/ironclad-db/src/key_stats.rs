@@ -0,0 +1,340 @@
+//! Key Stats: Per-Prefix Usage and the Most Active Keys
+//!
+//! Answers "what is filling up the store, and what is busy" without
+//! exporting anything. With `StoreOptions::key_stats` set:
+//!
+//! ```ignore
+//! let options = StoreOptions {
+//!     key_stats: KeyStatsOptions { prefixes: vec!["user:".into(), "session:".into()], top_keys: 20, ..Default::default() },
+//!     ..Default::default()
+//! };
+//! let users = store.stats_for_prefix("user:").unwrap();
+//! println!("{} keys, {} bytes, {:.0} writes/s", users.keys, users.bytes, users.writes_per_sec);
+//! for key in store.top_keys().written { println!("{} ~{} writes", key.key, key.count); }
+//! ```
+//!
+//! - Each prefix in `prefixes` has its key count and bytes (keys plus
+//!   stored values) kept as writes are applied, recovery included, and its
+//!   reads and writes counted over the last `window` for rates.
+//! - `top_keys` > 0 keeps the most written and most read keys since the
+//!   store opened in Space-Saving sketches: bounded memory (a few entries
+//!   per reported key) however many keys there are. Counts may be over by
+//!   at most each entry's `error`; a key reported with a count well above
+//!   its error is truly that busy.
+//!
+//! Writes replayed by recovery count towards sizes but not rates or top
+//! keys. Patches and appends count as writes of the whole new value.
+
+use dashmap::DashMap;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::index::key_hash;
+use crate::kvstore::KVStore;
+
+/// Slots a rate window is divided into
+const SLOTS: u32 = 6;
+
+/// Sketch entries kept per key reported
+const SKETCH_ENTRIES_PER_KEY: usize = 4;
+
+/// What to track
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyStatsOptions {
+    /// Prefixes with usage and rates (default: none)
+    pub prefixes: Vec<String>,
+    /// Most written and most read keys to report (default: 0, none)
+    pub top_keys: usize,
+    /// Window rates are measured over (default: 60s)
+    pub window: Duration,
+}
+
+impl Default for KeyStatsOptions {
+    fn default() -> Self {
+        Self { prefixes: Vec::new(), top_keys: 0, window: Duration::from_secs(60) }
+    }
+}
+
+/// Usage and activity of the keys under one prefix
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrefixStats {
+    pub prefix: String,
+    pub keys: u64,
+    pub bytes: u64,
+    pub reads_per_sec: f64,
+    pub writes_per_sec: f64,
+}
+
+/// A key and (an upper bound on) how often it was accessed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TopKey {
+    pub key: String,
+    pub count: u64,
+    /// At most this much of `count` may belong to keys it displaced
+    pub error: u64,
+}
+
+/// The most active keys since the store opened, busiest first
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TopKeys {
+    pub written: Vec<TopKey>,
+    pub read: Vec<TopKey>,
+}
+
+/// Space-Saving heavy hitters sketch
+struct SpaceSaving {
+    capacity: usize,
+    /// Key to (count, error)
+    counts: HashMap<String, (u64, u64)>,
+}
+
+impl SpaceSaving {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, counts: HashMap::with_capacity(capacity) }
+    }
+
+    fn record(&mut self, key: &str) {
+        if let Some((count, _)) = self.counts.get_mut(key) {
+            *count += 1;
+            return;
+        }
+        if self.counts.len() < self.capacity {
+            self.counts.insert(key.to_string(), (1, 0));
+            return;
+        }
+        // Replace the least counted key, inheriting its count as error
+        let Some((evicted, min)) = self.counts.iter().min_by_key(|(_, (count, _))| *count).map(|(k, (c, _))| (k.clone(), *c))
+        else {
+            return;
+        };
+        self.counts.remove(&evicted);
+        self.counts.insert(key.to_string(), (min + 1, min));
+    }
+
+    fn top(&self, n: usize) -> Vec<TopKey> {
+        let mut top: Vec<TopKey> =
+            self.counts.iter().map(|(key, &(count, error))| TopKey { key: key.clone(), count, error }).collect();
+        top.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+        top.truncate(n);
+        top
+    }
+}
+
+/// Reads and writes in time slots over a window
+struct OpWindow {
+    slot_len: Duration,
+    /// (slot start, reads, writes), oldest first
+    slots: VecDeque<(Instant, u64, u64)>,
+}
+
+impl OpWindow {
+    fn new(window: Duration) -> Self {
+        Self { slot_len: (window / SLOTS).max(Duration::from_millis(1)), slots: VecDeque::new() }
+    }
+
+    fn rotate(&mut self, now: Instant) {
+        if self.slots.back().is_none_or(|(start, _, _)| now.duration_since(*start) >= self.slot_len) {
+            self.slots.push_back((now, 0, 0));
+        }
+        let window = self.slot_len * SLOTS;
+        while self.slots.front().is_some_and(|(start, _, _)| now.duration_since(*start) >= window) {
+            self.slots.pop_front();
+        }
+    }
+
+    fn record(&mut self, now: Instant, write: bool) {
+        self.rotate(now);
+        if let Some((_, reads, writes)) = self.slots.back_mut() {
+            *if write { writes } else { reads } += 1;
+        }
+    }
+
+    /// (reads, writes) per second over the window
+    fn rates(&mut self, now: Instant) -> (f64, f64) {
+        self.rotate(now);
+        let secs = (self.slot_len * SLOTS).as_secs_f64();
+        let (reads, writes) = self.slots.iter().fold((0, 0), |(r, w), (_, reads, writes)| (r + reads, w + writes));
+        (reads as f64 / secs, writes as f64 / secs)
+    }
+}
+
+struct PrefixCounters {
+    prefix: String,
+    keys: AtomicU64,
+    bytes: AtomicU64,
+    ops: Mutex<OpWindow>,
+}
+
+/// Key stats of a store
+pub(crate) struct KeyStats {
+    prefixes: Vec<PrefixCounters>,
+    top_keys: usize,
+    written: Mutex<SpaceSaving>,
+    read: Mutex<SpaceSaving>,
+    /// Size of each key under a tracked prefix, by key hash
+    sizes: DashMap<u128, u64>,
+    /// Off while recovery replays, so its writes don't count as activity
+    live: AtomicBool,
+}
+
+impl KeyStats {
+    pub fn new(options: &KeyStatsOptions) -> Self {
+        let sketch = options.top_keys * SKETCH_ENTRIES_PER_KEY;
+        Self {
+            prefixes: options
+                .prefixes
+                .iter()
+                .map(|prefix| PrefixCounters {
+                    prefix: prefix.clone(),
+                    keys: AtomicU64::new(0),
+                    bytes: AtomicU64::new(0),
+                    ops: Mutex::new(OpWindow::new(options.window)),
+                })
+                .collect(),
+            top_keys: options.top_keys,
+            written: Mutex::new(SpaceSaving::new(sketch)),
+            read: Mutex::new(SpaceSaving::new(sketch)),
+            sizes: DashMap::new(),
+            live: AtomicBool::new(false),
+        }
+    }
+
+    /// Recovery is done: count activity from now on
+    pub fn go_live(&self) {
+        self.live.store(true, Ordering::Relaxed);
+    }
+
+    fn prefixes_of<'a>(&'a self, key: &'a str) -> impl Iterator<Item = &'a PrefixCounters> + 'a {
+        self.prefixes.iter().filter(move |counters| key.starts_with(&counters.prefix))
+    }
+
+    /// Count an access to `key`
+    fn record_op(&self, key: &str, write: bool) {
+        if !self.live.load(Ordering::Relaxed) {
+            return;
+        }
+        if self.top_keys > 0 {
+            if write { &self.written } else { &self.read }.lock().record(key);
+        }
+        let now = Instant::now();
+        for counters in self.prefixes_of(key) {
+            counters.ops.lock().record(now, write);
+        }
+    }
+
+    pub fn record_read(&self, key: &str) {
+        self.record_op(key, false);
+    }
+
+    /// `key` now holds `value`
+    pub fn record_set(&self, key: &str, value: &str) {
+        self.record_op(key, true);
+        if self.prefixes_of(key).next().is_none() {
+            return;
+        }
+        let size = (key.len() + value.len()) as u64;
+        let previous = self.sizes.insert(key_hash(key), size);
+        for counters in self.prefixes_of(key) {
+            match previous {
+                Some(previous) => counters.bytes.fetch_sub(previous, Ordering::Relaxed),
+                None => counters.keys.fetch_add(1, Ordering::Relaxed),
+            };
+            counters.bytes.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
+    /// `key` was deleted
+    pub fn record_delete(&self, key: &str) {
+        self.record_op(key, true);
+        let Some((_, previous)) = self.sizes.remove(&key_hash(key)) else {
+            return;
+        };
+        for counters in self.prefixes_of(key) {
+            counters.keys.fetch_sub(1, Ordering::Relaxed);
+            counters.bytes.fetch_sub(previous, Ordering::Relaxed);
+        }
+    }
+
+    pub fn prefix_stats(&self, prefix: &str) -> Option<PrefixStats> {
+        let counters = self.prefixes.iter().find(|counters| counters.prefix == prefix)?;
+        let (reads_per_sec, writes_per_sec) = counters.ops.lock().rates(Instant::now());
+        Some(PrefixStats {
+            prefix: prefix.to_string(),
+            keys: counters.keys.load(Ordering::Relaxed),
+            bytes: counters.bytes.load(Ordering::Relaxed),
+            reads_per_sec,
+            writes_per_sec,
+        })
+    }
+
+    pub fn top(&self) -> TopKeys {
+        TopKeys { written: self.written.lock().top(self.top_keys), read: self.read.lock().top(self.top_keys) }
+    }
+}
+
+impl KVStore {
+    /// Usage and rates of `prefix` (None unless it is in
+    /// `StoreOptions::key_stats.prefixes`)
+    pub fn stats_for_prefix(&self, prefix: &str) -> Option<PrefixStats> {
+        self.key_stats().prefix_stats(prefix)
+    }
+
+    /// The most written and most read keys (empty unless
+    /// `StoreOptions::key_stats.top_keys` is set)
+    pub fn top_keys(&self) -> TopKeys {
+        self.key_stats().top()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats() -> KeyStats {
+        let stats = KeyStats::new(&KeyStatsOptions { prefixes: vec!["user:".to_string()], top_keys: 2, ..Default::default() });
+        stats.go_live();
+        stats
+    }
+
+    #[test]
+    fn test_prefix_usage_follows_writes() {
+        let stats = stats();
+        stats.record_set("user:1", "abc");
+        stats.record_set("user:1", "abcdef");
+        stats.record_set("user:2", "x");
+        stats.record_set("order:1", "ignored");
+        stats.record_delete("user:2");
+        stats.record_read("user:1");
+
+        let user = stats.prefix_stats("user:").unwrap();
+        assert_eq!((user.keys, user.bytes), (1, 12));
+        assert!(user.writes_per_sec > 0.0 && user.reads_per_sec > 0.0);
+        assert!(stats.prefix_stats("order:").is_none());
+    }
+
+    #[test]
+    fn test_top_keys_find_the_heavy_hitters() {
+        let stats = stats();
+        for i in 0..1_000 {
+            stats.record_set("hot", "v");
+            if i % 2 == 0 {
+                stats.record_set("warm", "v");
+            }
+            stats.record_set(&format!("cold{}", i), "v");
+        }
+        let written: Vec<String> = stats.top().written.into_iter().map(|key| key.key).collect();
+        assert_eq!(written, vec!["hot", "warm"]);
+        assert!(stats.top().read.is_empty());
+    }
+
+    #[test]
+    fn test_recovery_writes_are_not_activity() {
+        let stats = KeyStats::new(&KeyStatsOptions { prefixes: vec!["user:".to_string()], top_keys: 2, ..Default::default() });
+        stats.record_set("user:1", "v");
+        let user = stats.prefix_stats("user:").unwrap();
+        assert_eq!((user.keys, user.writes_per_sec), (1, 0.0));
+        assert!(stats.top().written.is_empty());
+    }
+}
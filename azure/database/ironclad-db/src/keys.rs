@@ -0,0 +1,422 @@
+//! Keys: Data Key Providers and Key Caching
+//!
+//! Encryption keys come from a `KeyProvider`, which looks a 256-bit data key
+//! up by its ID. A provider backed by a remote key service (Azure Key
+//! Vault, an HSM) costs a network round trip per lookup, so it is wrapped
+//! in a `CachedKeyProvider`:
+//!
+//! ```ignore
+//! let provider = CachedKeyProvider::new(Arc::new(vault_provider), KeyCachePolicy::default());
+//! ```
+//!
+//! The cache follows its `KeyCachePolicy`:
+//!
+//! - `ttl`: a fetched key is used without asking the provider for this long
+//! - `refresh_ahead`: within this long of expiring, a lookup still returns
+//!   the cached key but starts a background refresh, so busy keys never
+//!   expire on the request path
+//! - `max_stale`: if a fetch fails (the key service is down), an expired
+//!   key keeps being served for up to this long past its TTL; lookups only
+//!   fail after that. Zero never serves an expired key.
+//!
+//! `StaticKeyProvider` holds keys in memory, for tests and development.
+//!
+//! `destroy_key` deletes a key for good (see `KVStore::crypto_erase`). A
+//! cache forgets a key it destroys and never fetches it again, so neither
+//! a stale copy nor a refresh already in flight can bring it back.
+//!
+//! Like `LogStore`, providers return boxed futures so they can be held as
+//! `Arc<dyn KeyProvider>`.
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use crate::runtime::RuntimeHandle;
+
+/// Length of a data key in bytes
+pub const DATA_KEY_LEN: usize = 32;
+
+/// A 256-bit data key
+#[derive(Clone, PartialEq, Eq)]
+pub struct DataKey {
+    pub id: String,
+    bytes: [u8; DATA_KEY_LEN],
+}
+
+impl DataKey {
+    pub fn new(id: &str, bytes: [u8; DATA_KEY_LEN]) -> Self {
+        Self { id: id.to_string(), bytes }
+    }
+
+    pub fn bytes(&self) -> &[u8; DATA_KEY_LEN] {
+        &self.bytes
+    }
+}
+
+/// Never prints the key material
+impl std::fmt::Debug for DataKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DataKey").field("id", &self.id).finish_non_exhaustive()
+    }
+}
+
+/// Source of data keys
+pub trait KeyProvider: Send + Sync {
+    /// The key with ID `key_id`
+    fn fetch_key<'a>(&'a self, key_id: &'a str) -> BoxFuture<'a, Result<DataKey>>;
+
+    /// Delete the key with ID `key_id` permanently
+    fn destroy_key<'a>(&'a self, key_id: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move { anyhow::bail!("This key provider can't destroy keys (asked to destroy {})", key_id) })
+    }
+}
+
+/// Keys held in memory
+#[derive(Default)]
+pub struct StaticKeyProvider {
+    keys: Mutex<HashMap<String, [u8; DATA_KEY_LEN]>>,
+}
+
+impl StaticKeyProvider {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add (or replace) a key
+    pub fn insert(&self, key_id: &str, bytes: [u8; DATA_KEY_LEN]) {
+        self.keys.lock().insert(key_id.to_string(), bytes);
+    }
+}
+
+impl KeyProvider for StaticKeyProvider {
+    fn fetch_key<'a>(&'a self, key_id: &'a str) -> BoxFuture<'a, Result<DataKey>> {
+        Box::pin(async move {
+            match self.keys.lock().get(key_id) {
+                Some(bytes) => Ok(DataKey::new(key_id, *bytes)),
+                None => anyhow::bail!("Unknown data key {}", key_id),
+            }
+        })
+    }
+
+    fn destroy_key<'a>(&'a self, key_id: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            match self.keys.lock().remove(key_id) {
+                Some(_) => Ok(()),
+                None => anyhow::bail!("Unknown data key {}", key_id),
+            }
+        })
+    }
+}
+
+/// How long cached keys are used (see the module docs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyCachePolicy {
+    pub ttl: Duration,
+    pub refresh_ahead: Duration,
+    pub max_stale: Duration,
+}
+
+impl Default for KeyCachePolicy {
+    fn default() -> Self {
+        Self {
+            ttl: Duration::from_secs(5 * 60),
+            refresh_ahead: Duration::from_secs(60),
+            max_stale: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+/// Key cache counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyCacheStats {
+    pub hits: u64,
+    /// Lookups that had to wait for the provider
+    pub misses: u64,
+    pub background_refreshes: u64,
+    /// Expired keys served because the provider failed
+    pub stale_served: u64,
+    pub fetch_failures: u64,
+}
+
+struct CachedKey {
+    key: DataKey,
+    fetched_at: Instant,
+    refreshing: bool,
+}
+
+struct CacheState {
+    inner: Arc<dyn KeyProvider>,
+    runtime: RuntimeHandle,
+    policy: KeyCachePolicy,
+    entries: Mutex<HashMap<String, CachedKey>>,
+    /// Keys destroyed through this cache
+    destroyed: Mutex<HashSet<String>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    background_refreshes: AtomicU64,
+    stale_served: AtomicU64,
+    fetch_failures: AtomicU64,
+}
+
+impl CacheState {
+    /// Fetch `key_id` from the provider into the cache
+    async fn fetch(&self, key_id: &str) -> Result<DataKey> {
+        let result = self.inner.fetch_key(key_id).await;
+        let mut entries = self.entries.lock();
+        if self.destroyed.lock().contains(key_id) {
+            entries.remove(key_id);
+            anyhow::bail!("Data key {} was destroyed", key_id);
+        }
+        match result {
+            Ok(key) => {
+                entries.insert(key_id.to_string(), CachedKey { key: key.clone(), fetched_at: Instant::now(), refreshing: false });
+                Ok(key)
+            }
+            Err(e) => {
+                self.fetch_failures.fetch_add(1, Ordering::Relaxed);
+                if let Some(entry) = entries.get_mut(key_id) {
+                    entry.refreshing = false;
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+/// A key provider caching another (see the module docs)
+#[derive(Clone)]
+pub struct CachedKeyProvider {
+    state: Arc<CacheState>,
+}
+
+impl CachedKeyProvider {
+    pub fn new(inner: Arc<dyn KeyProvider>, policy: KeyCachePolicy) -> Self {
+        Self {
+            state: Arc::new(CacheState {
+                inner,
+                runtime: RuntimeHandle::default(),
+                policy,
+                entries: Mutex::new(HashMap::new()),
+                destroyed: Mutex::new(HashSet::new()),
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                background_refreshes: AtomicU64::new(0),
+                stale_served: AtomicU64::new(0),
+                fetch_failures: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Refresh keys in the background on `runtime` (see runtime.rs)
+    ///
+    /// Call before the provider is cloned or used.
+    pub fn with_runtime(mut self, runtime: RuntimeHandle) -> Self {
+        if let Some(state) = Arc::get_mut(&mut self.state) {
+            state.runtime = runtime;
+        }
+        self
+    }
+
+    pub fn stats(&self) -> KeyCacheStats {
+        let state = &self.state;
+        KeyCacheStats {
+            hits: state.hits.load(Ordering::Relaxed),
+            misses: state.misses.load(Ordering::Relaxed),
+            background_refreshes: state.background_refreshes.load(Ordering::Relaxed),
+            stale_served: state.stale_served.load(Ordering::Relaxed),
+            fetch_failures: state.fetch_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Drop a cached key, so the next lookup asks the provider
+    pub fn invalidate(&self, key_id: &str) {
+        self.state.entries.lock().remove(key_id);
+    }
+
+    /// The key with ID `key_id`, from the cache when the policy allows
+    pub async fn key(&self, key_id: &str) -> Result<DataKey> {
+        let state = &self.state;
+        let policy = state.policy;
+        if state.destroyed.lock().contains(key_id) {
+            anyhow::bail!("Data key {} was destroyed", key_id);
+        }
+
+        // (key, stale?) if cached
+        let cached = {
+            let mut entries = state.entries.lock();
+            entries.get_mut(key_id).map(|entry| {
+                let age = entry.fetched_at.elapsed();
+                if age < policy.ttl && age + policy.refresh_ahead >= policy.ttl && !entry.refreshing {
+                    entry.refreshing = true;
+                    self.refresh_in_background(key_id);
+                }
+                (entry.key.clone(), age >= policy.ttl, age)
+            })
+        };
+
+        match cached {
+            Some((key, false, _)) => {
+                state.hits.fetch_add(1, Ordering::Relaxed);
+                Ok(key)
+            }
+            Some((key, true, age)) => {
+                state.misses.fetch_add(1, Ordering::Relaxed);
+                match state.fetch(key_id).await {
+                    Ok(key) => Ok(key),
+                    Err(e) if age < policy.ttl + policy.max_stale => {
+                        warn!("KEYS: refreshing {} failed, using the cached key: {:#}", key_id, e);
+                        state.stale_served.fetch_add(1, Ordering::Relaxed);
+                        Ok(key)
+                    }
+                    Err(e) => Err(e.context(format!("Data key {} expired and could not be fetched", key_id))),
+                }
+            }
+            None => {
+                state.misses.fetch_add(1, Ordering::Relaxed);
+                state.fetch(key_id).await
+            }
+        }
+    }
+
+    /// Destroy `key_id` in the provider, then forget it
+    pub async fn destroy(&self, key_id: &str) -> Result<()> {
+        self.state.inner.destroy_key(key_id).await?;
+        self.state.destroyed.lock().insert(key_id.to_string());
+        self.state.entries.lock().remove(key_id);
+        Ok(())
+    }
+
+    fn refresh_in_background(&self, key_id: &str) {
+        let state = Arc::clone(&self.state);
+        let key_id = key_id.to_string();
+        state.background_refreshes.fetch_add(1, Ordering::Relaxed);
+        let runtime = state.runtime.clone();
+        runtime.spawn(async move {
+            match state.fetch(&key_id).await {
+                Ok(_) => debug!("KEYS: refreshed {} ahead of expiry", key_id),
+                Err(e) => warn!("KEYS: background refresh of {} failed: {:#}", key_id, e),
+            }
+        });
+    }
+}
+
+impl KeyProvider for CachedKeyProvider {
+    fn fetch_key<'a>(&'a self, key_id: &'a str) -> BoxFuture<'a, Result<DataKey>> {
+        Box::pin(self.key(key_id))
+    }
+
+    fn destroy_key<'a>(&'a self, key_id: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(self.destroy(key_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    /// Counts fetches; fails them while `down` is set
+    #[derive(Default)]
+    struct FlakyProvider {
+        fetches: AtomicU64,
+        down: AtomicBool,
+    }
+
+    impl KeyProvider for FlakyProvider {
+        fn fetch_key<'a>(&'a self, key_id: &'a str) -> BoxFuture<'a, Result<DataKey>> {
+            Box::pin(async move {
+                self.fetches.fetch_add(1, Ordering::SeqCst);
+                if self.down.load(Ordering::SeqCst) {
+                    anyhow::bail!("key service unavailable");
+                }
+                Ok(DataKey::new(key_id, [7; DATA_KEY_LEN]))
+            })
+        }
+    }
+
+    fn policy(ttl_ms: u64, refresh_ahead_ms: u64, max_stale_ms: u64) -> KeyCachePolicy {
+        KeyCachePolicy {
+            ttl: Duration::from_millis(ttl_ms),
+            refresh_ahead: Duration::from_millis(refresh_ahead_ms),
+            max_stale: Duration::from_millis(max_stale_ms),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_keys_are_cached_until_the_ttl() {
+        let inner = Arc::new(FlakyProvider::default());
+        let cache = CachedKeyProvider::new(inner.clone(), policy(40, 0, 0));
+
+        cache.key("k1").await.unwrap();
+        cache.key("k1").await.unwrap();
+        assert_eq!(inner.fetches.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        cache.key("k1").await.unwrap();
+        assert_eq!(inner.fetches.load(Ordering::SeqCst), 2);
+        assert_eq!((cache.stats().hits, cache.stats().misses), (1, 2));
+    }
+
+    #[tokio::test]
+    async fn test_stale_keys_cover_outages() {
+        let inner = Arc::new(FlakyProvider::default());
+        let cache = CachedKeyProvider::new(inner.clone(), policy(20, 0, 60_000));
+        cache.key("k1").await.unwrap();
+
+        inner.down.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(cache.key("k1").await.unwrap().bytes(), &[7; DATA_KEY_LEN]);
+        assert_eq!(cache.stats().stale_served, 1);
+
+        // Nothing cached to fall back on
+        assert!(cache.key("k2").await.is_err());
+        // And no fallback at all without max_stale
+        let strict = CachedKeyProvider::new(inner.clone(), policy(20, 0, 0));
+        inner.down.store(false, Ordering::SeqCst);
+        strict.key("k1").await.unwrap();
+        inner.down.store(true, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(strict.key("k1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_ahead_of_expiry() {
+        let inner = Arc::new(FlakyProvider::default());
+        let cache = CachedKeyProvider::new(inner.clone(), policy(10_000, 10_000, 0));
+
+        cache.key("k1").await.unwrap();
+        // Within refresh_ahead of expiry from the start: served, and refreshed behind the scenes
+        cache.key("k1").await.unwrap();
+        tokio::task::yield_now().await;
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(cache.stats().background_refreshes, 1);
+        assert_eq!(inner.fetches.load(Ordering::SeqCst), 2);
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_destroyed_keys_stay_gone() {
+        let inner = Arc::new(StaticKeyProvider::new());
+        inner.insert("k1", [3; DATA_KEY_LEN]);
+        let cache = CachedKeyProvider::new(inner.clone(), policy(60_000, 0, 60_000));
+        cache.key("k1").await.unwrap();
+
+        cache.destroy("k1").await.unwrap();
+        assert!(cache.key("k1").await.is_err());
+        assert!(inner.fetch_key("k1").await.is_err());
+        // Destroying needs a provider that can
+        assert!(CachedKeyProvider::new(Arc::new(FlakyProvider::default()), policy(1, 0, 0)).destroy("k1").await.is_err());
+    }
+
+    #[test]
+    fn test_debug_hides_key_material() {
+        let key = DataKey::new("k1", [42; DATA_KEY_LEN]);
+        assert_eq!(format!("{:?}", key), "DataKey { id: \"k1\", .. }");
+    }
+}
@@ -1,23 +1,61 @@
-/// KVStore: Key-Value Store Engine
-/// 
-/// This is the top-level database layer that provides ACID-compliant
-/// key-value operations. It orchestrates the BufferPool, WAL, and AzureDisk
-/// to provide a complete database system.
+//! KVStore: Key-Value Store Engine
+//! 
+//! This is the top-level database layer that provides ACID-compliant
+//! key-value operations. It orchestrates the BufferPool, WAL, and a PageStore
+//! (AzureDisk by default; see page_store.rs) to provide a complete
+//! database system.
 
 use anyhow::Result;
 use dashmap::DashMap;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tracing::{debug, info, warn};
+use std::time::{Duration, Instant};
+use tracing::{debug, info, info_span, warn, Instrument};
 
-use crate::buffer_pool::BufferPool;
-use crate::wal::{WalEntry, WAL};
-use crate::azure_disk::AzureDisk;
+use crate::auto_checkpoint::{AutoCheckpoint, AutoCheckpointStats};
+use crate::auto_key::AutoKeys;
+use crate::bootstrap;
+use crate::column_family;
+use crate::config::ConnectionConfig;
+use crate::events::{CheckpointEvent, FlushEvent, OpenEvent};
+use crate::buffer_pool::{BufferPool, CachePriority, DirtyPage};
+use crate::group_commit::GroupCommitStats;
+use crate::index::{key_hash, IndexEntry, IndexMode, IndexSnapshot, KeyIndex};
+use crate::key_stats::KeyStats;
+use crate::lag::LaggingConsumers;
+use crate::warm_cache::WarmCache;
+use crate::l2_cache::{L2Cache, L2CacheStats};
+use crate::log_store::{MemoryLog, WalBackend};
+use crate::metadata::{self, KeyMetadata};
+use crate::options::StoreOptions;
+use crate::page;
+use crate::page_store::PageStore;
+use crate::patch;
+use crate::quota::QuotaTracker;
+use crate::read_through::InFlightLoads;
+use crate::readback::{Readback, ReadbackStats};
+use crate::recovery::RecoveryReport;
+use crate::replay::{self, ReplayMarker, ReplayProgress};
+use crate::retry_budget;
+use crate::session::SessionToken;
+use crate::shadow::{self, Durability, ShadowPages};
+use crate::stalls::{StallCause, StallEvent, StallMonitor};
+use crate::storage_metrics::{storage_metrics, StorageMetrics};
+use crate::store_meta;
+use crate::superblock::{checksum, Superblock, FIRST_DATA_PAGE, SUPERBLOCK_PAGE};
+use crate::error::IronCladError;
+use crate::txn::{Isolation, Transaction};
+use crate::wal::{RecoveryLog, WalEntry, WAL};
 
 /// KVStore provides ACID-compliant key-value operations
+/// 
+/// Clones share the same store.
+#[derive(Clone)]
 pub struct KVStore {
-    /// In-memory index: Maps keys to page IDs
+    /// In-memory index: Maps keys (or key hashes) to page IDs
     /// Thread-safe using DashMap
-    index: Arc<DashMap<String, u64>>,
+    index: Arc<KeyIndex>,
     
     /// Buffer pool for caching pages
     buffer_pool: Arc<BufferPool>,
@@ -26,64 +64,604 @@ pub struct KVStore {
     wal: Arc<WAL>,
     
     /// Azure Disk (Page Blob) storage
-    disk: Arc<AzureDisk>,
+    disk: Arc<dyn PageStore>,
     
     /// Next available page ID
     next_page_id: Arc<parking_lot::RwLock<u64>>,
+    
+    /// Highest WAL LSN whose effects are visible in this instance
+    applied_lsn: Arc<AtomicU64>,
+    
+    /// Next transaction ID (unique within the current log)
+    next_txn_id: Arc<AtomicU64>,
+    
+    /// Store-local commit sequence, bumped on every applied write
+    commit_seq: Arc<AtomicU64>,
+    
+    /// Commit sequence of the last write to each key (deletes included),
+    /// by key hash: a collision can only cause a spurious write conflict
+    key_versions: Arc<DashMap<u128, u64>>,
+    
+    /// Serializes snapshot validation with the batch it validates
+    commit_lock: Arc<tokio::sync::Mutex<()>>,
+    
+    /// Superblock (page 0); the mutex serializes read-modify-write persists
+    superblock: Arc<tokio::sync::Mutex<Superblock>>,
+    
+    /// Cached superblock epoch, the current fencing token
+    epoch: Arc<AtomicU64>,
+    
+    /// Options the store was opened with
+    options: StoreOptions,
+    
+    /// Data pages written to the blob since the last backup set
+    /// (None = unknown, e.g. after a restart)
+    changed_pages: Arc<parking_lot::Mutex<Option<BTreeSet<u64>>>>,
+    
+    /// Local-disk page cache between the buffer pool and the data blob
+    l2_cache: Option<L2Cache>,
+    
+    /// Page runs (start, count) that held inline values before the last
+    /// checkpoint, free for the next one
+    free_inline_runs: Arc<parking_lot::Mutex<Vec<(u64, u64)>>>,
+    
+    /// Hash of each key's current value, by key hash (only with `dedup_writes`)
+    value_hashes: Arc<DashMap<u128, u64>>,
+    
+    /// Sets skipped because the value was unchanged
+    deduplicated_writes: Arc<AtomicU64>,
+    
+    /// Metadata of each key written since the restart, by key hash
+    key_metadata: Arc<DashMap<u128, KeyMetadata>>,
+    
+    /// Page IDs assigned ahead of a parallel replay segment, by key hash
+    reserved_page_ids: Arc<DashMap<u128, VecDeque<u64>>>,
+    
+    /// Pages freed by the page GC, handed out lowest first (see page_gc.rs)
+    free_pages: Arc<parking_lot::Mutex<BTreeSet<u64>>>,
+    
+    /// Held (shared) from allocating a key's page until the index points at
+    /// it, so the page GC never takes a new page for an orphan
+    page_allocation: Arc<parking_lot::RwLock<()>>,
+    
+    /// Serializes checkpoints and page collections, which both move pages
+    checkpoint_lock: Arc<tokio::sync::Mutex<()>>,
+    
+    /// Held (shared) from logging a write until it is applied; a checkpoint
+    /// holds it exclusively while it persists and clears the WAL, so the
+    /// cleared log never takes a write the pages don't show yet
+    log_gate: Arc<tokio::sync::RwLock<()>>,
+    
+    /// Installed root of a shadow-paged store (None = WAL durability)
+    shadow: Option<Arc<ShadowPages>>,
+    
+    /// Usage of the buckets in `options.quotas`
+    quotas: Arc<QuotaTracker>,
+    
+    /// Publishes stall events (see stalls.rs)
+    stalls: Arc<StallMonitor>,
+    
+    /// Verification reads of written pages (see readback.rs)
+    readback: Arc<Readback>,
+    
+    /// Read counter and choices of the auto checkpoint tuner
+    auto_checkpoint: Arc<AutoCheckpoint>,
+    
+    /// Per-prefix usage and the most active keys (see key_stats.rs)
+    key_stats: Arc<KeyStats>,
+    
+    /// Buffer pool snapshot for warm restarts (see warm_cache.rs)
+    warm_cache: Option<Arc<WarmCache>>,
+    
+    /// Snowflake and counter state of `insert_auto` (see auto_key.rs)
+    auto_keys: Arc<AutoKeys>,
+    
+    /// Keys `get_or_load` is loading (see read_through.rs)
+    in_flight_loads: Arc<InFlightLoads>,
+    
+    /// Change consumers over `StoreOptions::lag_alerts` (see lag.rs)
+    lagging_consumers: Arc<LaggingConsumers>,
 }
 
+/// Page blob holding the data pages, within the store's container
+pub(crate) const DATA_BLOB: &str = "db-data.vhd";
+
+/// Append blob holding the WAL, within the store's container
+pub(crate) const WAL_BLOB: &str = "db-wal";
+
+/// Entries replayed between progress reports (and split across replay workers at a time)
+const REPLAY_SEGMENT: usize = 1_000;
+
+/// Least time between replay progress log lines
+const REPLAY_LOG_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How often a session read re-checks the shared WAL while waiting to catch up
+const SESSION_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
 impl KVStore {
     /// Create a new KVStore instance
     pub async fn new(connection_string: &str) -> Result<Self> {
-        info!("Initializing KVStore");
+        Self::open(connection_string, StoreOptions::default()).await
+    }
+    
+    /// Create a KVStore instance with explicit options
+    pub async fn open(connection_string: &str, options: StoreOptions) -> Result<Self> {
+        Ok(Self::open_with_report(connection_string, options).await?.0)
+    }
+    
+    /// `open`, also returning what crash recovery did (see recovery.rs)
+    pub async fn open_with_report(connection_string: &str, options: StoreOptions) -> Result<(Self, RecoveryReport)> {
+        let listeners = options.listeners.clone();
+        let started = Instant::now();
+        let (store, report) = Self::open_store(connection_string, options).await.inspect_err(|e| listeners.error("open", e))?;
         
-        // Configuration
-        let container_name = "ironclad-db";
+        let event = OpenEvent {
+            container: store.options.container.clone(),
+            epoch: store.fencing_token(),
+            keys: store.index.len(),
+            duration: started.elapsed(),
+        };
+        listeners.emit(|listener| listener.on_open(&event));
+        Ok((store, report))
+    }
+    
+    async fn open_store(connection_string: &str, options: StoreOptions) -> Result<(Self, RecoveryReport)> {
+        info!(
+            "Initializing KVStore (container: {}, steal: {}, force: {})",
+            options.container, options.steal, options.force
+        );
         
-        let buffer_pool = Arc::new(BufferPool::new());
-        let wal = Arc::new(WAL::new(connection_string, container_name, "db-wal").await?);
-        let disk = Arc::new(AzureDisk::new(connection_string, container_name, "db-data.vhd").await?);
+        let buffer_pool = Arc::new(
+            BufferPool::new()
+                .with_steal(options.steal)
+                .with_hot_pinning(options.hot_pinning)
+                .with_listeners(options.listeners.clone()),
+        );
+        if let Some(budget) = options.retry_budget {
+            retry_budget::configure(&ConnectionConfig::parse(connection_string)?.account_name, budget);
+        }
+        let disk = options.page_backend.open(connection_string, &options, DATA_BLOB).await?;
         
+        // A shadow-paged store keeps its log in memory (see shadow.rs)
+        let recorded = Superblock::decode(&disk.read_page(SUPERBLOCK_PAGE).await?)?.map(|sb| sb.durability);
+        let durability = shadow::effective(recorded, options.durability);
+        if durability == Durability::ShadowPaging && options.index_mode != IndexMode::Full {
+            anyhow::bail!("Shadow paging needs the full index (IndexMode::Full)");
+        }
+        if durability == Durability::Wal && options.strict_durability && !options.wal_backend.is_persistent() {
+            let reason = "with strict durability, an in-memory WAL loses acknowledged writes at restart".to_string();
+            return Err(IronCladError::NotSupported { operation: "open", reason }.into());
+        }
+        let wal_backend = match durability {
+            Durability::Wal => options.wal_backend.clone(),
+            Durability::ShadowPaging => WalBackend::Memory(MemoryLog::new()),
+        };
+        let wal_lost = wal_backend.exists(connection_string, &options.container, WAL_BLOB).await? == Some(false);
+        let mut wal = WAL::open(connection_string, &options.container, WAL_BLOB, &wal_backend).await?;
+        let stalls = Arc::new(StallMonitor::new(options.stall_threshold));
+        wal = wal
+            .with_group_commit(options.group_commit)
+            .with_value_logging(options.value_logging)
+            .with_record_format(options.wal_record_format)
+            .with_runtime(options.runtime.clone())
+            .with_stall_monitor(stalls.clone());
+        if let Some(dir) = &options.wal_cache_dir {
+            wal = wal.with_tail_cache(dir);
+        }
+        let wal = Arc::new(wal);
+        
+        // Create the store, or finish an interrupted creation
+        let superblock = bootstrap::run(disk.as_ref(), &wal, durability, wal_lost, options.accept_lost_wal).await?;
+        info!("Superblock: format v{}, epoch {}", superblock.format_version, superblock.epoch);
+        
+        // Cached pages are only trusted if no other writer has opened the store since
+        let l2_cache = match &options.l2_cache_path {
+            Some(path) => Some(L2Cache::open(path, options.l2_cache_pages, superblock.epoch)?),
+            None => None,
+        };
+        
+        // Like the L2 cache, only valid if no other writer opened the store since
+        let warm_cache = options.warm_cache_dir.as_deref().map(|dir| Arc::new(WarmCache::new(dir)));
+        if let Some(warm_cache) = &warm_cache {
+            warm_cache.load(&buffer_pool, superblock.epoch);
+        }
+        
+        let quotas = Arc::new(QuotaTracker::new(&options.quotas));
+        let key_stats = Arc::new(KeyStats::new(&options.key_stats));
         let store = Self {
-            index: Arc::new(DashMap::new()),
+            index: Arc::new(KeyIndex::new(options.index_mode)),
             buffer_pool,
             wal,
             disk,
-            next_page_id: Arc::new(parking_lot::RwLock::new(0)),
+            next_page_id: Arc::new(parking_lot::RwLock::new(FIRST_DATA_PAGE)),
+            applied_lsn: Arc::new(AtomicU64::new(0)),
+            next_txn_id: Arc::new(AtomicU64::new(1)),
+            commit_seq: Arc::new(AtomicU64::new(0)),
+            key_versions: Arc::new(DashMap::new()),
+            commit_lock: Arc::new(tokio::sync::Mutex::new(())),
+            epoch: Arc::new(AtomicU64::new(superblock.epoch)),
+            superblock: Arc::new(tokio::sync::Mutex::new(superblock)),
+            options,
+            changed_pages: Arc::new(parking_lot::Mutex::new(None)),
+            l2_cache,
+            free_inline_runs: Arc::new(parking_lot::Mutex::new(Vec::new())),
+            value_hashes: Arc::new(DashMap::new()),
+            deduplicated_writes: Arc::new(AtomicU64::new(0)),
+            key_metadata: Arc::new(DashMap::new()),
+            reserved_page_ids: Arc::new(DashMap::new()),
+            free_pages: Arc::new(parking_lot::Mutex::new(BTreeSet::new())),
+            page_allocation: Arc::new(parking_lot::RwLock::new(())),
+            checkpoint_lock: Arc::new(tokio::sync::Mutex::new(())),
+            log_gate: Arc::new(tokio::sync::RwLock::new(())),
+            shadow: (durability == Durability::ShadowPaging).then(|| Arc::new(ShadowPages::default())),
+            quotas,
+            stalls,
+            readback: Arc::new(Readback::default()),
+            auto_checkpoint: Arc::new(AutoCheckpoint::default()),
+            key_stats,
+            warm_cache,
+            auto_keys: Arc::new(AutoKeys::default()),
+            in_flight_loads: Arc::new(InFlightLoads::default()),
+            lagging_consumers: Arc::new(LaggingConsumers::default()),
         };
         
+        // Fence out any previous writer
+        store.new_epoch().await?;
+        
         // Perform crash recovery
-        store.recover().await?;
+        let report = store.recover().await?;
+        store.register_column_families().await?;
+        store.key_stats.go_live();
+        
+        Ok((store, report))
+    }
+    
+    /// Apply `update` to the superblock and write it straight to page 0
+    /// 
+    /// The in-memory copy only changes once the write succeeded.
+    pub(crate) async fn update_superblock<F: FnOnce(&mut Superblock)>(&self, update: F) -> Result<Superblock> {
+        let mut superblock = self.superblock.lock().await;
+        
+        let mut updated = superblock.clone();
+        update(&mut updated);
         
-        Ok(store)
+        self.disk.write_page(SUPERBLOCK_PAGE, &updated.encode()?).await?;
+        self.disk.flush().await?;
+        
+        *superblock = updated.clone();
+        Ok(updated)
+    }
+    
+    /// Start a new writer epoch, persisted in the superblock
+    /// 
+    /// Called on open, and whenever this instance (re)gains leadership, e.g.
+    /// after acquiring a leader lock. Returns the new fencing token.
+    pub async fn new_epoch(&self) -> Result<u64> {
+        let superblock = self.update_superblock(|sb| sb.epoch += 1).await?;
+        self.epoch.store(superblock.epoch, Ordering::SeqCst);
+        if let Some(l2_cache) = &self.l2_cache {
+            l2_cache.set_epoch(superblock.epoch).await?;
+        }
+        
+        info!("New writer epoch {}", superblock.epoch);
+        Ok(superblock.epoch)
+    }
+    
+    /// Monotonically increasing token for the current writer epoch
+    /// 
+    /// Attach it to writes sent to external systems coordinating on this store;
+    /// they should reject any write carrying a lower token than one already seen,
+    /// which fences out a deposed leader that still thinks it's in charge.
+    pub fn fencing_token(&self) -> u64 {
+        self.epoch.load(Ordering::SeqCst)
     }
     
     /// Recover from crash by replaying WAL
-    async fn recover(&self) -> Result<()> {
+    async fn recover(&self) -> Result<RecoveryReport> {
+        let started = Instant::now();
+        // Nothing to replay: the installed root is the whole state
+        if self.shadow.is_some() {
+            self.load_shadow_root().await?;
+            return Ok(RecoveryReport { duration: started.elapsed(), ..Default::default() });
+        }
         info!("Starting crash recovery...");
         
-        let entries = self.wal.replay().await?;
-        let entry_count = entries.len();
-        
-        for entry in entries {
-            match entry {
-                WalEntry::Set { key, value } => {
-                    // Replay the set operation (without logging again)
-                    self.set_internal(&key, &value).await?;
-                    debug!("Recovered: SET {}={}", key, value);
-                },
-                WalEntry::Delete { key } => {
-                    // Replay the delete operation (without logging again)
-                    self.delete_internal(&key).await?;
-                    debug!("Recovered: DELETE {}", key);
-                },
-                WalEntry::Checkpoint { lsn } => {
-                    debug!("Recovered checkpoint at LSN {}", lsn);
-                },
+        // The index and inline values as of the last checkpoint; the WAL has
+        // everything since
+        let snapshot_lsn = self.load_index_snapshot().await?;
+        self.load_inline_values().await?;
+        
+        // Every record in the log, with its LSN so replayed pages carry
+        // accurate page and recovery LSNs
+        let mut log = self.wal.replay_for_recovery().await?;
+        // A crash before the checkpoint cleared the log leaves records the
+        // snapshot already reflects
+        log.entries.retain(|(lsn, _, _)| *lsn > snapshot_lsn);
+        let lsns = (log.last_lsn > 0).then_some(log.first_lsn..=log.last_lsn);
+        let torn_bytes = log.torn_bytes;
+        let span = info_span!("replay", entries = log.entries.len(), bytes = log.bytes);
+        let (entries_replayed, pages_repaired) = self.replay(log).instrument(span).await?;
+        
+        self.applied_lsn.store(self.wal.current_lsn(), Ordering::SeqCst);
+        
+        // The page GC's free list was only in memory: sweep for it again
+        if self.options.page_gc_on_checkpoint {
+            self.collect_pages_locked().await?;
+        }
+        
+        let report = RecoveryReport { entries_replayed, lsns, duration: started.elapsed(), torn_bytes, pages_repaired };
+        info!("Crash recovery complete: {}", report);
+        Ok(report)
+    }
+    
+    /// Apply the log, reporting progress and persisting replay markers (see
+    /// replay.rs); returns the entries replayed and the pages they rewrote
+    async fn replay(&self, log: RecoveryLog) -> Result<(u64, usize)> {
+        let marker = self.superblock.lock().await.replay_marker;
+        // Inlining depends on each value, which index-only entries don't build
+        let resume_lsn = match self.options.inline_threshold {
+            0 => ReplayMarker::resume_lsn(marker.as_ref(), log.first_lsn, log.last_lsn),
+            _ => 0,
+        };
+        if resume_lsn > 0 {
+            info!("Resuming recovery: pages already reflect the log up to LSN {}", resume_lsn);
+        }
+        
+        let entries_total = log.entries.len() as u64;
+        let bytes_at_start = log
+            .entries
+            .iter()
+            .take_while(|(lsn, _, _)| *lsn <= resume_lsn)
+            .last()
+            .map(|(_, end, _)| *end)
+            .unwrap_or(0);
+        // Parallel workers need page IDs assigned up front, which needs
+        // every written value to go to a page
+        let workers = match self.options.inline_threshold {
+            0 => self.options.replay_workers.max(1),
+            _ => 1,
+        };
+        let progress = |replayed: u64, end: u64, elapsed: Duration| {
+            ReplayProgress::new((replayed, entries_total), (end, log.bytes), bytes_at_start, resume_lsn, elapsed)
+        };
+        let started = Instant::now();
+        let mut last_logged = started;
+        let mut replayed = 0;
+        let mut since_marker = 0;
+        
+        let mut rewritten = HashSet::new();
+        let mut entries = log.entries.into_iter().peekable();
+        while let Some((lsn, _, entry)) = entries.next_if(|(lsn, _, _)| *lsn <= resume_lsn) {
+            self.index_entry(entry, lsn).await?;
+            replayed += 1;
+        }
+        if replayed > 0 {
+            self.report_replay_progress(&progress(replayed, bytes_at_start, started.elapsed()), &mut last_logged);
+        }
+        
+        loop {
+            let segment: Vec<(u64, u64, WalEntry)> = entries.by_ref().take(REPLAY_SEGMENT).collect();
+            let Some(&(last_lsn, end, _)) = segment.last() else {
+                break;
+            };
+            let count = segment.len() as u64;
+            rewritten.extend(segment.iter().filter_map(|(_, _, entry)| entry.key().map(str::to_string)));
+            self.apply_segment(segment.into_iter().map(|(lsn, _, entry)| (lsn, entry)).collect(), workers).await?;
+            replayed += count;
+            since_marker += count;
+            
+            if self.options.replay_marker_interval > 0 && since_marker >= self.options.replay_marker_interval {
+                self.persist_replay_marker(log.first_lsn, last_lsn).await?;
+                since_marker = 0;
+            }
+            self.report_replay_progress(&progress(replayed, end, started.elapsed()), &mut last_logged);
+        }
+        
+        // Keys deleted since, or kept inline, left no page to rewrite
+        let pages: HashSet<u64> = rewritten
+            .iter()
+            .filter_map(|key| match self.index.get(key) {
+                Some(IndexEntry::Page(page_id)) => Some(page_id),
+                _ => None,
+            })
+            .collect();
+        Ok((replayed, pages.len()))
+    }
+    
+    /// Apply a run of log entries, split by key hash across `workers` tasks
+    async fn apply_segment(&self, segment: Vec<(u64, WalEntry)>, workers: usize) -> Result<()> {
+        if workers == 1 {
+            for (lsn, entry) in segment {
+                self.apply_entry(entry, lsn).await?;
             }
+            return Ok(());
+        }
+        
+        self.reserve_replay_page_ids(&segment);
+        let tasks: Vec<_> = replay::partition_by_key(segment, workers)
+            .into_iter()
+            .filter(|partition| !partition.is_empty())
+            .map(|partition| {
+                let store = self.clone();
+                self.options.runtime.spawn_joinable(async move {
+                    for (lsn, entry) in partition {
+                        store.apply_entry(entry, lsn).await?;
+                    }
+                    Ok::<_, anyhow::Error>(())
+                })
+            })
+            .collect();
+        let results = futures::future::join_all(tasks).await;
+        self.reserved_page_ids.clear();
+        for result in results {
+            result?;
+        }
+        Ok(())
+    }
+    
+    /// Assign the pages the keys created in `segment` will get, in log
+    /// order, so parallel workers allocate exactly what a sequential replay
+    /// would (replay markers depend on it, see replay.rs)
+    fn reserve_replay_page_ids(&self, segment: &[(u64, WalEntry)]) {
+        let mut present: HashMap<&str, bool> = HashMap::new();
+        for (_, entry) in segment {
+            let (key, writes) = match entry {
+                WalEntry::Set { key, .. } => (key.as_str(), true),
+                WalEntry::Delete { key } => (key.as_str(), false),
+                _ => continue,
+            };
+            let was_present = *present.entry(key).or_insert_with(|| self.index.get(key).is_some());
+            if writes && !was_present {
+                let page_id = self.allocate_page_id();
+                self.reserved_page_ids.entry(key_hash(key)).or_default().push_back(page_id);
+            }
+            present.insert(key, writes);
+        }
+    }
+    
+    /// Report replay progress to the observer, and to the log every so often
+    fn report_replay_progress(&self, progress: &ReplayProgress, last_logged: &mut Instant) {
+        if let Some(observer) = &self.options.replay_progress {
+            observer.notify(progress);
+        }
+        self.options.listeners.emit(|listener| listener.on_recovery_progress(progress));
+        if last_logged.elapsed() >= REPLAY_LOG_INTERVAL || progress.entries_replayed == progress.entries_total {
+            info!(
+                "Recovery: {}/{} entries, {}/{} bytes, ETA {:?}",
+                progress.entries_replayed, progress.entries_total, progress.bytes_replayed, progress.bytes_total, progress.eta
+            );
+            *last_logged = Instant::now();
+        }
+    }
+    
+    /// Flush the pages replayed so far and record that in the superblock
+    async fn persist_replay_marker(&self, log_start_lsn: u64, lsn: u64) -> Result<()> {
+        self.flush().await?;
+        self.update_superblock(|sb| sb.replay_marker = Some(ReplayMarker { log_start_lsn, lsn })).await?;
+        debug!("Recovery: replay marker at LSN {}", lsn);
+        Ok(())
+    }
+    
+    /// Rebuild the index for an entry the data blob's pages already reflect
+    /// (see replay.rs); needs no values and does no page I/O
+    async fn index_entry(&self, entry: WalEntry, lsn: u64) -> Result<()> {
+        match entry {
+            WalEntry::Set { key, value, at_ms } => {
+                self.record_metadata(&key, lsn, at_ms);
+                self.index_page(&key);
+                self.record_value_hash(&key, &value);
+                self.quotas.record_set(&key, &value);
+                self.key_stats.record_set(&key, &value);
+            },
+            // Patching a missing key failed when first applied, too
+            WalEntry::Patch { key, .. } if self.index.get(&key).is_none() => {
+                debug!("Recovery: skipping patch of missing {} at LSN {}", key, lsn);
+            },
+            WalEntry::Patch { key, at_ms, .. } => {
+                self.record_metadata(&key, lsn, at_ms);
+                self.index_page(&key);
+                self.value_hashes.remove(&key_hash(&key));
+            },
+            other => self.apply_entry(other, lsn).await?,
+        }
+        Ok(())
+    }
+    
+    /// Index the page-backed keys persisted by the last checkpoint; returns
+    /// the LSN the snapshot reflects (0 without one)
+    async fn load_index_snapshot(&self) -> Result<u64> {
+        let Some(snapshot) = self.superblock.lock().await.index_snapshot else {
+            return Ok(0);
+        };
+        
+        // Don't hand out pages in use at the checkpoint
+        self.reserve_page_ids_below(snapshot.next_page_id.max(snapshot.start + snapshot.count));
+        
+        let mut loaded = 0;
+        for page_id in snapshot.start..snapshot.start + snapshot.count {
+            for (key, encoded) in page::decode_packed_page(&self.disk.read_page(page_id).await?)? {
+                self.index.restore_page(&key, &encoded)?;
+                loaded += 1;
+            }
+        }
+        
+        info!("Loaded {} index entries from pages {}..{} (LSN {})", loaded, snapshot.start, snapshot.start + snapshot.count, snapshot.lsn);
+        Ok(snapshot.lsn)
+    }
+    
+    /// Index the inline values persisted by the last checkpoint
+    async fn load_inline_values(&self) -> Result<()> {
+        let (start, count) = {
+            let superblock = self.superblock.lock().await;
+            (superblock.inline_start, superblock.inline_count)
+        };
+        if count == 0 {
+            return Ok(());
+        }
+        
+        // Don't hand these pages out to new keys
+        {
+            let mut next_id = self.next_page_id.write();
+            *next_id = (*next_id).max(start + count);
+        }
+        
+        let mut loaded = 0;
+        for page_id in start..start + count {
+            for (key, value) in page::decode_packed_page(&self.disk.read_page(page_id).await?)? {
+                // Goes to a page instead if the options no longer inline it
+                self.set_internal(&key, &value, 0, 0).await?;
+                loaded += 1;
+            }
+        }
+        
+        info!("Loaded {} inline values from pages {}..{}", loaded, start, start + count);
+        Ok(())
+    }
+    
+    /// Apply the entry logged at `lsn` to the index and buffer pool (without logging again)
+    async fn apply_entry(&self, entry: WalEntry, lsn: u64) -> Result<()> {
+        match entry {
+            WalEntry::Set { key, value, at_ms } => {
+                self.set_internal(&key, &value, lsn, at_ms).await?;
+                debug!("Recovered: SET {}={}", key, self.options.value_logging.show(&value));
+            },
+            WalEntry::Delete { key } => {
+                self.delete_internal(&key).await?;
+                debug!("Recovered: DELETE {}", key);
+            },
+            WalEntry::Patch { key, .. } if self.page_reflects(&key, lsn).await? => {
+                debug!("Recovered: {} already reflects LSN {}", key, lsn);
+            },
+            WalEntry::Patch { key, pointer, value, at_ms } => {
+                // Validated when logged; a missing base means its Set was lost
+                let current = self.get(&key).await?;
+                match patch::patched_value(&key, current.as_deref(), &pointer, value) {
+                    Ok(patched) => {
+                        self.set_internal(&key, &patched, lsn, at_ms).await?;
+                        debug!("Recovered: PATCH {} at {:?}", key, pointer);
+                    },
+                    Err(e) if self.options.strict_durability => {
+                        let reason = format!("with strict durability, patch of {} at LSN {} can't be reapplied: {}", key, lsn, e);
+                        return Err(IronCladError::NotSupported { operation: "recovery", reason }.into());
+                    },
+                    Err(e) => warn!("Recovery: skipping patch of {} at LSN {}: {}", key, lsn, e),
+                }
+            },
+            WalEntry::Checkpoint { lsn } => {
+                debug!("Recovered checkpoint at LSN {}", lsn);
+            },
+            WalEntry::Begin { txn_id } => {
+                // Never reuse an ID that is still in the log
+                self.next_txn_id.fetch_max(txn_id + 1, Ordering::SeqCst);
+            },
+            WalEntry::Commit { txn_id } => {
+                debug!("Recovered transaction {}", txn_id);
+            },
+            WalEntry::Unknown { record_type } => {
+                debug!("Recovery: skipped unknown {} record at LSN {}", record_type, lsn);
+            },
         }
         
-        info!("Crash recovery complete: recovered {} entries", entry_count);
         Ok(())
     }
     
@@ -94,94 +672,531 @@ impl KVStore {
     /// - Isolated: Uses thread-safe structures
     /// - Durable: Logged to WAL before returning
     pub async fn set(&self, key: &str, value: &str) -> Result<()> {
-        // 1. Log to WAL first (DURABILITY POINT)
-        self.wal.append_entry(WalEntry::Set {
+        if self.keeps_versions(key) {
+            return self.set_versioned(key, value).await;
+        }
+        self.validate(key, value)?;
+        let logged = value;
+        let value = &self.stored_value(key, value).await?;
+        
+        // Re-writing the current value changes nothing: skip the WAL and page
+        if self.is_current_value(key, value) {
+            self.deduplicated_writes.fetch_add(1, Ordering::Relaxed);
+            debug!("SET: {} unchanged, skipped", key);
+            return Ok(());
+        }
+        
+        // A value that can't be applied must not reach the log
+        page::encode_kv_page(key, value)?;
+        self.check_quotas(&[(key, Some(value))])?;
+        
+        // 1. Log to WAL first (DURABILITY POINT), making room for the page
+        //    while the append is in flight
+        let at_ms = metadata::now_ms();
+        let entry = WalEntry::Set {
             key: key.to_string(),
             value: value.to_string(),
-        }).await?;
+            at_ms,
+        };
+        let _logging = self.enter_log_gate("set").await;
+        let (lsn, prepared) = tokio::join!(self.wal.append_entry(entry), self.prepare_set(key, value));
+        let lsn = lsn?;
+        // A logged write must still be applied; the apply retries the write-back
+        if let Err(e) = prepared {
+            warn!("SET: preparing {} failed: {}", key, e);
+        }
         
-        // 2. Apply the change
-        self.set_internal(key, value).await?;
+        // 2. Apply the change (only now does the index show it)
+        self.apply_logged_set(key, value, lsn, at_ms).await?;
         
-        info!("SET: {}={}", key, value);
+        info!("SET: {}={}", key, self.options.value_logging.show(logged));
         Ok(())
     }
     
-    /// Internal set operation (used during recovery)
-    async fn set_internal(&self, key: &str, value: &str) -> Result<()> {
-        // Encode key-value as a page
-        let data = self.encode_kv_page(key, value)?;
+    /// Work a set can do before its WAL record is durable: free a frame for
+    /// a new page, writing back the evicted page
+    /// 
+    /// Touches nothing readers can see, so WAL-first still holds.
+    async fn prepare_set(&self, key: &str, value: &str) -> Result<()> {
+        if self.should_inline(key, value) {
+            return Ok(());
+        }
+        if let Some(IndexEntry::Page(page_id)) = self.index.get(key) {
+            if self.buffer_pool.is_resident(page_id) {
+                return Ok(());
+            }
+        }
+        self.buffer_pool.reserve_frame()?;
+        self.write_back_evicted("set").await
+    }
+    
+    /// Apply a value already logged at `lsn` (step 2 of a single write)
+    pub(crate) async fn apply_logged_set(&self, key: &str, value: &str, lsn: u64, at_ms: u64) -> Result<()> {
+        self.set_internal(key, value, lsn, at_ms).await?;
+        self.applied_lsn.fetch_max(lsn, Ordering::SeqCst);
         
-        // Get or allocate page ID for this key
-        let page_id = if let Some(entry) = self.index.get(key) {
-            *entry.value()
-        } else {
-            let mut next_id = self.next_page_id.write();
-            let page_id = *next_id;
-            *next_id += 1;
-            page_id
-        };
+        if self.options.force {
+            self.force_pages([key]).await?;
+        }
+        self.install_shadow_root().await?;
+        Ok(())
+    }
+    
+    /// Internal set operation (used during recovery); `lsn` is the WAL record
+    /// being applied and `at_ms` the time it records
+    async fn set_internal(&self, key: &str, value: &str, lsn: u64, at_ms: u64) -> Result<()> {
+        let metadata = self.record_metadata(key, lsn, at_ms);
         
-        // Update buffer pool
-        self.buffer_pool.put_page(page_id, data)?;
+        // Small values live in the index itself: no page, no page I/O
+        self.quotas.record_set(key, value);
+        self.key_stats.record_set(key, value);
+        if self.should_inline(key, value) {
+            self.index.insert(key, IndexEntry::Inline(value.to_string()));
+            self.bump_version(key);
+            self.record_value_hash(key, value);
+            return Ok(());
+        }
         
-        // Update index
-        self.index.insert(key.to_string(), page_id);
+        // Encode key-value as a page, with its metadata after the value
+        let data = page::encode_kv_page_with_trailer(key, value, &metadata.encode())?;
         
-        Ok(())
+        {
+            let _allocation = self.page_allocation.read();
+            let page_id = self.page_id_for(key);
+            
+            // Update buffer pool
+            if let Some(group) = column_family::cache_group(key) {
+                self.buffer_pool.tag_page(page_id, group);
+            }
+            self.buffer_pool.put_page_at(page_id, data, lsn)?;
+            
+            // Update index
+            self.index.insert(key, IndexEntry::Page(page_id));
+        }
+        self.bump_version(key);
+        self.record_value_hash(key, value);
+        
+        // Making room may have evicted a dirty page
+        self.write_back_evicted("write").await
+    }
+    
+    /// `key`'s page ID, allocating one (or taking the one reserved for a
+    /// parallel replay) if it has none
+    fn page_id_for(&self, key: &str) -> u64 {
+        if let Some(IndexEntry::Page(page_id)) = self.index.get(key) {
+            // Copy on write: the installed shadow root must stay intact
+            if !self.shadow.as_ref().is_some_and(|shadow| shadow.is_installed(page_id)) {
+                return page_id;
+            }
+        }
+        let reserved = self.reserved_page_ids.get_mut(&key_hash(key)).and_then(|mut ids| ids.pop_front());
+        reserved.unwrap_or_else(|| self.allocate_page_id())
+    }
+    
+    fn allocate_page_id(&self) -> u64 {
+        if let Some(page_id) = self.free_pages.lock().pop_first() {
+            return page_id;
+        }
+        let mut next_id = self.next_page_id.write();
+        let page_id = *next_id;
+        *next_id += 1;
+        page_id
+    }
+    
+    /// Point `key` at its page without building the page
+    fn index_page(&self, key: &str) {
+        let _allocation = self.page_allocation.read();
+        let page_id = self.page_id_for(key);
+        self.index.insert(key, IndexEntry::Page(page_id));
+        self.bump_version(key);
+    }
+    
+    /// Does `key`'s page already hold the write logged at `lsn`? (per the
+    /// last-write LSN in its metadata trailer)
+    /// 
+    /// Lets recovery skip patches a page written back ahead of
+    /// the replay already reflects, instead of applying them twice.
+    async fn page_reflects(&self, key: &str, lsn: u64) -> Result<bool> {
+        let Some(IndexEntry::Page(page_id)) = self.index.get(key) else {
+            return Ok(false);
+        };
+        let Some(data) = self.load_page(page_id, CachePriority::Normal).await? else {
+            return Ok(false);
+        };
+        Ok(KeyMetadata::decode(page::page_trailer(&data)?).is_some_and(|metadata| metadata.last_lsn >= lsn))
+    }
+    
+    /// Update `key`'s metadata for a write; returns the new metadata
+    fn record_metadata(&self, key: &str, lsn: u64, at_ms: u64) -> KeyMetadata {
+        let hash = key_hash(key);
+        let previous = self.key_metadata.get(&hash).map(|metadata| *metadata.value());
+        let metadata = KeyMetadata::after_write(previous.as_ref(), metadata::write_time(at_ms), lsn);
+        self.key_metadata.insert(hash, metadata);
+        metadata
+    }
+    
+    /// Does `key` already hold `value`? (always false without `dedup_writes`)
+    /// 
+    /// Compares 64-bit value hashes, so a hash collision would drop a real
+    /// change; the odds are about 2^-64 per write.
+    fn is_current_value(&self, key: &str, value: &str) -> bool {
+        self.options.dedup_writes
+            && self.value_hashes.get(&key_hash(key)).map(|hash| *hash.value()) == Some(checksum(value.as_bytes()))
+    }
+    
+    fn record_value_hash(&self, key: &str, value: &str) {
+        if self.options.dedup_writes {
+            self.value_hashes.insert(key_hash(key), checksum(value.as_bytes()));
+        }
+    }
+    
+    /// Is `value` small enough to keep in the index?
+    fn should_inline(&self, key: &str, value: &str) -> bool {
+        value.len() < self.options.inline_threshold && self.index.can_inline(key) && page::fits_packed(key, value)
     }
     
     /// Get a value by key
     pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        self.get_with_priority(key, CachePriority::Normal).await
+    }
+    
+    /// Get a value, caching its page (on a miss) according to `priority`
+    pub(crate) async fn get_with_priority(&self, key: &str, priority: CachePriority) -> Result<Option<String>> {
+        self.auto_checkpoint.note_read();
+        self.key_stats.record_read(key);
         // Lookup page ID in index
         let page_id = match self.index.get(key) {
-            Some(entry) => *entry.value(),
+            Some(IndexEntry::Page(page_id)) => page_id,
+            Some(IndexEntry::Inline(value)) => {
+                debug!("GET: {} served inline", key);
+                return self.read_value(key, value).await.map(Some);
+            }
             None => {
                 debug!("GET: {} not found", key);
                 return Ok(None);
             }
         };
         
+        // Pages only read from disk since the restart haven't been tagged yet
+        if let Some(group) = column_family::cache_group(key) {
+            self.buffer_pool.tag_page(page_id, group);
+        }
+        
+        let Some(data) = self.load_page(page_id, priority).await? else {
+            return Ok(None);
+        };
+        
+        // Decode the page
+        let (page_key, value) = page::decode_kv_page(&data)?;
+        
+        // A hashed index entry may belong to a different key
+        if page_key != key {
+            warn!("GET: page {} holds {}, not {} (key hash collision)", page_id, page_key, key);
+            return Ok(None);
+        }
+        
+        let value = self.read_value(key, value).await?;
+        info!("GET: {}={}", key, self.options.value_logging.show(&value));
+        Ok(Some(value))
+    }
+    
+    /// A page's contents from the buffer pool, else the L2 cache or disk
+    /// (None if the read failed)
+    pub(crate) async fn load_page(&self, page_id: u64, priority: CachePriority) -> Result<Option<Vec<u8>>> {
         // Try to get from buffer pool
         let data = match self.buffer_pool.get_page(page_id) {
             Some(data) => data,
             None => {
-                // Not in cache, fetch from AzureDisk
-                debug!("GET: {} not in cache, fetching from disk page {}", key, page_id);
-                match self.disk.read_page(page_id).await {
+                // Not in cache, fetch from the L2 cache or AzureDisk
+                debug!("GET: page {} not in cache, fetching from disk", page_id);
+                match self.read_page(page_id).await {
                     Ok(data) => {
-                        // Put into buffer pool for future access
-                        // Note: put_page might fail if cache is full and everything is pinned, but rare here
-                         match self.buffer_pool.put_page(page_id, data.clone()) {
-                             Ok(_) => debug!("Page {} loaded into cache", page_id),
+                        // Offer to the buffer pool for future access (the admission
+                        // filter may decline one-off reads)
+                        // Note: admit_page might fail if cache is full and everything is pinned, but rare here
+                         match self.buffer_pool.admit_page_with(page_id, data.clone(), priority) {
+                             Ok(true) => {
+                                 debug!("Page {} loaded into cache", page_id);
+                                 self.write_back_evicted("read").await?;
+                             },
+                             Ok(false) => debug!("Page {} not admitted to cache", page_id),
                              Err(e) => warn!("Failed to cache page {}: {}", page_id, e),
                          }
                         data
                     },
+                    Err(e) if self.options.strict_durability => {
+                        // Reporting the key missing would hide that it exists
+                        let reason = format!("with strict durability, page {} can't be read: {:#}", page_id, e);
+                        return Err(IronCladError::NotSupported { operation: "read", reason }.into());
+                    }
                     Err(e) => {
                         warn!("Failed to read page {} from disk: {}", page_id, e);
-                        return Ok(None); 
+                        return Ok(None);
                     }
                 }
             }
         };
         
-        // Decode the page
-        let value = self.decode_kv_page(&data)?;
+        Ok(Some(data))
+    }
+    
+    /// Start a read-committed transaction whose writes are committed atomically
+    pub fn begin(&self) -> Transaction<'_> {
+        self.begin_with(Isolation::ReadCommitted)
+    }
+    
+    /// Start a transaction at the given isolation level
+    pub fn begin_with(&self, isolation: Isolation) -> Transaction<'_> {
+        let txn_id = self.new_txn_id();
+        let snapshot_seq = self.commit_seq.load(Ordering::SeqCst);
+        debug!("BEGIN: transaction {} ({:?} at seq {})", txn_id, isolation, snapshot_seq);
+        Transaction::new(self, txn_id, isolation, snapshot_seq)
+    }
+    
+    /// ID for a new WAL batch
+    pub(crate) fn new_txn_id(&self) -> u64 {
+        self.next_txn_id.fetch_add(1, Ordering::SeqCst)
+    }
+    
+    /// Commit sequence of the last write to `key` (0 if never written)
+    pub(crate) fn key_version(&self, key: &str) -> u64 {
+        self.key_versions.get(&key_hash(key)).map(|v| *v.value()).unwrap_or(0)
+    }
+    
+    /// Record a write to `key` at the next commit sequence
+    fn bump_version(&self, key: &str) {
+        let seq = self.commit_seq.fetch_add(1, Ordering::SeqCst) + 1;
+        self.key_versions.insert(key_hash(key), seq);
+    }
+    
+    /// Log a transaction's writes as one WAL batch, then apply them
+    /// 
+    /// With `snapshot_seq` set (snapshot isolation), the commit fails with
+    /// `WriteConflict` if any written key changed after the snapshot
+    /// (first committer wins). Returns the LSN of the batch's Commit marker.
+    pub(crate) async fn commit_batch(
+        &self,
+        txn_id: u64,
+        writes: Vec<WalEntry>,
+        snapshot_seq: Option<u64>,
+    ) -> Result<u64> {
+        let _commit_guard = self.commit_lock.lock().await;
+        self.commit_batch_locked(txn_id, writes, snapshot_seq).await
+    }
+    
+    /// Hold off other batch commits (and conditional writes)
+    pub(crate) async fn lock_commits(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.commit_lock.lock().await
+    }
+    
+    /// `commit_batch` for a caller already holding `lock_commits`
+    pub(crate) async fn commit_batch_locked(
+        &self,
+        txn_id: u64,
+        writes: Vec<WalEntry>,
+        snapshot_seq: Option<u64>,
+    ) -> Result<u64> {
+        for write in &writes {
+            if let WalEntry::Set { key, value, .. } = write {
+                self.validate(key, value)?;
+            }
+        }
+        let writes = self.transform_writes(writes).await?;
+        self.commit_stored_locked(txn_id, writes, snapshot_seq).await
+    }
+    
+    /// `commit_batch_locked` for writes already in their stored form
+    pub(crate) async fn commit_stored_locked(
+        &self,
+        txn_id: u64,
+        writes: Vec<WalEntry>,
+        snapshot_seq: Option<u64>,
+    ) -> Result<u64> {
+        if let Some(snapshot_seq) = snapshot_seq {
+            for write in &writes {
+                let key = match write {
+                    WalEntry::Set { key, .. } | WalEntry::Delete { key } => key,
+                    _ => continue,
+                };
+                if self.key_version(key) > snapshot_seq {
+                    debug!("COMMIT: transaction {} conflicts on {}", txn_id, key);
+                    return Err(IronCladError::WriteConflict { key: key.clone() }.into());
+                }
+            }
+        }
         
-        info!("GET: {}={}", key, value);
-        Ok(Some(value))
+        // Reject values that can't be applied before anything is logged,
+        // otherwise recovery would replay a batch we failed to apply
+        for write in &writes {
+            if let WalEntry::Set { key, value, .. } = write {
+                page::encode_kv_page(key, value)?;
+            }
+        }
+        self.check_batch_quotas(&writes)?;
+        
+        // 1. Log the whole batch first (DURABILITY POINT)
+        let _logging = self.enter_log_gate("commit").await;
+        let (first_lsn, last_lsn) = self.wal.append_batch(txn_id, &writes).await?;
+        
+        // 2. Apply the changes
+        // Entries sit between the Begin marker (first_lsn) and the Commit marker
+        let write_count = writes.len();
+        let keys: Vec<String> = writes.iter().filter_map(|write| write.key().map(str::to_string)).collect();
+        for (i, write) in writes.into_iter().enumerate() {
+            self.apply_entry(write, first_lsn + 1 + i as u64).await?;
+        }
+        self.applied_lsn.fetch_max(last_lsn, Ordering::SeqCst);
+        
+        if self.options.force {
+            self.force_pages(keys.iter().map(String::as_str)).await?;
+        }
+        self.install_shadow_root().await?;
+        
+        info!("COMMIT: transaction {} ({} writes, LSN {}..={})", txn_id, write_count, first_lsn, last_lsn);
+        Ok(last_lsn)
+    }
+    
+    /// A batch's writes as they should be stored
+    pub(crate) async fn transform_writes(&self, writes: Vec<WalEntry>) -> Result<Vec<WalEntry>> {
+        if self.options.middleware.is_empty() && self.options.encryption.is_none() {
+            return Ok(writes);
+        }
+        let mut transformed = Vec::with_capacity(writes.len());
+        for write in writes {
+            transformed.push(match write {
+                WalEntry::Set { key, value, at_ms } => {
+                    let value = self.stored_value(&key, &value).await?;
+                    WalEntry::Set { key, value, at_ms }
+                }
+                other => other,
+            });
+        }
+        Ok(transformed)
+    }
+    
+    /// `value` as stored under `key`: through the middleware (see
+    /// middleware.rs), then encrypted (see encryption.rs)
+    pub(crate) async fn stored_value(&self, key: &str, value: &str) -> Result<String> {
+        let value = self.options.middleware.on_write(key, value)?;
+        self.encrypt_value(key, value).await
+    }
+    
+    /// A value stored under `key` as returned to readers
+    pub(crate) async fn read_value(&self, key: &str, stored: String) -> Result<String> {
+        let value = self.decrypt_value(key, stored).await?;
+        self.options.middleware.on_read(key, value)
+    }
+    
+    /// Are `key`'s values stored transformed, so patches can't
+    /// be applied to the stored value?
+    pub(crate) fn transforms_values(&self, key: &str) -> bool {
+        self.options.middleware.covers(key) || self.encrypts(key)
+    }
+    
+    /// Session token covering every write applied by this instance so far
+    /// 
+    /// Hand this to the client after a write; reads routed to another instance
+    /// pass it back to `get_with_session` to get read-your-writes consistency.
+    pub fn session_token(&self) -> SessionToken {
+        SessionToken::new(self.applied_lsn.load(Ordering::SeqCst))
+    }
+    
+    /// Apply WAL entries written by other instances since our last applied LSN
+    /// Returns the number of entries applied
+    /// 
+    /// Only the log bytes appended since the last call are fetched (see
+    /// `WAL::read_since`).
+    pub async fn catch_up(&self) -> Result<usize> {
+        let applied = self.applied_lsn.load(Ordering::SeqCst);
+        let entries = self.wal.read_since(applied).await?;
+        let count = entries.len();
+        
+        for (lsn, entry) in entries {
+            self.apply_entry(entry, lsn).await?;
+            self.applied_lsn.fetch_max(lsn, Ordering::SeqCst);
+        }
+        
+        if count > 0 {
+            debug!("Caught up {} entries (now at LSN {})", count, self.applied_lsn.load(Ordering::SeqCst));
+        }
+        Ok(count)
     }
     
-    /// Delete a key
+    /// Wait until this instance has applied the session's last write
+    /// 
+    /// Fails once `timeout` elapses; the caller should then proxy the read
+    /// to the primary instead.
+    pub async fn wait_for_session(&self, token: &SessionToken, timeout: Duration) -> Result<()> {
+        let start = Instant::now();
+        
+        loop {
+            if token.is_satisfied_by(self.applied_lsn.load(Ordering::SeqCst)) {
+                return Ok(());
+            }
+            
+            self.catch_up().await?;
+            
+            let applied = self.applied_lsn.load(Ordering::SeqCst);
+            if token.is_satisfied_by(applied) {
+                return Ok(());
+            }
+            
+            if start.elapsed() >= timeout {
+                anyhow::bail!(
+                    "Session not caught up: applied LSN {} < session LSN {} (proxy the read to the primary)",
+                    applied, token.lsn
+                );
+            }
+            
+            self.options.runtime.sleep(SESSION_POLL_INTERVAL).await;
+        }
+    }
+    
+    /// Get a value by key with read-your-writes consistency for the session
+    pub async fn get_with_session(
+        &self,
+        key: &str,
+        token: &SessionToken,
+        timeout: Duration,
+    ) -> Result<Option<String>> {
+        self.wait_for_session(token, timeout).await?;
+        self.get(key).await
+    }
+    
+    /// Delete a key (moving it to the trash if `trash_retention` is set)
+    /// 
+    /// `__meta/` keys can't be deleted this way (see store_meta.rs).
     pub async fn delete(&self, key: &str) -> Result<bool> {
+        if store_meta::is_meta(key) {
+            return Err(IronCladError::NotSupported {
+                operation: "delete",
+                reason: format!("{} is store metadata; use delete_meta", key),
+            }
+            .into());
+        }
+        self.delete_unchecked(key).await
+    }
+    
+    /// Delete a key, `__meta/` keys included
+    pub(crate) async fn delete_unchecked(&self, key: &str) -> Result<bool> {
+        // With a trash retention set, user keys go to the trash instead
+        if self.trashes(key) {
+            return self.move_to_trash(key).await;
+        }
+        if self.keeps_versions(key) {
+            return self.delete_versioned(key).await;
+        }
+        
         // 1. Log to WAL first (DURABILITY POINT)
-        self.wal.append_entry(WalEntry::Delete {
+        let _logging = self.enter_log_gate("delete").await;
+        let lsn = self.wal.append_entry(WalEntry::Delete {
             key: key.to_string(),
         }).await?;
         
         // 2. Apply the change
         let deleted = self.delete_internal(key).await?;
+        self.applied_lsn.fetch_max(lsn, Ordering::SeqCst);
+        self.install_shadow_root().await?;
         
         if deleted {
             info!("DELETE: {}", key);
@@ -192,19 +1207,259 @@ impl KVStore {
         Ok(deleted)
     }
     
+    /// Set `key` only if it doesn't exist yet
+    /// Returns whether the value was written
+    pub async fn set_nx(&self, key: &str, value: &str) -> Result<bool> {
+        self.compare_and_set(key, None, value).await
+    }
+    
+    /// Set `key` only if its current value is `expected` (None = absent)
+    /// 
+    /// Atomic with respect to other conditional writes and transaction
+    /// commits on this instance; plain `set`/`delete` calls don't take part.
+    /// Returns whether the value was written.
+    pub async fn compare_and_set(&self, key: &str, expected: Option<&str>, value: &str) -> Result<bool> {
+        let _commit_guard = self.commit_lock.lock().await;
+        
+        if self.get(key).await?.as_deref() != expected {
+            debug!("CAS: {} did not match expected value", key);
+            return Ok(false);
+        }
+        
+        self.set(key, value).await?;
+        Ok(true)
+    }
+    
+    /// Delete `key` only if its current value is `expected`
+    /// Returns whether the key was deleted
+    pub async fn compare_and_delete(&self, key: &str, expected: &str) -> Result<bool> {
+        let _commit_guard = self.commit_lock.lock().await;
+        
+        if self.get(key).await?.as_deref() != Some(expected) {
+            debug!("CAS: {} did not match expected value", key);
+            return Ok(false);
+        }
+        
+        // Already holding the commit lock the trash move takes
+        if self.trashes(key) {
+            return self.move_to_trash_locked(key).await;
+        }
+        self.delete(key).await
+    }
+    
     /// Internal delete operation (used during recovery)
     async fn delete_internal(&self, key: &str) -> Result<bool> {
-        let removed = self.index.remove(key).is_some();
+        let removed = self.index.remove(key);
+        self.bump_version(key);
+        self.quotas.record_delete(key);
+        self.key_stats.record_delete(key);
+        self.value_hashes.remove(&key_hash(key));
+        self.key_metadata.remove(&key_hash(key));
         Ok(removed)
     }
     
+    /// The store's superblock
+    pub(crate) fn superblock(&self) -> &tokio::sync::Mutex<Superblock> {
+        &self.superblock
+    }
+    
+    /// Shadow paging state (None = WAL durability)
+    pub(crate) fn shadow(&self) -> Option<&ShadowPages> {
+        self.shadow.as_deref()
+    }
+    
+    /// Store-local commit sequence so far
+    pub(crate) fn commit_seq(&self) -> u64 {
+        self.commit_seq.load(Ordering::SeqCst)
+    }
+    
+    /// Hold off checkpoints and page collections
+    pub(crate) async fn lock_checkpoints(&self) -> tokio::sync::MutexGuard<'_, ()> {
+        self.checkpoint_lock.lock().await
+    }
+    
+    /// Hold off a checkpoint's WAL reset from logging a write until it is
+    /// applied (see `log_gate`)
+    pub(crate) async fn enter_log_gate(&self, operation: &'static str) -> tokio::sync::RwLockReadGuard<'_, ()> {
+        if let Ok(guard) = self.log_gate.try_read() {
+            return guard;
+        }
+        let started = Instant::now();
+        let guard = self.log_gate.read().await;
+        self.stalls.report(StallCause::CheckpointLogReset, operation, started.elapsed(), 1);
+        guard
+    }
+    
+    /// Held (shared) while a key's page is allocated and indexed
+    pub(crate) fn page_allocation(&self) -> &parking_lot::RwLock<()> {
+        &self.page_allocation
+    }
+    
+    /// Pages the page GC freed
+    pub(crate) fn free_pages(&self) -> &parking_lot::Mutex<BTreeSet<u64>> {
+        &self.free_pages
+    }
+    
+    /// Pages in use that no index entry points at: the inline runs, the
+    /// index snapshot and the pages reserved for a replay segment
+    pub(crate) async fn pages_outside_index(&self) -> Vec<u64> {
+        let (start, count, snapshot) = {
+            let superblock = self.superblock.lock().await;
+            (superblock.inline_start, superblock.inline_count, superblock.index_snapshot)
+        };
+        let mut pages: Vec<u64> = (start..start + count).collect();
+        if let Some(snapshot) = snapshot {
+            pages.extend(snapshot.start..snapshot.start + snapshot.count);
+        }
+        for (start, count) in self.free_inline_runs.lock().iter() {
+            pages.extend(*start..start + count);
+        }
+        for ids in self.reserved_page_ids.iter() {
+            pages.extend(ids.value().iter().copied());
+        }
+        if let Some(shadow) = &self.shadow {
+            pages.extend(shadow.installed());
+            pages.extend(self.shadow_table_pages().await);
+        }
+        pages
+    }
+    
+    /// Forget how far the last recovery got, so the next one starts over
+    pub(crate) async fn clear_replay_marker(&self) -> Result<()> {
+        if self.superblock.lock().await.replay_marker.is_some() {
+            self.update_superblock(|sb| sb.replay_marker = None).await?;
+        }
+        Ok(())
+    }
+    
+    /// The store's key index
+    pub(crate) fn index(&self) -> &KeyIndex {
+        &self.index
+    }
+    
+    /// Metadata of `key` tracked since the restart
+    pub(crate) fn cached_metadata(&self, key: &str) -> Option<KeyMetadata> {
+        self.key_metadata.get(&key_hash(key)).map(|metadata| *metadata.value())
+    }
+    
+    /// Receive an event for each stall at least `stall_threshold` long
+    /// (see stalls.rs)
+    pub fn subscribe_stalls(&self) -> tokio::sync::broadcast::Receiver<StallEvent> {
+        self.stalls.subscribe()
+    }
+    
+    /// Usage of the store's buckets
+    pub(crate) fn quotas(&self) -> &QuotaTracker {
+        &self.quotas
+    }
+    
+    /// The store's buffer pool
+    pub(crate) fn buffer_pool(&self) -> &BufferPool {
+        &self.buffer_pool
+    }
+    
+    /// Auto checkpoint state (see auto_checkpoint.rs)
+    pub(crate) fn auto_checkpoint(&self) -> &AutoCheckpoint {
+        &self.auto_checkpoint
+    }
+    
+    /// Verification read state
+    pub(crate) fn readback(&self) -> &Readback {
+        &self.readback
+    }
+    
+    /// Per-prefix usage and the most active keys
+    pub(crate) fn key_stats(&self) -> &KeyStats {
+        &self.key_stats
+    }
+    
+    /// Buffer pool snapshot directory, if configured
+    pub(crate) fn warm_cache(&self) -> Option<&WarmCache> {
+        self.warm_cache.as_deref()
+    }
+    
+    /// Generator state of `insert_auto`
+    pub(crate) fn auto_keys(&self) -> &AutoKeys {
+        &self.auto_keys
+    }
+    
+    /// Keys `get_or_load` is loading
+    pub(crate) fn in_flight_loads(&self) -> &InFlightLoads {
+        &self.in_flight_loads
+    }
+    
+    /// Change consumers over their lag thresholds (see lag.rs)
+    pub(crate) fn lagging_consumers(&self) -> &LaggingConsumers {
+        &self.lagging_consumers
+    }
+    
+    /// The store's data pages
+    pub(crate) fn disk(&self) -> &dyn PageStore {
+        self.disk.as_ref()
+    }
+    
+    /// The store's write-ahead log
+    pub(crate) fn wal(&self) -> &WAL {
+        &self.wal
+    }
+    
+    /// Options the store was opened with
+    pub fn options(&self) -> &StoreOptions {
+        &self.options
+    }
+    
+    /// One past the highest data page allocated so far
+    pub(crate) fn next_page_id(&self) -> u64 {
+        *self.next_page_id.read()
+    }
+    
+    /// Are the pages changed since the last backup set known?
+    pub(crate) fn changed_pages_known(&self) -> bool {
+        self.changed_pages.lock().is_some()
+    }
+    
+    /// Take the changed pages and start tracking afresh
+    pub(crate) fn take_changed_pages(&self) -> Option<BTreeSet<u64>> {
+        self.changed_pages.lock().replace(BTreeSet::new())
+    }
+    
+    /// Put back pages taken by a backup set that failed
+    pub(crate) fn restore_changed_pages(&self, pages: BTreeSet<u64>) {
+        if let Some(changed) = self.changed_pages.lock().as_mut() {
+            changed.extend(pages);
+        }
+    }
+    
+    /// Keys starting with `prefix`, collected up front so no index lock is
+    /// held while the caller awaits
+    /// 
+    /// With a hashed index only reserved (`__`) keys can be listed this way;
+    /// see `all_keys`.
+    pub(crate) fn keys_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.index.keys_with_prefix(prefix)
+    }
+    
+    /// Every key in the store; with a hashed index this reads every page
+    pub(crate) async fn all_keys(&self) -> Result<Vec<String>> {
+        let mut keys = self.index.keys_with_prefix("");
+        for page_id in self.index.hashed_pages() {
+            if let Some(data) = self.load_page(page_id, CachePriority::Low).await? {
+                keys.push(page::decode_kv_page(&data)?.0);
+            }
+        }
+        Ok(keys)
+    }
+    
     /// Scan all entries
-    /// Returns all key-value pairs currently in the store
+    /// Returns all key-value pairs currently in the store, except store
+    /// metadata (`__meta/`)
     pub async fn scan(&self) -> Result<Vec<(String, String)>> {
         let mut results = Vec::new();
         
-        for entry in self.index.iter() {
-            let key = entry.key().clone();
+        for key in self.all_keys().await? {
+            if store_meta::is_meta(&key) {
+                continue;
+            }
             if let Ok(Some(value)) = self.get(&key).await {
                 results.push((key, value));
             }
@@ -216,110 +1471,320 @@ impl KVStore {
     
     /// Flush all dirty pages to disk
     pub async fn flush(&self) -> Result<()> {
-        let dirty_pages = self.buffer_pool.get_dirty_pages();
-        
-        if !dirty_pages.is_empty() {
-            info!("Flushing {} dirty pages to AzureDisk", dirty_pages.len());
-            
-            for (page_id, data) in dirty_pages {
-                // Write to Azure Page Blob
-                self.disk.write_page(page_id, &data).await?;
-                
-                // Mark clean in buffer pool
-                self.buffer_pool.clear_dirty(page_id)?;
+        self.flush_up_to(u64::MAX).await?;
+        Ok(())
+    }
+    
+    /// Flush only the pages holding changes logged at or before `lsn`
+    /// 
+    /// Lets a checkpoint bound recovery time (see `recovery_lsn`) without
+    /// writing the whole dirty set at once. Returns the number of pages written.
+    pub async fn flush_up_to(&self, lsn: u64) -> Result<usize> {
+        let started = Instant::now();
+        let result = self.write_dirty_pages(lsn).await;
+        match &result {
+            Ok(pages) if *pages > 0 => {
+                let event = FlushEvent { pages: *pages, up_to_lsn: lsn, duration: started.elapsed() };
+                self.options.listeners.emit(|listener| listener.on_flush(&event));
             }
+            Ok(_) => {}
+            Err(e) => self.options.listeners.error("flush", e),
+        }
+        result
+    }
+    
+    /// Write the pages of `keys` to the data blob now (the force policy),
+    /// leaving other writes' dirty pages in the pool
+    async fn force_pages<'k>(&self, keys: impl IntoIterator<Item = &'k str>) -> Result<()> {
+        let page_ids: Vec<u64> = keys
+            .into_iter()
+            .filter_map(|key| match self.index.get(key) {
+                Some(IndexEntry::Page(page_id)) => Some(page_id),
+                _ => None,
+            })
+            .collect();
+        let written = self
+            .buffer_pool
+            .flush_pages(&page_ids, |dirty| async move { self.write_page_after_wal(&dirty).await })
+            .await?;
+        if written > 0 {
+            self.disk.flush().await?;
+        }
+        Ok(())
+    }
+    
+    async fn write_dirty_pages(&self, lsn: u64) -> Result<usize> {
+        let written = self
+            .buffer_pool
+            .flush_up_to(lsn, |dirty| async move { self.write_page_after_wal(&dirty).await })
+            .await?;
+        
+        if written > 0 {
+            info!("Flushed {} dirty pages to AzureDisk (up to LSN {})", written, lsn);
             
             // Wait for all writes to persist (although we await each one)
             self.disk.flush().await?;
         }
         
+        Ok(written)
+    }
+    
+    /// LSN crash recovery would have to redo the WAL from
+    /// 
+    /// Everything logged before it is already in the data blob. Returns the
+    /// next LSN to be written when no page is dirty.
+    pub fn recovery_lsn(&self) -> u64 {
+        self.buffer_pool
+            .oldest_dirty_lsn()
+            .unwrap_or_else(|| self.wal.current_lsn() + 1)
+    }
+    
+    /// Write dirty pages the buffer pool evicted (steal policy) to disk
+    /// 
+    /// `operation` (the one held up meanwhile) names the stall event.
+    async fn write_back_evicted(&self, operation: &'static str) -> Result<()> {
+        let pending = self.buffer_pool.pending_writebacks();
+        if pending.is_empty() {
+            return Ok(());
+        }
+        let started = Instant::now();
+        let pages = pending.len();
+        for evicted in pending {
+            self.write_page_after_wal(&evicted).await?;
+            self.buffer_pool.complete_writeback(evicted.page_id, evicted.page_lsn);
+            debug!("Wrote back evicted page {} (LSN {})", evicted.page_id, evicted.page_lsn);
+        }
+        self.stalls.report(StallCause::EvictionWriteback { pages }, operation, started.elapsed(), 1);
         Ok(())
     }
     
-    /// Create a checkpoint
-    pub async fn checkpoint(&self) -> Result<()> {
-        info!("Creating checkpoint...");
-        
-        // 1. Flush all dirty pages
-        self.flush().await?;
-        
-        // 2. Create checkpoint in WAL
-        self.wal.checkpoint().await?;
+    /// Read a page the buffer pool doesn't hold, via the L2 cache if any
+    async fn read_page(&self, page_id: u64) -> Result<Vec<u8>> {
+        let Some(l2_cache) = &self.l2_cache else {
+            return self.disk.read_page(page_id).await;
+        };
+        if let Some(data) = l2_cache.get(page_id).await {
+            return Ok(data);
+        }
         
-        // 3. Can now safely clear old WAL entries
-        self.wal.clear().await?;
+        let data = self.disk.read_page(page_id).await?;
+        l2_cache.put(page_id, &data).await;
+        Ok(data)
+    }
+    
+    /// Write a dirty page to the data blob once the WAL covers its changes
+    async fn write_page_after_wal(&self, dirty: &DirtyPage) -> Result<()> {
+        // WAL-before-data: the page must never be ahead of the durable log
+        self.wal.flush_to(dirty.page_lsn).await?;
         
-        info!("Checkpoint complete");
-        Ok(())
+        self.write_page(dirty.page_id, &dirty.data, dirty.dirty_range(), true).await
     }
     
-    /// Get store statistics
-    pub fn stats(&self) -> KVStoreStats {
-        let bp_stats = self.buffer_pool.stats();
+    /// Write a page to the data blob, keeping the L2 cache and the backup
+    /// change tracking in step
+    pub(crate) async fn write_page_direct(&self, page_id: u64, data: &[u8]) -> Result<()> {
+        self.write_page(page_id, data, 0..data.len(), false).await
+    }
+    
+    /// `write_page_direct`, writing only bytes `range` of the page (the rest
+    /// match the disk) and sampling the write for a verification read if
+    /// it's a write-back
+    async fn write_page(&self, page_id: u64, data: &[u8], range: std::ops::Range<usize>, write_back: bool) -> Result<()> {
+        // Write-through: a crash mid-write must not leave the old copy cached
+        if let Some(l2_cache) = &self.l2_cache {
+            l2_cache.invalidate(page_id).await;
+        }
+        if let Some(warm_cache) = &self.warm_cache {
+            warm_cache.invalidate();
+        }
         
-        KVStoreStats {
-            num_keys: self.index.len(),
-            wal_entries: self.wal.entry_count(),
-            buffer_pool_used_mb: (bp_stats.used_frames * 4096) / (1024 * 1024),
-            buffer_pool_total_mb: bp_stats.buffer_size_mb,
+        // Write to Azure Page Blob (see readback.rs)
+        let repairs = self.readback.write_guard().await;
+        self.readback.note_write(page_id);
+        let check = if write_back { self.sample_write_back(page_id) } else { None };
+        let written = self.disk.write_page_range(page_id, data, range).await;
+        drop(repairs);
+        if let Some(write) = check {
+            self.schedule_check(page_id, write, data, written.is_ok());
+        }
+        written?;
+        
+        if let Some(l2_cache) = &self.l2_cache {
+            l2_cache.put(page_id, data).await;
+        }
+        
+        if let Some(changed) = self.changed_pages.lock().as_mut() {
+            changed.insert(page_id);
         }
+        Ok(())
     }
     
-    /// Encode a key-value pair into a 4KB page
-    fn encode_kv_page(&self, key: &str, value: &str) -> Result<Vec<u8>> {
-        // Simple encoding: length-prefixed key and value
-        let mut page = vec![0u8; 4096];
+    /// Write the inline values and the page-backed index entries to packed
+    /// pages and point the superblock at them
+    /// 
+    /// Until now only the WAL held them, and a checkpoint is about to clear it.
+    /// Writes must be held off (see `log_gate`) so the snapshot reflects the
+    /// log up to its LSN. The new pages never overwrite the ones the
+    /// superblock names, so a crash at any point leaves one complete set.
+    async fn persist_index(&self) -> Result<()> {
+        let inline_pages = page::encode_packed_pages(&self.index.inline_entries())?;
+        let index_pages = page::encode_packed_pages(&self.index.page_snapshot())?;
+        let (inline_count, index_count) = (inline_pages.len() as u64, index_pages.len() as u64);
         
-        let key_bytes = key.as_bytes();
-        let value_bytes = value.as_bytes();
+        let (current_inline, current_index) = {
+            let superblock = self.superblock.lock().await;
+            ((superblock.inline_start, superblock.inline_count), superblock.index_snapshot)
+        };
         
-        if key_bytes.len() + value_bytes.len() + 8 > 4096 {
-            anyhow::bail!("Key-value pair too large for single page");
+        let inline_start = self.allocate_page_run(inline_count);
+        for (i, data) in inline_pages.iter().enumerate() {
+            self.write_page_direct(inline_start + i as u64, data).await?;
         }
+        let index_start = self.allocate_page_run(index_count);
+        for (i, data) in index_pages.iter().enumerate() {
+            self.write_page_direct(index_start + i as u64, data).await?;
+        }
+        let snapshot = IndexSnapshot {
+            start: index_start,
+            count: index_count,
+            lsn: self.wal.current_lsn(),
+            next_page_id: self.next_page_id(),
+        };
+        self.update_superblock(|sb| {
+            sb.inline_start = inline_start;
+            sb.inline_count = inline_count;
+            sb.index_snapshot = Some(snapshot);
+        })
+        .await?;
         
-        // Write key length (4 bytes)
-        let key_len = key_bytes.len() as u32;
-        page[0..4].copy_from_slice(&key_len.to_le_bytes());
-        
-        // Write key
-        page[4..4 + key_bytes.len()].copy_from_slice(key_bytes);
+        if current_inline.1 > 0 {
+            self.release_page_run(current_inline.0, current_inline.1);
+        }
+        if let Some(current) = current_index.filter(|current| current.count > 0) {
+            self.release_page_run(current.start, current.count);
+        }
+        debug!(
+            "Persisted inline values to {} pages from {}, index to {} pages from {} (LSN {})",
+            inline_count, inline_start, index_count, index_start, snapshot.lsn
+        );
+        Ok(())
+    }
+    
+    /// Contiguous pages for `count` packed pages: a freed run if one is big
+    /// enough, else fresh pages
+    pub(crate) fn allocate_page_run(&self, count: u64) -> u64 {
+        if count == 0 {
+            return 0;
+        }
         
-        // Write value length (4 bytes)
-        let value_len = value_bytes.len() as u32;
-        let value_len_offset = 4 + key_bytes.len();
-        page[value_len_offset..value_len_offset + 4].copy_from_slice(&value_len.to_le_bytes());
+        {
+            let mut free = self.free_inline_runs.lock();
+            if let Some(i) = free.iter().position(|(_, len)| *len >= count) {
+                let (start, len) = free.remove(i);
+                if len > count {
+                    free.push((start + count, len - count));
+                }
+                return start;
+            }
+        }
         
-        // Write value
-        let value_offset = value_len_offset + 4;
-        page[value_offset..value_offset + value_bytes.len()].copy_from_slice(value_bytes);
+        let mut next_id = self.next_page_id.write();
+        let start = *next_id;
+        *next_id += count;
+        start
+    }
+    
+    /// Free a run of packed pages for the next `allocate_page_run`
+    pub(crate) fn release_page_run(&self, start: u64, count: u64) {
+        self.free_inline_runs.lock().push((start, count));
+    }
+    
+    /// Don't hand out page IDs below `end`
+    pub(crate) fn reserve_page_ids_below(&self, end: u64) {
+        let mut next_id = self.next_page_id.write();
+        *next_id = (*next_id).max(end);
+    }
+    
+    /// Create a checkpoint
+    pub async fn checkpoint(&self) -> Result<()> {
+        let _checkpoint = self.checkpoint_lock.lock().await;
+        let listeners = &self.options.listeners;
+        listeners.emit(|listener| listener.on_checkpoint_start());
+        let started = Instant::now();
         
-        Ok(page)
+        let result = match self.checkpoint_locked().await {
+            Ok(pages_flushed) => self.record_checkpoint(pages_flushed, started.elapsed()).await,
+            Err(e) => Err(e),
+        };
+        if let Err(e) = &result {
+            listeners.error("checkpoint", e);
+        }
+        let event = CheckpointEvent { duration: started.elapsed(), succeeded: result.is_ok() };
+        listeners.emit(|listener| listener.on_checkpoint_end(&event));
+        result
     }
     
-    /// Decode a 4KB page into a value
-    fn decode_kv_page(&self, page: &[u8]) -> Result<String> {
-        if page.len() != 4096 {
-            anyhow::bail!("Invalid page size");
+    /// Returns the dirty pages flushed
+    async fn checkpoint_locked(&self) -> Result<usize> {
+        // A shadow-paged store's installed root is always a checkpoint
+        if self.shadow.is_some() {
+            return self.install_shadow_root().await;
         }
+        info!("Creating checkpoint...");
         
-        // Read key length
-        let key_len = u32::from_le_bytes([page[0], page[1], page[2], page[3]]) as usize;
+        // 1. Flush all dirty pages while writes go on
+        let mut pages_flushed = self.flush_up_to(u64::MAX).await?;
+        
+        // From here until the WAL is cleared, every logged write is applied
+        // and no new one is logged
+        {
+            let _writes = self.log_gate.write().await;
+            pages_flushed += self.flush_up_to(u64::MAX).await?;
+            
+            // 2. Persist the index and inline values, which only the WAL
+            //    holds so far
+            self.persist_index().await?;
+            
+            // 3. Create checkpoint in WAL
+            self.wal.checkpoint().await?;
+            
+            // 4. Can now safely clear old WAL entries
+            self.wal.clear().await?;
+            
+            // LSNs continue across the cleared log, so sessions stay valid
+            self.applied_lsn.fetch_max(self.wal.current_lsn(), Ordering::SeqCst);
+        }
         
-        // Read value length
-        let value_len_offset = 4 + key_len;
-        let value_len = u32::from_le_bytes([
-            page[value_len_offset],
-            page[value_len_offset + 1],
-            page[value_len_offset + 2],
-            page[value_len_offset + 3],
-        ]) as usize;
+        // 5. Reclaim orphaned pages
+        if self.options.page_gc_on_checkpoint {
+            self.collect_pages_locked().await?;
+        }
         
-        // Read value
-        let value_offset = value_len_offset + 4;
-        let value_bytes = &page[value_offset..value_offset + value_len];
-        let value = String::from_utf8(value_bytes.to_vec())?;
+        info!("Checkpoint complete");
+        Ok(pages_flushed)
+    }
+    
+    /// L2 cache statistics (None when no L2 cache is configured)
+    pub fn l2_cache_stats(&self) -> Option<L2CacheStats> {
+        self.l2_cache.as_ref().map(L2Cache::stats)
+    }
+    
+    /// Get store statistics
+    pub fn stats(&self) -> KVStoreStats {
+        let bp_stats = self.buffer_pool.stats();
         
-        Ok(value)
+        KVStoreStats {
+            num_keys: self.index.len(),
+            inline_values: self.index.inline_len(),
+            deduplicated_writes: self.deduplicated_writes.load(Ordering::Relaxed),
+            wal_entries: self.wal.entry_count(),
+            group_commit: self.wal.group_commit_stats(),
+            storage: storage_metrics(),
+            readback: self.readback.stats(),
+            auto_checkpoint: self.auto_checkpoint.stats(),
+            buffer_pool_used_mb: (bp_stats.used_frames * 4096) / (1024 * 1024),
+            buffer_pool_total_mb: bp_stats.buffer_size_mb,
+        }
     }
 }
 
@@ -327,7 +1792,19 @@ impl KVStore {
 #[derive(Debug, Clone)]
 pub struct KVStoreStats {
     pub num_keys: usize,
+    /// Keys whose values live in the index (see `StoreOptions::inline_threshold`)
+    pub inline_values: usize,
+    /// Sets skipped as no-ops (see `StoreOptions::dedup_writes`)
+    pub deduplicated_writes: u64,
     pub wal_entries: usize,
+    /// Group commit settings in effect and append counters
+    pub group_commit: GroupCommitStats,
+    /// Azure throttling and retry counters (shared by every store in the process)
+    pub storage: StorageMetrics,
+    /// Verification reads of written pages (see readback.rs)
+    pub readback: ReadbackStats,
+    /// Auto checkpoint choices (None until `start_auto_checkpoint`)
+    pub auto_checkpoint: Option<AutoCheckpointStats>,
     pub buffer_pool_used_mb: usize,
     pub buffer_pool_total_mb: usize,
 }
@@ -335,7 +1812,88 @@ pub struct KVStoreStats {
 #[cfg(test)]
 mod tests {
     use super::*;
-    
-    // Existing tests skipped as they require real Azure connection
+    use crate::latency::{Latency, LatencyProfile};
+    use crate::verify::VerifyMode;
+    use crate::page_store::MemoryDisk;
+    use crate::testing::{memory_options, CONNECTION};
+
+    #[tokio::test]
+    async fn test_checkpointed_keys_survive_reopen() {
+        let (disk, log) = (MemoryDisk::new(), MemoryLog::new());
+        let options = StoreOptions { inline_threshold: 8, ..memory_options(&disk, &log) };
+        let store = KVStore::open(CONNECTION, options.clone()).await.unwrap();
+        store.set("page", "a value too long to inline").await.unwrap();
+        store.set("inline", "short").await.unwrap();
+        store.checkpoint().await.unwrap();
+        drop(store);
+
+        // Only the checkpoint's pages hold the keys now
+        let store = KVStore::open(CONNECTION, options.clone()).await.unwrap();
+        assert_eq!(store.get("page").await.unwrap().as_deref(), Some("a value too long to inline"));
+        assert_eq!(store.get("inline").await.unwrap().as_deref(), Some("short"));
+
+        // New keys don't take the checkpointed keys' pages
+        store.set("later", "another long page-backed value").await.unwrap();
+        drop(store);
+        let store = KVStore::open(CONNECTION, options).await.unwrap();
+        assert_eq!(store.get("page").await.unwrap().as_deref(), Some("a value too long to inline"));
+        assert_eq!(store.get("later").await.unwrap().as_deref(), Some("another long page-backed value"));
+        let report = store.verify(VerifyMode::Full).await.unwrap();
+        assert!(report.is_ok(), "{:?}", report.problems);
+    }
+
+    #[tokio::test]
+    async fn test_hashed_index_survives_checkpoint() {
+        let (disk, log) = (MemoryDisk::new(), MemoryLog::new());
+        let options = StoreOptions { index_mode: IndexMode::Hashed, ..memory_options(&disk, &log) };
+        let store = KVStore::open(CONNECTION, options.clone()).await.unwrap();
+        store.set("https://example.com/a", "1").await.unwrap();
+        store.checkpoint().await.unwrap();
+        drop(store);
+
+        let store = KVStore::open(CONNECTION, options).await.unwrap();
+        assert_eq!(store.get("https://example.com/a").await.unwrap().as_deref(), Some("1"));
+
+        // Hashes can't be listed as keys again
+        let full = memory_options(&disk, &log);
+        assert!(KVStore::open(CONNECTION, full).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_writes_during_checkpoint_survive_reopen() {
+        let (disk, log) = (MemoryDisk::new(), MemoryLog::new());
+        // Slow appends keep writes in flight across each checkpoint
+        let latency = LatencyProfile { append: Latency::Fixed(Duration::from_millis(1)), ..Default::default() };
+        let options = StoreOptions {
+            inline_threshold: 64,
+            wal_backend: WalBackend::Simulated { backend: Box::new(WalBackend::Memory(log.clone())), latency },
+            ..memory_options(&disk, &log)
+        };
+        let store = KVStore::open(CONNECTION, options.clone()).await.unwrap();
+
+        // Writes race the checkpoints' persist and WAL reset
+        let writers: Vec<_> = (0..4)
+            .map(|w| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    for i in (w..400).step_by(4) {
+                        store.set(&format!("k{}", i), &i.to_string()).await.unwrap();
+                    }
+                })
+            })
+            .collect();
+        for _ in 0..20 {
+            store.checkpoint().await.unwrap();
+        }
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        drop(store);
+
+        let store = KVStore::open(CONNECTION, options).await.unwrap();
+        for i in 0..400 {
+            assert_eq!(store.get(&format!("k{}", i)).await.unwrap(), Some(i.to_string()), "k{}", i);
+        }
+    }
 }
 
@@ -0,0 +1,382 @@
+//! L2 Cache: Local-Disk Page Cache Below the Buffer Pool
+//!
+//! A buffer pool miss normally costs an Azure round trip (~100ms). With an
+//! L2 cache configured, pages are also kept in a fixed-size file on local
+//! disk (ideally SSD), so a miss on a warm node costs a local read instead.
+//!
+//! - **Capped**: the file holds `capacity` page slots; the least recently
+//!   used page is replaced when it is full.
+//! - **Checksummed**: each slot carries the page ID and an FNV-1a checksum;
+//!   a slot that fails the check is dropped and read from Azure instead.
+//! - **Write-through**: a page is invalidated before it is written to the
+//!   data blob and re-cached after, so a crash in between leaves a miss,
+//!   never a stale page.
+//!
+//! The file outlives the process. Its header records the writer epoch (see
+//! superblock.rs) it is valid for; when the store is opened at any other
+//! epoch, some other instance may have written since, and the cache starts
+//! out empty.
+//!
+//! File layout:
+//!
+//! ```text
+//! [header: "ICL2CAC1"][epoch: u64 LE][capacity: u64 LE], padded to 4096 bytes
+//! [page_id: u64 LE][checksum: u64 LE][page: 4096 bytes] x capacity
+//! ```
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::page::PAGE_SIZE;
+use crate::superblock::checksum;
+
+const MAGIC: &[u8; 8] = b"ICL2CAC1";
+const HEADER_SIZE: u64 = 4096;
+const SLOT_HEADER: usize = 16;
+const SLOT_SIZE: usize = SLOT_HEADER + PAGE_SIZE;
+
+/// Page ID marking an unused slot
+const EMPTY_SLOT: u64 = u64::MAX;
+
+/// L2 cache statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct L2CacheStats {
+    pub capacity: usize,
+    pub pages: usize,
+    pub hits: u64,
+    pub misses: u64,
+    /// Slots dropped because their checksum didn't match
+    pub corrupt: u64,
+}
+
+/// Which slot holds which page, and the LRU order
+#[derive(Default)]
+struct Slots {
+    by_page: HashMap<u64, (usize, u64)>,
+    /// Last use tick -> slot's page
+    lru: BTreeMap<u64, u64>,
+    free: Vec<usize>,
+    tick: u64,
+}
+
+impl Slots {
+    fn touch(&mut self, page_id: u64) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some((_, last)) = self.by_page.get_mut(&page_id) {
+            self.lru.remove(last);
+            *last = tick;
+            self.lru.insert(tick, page_id);
+        }
+    }
+
+    fn insert(&mut self, page_id: u64, slot: usize) {
+        self.tick += 1;
+        self.by_page.insert(page_id, (slot, self.tick));
+        self.lru.insert(self.tick, page_id);
+    }
+
+    fn remove(&mut self, page_id: u64) -> Option<usize> {
+        let (slot, last) = self.by_page.remove(&page_id)?;
+        self.lru.remove(&last);
+        Some(slot)
+    }
+
+    /// Slot for a page not cached yet: a free one, else the LRU page's
+    fn claim(&mut self) -> Option<usize> {
+        if let Some(slot) = self.free.pop() {
+            return Some(slot);
+        }
+        let (_, victim) = self.lru.pop_first()?;
+        self.by_page.remove(&victim).map(|(slot, _)| slot)
+    }
+}
+
+struct Inner {
+    file: Mutex<File>,
+    slots: Mutex<Slots>,
+    capacity: usize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    corrupt: AtomicU64,
+}
+
+/// Local-disk page cache
+#[derive(Clone)]
+pub struct L2Cache {
+    inner: Arc<Inner>,
+}
+
+impl L2Cache {
+    /// Open (or create) the cache file at `path` for writer epoch `epoch`
+    ///
+    /// Slots are kept if the file was last used at `epoch` with the same
+    /// capacity; otherwise the cache is reset.
+    pub fn open(path: &Path, capacity: usize, epoch: u64) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+
+        let mut header = [0u8; 24];
+        let reusable = file.read_exact(&mut header).is_ok()
+            && &header[0..8] == MAGIC
+            && read_u64(&header, 8) == epoch
+            && read_u64(&header, 16) == capacity as u64;
+
+        let mut slots = Slots::default();
+        if reusable {
+            // Only slot headers are read here; `get` verifies checksums
+            let mut slot_header = [0u8; SLOT_HEADER];
+            for index in 0..capacity {
+                file.seek(SeekFrom::Start(slot_offset(index)))?;
+                match file.read_exact(&mut slot_header) {
+                    Ok(()) if read_u64(&slot_header, 0) != EMPTY_SLOT => {
+                        slots.insert(read_u64(&slot_header, 0), index)
+                    }
+                    _ => slots.free.push(index),
+                }
+            }
+            info!("L2 cache: reusing {} pages from {}", slots.by_page.len(), path.display());
+        } else {
+            file.set_len(0)?;
+            file.set_len(slot_offset(capacity))?;
+            write_header(&mut file, epoch, capacity)?;
+            // A zeroed slot reads as page 0 with a bad checksum; mark them empty
+            let empty = EMPTY_SLOT.to_le_bytes();
+            for index in 0..capacity {
+                file.seek(SeekFrom::Start(slot_offset(index)))?;
+                file.write_all(&empty)?;
+            }
+            slots.free.extend(0..capacity);
+            info!("L2 cache: created {} ({} pages)", path.display(), capacity);
+        }
+        slots.free.reverse();
+
+        Ok(Self {
+            inner: Arc::new(Inner {
+                file: Mutex::new(file),
+                slots: Mutex::new(slots),
+                capacity,
+                hits: AtomicU64::new(0),
+                misses: AtomicU64::new(0),
+                corrupt: AtomicU64::new(0),
+            }),
+        })
+    }
+
+    /// Record that the cache now follows writer epoch `epoch`
+    pub async fn set_epoch(&self, epoch: u64) -> Result<()> {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || write_header(&mut inner.file.lock(), epoch, inner.capacity)).await?
+    }
+
+    /// The cached copy of a page, if present and intact
+    pub async fn get(&self, page_id: u64) -> Option<Vec<u8>> {
+        let Some(index) = self.inner.slots.lock().by_page.get(&page_id).map(|(slot, _)| *slot) else {
+            self.inner.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        let inner = Arc::clone(&self.inner);
+        let read = tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+            let mut file = inner.file.lock();
+            let mut slot = vec![0u8; SLOT_SIZE];
+            file.seek(SeekFrom::Start(slot_offset(index)))?;
+            file.read_exact(&mut slot)?;
+            Ok(slot)
+        })
+        .await;
+
+        match read {
+            Ok(Ok(slot)) if read_u64(&slot, 0) == page_id && slot_valid(&slot) => {
+                self.inner.slots.lock().touch(page_id);
+                self.inner.hits.fetch_add(1, Ordering::Relaxed);
+                Some(slot[SLOT_HEADER..].to_vec())
+            }
+            Ok(Ok(slot)) if read_u64(&slot, 0) != page_id => {
+                // The slot was reassigned while we read it
+                self.inner.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Ok(Ok(_)) => {
+                warn!("L2 cache: slot for page {} failed its checksum, dropping it", page_id);
+                self.inner.corrupt.fetch_add(1, Ordering::Relaxed);
+                self.inner.misses.fetch_add(1, Ordering::Relaxed);
+                self.invalidate(page_id).await;
+                None
+            }
+            Ok(Err(e)) => {
+                warn!("L2 cache: failed to read page {}: {}", page_id, e);
+                self.inner.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+            Err(e) => {
+                warn!("L2 cache: read task failed: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Cache a page (replacing any older copy)
+    pub async fn put(&self, page_id: u64, data: &[u8]) {
+        if data.len() != PAGE_SIZE {
+            return;
+        }
+        let index = {
+            let mut slots = self.inner.slots.lock();
+            match slots.remove(page_id) {
+                Some(index) => Some(index),
+                None => slots.claim(),
+            }
+        };
+        let Some(index) = index else {
+            return; // Zero capacity
+        };
+
+        let mut slot = Vec::with_capacity(SLOT_SIZE);
+        slot.extend_from_slice(&page_id.to_le_bytes());
+        slot.extend_from_slice(&checksum(data).to_le_bytes());
+        slot.extend_from_slice(data);
+
+        match self.write_slot(index, slot).await {
+            Ok(()) => self.inner.slots.lock().insert(page_id, index),
+            Err(e) => {
+                warn!("L2 cache: failed to cache page {}: {}", page_id, e);
+                self.inner.slots.lock().free.push(index);
+            }
+        }
+    }
+
+    /// Forget a page (before it is rewritten elsewhere)
+    pub async fn invalidate(&self, page_id: u64) {
+        let Some(index) = self.inner.slots.lock().remove(page_id) else {
+            return;
+        };
+        if let Err(e) = self.write_slot(index, EMPTY_SLOT.to_le_bytes().to_vec()).await {
+            // The slot still names the page: keep it out of use rather than
+            // let a restart find the old copy
+            warn!("L2 cache: failed to invalidate page {}: {}", page_id, e);
+            return;
+        }
+        self.inner.slots.lock().free.push(index);
+    }
+
+    pub fn stats(&self) -> L2CacheStats {
+        L2CacheStats {
+            capacity: self.inner.capacity,
+            pages: self.inner.slots.lock().by_page.len(),
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            corrupt: self.inner.corrupt.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn write_slot(&self, index: usize, bytes: Vec<u8>) -> Result<()> {
+        let inner = Arc::clone(&self.inner);
+        tokio::task::spawn_blocking(move || -> Result<()> {
+            let mut file = inner.file.lock();
+            file.seek(SeekFrom::Start(slot_offset(index)))?;
+            file.write_all(&bytes)?;
+            Ok(())
+        })
+        .await?
+    }
+}
+
+fn write_header(file: &mut File, epoch: u64, capacity: usize) -> Result<()> {
+    let mut header = Vec::with_capacity(24);
+    header.extend_from_slice(MAGIC);
+    header.extend_from_slice(&epoch.to_le_bytes());
+    header.extend_from_slice(&(capacity as u64).to_le_bytes());
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header)?;
+    Ok(())
+}
+
+fn slot_offset(index: usize) -> u64 {
+    HEADER_SIZE + (index * SLOT_SIZE) as u64
+}
+
+fn slot_valid(slot: &[u8]) -> bool {
+    checksum(&slot[SLOT_HEADER..]) == read_u64(slot, 8)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path() -> PathBuf {
+        std::env::temp_dir().join(format!("ironclad-l2-{}.cache", rand::random::<u64>()))
+    }
+
+    #[tokio::test]
+    async fn test_put_get_and_lru_replacement() {
+        let path = temp_path();
+        let cache = L2Cache::open(&path, 2, 1).unwrap();
+
+        cache.put(10, &[1u8; PAGE_SIZE]).await;
+        cache.put(11, &[2u8; PAGE_SIZE]).await;
+        assert_eq!(cache.get(10).await, Some(vec![1u8; PAGE_SIZE]));
+
+        // 11 is least recently used
+        cache.put(12, &[3u8; PAGE_SIZE]).await;
+        assert_eq!(cache.get(11).await, None);
+        assert_eq!(cache.get(12).await, Some(vec![3u8; PAGE_SIZE]));
+
+        cache.invalidate(10).await;
+        assert_eq!(cache.get(10).await, None);
+
+        let stats = cache.stats();
+        assert_eq!((stats.pages, stats.hits, stats.misses), (1, 2, 2));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reopen_keeps_pages_only_at_same_epoch() {
+        let path = temp_path();
+        let cache = L2Cache::open(&path, 4, 7).unwrap();
+        cache.put(5, &[9u8; PAGE_SIZE]).await;
+        drop(cache);
+
+        let reopened = L2Cache::open(&path, 4, 7).unwrap();
+        assert_eq!(reopened.get(5).await, Some(vec![9u8; PAGE_SIZE]));
+        drop(reopened);
+
+        let other_epoch = L2Cache::open(&path, 4, 8).unwrap();
+        assert_eq!(other_epoch.get(5).await, None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_slot_is_dropped() {
+        let path = temp_path();
+        let cache = L2Cache::open(&path, 1, 1).unwrap();
+        cache.put(3, &[4u8; PAGE_SIZE]).await;
+
+        // Flip a byte of the cached page
+        {
+            let mut file = OpenOptions::new().write(true).open(&path).unwrap();
+            file.seek(SeekFrom::Start(slot_offset(0) + SLOT_HEADER as u64)).unwrap();
+            file.write_all(&[0xff]).unwrap();
+        }
+
+        assert_eq!(cache.get(3).await, None);
+        assert_eq!(cache.stats().corrupt, 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
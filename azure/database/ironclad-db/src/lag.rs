@@ -0,0 +1,174 @@
+//! Lag: How Far Behind the WAL Each Change Consumer Is
+//!
+//! Consumers of the change feed record the LSN they have processed up to:
+//! change publishers do it after every delivery (see changefeed.rs), and a
+//! replica or job tailing `changes_since` itself does it with
+//! `record_consumer_position`. `consumer_lag` compares each position with
+//! the head of the WAL:
+//!
+//! ```ignore
+//! for lag in store.consumer_lag().await? {
+//!     println!("{}: {} writes behind, {:?} stale", lag.name, lag.lsn_lag, lag.time_lag);
+//! }
+//! ```
+//!
+//! `time_lag` estimates staleness from the wall clock: how long ago the
+//! oldest write the consumer hasn't processed was issued (None when it is
+//! caught up, or only deletes, which carry no time, are pending).
+//!
+//! With `StoreOptions::lag_alerts` set, listeners get `on_lag` when a
+//! consumer goes over a threshold and again when it is back under (see
+//! events.rs). Publishers check their own lag at every poll, and
+//! `consumer_lag` checks every consumer it reports on.
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::collections::HashSet;
+use std::time::Duration;
+use tracing::warn;
+
+use crate::changefeed::{marker_key, FeedMarker, CHANGEFEED_PREFIX};
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+use crate::trash::is_reserved;
+use crate::wal::WalEntry;
+
+/// How far behind a consumer is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumerLag {
+    /// Change feed (or consumer) name
+    pub name: String,
+    /// LSN processed up to
+    pub lsn: u64,
+    /// Last LSN in the WAL
+    pub head_lsn: u64,
+    /// LSNs not processed yet (every log record counts, position updates
+    /// included, so an idle consumer may be a record or two behind)
+    pub lsn_lag: u64,
+    /// Age of the oldest write not processed yet
+    pub time_lag: Option<Duration>,
+}
+
+/// When `on_lag` fires (None = no limit)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LagThresholds {
+    pub max_lsn_lag: Option<u64>,
+    pub max_time_lag: Option<Duration>,
+}
+
+impl LagThresholds {
+    /// Is `lag` over a threshold?
+    pub fn exceeded_by(&self, lag: &ConsumerLag) -> bool {
+        self.max_lsn_lag.is_some_and(|max| lag.lsn_lag > max)
+            || self.max_time_lag.is_some_and(|max| lag.time_lag.is_some_and(|time_lag| time_lag > max))
+    }
+}
+
+/// Consumers currently over a threshold
+pub(crate) type LaggingConsumers = Mutex<HashSet<String>>;
+
+/// When a WAL entry's write was issued (0 if unknown, or not a change
+/// consumers see: writes to reserved keys, the positions included)
+fn issued_ms(entry: &WalEntry) -> u64 {
+    if entry.key().is_some_and(is_reserved) {
+        return 0;
+    }
+    match entry {
+        WalEntry::Set { at_ms, .. } | WalEntry::Patch { at_ms, .. } => *at_ms,
+        _ => 0,
+    }
+}
+
+/// Lag of a consumer at `lsn`, given (LSN, issue time) of writes after
+/// some LSN at or below it, in LSN order
+pub(crate) fn lag_of(
+    name: &str,
+    lsn: u64,
+    head_lsn: u64,
+    writes: impl IntoIterator<Item = (u64, u64)>,
+    now_ms: u64,
+) -> ConsumerLag {
+    let oldest_ms = writes.into_iter().find(|(write_lsn, at_ms)| *write_lsn > lsn && *at_ms > 0).map(|(_, at_ms)| at_ms);
+    ConsumerLag {
+        name: name.to_string(),
+        lsn,
+        head_lsn,
+        lsn_lag: head_lsn.saturating_sub(lsn),
+        time_lag: oldest_ms.map(|at_ms| Duration::from_millis(now_ms.saturating_sub(at_ms))),
+    }
+}
+
+impl KVStore {
+    /// Lag of every consumer with a recorded position, by name (see lag.rs)
+    pub async fn consumer_lag(&self) -> Result<Vec<ConsumerLag>> {
+        let mut positions = Vec::new();
+        for key in self.keys_with_prefix(CHANGEFEED_PREFIX) {
+            if let Some(json) = self.get(&key).await? {
+                positions.push((key[CHANGEFEED_PREFIX.len()..].to_string(), serde_json::from_str::<FeedMarker>(&json)?.lsn));
+            }
+        }
+        positions.sort();
+
+        let oldest = positions.iter().map(|(_, lsn)| *lsn).min().unwrap_or(0);
+        let entries = self.wal().replay_since(oldest).await?;
+        let head_lsn = self.wal().current_lsn();
+        let now = now_ms();
+        let writes = || entries.iter().map(|(lsn, entry)| (*lsn, issued_ms(entry)));
+        let lags: Vec<ConsumerLag> =
+            positions.iter().map(|(name, lsn)| lag_of(name, *lsn, head_lsn, writes(), now)).collect();
+        for lag in &lags {
+            self.check_lag(lag);
+        }
+        Ok(lags)
+    }
+
+    /// Record that consumer `name` has processed the changes up to `lsn`
+    pub async fn record_consumer_position(&self, name: &str, lsn: u64) -> Result<()> {
+        self.set(&marker_key(name), &serde_json::to_string(&FeedMarker { lsn })?).await
+    }
+
+    /// Tell listeners if `lag` crossed `StoreOptions::lag_alerts`
+    pub(crate) fn check_lag(&self, lag: &ConsumerLag) {
+        let thresholds = self.options().lag_alerts;
+        if thresholds == LagThresholds::default() {
+            return;
+        }
+        let exceeded = thresholds.exceeded_by(lag);
+        let crossed = {
+            let mut lagging = self.lagging_consumers().lock();
+            if exceeded { lagging.insert(lag.name.clone()) } else { lagging.remove(&lag.name) }
+        };
+        if crossed {
+            if exceeded {
+                warn!("LAG: {} is {} writes ({:?}) behind", lag.name, lag.lsn_lag, lag.time_lag);
+            }
+            self.options().listeners.emit(|listener| listener.on_lag(lag, exceeded));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lag_counts_writes_and_ages_the_oldest_pending_one() {
+        let entries = [
+            (5, WalEntry::Set { key: "a".into(), value: "1".into(), at_ms: 1_000 }),
+            (6, WalEntry::Delete { key: "a".into() }),
+            (7, WalEntry::Set { key: "__changefeed/feed".into(), value: "{}".into(), at_ms: 2_000 }),
+            (8, WalEntry::Set { key: "b".into(), value: "2".into(), at_ms: 3_000 }),
+        ];
+        let writes = || entries.iter().map(|(lsn, entry)| (*lsn, issued_ms(entry)));
+        let lag = lag_of("feed", 5, 8, writes(), 10_000);
+        assert_eq!(lag.lsn_lag, 3);
+        // Neither the delete nor the position update carry a time
+        assert_eq!(lag.time_lag, Some(Duration::from_secs(7)));
+        assert_eq!(lag_of("feed", 8, 8, writes(), 10_000).time_lag, None);
+
+        let thresholds = LagThresholds { max_lsn_lag: Some(5), max_time_lag: Some(Duration::from_secs(5)) };
+        assert!(thresholds.exceeded_by(&lag));
+        assert!(!LagThresholds { max_time_lag: None, ..thresholds }.exceeded_by(&lag));
+        assert!(!LagThresholds::default().exceeded_by(&lag));
+    }
+}
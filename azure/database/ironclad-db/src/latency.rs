@@ -0,0 +1,179 @@
+//! Latency: Simulated Storage Latency for Benchmarks
+//!
+//! The memory and local file log stores answer in microseconds, so
+//! batching work (group commit, the write path's overlap of appends and
+//! page preparation) looks free on them. `SimulatedLatencyLog` wraps any
+//! log store and sleeps before each operation for a delay drawn from that
+//! operation's `Latency`:
+//!
+//! ```ignore
+//! let backend = WalBackend::Simulated {
+//!     backend: Box::new(WalBackend::Memory(MemoryLog::new())),
+//!     latency: LatencyProfile::azure_like(),
+//! };
+//! ```
+//!
+//! Delays come from a generator seeded by `LatencyProfile::seed`, so the
+//! same sequence of operations sees the same delays run after run.
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use parking_lot::Mutex;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::ops::Range;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::log_store::{LogStat, LogStore};
+
+/// Delay distribution for one kind of operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Latency {
+    #[default]
+    None,
+    Fixed(Duration),
+    /// Uniform between `min` and `max`
+    Uniform { min: Duration, max: Duration },
+    /// `base`, except `spike` for about `spikes_per_mille` operations in 1000
+    Spiky { base: Duration, spike: Duration, spikes_per_mille: u16 },
+}
+
+impl Latency {
+    fn sample(&self, rng: &mut StdRng) -> Duration {
+        match *self {
+            Latency::None => Duration::ZERO,
+            Latency::Fixed(delay) => delay,
+            Latency::Uniform { min, max } if max > min => rng.gen_range(min..=max),
+            Latency::Uniform { min, .. } => min,
+            Latency::Spiky { base, spike, spikes_per_mille } => {
+                if rng.gen_range(0..1000) < spikes_per_mille {
+                    spike
+                } else {
+                    base
+                }
+            }
+        }
+    }
+}
+
+/// Delays per log store operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyProfile {
+    pub append: Latency,
+    pub read: Latency,
+    pub stat: Latency,
+    pub reset: Latency,
+    /// Seed for the delay generator
+    pub seed: u64,
+}
+
+impl LatencyProfile {
+    /// Rough append blob latencies within an Azure region: a few ms per
+    /// call, with occasional slow appends
+    pub fn azure_like() -> Self {
+        Self {
+            append: Latency::Spiky {
+                base: Duration::from_millis(5),
+                spike: Duration::from_millis(50),
+                spikes_per_mille: 10,
+            },
+            read: Latency::Uniform { min: Duration::from_millis(3), max: Duration::from_millis(8) },
+            stat: Latency::Uniform { min: Duration::from_millis(2), max: Duration::from_millis(4) },
+            reset: Latency::Fixed(Duration::from_millis(10)),
+            seed: 0,
+        }
+    }
+}
+
+/// A log store with simulated latency in front of another
+pub struct SimulatedLatencyLog {
+    inner: Arc<dyn LogStore>,
+    profile: LatencyProfile,
+    rng: Mutex<StdRng>,
+}
+
+impl SimulatedLatencyLog {
+    pub fn new(inner: Arc<dyn LogStore>, profile: LatencyProfile) -> Self {
+        Self { inner, profile, rng: Mutex::new(StdRng::seed_from_u64(profile.seed)) }
+    }
+
+    fn delay(&self, latency: &Latency) -> Duration {
+        latency.sample(&mut self.rng.lock())
+    }
+}
+
+/// Sleep for `delay`, then run `operation`
+async fn delayed<T>(delay: Duration, operation: BoxFuture<'_, Result<T>>) -> Result<T> {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    operation.await
+}
+
+impl LogStore for SimulatedLatencyLog {
+    fn append(&self, data: Bytes) -> BoxFuture<'_, Result<()>> {
+        Box::pin(delayed(self.delay(&self.profile.append), self.inner.append(data)))
+    }
+
+    fn stat(&self) -> BoxFuture<'_, Result<LogStat>> {
+        Box::pin(delayed(self.delay(&self.profile.stat), self.inner.stat()))
+    }
+
+    fn read(&self, range: Range<u64>) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(delayed(self.delay(&self.profile.read), self.inner.read(range)))
+    }
+
+    fn reset(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(delayed(self.delay(&self.profile.reset), self.inner.reset()))
+    }
+
+    fn truncate(&self, len: u64) -> BoxFuture<'_, Result<()>> {
+        self.inner.truncate(len)
+    }
+
+    fn snapshot(&self) -> BoxFuture<'_, Result<String>> {
+        self.inner.snapshot()
+    }
+
+    fn delete_snapshot<'a>(&'a self, snapshot: &'a str) -> BoxFuture<'a, Result<()>> {
+        self.inner.delete_snapshot(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_store::MemoryLog;
+
+    #[test]
+    fn test_samples_are_deterministic() {
+        let latency = Latency::Uniform { min: Duration::from_millis(1), max: Duration::from_millis(9) };
+        let draw = |seed| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            (0..20).map(|_| latency.sample(&mut rng)).collect::<Vec<_>>()
+        };
+        assert_eq!(draw(7), draw(7));
+        assert!(draw(7).iter().all(|d| *d >= Duration::from_millis(1) && *d <= Duration::from_millis(9)));
+    }
+
+    #[test]
+    fn test_spike_rate() {
+        let latency = Latency::Spiky { base: Duration::ZERO, spike: Duration::from_secs(1), spikes_per_mille: 100 };
+        let mut rng = StdRng::seed_from_u64(1);
+        let spikes = (0..10_000).filter(|_| !latency.sample(&mut rng).is_zero()).count();
+        assert!((800..1200).contains(&spikes), "{}", spikes);
+    }
+
+    #[tokio::test]
+    async fn test_wrapped_log_still_works() {
+        let profile = LatencyProfile { append: Latency::Fixed(Duration::from_millis(2)), ..Default::default() };
+        let log = SimulatedLatencyLog::new(Arc::new(MemoryLog::new()), profile);
+
+        let started = std::time::Instant::now();
+        log.append(Bytes::from_static(b"record\n")).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(2));
+        assert_eq!(log.read(0..7).await.unwrap(), b"record\n");
+    }
+}
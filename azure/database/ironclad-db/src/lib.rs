@@ -1,15 +1,171 @@
-/// Project IronClad - Azure Page Blob KV Store
-/// 
-/// A persistent, crash-safe Key-Value Store built on Azure Page Blobs.
-/// Inspired by Azure SQL and Rubrik's internal architecture.
+//! Project IronClad - Azure Page Blob KV Store
+//! 
+//! A persistent, crash-safe Key-Value Store built on Azure Page Blobs.
+//! Inspired by Azure SQL and Rubrik's internal architecture.
 
+pub mod admission;
+pub mod auto_checkpoint;
+pub mod auto_key;
+pub mod blocking;
+pub mod bootstrap;
+pub mod branch;
 pub mod azure_disk;
+pub mod backup;
+pub mod backup_set;
+pub mod batch;
+pub mod config;
+pub mod encryption;
+pub mod error;
+pub mod events;
+pub mod idempotency;
 pub mod buffer_pool;
+pub mod change_sinks;
+pub mod changefeed;
+pub mod checkpoint_history;
+pub mod codec;
+pub mod collation;
+pub mod collections;
+pub mod column_family;
+pub mod composite;
+pub mod cron;
+pub mod deadline;
+pub mod diff;
+pub mod dir;
+pub mod dry_run;
+pub mod geo_reads;
+pub mod group_commit;
+pub mod heat;
+pub mod import;
+pub mod index;
+#[cfg(feature = "wasm")]
+pub mod indexed_db;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod lag;
+pub mod key_stats;
+pub mod keys;
 pub mod wal;
+pub mod wal_cache;
+pub mod wal_record;
 pub mod kvstore;
+pub mod l2_cache;
+pub mod latency;
+pub mod local_disk;
+pub mod lock;
+pub mod log_store;
+pub mod metadata;
+pub mod middleware;
+pub mod mirror;
+pub mod options;
+pub mod page;
+pub mod page_gc;
+pub mod page_store;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod patch;
+pub mod queue;
+pub mod quota;
+pub mod read_through;
+pub mod readback;
+pub mod recovery;
+pub mod redact;
+pub mod replay;
+pub mod request_tags;
+pub mod retention;
+pub mod retry_budget;
+pub mod runtime;
+pub mod sample;
+pub mod scan_cursor;
+pub mod session;
+pub mod shadow;
+pub mod sorted_set;
+pub mod stalls;
+pub mod storage_metrics;
+pub mod store_meta;
+pub mod superblock;
+#[cfg(test)]
+mod testing;
+pub mod time_series;
+pub mod time_travel;
+pub mod trash;
+pub mod txn;
+pub mod validation;
+pub mod verify;
+pub mod versions;
+pub mod warm_cache;
+pub mod watch;
 
 // Re-export main types for convenience
+pub use auto_checkpoint::{AutoCheckpointOptions, AutoCheckpointStats, AutoCheckpointer};
+pub use checkpoint_history::CheckpointRecord;
+pub use auto_key::{CustomKeyGenerator, KeyGenerator};
 pub use azure_disk::AzureDisk;
-pub use buffer_pool::{BufferPool, BufferPoolStats};
+pub use backup::{BackupInfo, BackupScheduler, RetentionPolicy};
+pub use backup_set::{BackupSet, BackupSetInfo, BackupSetKind};
+pub use batch::WriteOutcome;
+pub use branch::{BranchChange, ConflictPolicy, ConflictResolver, MergeConflict, MergeReport, StoreBranch};
+pub use config::ConnectionConfig;
+pub use encryption::{CryptoErasure, EncryptionOptions, EncryptionScope};
+pub use error::IronCladError;
+pub use events::{CheckpointEvent, ErrorEvent, EventListener, EventListeners, EvictionEvent, FlushEvent, OpenEvent};
+pub use buffer_pool::{BufferPool, BufferPoolStats, CachePriority, DirtyPage, GroupStats};
+pub use change_sinks::{EventGridSink, ServiceBusSink};
+pub use changefeed::{Change, ChangeBatch, ChangeEvent, ChangeFeedOptions, ChangePublisher, ChangePublisherStats, ChangeSink};
+pub use codec::ValueCodec;
+pub use collation::{BucketCollation, Collation};
+pub use collections::{StoreList, StoreSet};
+pub use composite::{decode_key, encode_key, prefix_range, FromKeyPart, FromKeyTuple, ToKeyPart, ToKeyTuple};
+pub use column_family::{ColumnFamily, ColumnFamilyOptions, ColumnFamilyStats, Compression};
+pub use deadline::Deadline;
+pub use lag::{ConsumerLag, LagThresholds};
+pub use retry_budget::RetryBudget;
+pub use diff::DiffEntry;
+pub use dir::DirEntry;
+pub use dry_run::IngestReport;
+pub use geo_reads::{GeoReadStats, SecondaryReads};
+pub use group_commit::{GroupCommitOptions, GroupCommitStats};
+pub use heat::{HeatMap, HotPinning, KeyHeat, PageHeat};
+pub use import::{ImportFormat, ImportJob, ImportProgress, ImportReport};
+pub use index::IndexMode;
+#[cfg(feature = "wasm")]
+pub use indexed_db::{IndexedDbDisk, IndexedDbLog};
+pub use key_stats::{KeyStatsOptions, PrefixStats, TopKey, TopKeys};
+pub use keys::{CachedKeyProvider, DataKey, KeyCachePolicy, KeyCacheStats, KeyProvider, StaticKeyProvider};
 pub use wal::{WAL, WalEntry};
+pub use wal_record::RecordFormat;
 pub use kvstore::{KVStore, KVStoreStats};
+pub use l2_cache::{L2Cache, L2CacheStats};
+pub use local_disk::LocalDisk;
+pub use latency::{Latency, LatencyProfile, SimulatedLatencyLog};
+pub use lock::LockGuard;
+pub use log_store::{AppendBlobLog, LocalFileLog, LogStat, LogStore, MemoryLog, WalBackend};
+pub use metadata::{ConditionalGet, KeyMetadata};
+pub use mirror::MirroredLog;
+pub use middleware::{MiddlewareChain, ValueMiddleware};
+pub use options::StoreOptions;
+pub use page_gc::PageGcReport;
+pub use page_store::{MemoryDisk, PageBackend, PageStore};
+pub use queue::{QueueMessage, StoreQueue};
+pub use quota::{BucketQuota, QuotaEvent, QuotaObserver, QuotaUsage};
+pub use read_through::LoadPolicy;
+pub use readback::ReadbackStats;
+pub use recovery::RecoveryReport;
+pub use redact::ValueLogging;
+pub use replay::{ReplayMarker, ReplayObserver, ReplayProgress};
+pub use retention::{ReclaimCandidate, ReclaimKind, RetentionReport};
+pub use runtime::{Runtime, RuntimeHandle, TaskHandle, TokioRuntime};
+pub use scan_cursor::ScanIter;
+pub use session::SessionToken;
+pub use shadow::{Durability, ShadowRoot};
+pub use sorted_set::StoreSortedSet;
+pub use stalls::{StallCause, StallEvent};
+pub use storage_metrics::{storage_metrics, OperationMetrics, StorageMetrics};
+pub use store_meta::LifecycleMarker;
+pub use superblock::{StoreState, Superblock};
+pub use time_series::{TimeSeries, TimeSeriesOptions};
+pub use time_travel::StoreAtLsn;
+pub use txn::{Isolation, Transaction};
+pub use validation::BucketValidator;
+pub use verify::{VerificationReport, VerifyMode};
+pub use watch::{ChangeOp, OverflowPolicy, Watch, WatchOptions, WatchStats};
+pub use versions::{KeyVersion, VersionRetention};
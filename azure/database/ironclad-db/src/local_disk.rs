@@ -0,0 +1,182 @@
+//! LocalDisk: Pager Layer on a Local File
+//!
+//! The local counterpart of `AzureDisk` (see azure_disk.rs and
+//! page_store.rs): pages live in one file, sized up front to the same 1GB a
+//! data blob has, so page N is always at offset N * 4KB. With the WAL in a
+//! local file too, a store runs with no storage account or Azurite, e.g. in
+//! development and CI:
+//!
+//! ```ignore
+//! let store = KVStore::new_local(Path::new("/tmp/ironclad")).await?;
+//! store.set("k", "v").await?;
+//! ```
+//!
+//! `new_local` keeps `data.pages` and `wal.log` in the directory it's
+//! given. To combine a local file with other options, set
+//! `StoreOptions::page_backend` to `PageBackend::LocalFile`.
+//!
+//! Each write is a whole 4KB page at a page-aligned offset, synced to disk
+//! before `write_page` returns. Writes go through the OS page cache and
+//! aren't atomic: a crash mid-write can leave a page torn, part old and
+//! part new, which only a WAL record still holding the key repairs.
+//! Reads and writes use positional I/O, so they don't serialize on a file
+//! cursor. The file is sized with `set_len`, which is sparse on most
+//! filesystems: space is taken as pages are written. Snapshots, and so
+//! backups, aren't supported.
+
+use anyhow::Result;
+use futures::future::BoxFuture;
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::{debug, info};
+
+use crate::kvstore::KVStore;
+use crate::log_store::WalBackend;
+use crate::options::StoreOptions;
+use crate::page::PAGE_SIZE;
+use crate::page_store::{PageBackend, PageStore};
+
+const FILE_SIZE: u64 = 1024 * 1024 * 1024; // 1GB, as a data blob
+
+/// Files `new_local` keeps in its directory
+const DATA_FILE: &str = "data.pages";
+const WAL_FILE: &str = "wal.log";
+
+/// `open` parses a connection string even when nothing is in Azure
+const LOCAL_CONNECTION_STRING: &str = "AccountName=local;AccountKey=local";
+
+/// Pages in a local file (see local_disk.rs)
+pub struct LocalDisk {
+    file: Arc<File>,
+    path: PathBuf,
+}
+
+impl LocalDisk {
+    /// Use (or create) the page file at `path`
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let file = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(path)?;
+        if file.metadata()?.len() < FILE_SIZE {
+            info!("Creating page file {} with size {} bytes", path.display(), FILE_SIZE);
+            file.set_len(FILE_SIZE)?;
+            file.sync_all()?;
+        }
+        Ok(Self { file: Arc::new(file), path: path.to_path_buf() })
+    }
+
+    /// Get maximum number of pages
+    pub fn max_pages(&self) -> u64 {
+        FILE_SIZE / PAGE_SIZE as u64
+    }
+
+    /// Offset of `page_id`, or an error past the end of the file
+    fn offset(&self, page_id: u64) -> Result<u64> {
+        if page_id >= self.max_pages() {
+            anyhow::bail!("Page {} is past the end of {} ({} pages)", page_id, self.path.display(), self.max_pages());
+        }
+        Ok(page_id * PAGE_SIZE as u64)
+    }
+}
+
+impl PageStore for LocalDisk {
+    fn read_page(&self, page_id: u64) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let offset = self.offset(page_id)?;
+            debug!("Reading page {} from offset {}", page_id, offset);
+            let file = Arc::clone(&self.file);
+            tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+                let mut data = vec![0u8; PAGE_SIZE];
+                file.read_exact_at(&mut data, offset)?;
+                Ok(data)
+            })
+            .await?
+        })
+    }
+
+    fn write_page<'a>(&'a self, page_id: u64, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if data.len() != PAGE_SIZE {
+                anyhow::bail!("Invalid page size: expected {}, got {}", PAGE_SIZE, data.len());
+            }
+            let offset = self.offset(page_id)?;
+            debug!("Writing page {} at offset {}", page_id, offset);
+            let (file, data) = (Arc::clone(&self.file), data.to_vec());
+            tokio::task::spawn_blocking(move || -> Result<()> {
+                file.write_all_at(&data, offset)?;
+                file.sync_data()?;
+                Ok(())
+            })
+            .await?
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<()>> {
+        // Each write was synced before it returned
+        Box::pin(async { Ok(()) })
+    }
+}
+
+impl KVStore {
+    /// Open (or create) a store kept entirely in local files under `dir`
+    /// (see local_disk.rs)
+    pub async fn new_local(dir: &Path) -> Result<Self> {
+        let options = StoreOptions {
+            page_backend: PageBackend::LocalFile(dir.join(DATA_FILE)),
+            wal_backend: WalBackend::LocalFile(dir.join(WAL_FILE)),
+            ..Default::default()
+        };
+        Self::open(LOCAL_CONNECTION_STRING, options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("ironclad-local-{}", rand::random::<u64>()))
+    }
+
+    #[tokio::test]
+    async fn test_pages_round_trip_and_stay_in_bounds() {
+        let dir = temp_dir();
+        let disk = LocalDisk::open(&dir.join(DATA_FILE)).unwrap();
+        assert_eq!(disk.read_page(7).await.unwrap(), vec![0u8; PAGE_SIZE]);
+        disk.write_page(7, &[9u8; PAGE_SIZE]).await.unwrap();
+        assert_eq!(disk.read_page(7).await.unwrap(), vec![9u8; PAGE_SIZE]);
+        assert!(disk.write_page(7, &[1u8; 100]).await.is_err());
+        assert!(disk.read_page(disk.max_pages()).await.is_err());
+
+        // Reopening keeps the pages and the size
+        let disk = LocalDisk::open(&dir.join(DATA_FILE)).unwrap();
+        assert_eq!(disk.read_page(7).await.unwrap()[0], 9);
+        assert_eq!(std::fs::metadata(dir.join(DATA_FILE)).unwrap().len(), FILE_SIZE);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_store_survives_reopen() {
+        let dir = temp_dir();
+        let store = KVStore::new_local(&dir).await.unwrap();
+        store.set("k", "v").await.unwrap();
+        drop(store);
+
+        let store = KVStore::new_local(&dir).await.unwrap();
+        assert_eq!(store.get("k").await.unwrap().as_deref(), Some("v"));
+
+        // Once checkpointed, only the page file holds the key
+        store.set("k2", "v2").await.unwrap();
+        store.checkpoint().await.unwrap();
+        drop(store);
+
+        let store = KVStore::new_local(&dir).await.unwrap();
+        assert_eq!(store.get("k").await.unwrap().as_deref(), Some("v"));
+        assert_eq!(store.get("k2").await.unwrap().as_deref(), Some("v2"));
+        drop(store);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,268 @@
+//! Lock: Lease-Based Distributed Locks
+//!
+//! `store.acquire_lock(name, ttl)` takes a named lock by writing a lease
+//! record (owner ID + expiry) under `__lock/<name>` with `set_nx`, or by
+//! replacing an expired lease with `compare_and_set`. While the returned
+//! guard is alive a background task renews the lease every `ttl / 3`.
+//!
+//! Leases expire in whole milliseconds, so acquiring fails unless `ttl / 3`
+//! is at least a millisecond. Otherwise the TTL is the caller's trade-off:
+//! a short lease leaves a renewal little time to land, a long one keeps a
+//! crashed holder's lock stuck until it runs out.
+//!
+//! If a renewal finds the lease gone or owned by someone else, or the
+//! renewal write fails and the lease runs out, the lock is lost:
+//! `LockGuard::lost()` resolves and `is_held()` turns false. Work protected
+//! by the lock should race against `lost()` and stop when it fires.
+//!
+//! Each acquisition starts a new writer epoch, so `LockGuard::fencing_token()`
+//! strictly increases across successive holders of any lock on the store.
+//!
+//! Leases compare wall-clock time, so lockers need roughly synchronized
+//! clocks; the TTL should comfortably exceed expected clock skew. Like other
+//! conditional writes, acquisition is atomic on the instance it runs on, so
+//! lockers should all go through the same (primary) instance.
+
+use anyhow::Result;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+use crate::runtime::TaskHandle;
+
+/// Key prefix for lock lease records
+const LOCK_PREFIX: &str = "__lock/";
+
+/// Shortest interval between lease renewals (lease expiries are in ms)
+const MIN_RENEWAL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Lease record stored as the lock key's value (also leases queue messages)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) struct Lease {
+    pub owner: String,
+    pub expires_at_ms: u64,
+}
+
+impl Lease {
+    pub fn new(owner: &str, ttl: Duration) -> Self {
+        Self {
+            owner: owner.to_string(),
+            expires_at_ms: now_ms() + ttl.as_millis() as u64,
+        }
+    }
+
+    pub fn is_expired(&self, now_ms: u64) -> bool {
+        now_ms >= self.expires_at_ms
+    }
+
+    fn encode(&self) -> Result<String> {
+        Ok(serde_json::to_string(self)?)
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        serde_json::from_str(value).ok()
+    }
+}
+
+/// Held lock; dropping it stops renewal (the lease then expires after its TTL)
+pub struct LockGuard {
+    store: Arc<KVStore>,
+    key: String,
+    owner: String,
+    fencing_token: u64,
+    held: watch::Receiver<bool>,
+    renewal: TaskHandle,
+}
+
+impl LockGuard {
+    /// Unique ID of this holder, recorded in the lease
+    pub fn owner(&self) -> &str {
+        &self.owner
+    }
+
+    /// Fencing token issued when the lock was acquired
+    /// 
+    /// Pass it along with writes to external systems so they can reject
+    /// writes from a holder that has since lost the lock.
+    pub fn fencing_token(&self) -> u64 {
+        self.fencing_token
+    }
+
+    /// Is the lease still ours as of the last renewal?
+    pub fn is_held(&self) -> bool {
+        *self.held.borrow()
+    }
+
+    /// Resolves once the lock is lost (immediately if it already is)
+    pub async fn lost(&self) {
+        let mut held = self.held.clone();
+        // An error means the renewal task is gone, which only happens once it gave up
+        let _ = held.wait_for(|held| !*held).await;
+    }
+
+    /// Stop renewing and delete the lease if it's still ours
+    pub async fn release(self) -> Result<()> {
+        self.renewal.abort();
+        let current = self.store.get(&self.key).await?;
+
+        if let Some(value) = current {
+            if Lease::decode(&value).is_some_and(|lease| lease.owner == self.owner) {
+                self.store.compare_and_delete(&self.key, &value).await?;
+                info!("LOCK: released {}", self.key);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LockGuard {
+    fn drop(&mut self) {
+        self.renewal.abort();
+    }
+}
+
+impl KVStore {
+    /// Acquire the named lock with a lease of `ttl`, renewed while the guard lives
+    ///
+    /// Fails with `IronCladError::LockHeld` if another owner holds an unexpired
+    /// lease, or if `ttl` is too short to renew (see the module docs).
+    pub async fn acquire_lock(self: &Arc<Self>, name: &str, ttl: Duration) -> Result<LockGuard> {
+        if ttl / 3 < MIN_RENEWAL_INTERVAL {
+            anyhow::bail!("Lock {} needs a lease TTL of at least {:?} to renew, got {:?}", name, MIN_RENEWAL_INTERVAL * 3, ttl);
+        }
+        let key = format!("{}{}", LOCK_PREFIX, name);
+        let owner = format!("{:032x}", rand::thread_rng().gen::<u128>());
+        let lease = Lease::new(&owner, ttl).encode()?;
+
+        if !self.set_nx(&key, &lease).await? {
+            // Someone holds (or held) it: only take over an expired lease
+            let current = self.get(&key).await?;
+            let takeover = match current.as_deref().map(|v| (v, Lease::decode(v))) {
+                Some((_, Some(existing))) if !existing.is_expired(now_ms()) => {
+                    return Err(IronCladError::LockHeld {
+                        name: name.to_string(),
+                        owner: existing.owner,
+                    }
+                    .into());
+                }
+                // Expired or unreadable lease (or deleted since set_nx)
+                Some((value, _)) => self.compare_and_set(&key, Some(value), &lease).await?,
+                None => self.set_nx(&key, &lease).await?,
+            };
+
+            if !takeover {
+                return Err(IronCladError::LockHeld {
+                    name: name.to_string(),
+                    owner: "<concurrent acquirer>".to_string(),
+                }
+                .into());
+            }
+        }
+
+        let fencing_token = self.new_epoch().await?;
+        info!("LOCK: acquired {} (ttl {:?}, fencing token {})", key, ttl, fencing_token);
+
+        let (held_tx, held_rx) = watch::channel(true);
+        let renewal = self.options().runtime.spawn(renew_lease(Arc::clone(self), key.clone(), owner.clone(), lease, ttl, held_tx));
+
+        Ok(LockGuard {
+            store: Arc::clone(self),
+            key,
+            owner,
+            fencing_token,
+            held: held_rx,
+            renewal,
+        })
+    }
+}
+
+/// Renew the lease every ttl/3 until it is lost
+async fn renew_lease(
+    store: Arc<KVStore>,
+    key: String,
+    owner: String,
+    mut current: String,
+    ttl: Duration,
+    held: watch::Sender<bool>,
+) {
+    let interval = ttl / 3;
+    let mut expires_at_ms = now_ms() + ttl.as_millis() as u64;
+
+    loop {
+        store.options().runtime.sleep(interval).await;
+
+        let renewed = match Lease::new(&owner, ttl).encode() {
+            Ok(next) => match store.compare_and_set(&key, Some(&current), &next).await {
+                Ok(true) => {
+                    current = next;
+                    expires_at_ms = now_ms() + ttl.as_millis() as u64;
+                    debug!("LOCK: renewed {}", key);
+                    true
+                }
+                Ok(false) => {
+                    warn!("LOCK: lease on {} was taken over", key);
+                    break;
+                }
+                Err(e) => {
+                    warn!("LOCK: failed to renew {}: {}", key, e);
+                    false
+                }
+            },
+            Err(e) => {
+                warn!("LOCK: failed to encode lease for {}: {}", key, e);
+                false
+            }
+        };
+
+        // A failed renewal is retried next round, unless the lease ran out meanwhile
+        if !renewed && now_ms() >= expires_at_ms {
+            warn!("LOCK: lease on {} expired before it could be renewed", key);
+            break;
+        }
+    }
+
+    let _ = held.send(false);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::memory_store;
+
+    #[test]
+    fn test_lease_round_trip() {
+        let lease = Lease::new("owner-1", Duration::from_secs(10));
+        let decoded = Lease::decode(&lease.encode().unwrap()).unwrap();
+        assert_eq!(decoded, lease);
+        assert!(Lease::decode("not a lease").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ttl_too_short_to_renew_is_rejected() {
+        let store = Arc::new(memory_store().await);
+        for ttl in [Duration::ZERO, Duration::from_millis(2)] {
+            assert!(store.acquire_lock("leader", ttl).await.is_err());
+        }
+        assert!(store.get("__lock/leader").await.unwrap().is_none());
+
+        // Any TTL that can be renewed is the caller's call
+        for (name, ttl) in [("short", Duration::from_millis(3)), ("long", Duration::from_secs(600))] {
+            let guard = store.acquire_lock(name, ttl).await.unwrap();
+            assert!(guard.is_held());
+            guard.release().await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_lease_expiry() {
+        let lease = Lease { owner: "o".to_string(), expires_at_ms: 1_000 };
+        assert!(!lease.is_expired(999));
+        assert!(lease.is_expired(1_000));
+    }
+}
@@ -0,0 +1,444 @@
+//! Log Store: Where the WAL's Bytes Live
+//!
+//! The WAL (wal.rs) owns record encoding, LSNs and replay; a `LogStore`
+//! only stores an append-only byte log. Four backends:
+//!
+//! - `AppendBlobLog`: an Azure Append Blob (the default; what `WAL::new`
+//!   opens). Snapshots are blob snapshots, used by backups.
+//! - `LocalFileLog`: a local file, fsynced on every append, for local
+//!   development with real durability.
+//! - `IndexedDbLog`: IndexedDB, for browser builds with the `wasm`
+//!   feature (see indexed_db.rs).
+//! - `MemoryLog`: a byte vector, for deterministic tests. Nothing survives
+//!   the process, but clones share the log, so a test can "restart" a WAL
+//!   on the same bytes.
+//!
+//! `WalBackend::Simulated` puts synthetic latency in front of any of them
+//! for benchmarks (see latency.rs).
+//!
+//! `WalBackend::AppendBlobIn` keeps the append blob in another account
+//! and container than the data blob, so each can have its own redundancy
+//! (e.g. ZRS for the WAL, LRS for the data the WAL can rebuild):
+//!
+//! ```ignore
+//! let wal_account = ConnectionConfig::parse(&zrs_connection_string)?;
+//! let options = StoreOptions {
+//!     wal_backend: WalBackend::AppendBlobIn { account: wal_account, container: "orders-wal".into() },
+//!     ..Default::default()
+//! };
+//! let store = KVStore::open(&lrs_connection_string, options).await?;
+//! ```
+//!
+//! The WAL blob has the same name wherever it is, so stores must not
+//! share a WAL container.
+//!
+//! `WalBackend::Mirrored` appends to two backends and acknowledges once
+//! both hold the record (see mirror.rs).
+//!
+//! `append` must not return until the bytes are durable: the WAL hands
+//! out an LSN (and callers treat the write as committed) once it does.
+//!
+//! `StoreOptions::wal_backend` picks the backend a store opens its WAL on.
+//!
+//! Methods return boxed futures (rather than `async fn`) so the WAL can
+//! hold any backend as `Arc<dyn LogStore>`.
+
+use anyhow::Result;
+use azure_storage_blobs::prelude::*;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::StreamExt;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+use crate::backup::{delete_blob_snapshot, snapshot_blob};
+use crate::bootstrap::{ensure_append_blob, ensure_container};
+use crate::config::ConnectionConfig;
+use crate::latency::{LatencyProfile, SimulatedLatencyLog};
+use crate::mirror::MirroredLog;
+use crate::retry_budget;
+
+/// Which log store a store's WAL uses
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum WalBackend {
+    /// Append blob next to the data blob
+    #[default]
+    AppendBlob,
+    /// Append blob in its own account and container
+    AppendBlobIn { account: ConnectionConfig, container: String },
+    /// Local file (fsynced per append)
+    LocalFile(PathBuf),
+    /// Memory (shared by clones of the `MemoryLog`)
+    Memory(MemoryLog),
+    /// IndexedDB database, by name (see indexed_db.rs)
+    #[cfg(feature = "wasm")]
+    IndexedDb(String),
+    /// Another backend behind simulated latency (see latency.rs)
+    Simulated { backend: Box<WalBackend>, latency: LatencyProfile },
+    /// Appends acknowledged once both backends hold them (see mirror.rs)
+    Mirrored { primary: Box<WalBackend>, mirror: Box<WalBackend> },
+}
+
+impl WalBackend {
+    /// Open the log for blob `blob` in `container`
+    pub(crate) async fn open(&self, connection_string: &str, container: &str, blob: &str) -> Result<Arc<dyn LogStore>> {
+        Ok(match self {
+            WalBackend::AppendBlob => {
+                Arc::new(AppendBlobLog::open(&ConnectionConfig::parse(connection_string)?, container, blob).await?)
+            }
+            WalBackend::AppendBlobIn { account, container } => {
+                Arc::new(AppendBlobLog::open(account, container, blob).await?)
+            }
+            WalBackend::LocalFile(path) => Arc::new(LocalFileLog::open(path).await?),
+            WalBackend::Memory(log) => Arc::new(log.clone()),
+            #[cfg(feature = "wasm")]
+            WalBackend::IndexedDb(name) => {
+                Arc::new(send_wrapper::SendWrapper::new(crate::indexed_db::IndexedDbLog::open(name)).await?)
+            }
+            WalBackend::Simulated { backend, latency } => {
+                let inner = Box::pin(backend.open(connection_string, container, blob)).await?;
+                Arc::new(SimulatedLatencyLog::new(inner, *latency))
+            }
+            WalBackend::Mirrored { primary, mirror } => {
+                if primary == mirror {
+                    anyhow::bail!("A WAL can't be mirrored to itself: {:?}", primary);
+                }
+                let primary = Box::pin(primary.open(connection_string, container, blob)).await?;
+                let mirror = Box::pin(mirror.open(connection_string, container, blob)).await?;
+                Arc::new(MirroredLog::open(primary, mirror).await?)
+            }
+        })
+    }
+
+    /// Is the log there already? None if the backend can't tell (a memory
+    /// log is always new)
+    pub(crate) async fn exists(&self, connection_string: &str, container: &str, blob: &str) -> Result<Option<bool>> {
+        Ok(match self {
+            WalBackend::AppendBlob => {
+                let container_client = ConnectionConfig::parse(connection_string)?.container_client(container);
+                Some(container_client.blob_client(blob).exists().await?)
+            }
+            WalBackend::AppendBlobIn { account, container } => {
+                Some(account.container_client(container).blob_client(blob).exists().await?)
+            }
+            WalBackend::LocalFile(path) => Some(tokio::fs::try_exists(path).await?),
+            WalBackend::Memory(_) => None,
+            #[cfg(feature = "wasm")]
+            WalBackend::IndexedDb(_) => None,
+            WalBackend::Simulated { backend, .. } => Box::pin(backend.exists(connection_string, container, blob)).await?,
+            WalBackend::Mirrored { primary, .. } => Box::pin(primary.exists(connection_string, container, blob)).await?,
+        })
+    }
+
+    /// Does the log outlive the process?
+    pub fn is_persistent(&self) -> bool {
+        match self {
+            WalBackend::AppendBlob | WalBackend::AppendBlobIn { .. } | WalBackend::LocalFile(_) => true,
+            WalBackend::Memory(_) => false,
+            #[cfg(feature = "wasm")]
+            WalBackend::IndexedDb(_) => true,
+            WalBackend::Simulated { backend, .. } => backend.is_persistent(),
+            // The mirror is only a copy: the primary is what's replayed
+            WalBackend::Mirrored { primary, .. } => primary.is_persistent(),
+        }
+    }
+}
+
+/// Size and identity of a log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogStat {
+    /// Bytes in the log
+    pub len: u64,
+    /// Changes whenever the log is recreated (by `reset`); None if the
+    /// backend can't tell, which disables the WAL tail cache
+    pub identity: Option<String>,
+}
+
+/// Append-only byte log backing a WAL
+pub trait LogStore: Send + Sync {
+    /// Append `data`, returning once it is durable
+    fn append(&self, data: Bytes) -> BoxFuture<'_, Result<()>>;
+
+    fn stat(&self) -> BoxFuture<'_, Result<LogStat>>;
+
+    /// Read a byte range (within `stat().len`)
+    fn read(&self, range: Range<u64>) -> BoxFuture<'_, Result<Vec<u8>>>;
+
+    /// Replace the log with an empty one
+    fn reset(&self) -> BoxFuture<'_, Result<()>>;
+
+    /// Cut the log back to its first `len` bytes, dropping a torn record
+    /// at the end (append blobs can't be cut, nor torn: each append is
+    /// all or nothing)
+    fn truncate(&self, _len: u64) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { anyhow::bail!("This log store can't be truncated") })
+    }
+
+    /// Take a point-in-time copy of the log; returns its ID
+    fn snapshot(&self) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async { anyhow::bail!("This log store doesn't support snapshots") })
+    }
+
+    /// Delete a snapshot taken with `snapshot`
+    fn delete_snapshot<'a>(&'a self, _snapshot: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { anyhow::bail!("This log store doesn't support snapshots") })
+    }
+}
+
+/// Log in an Azure Append Blob
+pub struct AppendBlobLog {
+    blob_client: BlobClient,
+}
+
+impl AppendBlobLog {
+    /// Use an existing append blob (see bootstrap.rs for creating one)
+    pub fn new(blob_client: BlobClient) -> Self {
+        Self { blob_client }
+    }
+
+    /// Open append blob `blob` in `container` of `account`, creating both
+    /// if needed
+    pub async fn open(account: &ConnectionConfig, container: &str, blob: &str) -> Result<Self> {
+        let container_client = account.container_client(container);
+        ensure_container(&container_client).await?;
+        let blob_client = container_client.blob_client(blob);
+        ensure_append_blob(&blob_client).await?;
+        Ok(Self::new(blob_client))
+    }
+}
+
+impl LogStore for AppendBlobLog {
+    fn append(&self, data: Bytes) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.blob_client.append_block(data).await.map_err(retry_budget::surface)?;
+            Ok(())
+        })
+    }
+
+    fn stat(&self) -> BoxFuture<'_, Result<LogStat>> {
+        Box::pin(async move {
+            let properties = self.blob_client.get_properties().await?;
+            Ok(LogStat {
+                len: properties.blob.properties.content_length,
+                identity: Some(properties.blob.properties.creation_time.to_string()),
+            })
+        })
+    }
+
+    fn read(&self, range: Range<u64>) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let mut stream = self.blob_client.get().range(range).into_stream();
+            let mut buffer = Vec::new();
+
+            while let Some(response_res) = stream.next().await {
+                let mut body = response_res.map_err(retry_budget::surface)?.data;
+                while let Some(chunk_res) = body.next().await {
+                    let chunk: Bytes = chunk_res?;
+                    buffer.extend_from_slice(&chunk);
+                }
+            }
+            Ok(buffer)
+        })
+    }
+
+    fn reset(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.blob_client.delete().await?;
+            self.blob_client.put_append_blob().await?;
+            Ok(())
+        })
+    }
+
+    fn snapshot(&self) -> BoxFuture<'_, Result<String>> {
+        Box::pin(snapshot_blob(&self.blob_client))
+    }
+
+    fn delete_snapshot<'a>(&'a self, snapshot: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(delete_blob_snapshot(&self.blob_client, snapshot))
+    }
+}
+
+/// Log in a local file, fsynced on every append
+pub struct LocalFileLog {
+    path: PathBuf,
+    /// Serializes appends and resets
+    lock: tokio::sync::Mutex<()>,
+}
+
+impl LocalFileLog {
+    /// Use (or create) the log file at `path`
+    pub async fn open(path: &Path) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        tokio::fs::OpenOptions::new().create(true).append(true).open(path).await?;
+        Ok(Self { path: path.to_path_buf(), lock: tokio::sync::Mutex::new(()) })
+    }
+}
+
+impl LogStore for LocalFileLog {
+    fn append(&self, data: Bytes) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let _guard = self.lock.lock().await;
+            let mut file = tokio::fs::OpenOptions::new().append(true).open(&self.path).await?;
+            file.write_all(&data).await?;
+            file.sync_data().await?;
+            Ok(())
+        })
+    }
+
+    fn stat(&self) -> BoxFuture<'_, Result<LogStat>> {
+        Box::pin(async move {
+            let len = tokio::fs::metadata(&self.path).await?.len();
+            Ok(LogStat { len, identity: None })
+        })
+    }
+
+    fn read(&self, range: Range<u64>) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(async move {
+            let data = tokio::fs::read(&self.path).await?;
+            Ok(slice(&data, range)?.to_vec())
+        })
+    }
+
+    fn reset(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let _guard = self.lock.lock().await;
+            let file = tokio::fs::File::create(&self.path).await?;
+            file.sync_all().await?;
+            Ok(())
+        })
+    }
+
+    fn truncate(&self, len: u64) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let _guard = self.lock.lock().await;
+            let file = tokio::fs::OpenOptions::new().write(true).open(&self.path).await?;
+            file.set_len(len).await?;
+            file.sync_all().await?;
+            Ok(())
+        })
+    }
+}
+
+/// Log in memory; clones share the same bytes
+#[derive(Clone, Default)]
+pub struct MemoryLog {
+    data: Arc<parking_lot::Mutex<Vec<u8>>>,
+}
+
+impl MemoryLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl std::fmt::Debug for MemoryLog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryLog").field("len", &self.data.lock().len()).finish()
+    }
+}
+
+/// Logs are equal when they share bytes
+impl PartialEq for MemoryLog {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.data, &other.data)
+    }
+}
+
+impl Eq for MemoryLog {}
+
+impl LogStore for MemoryLog {
+    fn append(&self, data: Bytes) -> BoxFuture<'_, Result<()>> {
+        self.data.lock().extend_from_slice(&data);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn stat(&self) -> BoxFuture<'_, Result<LogStat>> {
+        let len = self.data.lock().len() as u64;
+        Box::pin(async move { Ok(LogStat { len, identity: None }) })
+    }
+
+    fn read(&self, range: Range<u64>) -> BoxFuture<'_, Result<Vec<u8>>> {
+        let data = slice(&self.data.lock(), range).map(|bytes| bytes.to_vec());
+        Box::pin(async move { data })
+    }
+
+    fn reset(&self) -> BoxFuture<'_, Result<()>> {
+        self.data.lock().clear();
+        Box::pin(async { Ok(()) })
+    }
+
+    fn truncate(&self, len: u64) -> BoxFuture<'_, Result<()>> {
+        self.data.lock().truncate(len as usize);
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// `data[range]`, or an error if the range runs past the end
+fn slice(data: &[u8], range: Range<u64>) -> Result<&[u8]> {
+    let (start, end) = (range.start as usize, range.end as usize);
+    data.get(start..end)
+        .ok_or_else(|| anyhow::anyhow!("Log range {}..{} is past the end ({} bytes)", start, end, data.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn exercise(log: &dyn LogStore) {
+        log.append(Bytes::from_static(b"one\n")).await.unwrap();
+        log.append(Bytes::from_static(b"two\n")).await.unwrap();
+        assert_eq!(log.stat().await.unwrap().len, 8);
+        assert_eq!(log.read(4..8).await.unwrap(), b"two\n");
+        assert!(log.read(4..9).await.is_err());
+
+        log.truncate(6).await.unwrap();
+        assert_eq!(log.read(0..6).await.unwrap(), b"one\ntw");
+
+        log.reset().await.unwrap();
+        assert_eq!(log.stat().await.unwrap().len, 0);
+        assert!(log.snapshot().await.is_err());
+    }
+
+    #[test]
+    fn test_only_memory_logs_are_lost_at_restart() {
+        assert!(WalBackend::AppendBlob.is_persistent());
+        assert!(WalBackend::LocalFile("wal.log".into()).is_persistent());
+        assert!(!WalBackend::Memory(MemoryLog::new()).is_persistent());
+        let account = ConnectionConfig::parse("AccountName=walzrs;AccountKey=a2V5").unwrap();
+        let elsewhere = WalBackend::AppendBlobIn { account, container: "orders-wal".to_string() };
+        assert!(elsewhere.is_persistent());
+        // Logged when the WAL opens: the key stays out
+        assert!(!format!("{:?}", elsewhere).contains("a2V5"));
+        let simulated = WalBackend::Simulated {
+            backend: Box::new(WalBackend::Memory(MemoryLog::new())),
+            latency: LatencyProfile::azure_like(),
+        };
+        assert!(!simulated.is_persistent());
+    }
+
+    #[tokio::test]
+    async fn test_memory_log() {
+        let log = MemoryLog::new();
+        exercise(&log).await;
+
+        // Clones share the bytes
+        log.append(Bytes::from_static(b"x")).await.unwrap();
+        assert_eq!(log.clone().read(0..1).await.unwrap(), b"x");
+    }
+
+    #[tokio::test]
+    async fn test_local_file_log() {
+        let path = std::env::temp_dir().join(format!("ironclad-log-{}", rand::random::<u64>())).join("wal.log");
+        exercise(&LocalFileLog::open(&path).await.unwrap()).await;
+
+        // Reopening keeps what was appended
+        LocalFileLog::open(&path).await.unwrap().append(Bytes::from_static(b"kept")).await.unwrap();
+        assert_eq!(LocalFileLog::open(&path).await.unwrap().stat().await.unwrap().len, 4);
+
+        tokio::fs::remove_dir_all(path.parent().unwrap()).await.unwrap();
+    }
+}
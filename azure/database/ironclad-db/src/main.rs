@@ -1,5 +1,4 @@
 use ironclad_db::KVStore;
-use tracing_subscriber;
 use std::env;
 
 #[tokio::main]
@@ -0,0 +1,181 @@
+//! Key Metadata: Creation and Update Times, Update Counters
+//!
+//! Every write updates the key's metadata: when it was created, when it was
+//! last written, how many times it has been written and the LSN of that
+//! write. Times come from the WAL record (`WalEntry::Set::at_ms`), so
+//! recovery reproduces them; records from older logs carry no time and are
+//! stamped when applied.
+//!
+//! Metadata is kept in memory, keyed by key hash, and written as a trailer
+//! after the value in the key's page:
+//!
+//! ```text
+//! ["KMD1"][created_ms: u64 LE][updated_ms: u64 LE][update_count: u64 LE][last_lsn: u64 LE]
+//! ```
+//!
+//! Pages written before metadata existed (or too full for the trailer)
+//! have none. Inline values' packed pages don't carry metadata, so keys
+//! loaded from them at startup report the load as their creation.
+//!
+//! `get_if_newer` gives HTTP front-ends cheap ETag-style revalidation. A
+//! key's version is the LSN of its last write: it changes on every write
+//! and, unlike the update count, is never reused after a delete.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::buffer_pool::CachePriority;
+use crate::index::IndexEntry;
+use crate::kvstore::KVStore;
+use crate::page;
+
+const MAGIC: &[u8; 4] = b"KMD1";
+
+/// Size of an encoded trailer
+pub const TRAILER_LEN: usize = 4 + 4 * 8;
+
+/// Metadata of one key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyMetadata {
+    /// First write (ms since the epoch)
+    pub created_ms: u64,
+    /// Last write (ms since the epoch)
+    pub updated_ms: u64,
+    /// Writes since the key was created
+    pub update_count: u64,
+    /// LSN of the last write (0 if it wasn't logged, e.g. loaded at startup)
+    pub last_lsn: u64,
+}
+
+impl KeyMetadata {
+    /// Metadata after a write at `at_ms` with `lsn` (`previous` = None for a new key)
+    pub fn after_write(previous: Option<&KeyMetadata>, at_ms: u64, lsn: u64) -> Self {
+        match previous {
+            Some(previous) => Self {
+                created_ms: previous.created_ms,
+                updated_ms: at_ms,
+                update_count: previous.update_count + 1,
+                last_lsn: lsn,
+            },
+            None => Self {
+                created_ms: at_ms,
+                updated_ms: at_ms,
+                update_count: 1,
+                last_lsn: lsn,
+            },
+        }
+    }
+
+    pub fn encode(&self) -> [u8; TRAILER_LEN] {
+        let mut trailer = [0u8; TRAILER_LEN];
+        trailer[..4].copy_from_slice(MAGIC);
+        for (i, field) in [self.created_ms, self.updated_ms, self.update_count, self.last_lsn].iter().enumerate() {
+            trailer[4 + i * 8..12 + i * 8].copy_from_slice(&field.to_le_bytes());
+        }
+        trailer
+    }
+
+    /// Parse a page trailer; None if the page has no metadata
+    pub fn decode(trailer: &[u8]) -> Option<Self> {
+        let fields = trailer.get(..TRAILER_LEN)?.strip_prefix(MAGIC.as_slice())?;
+        let field = |i: usize| u64::from_le_bytes(fields[i * 8..i * 8 + 8].try_into().unwrap());
+        Some(Self {
+            created_ms: field(0),
+            updated_ms: field(1),
+            update_count: field(2),
+            last_lsn: field(3),
+        })
+    }
+}
+
+/// Result of `get_if_newer`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConditionalGet {
+    /// The key still has the caller's version
+    NotModified,
+    /// The key changed; `version` is what to pass next time
+    Modified { value: String, version: u64 },
+    /// The key doesn't exist
+    NotFound,
+}
+
+impl KVStore {
+    /// Get `key` unless its version is still `version`
+    /// 
+    /// The version is read before the value, so a concurrent write can pair
+    /// a newer value with the older version; the next call then reports
+    /// the key as modified again.
+    pub async fn get_if_newer(&self, key: &str, version: u64) -> Result<ConditionalGet> {
+        let current = self.metadata(key).await?.map(|metadata| metadata.last_lsn).unwrap_or(0);
+        if current == version && self.index().get(key).is_some() {
+            return Ok(ConditionalGet::NotModified);
+        }
+        Ok(match self.get(key).await? {
+            Some(value) => ConditionalGet::Modified { value, version: current },
+            None => ConditionalGet::NotFound,
+        })
+    }
+
+    /// Metadata of `key`, or None if it doesn't exist (or its metadata
+    /// predates tracking)
+    pub async fn metadata(&self, key: &str) -> Result<Option<KeyMetadata>> {
+        let Some(entry) = self.index().get(key) else {
+            return Ok(None);
+        };
+        if let Some(metadata) = self.cached_metadata(key) {
+            return Ok(Some(metadata));
+        }
+
+        // Not tracked since the restart: fall back to the page trailer
+        let IndexEntry::Page(page_id) = entry else {
+            return Ok(None);
+        };
+        let Some(data) = self.load_page(page_id, CachePriority::Normal).await? else {
+            return Ok(None);
+        };
+        let (page_key, _) = page::decode_kv_page(&data)?;
+        if page_key != key {
+            return Ok(None);
+        }
+        Ok(KeyMetadata::decode(page::page_trailer(&data)?))
+    }
+}
+
+/// Time for a write logged at `at_ms` (0 = not recorded: now)
+pub(crate) fn write_time(at_ms: u64) -> u64 {
+    if at_ms != 0 { at_ms } else { now_ms() }
+}
+
+pub(crate) fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata_after_writes() {
+        let created = KeyMetadata::after_write(None, 1_000, 5);
+        assert_eq!(created, KeyMetadata { created_ms: 1_000, updated_ms: 1_000, update_count: 1, last_lsn: 5 });
+
+        let updated = KeyMetadata::after_write(Some(&created), 2_000, 9);
+        assert_eq!(updated, KeyMetadata { created_ms: 1_000, updated_ms: 2_000, update_count: 2, last_lsn: 9 });
+    }
+
+    #[test]
+    fn test_trailer_round_trip() {
+        let metadata = KeyMetadata { created_ms: 1, updated_ms: 2, update_count: 3, last_lsn: 4 };
+        let page = page::encode_kv_page_with_trailer("k", "v", &metadata.encode()).unwrap();
+        assert_eq!(KeyMetadata::decode(page::page_trailer(&page).unwrap()), Some(metadata));
+
+        // Pages without a trailer
+        let page = page::encode_kv_page("k", "v").unwrap();
+        assert_eq!(KeyMetadata::decode(page::page_trailer(&page).unwrap()), None);
+        assert_eq!(KeyMetadata::decode(b"KMD1"), None);
+    }
+}
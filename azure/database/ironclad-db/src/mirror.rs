@@ -0,0 +1,171 @@
+//! Mirror: WAL Appends Acknowledged Only Once Two Logs Hold Them
+//!
+//! A write is committed once its WAL record is durable, and durable means
+//! whatever the log's storage promises: LRS or ZRS keeps copies within one
+//! region. For compliance regimes that require a commit to survive the
+//! loss of a zone or region at the moment it is acknowledged,
+//! `WalBackend::Mirrored` appends every record to two logs at once, e.g.
+//! the default append blob and one in an account in another region, and
+//! acknowledges the write only when both appends succeeded:
+//!
+//! ```ignore
+//! let secondary = ConnectionConfig::parse(&paired_region_connection_string)?;
+//! let options = StoreOptions {
+//!     wal_backend: WalBackend::Mirrored {
+//!         primary: Box::new(WalBackend::AppendBlob),
+//!         mirror: Box::new(WalBackend::AppendBlobIn { account: secondary, container: "orders-wal".into() }),
+//!     },
+//!     strict_durability: true,
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! The account's own geo-replication (GRS, and its RA-GRS secondary read
+//! endpoint) can't stand in for the mirror: it is asynchronous, minutes
+//! behind at times, so checking it before acknowledging would stall every
+//! commit for that long.
+//!
+//! Reads come from the primary; the mirror is there to fail over to (open
+//! the store with the mirror as its `wal_backend`). Commit latency is the
+//! slower of the two appends.
+//!
+//! An append that reaches only one of the logs fails, and leaves the logs
+//! out of step: every later append fails too, until the store is reopened.
+//! Opening brings the mirror back in step by copying the primary to it
+//! whenever their lengths differ.
+
+use anyhow::Result;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tracing::{info, warn};
+
+use crate::log_store::{LogStat, LogStore};
+
+/// Bytes copied per append when resyncing the mirror
+const RESYNC_CHUNK: u64 = 4 * 1024 * 1024;
+
+/// A log whose appends go to a primary and a mirror
+pub struct MirroredLog {
+    primary: Arc<dyn LogStore>,
+    mirror: Arc<dyn LogStore>,
+    /// An append reached one log but not the other
+    diverged: AtomicBool,
+}
+
+impl MirroredLog {
+    /// Mirror `primary` to `mirror`, first copying the primary over if the
+    /// two differ in length
+    pub async fn open(primary: Arc<dyn LogStore>, mirror: Arc<dyn LogStore>) -> Result<Self> {
+        let log = Self { primary, mirror, diverged: AtomicBool::new(false) };
+        log.resync().await?;
+        Ok(log)
+    }
+
+    async fn resync(&self) -> Result<()> {
+        let primary_len = self.primary.stat().await?.len;
+        if self.mirror.stat().await?.len == primary_len {
+            return Ok(());
+        }
+        warn!("WAL mirror: out of step with the primary, copying {} bytes to it", primary_len);
+        self.mirror.reset().await?;
+        let mut offset = 0;
+        while offset < primary_len {
+            let end = (offset + RESYNC_CHUNK).min(primary_len);
+            self.mirror.append(Bytes::from(self.primary.read(offset..end).await?)).await?;
+            offset = end;
+        }
+        info!("WAL mirror: back in step");
+        Ok(())
+    }
+}
+
+impl LogStore for MirroredLog {
+    fn append(&self, data: Bytes) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            if self.diverged.load(Ordering::SeqCst) {
+                anyhow::bail!("WAL mirror is out of step with the primary; reopen the store to resync it");
+            }
+            let (primary, mirror) = tokio::join!(self.primary.append(data.clone()), self.mirror.append(data));
+            if primary.is_ok() != mirror.is_ok() {
+                self.diverged.store(true, Ordering::SeqCst);
+            }
+            primary?;
+            mirror.map_err(|e| e.context("WAL mirror append failed"))
+        })
+    }
+
+    fn stat(&self) -> BoxFuture<'_, Result<LogStat>> {
+        self.primary.stat()
+    }
+
+    fn read(&self, range: Range<u64>) -> BoxFuture<'_, Result<Vec<u8>>> {
+        self.primary.read(range)
+    }
+
+    fn reset(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            self.primary.reset().await?;
+            self.mirror.reset().await?;
+            // Both empty: in step again
+            self.diverged.store(false, Ordering::SeqCst);
+            Ok(())
+        })
+    }
+
+    fn snapshot(&self) -> BoxFuture<'_, Result<String>> {
+        self.primary.snapshot()
+    }
+
+    fn delete_snapshot<'a>(&'a self, snapshot: &'a str) -> BoxFuture<'a, Result<()>> {
+        self.primary.delete_snapshot(snapshot)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_store::MemoryLog;
+
+    /// Fails every append
+    struct Unreachable;
+
+    impl LogStore for Unreachable {
+        fn append(&self, _data: Bytes) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async { anyhow::bail!("region unreachable") })
+        }
+        fn stat(&self) -> BoxFuture<'_, Result<LogStat>> {
+            Box::pin(async { Ok(LogStat { len: 0, identity: None }) })
+        }
+        fn read(&self, _range: Range<u64>) -> BoxFuture<'_, Result<Vec<u8>>> {
+            Box::pin(async { Ok(Vec::new()) })
+        }
+        fn reset(&self) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_copies_primary_to_mirror() {
+        let (primary, mirror) = (MemoryLog::new(), MemoryLog::new());
+        primary.append(Bytes::from_static(b"one\ntwo\n")).await.unwrap();
+        mirror.append(Bytes::from_static(b"stale\n")).await.unwrap();
+
+        let log = MirroredLog::open(Arc::new(primary), Arc::new(mirror.clone())).await.unwrap();
+        log.append(Bytes::from_static(b"three\n")).await.unwrap();
+        assert_eq!(mirror.read(0..14).await.unwrap(), b"one\ntwo\nthree\n");
+    }
+
+    #[tokio::test]
+    async fn test_append_needs_both_logs_and_stops_once_out_of_step() {
+        let primary = MemoryLog::new();
+        let log = MirroredLog::open(Arc::new(primary.clone()), Arc::new(Unreachable)).await.unwrap();
+
+        assert!(log.append(Bytes::from_static(b"one\n")).await.is_err());
+        assert!(log.append(Bytes::from_static(b"two\n")).await.unwrap_err().to_string().contains("out of step"));
+        // The primary got the first record only; it was never acknowledged
+        assert_eq!(primary.stat().await.unwrap().len, 4);
+    }
+}
@@ -0,0 +1,289 @@
+//! Options: Tunables Chosen When Opening a Store
+//!
+//! `KVStore::new` opens with `StoreOptions::default()`; `KVStore::open`
+//! takes explicit options. `container` picks which store in the account to
+//! open (e.g. one restored from a backup set).
+//!
+//! # Buffer management policies
+//!
+//! Whatever the policy, a dirty page is never written to the data blob
+//! before the WAL records that produced it are durable (WAL-before-data).
+//!
+//! - `steal`: may a dirty page be evicted (and written back) to make room?
+//!   With no-steal, dirty pages stay in memory until a flush or checkpoint,
+//!   and writes fail once every frame is dirty.
+//! - `force`: is every write's page written to the data blob before the
+//!   write returns? With no-force, pages reach the blob on flush,
+//!   checkpoint or eviction, and recovery redoes the rest from the WAL.
+//!
+//! # Strict durability
+//!
+//! Some paths degrade quietly rather than fail: a page that can't be read
+//! makes `get` report the key missing, recovery skips a patch whose base
+//! value is gone, and an in-memory WAL acknowledges writes a restart
+//! loses. With `strict_durability` each of these fails with
+//! `IronCladError::NotSupported` instead.
+//!
+//! # Backups
+//!
+//! `backup_schedule` is a cron expression (see cron.rs) for
+//! `KVStore::start_backup_scheduler`; `backup_retention` decides which
+//! backups each scheduled run keeps.
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::backup::RetentionPolicy;
+use crate::collation::BucketCollation;
+use crate::encryption::EncryptionOptions;
+use crate::group_commit::GroupCommitOptions;
+use crate::key_stats::KeyStatsOptions;
+use crate::runtime::RuntimeHandle;
+use crate::shadow::Durability;
+use crate::validation::BucketValidator;
+use crate::wal_record::RecordFormat;
+use crate::heat::HotPinning;
+use crate::index::IndexMode;
+use crate::lag::LagThresholds;
+use crate::log_store::WalBackend;
+use crate::page_store::PageBackend;
+use crate::middleware::MiddlewareChain;
+use crate::quota::{BucketQuota, QuotaObserver};
+use crate::redact::ValueLogging;
+use crate::auto_checkpoint::AutoCheckpointOptions;
+use crate::auto_key::KeyGenerator;
+use crate::events::EventListeners;
+use crate::geo_reads::SecondaryReads;
+use crate::replay::ReplayObserver;
+use crate::retry_budget::RetryBudget;
+use crate::versions::VersionRetention;
+
+/// Options for opening a store
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreOptions {
+    /// Container holding the data and WAL blobs (default: "ironclad-db")
+    pub container: String,
+
+    /// Index full keys or 128-bit key hashes (see index.rs; default: full)
+    pub index_mode: IndexMode,
+
+    /// Values shorter than this many bytes are kept in the index instead of
+    /// a page, and persisted at checkpoint (default: 0, never inline)
+    pub inline_threshold: usize,
+
+    /// Skip `set`s that write a key's current value (compared by hash),
+    /// counted in `KVStoreStats::deduplicated_writes` (default: false)
+    pub dedup_writes: bool,
+
+    /// How long an idempotency token keeps retries from re-applying a
+    /// write (see idempotency.rs; default: 24 hours)
+    pub idempotency_window: Duration,
+
+    /// Keep deleted keys restorable with `undelete` for this long (see
+    /// trash.rs; None = delete outright, the default)
+    pub trash_retention: Option<Duration>,
+
+    /// Keep the values keys held before each overwrite or delete, readable
+    /// with `history` (see versions.rs; None = keep none, the default)
+    pub version_retention: Option<VersionRetention>,
+
+    /// How values appear in logs: in full, hashed, truncated or not at all
+    /// (see redact.rs; default: in full)
+    pub value_logging: ValueLogging,
+
+    /// Transformations applied to values written and read (see
+    /// middleware.rs; default: none)
+    pub middleware: MiddlewareChain,
+
+    /// Per-bucket value encryption (see encryption.rs; None = none)
+    pub encryption: Option<EncryptionOptions>,
+
+    /// How `scan_range` orders keys, per key prefix (see collation.rs;
+    /// default: byte order)
+    pub collations: Vec<BucketCollation>,
+
+    /// Key and byte limits per key prefix (see quota.rs; default: none)
+    pub quotas: Vec<BucketQuota>,
+
+    /// Told about each write a quota refuses
+    pub quota_exceeded: Option<QuotaObserver>,
+
+    /// Rules values must meet, per key prefix (see validation.rs; default:
+    /// none)
+    pub validators: Vec<BucketValidator>,
+
+    /// Prefixes and top keys to keep stats for (see key_stats.rs;
+    /// default: none)
+    pub key_stats: KeyStatsOptions,
+
+    /// Allow evicting dirty pages before they're flushed (default: true)
+    pub steal: bool,
+
+    /// Write a change's pages to the data blob before the write returns (default: false)
+    pub force: bool,
+
+    /// Pin the pages of the most read keys in the buffer pool (see heat.rs;
+    /// None = never, the default)
+    pub hot_pinning: Option<HotPinning>,
+
+    /// When scheduled backups run, e.g. `"0 3 * * *"` (None = no schedule)
+    pub backup_schedule: Option<String>,
+
+    /// Backups kept by scheduled pruning
+    pub backup_retention: RetentionPolicy,
+
+    /// Where the data pages are kept (see page_store.rs; default: a page
+    /// blob in `container`)
+    pub page_backend: PageBackend,
+
+    /// Where the WAL is kept, possibly in another account (see
+    /// log_store.rs; default: an append blob in `container`)
+    pub wal_backend: WalBackend,
+
+    /// How WAL records are written (see wal_record.rs; `Bare` while
+    /// readers older than the envelope may still read the log)
+    pub wal_record_format: RecordFormat,
+
+    /// Executor for background tasks and timers (see runtime.rs; default:
+    /// Tokio)
+    pub runtime: RuntimeHandle,
+
+    /// How concurrent WAL appends are batched (see group_commit.rs)
+    pub group_commit: GroupCommitOptions,
+
+    /// Shortest internal wait published as a stall event (see stalls.rs;
+    /// default: 50ms)
+    pub stall_threshold: Duration,
+
+    /// Re-read and verify one in this many written-back pages (see
+    /// readback.rs; default: 0, never)
+    pub readback_one_in: u64,
+
+    /// Called with recovery progress while the WAL replays (see replay.rs)
+    pub replay_progress: Option<ReplayObserver>,
+
+    /// Receive opens, checkpoints, flushes, evictions, recovery progress and
+    /// errors (see events.rs)
+    pub listeners: EventListeners,
+
+    /// Tasks replaying the WAL in parallel, partitioned by key (see
+    /// replay.rs; default: one per core, 1 = sequential)
+    pub replay_workers: usize,
+
+    /// Replayed entries between persisted replay markers, which let an
+    /// interrupted recovery resume (default: 50000; 0 = never)
+    pub replay_marker_interval: u64,
+
+    /// How writes are made durable, for a store being created (see
+    /// shadow.rs; default: the WAL)
+    pub durability: Durability,
+
+    /// Fail with `NotSupported` where data would otherwise be lost or
+    /// hidden silently (see above; default: false)
+    pub strict_durability: bool,
+
+    /// Open a store whose WAL blob was deleted on a new WAL, from the data
+    /// blob as of its last flush, instead of failing (see bootstrap.rs;
+    /// default: false)
+    pub accept_lost_wal: bool,
+
+    /// Pace checkpoints and flushes from the workload once
+    /// `start_auto_checkpoint` runs (see auto_checkpoint.rs; default: None)
+    pub auto_checkpoint: Option<AutoCheckpointOptions>,
+
+    /// Reclaim orphaned data pages at every checkpoint (see page_gc.rs)
+    pub page_gc_on_checkpoint: bool,
+
+    /// Directory for a local copy of the WAL, so restarts only fetch new
+    /// records (None = always download the whole log)
+    pub wal_cache_dir: Option<PathBuf>,
+
+    /// File for a local-disk page cache below the buffer pool (see
+    /// l2_cache.rs; None = no L2 cache)
+    pub l2_cache_path: Option<PathBuf>,
+
+    /// L2 cache size in pages (default: 262144, 1GB of pages)
+    pub l2_cache_pages: usize,
+
+    /// Directory for a buffer pool snapshot saved at shutdown and loaded at
+    /// the next open on this node (see warm_cache.rs; None = start cold)
+    pub warm_cache_dir: Option<PathBuf>,
+
+    /// How `insert_auto` makes keys (see auto_key.rs; default: UUIDv7)
+    pub key_generator: KeyGenerator,
+
+    /// Cap retries to the storage account, shared with every store on it
+    /// (see retry_budget.rs; None = leave them uncapped)
+    pub retry_budget: Option<RetryBudget>,
+
+    /// Serve page reads from the account's RA-GRS secondary when it is
+    /// faster and fresh enough (see geo_reads.rs; None = primary only)
+    pub secondary_reads: Option<SecondaryReads>,
+
+    /// Tell listeners when a change consumer falls this far behind (see
+    /// lag.rs; default: never)
+    pub lag_alerts: LagThresholds,
+}
+
+impl Default for StoreOptions {
+    fn default() -> Self {
+        Self {
+            container: "ironclad-db".to_string(),
+            index_mode: IndexMode::Full,
+            inline_threshold: 0,
+            dedup_writes: false,
+            idempotency_window: Duration::from_secs(24 * 60 * 60),
+            trash_retention: None,
+            version_retention: None,
+            value_logging: ValueLogging::Full,
+            middleware: MiddlewareChain::default(),
+            encryption: None,
+            collations: Vec::new(),
+            quotas: Vec::new(),
+            quota_exceeded: None,
+            validators: Vec::new(),
+            key_stats: KeyStatsOptions::default(),
+            steal: true,
+            force: false,
+            hot_pinning: None,
+            backup_schedule: None,
+            backup_retention: RetentionPolicy::default(),
+            page_backend: PageBackend::PageBlob,
+            wal_backend: WalBackend::AppendBlob,
+            wal_record_format: RecordFormat::Envelope,
+            runtime: RuntimeHandle::default(),
+            group_commit: GroupCommitOptions::default(),
+            stall_threshold: Duration::from_millis(50),
+            readback_one_in: 0,
+            replay_progress: None,
+            listeners: EventListeners::default(),
+            replay_workers: std::thread::available_parallelism().map_or(1, usize::from),
+            replay_marker_interval: 50_000,
+            durability: Durability::Wal,
+            strict_durability: false,
+            accept_lost_wal: false,
+            auto_checkpoint: None,
+            page_gc_on_checkpoint: false,
+            wal_cache_dir: None,
+            l2_cache_path: None,
+            l2_cache_pages: 262_144,
+            warm_cache_dir: None,
+            key_generator: KeyGenerator::UuidV7,
+            retry_budget: None,
+            secondary_reads: None,
+            lag_alerts: LagThresholds::default(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy_is_steal_no_force() {
+        let options = StoreOptions::default();
+        assert!(options.steal);
+        assert!(!options.force);
+    }
+}
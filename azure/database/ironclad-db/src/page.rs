@@ -0,0 +1,327 @@
+//! Page: Key-Value Page Codec
+//!
+//! Each key-value pair is stored in a single 4KB page:
+//!
+//! ```text
+//! [key_len: u32 LE][key bytes][value_len: u32 LE][value bytes][zero padding]
+//! ```
+//!
+//! The value bytes are produced by a `ValueCodec`; `encode_kv_page` /
+//! `decode_kv_page` use the store's native `Utf8String` codec.
+//!
+//! A page may carry a trailer (key metadata, see metadata.rs) in the space
+//! after the value; pages without one read it back as zero padding.
+//!
+//! Packed pages hold many small pairs (inline values persisted at a
+//! checkpoint, see kvstore.rs):
+//!
+//! ```text
+//! [count: u32 LE]([key_len: u32 LE][key bytes][value_len: u32 LE][value bytes]) x count [zero padding]
+//! ```
+//!
+//! Pages come back from Azure, so decoding treats them as untrusted input:
+//! every length is bounds-checked and a malformed page yields
+//! `IronCladError::InvalidPageFormat` instead of a panic.
+
+use anyhow::Result;
+
+use crate::codec::{Utf8String, ValueCodec};
+use crate::error::IronCladError;
+
+/// Size of a KV page (matches the pager's 4KB page size)
+pub const PAGE_SIZE: usize = 4096;
+
+/// Size of each length prefix
+const LEN_PREFIX: usize = 4;
+
+/// Encode a key-value pair into a 4KB page
+pub fn encode_kv_page(key: &str, value: &str) -> Result<Vec<u8>> {
+    encode_kv_page_with(&Utf8String, key, &value.to_string())
+}
+
+/// Decode a 4KB page into its key and value
+pub fn decode_kv_page(page: &[u8]) -> Result<(String, String)> {
+    decode_kv_page_with(&Utf8String, page)
+}
+
+/// Encode a key and a codec-serialized value into a 4KB page
+pub fn encode_kv_page_with<C: ValueCodec>(codec: &C, key: &str, value: &C::Value) -> Result<Vec<u8>> {
+    let value_bytes = codec.encode(value)?;
+    encode_raw_page(key, &value_bytes)
+}
+
+/// Decode a 4KB page, deserializing the value with `codec`
+pub fn decode_kv_page_with<C: ValueCodec>(codec: &C, page: &[u8]) -> Result<(String, C::Value)> {
+    let (key, value_bytes) = decode_raw_page(page)?;
+    Ok((key, codec.decode(value_bytes)?))
+}
+
+/// Encode a key-value pair followed by `trailer`
+/// 
+/// The trailer is left out if it doesn't fit alongside the pair.
+pub fn encode_kv_page_with_trailer(key: &str, value: &str, trailer: &[u8]) -> Result<Vec<u8>> {
+    let mut page = encode_raw_page(key, value.as_bytes())?;
+    let start = 2 * LEN_PREFIX + key.len() + value.len();
+    if start + trailer.len() <= PAGE_SIZE {
+        page[start..start + trailer.len()].copy_from_slice(trailer);
+    }
+    Ok(page)
+}
+
+/// Does a key-value pair fit in a page with a `trailer_len` trailer after it?
+/// 
+/// `encode_kv_page_with_trailer` leaves out a trailer that doesn't fit, so
+/// a write whose replay relies on the trailer has to check first.
+pub fn fits_with_trailer(key: &str, value: &str, trailer_len: usize) -> bool {
+    2 * LEN_PREFIX + key.len() + value.len() + trailer_len <= PAGE_SIZE
+}
+
+/// The bytes after a page's value (the trailer, or zero padding)
+pub fn page_trailer(page: &[u8]) -> Result<&[u8]> {
+    if page.len() != PAGE_SIZE {
+        return Err(invalid(format!("expected {} bytes, got {}", PAGE_SIZE, page.len())));
+    }
+
+    let mut offset = 0;
+    read_field(page, &mut offset, "key")?;
+    read_field(page, &mut offset, "value")?;
+    Ok(&page[offset..])
+}
+
+/// Does a pair fit in a packed page (alongside the count)?
+pub fn fits_packed(key: &str, value: &str) -> bool {
+    packed_size(key, value) <= PAGE_SIZE - LEN_PREFIX
+}
+
+fn packed_size(key: &str, value: &str) -> usize {
+    2 * LEN_PREFIX + key.len() + value.len()
+}
+
+/// Pack pairs into as few pages as possible, in order
+pub fn encode_packed_pages(pairs: &[(String, String)]) -> Result<Vec<Vec<u8>>> {
+    let mut pages = Vec::new();
+    let mut page = vec![0u8; PAGE_SIZE];
+    let mut count = 0u32;
+    let mut offset = LEN_PREFIX;
+
+    for (key, value) in pairs {
+        if !fits_packed(key, value) {
+            anyhow::bail!("Key-value pair too large for a packed page");
+        }
+        if offset + packed_size(key, value) > PAGE_SIZE {
+            page[0..LEN_PREFIX].copy_from_slice(&count.to_le_bytes());
+            pages.push(std::mem::replace(&mut page, vec![0u8; PAGE_SIZE]));
+            count = 0;
+            offset = LEN_PREFIX;
+        }
+        for field in [key.as_bytes(), value.as_bytes()] {
+            page[offset..offset + LEN_PREFIX].copy_from_slice(&(field.len() as u32).to_le_bytes());
+            page[offset + LEN_PREFIX..offset + LEN_PREFIX + field.len()].copy_from_slice(field);
+            offset += LEN_PREFIX + field.len();
+        }
+        count += 1;
+    }
+
+    if count > 0 {
+        page[0..LEN_PREFIX].copy_from_slice(&count.to_le_bytes());
+        pages.push(page);
+    }
+    Ok(pages)
+}
+
+/// Decode a packed page into its pairs
+pub fn decode_packed_page(page: &[u8]) -> Result<Vec<(String, String)>> {
+    if page.len() != PAGE_SIZE {
+        return Err(invalid(format!("expected {} bytes, got {}", PAGE_SIZE, page.len())));
+    }
+
+    let mut count_bytes = [0u8; LEN_PREFIX];
+    count_bytes.copy_from_slice(&page[0..LEN_PREFIX]);
+    let count = u32::from_le_bytes(count_bytes);
+
+    let mut offset = LEN_PREFIX;
+    let mut pairs = Vec::new();
+    for _ in 0..count {
+        let key = read_field(page, &mut offset, "key")?;
+        let value = read_field(page, &mut offset, "value")?;
+        pairs.push((utf8(key, "key")?, utf8(value, "value")?));
+    }
+    Ok(pairs)
+}
+
+fn utf8(bytes: &[u8], field: &str) -> Result<String> {
+    String::from_utf8(bytes.to_vec()).map_err(|e| invalid(format!("{} is not UTF-8: {}", field, e)))
+}
+
+/// Encode a key and raw value bytes into a 4KB page
+fn encode_raw_page(key: &str, value_bytes: &[u8]) -> Result<Vec<u8>> {
+    // Simple encoding: length-prefixed key and value
+    let mut page = vec![0u8; PAGE_SIZE];
+
+    let key_bytes = key.as_bytes();
+
+    if key_bytes.len() + value_bytes.len() + 2 * LEN_PREFIX > PAGE_SIZE {
+        anyhow::bail!("Key-value pair too large for single page");
+    }
+
+    // Write key length (4 bytes)
+    let key_len = key_bytes.len() as u32;
+    page[0..LEN_PREFIX].copy_from_slice(&key_len.to_le_bytes());
+
+    // Write key
+    page[LEN_PREFIX..LEN_PREFIX + key_bytes.len()].copy_from_slice(key_bytes);
+
+    // Write value length (4 bytes)
+    let value_len = value_bytes.len() as u32;
+    let value_len_offset = LEN_PREFIX + key_bytes.len();
+    page[value_len_offset..value_len_offset + LEN_PREFIX].copy_from_slice(&value_len.to_le_bytes());
+
+    // Write value
+    let value_offset = value_len_offset + LEN_PREFIX;
+    page[value_offset..value_offset + value_bytes.len()].copy_from_slice(value_bytes);
+
+    Ok(page)
+}
+
+/// Decode a 4KB page into its key and raw value bytes
+fn decode_raw_page(page: &[u8]) -> Result<(String, &[u8])> {
+    if page.len() != PAGE_SIZE {
+        return Err(invalid(format!("expected {} bytes, got {}", PAGE_SIZE, page.len())));
+    }
+
+    let mut offset = 0;
+    let key_bytes = read_field(page, &mut offset, "key")?;
+    let value_bytes = read_field(page, &mut offset, "value")?;
+
+    let key = utf8(key_bytes, "key")?;
+
+    Ok((key, value_bytes))
+}
+
+/// Read a length-prefixed field at `offset`, advancing it past the field
+fn read_field<'a>(page: &'a [u8], offset: &mut usize, field: &str) -> Result<&'a [u8]> {
+    let len_end = offset
+        .checked_add(LEN_PREFIX)
+        .filter(|end| *end <= page.len())
+        .ok_or_else(|| invalid(format!("{} length at offset {} runs past end of page", field, offset)))?;
+
+    let mut len_bytes = [0u8; LEN_PREFIX];
+    len_bytes.copy_from_slice(&page[*offset..len_end]);
+    let len = u32::from_le_bytes(len_bytes) as usize;
+
+    let end = len_end
+        .checked_add(len)
+        .filter(|end| *end <= page.len())
+        .ok_or_else(|| invalid(format!("{} length {} at offset {} runs past end of page", field, len, offset)))?;
+
+    *offset = end;
+    Ok(&page[len_end..end])
+}
+
+fn invalid(reason: String) -> anyhow::Error {
+    IronCladError::InvalidPageFormat(reason).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_invalid(result: Result<(String, String)>) {
+        let err = result.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<IronCladError>(),
+            Some(IronCladError::InvalidPageFormat(_))
+        ));
+    }
+
+    #[test]
+    fn test_encode_decode_page() {
+        let page = encode_kv_page("user:1", "Alice").unwrap();
+        assert_eq!(page.len(), PAGE_SIZE);
+
+        let (key, value) = decode_kv_page(&page).unwrap();
+        assert_eq!(key, "user:1");
+        assert_eq!(value, "Alice");
+    }
+
+    #[test]
+    fn test_too_large_for_page() {
+        let value = "x".repeat(PAGE_SIZE);
+        assert!(encode_kv_page("k", &value).is_err());
+    }
+
+    #[test]
+    fn test_wrong_page_size() {
+        assert_invalid(decode_kv_page(&[0u8; 100]));
+    }
+
+    #[test]
+    fn test_key_length_past_end() {
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_invalid(decode_kv_page(&page));
+    }
+
+    #[test]
+    fn test_value_length_past_end() {
+        let mut page = encode_kv_page("key", "value").unwrap();
+        let value_len_offset = 4 + 3;
+        page[value_len_offset..value_len_offset + 4].copy_from_slice(&(PAGE_SIZE as u32).to_le_bytes());
+        assert_invalid(decode_kv_page(&page));
+    }
+
+    #[test]
+    fn test_value_length_prefix_past_end() {
+        // Key fills the page so the value length prefix has no room
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[0..4].copy_from_slice(&((PAGE_SIZE - 4) as u32).to_le_bytes());
+        assert_invalid(decode_kv_page(&page));
+    }
+
+    #[test]
+    fn test_page_with_json_codec() {
+        let codec = crate::codec::Json::<Vec<u32>>::new();
+        let page = encode_kv_page_with(&codec, "ids", &vec![1, 2, 3]).unwrap();
+
+        let (key, value) = decode_kv_page_with(&codec, &page).unwrap();
+        assert_eq!(key, "ids");
+        assert_eq!(value, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_packed_pages_round_trip() {
+        let pairs: Vec<(String, String)> = (0..500).map(|i| (format!("flag:{}", i), format!("{}", i % 2 == 0))).collect();
+        let pages = encode_packed_pages(&pairs).unwrap();
+        assert!(pages.len() > 1);
+
+        let decoded: Vec<(String, String)> = pages.iter().flat_map(|p| decode_packed_page(p).unwrap()).collect();
+        assert_eq!(decoded, pairs);
+        assert!(encode_packed_pages(&[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_packed_page_count_past_end() {
+        let mut page = encode_packed_pages(&[("k".to_string(), "v".to_string())]).unwrap().remove(0);
+        page[0..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_invalid(decode_packed_page(&page).map(|_| (String::new(), String::new())));
+    }
+
+    #[test]
+    fn test_trailer_follows_value() {
+        let page = encode_kv_page_with_trailer("key", "value", b"meta").unwrap();
+        assert_eq!(decode_kv_page(&page).unwrap(), ("key".to_string(), "value".to_string()));
+        assert!(page_trailer(&page).unwrap().starts_with(b"meta\0"));
+
+        // No room: the pair is still written
+        let value = "x".repeat(PAGE_SIZE - 2 * LEN_PREFIX - 3);
+        let page = encode_kv_page_with_trailer("key", &value, b"meta").unwrap();
+        assert!(page_trailer(&page).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        let mut page = encode_kv_page("key", "value").unwrap();
+        page[4] = 0xFF;
+        assert_invalid(decode_kv_page(&page));
+    }
+}
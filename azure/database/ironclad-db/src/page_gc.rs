@@ -0,0 +1,122 @@
+//! Page GC: Reclaiming Orphaned Data Pages
+//!
+//! Page IDs are handed out in order and never returned: a deleted key's
+//! page, or a page allocated by a write that crashed before the index
+//! pointed at it, stays allocated for good. `KVStore::collect_pages` finds
+//! them by mark and sweep:
+//!
+//! - mark: every page the index points at, the superblock's inline run,
+//!   the free inline runs and the pages reserved for a replay segment
+//! - sweep: every other page below the next page ID goes on the free list
+//!
+//! New keys take the lowest free page before a fresh one. The free list is
+//! only kept in memory, but it can always be swept for again: the index
+//! and the next page ID come back from the checkpoint's index snapshot and
+//! the WAL. With `page_gc_on_checkpoint`, recovery sweeps before the store
+//! opens; otherwise call `collect_pages` again after a restart.
+//!
+//! Reusing pages means the data blob no longer matches the layout a replay
+//! from the start of the log would build, so collecting clears the replay
+//! marker first (see replay.rs): an interrupted recovery then starts over
+//! instead of trusting pages that may since hold other keys.
+//!
+//! With `StoreOptions::page_gc_on_checkpoint`, every checkpoint collects.
+//!
+//! Values larger than a page are refused, not spread over overflow pages,
+//! so every page belongs to at most one key and no chains need marking.
+
+use anyhow::Result;
+use std::collections::{BTreeSet, HashSet};
+use tracing::info;
+
+use crate::kvstore::KVStore;
+use crate::superblock::FIRST_DATA_PAGE;
+
+/// Outcome of one collection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PageGcReport {
+    /// Data pages allocated so far
+    pub scanned: u64,
+    /// Pages still in use
+    pub reachable: u64,
+    /// Pages this collection added to the free list
+    pub freed: u64,
+    /// Pages on the free list now
+    pub free_pages: u64,
+}
+
+/// Pages from `FIRST_DATA_PAGE` up to `next_page_id` that are neither
+/// reachable nor already free
+pub(crate) fn orphans(next_page_id: u64, reachable: &HashSet<u64>, free: &BTreeSet<u64>) -> Vec<u64> {
+    (FIRST_DATA_PAGE..next_page_id).filter(|page_id| !reachable.contains(page_id) && !free.contains(page_id)).collect()
+}
+
+impl KVStore {
+    /// Find unreachable data pages and put them on the free list
+    pub async fn collect_pages(&self) -> Result<PageGcReport> {
+        let _checkpoint = self.lock_checkpoints().await;
+        self.collect_pages_locked().await
+    }
+
+    /// `collect_pages` for a caller holding `lock_checkpoints`
+    pub(crate) async fn collect_pages_locked(&self) -> Result<PageGcReport> {
+        self.clear_replay_marker().await?;
+        let outside_index = self.pages_outside_index().await;
+
+        let report = {
+            // No key is between allocating its page and indexing it
+            let _allocation = self.page_allocation().write();
+            let next_page_id = self.next_page_id();
+
+            let mut reachable: HashSet<u64> = self.index().pages().into_iter().map(|(_, page_id)| page_id).collect();
+            reachable.extend(outside_index);
+
+            let mut free = self.free_pages().lock();
+            let orphans = orphans(next_page_id, &reachable, &free);
+            free.extend(&orphans);
+            PageGcReport {
+                scanned: next_page_id.saturating_sub(FIRST_DATA_PAGE),
+                reachable: reachable.len() as u64,
+                freed: orphans.len() as u64,
+                free_pages: free.len() as u64,
+            }
+        };
+
+        info!("PAGE GC: freed {} of {} pages ({} free)", report.freed, report.scanned, report.free_pages);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::StoreOptions;
+    use crate::testing::{fresh_options, open_store};
+
+    #[tokio::test]
+    async fn test_free_list_is_rebuilt_at_recovery() {
+        let options = StoreOptions { page_gc_on_checkpoint: true, ..fresh_options() };
+        let store = open_store(options.clone()).await;
+        store.set("a", "1").await.unwrap();
+        store.set("b", "2").await.unwrap();
+        store.delete("a").await.unwrap();
+        store.checkpoint().await.unwrap();
+        let free = store.free_pages().lock().clone();
+        assert!(!free.is_empty());
+        drop(store);
+
+        let store = open_store(options).await;
+        assert!(free.is_subset(&store.free_pages().lock()));
+        let report = store.collect_pages().await.unwrap();
+        assert_eq!(report.freed, 0);
+        assert_eq!(store.get("b").await.unwrap().as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_sweep_skips_reachable_and_free_pages() {
+        let reachable: HashSet<u64> = [1, 3].into();
+        let free: BTreeSet<u64> = [4].into();
+        assert_eq!(orphans(6, &reachable, &free), vec![2, 5]);
+        assert!(orphans(FIRST_DATA_PAGE, &reachable, &free).is_empty());
+    }
+}
@@ -0,0 +1,226 @@
+//! Page Store: Where the Data Pages Live
+//!
+//! The store (kvstore.rs) and the buffer pool own page contents and
+//! write-back; a `PageStore` only reads and writes 4KB pages by ID. Four
+//! backends:
+//!
+//! - `AzureDisk`: an Azure Page Blob (the default; see azure_disk.rs).
+//!   Snapshots are blob snapshots, used by backups, and backup sets are
+//!   kept in its container.
+//! - `LocalDisk`: a local file, for development and CI without a storage
+//!   account (see local_disk.rs).
+//! - `IndexedDbDisk`: IndexedDB, for browser builds with the `wasm`
+//!   feature (see indexed_db.rs).
+//! - `MemoryDisk`: a page map, for tests that shouldn't need a storage
+//!   account. Clones share the pages, so a test can "restart" a store on
+//!   the same data.
+//!
+//! `StoreOptions::page_backend` picks the backend a store opens its data
+//! pages on. With a memory WAL too, a store opens without touching Azure
+//! (the connection string is still parsed, so pass any well-formed one):
+//!
+//! ```ignore
+//! let options = StoreOptions {
+//!     page_backend: PageBackend::Memory(MemoryDisk::new()),
+//!     wal_backend: WalBackend::Memory(MemoryLog::new()),
+//!     ..Default::default()
+//! };
+//! let store = KVStore::open("AccountName=test;AccountKey=test", options).await?;
+//! ```
+//!
+//! A page never written reads as zeros. `write_page` must not return
+//! until the page is durable: checkpoints and the WAL-before-data rule
+//! rely on it.
+//!
+//! Methods return boxed futures (rather than `async fn`) so the store can
+//! hold any backend as `Arc<dyn PageStore>`, as the WAL does its log.
+
+use anyhow::Result;
+use azure_storage_blobs::prelude::*;
+use futures::future::BoxFuture;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::azure_disk::AzureDisk;
+use crate::geo_reads::GeoReadStats;
+use crate::local_disk::LocalDisk;
+use crate::options::StoreOptions;
+use crate::page::PAGE_SIZE;
+
+/// Fixed-size pages backing a store's data
+pub trait PageStore: Send + Sync {
+    /// Read page `page_id` (zeros if never written)
+    fn read_page(&self, page_id: u64) -> BoxFuture<'_, Result<Vec<u8>>>;
+
+    /// Write a whole page, returning once it is durable
+    fn write_page<'a>(&'a self, page_id: u64, data: &'a [u8]) -> BoxFuture<'a, Result<()>>;
+
+    /// Write only bytes `range` of a page (512-byte aligned, see
+    /// `DirtyPage::dirty_range`); `data` is the whole page. Backends that
+    /// can't write part of a page write all of it.
+    fn write_page_range<'a>(&'a self, page_id: u64, data: &'a [u8], _range: Range<usize>) -> BoxFuture<'a, Result<()>> {
+        self.write_page(page_id, data)
+    }
+
+    /// Make every completed write durable
+    fn flush(&self) -> BoxFuture<'_, Result<()>>;
+
+    /// Take a point-in-time copy of the pages; returns its ID
+    fn snapshot(&self) -> BoxFuture<'_, Result<String>> {
+        Box::pin(async { anyhow::bail!("This page store doesn't support snapshots") })
+    }
+
+    /// Delete a snapshot taken with `snapshot`
+    fn delete_snapshot<'a>(&'a self, _snapshot: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async { anyhow::bail!("This page store doesn't support snapshots") })
+    }
+
+    /// Container backup sets are kept in (None = backup sets unsupported)
+    fn container_client(&self) -> Option<&ContainerClient> {
+        None
+    }
+
+    /// Where page reads went, if some may go to a secondary region (see geo_reads.rs)
+    fn geo_read_stats(&self) -> Option<GeoReadStats> {
+        None
+    }
+}
+
+impl PageStore for AzureDisk {
+    fn read_page(&self, page_id: u64) -> BoxFuture<'_, Result<Vec<u8>>> {
+        Box::pin(AzureDisk::read_page(self, page_id))
+    }
+
+    fn write_page<'a>(&'a self, page_id: u64, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(AzureDisk::write_page(self, page_id, data))
+    }
+
+    fn write_page_range<'a>(&'a self, page_id: u64, data: &'a [u8], range: Range<usize>) -> BoxFuture<'a, Result<()>> {
+        Box::pin(AzureDisk::write_page_range(self, page_id, data, range))
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(AzureDisk::flush(self))
+    }
+
+    fn snapshot(&self) -> BoxFuture<'_, Result<String>> {
+        Box::pin(AzureDisk::snapshot(self))
+    }
+
+    fn delete_snapshot<'a>(&'a self, snapshot: &'a str) -> BoxFuture<'a, Result<()>> {
+        Box::pin(AzureDisk::delete_snapshot(self, snapshot))
+    }
+
+    fn container_client(&self) -> Option<&ContainerClient> {
+        Some(AzureDisk::container_client(self))
+    }
+
+    fn geo_read_stats(&self) -> Option<GeoReadStats> {
+        AzureDisk::geo_read_stats(self)
+    }
+}
+
+/// Which page store a store's data uses
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum PageBackend {
+    /// Page blob in the store's container
+    #[default]
+    PageBlob,
+    /// Local file (see local_disk.rs)
+    LocalFile(PathBuf),
+    /// Memory (shared by clones of the `MemoryDisk`)
+    Memory(MemoryDisk),
+    /// IndexedDB database, by name (see indexed_db.rs)
+    #[cfg(feature = "wasm")]
+    IndexedDb(String),
+}
+
+impl PageBackend {
+    /// Open the pages for blob `blob` of the store `options` describe
+    pub(crate) async fn open(&self, connection_string: &str, options: &StoreOptions, blob: &str) -> Result<Arc<dyn PageStore>> {
+        Ok(match self {
+            PageBackend::PageBlob => {
+                let secondary_reads = options.secondary_reads.as_ref();
+                Arc::new(AzureDisk::open(connection_string, &options.container, blob, secondary_reads).await?)
+            }
+            PageBackend::LocalFile(path) => Arc::new(LocalDisk::open(path)?),
+            PageBackend::Memory(disk) => Arc::new(disk.clone()),
+            #[cfg(feature = "wasm")]
+            PageBackend::IndexedDb(name) => {
+                Arc::new(send_wrapper::SendWrapper::new(crate::indexed_db::IndexedDbDisk::open(name)).await?)
+            }
+        })
+    }
+}
+
+/// Pages in memory (see page_store.rs)
+#[derive(Clone, Default)]
+pub struct MemoryDisk {
+    pages: Arc<parking_lot::Mutex<HashMap<u64, Vec<u8>>>>,
+}
+
+impl MemoryDisk {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl std::fmt::Debug for MemoryDisk {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryDisk").field("pages", &self.pages.lock().len()).finish()
+    }
+}
+
+/// Disks are equal when they share pages
+impl PartialEq for MemoryDisk {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.pages, &other.pages)
+    }
+}
+
+impl Eq for MemoryDisk {}
+
+impl PageStore for MemoryDisk {
+    fn read_page(&self, page_id: u64) -> BoxFuture<'_, Result<Vec<u8>>> {
+        let page = self.pages.lock().get(&page_id).cloned().unwrap_or_else(|| vec![0; PAGE_SIZE]);
+        Box::pin(async { Ok(page) })
+    }
+
+    fn write_page<'a>(&'a self, page_id: u64, data: &'a [u8]) -> BoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            if data.len() != PAGE_SIZE {
+                anyhow::bail!("Invalid page size: expected {}, got {}", PAGE_SIZE, data.len());
+            }
+            self.pages.lock().insert(page_id, data.to_vec());
+            Ok(())
+        })
+    }
+
+    fn flush(&self) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_store::MemoryLog;
+    use crate::testing::{memory_options, open_store};
+
+    #[tokio::test]
+    async fn test_store_reopens_on_memory_pages() {
+        let (disk, log) = (MemoryDisk::new(), MemoryLog::new());
+        let store = open_store(memory_options(&disk, &log)).await;
+        store.set("a", "1").await.unwrap();
+        let epoch = store.fencing_token();
+        drop(store);
+
+        // The superblock came back from the pages, the value from the WAL
+        let store = open_store(memory_options(&disk, &log)).await;
+        assert_eq!(store.fencing_token(), epoch + 1);
+        assert_eq!(store.get("a").await.unwrap().as_deref(), Some("1"));
+        assert!(store.disk().snapshot().await.is_err());
+    }
+}
@@ -0,0 +1,175 @@
+//! Patch: Server-Side Edits of JSON Values
+//!
+//! `patch_json` sets one location inside a JSON value, addressed by a JSON
+//! Pointer (RFC 6901), without the caller reading and rewriting the whole
+//! document. The WAL records only the pointer and the new fragment
+//! (`WalEntry::Patch`); recovery re-applies it to the value replayed
+//! before it.
+//!
+//! The read-modify-write runs under the commit lock, so it is atomic with
+//! respect to other patches, conditional writes and transaction commits
+//! (plain `set`/`delete` calls don't take part, as with `compare_and_set`).
+//!
+//! Recovery skips a patch the key's page already reflects (per the page's
+//! metadata trailer), so a patch must leave room in the page for the
+//! trailer: appending to an array twice isn't harmless.
+//!
+//! The pointer follows `serde_json`'s rules, with two additions for the
+//! last token: a missing object member is inserted, and on an array `-`
+//! (or the array's length) appends. The empty pointer replaces the value.
+
+use anyhow::Result;
+use serde_json::Value;
+use tracing::info;
+
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::metadata;
+use crate::page;
+use crate::wal::WalEntry;
+
+impl KVStore {
+    /// Set the location `pointer` inside `key`'s JSON value to `new_value`
+    ///
+    /// Fails with `IronCladError::PatchFailed` if the key doesn't exist, its
+    /// value isn't JSON, or `pointer` doesn't address a settable location.
+    pub async fn patch_json(&self, key: &str, pointer: &str, new_value: Value) -> Result<()> {
+        let _commit_guard = self.lock_commits().await;
+
+        let current = self.get(key).await?;
+        let patched = patched_value(key, current.as_deref(), pointer, new_value.clone())?;
+        // A patch can't be applied to a transformed value: log the whole value
+        if self.transforms_values(key) {
+            return self.set(key, &patched).await;
+        }
+        // Don't log a patch whose result can't be stored
+        self.validate(key, &patched)?;
+        if !page::fits_with_trailer(key, &patched, metadata::TRAILER_LEN) {
+            let reason = "the result leaves no room in the page for the key's metadata".to_string();
+            return Err(IronCladError::PatchFailed { key: key.to_string(), reason }.into());
+        }
+        self.check_quotas(&[(key, Some(patched.as_str()))])?;
+
+        // 1. Log only the patch (DURABILITY POINT)
+        let at_ms = metadata::now_ms();
+        let _logging = self.enter_log_gate("patch").await;
+        let lsn = self.wal().append_entry(WalEntry::Patch {
+            key: key.to_string(),
+            pointer: pointer.to_string(),
+            value: new_value,
+            at_ms,
+        }).await?;
+
+        // 2. Apply the patched value
+        self.apply_logged_set(key, &patched, lsn, at_ms).await?;
+
+        info!("PATCH: {} at {:?}", key, pointer);
+        Ok(())
+    }
+}
+
+/// `current` (the value of `key`) with `new_value` set at `pointer`, as JSON text
+pub(crate) fn patched_value(key: &str, current: Option<&str>, pointer: &str, new_value: Value) -> Result<String> {
+    let failed = |reason: String| IronCladError::PatchFailed { key: key.to_string(), reason };
+
+    let current = current.ok_or_else(|| failed("key not found".to_string()))?;
+    let mut document: Value = serde_json::from_str(current).map_err(|e| failed(format!("value is not JSON: {}", e)))?;
+    set_pointer(&mut document, pointer, new_value).map_err(failed)?;
+    Ok(serde_json::to_string(&document)?)
+}
+
+/// Set the location `pointer` in `document`
+fn set_pointer(document: &mut Value, pointer: &str, new_value: Value) -> std::result::Result<(), String> {
+    if pointer.is_empty() {
+        *document = new_value;
+        return Ok(());
+    }
+
+    let (parent, last) = pointer
+        .rsplit_once('/')
+        .ok_or_else(|| format!("pointer {:?} doesn't start with '/'", pointer))?;
+    let last = last.replace("~1", "/").replace("~0", "~");
+    let parent = document
+        .pointer_mut(parent)
+        .ok_or_else(|| format!("{:?} doesn't exist", parent))?;
+
+    match parent {
+        Value::Object(members) => {
+            members.insert(last, new_value);
+        }
+        Value::Array(items) => {
+            let len = items.len();
+            let index = if last == "-" { len } else { last.parse().map_err(|_| format!("{:?} is not an array index", last))? };
+            if index == len {
+                items.push(new_value);
+            } else {
+                *items.get_mut(index).ok_or_else(|| format!("index {} is out of bounds (length {})", index, len))? = new_value;
+            }
+        }
+        _ => return Err(format!("{:?} is neither an object nor an array", pointer)),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_store::MemoryLog;
+    use crate::page_store::MemoryDisk;
+    use crate::testing::{memory_options, open_store};
+    use serde_json::json;
+
+    fn patch(current: &str, pointer: &str, new_value: Value) -> Result<Value> {
+        Ok(serde_json::from_str(&patched_value("k", Some(current), pointer, new_value)?)?)
+    }
+
+    #[test]
+    fn test_patch_objects_and_arrays() {
+        let doc = r#"{"user":{"name":"a","tags":["x"]},"a/b":1}"#;
+        assert_eq!(patch(doc, "/user/name", json!("b")).unwrap()["user"]["name"], json!("b"));
+        assert_eq!(patch(doc, "/user/age", json!(3)).unwrap()["user"]["age"], json!(3));
+        assert_eq!(patch(doc, "/user/tags/0", json!("y")).unwrap()["user"]["tags"], json!(["y"]));
+        assert_eq!(patch(doc, "/user/tags/-", json!("z")).unwrap()["user"]["tags"], json!(["x", "z"]));
+        assert_eq!(patch(doc, "/a~1b", json!(2)).unwrap()["a/b"], json!(2));
+        assert_eq!(patch(doc, "", json!(null)).unwrap(), json!(null));
+    }
+
+    #[test]
+    fn test_patch_failures_are_typed() {
+        let doc = r#"{"list":[1]}"#;
+        for result in [
+            patched_value("k", None, "/a", json!(1)),
+            patched_value("k", Some("not json"), "/a", json!(1)),
+            patched_value("k", Some(doc), "/missing/a", json!(1)),
+            patched_value("k", Some(doc), "/list/5", json!(1)),
+            patched_value("k", Some(doc), "/list/0/a", json!(1)),
+            patched_value("k", Some(doc), "no-slash", json!(1)),
+        ] {
+            let err = result.unwrap_err();
+            assert!(matches!(err.downcast_ref::<IronCladError>(), Some(IronCladError::PatchFailed { .. })));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_patch_to_the_page_boundary_is_not_replayed_twice() {
+        let (disk, log) = (MemoryDisk::new(), MemoryLog::new());
+        let options = memory_options(&disk, &log);
+        let store = open_store(options.clone()).await;
+        // Pushing "x" onto the list grows the value to fill the page up to the trailer
+        let room = page::PAGE_SIZE - 8 - "doc".len() - metadata::TRAILER_LEN;
+        store.set("doc", &format!(r#"{{"l":["{}"]}}"#, "p".repeat(room - 14))).await.unwrap();
+        store.checkpoint().await.unwrap();
+
+        let err = store.patch_json("doc", "/l/-", json!("xy")).await.unwrap_err();
+        assert!(matches!(err.downcast_ref::<IronCladError>(), Some(IronCladError::PatchFailed { .. })));
+        store.patch_json("doc", "/l/-", json!("x")).await.unwrap();
+        let value = store.get("doc").await.unwrap();
+        assert_eq!(value.as_ref().map(String::len), Some(room));
+        store.flush().await.unwrap();
+        drop(store);
+
+        // The flushed page's trailer shows the patch, so it isn't replayed
+        let store = open_store(options).await;
+        assert_eq!(store.get("doc").await.unwrap(), value);
+    }
+}
@@ -0,0 +1,267 @@
+//! Queue: Durable FIFO Job Queue with Visibility Timeouts
+//!
+//! `StoreQueue` gives at-least-once delivery in the style of Azure Storage
+//! queues:
+//!
+//! ```ignore
+//! let jobs = store.queue("jobs")?;
+//! jobs.enqueue("resize:42").await?;
+//! if let Some(message) = jobs.dequeue_with_lease(Duration::from_secs(30)).await? {
+//!     process(&message.body).await?;
+//!     jobs.ack(&message).await?;
+//! }
+//! ```
+//!
+//! A dequeued message stays in the queue under a lease (the lock layer's
+//! lease record, see lock.rs) and is hidden until the lease expires. `ack`
+//! deletes it; a consumer that crashes or runs past the lease lets the
+//! message reappear for the next `dequeue_with_lease`.
+//!
+//! Layout:
+//!
+//! ```text
+//! __queue/<name>            {"next": ..}   ID of the next enqueued message
+//! __queue/<name>/<id:016x>  {"body": .., "lease": .., "dequeue_count": ..}
+//! ```
+//!
+//! Every operation runs under the commit lock. Dequeue walks the queue's
+//! keys in ID order, so it costs a prefix listing of the index plus a read
+//! per leased message ahead of the first visible one.
+
+use anyhow::Result;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::collections::validate_name;
+use crate::kvstore::KVStore;
+use crate::lock::Lease;
+use crate::metadata::now_ms;
+use crate::wal::WalEntry;
+
+/// Key prefix for queues
+const QUEUE_PREFIX: &str = "__queue/";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+struct QueueMeta {
+    next: u64,
+}
+
+/// Stored form of a message
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct StoredMessage {
+    body: String,
+    #[serde(default)]
+    lease: Option<Lease>,
+    #[serde(default)]
+    dequeue_count: u32,
+}
+
+impl StoredMessage {
+    fn is_visible(&self, now_ms: u64) -> bool {
+        self.lease.as_ref().is_none_or(|lease| lease.is_expired(now_ms))
+    }
+}
+
+/// A dequeued message, leased to the caller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueueMessage {
+    /// Position in the queue (increasing in enqueue order)
+    pub id: u64,
+    pub body: String,
+    /// Identifies this delivery; `ack` only succeeds while it holds the lease
+    pub receipt: String,
+    /// Deliveries so far, this one included
+    pub dequeue_count: u32,
+}
+
+/// Handle to a queue
+pub struct StoreQueue<'a> {
+    store: &'a KVStore,
+    meta_key: String,
+}
+
+impl<'a> StoreQueue<'a> {
+    /// Add a message to the back; returns its ID
+    pub async fn enqueue(&self, body: &str) -> Result<u64> {
+        let _commit_guard = self.store.lock_commits().await;
+
+        let mut meta = self.meta().await?;
+        let id = meta.next;
+        meta.next += 1;
+
+        let message = StoredMessage { body: body.to_string(), lease: None, dequeue_count: 0 };
+        let writes = vec![
+            WalEntry::set(&self.message_key(id), &serde_json::to_string(&message)?),
+            WalEntry::set(&self.meta_key, &serde_json::to_string(&meta)?),
+        ];
+        self.store.commit_batch_locked(self.store.new_txn_id(), writes, None).await?;
+
+        debug!("QUEUE: enqueued {} as {}", self.meta_key, id);
+        Ok(id)
+    }
+
+    /// Lease the oldest visible message, hiding it for `visibility`
+    pub async fn dequeue_with_lease(&self, visibility: Duration) -> Result<Option<QueueMessage>> {
+        let _commit_guard = self.store.lock_commits().await;
+        let now = now_ms();
+
+        for (id, key) in self.message_keys() {
+            let Some(mut message) = self.message(&key).await? else {
+                continue;
+            };
+            if !message.is_visible(now) {
+                continue;
+            }
+
+            let receipt = format!("{:032x}", rand::thread_rng().gen::<u128>());
+            message.lease = Some(Lease::new(&receipt, visibility));
+            message.dequeue_count += 1;
+            let writes = vec![WalEntry::set(&key, &serde_json::to_string(&message)?)];
+            self.store.commit_batch_locked(self.store.new_txn_id(), writes, None).await?;
+
+            debug!("QUEUE: leased {} message {} for {:?}", self.meta_key, id, visibility);
+            return Ok(Some(QueueMessage {
+                id,
+                body: message.body,
+                receipt,
+                dequeue_count: message.dequeue_count,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Delete a processed message; returns false if its lease was lost
+    /// (expired and taken by another consumer, or already acked)
+    pub async fn ack(&self, message: &QueueMessage) -> Result<bool> {
+        let _commit_guard = self.store.lock_commits().await;
+
+        let key = self.message_key(message.id);
+        let held = self
+            .message(&key)
+            .await?
+            .and_then(|stored| stored.lease)
+            .is_some_and(|lease| lease.owner == message.receipt);
+        if !held {
+            return Ok(false);
+        }
+
+        self.store.commit_batch_locked(self.store.new_txn_id(), vec![WalEntry::Delete { key }], None).await?;
+        Ok(true)
+    }
+
+    /// Messages in the queue, leased ones included
+    pub fn len(&self) -> usize {
+        self.message_keys().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    async fn meta(&self) -> Result<QueueMeta> {
+        match self.store.get(&self.meta_key).await? {
+            Some(stored) => Ok(serde_json::from_str(&stored)?),
+            None => Ok(QueueMeta::default()),
+        }
+    }
+
+    async fn message(&self, key: &str) -> Result<Option<StoredMessage>> {
+        match self.store.get(key).await? {
+            Some(stored) => Ok(Some(serde_json::from_str(&stored)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// (ID, key) of every message, in ID order
+    fn message_keys(&self) -> Vec<(u64, String)> {
+        let prefix = format!("{}/", self.meta_key);
+        let mut keys: Vec<(u64, String)> = self
+            .store
+            .keys_with_prefix(&prefix)
+            .into_iter()
+            .filter_map(|key| Some((u64::from_str_radix(&key[prefix.len()..], 16).ok()?, key)))
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    fn message_key(&self, id: u64) -> String {
+        format!("{}/{:016x}", self.meta_key, id)
+    }
+}
+
+impl KVStore {
+    /// Handle to the queue `name` (empty until enqueued to)
+    pub fn queue(&self, name: &str) -> Result<StoreQueue<'_>> {
+        validate_name(name)?;
+        Ok(StoreQueue { store: self, meta_key: format!("{}{}", QUEUE_PREFIX, name) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{fresh_options, open_store};
+
+    #[test]
+    fn test_leased_messages_are_hidden_until_expiry() {
+        let mut message = StoredMessage { body: "job".to_string(), lease: None, dequeue_count: 0 };
+        assert!(message.is_visible(0));
+
+        message.lease = Some(Lease { owner: "receipt".to_string(), expires_at_ms: 1_000 });
+        assert!(!message.is_visible(999));
+        assert!(message.is_visible(1_000));
+    }
+
+    #[test]
+    fn test_stored_message_round_trip() {
+        let message = StoredMessage {
+            body: "job".to_string(),
+            lease: Some(Lease { owner: "r".to_string(), expires_at_ms: 5 }),
+            dequeue_count: 2,
+        };
+        let decoded: StoredMessage = serde_json::from_str(&serde_json::to_string(&message).unwrap()).unwrap();
+        assert_eq!(decoded, message);
+        let fresh: StoredMessage = serde_json::from_str(r#"{"body":"b"}"#).unwrap();
+        assert_eq!(fresh.dequeue_count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_redelivers_through_the_store() {
+        let options = fresh_options();
+        let store = open_store(options.clone()).await;
+        let jobs = store.queue("jobs").unwrap();
+        jobs.enqueue("a").await.unwrap();
+        jobs.enqueue("b").await.unwrap();
+
+        let lease = Duration::from_millis(100);
+        let first = jobs.dequeue_with_lease(lease).await.unwrap().unwrap();
+        let second = jobs.dequeue_with_lease(lease).await.unwrap().unwrap();
+        assert_eq!((first.body.as_str(), second.body.as_str()), ("a", "b"));
+        assert!(jobs.dequeue_with_lease(lease).await.unwrap().is_none());
+
+        // The consumer of "a" ran past its lease: "a" is delivered again
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        let retry = jobs.dequeue_with_lease(Duration::from_secs(60)).await.unwrap().unwrap();
+        assert_eq!((retry.id, retry.dequeue_count), (first.id, 2));
+        assert_ne!(retry.receipt, first.receipt);
+        assert!(!jobs.ack(&first).await.unwrap());
+        assert!(jobs.ack(&retry).await.unwrap());
+        // "b" expired too, but nobody took it, so its lease still holds
+        assert!(jobs.ack(&second).await.unwrap());
+        assert!(jobs.is_empty());
+
+        // Leases are stored with the message, so they outlive a reopen
+        jobs.enqueue("c").await.unwrap();
+        jobs.dequeue_with_lease(Duration::from_secs(60)).await.unwrap().unwrap();
+        store.flush().await.unwrap();
+        drop(jobs);
+        drop(store);
+        let store = open_store(options).await;
+        let jobs = store.queue("jobs").unwrap();
+        assert_eq!(jobs.len(), 1);
+        assert!(jobs.dequeue_with_lease(Duration::from_secs(60)).await.unwrap().is_none());
+    }
+}
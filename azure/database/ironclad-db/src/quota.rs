@@ -0,0 +1,308 @@
+//! Quota: Per-Bucket Limits on Keys and Bytes
+//!
+//! A bucket is the set of keys sharing a prefix (e.g. one tenant's
+//! `tenant42/`). `StoreOptions::quotas` caps how many keys and how many
+//! bytes (keys plus stored values) each bucket may hold:
+//!
+//! ```ignore
+//! let options = StoreOptions {
+//!     quotas: vec![BucketQuota::new("tenant42/").max_keys(10_000).max_bytes(64 << 20)],
+//!     quota_exceeded: Some(QuotaObserver::new(|event| alert(event))),
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! A write that would take a bucket over a limit fails with
+//! `IronCladError::QuotaExceeded` before anything is logged, and the
+//! `quota_exceeded` observer hears about it. Writes that shrink a bucket
+//! are always allowed, so a bucket already over its limit (say, after the
+//! limit was lowered) can be cleaned up.
+//!
+//! Usage is counted as writes are applied, recovery included. Checks and
+//! applies aren't serialized, so concurrent writes to one bucket can
+//! overshoot a limit by the writes in flight; transactions are checked as
+//! a whole.
+
+use anyhow::Result;
+use dashmap::DashMap;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::error::IronCladError;
+use crate::index::key_hash;
+use crate::kvstore::KVStore;
+use crate::wal::WalEntry;
+
+/// Limits for the keys starting with `prefix`
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BucketQuota {
+    pub prefix: String,
+    pub max_keys: Option<u64>,
+    /// Keys plus stored values (after middleware), in bytes
+    pub max_bytes: Option<u64>,
+}
+
+impl BucketQuota {
+    pub fn new(prefix: &str) -> Self {
+        Self { prefix: prefix.to_string(), ..Default::default() }
+    }
+
+    pub fn max_keys(mut self, max_keys: u64) -> Self {
+        self.max_keys = Some(max_keys);
+        self
+    }
+
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+}
+
+/// A bucket's usage against its limits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaUsage {
+    pub quota: BucketQuota,
+    pub keys: u64,
+    pub bytes: u64,
+}
+
+/// A write refused by a quota
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaEvent {
+    pub prefix: String,
+    /// The (first) key whose write was refused
+    pub key: String,
+    /// `"keys"` or `"bytes"`
+    pub resource: &'static str,
+    pub limit: u64,
+    /// Usage the write would have resulted in
+    pub requested: u64,
+}
+
+/// Called when a quota refuses a write
+#[derive(Clone)]
+pub struct QuotaObserver(Arc<dyn Fn(&QuotaEvent) + Send + Sync>);
+
+impl QuotaObserver {
+    pub fn new<F: Fn(&QuotaEvent) + Send + Sync + 'static>(callback: F) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    fn notify(&self, event: &QuotaEvent) {
+        (self.0)(event)
+    }
+}
+
+impl std::fmt::Debug for QuotaObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("QuotaObserver")
+    }
+}
+
+/// Observers are equal when they share the callback
+impl PartialEq for QuotaObserver {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for QuotaObserver {}
+
+/// Usage of each configured bucket
+pub(crate) struct QuotaTracker {
+    quotas: Vec<BucketQuota>,
+    /// (keys, bytes) per quota
+    usage: Vec<(AtomicU64, AtomicU64)>,
+    /// Size of each key in some bucket, by key hash
+    sizes: DashMap<u128, u64>,
+}
+
+impl QuotaTracker {
+    pub fn new(quotas: &[BucketQuota]) -> Self {
+        Self {
+            quotas: quotas.to_vec(),
+            usage: quotas.iter().map(|_| (AtomicU64::new(0), AtomicU64::new(0))).collect(),
+            sizes: DashMap::new(),
+        }
+    }
+
+    fn buckets_of<'a>(&'a self, key: &'a str) -> impl Iterator<Item = usize> + 'a {
+        self.quotas.iter().enumerate().filter(move |(_, quota)| key.starts_with(&quota.prefix)).map(|(i, _)| i)
+    }
+
+    /// `key` now holds `value`
+    pub fn record_set(&self, key: &str, value: &str) {
+        if self.quotas.is_empty() {
+            return;
+        }
+        let size = (key.len() + value.len()) as u64;
+        let previous = self.sizes.insert(key_hash(key), size);
+        for i in self.buckets_of(key) {
+            let (keys, bytes) = &self.usage[i];
+            match previous {
+                Some(previous) => {
+                    bytes.fetch_sub(previous, Ordering::Relaxed);
+                }
+                None => {
+                    keys.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            bytes.fetch_add(size, Ordering::Relaxed);
+        }
+    }
+
+    /// `key` was deleted
+    pub fn record_delete(&self, key: &str) {
+        let Some((_, previous)) = self.sizes.remove(&key_hash(key)) else {
+            return;
+        };
+        for i in self.buckets_of(key) {
+            let (keys, bytes) = &self.usage[i];
+            keys.fetch_sub(1, Ordering::Relaxed);
+            bytes.fetch_sub(previous, Ordering::Relaxed);
+        }
+    }
+
+    /// Would these writes (key, new value or None for a delete), applied
+    /// together, take a bucket over a limit?
+    pub fn check(&self, writes: &[(&str, Option<&str>)]) -> Option<QuotaEvent> {
+        if self.quotas.is_empty() {
+            return None;
+        }
+        // Net change per bucket, with later writes to a key replacing earlier ones
+        let mut sizes: HashMap<&str, Option<u64>> = HashMap::new();
+        let mut deltas = vec![(0i64, 0i64, ""); self.quotas.len()];
+        for &(key, value) in writes {
+            let new = value.map(|value| (key.len() + value.len()) as u64);
+            let old = match sizes.insert(key, new) {
+                Some(pending) => pending,
+                None => self.sizes.get(&key_hash(key)).map(|size| *size),
+            };
+            for i in self.buckets_of(key) {
+                let delta = &mut deltas[i];
+                delta.0 += new.is_some() as i64 - old.is_some() as i64;
+                delta.1 += new.unwrap_or(0) as i64 - old.unwrap_or(0) as i64;
+                if delta.2.is_empty() && new > old {
+                    delta.2 = key;
+                }
+            }
+        }
+
+        for (i, (key_delta, byte_delta, key)) in deltas.into_iter().enumerate() {
+            let quota = &self.quotas[i];
+            let (keys, bytes) = &self.usage[i];
+            let limits = [("keys", quota.max_keys, keys, key_delta), ("bytes", quota.max_bytes, bytes, byte_delta)];
+            for (resource, limit, used, delta) in limits {
+                let Some(limit) = limit else { continue };
+                let requested = used.load(Ordering::Relaxed) as i64 + delta;
+                if delta > 0 && requested > limit as i64 {
+                    return Some(QuotaEvent {
+                        prefix: quota.prefix.clone(),
+                        key: key.to_string(),
+                        resource,
+                        limit,
+                        requested: requested as u64,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    pub fn usage(&self) -> Vec<QuotaUsage> {
+        self.quotas
+            .iter()
+            .zip(&self.usage)
+            .map(|(quota, (keys, bytes))| QuotaUsage {
+                quota: quota.clone(),
+                keys: keys.load(Ordering::Relaxed),
+                bytes: bytes.load(Ordering::Relaxed),
+            })
+            .collect()
+    }
+}
+
+impl KVStore {
+    /// Usage of each bucket in `StoreOptions::quotas`
+    pub fn quota_usage(&self) -> Vec<QuotaUsage> {
+        self.quotas().usage()
+    }
+
+    /// Fail with `QuotaExceeded` if `writes` would take a bucket over a limit
+    pub(crate) fn check_quotas(&self, writes: &[(&str, Option<&str>)]) -> Result<()> {
+        let Some(event) = self.quotas().check(writes) else {
+            return Ok(());
+        };
+        warn!(
+            "QUOTA: write to {} refused, {} would hold {} {} (limit {})",
+            event.key, event.prefix, event.requested, event.resource, event.limit
+        );
+        if let Some(observer) = &self.options().quota_exceeded {
+            observer.notify(&event);
+        }
+        Err(IronCladError::QuotaExceeded { prefix: event.prefix, resource: event.resource, limit: event.limit }.into())
+    }
+
+    /// `check_quotas` for a batch of WAL entries
+    pub(crate) fn check_batch_quotas(&self, writes: &[WalEntry]) -> Result<()> {
+        let changes: Vec<(&str, Option<&str>)> = writes
+            .iter()
+            .filter_map(|write| match write {
+                WalEntry::Set { key, value, .. } => Some((key.as_str(), Some(value.as_str()))),
+                WalEntry::Delete { key } => Some((key.as_str(), None)),
+                _ => None,
+            })
+            .collect();
+        self.check_quotas(&changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tracker() -> QuotaTracker {
+        QuotaTracker::new(&[BucketQuota::new("a/").max_keys(2).max_bytes(20)])
+    }
+
+    #[test]
+    fn test_usage_follows_writes() {
+        let tracker = tracker();
+        tracker.record_set("a/1", "xx");
+        tracker.record_set("a/1", "xxxx");
+        tracker.record_set("b/1", "ignored");
+        assert_eq!((tracker.usage()[0].keys, tracker.usage()[0].bytes), (1, 7));
+
+        tracker.record_delete("a/1");
+        tracker.record_delete("a/1");
+        assert_eq!((tracker.usage()[0].keys, tracker.usage()[0].bytes), (0, 0));
+    }
+
+    #[test]
+    fn test_limits() {
+        let tracker = tracker();
+        tracker.record_set("a/1", "x");
+        tracker.record_set("a/2", "x");
+
+        let event = tracker.check(&[("a/3", Some("x"))]).unwrap();
+        assert_eq!((event.resource, event.limit, event.requested), ("keys", 2, 3));
+        assert_eq!(event.key, "a/3");
+        // Overwrites and other buckets don't add keys
+        assert!(tracker.check(&[("a/1", Some("xxxx")), ("b/9", Some("x"))]).is_none());
+        // A batch that deletes one key may add another
+        assert!(tracker.check(&[("a/2", None), ("a/3", Some("x"))]).is_none());
+
+        let event = tracker.check(&[("a/1", Some("x".repeat(20).as_str()))]).unwrap();
+        assert_eq!(event.resource, "bytes");
+    }
+
+    #[test]
+    fn test_shrinking_is_allowed_over_the_limit() {
+        let tracker = tracker();
+        tracker.record_set("a/1", &"x".repeat(30));
+        assert!(tracker.check(&[("a/1", Some("short"))]).is_none());
+        assert!(tracker.check(&[("a/1", None)]).is_none());
+    }
+}
@@ -0,0 +1,212 @@
+//! Read-Through: The Store as a Durable Cache in Front of a Loader
+//!
+//! `get_or_load` returns a key's value if the store has it, and otherwise
+//! calls a loader (say, a request to an upstream API) and stores what it
+//! returns before returning it:
+//!
+//! ```ignore
+//! let profile = store
+//!     .get_or_load("profile:42", Some(Duration::from_secs(600)), || fetch_profile(42))
+//!     .await?;
+//! ```
+//!
+//! With a TTL, a value last written longer ago than that is loaded again.
+//! Age comes from the key's metadata (see metadata.rs), so it survives
+//! restarts; a key without metadata counts as expired.
+//!
+//! Concurrent misses on one key call the loader once (singleflight): the
+//! first caller loads while the others wait, then read what it stored. If
+//! the load fails, only the caller that ran it sees the error; the next
+//! waiter tries its own loader.
+//!
+//! Values written by `get_or_load` are ordinary writes: they are logged,
+//! replicated and readable with `get`.
+//!
+//! # Negative caching
+//!
+//! A loader that can find nothing (the upstream entity doesn't exist)
+//! goes through `get_or_try_load`, whose loader returns an `Option`. With
+//! `LoadPolicy::negative_ttl` set, a miss stores an absent marker under
+//! `__absent/<key>`, and lookups within that TTL return None without
+//! calling the loader:
+//!
+//! ```ignore
+//! let policy = LoadPolicy { ttl: Some(Duration::from_secs(600)), negative_ttl: Some(Duration::from_secs(30)) };
+//! let profile = store.get_or_try_load("profile:42", policy, || fetch_profile_if_any(42)).await?;
+//! ```
+//!
+//! A marker is replaced on the next load after it expires, and deleted
+//! once a load finds a value, or writing the key itself hides it. So only
+//! keys still being asked about keep markers.
+
+use anyhow::Result;
+use dashmap::DashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing::debug;
+
+use crate::kvstore::KVStore;
+use crate::metadata::{now_ms, KeyMetadata};
+
+/// Key prefix for absent markers
+const ABSENT_PREFIX: &str = "__absent/";
+
+/// How long loaded values and misses are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LoadPolicy {
+    /// Load values older than this again (None = keep them)
+    pub ttl: Option<Duration>,
+    /// Remember a miss for this long (None = don't, call the loader on
+    /// every lookup of a missing key)
+    pub negative_ttl: Option<Duration>,
+}
+
+/// Keys being loaded, each with a lock its loader holds
+#[derive(Debug, Default)]
+pub(crate) struct InFlightLoads {
+    loads: DashMap<String, Arc<tokio::sync::Mutex<()>>>,
+}
+
+impl InFlightLoads {
+    /// Join (or start) the loads of `key`, until the ticket is dropped
+    fn join(&self, key: &str) -> LoadTicket<'_> {
+        let load = self.loads.entry(key.to_string()).or_default().clone();
+        LoadTicket { loads: self, key: key.to_string(), load }
+    }
+
+    /// Keys with a load in flight or waiters
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.loads.len()
+    }
+}
+
+/// A caller's place in the loads of a key; dropping it (even when the
+/// caller is cancelled) forgets the key once nobody else waits
+struct LoadTicket<'a> {
+    loads: &'a InFlightLoads,
+    key: String,
+    load: Arc<tokio::sync::Mutex<()>>,
+}
+
+impl Drop for LoadTicket<'_> {
+    fn drop(&mut self) {
+        // The map's reference and ours
+        self.loads
+            .loads
+            .remove_if(&self.key, |_, load| Arc::ptr_eq(load, &self.load) && Arc::strong_count(load) <= 2);
+    }
+}
+
+impl KVStore {
+    /// `key`'s value, or else the one `loader` returns, stored first;
+    /// values older than `ttl` are loaded again (see read_through.rs)
+    pub async fn get_or_load<F, Fut>(&self, key: &str, ttl: Option<Duration>, loader: F) -> Result<String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
+        let policy = LoadPolicy { ttl, negative_ttl: None };
+        let value = self.get_or_try_load(key, policy, || async { loader().await.map(Some) }).await?;
+        Ok(value.expect("loader always returns a value"))
+    }
+
+    /// Like `get_or_load`, for a loader that may find nothing; misses are
+    /// remembered for `policy.negative_ttl` (see read_through.rs)
+    pub async fn get_or_try_load<F, Fut>(&self, key: &str, policy: LoadPolicy, loader: F) -> Result<Option<String>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Option<String>>>,
+    {
+        let marker = format!("{}{}", ABSENT_PREFIX, key);
+        if let Some(cached) = self.get_cached(key, &marker, policy).await? {
+            return Ok(cached);
+        }
+
+        let ticket = self.in_flight_loads().join(key);
+        let _loading = ticket.load.lock().await;
+        // Another caller may have loaded it while we waited
+        if let Some(cached) = self.get_cached(key, &marker, policy).await? {
+            return Ok(cached);
+        }
+        debug!("GET_OR_LOAD: loading {}", key);
+        let value = loader().await?;
+        match (&value, policy.negative_ttl) {
+            (Some(value), _) => self.set(key, value).await?,
+            (None, Some(_)) => self.set(&marker, "").await?,
+            (None, None) => {}
+        }
+        if (value.is_some() || policy.negative_ttl.is_none()) && self.index().get(&marker).is_some() {
+            self.delete(&marker).await?;
+        }
+        Ok(value)
+    }
+
+    /// What the store holds for `key` under `policy`: Some(value), Some(None)
+    /// for a remembered miss, None if it must be loaded
+    async fn get_cached(&self, key: &str, marker: &str, policy: LoadPolicy) -> Result<Option<Option<String>>> {
+        if let Some(value) = self.get_fresh(key, policy.ttl).await? {
+            return Ok(Some(Some(value)));
+        }
+        if let Some(negative_ttl) = policy.negative_ttl {
+            if self.get_fresh(marker, Some(negative_ttl)).await?.is_some() {
+                return Ok(Some(None));
+            }
+        }
+        Ok(None)
+    }
+
+    /// `key`'s value unless it is missing or older than `ttl`
+    async fn get_fresh(&self, key: &str, ttl: Option<Duration>) -> Result<Option<String>> {
+        if let Some(ttl) = ttl {
+            if !is_fresh(self.metadata(key).await?, ttl, now_ms()) {
+                return Ok(None);
+            }
+        }
+        self.get(key).await
+    }
+}
+
+/// Was the key last written within `ttl` of `now_ms`? (No metadata: no)
+fn is_fresh(metadata: Option<KeyMetadata>, ttl: Duration, now_ms: u64) -> bool {
+    metadata.is_some_and(|metadata| now_ms.saturating_sub(metadata.updated_ms) < ttl.as_millis() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_waiters_queue_behind_one_load_and_are_forgotten() {
+        let loads = Arc::new(InFlightLoads::default());
+        let first = loads.join("k");
+        let guard = first.load.lock().await;
+
+        let waiter = {
+            let loads = loads.clone();
+            tokio::spawn(async move {
+                let ticket = loads.join("k");
+                drop(ticket.load.lock().await);
+            })
+        };
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        // The leader leaves while a waiter still holds on: the entry stays
+        drop(guard);
+        drop(first);
+        assert_eq!(loads.len(), 1);
+        waiter.await.unwrap();
+        assert_eq!(loads.len(), 0);
+    }
+
+    #[test]
+    fn test_freshness_by_last_write() {
+        let written_at = |updated_ms| Some(KeyMetadata { created_ms: 0, updated_ms, update_count: 1, last_lsn: 1 });
+        let ttl = Duration::from_secs(30);
+        assert!(is_fresh(written_at(1_000), ttl, 30_999));
+        assert!(!is_fresh(written_at(1_000), ttl, 31_000));
+        assert!(!is_fresh(None, ttl, 0));
+    }
+}
@@ -0,0 +1,192 @@
+//! Readback: Sampled Verification Reads After Write-Back
+//!
+//! A write the blob service acknowledged is trusted from then on. To catch
+//! a persistence path that loses or mangles pages anyway (a misbehaving
+//! proxy, a storage incident, a bug in our own write path), a store with
+//! `StoreOptions::readback_one_in` set re-reads a random sample of the
+//! pages it writes back (one in that many) and compares their checksums
+//! with what was written.
+//!
+//! The read happens on a background task, so write-back doesn't wait for
+//! it. A page written again before its check reads it is skipped: the old
+//! content is expected to be gone. On a mismatch, the store:
+//!
+//! 1. logs an error and reports it to the event listeners (operation
+//!    "readback", see events.rs)
+//! 2. repairs the page by writing the expected content again, unless the
+//!    page was written since (repairs hold off other page writes)
+//!
+//! Counters are in `KVStoreStats::readback`.
+
+use dashmap::DashMap;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{debug, error};
+
+use crate::kvstore::KVStore;
+
+/// Verification read counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ReadbackStats {
+    /// Pages re-read and found intact
+    pub verified: u64,
+    /// Pages whose content differed from what was written
+    pub mismatches: u64,
+    /// Mismatched pages written again
+    pub repaired: u64,
+    /// Checks skipped because the page was written again first
+    pub superseded: u64,
+    /// Checks whose read failed
+    pub read_errors: u64,
+}
+
+/// Readback state of a store
+#[derive(Default)]
+pub(crate) struct Readback {
+    /// Sampled pages awaiting their check, with the write being checked
+    pending: DashMap<u64, u64>,
+    next_write: AtomicU64,
+    verified: AtomicU64,
+    mismatches: AtomicU64,
+    repaired: AtomicU64,
+    superseded: AtomicU64,
+    read_errors: AtomicU64,
+    /// Held shared by page writes, exclusively by a repair
+    writes: tokio::sync::RwLock<()>,
+}
+
+impl Readback {
+    /// Hold while writing a page; a repair waits for it
+    pub async fn write_guard(&self) -> tokio::sync::RwLockReadGuard<'_, ()> {
+        self.writes.read().await
+    }
+
+    /// A page is about to be written (call with `write_guard` held): a
+    /// check still pending for it would compare against stale content
+    pub fn note_write(&self, page_id: u64) {
+        if self.pending.remove(&page_id).is_some() {
+            self.superseded.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Start tracking a check of a write about to be made (call with
+    /// `write_guard` held); returns its ID
+    fn track(&self, page_id: u64) -> u64 {
+        let write = self.next_write.fetch_add(1, Ordering::Relaxed);
+        self.pending.insert(page_id, write);
+        write
+    }
+
+    /// Is the write still the page's latest?
+    fn still_current(&self, page_id: u64, write: u64) -> bool {
+        self.pending.get(&page_id).is_some_and(|pending| *pending == write)
+    }
+
+    fn finish(&self, page_id: u64, write: u64) {
+        self.pending.remove_if(&page_id, |_, pending| *pending == write);
+    }
+
+    pub fn stats(&self) -> ReadbackStats {
+        ReadbackStats {
+            verified: self.verified.load(Ordering::Relaxed),
+            mismatches: self.mismatches.load(Ordering::Relaxed),
+            repaired: self.repaired.load(Ordering::Relaxed),
+            superseded: self.superseded.load(Ordering::Relaxed),
+            read_errors: self.read_errors.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Should a write be checked, sampling one in `one_in` (0 = never)?
+pub(crate) fn sampled(one_in: u64) -> bool {
+    one_in > 0 && rand::thread_rng().gen_range(0..one_in) == 0
+}
+
+impl KVStore {
+    /// Maybe pick the write-back of `page_id` about to be made for a check
+    /// (call with `write_guard` held); returns the write's ID if picked
+    pub(crate) fn sample_write_back(&self, page_id: u64) -> Option<u64> {
+        sampled(self.options().readback_one_in).then(|| self.readback().track(page_id))
+    }
+
+    /// Check in the background that the picked write left `data` on disk
+    /// (`written` = did the write succeed?)
+    pub(crate) fn schedule_check(&self, page_id: u64, write: u64, data: &[u8], written: bool) {
+        if !written {
+            self.readback().finish(page_id, write);
+            return;
+        }
+        let expected = data.to_vec();
+        let store = self.clone();
+        self.options().runtime.spawn(async move {
+            store.check_page(page_id, write, expected).await;
+        });
+    }
+
+    async fn check_page(&self, page_id: u64, write: u64, expected: Vec<u8>) {
+        let readback = self.readback();
+        let read = self.disk().read_page(page_id).await;
+        if !readback.still_current(page_id, write) {
+            return;
+        }
+        let actual = match read {
+            Ok(actual) => actual,
+            Err(e) => {
+                readback.finish(page_id, write);
+                readback.read_errors.fetch_add(1, Ordering::Relaxed);
+                debug!("READBACK: couldn't read page {}: {:#}", page_id, e);
+                return;
+            }
+        };
+        if Sha256::digest(&actual) == Sha256::digest(&expected) {
+            readback.finish(page_id, write);
+            readback.verified.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+
+        readback.mismatches.fetch_add(1, Ordering::Relaxed);
+        let mismatch = anyhow::anyhow!("page {} read back differs from what was written", page_id);
+        error!("READBACK: {}, repairing", mismatch);
+        self.options().listeners.error("readback", &mismatch);
+
+        // A write since the check makes the repair moot, and overwriting it
+        // would lose it
+        let _writes = readback.writes.write().await;
+        if !readback.still_current(page_id, write) {
+            return;
+        }
+        match self.disk().write_page(page_id, &expected).await {
+            Ok(()) => {
+                readback.repaired.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => error!("READBACK: repair of page {} failed: {:#}", page_id, e),
+        }
+        readback.finish(page_id, write);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_a_newer_write_supersedes_the_check() {
+        let readback = Readback::default();
+        let first = readback.track(7);
+        readback.note_write(7);
+        assert!(!readback.still_current(7, first));
+        assert_eq!(readback.stats().superseded, 1);
+
+        let second = readback.track(7);
+        assert!(readback.still_current(7, second));
+        readback.finish(7, second);
+        assert!(!readback.still_current(7, second));
+    }
+
+    #[test]
+    fn test_sampling_bounds() {
+        assert!(!sampled(0));
+        assert!(sampled(1));
+    }
+}
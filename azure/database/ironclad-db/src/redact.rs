@@ -0,0 +1,98 @@
+//! Redact: Keeping Values Out of Logs
+//!
+//! Writes and reads are logged with their key and value, and values may
+//! hold personal data. `StoreOptions::value_logging` decides what of a
+//! value reaches the logs (the store and its WAL honor it):
+//!
+//! - `Full`: the value itself (the default)
+//! - `KeyOnly`: nothing, just `<redacted>`
+//! - `Hash`: a hash and the length, enough to tell values apart
+//! - `Truncate(n)`: the first `n` characters and the length
+//!
+//! Keys are always logged in full.
+
+use std::fmt;
+
+use crate::superblock::checksum;
+use crate::wal::WalEntry;
+
+/// How values appear in logs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueLogging {
+    #[default]
+    Full,
+    KeyOnly,
+    Hash,
+    Truncate(usize),
+}
+
+impl ValueLogging {
+    /// `value` as it should be logged
+    pub fn show<'a>(&self, value: &'a str) -> Redacted<'a> {
+        Redacted { logging: *self, value }
+    }
+
+    /// A WAL entry as it should be logged
+    pub(crate) fn entry(&self, entry: &WalEntry) -> String {
+        match entry {
+            WalEntry::Set { key, value, .. } => format!("SET {}={}", key, self.show(value)),
+            WalEntry::Patch { key, pointer, value, .. } => {
+                format!("PATCH {} at {:?} = {}", key, pointer, self.show(&value.to_string()))
+            }
+            other => format!("{:?}", other),
+        }
+    }
+}
+
+/// A value formatted per a `ValueLogging`
+pub struct Redacted<'a> {
+    logging: ValueLogging,
+    value: &'a str,
+}
+
+impl fmt::Display for Redacted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.logging {
+            ValueLogging::Full => f.write_str(self.value),
+            ValueLogging::KeyOnly => f.write_str("<redacted>"),
+            ValueLogging::Hash => {
+                write!(f, "<{} bytes, hash {:016x}>", self.value.len(), checksum(self.value.as_bytes()))
+            }
+            ValueLogging::Truncate(max_chars) => match self.value.char_indices().nth(max_chars) {
+                Some((end, _)) => write!(f, "{}…<{} bytes>", &self.value[..end], self.value.len()),
+                None => f.write_str(self.value),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_value_logging_modes() {
+        let value = "alice@example.com";
+        assert_eq!(ValueLogging::Full.show(value).to_string(), value);
+        assert_eq!(ValueLogging::KeyOnly.show(value).to_string(), "<redacted>");
+        assert_eq!(ValueLogging::Truncate(5).show(value).to_string(), "alice…<17 bytes>");
+        assert_eq!(ValueLogging::Truncate(50).show(value).to_string(), value);
+
+        let hashed = ValueLogging::Hash.show(value).to_string();
+        assert!(hashed.starts_with("<17 bytes, hash ") && !hashed.contains("alice"));
+        assert_eq!(hashed, ValueLogging::Hash.show(value).to_string());
+    }
+
+    #[test]
+    fn test_truncate_respects_char_boundaries() {
+        assert_eq!(ValueLogging::Truncate(2).show("héllo").to_string(), "hé…<6 bytes>");
+    }
+
+    #[test]
+    fn test_entries_hide_values() {
+        let entry = WalEntry::set("user:1", "secret");
+        assert_eq!(ValueLogging::KeyOnly.entry(&entry), "SET user:1=<redacted>");
+        let delete = WalEntry::Delete { key: "user:1".to_string() };
+        assert!(ValueLogging::KeyOnly.entry(&delete).contains("user:1"));
+    }
+}
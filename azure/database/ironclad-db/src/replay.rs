@@ -0,0 +1,186 @@
+//! Replay: Progress Reporting and Resumable Recovery
+//!
+//! Replaying a large WAL can take minutes. Recovery reports its progress
+//! through the `replay` tracing span and periodic log lines, and to the
+//! `StoreOptions::replay_progress` observer if one is set: entries and log
+//! bytes replayed, and an ETA from the rate so far.
+//!
+//! # Parallel replay
+//!
+//! With `StoreOptions::replay_workers` above one, entries are replayed in
+//! segments, each split by key hash across worker tasks: a key's entries
+//! all go to one worker, in log order, so each key still sees its writes
+//! in order. Before a segment runs, the pages of the keys it creates are
+//! assigned in log order, so the page layout matches a sequential replay.
+//! That needs every value to go to a page, so with `inline_threshold` set
+//! replay is sequential.
+//!
+//! # Resuming
+//!
+//! Every `StoreOptions::replay_marker_interval` entries, recovery flushes
+//! the pages it has rebuilt and persists a `ReplayMarker` in the superblock:
+//! every page in the data blob now reflects the log up to `marker.lsn`. A
+//! recovery that is interrupted and restarted on the same log (same first
+//! LSN) only rebuilds the index for entries up to the marker, without
+//! building or writing their pages, then replays the rest as usual.
+//!
+//! Only the index needs rebuilding because entries up to the marker don't
+//! need their values, except to decide whether a value is inlined: with
+//! `inline_threshold` set, recovery always starts from the beginning.
+//!
+//! Patches read their base value, which may be a page written
+//! back after the marker. Such a page's metadata trailer (see metadata.rs)
+//! records the LSN of its last write, so an entry the page already reflects
+//! is skipped rather than applied twice.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::index::key_hash;
+use crate::wal::WalEntry;
+
+/// How far an earlier recovery got, persisted in the superblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ReplayMarker {
+    /// LSN of the first record in the log the marker belongs to
+    pub log_start_lsn: u64,
+    /// Every page in the data blob reflects the log up to this LSN
+    pub lsn: u64,
+}
+
+impl ReplayMarker {
+    /// LSN up to which a replay of a log starting at `log_start_lsn` and
+    /// ending at `last_lsn` can skip pages (0 = replay everything)
+    pub fn resume_lsn(marker: Option<&ReplayMarker>, log_start_lsn: u64, last_lsn: u64) -> u64 {
+        match marker {
+            Some(marker) if marker.log_start_lsn == log_start_lsn && marker.lsn <= last_lsn => marker.lsn,
+            _ => 0,
+        }
+    }
+}
+
+/// Progress of a WAL replay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReplayProgress {
+    pub entries_replayed: u64,
+    pub entries_total: u64,
+    /// Log bytes up to the last replayed entry
+    pub bytes_replayed: u64,
+    pub bytes_total: u64,
+    /// Entries up to this LSN only rebuilt the index (see the module docs; 0 = none)
+    pub resumed_from_lsn: u64,
+    pub elapsed: Duration,
+    /// Time left at the rate so far (None until there is a rate)
+    pub eta: Option<Duration>,
+}
+
+impl ReplayProgress {
+    /// Progress after replaying up to byte `bytes_replayed`, of which the
+    /// first `bytes_at_start` cost nothing (skipped or index-only)
+    pub(crate) fn new(
+        (entries_replayed, entries_total): (u64, u64),
+        (bytes_replayed, bytes_total): (u64, u64),
+        bytes_at_start: u64,
+        resumed_from_lsn: u64,
+        elapsed: Duration,
+    ) -> Self {
+        let done = bytes_replayed.saturating_sub(bytes_at_start);
+        let remaining = bytes_total.saturating_sub(bytes_replayed);
+        let eta = (done > 0).then(|| elapsed.mul_f64(remaining as f64 / done as f64));
+        Self {
+            entries_replayed,
+            entries_total,
+            bytes_replayed,
+            bytes_total,
+            resumed_from_lsn,
+            elapsed,
+            eta,
+        }
+    }
+}
+
+/// Callback receiving replay progress
+#[derive(Clone)]
+pub struct ReplayObserver(Arc<dyn Fn(&ReplayProgress) + Send + Sync>);
+
+impl ReplayObserver {
+    pub fn new<F: Fn(&ReplayProgress) + Send + Sync + 'static>(callback: F) -> Self {
+        Self(Arc::new(callback))
+    }
+
+    pub(crate) fn notify(&self, progress: &ReplayProgress) {
+        (self.0)(progress)
+    }
+}
+
+impl std::fmt::Debug for ReplayObserver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ReplayObserver")
+    }
+}
+
+/// Observers are equal when they share the callback
+impl PartialEq for ReplayObserver {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ReplayObserver {}
+
+/// Split entries into `workers` partitions by key hash, keeping log order
+/// within each; entries without a key go to the first
+pub(crate) fn partition_by_key(entries: Vec<(u64, WalEntry)>, workers: usize) -> Vec<Vec<(u64, WalEntry)>> {
+    let mut partitions: Vec<Vec<(u64, WalEntry)>> = (0..workers).map(|_| Vec::new()).collect();
+    for (lsn, entry) in entries {
+        let partition = entry.key().map_or(0, |key| (key_hash(key) % workers as u128) as usize);
+        partitions[partition].push((lsn, entry));
+    }
+    partitions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_partitions_keep_each_keys_order() {
+        let entries: Vec<(u64, WalEntry)> = (1..=40)
+            .map(|lsn| (lsn, WalEntry::set(&format!("key{}", lsn % 5), &lsn.to_string())))
+            .collect();
+        let partitions = partition_by_key(entries, 3);
+        assert_eq!(partitions.iter().map(Vec::len).sum::<usize>(), 40);
+
+        for partition in &partitions {
+            assert!(partition.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        }
+        // A key never spans partitions
+        for key in 0..5 {
+            let key = format!("key{}", key);
+            let holding = partitions.iter().filter(|p| p.iter().any(|(_, e)| e.key() == Some(&key))).count();
+            assert_eq!(holding, 1);
+        }
+    }
+
+    #[test]
+    fn test_resume_lsn_needs_the_same_log() {
+        let marker = ReplayMarker { log_start_lsn: 101, lsn: 500 };
+        assert_eq!(ReplayMarker::resume_lsn(Some(&marker), 101, 900), 500);
+        // Log was reset by a checkpoint since
+        assert_eq!(ReplayMarker::resume_lsn(Some(&marker), 1, 900), 0);
+        // Log ends before the marker (not the log it was written for)
+        assert_eq!(ReplayMarker::resume_lsn(Some(&marker), 101, 400), 0);
+        assert_eq!(ReplayMarker::resume_lsn(None, 101, 900), 0);
+    }
+
+    #[test]
+    fn test_eta_from_rate_since_start() {
+        let progress = ReplayProgress::new((50, 100), (600, 1_000), 200, 0, Duration::from_secs(4));
+        // 400 bytes in 4s, 400 left
+        assert_eq!(progress.eta, Some(Duration::from_secs(4)));
+
+        let fresh = ReplayProgress::new((0, 100), (200, 1_000), 200, 0, Duration::ZERO);
+        assert_eq!(fresh.eta, None);
+    }
+}
@@ -0,0 +1,150 @@
+//! Request Tags: Correlating Blob Requests with Azure Diagnostics
+//!
+//! Every blob request carries an `x-ms-client-request-id` naming the store
+//! and the operation:
+//!
+//! ```text
+//! ironclad/<container>/<operation>/<32 hex digits>
+//! ```
+//!
+//! e.g. `ironclad/ironclad-db/PUT-appendblock/5f0c…`. Azure Storage records
+//! it in its diagnostics logs next to its own `x-ms-request-id`, and the
+//! ID stays the same across the SDK's retries of one call.
+//!
+//! When a call fails, the error gains both IDs (the server one from the
+//! last response received), so a support case can start from the error
+//! message alone. Requests are also traced at debug level with both IDs.
+//!
+//! Like storage_metrics.rs, these are pipeline policies installed on every
+//! blob client by `ConnectionConfig::container_client`.
+
+use azure_core::headers::{CLIENT_REQUEST_ID, REQUEST_ID};
+use azure_core::{Context, Policy, PolicyResult, Request};
+use parking_lot::Mutex;
+use rand::Rng;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tracing::debug;
+
+use crate::storage_metrics::operation_name;
+
+/// Server request ID of the last response to the current call
+struct ServerRequestId(Mutex<Option<String>>);
+
+/// Per-call policy: tags the request and annotates errors with request IDs
+#[derive(Debug)]
+pub(crate) struct RequestTagPolicy {
+    container: String,
+}
+
+impl RequestTagPolicy {
+    pub fn new(container: &str) -> Self {
+        Self { container: container.to_string() }
+    }
+
+    /// A fresh client request ID for an `operation` request
+    fn client_request_id(&self, operation: &str) -> String {
+        format!(
+            "ironclad/{}/{}/{:032x}",
+            self.container,
+            operation.replace(' ', "-"),
+            rand::thread_rng().gen::<u128>()
+        )
+    }
+}
+
+impl Policy for RequestTagPolicy {
+    fn send<'life0, 'life1, 'life2, 'life3, 'async_trait>(
+        &'life0 self,
+        ctx: &'life1 Context,
+        request: &'life2 mut Request,
+        next: &'life3 [Arc<dyn Policy>],
+    ) -> Pin<Box<dyn Future<Output = PolicyResult> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        'life3: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            let operation = operation_name(request);
+            // Keep an ID the caller set
+            let client_request_id = match request.headers().get_optional_string(&CLIENT_REQUEST_ID) {
+                Some(id) => id,
+                None => {
+                    let id = self.client_request_id(&operation);
+                    request.insert_header(CLIENT_REQUEST_ID, id.clone());
+                    id
+                }
+            };
+            let mut ctx = ctx.clone();
+            ctx.insert(ServerRequestId(Mutex::new(None)));
+
+            let result = next[0].send(&ctx, request, &next[1..]).await;
+
+            let server_request_id = ctx.get::<ServerRequestId>().and_then(|id| id.0.lock().clone());
+            let server_request_id = server_request_id.as_deref().unwrap_or("none");
+            match result {
+                Ok(response) => {
+                    debug!(
+                        "AZURE: {} {} (client request {}, server request {})",
+                        operation,
+                        response.status(),
+                        client_request_id,
+                        server_request_id
+                    );
+                    Ok(response)
+                }
+                Err(error) => Err(error.context(format!(
+                    "{} failed (client request ID {}, server request ID {})",
+                    operation, client_request_id, server_request_id
+                ))),
+            }
+        })
+    }
+}
+
+/// Per-retry policy: remembers the server request ID of each response
+#[derive(Debug)]
+pub(crate) struct ServerRequestIdPolicy;
+
+impl Policy for ServerRequestIdPolicy {
+    fn send<'life0, 'life1, 'life2, 'life3, 'async_trait>(
+        &'life0 self,
+        ctx: &'life1 Context,
+        request: &'life2 mut Request,
+        next: &'life3 [Arc<dyn Policy>],
+    ) -> Pin<Box<dyn Future<Output = PolicyResult> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        'life3: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            let result = next[0].send(ctx, request, &next[1..]).await;
+            if let (Ok(response), Some(slot)) = (&result, ctx.get::<ServerRequestId>()) {
+                *slot.0.lock() = response.headers().get_optional_string(&REQUEST_ID);
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_request_id_names_store_and_operation() {
+        let policy = RequestTagPolicy::new("orders");
+        let id = policy.client_request_id("PUT appendblock");
+        let (prefix, random) = id.rsplit_once('/').unwrap();
+        assert_eq!(prefix, "ironclad/orders/PUT-appendblock");
+        assert_eq!(random.len(), 32);
+        assert_ne!(policy.client_request_id("GET"), policy.client_request_id("GET"));
+    }
+}
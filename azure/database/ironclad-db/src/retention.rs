@@ -0,0 +1,217 @@
+//! Retention: One Engine for Everything the Store Reclaims
+//!
+//! Several features keep data around for a while and need it deleted
+//! later, each under its own policy in `StoreOptions`:
+//!
+//! | Kind               | Kept under      | Reclaimed when                                    |
+//! |--------------------|-----------------|---------------------------------------------------|
+//! | `OldVersion`       | `__versions/`   | over `version_retention` (see versions.rs)        |
+//! | `Expired`          | `__cfd/`        | a column family value's TTL ran out               |
+//! | `IdempotencyToken` | `__idem/`       | older than `idempotency_window`                   |
+//! | `Trash`            | `__trash/`      | older than `trash_retention` (see trash.rs)       |
+//! | `WalArchive`       | `__backup/`     | a backup `backup_retention` doesn't keep          |
+//!
+//! The store keeps no archive of the WAL a checkpoint clears; the only
+//! copies of old log are the WAL snapshots backups take, so `WalArchive`
+//! covers backups (both their snapshots are deleted).
+//!
+//! `retention_report` is a dry run: it lists what would be deleted, with
+//! sizes, and deletes nothing. `enforce_retention` deletes it, then
+//! collects the pages it freed (see page_gc.rs):
+//!
+//! ```ignore
+//! let report = store.retention_report(&ReclaimKind::ALL).await?;
+//! println!("{} trash entries, {} bytes", report.count(ReclaimKind::Trash), report.bytes());
+//! store.enforce_retention(&[ReclaimKind::Trash, ReclaimKind::Expired]).await?;
+//! ```
+//!
+//! Each key is checked again under the commit lock before it is deleted,
+//! so one restored or rewritten since the report is left alone; the report
+//! `enforce_retention` returns lists only what was deleted.
+
+use anyhow::Result;
+use tracing::info;
+
+use crate::column_family;
+use crate::idempotency::TOKEN_PREFIX;
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+use crate::page_gc::PageGcReport;
+use crate::trash::TRASH_PREFIX;
+use crate::versions::VERSIONS_PREFIX;
+
+/// What a reclaimed entry is (see retention.rs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReclaimKind {
+    /// A prior version of a key
+    OldVersion,
+    /// A column family value past its TTL
+    Expired,
+    /// An idempotency token past its window
+    IdempotencyToken,
+    /// A deleted key past its trash retention
+    Trash,
+    /// A backup, with its data and WAL snapshots
+    WalArchive,
+}
+
+impl ReclaimKind {
+    pub const ALL: [ReclaimKind; 5] = [
+        ReclaimKind::OldVersion,
+        ReclaimKind::Expired,
+        ReclaimKind::IdempotencyToken,
+        ReclaimKind::Trash,
+        ReclaimKind::WalArchive,
+    ];
+
+    /// Key prefix the kind's entries are stored under
+    fn prefix(&self) -> &'static str {
+        match self {
+            ReclaimKind::OldVersion => VERSIONS_PREFIX,
+            ReclaimKind::Expired => column_family::DATA_PREFIX,
+            ReclaimKind::IdempotencyToken => TOKEN_PREFIX,
+            ReclaimKind::Trash => TRASH_PREFIX,
+            ReclaimKind::WalArchive => "",
+        }
+    }
+}
+
+/// An entry the policies no longer keep
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReclaimCandidate {
+    pub kind: ReclaimKind,
+    /// The key (a backup's ID for `WalArchive`)
+    pub key: String,
+    /// Key and value bytes (0 for `WalArchive`: snapshot sizes aren't known)
+    pub bytes: u64,
+}
+
+/// What a retention pass found or deleted
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RetentionReport {
+    /// Nothing was deleted
+    pub dry_run: bool,
+    pub candidates: Vec<ReclaimCandidate>,
+    /// Page collection after deleting (None for a dry run)
+    pub pages: Option<PageGcReport>,
+}
+
+impl RetentionReport {
+    /// Entries of `kind`
+    pub fn count(&self, kind: ReclaimKind) -> usize {
+        self.candidates.iter().filter(|candidate| candidate.kind == kind).count()
+    }
+
+    /// Bytes of every entry
+    pub fn bytes(&self) -> u64 {
+        self.candidates.iter().map(|candidate| candidate.bytes).sum()
+    }
+}
+
+impl KVStore {
+    /// What `enforce_retention` would delete for `kinds`, deleting nothing
+    pub async fn retention_report(&self, kinds: &[ReclaimKind]) -> Result<RetentionReport> {
+        let candidates = self.reclaim_candidates(kinds).await?;
+        Ok(RetentionReport { dry_run: true, candidates, pages: None })
+    }
+
+    /// Delete every entry of `kinds` the policies no longer keep, then
+    /// collect the pages freed
+    pub async fn enforce_retention(&self, kinds: &[ReclaimKind]) -> Result<RetentionReport> {
+        let candidates = self.reclaim(kinds).await?;
+        let pages = if candidates.iter().any(|candidate| candidate.kind != ReclaimKind::WalArchive) {
+            Some(self.collect_pages().await?)
+        } else {
+            None
+        };
+        info!("RETENTION: reclaimed {} entries", candidates.len());
+        Ok(RetentionReport { dry_run: false, candidates, pages })
+    }
+
+    /// Delete the entries of `kinds` the policies no longer keep; returns them
+    pub(crate) async fn reclaim(&self, kinds: &[ReclaimKind]) -> Result<Vec<ReclaimCandidate>> {
+        let mut reclaimed = Vec::new();
+        for candidate in self.reclaim_candidates(kinds).await? {
+            let deleted = match candidate.kind {
+                ReclaimKind::WalArchive => self.delete_backup(&candidate.key).await?,
+                kind => {
+                    let _commit_guard = self.lock_commits().await;
+                    let now = now_ms();
+                    match self.get(&candidate.key).await? {
+                        Some(value) if self.is_reclaimable(kind, &candidate.key, &value, now) => {
+                            self.delete(&candidate.key).await?
+                        }
+                        _ => false,
+                    }
+                }
+            };
+            if deleted {
+                reclaimed.push(candidate);
+            }
+        }
+        Ok(reclaimed)
+    }
+
+    async fn reclaim_candidates(&self, kinds: &[ReclaimKind]) -> Result<Vec<ReclaimCandidate>> {
+        let now = now_ms();
+        let mut candidates = Vec::new();
+        for &kind in kinds {
+            if kind == ReclaimKind::WalArchive {
+                let backups = self.list_backups().await?;
+                let pruned = self.options().backup_retention.prune(&backups);
+                candidates.extend(pruned.into_iter().map(|backup| ReclaimCandidate { kind, key: backup.id.clone(), bytes: 0 }));
+                continue;
+            }
+
+            let mut keys = self.keys_with_prefix(kind.prefix());
+            keys.sort();
+            for key in keys {
+                if let Some(value) = self.get(&key).await? {
+                    if self.is_reclaimable(kind, &key, &value, now) {
+                        let bytes = (key.len() + value.len()) as u64;
+                        candidates.push(ReclaimCandidate { kind, key, bytes });
+                    }
+                }
+            }
+        }
+        Ok(candidates)
+    }
+
+    /// Does `kind`'s policy no longer keep `key`, stored as `value`?
+    fn is_reclaimable(&self, kind: ReclaimKind, key: &str, value: &str, now_ms: u64) -> bool {
+        match kind {
+            ReclaimKind::OldVersion => self.version_stale(key, value, now_ms),
+            ReclaimKind::Expired => column_family::envelope_expired(value, now_ms),
+            ReclaimKind::IdempotencyToken => self.token_expired(value, now_ms),
+            ReclaimKind::Trash => self.trash_expired(value, now_ms),
+            ReclaimKind::WalArchive => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_totals_by_kind() {
+        let candidate = |kind, key: &str, bytes| ReclaimCandidate { kind, key: key.into(), bytes };
+        let report = RetentionReport {
+            dry_run: true,
+            candidates: vec![
+                candidate(ReclaimKind::Trash, "__trash/a", 10),
+                candidate(ReclaimKind::Trash, "__trash/b", 5),
+                candidate(ReclaimKind::WalArchive, "0000000000001", 0),
+            ],
+            pages: None,
+        };
+        assert_eq!(report.count(ReclaimKind::Trash), 2);
+        assert_eq!(report.count(ReclaimKind::OldVersion), 0);
+        assert_eq!(report.bytes(), 15);
+
+        // Every key-kept kind has its own namespace
+        let prefixes: Vec<&str> = ReclaimKind::ALL.iter().map(|kind| kind.prefix()).filter(|p| !p.is_empty()).collect();
+        assert_eq!(prefixes.len(), 4);
+        assert!(prefixes.iter().all(|prefix| prefixes.iter().filter(|other| other.starts_with(prefix)).count() == 1));
+    }
+}
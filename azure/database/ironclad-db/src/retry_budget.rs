@@ -0,0 +1,234 @@
+//! Retry Budget: Capping Retries When Storage Is Struggling
+//!
+//! The SDK retries every failed blob call on its own schedule. When the
+//! storage account is throttling, every caller retrying several times
+//! multiplies the load that is making it throttle. A retry budget caps
+//! retries across all calls to an account with a token bucket:
+//!
+//! - each call that doesn't fail retryably earns `retry_percent` / 100 of
+//!   a retry, and the budget also refills by `min_retries_per_sec`;
+//! - each retryable failure spends one retry, and with none left the call
+//!   fails at once, without waiting out the backoff, with
+//!   `IronCladError::RetryBudgetExhausted`;
+//! - at most `max_retries` are saved up, so a quiet spell doesn't bank an
+//!   unlimited burst.
+//!
+//! ```ignore
+//! let options = StoreOptions {
+//!     retry_budget: Some(RetryBudget { retry_percent: 10, min_retries_per_sec: 5, max_retries: 50 }),
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! The budget belongs to the storage account: every store in the process
+//! on that account draws from the same one, as do its WAL and data blob
+//! clients. Without `StoreOptions::retry_budget` retries aren't capped.
+//! The SDK's last attempt spends a retry too, so the cap is approximate.
+//! Refused retries are counted in `StorageMetrics::retry_budget_exhausted`.
+
+use azure_core::error::ErrorKind;
+use azure_core::{Context, Policy, PolicyResult, Request};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::time::Instant;
+use tracing::warn;
+
+use crate::error::IronCladError;
+use crate::storage_metrics::{operation_name, record_budget_exhausted, RETRYABLE_STATUSES};
+
+/// Token bucket settings (see retry_budget.rs)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryBudget {
+    /// Retries earned per 100 calls
+    pub retry_percent: u32,
+    /// Retries earned per second regardless of traffic
+    pub min_retries_per_sec: u32,
+    /// Most retries saved up (the bucket starts full)
+    pub max_retries: u32,
+}
+
+impl Default for RetryBudget {
+    fn default() -> Self {
+        Self { retry_percent: 10, min_retries_per_sec: 10, max_retries: 100 }
+    }
+}
+
+/// Tokens are kept in thousandths of a retry
+const MILLI: u64 = 1_000;
+
+/// A budget's bucket
+#[derive(Debug)]
+struct Bucket {
+    budget: Option<RetryBudget>,
+    milli_tokens: u64,
+    refilled: Instant,
+}
+
+impl Bucket {
+    fn refill(&mut self, budget: &RetryBudget, now: Instant) {
+        let elapsed_ms = now.saturating_duration_since(self.refilled).as_millis() as u64;
+        let earned = elapsed_ms * u64::from(budget.min_retries_per_sec);
+        self.milli_tokens = (self.milli_tokens + earned).min(u64::from(budget.max_retries) * MILLI);
+        self.refilled = now;
+    }
+
+    /// A call succeeded or failed for good
+    fn deposit(&mut self, now: Instant) {
+        if let Some(budget) = self.budget {
+            self.refill(&budget, now);
+            let earned = u64::from(budget.retry_percent) * MILLI / 100;
+            self.milli_tokens = (self.milli_tokens + earned).min(u64::from(budget.max_retries) * MILLI);
+        }
+    }
+
+    /// Take one retry; false if none is left
+    fn withdraw(&mut self, now: Instant) -> bool {
+        let Some(budget) = self.budget else {
+            return true;
+        };
+        self.refill(&budget, now);
+        match self.milli_tokens.checked_sub(MILLI) {
+            Some(left) => {
+                self.milli_tokens = left;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Buckets by storage account
+fn buckets() -> &'static Mutex<HashMap<String, Arc<Mutex<Bucket>>>> {
+    static BUCKETS: OnceLock<Mutex<HashMap<String, Arc<Mutex<Bucket>>>>> = OnceLock::new();
+    BUCKETS.get_or_init(Default::default)
+}
+
+/// `account`'s bucket, uncapped until configured
+fn bucket(account: &str) -> Arc<Mutex<Bucket>> {
+    buckets()
+        .lock()
+        .entry(account.to_string())
+        .or_insert_with(|| Arc::new(Mutex::new(Bucket { budget: None, milli_tokens: 0, refilled: Instant::now() })))
+        .clone()
+}
+
+/// Cap retries to `account` with `budget`, starting with a full bucket
+pub(crate) fn configure(account: &str, budget: RetryBudget) {
+    let bucket = bucket(account);
+    let mut bucket = bucket.lock();
+    if bucket.budget != Some(budget) {
+        *bucket = Bucket { budget: Some(budget), milli_tokens: u64::from(budget.max_retries) * MILLI, refilled: Instant::now() };
+    }
+}
+
+/// Per-retry policy: spends a retry on each retryable failure and fails
+/// the call once the account's budget is empty
+#[derive(Debug)]
+pub(crate) struct RetryBudgetPolicy {
+    account: String,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RetryBudgetPolicy {
+    pub(crate) fn new(account: &str) -> Self {
+        Self { account: account.to_string(), bucket: bucket(account) }
+    }
+}
+
+impl Policy for RetryBudgetPolicy {
+    fn send<'life0, 'life1, 'life2, 'life3, 'async_trait>(
+        &'life0 self,
+        ctx: &'life1 Context,
+        request: &'life2 mut Request,
+        next: &'life3 [Arc<dyn Policy>],
+    ) -> Pin<Box<dyn Future<Output = PolicyResult> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        'life3: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            let result = next[0].send(ctx, request, &next[1..]).await;
+            let retryable = match &result {
+                Ok(response) => RETRYABLE_STATUSES.contains(&response.status()),
+                Err(error) => error.kind() == &ErrorKind::Io,
+            };
+            let now = Instant::now();
+            if !retryable {
+                self.bucket.lock().deposit(now);
+                return result;
+            }
+            if self.bucket.lock().withdraw(now) {
+                return result;
+            }
+
+            let operation = operation_name(request);
+            warn!("RETRY BUDGET: {} to {} not retried, the budget is spent", operation, self.account);
+            record_budget_exhausted();
+            let exhausted = IronCladError::RetryBudgetExhausted { account: self.account.clone(), operation };
+            Err(azure_core::Error::new(ErrorKind::Other, exhausted))
+        })
+    }
+}
+
+/// `error`, raised as the `IronCladError` it carries if the retry budget
+/// refused it, so callers can match it with `downcast_ref`
+pub(crate) fn surface(error: azure_core::Error) -> anyhow::Error {
+    let exhausted = std::iter::successors(Some(&error as &(dyn std::error::Error + 'static)), |e| e.source())
+        .find_map(|e| match e.downcast_ref::<IronCladError>() {
+            Some(IronCladError::RetryBudgetExhausted { account, operation }) => {
+                Some(IronCladError::RetryBudgetExhausted { account: account.clone(), operation: operation.clone() })
+            }
+            _ => None,
+        });
+    match exhausted {
+        Some(exhausted) => anyhow::Error::new(error).context(exhausted),
+        None => error.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_bucket_refuses_retries_once_spent() {
+        let budget = RetryBudget { retry_percent: 50, min_retries_per_sec: 1, max_retries: 2 };
+        let start = Instant::now();
+        let mut bucket = Bucket { budget: Some(budget), milli_tokens: 2 * MILLI, refilled: start };
+        assert!(bucket.withdraw(start));
+        assert!(bucket.withdraw(start));
+        assert!(!bucket.withdraw(start));
+
+        // Two good calls earn one retry, and so does a second of time
+        bucket.deposit(start);
+        bucket.deposit(start);
+        assert!(bucket.withdraw(start));
+        assert!(!bucket.withdraw(start));
+        assert!(bucket.withdraw(start + Duration::from_secs(1)));
+
+        // Never more than max_retries saved up
+        bucket.refill(&budget, start + Duration::from_secs(60));
+        assert_eq!(bucket.milli_tokens, 2 * MILLI);
+
+        let mut uncapped = Bucket { budget: None, milli_tokens: 0, refilled: start };
+        assert!(uncapped.withdraw(start));
+    }
+
+    #[test]
+    fn test_refusal_surfaces_as_typed_error() {
+        let refused = IronCladError::RetryBudgetExhausted { account: "acct".into(), operation: "PUT page".into() };
+        let error = azure_core::Error::new(ErrorKind::Other, refused).context("non-io error occurred which will not be retried");
+        let error = surface(error);
+        assert!(matches!(error.downcast_ref::<IronCladError>(), Some(IronCladError::RetryBudgetExhausted { .. })));
+
+        let other = surface(azure_core::Error::message(ErrorKind::Other, "boom"));
+        assert!(other.downcast_ref::<IronCladError>().is_none());
+    }
+}
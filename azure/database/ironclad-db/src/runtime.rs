@@ -0,0 +1,182 @@
+//! Runtime: The Executor Behind Background Tasks and Timers
+//!
+//! The store spawns tasks (lock renewal, backup schedules, parallel replay,
+//! key refreshes) and sleeps (group commit delays, session polling). Both
+//! go through a `Runtime`, so an executor other than Tokio can drive them:
+//!
+//! ```ignore
+//! struct AsyncStd;
+//!
+//! impl Runtime for AsyncStd {
+//!     fn spawn(&self, task: BoxFuture<'static, ()>) {
+//!         async_std::task::spawn(task);
+//!     }
+//!     fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+//!         Box::pin(async_std::task::sleep(duration))
+//!     }
+//! }
+//!
+//! let options = StoreOptions { runtime: RuntimeHandle::new(Arc::new(AsyncStd)), ..Default::default() };
+//! ```
+//!
+//! The default, `TokioRuntime`, uses the current Tokio runtime. The
+//! store's locks and channels are `tokio::sync` types, which work on any
+//! executor. What still needs a Tokio reactor: the Azure SDK's HTTP client,
+//! `LocalFileLog` (Tokio file I/O), the L2 cache (blocking file I/O on
+//! Tokio's pool) and `SimulatedLatencyLog`; under another executor, run the
+//! store inside a Tokio compatibility layer for those.
+//!
+//! A runtime only has to start a detached task; cancelling and joining
+//! tasks is done here on top of it (`futures` abort and remote handles), so
+//! every runtime gets both.
+
+use futures::future::{AbortHandle, Abortable, BoxFuture, RemoteHandle};
+use futures::FutureExt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// An async executor
+pub trait Runtime: Send + Sync {
+    /// Run `task` in the background
+    fn spawn(&self, task: BoxFuture<'static, ()>);
+
+    /// Complete after `duration`
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()>;
+}
+
+/// Tokio (the current runtime)
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: BoxFuture<'static, ()>) {
+        tokio::spawn(task);
+    }
+
+    fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A background task started with `RuntimeHandle::spawn`; dropping the
+/// handle leaves the task running
+#[derive(Debug)]
+pub struct TaskHandle {
+    abort: AbortHandle,
+}
+
+impl TaskHandle {
+    /// Stop the task at its next await point
+    pub fn abort(&self) {
+        self.abort.abort();
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.abort.is_aborted()
+    }
+}
+
+/// A shared `Runtime` (Tokio by default)
+#[derive(Clone)]
+pub struct RuntimeHandle {
+    runtime: Arc<dyn Runtime>,
+}
+
+impl RuntimeHandle {
+    pub fn new(runtime: Arc<dyn Runtime>) -> Self {
+        Self { runtime }
+    }
+
+    /// Run `task` in the background
+    pub fn spawn<F>(&self, task: F) -> TaskHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let (abort, registration) = AbortHandle::new_pair();
+        self.runtime.spawn(Box::pin(Abortable::new(task, registration).map(|_| ())));
+        TaskHandle { abort }
+    }
+
+    /// Run `task` in the background; await the handle for its output
+    /// (dropping the handle cancels the task)
+    pub fn spawn_joinable<F>(&self, task: F) -> RemoteHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (remote, handle) = task.remote_handle();
+        self.runtime.spawn(Box::pin(remote));
+        handle
+    }
+
+    pub fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+        self.runtime.sleep(duration)
+    }
+}
+
+impl Default for RuntimeHandle {
+    fn default() -> Self {
+        Self::new(Arc::new(TokioRuntime))
+    }
+}
+
+impl std::fmt::Debug for RuntimeHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RuntimeHandle").finish_non_exhaustive()
+    }
+}
+
+/// Handles are equal when they share the runtime
+impl PartialEq for RuntimeHandle {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::addr_eq(Arc::as_ptr(&self.runtime), Arc::as_ptr(&other.runtime))
+    }
+}
+
+impl Eq for RuntimeHandle {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Tokio underneath, counting spawns
+    #[derive(Default)]
+    struct Counting {
+        spawned: AtomicUsize,
+    }
+
+    impl Runtime for Counting {
+        fn spawn(&self, task: BoxFuture<'static, ()>) {
+            self.spawned.fetch_add(1, Ordering::SeqCst);
+            tokio::spawn(task);
+        }
+
+        fn sleep(&self, duration: Duration) -> BoxFuture<'static, ()> {
+            TokioRuntime.sleep(duration)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tasks_run_join_and_abort_on_any_runtime() {
+        let counting = Arc::new(Counting::default());
+        let runtime = RuntimeHandle::new(counting.clone());
+
+        assert_eq!(runtime.spawn_joinable(async { 6 * 7 }).await, 42);
+
+        let ran = Arc::new(AtomicUsize::new(0));
+        let task = runtime.spawn({
+            let (runtime, ran) = (runtime.clone(), ran.clone());
+            async move {
+                runtime.sleep(Duration::from_secs(60)).await;
+                ran.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        task.abort();
+        assert!(task.is_aborted());
+        tokio::task::yield_now().await;
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+        assert_eq!(counting.spawned.load(Ordering::SeqCst), 2);
+    }
+}
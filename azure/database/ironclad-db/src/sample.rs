@@ -0,0 +1,105 @@
+//! Sample: Uniform Random Samples of Keys
+//!
+//! Profiling data (what do the keys look like, how big are the values, how
+//! are they spread over prefixes) rarely needs every key. `sample_keys(n)`
+//! returns `n` keys chosen uniformly at random, every key equally likely,
+//! from one pass over the in-memory index with reservoir sampling:
+//!
+//! ```ignore
+//! for key in store.sample_keys(100).await? {
+//!     let value = store.get(&key).await?;
+//!     // ...
+//! }
+//! ```
+//!
+//! The pass reads the index one shard at a time and copies out only the
+//! keys that enter the reservoir, so it costs a fraction of a `scan` and
+//! reads no values. Reserved (`__`) keys aren't sampled. With a hashed
+//! index, sampled keys are only hashes; their pages are read to recover
+//! the keys (at most `n` page reads).
+//!
+//! Keys written during the pass may or may not be counted.
+
+use anyhow::Result;
+use rand::Rng;
+
+use crate::buffer_pool::CachePriority;
+use crate::index::SampledKey;
+use crate::kvstore::KVStore;
+use crate::page;
+
+/// Keep `n` of `items`, each equally likely to be kept (Algorithm R);
+/// `take` copies out an item that enters the reservoir
+pub(crate) fn reservoir<I, T>(
+    items: impl Iterator<Item = I>,
+    n: usize,
+    rng: &mut impl Rng,
+    mut take: impl FnMut(&I) -> T,
+) -> Vec<T> {
+    let mut kept = Vec::with_capacity(n);
+    if n == 0 {
+        return kept;
+    }
+    for (seen, item) in items.enumerate() {
+        if seen < n {
+            kept.push(take(&item));
+        } else {
+            let slot = rng.gen_range(0..=seen);
+            if slot < n {
+                kept[slot] = take(&item);
+            }
+        }
+    }
+    kept
+}
+
+impl KVStore {
+    /// Up to `n` keys picked uniformly at random (fewer if the store holds
+    /// fewer); see sample.rs
+    pub async fn sample_keys(&self, n: usize) -> Result<Vec<String>> {
+        let sampled = self.index().sample(n, &mut rand::thread_rng());
+        let mut keys = Vec::with_capacity(sampled.len());
+        for key in sampled {
+            match key {
+                SampledKey::Full(key) => keys.push(key),
+                SampledKey::Hashed(page_id) => {
+                    if let Some(data) = self.load_page(page_id, CachePriority::Low).await? {
+                        keys.push(page::decode_kv_page(&data)?.0);
+                    }
+                }
+            }
+        }
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_reservoir_keeps_everything_when_small() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut kept = reservoir(0..3, 5, &mut rng, |i| *i);
+        kept.sort();
+        assert_eq!(kept, vec![0, 1, 2]);
+        assert!(reservoir(0..3, 0, &mut rng, |i| *i).is_empty());
+    }
+
+    #[test]
+    fn test_reservoir_is_uniform() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let mut counts = [0u32; 10];
+        for _ in 0..20_000 {
+            for i in reservoir(0..10usize, 2, &mut rng, |i| *i) {
+                counts[i] += 1;
+            }
+        }
+        // Each item is kept 2 times in 10: 4,000 expected
+        for count in counts {
+            assert!((3_700..4_300).contains(&count), "{:?}", counts);
+        }
+    }
+}
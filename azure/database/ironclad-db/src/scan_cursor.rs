@@ -0,0 +1,148 @@
+//! Scan Cursors: Resumable Iteration for Long-Running Jobs
+//!
+//! `iter_range` walks the same keys as `scan_range`, one entry at a time,
+//! and remembers the last key it returned. A batch job saves that position
+//! in the store as it goes, and after a crash picks up where it left off
+//! instead of starting over:
+//!
+//! ```ignore
+//! let mut iter = match store.resume_from("reindex").await? {
+//!     Some(iter) => iter,
+//!     None => store.iter_range("items/", None).await?,
+//! };
+//! let mut done = 0;
+//! while let Some((key, value)) = iter.next().await? {
+//!     reindex(&key, &value).await?;
+//!     done += 1;
+//!     if done % 10_000 == 0 {
+//!         iter.save_checkpoint("reindex").await?;
+//!     }
+//! }
+//! store.delete_scan_checkpoint("reindex").await?;
+//! ```
+//!
+//! A checkpoint records the range and the last key returned under
+//! `__scan/<name>`; resuming continues with the keys after it in the
+//! bucket's collation (see collation.rs). Entries processed after the last
+//! save are returned again, so the work should be idempotent.
+//!
+//! The iterator lists the range's keys when it starts (or resumes): keys
+//! added to the range afterwards aren't returned, and keys deleted
+//! meanwhile are skipped.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use tracing::info;
+
+use crate::collation::Collation;
+use crate::kvstore::KVStore;
+
+/// Key prefix for saved scan positions
+const SCAN_PREFIX: &str = "__scan/";
+
+/// A saved scan position
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct ScanCheckpoint {
+    start: String,
+    end: Option<String>,
+    /// Last key returned (None = none yet)
+    after: Option<String>,
+}
+
+/// Iterator over a key range that can save its position (see scan_cursor.rs)
+pub struct ScanIter<'a> {
+    store: &'a KVStore,
+    start: String,
+    end: Option<String>,
+    keys: std::vec::IntoIter<String>,
+    position: Option<String>,
+}
+
+impl<'a> ScanIter<'a> {
+    /// The next live entry, or None at the end of the range
+    pub async fn next(&mut self) -> Result<Option<(String, String)>> {
+        for key in self.keys.by_ref() {
+            self.position = Some(key.clone());
+            if let Some(value) = self.store.get(&key).await? {
+                return Ok(Some((key, value)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Last key returned (None before the first)
+    pub fn position(&self) -> Option<&str> {
+        self.position.as_deref()
+    }
+
+    /// Save the position as `name`, replacing any saved before
+    pub async fn save_checkpoint(&self, name: &str) -> Result<()> {
+        let checkpoint = ScanCheckpoint { start: self.start.clone(), end: self.end.clone(), after: self.position.clone() };
+        self.store.set(&checkpoint_key(name), &serde_json::to_string(&checkpoint)?).await
+    }
+}
+
+/// `keys` (in `collation` order) after `after`
+fn keys_after(keys: Vec<String>, collation: Collation, after: Option<&str>) -> Vec<String> {
+    match after {
+        Some(after) => keys.into_iter().filter(|key| collation.compare(key, after) == Ordering::Greater).collect(),
+        None => keys,
+    }
+}
+
+fn checkpoint_key(name: &str) -> String {
+    format!("{}{}", SCAN_PREFIX, name)
+}
+
+impl KVStore {
+    /// Iterate the entries `scan_range(start, end)` returns
+    pub async fn iter_range(&self, start: &str, end: Option<&str>) -> Result<ScanIter<'_>> {
+        let (keys, _) = self.range_keys(start, end).await?;
+        Ok(ScanIter {
+            store: self,
+            start: start.to_string(),
+            end: end.map(str::to_string),
+            keys: keys.into_iter(),
+            position: None,
+        })
+    }
+
+    /// Continue the scan saved as `name`; None if there is no such checkpoint
+    pub async fn resume_from(&self, name: &str) -> Result<Option<ScanIter<'_>>> {
+        let Some(json) = self.get(&checkpoint_key(name)).await? else {
+            return Ok(None);
+        };
+        let checkpoint: ScanCheckpoint = serde_json::from_str(&json)?;
+        let (keys, collation) = self.range_keys(&checkpoint.start, checkpoint.end.as_deref()).await?;
+        let keys = keys_after(keys, collation, checkpoint.after.as_deref());
+
+        info!("SCAN: resuming {} after {:?} ({} keys left)", name, checkpoint.after, keys.len());
+        Ok(Some(ScanIter {
+            store: self,
+            start: checkpoint.start,
+            end: checkpoint.end,
+            keys: keys.into_iter(),
+            position: checkpoint.after,
+        }))
+    }
+
+    /// Delete the scan position saved as `name`; returns whether it existed
+    pub async fn delete_scan_checkpoint(&self, name: &str) -> Result<bool> {
+        self.delete(&checkpoint_key(name)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resuming_skips_keys_up_to_the_position() {
+        let keys = || vec!["item1".to_string(), "item2".to_string(), "item10".to_string()];
+        assert_eq!(keys_after(keys(), Collation::Numeric, Some("item2")), ["item10"]);
+        // The position needn't still exist
+        assert_eq!(keys_after(keys(), Collation::Numeric, Some("item3")), ["item10"]);
+        assert_eq!(keys_after(keys(), Collation::Numeric, None).len(), 3);
+    }
+}
@@ -0,0 +1,86 @@
+//! Session: Read-Your-Writes Tokens Across Store Instances
+//!
+//! When several KVStore instances sit behind a load balancer, a client may
+//! write through one instance and read through another. A session token
+//! carries the last LSN the client committed; an instance serving a read
+//! first waits until it has applied that LSN (or tells the caller to proxy
+//! the read to the primary), giving per-client read-your-writes consistency.
+
+use anyhow::Result;
+use std::fmt;
+use std::str::FromStr;
+
+/// Prefix used in the string form of a token ("lsn:<n>")
+const TOKEN_PREFIX: &str = "lsn:";
+
+/// Opaque token returned to clients after a write
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct SessionToken {
+    /// Last LSN committed by the session
+    pub lsn: u64,
+}
+
+impl SessionToken {
+    /// Create a token for the given committed LSN
+    pub fn new(lsn: u64) -> Self {
+        Self { lsn }
+    }
+
+    /// Has an instance that applied up to `applied_lsn` caught up with this session?
+    pub fn is_satisfied_by(&self, applied_lsn: u64) -> bool {
+        applied_lsn >= self.lsn
+    }
+
+    /// Combine two tokens, keeping the most recent write
+    pub fn merge(self, other: SessionToken) -> SessionToken {
+        self.max(other)
+    }
+}
+
+impl fmt::Display for SessionToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}{}", TOKEN_PREFIX, self.lsn)
+    }
+}
+
+impl FromStr for SessionToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let lsn = s
+            .strip_prefix(TOKEN_PREFIX)
+            .ok_or_else(|| anyhow::anyhow!("Invalid session token: {}", s))?
+            .parse::<u64>()
+            .map_err(|e| anyhow::anyhow!("Invalid session token {}: {}", s, e))?;
+
+        Ok(Self { lsn })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_round_trip() {
+        let token = SessionToken::new(42);
+        let encoded = token.to_string();
+        assert_eq!(encoded, "lsn:42");
+        assert_eq!(encoded.parse::<SessionToken>().unwrap(), token);
+    }
+
+    #[test]
+    fn test_invalid_token() {
+        assert!("42".parse::<SessionToken>().is_err());
+        assert!("lsn:abc".parse::<SessionToken>().is_err());
+    }
+
+    #[test]
+    fn test_satisfied_and_merge() {
+        let token = SessionToken::new(10);
+        assert!(!token.is_satisfied_by(9));
+        assert!(token.is_satisfied_by(10));
+        assert_eq!(token.merge(SessionToken::new(7)).lsn, 10);
+        assert_eq!(token.merge(SessionToken::new(12)).lsn, 12);
+    }
+}
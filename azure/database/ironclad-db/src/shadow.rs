@@ -0,0 +1,230 @@
+//! Shadow: Copy-on-Write Pages with an Atomic Root Switch
+//!
+//! A store created with `StoreOptions::durability` set to
+//! `Durability::ShadowPaging` doesn't make writes durable through the WAL.
+//! Instead, every write installs a new root:
+//!
+//! 1. A write to a key whose page the current root names goes to a fresh
+//!    page (copy on write); pages written since the root was installed
+//!    are updated in place, since no root names them yet.
+//! 2. Installing flushes the dirty pages, writes a page table naming every
+//!    key's page (and holding the inline values) to pages no root names,
+//!    then points the superblock at the table. The superblock is a single
+//!    checksummed page, so the switch is atomic: a crash leaves either the
+//!    old root or the new one, each with all of its pages intact.
+//! 3. Pages only the old root named go on the free list (see page_gc.rs).
+//!
+//! Recovery loads the index from the root's page table, with no log to
+//! replay. The WAL lives in memory, only ordering writes within the
+//! process (sessions, `catch_up`); it is cleared at every install.
+//!
+//! Each install rewrites the whole page table, so this suits write-light,
+//! read-heavy datasets: reads are unaffected, and there is no log to grow
+//! or replay. Concurrent writes share an install. Shadow paging needs the
+//! full index (`IndexMode::Full`), since the table names each key.
+//!
+//! The mode is recorded in the superblock when the store is created; an
+//! existing store keeps its mode whatever the options say.
+//!
+//! Page table entries are packed pairs (see page.rs): the key, then
+//! `@<page ID>` for a key in a page or `=<value>` for an inline value.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::{debug, info, warn};
+
+use crate::index::IndexEntry;
+use crate::kvstore::KVStore;
+use crate::page;
+
+/// How a store makes writes durable (chosen at creation)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Durability {
+    /// Log every write to the WAL before applying it
+    #[default]
+    Wal,
+    /// Install a new root of copy-on-write pages after every write
+    ShadowPaging,
+}
+
+/// The installed page table, persisted in the superblock
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ShadowRoot {
+    /// Page table pages: `count` pages from `start`
+    pub start: u64,
+    pub count: u64,
+    /// Installs so far
+    pub generation: u64,
+}
+
+/// Shadow paging state of a store
+#[derive(Default)]
+pub(crate) struct ShadowPages {
+    /// Pages the installed root names
+    installed: parking_lot::RwLock<HashSet<u64>>,
+    /// Commit sequence the installed root covers; serializes installs
+    installed_seq: tokio::sync::Mutex<u64>,
+}
+
+impl ShadowPages {
+    /// Does the installed root name `page_id`?
+    pub fn is_installed(&self, page_id: u64) -> bool {
+        self.installed.read().contains(&page_id)
+    }
+
+    /// Pages the installed root names
+    pub fn installed(&self) -> Vec<u64> {
+        self.installed.read().iter().copied().collect()
+    }
+}
+
+/// A page table entry's value
+fn encode_entry(entry: &IndexEntry) -> String {
+    match entry {
+        IndexEntry::Page(page_id) => format!("@{}", page_id),
+        IndexEntry::Inline(value) => format!("={}", value),
+    }
+}
+
+fn decode_entry(encoded: &str) -> Result<IndexEntry> {
+    if let Some(page_id) = encoded.strip_prefix('@') {
+        return Ok(IndexEntry::Page(page_id.parse()?));
+    }
+    match encoded.strip_prefix('=') {
+        Some(value) => Ok(IndexEntry::Inline(value.to_string())),
+        None => anyhow::bail!("Unreadable page table entry {:?}", encoded),
+    }
+}
+
+impl KVStore {
+    /// Make every write applied so far durable by installing a new root
+    /// (a no-op unless the store uses shadow paging); returns the dirty
+    /// pages flushed first
+    pub(crate) async fn install_shadow_root(&self) -> Result<usize> {
+        let Some(shadow) = self.shadow() else {
+            return Ok(0);
+        };
+        let applied_seq = self.commit_seq();
+        let mut installed_seq = shadow.installed_seq.lock().await;
+        // A concurrent install already covered this write
+        if *installed_seq >= applied_seq {
+            return Ok(0);
+        }
+
+        // Every key's entry, with no page allocated but not yet indexed
+        let (seq, entries) = {
+            let _allocation = self.page_allocation().write();
+            (self.commit_seq(), self.index().entries())
+        };
+        let flushed = self.flush_up_to(u64::MAX).await?;
+
+        let pairs: Vec<(String, String)> = entries.iter().map(|(key, entry)| (key.clone(), encode_entry(entry))).collect();
+        let pages = page::encode_packed_pages(&pairs)?;
+        let count = pages.len() as u64;
+        let start = self.allocate_page_run(count);
+        for (i, data) in pages.iter().enumerate() {
+            self.write_page_direct(start + i as u64, data).await?;
+        }
+        self.disk().flush().await?;
+
+        // The switch
+        let previous = self.superblock().lock().await.shadow_root;
+        let generation = previous.map_or(1, |root| root.generation + 1);
+        self.update_superblock(|sb| sb.shadow_root = Some(ShadowRoot { start, count, generation })).await?;
+
+        let named: HashSet<u64> = entries
+            .iter()
+            .filter_map(|(_, entry)| match entry {
+                IndexEntry::Page(page_id) => Some(*page_id),
+                IndexEntry::Inline(_) => None,
+            })
+            .collect();
+        let released: Vec<u64> = {
+            let mut installed = shadow.installed.write();
+            let released = installed.difference(&named).copied().collect();
+            *installed = named;
+            released
+        };
+        self.free_pages().lock().extend(&released);
+        if let Some(previous) = previous {
+            self.release_page_run(previous.start, previous.count);
+        }
+        *installed_seq = seq;
+
+        // Only this process ever reads the in-memory log
+        self.wal().clear().await?;
+        debug!("SHADOW: installed root {} ({} keys, {} table pages, {} pages freed)", generation, entries.len(), count, released.len());
+        Ok(flushed)
+    }
+
+    /// Load the index from the installed root
+    pub(crate) async fn load_shadow_root(&self) -> Result<()> {
+        let Some(shadow) = self.shadow() else {
+            return Ok(());
+        };
+        let Some(root) = self.superblock().lock().await.shadow_root else {
+            info!("SHADOW: no root installed yet");
+            return Ok(());
+        };
+
+        let mut installed = HashSet::new();
+        let mut keys = 0;
+        for page_id in root.start..root.start + root.count {
+            for (key, encoded) in page::decode_packed_page(&self.disk().read_page(page_id).await?)? {
+                let entry = decode_entry(&encoded)?;
+                if let IndexEntry::Page(page_id) = entry {
+                    installed.insert(page_id);
+                }
+                self.index().insert(&key, entry);
+                keys += 1;
+            }
+        }
+
+        // Don't hand out pages the root uses
+        let end = installed.iter().map(|page_id| page_id + 1).max().unwrap_or(0).max(root.start + root.count);
+        self.reserve_page_ids_below(end);
+        *shadow.installed.write() = installed;
+        info!("SHADOW: loaded root {} ({} keys)", root.generation, keys);
+        Ok(())
+    }
+
+    /// The page table pages of the installed root
+    pub(crate) async fn shadow_table_pages(&self) -> Vec<u64> {
+        match self.superblock().lock().await.shadow_root {
+            Some(root) => (root.start..root.start + root.count).collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// The durability a store opens with: the one recorded at creation, if any
+pub(crate) fn effective(recorded: Option<Durability>, requested: Durability) -> Durability {
+    match recorded {
+        Some(recorded) if recorded != requested => {
+            warn!("SHADOW: store was created with {:?} durability, ignoring {:?}", recorded, requested);
+            recorded
+        }
+        Some(recorded) => recorded,
+        None => requested,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page_table_entries_round_trip() {
+        for entry in [IndexEntry::Page(42), IndexEntry::Inline("=@ value".to_string())] {
+            assert_eq!(decode_entry(&encode_entry(&entry)).unwrap(), entry);
+        }
+        assert!(decode_entry("42").is_err());
+    }
+
+    #[test]
+    fn test_recorded_durability_wins() {
+        assert_eq!(effective(None, Durability::ShadowPaging), Durability::ShadowPaging);
+        assert_eq!(effective(Some(Durability::Wal), Durability::ShadowPaging), Durability::Wal);
+    }
+}
@@ -0,0 +1,167 @@
+//! Sorted Set: Members Ordered by Score (Leaderboards)
+//!
+//! `StoreSortedSet` maps members to `f64` scores and answers `top_n`,
+//! `rank` and score-range queries. Besides the member -> score record,
+//! each member has a key in a secondary index whose name encodes the score
+//! in an order-preserving form:
+//!
+//! ```text
+//! __zset/<name>/m/<member>                score (JSON number)
+//! __zset/<name>/s/<score:016x>/<member>   (empty value)
+//! ```
+//!
+//! Sorting the index keys sorts by score (ties by member), and the
+//! queries are answered from the keys alone, without reading any pages.
+//! The index itself isn't ordered, so each query still lists the set's
+//! keys (see index.rs).
+//!
+//! Both records are written in one WAL batch under the commit lock, so
+//! they can't disagree after a crash.
+
+use anyhow::Result;
+
+use crate::collections::validate_name;
+use crate::kvstore::KVStore;
+use crate::wal::WalEntry;
+
+/// Key prefix for sorted sets
+const ZSET_PREFIX: &str = "__zset/";
+
+/// Map a score to a `u64` with the same order
+fn encode_score(score: f64) -> u64 {
+    let bits = score.to_bits();
+    if bits >> 63 == 1 { !bits } else { bits | 1 << 63 }
+}
+
+fn decode_score(encoded: u64) -> f64 {
+    let bits = if encoded >> 63 == 1 { encoded & !(1 << 63) } else { !encoded };
+    f64::from_bits(bits)
+}
+
+/// Handle to a sorted set
+pub struct StoreSortedSet<'a> {
+    store: &'a KVStore,
+    prefix: String,
+}
+
+impl<'a> StoreSortedSet<'a> {
+    /// Set `member`'s score; returns true if the member is new
+    pub async fn add(&self, member: &str, score: f64) -> Result<bool> {
+        if score.is_nan() {
+            anyhow::bail!("Score for {:?} is NaN", member);
+        }
+        let _commit_guard = self.store.lock_commits().await;
+
+        let previous = self.score(member).await?;
+        let mut writes = Vec::with_capacity(3);
+        if let Some(previous) = previous {
+            writes.push(WalEntry::Delete { key: self.index_key(member, previous) });
+        }
+        writes.push(WalEntry::set(&self.member_key(member), &serde_json::to_string(&score)?));
+        writes.push(WalEntry::set(&self.index_key(member, score), ""));
+        self.store.commit_batch_locked(self.store.new_txn_id(), writes, None).await?;
+        Ok(previous.is_none())
+    }
+
+    /// Remove `member`; returns whether it was present
+    pub async fn remove(&self, member: &str) -> Result<bool> {
+        let _commit_guard = self.store.lock_commits().await;
+
+        let Some(score) = self.score(member).await? else {
+            return Ok(false);
+        };
+        let writes = vec![
+            WalEntry::Delete { key: self.member_key(member) },
+            WalEntry::Delete { key: self.index_key(member, score) },
+        ];
+        self.store.commit_batch_locked(self.store.new_txn_id(), writes, None).await?;
+        Ok(true)
+    }
+
+    /// `member`'s score
+    pub async fn score(&self, member: &str) -> Result<Option<f64>> {
+        match self.store.get(&self.member_key(member)).await? {
+            Some(stored) => Ok(Some(serde_json::from_str(&stored)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// `member`'s position by ascending score (0 = lowest)
+    pub fn rank(&self, member: &str) -> Option<u64> {
+        self.ordered().iter().position(|(m, _)| m == member).map(|rank| rank as u64)
+    }
+
+    /// The `n` highest-scoring members, highest first
+    pub fn top_n(&self, n: usize) -> Vec<(String, f64)> {
+        self.ordered().into_iter().rev().take(n).collect()
+    }
+
+    /// Members with `min <= score <= max`, by ascending score
+    pub fn range_by_score(&self, min: f64, max: f64) -> Vec<(String, f64)> {
+        self.ordered()
+            .into_iter()
+            .filter(|(_, score)| *score >= min && *score <= max)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.store.keys_with_prefix(&format!("{}m/", self.prefix)).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Every (member, score), by ascending score
+    fn ordered(&self) -> Vec<(String, f64)> {
+        let index_prefix = format!("{}s/", self.prefix);
+        let mut keys = self.store.keys_with_prefix(&index_prefix);
+        keys.sort();
+        keys.iter().filter_map(|key| parse_index_key(&key[index_prefix.len()..])).collect()
+    }
+
+    fn member_key(&self, member: &str) -> String {
+        format!("{}m/{}", self.prefix, member)
+    }
+
+    fn index_key(&self, member: &str, score: f64) -> String {
+        format!("{}s/{:016x}/{}", self.prefix, encode_score(score), member)
+    }
+}
+
+/// (member, score) from an index key, minus the set's index prefix
+fn parse_index_key(key: &str) -> Option<(String, f64)> {
+    let (score, member) = key.split_once('/')?;
+    Some((member.to_string(), decode_score(u64::from_str_radix(score, 16).ok()?)))
+}
+
+impl KVStore {
+    /// Handle to the sorted set `name` (empty until added to)
+    pub fn sorted_set(&self, name: &str) -> Result<StoreSortedSet<'_>> {
+        validate_name(name)?;
+        Ok(StoreSortedSet { store: self, prefix: format!("{}{}/", ZSET_PREFIX, name) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_score_encoding_preserves_order() {
+        let scores = [f64::NEG_INFINITY, -1e9, -1.5, -0.0, 0.0, 1e-9, 2.0, 1e300, f64::INFINITY];
+        for pair in scores.windows(2) {
+            assert!(encode_score(pair[0]) <= encode_score(pair[1]), "{:?}", pair);
+        }
+        for score in scores {
+            assert_eq!(decode_score(encode_score(score)), score);
+        }
+    }
+
+    #[test]
+    fn test_parse_index_key() {
+        let key = format!("{:016x}/player/1", encode_score(42.5));
+        assert_eq!(parse_index_key(&key), Some(("player/1".to_string(), 42.5)));
+        assert_eq!(parse_index_key("zz/member"), None);
+    }
+}
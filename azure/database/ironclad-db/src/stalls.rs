@@ -0,0 +1,96 @@
+//! Stalls: Events for Operations Held Up Inside the Store
+//!
+//! A p99 spike usually comes from one of a few internal waits. Each is
+//! timed where it happens, and any wait of at least
+//! `StoreOptions::stall_threshold` is published as a `StallEvent`:
+//!
+//! - `WalBackpressure`: a write waited for a slot because
+//!   `GroupCommitOptions::max_outstanding` writes were already queued
+//! - `EvictionWriteback`: an operation had to write back dirty pages
+//!   evicted to make room before it could finish
+//! - `CheckpointLogReset`: appends were held off while a checkpoint reset
+//!   the WAL
+//!
+//! ```ignore
+//! let mut stalls = store.subscribe_stalls();
+//! while let Ok(stall) = stalls.recv().await {
+//!     warn!("{:?} held up {} for {:?}", stall.cause, stall.operation, stall.duration);
+//! }
+//! ```
+//!
+//! Events go to a broadcast channel: a subscriber that falls more than
+//! `CHANNEL_CAPACITY` events behind misses the oldest (its `recv` reports
+//! how many). Nothing is recorded while nobody is subscribed.
+
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tracing::debug;
+
+use crate::metadata;
+
+/// Events buffered per subscriber
+pub const CHANNEL_CAPACITY: usize = 1024;
+
+/// What held an operation up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StallCause {
+    WalBackpressure,
+    EvictionWriteback { pages: usize },
+    CheckpointLogReset,
+}
+
+/// One stall
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StallEvent {
+    pub cause: StallCause,
+    /// The operation held up, e.g. `"wal append"` or `"set"`
+    pub operation: &'static str,
+    pub duration: Duration,
+    /// Operations delayed by this stall (those queued behind it included)
+    pub affected_ops: usize,
+    /// When the stall ended (Unix ms)
+    pub at_ms: u64,
+}
+
+/// Publishes stalls longer than a threshold
+pub(crate) struct StallMonitor {
+    sender: broadcast::Sender<StallEvent>,
+    threshold: Duration,
+}
+
+impl StallMonitor {
+    pub fn new(threshold: Duration) -> Self {
+        Self { sender: broadcast::channel(CHANNEL_CAPACITY).0, threshold }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<StallEvent> {
+        self.sender.subscribe()
+    }
+
+    pub fn report(&self, cause: StallCause, operation: &'static str, duration: Duration, affected_ops: usize) {
+        if duration < self.threshold || self.sender.receiver_count() == 0 {
+            return;
+        }
+        debug!("STALL: {} held up {:?} by {:?} ({} ops)", operation, duration, cause, affected_ops);
+        let _ = self.sender.send(StallEvent { cause, operation, duration, affected_ops, at_ms: metadata::now_ms() });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_only_long_stalls_are_published() {
+        let monitor = StallMonitor::new(Duration::from_millis(10));
+        let mut stalls = monitor.subscribe();
+
+        monitor.report(StallCause::WalBackpressure, "wal append", Duration::from_millis(2), 1);
+        monitor.report(StallCause::EvictionWriteback { pages: 3 }, "set", Duration::from_millis(25), 1);
+
+        let stall = stalls.try_recv().unwrap();
+        assert_eq!(stall.cause, StallCause::EvictionWriteback { pages: 3 });
+        assert_eq!(stall.operation, "set");
+        assert!(stalls.try_recv().is_err());
+    }
+}
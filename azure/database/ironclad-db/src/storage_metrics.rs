@@ -0,0 +1,270 @@
+//! Storage Metrics: Throttling and Retries Against Azure Storage
+//!
+//! The Azure SDK retries throttled (429), unavailable (503) and other
+//! transient failures on its own, so a storage account running out of
+//! capacity first shows up as slower requests, then as errors once the
+//! retries run out. Two pipeline policies, installed on every blob client
+//! (see config.rs), count what happens along the way:
+//!
+//! - `CallMetricsPolicy` wraps the SDK's retry loop: one call per operation,
+//!   with its attempts, total latency, and whether it failed on a
+//!   retryable error (retries exhausted).
+//! - `AttemptMetricsPolicy` sits inside the retry loop and sees each
+//!   attempt's status code.
+//!
+//! Counters are process-wide: every store in the process shares them. Read
+//! them with `storage_metrics()` (also in `KVStoreStats::storage`).
+//!
+//! Azure's `Policy` trait is declared with `async_trait`, so the
+//! implementations below spell out the boxed future it expands to.
+
+use azure_core::error::ErrorKind;
+use azure_core::{Context, Policy, PolicyResult, Request, StatusCode};
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Statuses the SDK retries
+pub(crate) const RETRYABLE_STATUSES: [StatusCode; 6] = [
+    StatusCode::RequestTimeout,
+    StatusCode::TooManyRequests,
+    StatusCode::InternalServerError,
+    StatusCode::BadGateway,
+    StatusCode::ServiceUnavailable,
+    StatusCode::GatewayTimeout,
+];
+
+/// Process-wide storage request counters
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct StorageMetrics {
+    /// Operations (each possibly several attempts)
+    pub calls: u64,
+    /// Attempts beyond each operation's first
+    pub retries: u64,
+    /// 429 Too Many Requests responses
+    pub throttled: u64,
+    /// 503 Server Busy / Service Unavailable responses
+    pub unavailable: u64,
+    /// Operations that failed after running out of retries
+    pub retries_exhausted: u64,
+    /// Retries refused by the retry budget (see retry_budget.rs)
+    pub retry_budget_exhausted: u64,
+    /// By operation, e.g. `"PUT page"` or `"PUT appendblock"`
+    pub operations: BTreeMap<String, OperationMetrics>,
+}
+
+/// Retry counters for one kind of operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct OperationMetrics {
+    pub calls: u64,
+    pub retries: u64,
+    /// Calls that needed at least one retry
+    pub retried_calls: u64,
+    /// Total and worst latency of the retried calls, retries included
+    pub retried_latency_us: u64,
+    pub max_retried_latency_us: u64,
+    pub retries_exhausted: u64,
+}
+
+struct Counters {
+    calls: AtomicU64,
+    retries: AtomicU64,
+    throttled: AtomicU64,
+    unavailable: AtomicU64,
+    retries_exhausted: AtomicU64,
+    retry_budget_exhausted: AtomicU64,
+    operations: Mutex<BTreeMap<String, OperationMetrics>>,
+}
+
+static COUNTERS: Counters = Counters {
+    calls: AtomicU64::new(0),
+    retries: AtomicU64::new(0),
+    throttled: AtomicU64::new(0),
+    unavailable: AtomicU64::new(0),
+    retries_exhausted: AtomicU64::new(0),
+    retry_budget_exhausted: AtomicU64::new(0),
+    operations: Mutex::new(BTreeMap::new()),
+};
+
+/// Current storage request counters
+pub fn storage_metrics() -> StorageMetrics {
+    StorageMetrics {
+        calls: COUNTERS.calls.load(Ordering::Relaxed),
+        retries: COUNTERS.retries.load(Ordering::Relaxed),
+        throttled: COUNTERS.throttled.load(Ordering::Relaxed),
+        unavailable: COUNTERS.unavailable.load(Ordering::Relaxed),
+        retries_exhausted: COUNTERS.retries_exhausted.load(Ordering::Relaxed),
+        retry_budget_exhausted: COUNTERS.retry_budget_exhausted.load(Ordering::Relaxed),
+        operations: COUNTERS.operations.lock().clone(),
+    }
+}
+
+/// Count an operation that took `attempts` attempts
+fn record_call(operation: String, attempts: u32, latency: Duration, exhausted: bool) {
+    let retries = u64::from(attempts.saturating_sub(1));
+    COUNTERS.calls.fetch_add(1, Ordering::Relaxed);
+    COUNTERS.retries.fetch_add(retries, Ordering::Relaxed);
+    if exhausted {
+        COUNTERS.retries_exhausted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let mut operations = COUNTERS.operations.lock();
+    let metrics = operations.entry(operation).or_default();
+    metrics.calls += 1;
+    metrics.retries += retries;
+    if retries > 0 {
+        let latency_us = latency.as_micros() as u64;
+        metrics.retried_calls += 1;
+        metrics.retried_latency_us += latency_us;
+        metrics.max_retried_latency_us = metrics.max_retried_latency_us.max(latency_us);
+    }
+    if exhausted {
+        metrics.retries_exhausted += 1;
+    }
+}
+
+/// Count a retry the retry budget refused
+pub(crate) fn record_budget_exhausted() {
+    COUNTERS.retry_budget_exhausted.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count one attempt's response status
+fn record_attempt(status: StatusCode) {
+    match status {
+        StatusCode::TooManyRequests => COUNTERS.throttled.fetch_add(1, Ordering::Relaxed),
+        StatusCode::ServiceUnavailable => COUNTERS.unavailable.fetch_add(1, Ordering::Relaxed),
+        _ => return,
+    };
+}
+
+/// Name for a request's operation: the method plus its `comp` (or
+/// `restype`) query parameter
+pub(crate) fn operation_name(request: &Request) -> String {
+    let url = request.url();
+    let kind = url
+        .query_pairs()
+        .find(|(name, _)| name == "comp")
+        .or_else(|| url.query_pairs().find(|(name, _)| name == "restype"))
+        .map(|(_, value)| value.into_owned());
+    match kind {
+        Some(kind) => format!("{} {}", request.method(), kind),
+        None => request.method().to_string(),
+    }
+}
+
+/// Did the call fail on an error the SDK retries (so it ran out of retries)?
+fn is_retries_exhausted(result: &PolicyResult) -> bool {
+    match result {
+        Ok(_) => false,
+        Err(error) => match error.kind() {
+            ErrorKind::Io => true,
+            ErrorKind::HttpResponse { status, .. } => RETRYABLE_STATUSES.contains(status),
+            _ => false,
+        },
+    }
+}
+
+/// Attempts made for the current call, shared with `AttemptMetricsPolicy`
+struct Attempts(AtomicU32);
+
+/// Per-call policy: counts operations, retries and exhaustion
+#[derive(Debug)]
+pub(crate) struct CallMetricsPolicy;
+
+impl Policy for CallMetricsPolicy {
+    fn send<'life0, 'life1, 'life2, 'life3, 'async_trait>(
+        &'life0 self,
+        ctx: &'life1 Context,
+        request: &'life2 mut Request,
+        next: &'life3 [Arc<dyn Policy>],
+    ) -> Pin<Box<dyn Future<Output = PolicyResult> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        'life3: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            let operation = operation_name(request);
+            let mut ctx = ctx.clone();
+            ctx.insert(Attempts(AtomicU32::new(0)));
+
+            let started = Instant::now();
+            let result = next[0].send(&ctx, request, &next[1..]).await;
+
+            let attempts = ctx.get::<Attempts>().map_or(1, |attempts| attempts.0.load(Ordering::Relaxed));
+            record_call(operation, attempts.max(1), started.elapsed(), is_retries_exhausted(&result));
+            result
+        })
+    }
+}
+
+/// Per-retry policy: counts attempts and their throttling responses
+#[derive(Debug)]
+pub(crate) struct AttemptMetricsPolicy;
+
+impl Policy for AttemptMetricsPolicy {
+    fn send<'life0, 'life1, 'life2, 'life3, 'async_trait>(
+        &'life0 self,
+        ctx: &'life1 Context,
+        request: &'life2 mut Request,
+        next: &'life3 [Arc<dyn Policy>],
+    ) -> Pin<Box<dyn Future<Output = PolicyResult> + Send + 'async_trait>>
+    where
+        'life0: 'async_trait,
+        'life1: 'async_trait,
+        'life2: 'async_trait,
+        'life3: 'async_trait,
+        Self: 'async_trait,
+    {
+        Box::pin(async move {
+            if let Some(attempts) = ctx.get::<Attempts>() {
+                attempts.0.fetch_add(1, Ordering::Relaxed);
+            }
+            let result = next[0].send(ctx, request, &next[1..]).await;
+            if let Ok(response) = &result {
+                record_attempt(response.status());
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use azure_core::{Method, Url};
+
+    #[test]
+    fn test_operation_names() {
+        let request = |url: &str, method| Request::new(Url::parse(url).unwrap(), method);
+        assert_eq!(
+            operation_name(&request("https://a.blob.core.windows.net/c/wal?comp=appendblock", Method::Put)),
+            "PUT appendblock"
+        );
+        assert_eq!(
+            operation_name(&request("https://a.blob.core.windows.net/c?restype=container", Method::Put)),
+            "PUT container"
+        );
+        assert_eq!(operation_name(&request("https://a.blob.core.windows.net/c/data", Method::Get)), "GET");
+    }
+
+    #[test]
+    fn test_retried_calls_are_counted_per_operation() {
+        record_call("TEST retried".to_string(), 3, Duration::from_millis(40), false);
+        record_call("TEST retried".to_string(), 1, Duration::from_millis(1), false);
+        record_call("TEST retried".to_string(), 4, Duration::from_millis(90), true);
+
+        let metrics = storage_metrics();
+        let operation = metrics.operations["TEST retried"];
+        assert_eq!((operation.calls, operation.retries, operation.retried_calls), (3, 5, 2));
+        assert_eq!(operation.max_retried_latency_us, 90_000);
+        assert_eq!(operation.retries_exhausted, 1);
+        assert!(metrics.retries >= 5 && metrics.retries_exhausted >= 1);
+    }
+}
@@ -0,0 +1,146 @@
+//! Store Meta: Application Control Data Under `__meta/`
+//!
+//! Applications need a few store-wide facts next to their data: which
+//! schema version the values follow, settings, whether a migration or a
+//! decommissioning has started. Kept as ordinary keys, they show up in
+//! scans and can be deleted along with user data. The `__meta/` namespace
+//! holds them instead, through typed accessors:
+//!
+//! ```ignore
+//! if store.schema_version().await? < Some(3) {
+//!     migrate_to_v3(&store).await?;
+//!     store.upgrade_schema(Some(2), 3).await?;
+//! }
+//! store.set_meta("owner", "payments-team").await?;
+//! store.mark_lifecycle("backfill-done", "orders up to 2024-06").await?;
+//! ```
+//!
+//! ```text
+//! __meta/schema_version      -> "3"
+//! __meta/app/<name>          -> application value
+//! __meta/lifecycle/<marker>  -> {"at_ms": ..., "detail": "..."}
+//! ```
+//!
+//! `scan` leaves `__meta/` keys out, and `delete` refuses them with
+//! `IronCladError::NotSupported`; `delete_meta` removes application
+//! values. Meta keys are written like any other key: logged, replicated
+//! and backed up with the store.
+//!
+//! Not to be confused with per-key metadata (metadata.rs).
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+
+/// Key prefix of the meta namespace
+const META_PREFIX: &str = "__meta/";
+
+const SCHEMA_VERSION_KEY: &str = "__meta/schema_version";
+const APP_PREFIX: &str = "__meta/app/";
+const LIFECYCLE_PREFIX: &str = "__meta/lifecycle/";
+
+/// A named point in the store's life, recorded once
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LifecycleMarker {
+    /// When it was recorded (ms since the epoch)
+    pub at_ms: u64,
+    /// Free-form detail given when recording it
+    pub detail: String,
+}
+
+/// Is `key` in the meta namespace?
+pub(crate) fn is_meta(key: &str) -> bool {
+    key.starts_with(META_PREFIX)
+}
+
+impl KVStore {
+    /// Schema version the application recorded (None = never set)
+    pub async fn schema_version(&self) -> Result<Option<u64>> {
+        match self.get(SCHEMA_VERSION_KEY).await? {
+            Some(version) => Ok(Some(version.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Record the schema version
+    pub async fn set_schema_version(&self, version: u64) -> Result<()> {
+        self.set(SCHEMA_VERSION_KEY, &version.to_string()).await
+    }
+
+    /// Move the schema version from `from` (None = never set) to `to`,
+    /// unless another migrator already moved it; returns whether it moved
+    pub async fn upgrade_schema(&self, from: Option<u64>, to: u64) -> Result<bool> {
+        let expected = from.map(|version| version.to_string());
+        self.compare_and_set(SCHEMA_VERSION_KEY, expected.as_deref(), &to.to_string()).await
+    }
+
+    /// Application metadata value `name`
+    pub async fn get_meta(&self, name: &str) -> Result<Option<String>> {
+        self.get(&format!("{}{}", APP_PREFIX, name)).await
+    }
+
+    /// Set application metadata value `name`
+    pub async fn set_meta(&self, name: &str, value: &str) -> Result<()> {
+        self.set(&format!("{}{}", APP_PREFIX, name), value).await
+    }
+
+    /// Delete application metadata value `name`; returns whether it existed
+    pub async fn delete_meta(&self, name: &str) -> Result<bool> {
+        self.delete_unchecked(&format!("{}{}", APP_PREFIX, name)).await
+    }
+
+    /// All application metadata, by name
+    pub async fn list_meta(&self) -> Result<Vec<(String, String)>> {
+        let mut entries = Vec::new();
+        for key in self.keys_with_prefix(APP_PREFIX) {
+            if let Some(value) = self.get(&key).await? {
+                entries.push((key[APP_PREFIX.len()..].to_string(), value));
+            }
+        }
+        entries.sort();
+        Ok(entries)
+    }
+
+    /// Record lifecycle marker `name` now; returns false (keeping the
+    /// first) if it was already recorded
+    pub async fn mark_lifecycle(&self, name: &str, detail: &str) -> Result<bool> {
+        let marker = LifecycleMarker { at_ms: now_ms(), detail: detail.to_string() };
+        self.set_nx(&format!("{}{}", LIFECYCLE_PREFIX, name), &serde_json::to_string(&marker)?).await
+    }
+
+    /// Lifecycle marker `name`, if recorded
+    pub async fn lifecycle_marker(&self, name: &str) -> Result<Option<LifecycleMarker>> {
+        match self.get(&format!("{}{}", LIFECYCLE_PREFIX, name)).await? {
+            Some(json) => Ok(Some(serde_json::from_str(&json)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Every lifecycle marker, by name, oldest first
+    pub async fn lifecycle_markers(&self) -> Result<Vec<(String, LifecycleMarker)>> {
+        let mut markers = Vec::new();
+        for key in self.keys_with_prefix(LIFECYCLE_PREFIX) {
+            if let Some(json) = self.get(&key).await? {
+                markers.push((key[LIFECYCLE_PREFIX.len()..].to_string(), serde_json::from_str::<LifecycleMarker>(&json)?));
+            }
+        }
+        markers.sort_by_key(|(_, marker)| marker.at_ms);
+        Ok(markers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_keys_are_reserved() {
+        assert!(is_meta(SCHEMA_VERSION_KEY));
+        assert!(is_meta(&format!("{}owner", APP_PREFIX)));
+        assert!(is_meta(&format!("{}backfill-done", LIFECYCLE_PREFIX)));
+        assert!(crate::trash::is_reserved(META_PREFIX));
+        assert!(!is_meta("meta/owner"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Superblock: Store-Wide Metadata in Page 0
+//!
+//! Page 0 of the data blob is reserved for the superblock; key-value pages
+//! start at `FIRST_DATA_PAGE`. Layout:
+//!
+//! ```text
+//! [magic: 8 bytes "IRONCLAD"][payload_len: u32 LE][checksum: u64 LE][JSON payload][zero padding]
+//! ```
+//!
+//! The payload is JSON so new fields can be added with `#[serde(default)]`
+//! without breaking older superblocks. The checksum (FNV-1a over the payload)
+//! catches torn or corrupted writes.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::checkpoint_history::CheckpointRecord;
+use crate::error::IronCladError;
+use crate::index::IndexSnapshot;
+use crate::page::PAGE_SIZE;
+use crate::replay::ReplayMarker;
+use crate::shadow::{Durability, ShadowRoot};
+
+/// Page ID holding the superblock
+pub const SUPERBLOCK_PAGE: u64 = 0;
+
+/// First page ID available for key-value pages
+pub const FIRST_DATA_PAGE: u64 = 1;
+
+const MAGIC: &[u8; 8] = b"IRONCLAD";
+const HEADER_SIZE: usize = 8 + 4 + 8;
+
+/// Current superblock format version
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Store-wide metadata persisted in page 0
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Superblock {
+    /// Format version of the store
+    pub format_version: u32,
+
+    /// Writer epoch: bumped every time a new writer takes over the store.
+    /// Doubles as the fencing token handed to external systems.
+    pub epoch: u64,
+
+    /// Creation marker (see bootstrap.rs); superblocks written before the
+    /// marker existed belong to fully created stores
+    #[serde(default)]
+    pub state: StoreState,
+
+    /// Packed pages holding the inline values as of the last checkpoint:
+    /// `inline_count` pages from `inline_start` (see kvstore.rs)
+    #[serde(default)]
+    pub inline_start: u64,
+    #[serde(default)]
+    pub inline_count: u64,
+
+    /// Packed pages holding the page-backed index entries as of the last
+    /// checkpoint (see index.rs)
+    #[serde(default)]
+    pub index_snapshot: Option<IndexSnapshot>,
+
+    /// How far the last recovery of the current log got (see replay.rs)
+    #[serde(default)]
+    pub replay_marker: Option<ReplayMarker>,
+
+    /// How writes are made durable, chosen at creation (see shadow.rs)
+    #[serde(default)]
+    pub durability: Durability,
+
+    /// Installed page table of a shadow-paged store
+    #[serde(default)]
+    pub shadow_root: Option<ShadowRoot>,
+
+    /// End of the values reserved so far by each auto-key counter (see
+    /// auto_key.rs)
+    #[serde(default)]
+    pub counters: BTreeMap<String, u64>,
+
+    /// The last checkpoints, oldest first (see checkpoint_history.rs)
+    #[serde(default)]
+    pub checkpoints: VecDeque<CheckpointRecord>,
+}
+
+/// How far store creation got
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum StoreState {
+    /// Bootstrap started but hasn't finished
+    Creating,
+    /// Store is fully created
+    #[default]
+    Ready,
+}
+
+impl Default for Superblock {
+    fn default() -> Self {
+        Self {
+            format_version: FORMAT_VERSION,
+            epoch: 0,
+            state: StoreState::Ready,
+            inline_start: 0,
+            inline_count: 0,
+            index_snapshot: None,
+            replay_marker: None,
+            durability: Durability::Wal,
+            shadow_root: None,
+            counters: BTreeMap::new(),
+            checkpoints: VecDeque::new(),
+        }
+    }
+}
+
+impl Superblock {
+    /// Encode into a full page
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        let payload = serde_json::to_vec(self)?;
+        if HEADER_SIZE + payload.len() > PAGE_SIZE {
+            anyhow::bail!("Superblock too large for a page ({} bytes)", payload.len());
+        }
+
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[0..8].copy_from_slice(MAGIC);
+        page[8..12].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        page[12..20].copy_from_slice(&checksum(&payload).to_le_bytes());
+        page[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(&payload);
+
+        Ok(page)
+    }
+
+    /// Decode page 0; returns None for a never-initialized (all zero / no magic) page
+    pub fn decode(page: &[u8]) -> Result<Option<Self>> {
+        if page.len() < HEADER_SIZE || &page[0..8] != MAGIC {
+            return Ok(None);
+        }
+
+        let payload_len = u32::from_le_bytes([page[8], page[9], page[10], page[11]]) as usize;
+        let mut checksum_bytes = [0u8; 8];
+        checksum_bytes.copy_from_slice(&page[12..20]);
+        let expected = u64::from_le_bytes(checksum_bytes);
+
+        let payload = page
+            .get(HEADER_SIZE..HEADER_SIZE.saturating_add(payload_len))
+            .ok_or_else(|| invalid(format!("payload length {} runs past end of page", payload_len)))?;
+
+        if checksum(payload) != expected {
+            return Err(invalid("checksum mismatch".to_string()));
+        }
+
+        let superblock = serde_json::from_slice(payload)
+            .map_err(|e| invalid(format!("unreadable payload: {}", e)))?;
+
+        Ok(Some(superblock))
+    }
+}
+
+/// FNV-1a 64-bit hash used as the superblock checksum
+pub fn checksum(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn invalid(reason: String) -> anyhow::Error {
+    IronCladError::InvalidPageFormat(format!("superblock: {}", reason)).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_superblock_round_trip() {
+        let superblock = Superblock { epoch: 42, ..Default::default() };
+        let page = superblock.encode().unwrap();
+        assert_eq!(page.len(), PAGE_SIZE);
+        assert_eq!(Superblock::decode(&page).unwrap(), Some(superblock));
+    }
+
+    #[test]
+    fn test_superblock_without_state_is_ready() {
+        let payload = br#"{"format_version":1,"epoch":3}"#;
+        let mut page = vec![0u8; PAGE_SIZE];
+        page[0..8].copy_from_slice(MAGIC);
+        page[8..12].copy_from_slice(&(payload.len() as u32).to_le_bytes());
+        page[12..20].copy_from_slice(&checksum(payload).to_le_bytes());
+        page[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+
+        let superblock = Superblock::decode(&page).unwrap().unwrap();
+        assert_eq!(superblock.state, StoreState::Ready);
+        assert_eq!(superblock.epoch, 3);
+        assert_eq!(superblock.inline_count, 0);
+        assert_eq!(superblock.replay_marker, None);
+    }
+
+    #[test]
+    fn test_blank_page_is_uninitialized() {
+        assert_eq!(Superblock::decode(&vec![0u8; PAGE_SIZE]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_corrupt_superblock() {
+        let mut page = Superblock::default().encode().unwrap();
+        page[HEADER_SIZE] ^= 0xFF;
+        assert!(Superblock::decode(&page).is_err());
+
+        let mut page = Superblock::default().encode().unwrap();
+        page[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Superblock::decode(&page).is_err());
+    }
+}
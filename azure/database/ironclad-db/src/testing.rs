@@ -0,0 +1,38 @@
+//! Test Support: Stores on In-Memory Backends
+//!
+//! Unit tests open stores on a `MemoryDisk` and a `MemoryLog`, so they
+//! need no storage account (see page_store.rs). Clones of the backends
+//! share their contents: reopening with the same options "restarts" the
+//! store on the same data.
+
+use crate::kvstore::KVStore;
+use crate::log_store::{MemoryLog, WalBackend};
+use crate::options::StoreOptions;
+use crate::page_store::{MemoryDisk, PageBackend};
+
+/// Any well-formed connection string; memory backends never use it
+pub(crate) const CONNECTION: &str = "AccountName=test;AccountKey=test";
+
+/// Default options, with pages in `disk` and the WAL in `log`
+pub(crate) fn memory_options(disk: &MemoryDisk, log: &MemoryLog) -> StoreOptions {
+    StoreOptions {
+        page_backend: PageBackend::Memory(disk.clone()),
+        wal_backend: WalBackend::Memory(log.clone()),
+        ..Default::default()
+    }
+}
+
+/// Default options on a fresh disk and log
+pub(crate) fn fresh_options() -> StoreOptions {
+    memory_options(&MemoryDisk::new(), &MemoryLog::new())
+}
+
+/// Open (or reopen) a store with `options`
+pub(crate) async fn open_store(options: StoreOptions) -> KVStore {
+    KVStore::open(CONNECTION, options).await.unwrap()
+}
+
+/// A store with default options on a fresh disk and log
+pub(crate) async fn memory_store() -> KVStore {
+    open_store(fresh_options()).await
+}
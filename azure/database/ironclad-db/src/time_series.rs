@@ -0,0 +1,184 @@
+//! Time Series: Samples in Time-Bucketed Keys with Retention
+//!
+//! `TimeSeries` stores `(timestamp_ms, value)` samples in one key per
+//! time bucket, so a bucket's samples share a page and a time-window query
+//! reads only the buckets it overlaps:
+//!
+//! ```text
+//! __ts/<name>/<bucket_start_ms:016x>   "<timestamp_ms> <value>\n" per sample
+//! ```
+//!
+//! A sample is added by reading its bucket and setting it with the new
+//! line at the end, under the commit lock so concurrent samples aren't
+//! lost. Each sample rewrites (and logs) the whole bucket, and a bucket
+//! must fit in a page: choose `bucket` so a bucket's samples stay under
+//! ~4KB (about 150 samples); a sample that doesn't fit fails like an
+//! oversized `set`.
+//!
+//! With `retention` set, whole buckets older than the retention window are
+//! deleted each time writing moves on to a new bucket (and on
+//! `enforce_retention`). Options are per handle, not stored.
+
+use anyhow::Result;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tracing::debug;
+
+use crate::collections::validate_name;
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+
+/// Key prefix for time series
+const TS_PREFIX: &str = "__ts/";
+
+/// Options for a time series handle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSeriesOptions {
+    /// Width of each bucket
+    pub bucket: Duration,
+    /// Age after which buckets are deleted (None = keep forever)
+    pub retention: Option<Duration>,
+}
+
+impl Default for TimeSeriesOptions {
+    fn default() -> Self {
+        Self {
+            bucket: Duration::from_secs(60),
+            retention: None,
+        }
+    }
+}
+
+/// Handle to a time series
+pub struct TimeSeries<'a> {
+    store: &'a KVStore,
+    prefix: String,
+    options: TimeSeriesOptions,
+    /// Bucket written last (u64::MAX before the first write)
+    last_bucket: AtomicU64,
+}
+
+impl<'a> TimeSeries<'a> {
+    /// Record a sample at `timestamp_ms`
+    pub async fn record(&self, timestamp_ms: u64, value: f64) -> Result<()> {
+        let bucket = self.bucket_of(timestamp_ms);
+        {
+            let _commit_guard = self.store.lock_commits().await;
+            let key = self.bucket_key(bucket);
+            let mut samples = self.store.get(&key).await?.unwrap_or_default();
+            samples.push_str(&format!("{} {}\n", timestamp_ms, value));
+            self.store.set(&key, &samples).await?;
+        }
+
+        if self.last_bucket.swap(bucket, Ordering::Relaxed) != bucket {
+            self.enforce_retention().await?;
+        }
+        Ok(())
+    }
+
+    /// Record a sample at the current time
+    pub async fn record_now(&self, value: f64) -> Result<()> {
+        self.record(now_ms(), value).await
+    }
+
+    /// Samples with `start_ms <= timestamp < end_ms`, by timestamp
+    pub async fn range(&self, start_ms: u64, end_ms: u64) -> Result<Vec<(u64, f64)>> {
+        let first_bucket = self.bucket_of(start_ms);
+        let mut samples = Vec::new();
+
+        for (bucket, key) in self.buckets() {
+            if bucket < first_bucket || bucket >= end_ms {
+                continue;
+            }
+            if let Some(stored) = self.store.get(&key).await? {
+                samples.extend(parse_samples(&stored).filter(|(ts, _)| *ts >= start_ms && *ts < end_ms));
+            }
+        }
+
+        samples.sort_by_key(|(ts, _)| *ts);
+        Ok(samples)
+    }
+
+    /// Delete buckets that ended before the retention window; returns how many
+    pub async fn enforce_retention(&self) -> Result<usize> {
+        let Some(retention) = self.options.retention else {
+            return Ok(0);
+        };
+        let cutoff = now_ms().saturating_sub(retention.as_millis() as u64);
+
+        let mut expired = 0;
+        for (bucket, key) in self.buckets() {
+            if bucket.saturating_add(self.bucket_ms()) <= cutoff {
+                self.store.delete(&key).await?;
+                expired += 1;
+            }
+        }
+        if expired > 0 {
+            debug!("TIMESERIES: {} expired {} buckets", self.prefix, expired);
+        }
+        Ok(expired)
+    }
+
+    pub fn options(&self) -> &TimeSeriesOptions {
+        &self.options
+    }
+
+    /// (start, key) of every bucket
+    fn buckets(&self) -> Vec<(u64, String)> {
+        self.store
+            .keys_with_prefix(&self.prefix)
+            .into_iter()
+            .filter_map(|key| Some((u64::from_str_radix(&key[self.prefix.len()..], 16).ok()?, key)))
+            .collect()
+    }
+
+    fn bucket_ms(&self) -> u64 {
+        (self.options.bucket.as_millis() as u64).max(1)
+    }
+
+    fn bucket_of(&self, timestamp_ms: u64) -> u64 {
+        timestamp_ms - timestamp_ms % self.bucket_ms()
+    }
+
+    fn bucket_key(&self, bucket: u64) -> String {
+        format!("{}{:016x}", self.prefix, bucket)
+    }
+}
+
+/// Samples stored in a bucket (malformed lines are skipped)
+fn parse_samples(stored: &str) -> impl Iterator<Item = (u64, f64)> + '_ {
+    stored.lines().filter_map(|line| {
+        let (ts, value) = line.split_once(' ')?;
+        Some((ts.parse().ok()?, value.parse().ok()?))
+    })
+}
+
+impl KVStore {
+    /// Handle to the time series `name`
+    pub fn time_series(&self, name: &str, options: TimeSeriesOptions) -> Result<TimeSeries<'_>> {
+        validate_name(name)?;
+        Ok(TimeSeries {
+            store: self,
+            prefix: format!("{}{}/", TS_PREFIX, name),
+            options,
+            last_bucket: AtomicU64::new(u64::MAX),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_samples() {
+        let samples: Vec<(u64, f64)> = parse_samples("1000 1.5\n1001 -2\ngarbage\n1002 inf\n").collect();
+        assert_eq!(samples, vec![(1000, 1.5), (1001, -2.0), (1002, f64::INFINITY)]);
+    }
+
+    #[test]
+    fn test_sample_line_round_trip() {
+        let line = format!("{} {}\n", 42u64, 0.1f64);
+        assert_eq!(parse_samples(&line).collect::<Vec<_>>(), vec![(42, 0.1)]);
+    }
+}
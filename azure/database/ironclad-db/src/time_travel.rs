@@ -0,0 +1,160 @@
+//! Time Travel: Read-Only Queries As of an Earlier LSN
+//!
+//! `at_lsn` gives a read-only view of the store as it stood once the write
+//! at an LSN was applied:
+//!
+//! ```ignore
+//! let lsn = store.wal().current_lsn();
+//! store.set("user:1", "new").await?;
+//! assert_eq!(store.at_lsn(lsn).get("user:1").await?, Some("old".into()));
+//! ```
+//!
+//! A value is rebuilt from the WAL: a key no write touched after the LSN
+//! reads its current value, and any other key replays its writes up to the
+//! LSN on top of the last set or delete before it.
+//!
+//! History only reaches back as far as the WAL does. A checkpoint clears
+//! the log, and the store keeps no archive of it, so:
+//!
+//! - reading at an LSN before the last checkpoint fails with
+//!   `IronCladError::NotSupported`;
+//! - a key whose value at the LSN depends on the checkpointed pages (it
+//!   was changed afterwards, but not set or deleted between the checkpoint
+//!   and the LSN) fails with `IronCladError::SnapshotTooOld`.
+//!
+//! Each `get` reads the whole WAL, so time travel suits audits and
+//! debugging rather than hot paths.
+
+use anyhow::Result;
+
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::patch;
+use crate::wal::WalEntry;
+
+/// A read-only view of the store as of an LSN (see time_travel.rs)
+pub struct StoreAtLsn<'a> {
+    store: &'a KVStore,
+    lsn: u64,
+}
+
+impl<'a> StoreAtLsn<'a> {
+    /// LSN the view reads at
+    pub fn lsn(&self) -> u64 {
+        self.lsn
+    }
+
+    /// A key's value as of the view's LSN
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        // Read the current value before the log, so a write racing with the
+        // read is in the log if the value reflects it
+        let current = self.store.get(key).await?;
+        let entries = self.store.wal().replay_since(0).await?;
+
+        let head_lsn = self.store.wal().current_lsn();
+        if self.lsn > head_lsn {
+            let reason = format!("LSN {} is ahead of the WAL (at {})", self.lsn, head_lsn);
+            return Err(IronCladError::NotSupported { operation: "at_lsn", reason }.into());
+        }
+        let oldest_lsn = oldest_lsn(&entries);
+        if self.lsn < oldest_lsn {
+            let reason = format!("LSN {} was checkpointed; the WAL starts at LSN {}", self.lsn, oldest_lsn);
+            return Err(IronCladError::NotSupported { operation: "at_lsn", reason }.into());
+        }
+
+        match value_at(&entries, key, self.lsn) {
+            Version::Unchanged => Ok(current),
+            Version::Known(Some(stored)) => self.store.read_value(key, stored).await.map(Some),
+            Version::Known(None) => Ok(None),
+            Version::Checkpointed => Err(IronCladError::SnapshotTooOld { key: key.to_string() }.into()),
+        }
+    }
+}
+
+/// A key's value at an LSN, as far as the WAL tells
+#[derive(Debug, PartialEq, Eq)]
+enum Version {
+    /// No write after the LSN touched the key: its current value
+    Unchanged,
+    /// The stored value (None = absent)
+    Known(Option<String>),
+    /// Changed since, from a value only the checkpointed pages held
+    Checkpointed,
+}
+
+/// Oldest LSN the WAL can serve: a cleared log opens with the LSN it was
+/// cleared at, one never cleared holds every write
+fn oldest_lsn(entries: &[(u64, WalEntry)]) -> u64 {
+    match entries.first() {
+        Some((_, WalEntry::Checkpoint { lsn })) => *lsn,
+        _ => 0,
+    }
+}
+
+/// `key`'s value after the entry at `lsn`, replaying `entries` (the whole
+/// WAL, in LSN order)
+fn value_at(entries: &[(u64, WalEntry)], key: &str, lsn: u64) -> Version {
+    let writes = || entries.iter().filter(|(_, entry)| entry.key() == Some(key));
+    if writes().all(|(write_lsn, _)| *write_lsn <= lsn) {
+        return Version::Unchanged;
+    }
+
+    // Before its first logged write a key holds what the checkpoint left
+    let cleared = matches!(entries.first(), Some((_, WalEntry::Checkpoint { .. })));
+    let mut value = if cleared { None } else { Some(None) };
+    for (_, entry) in writes().take_while(|(write_lsn, _)| *write_lsn <= lsn) {
+        value = match (entry, value) {
+            (WalEntry::Set { value, .. }, _) => Some(Some(value.clone())),
+            (WalEntry::Delete { .. }, _) => Some(None),
+            (_, None) => None,
+            // Patches that failed when first applied changed nothing then either
+            (WalEntry::Patch { pointer, value: fragment, .. }, Some(current)) => {
+                match patch::patched_value(key, current.as_deref(), pointer, fragment.clone()) {
+                    Ok(patched) => Some(Some(patched)),
+                    Err(_) => Some(current),
+                }
+            }
+            (_, current) => current,
+        };
+    }
+    value.map_or(Version::Checkpointed, Version::Known)
+}
+
+impl KVStore {
+    /// A read-only view of the store as of `lsn` (see time_travel.rs)
+    pub fn at_lsn(&self, lsn: u64) -> StoreAtLsn<'_> {
+        StoreAtLsn { store: self, lsn }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_values_are_rebuilt_from_the_log() {
+        let set = |key: &str, value: &str| WalEntry::Set { key: key.into(), value: value.into(), at_ms: 1 };
+        let entries = vec![
+            (1, set("a", r#"{"n":1}"#)),
+            (2, WalEntry::Patch { key: "a".into(), pointer: "/n".into(), value: json!(2), at_ms: 2 }),
+            (3, set("b", "x")),
+            (4, set("b", "xy")),
+            (5, WalEntry::Delete { key: "a".into() }),
+        ];
+        assert_eq!(value_at(&entries, "a", 0), Version::Known(None));
+        assert_eq!(value_at(&entries, "a", 1), Version::Known(Some(r#"{"n":1}"#.into())));
+        assert_eq!(value_at(&entries, "a", 4), Version::Known(Some(r#"{"n":2}"#.into())));
+        assert_eq!(value_at(&entries, "a", 5), Version::Unchanged);
+        assert_eq!(value_at(&entries, "b", 3), Version::Known(Some("x".into())));
+        assert_eq!(value_at(&entries, "c", 2), Version::Unchanged);
+        assert_eq!(oldest_lsn(&entries), 0);
+
+        // After a checkpoint, only writes replayed from a set are known
+        let mut cleared = vec![(11, WalEntry::Checkpoint { lsn: 10 })];
+        cleared.extend(entries.into_iter().map(|(lsn, entry)| (lsn + 11, entry)));
+        assert_eq!(oldest_lsn(&cleared), 10);
+        assert_eq!(value_at(&cleared, "a", 11), Version::Checkpointed);
+        assert_eq!(value_at(&cleared, "b", 14), Version::Known(Some("x".into())));
+    }
+}
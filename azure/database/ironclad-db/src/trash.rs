@@ -0,0 +1,191 @@
+//! Trash: Soft Deletes with a Retention Window
+//!
+//! With `StoreOptions::trash_retention` set, `KVStore::delete` doesn't drop
+//! a key outright: one WAL batch deletes it and records its last value under
+//! `__trash/<key>`. Until the retention window runs out, `undelete(key)`
+//! brings it back; `purge_trash` deletes entries past the window.
+//!
+//! Only `delete` and `compare_and_delete` go through the trash, and both
+//! hold the commit lock from reading the value to committing the batch.
+//! Transaction deletes, and deletes of keys in the reserved `__` namespace
+//! (locks, catalogs, column family data), remove keys outright.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+use crate::retention::ReclaimKind;
+use crate::wal::WalEntry;
+
+/// Key prefix for trashed entries
+pub(crate) const TRASH_PREFIX: &str = "__trash/";
+
+/// Prefix of keys the store uses internally
+const RESERVED_PREFIX: &str = "__";
+
+/// A deleted value awaiting purge
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct TrashEntry {
+    value: String,
+    deleted_at_ms: u64,
+}
+
+impl TrashEntry {
+    fn is_expired(&self, now_ms: u64, retention_ms: u64) -> bool {
+        now_ms.saturating_sub(self.deleted_at_ms) >= retention_ms
+    }
+}
+
+/// Is `key` in the reserved namespace (never trashed)?
+pub(crate) fn is_reserved(key: &str) -> bool {
+    key.starts_with(RESERVED_PREFIX)
+}
+
+impl KVStore {
+    /// Restore a key from the trash
+    ///
+    /// Returns false if there is nothing (unexpired) to restore, or if the
+    /// key has been written again since it was deleted.
+    pub async fn undelete(&self, key: &str) -> Result<bool> {
+        let _commit_guard = self.lock_commits().await;
+
+        let Some(entry) = self.trash_entry(key).await? else {
+            return Ok(false);
+        };
+        if entry.is_expired(now_ms(), self.retention_ms()) || self.get(key).await?.is_some() {
+            return Ok(false);
+        }
+
+        let writes = vec![
+            WalEntry::set(key, &entry.value),
+            WalEntry::Delete { key: trash_key(key) },
+        ];
+        self.commit_batch_locked(self.new_txn_id(), writes, None).await?;
+
+        info!("UNDELETE: {}", key);
+        Ok(true)
+    }
+
+    /// Keys in the trash that can still be restored
+    pub async fn trashed_keys(&self) -> Result<Vec<String>> {
+        let now = now_ms();
+        let mut keys = Vec::new();
+
+        for trashed in self.keys_with_prefix(TRASH_PREFIX) {
+            let key = &trashed[TRASH_PREFIX.len()..];
+            if let Some(entry) = self.trash_entry(key).await? {
+                if !entry.is_expired(now, self.retention_ms()) {
+                    keys.push(key.to_string());
+                }
+            }
+        }
+        keys.sort();
+        Ok(keys)
+    }
+
+    /// Permanently delete trash entries past the retention window; returns how many
+    pub async fn purge_trash(&self) -> Result<usize> {
+        let purged = self.reclaim(&[ReclaimKind::Trash]).await?.len();
+        info!("TRASH: purged {} entries", purged);
+        Ok(purged)
+    }
+
+    /// Is the trash entry stored as `value` past the retention window (or
+    /// unreadable)?
+    pub(crate) fn trash_expired(&self, value: &str, now_ms: u64) -> bool {
+        serde_json::from_str::<TrashEntry>(value).map_or(true, |entry| entry.is_expired(now_ms, self.retention_ms()))
+    }
+
+    /// Does deleting `key` move it to the trash?
+    pub(crate) fn trashes(&self, key: &str) -> bool {
+        self.options().trash_retention.is_some() && !is_reserved(key)
+    }
+
+    /// Delete `key`, keeping its value in the trash
+    ///
+    /// Holds off other commits between reading the value and the batch, so
+    /// a concurrent commit can't land in between and leave the trash a
+    /// stale value.
+    pub(crate) async fn move_to_trash(&self, key: &str) -> Result<bool> {
+        let _commit_guard = self.lock_commits().await;
+        self.move_to_trash_locked(key).await
+    }
+
+    /// `move_to_trash` for a caller already holding `lock_commits`
+    pub(crate) async fn move_to_trash_locked(&self, key: &str) -> Result<bool> {
+        let Some(value) = self.get(key).await? else {
+            return Ok(false);
+        };
+
+        let entry = TrashEntry { value: value.clone(), deleted_at_ms: now_ms() };
+        let mut writes = vec![
+            WalEntry::Delete { key: key.to_string() },
+            WalEntry::set(&trash_key(key), &serde_json::to_string(&entry)?),
+        ];
+        writes.extend(self.version_writes(key, value).await?);
+        self.commit_batch_locked(self.new_txn_id(), writes, None).await?;
+
+        info!("DELETE: {} (moved to trash)", key);
+        Ok(true)
+    }
+
+    async fn trash_entry(&self, key: &str) -> Result<Option<TrashEntry>> {
+        match self.get(&trash_key(key)).await? {
+            Some(value) => Ok(Some(serde_json::from_str(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn retention_ms(&self) -> u64 {
+        self.options().trash_retention.map(|d| d.as_millis() as u64).unwrap_or(0)
+    }
+}
+
+fn trash_key(key: &str) -> String {
+    format!("{}{}", TRASH_PREFIX, key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options::StoreOptions;
+    use crate::testing::{fresh_options, open_store};
+    use std::time::Duration;
+
+    #[test]
+    fn test_reserved_keys_skip_the_trash() {
+        assert!(is_reserved("__lock/leader"));
+        assert!(is_reserved("__trash/user:1"));
+        assert!(!is_reserved("user:1"));
+    }
+
+    #[test]
+    fn test_trash_entry_expiry() {
+        let entry = TrashEntry { value: "v".to_string(), deleted_at_ms: 10_000 };
+        assert!(!entry.is_expired(15_000, 10_000));
+        assert!(entry.is_expired(20_000, 10_000));
+    }
+
+    #[tokio::test]
+    async fn test_deletes_move_to_the_trash_under_the_commit_lock() {
+        let options = StoreOptions { trash_retention: Some(Duration::from_secs(60)), ..fresh_options() };
+        let store = open_store(options).await;
+        store.set("a", "1").await.unwrap();
+        store.set("b", "2").await.unwrap();
+
+        // compare_and_delete already holds the lock the trash move takes
+        let deletes = async {
+            assert!(!store.compare_and_delete("a", "other").await.unwrap());
+            assert!(store.compare_and_delete("a", "1").await.unwrap());
+            assert!(store.delete("b").await.unwrap());
+        };
+        tokio::time::timeout(Duration::from_secs(5), deletes).await.expect("trash move deadlocked");
+        assert_eq!(store.trashed_keys().await.unwrap(), vec!["a", "b"]);
+
+        assert!(store.undelete("a").await.unwrap());
+        assert_eq!(store.get("a").await.unwrap().as_deref(), Some("1"));
+        assert!(store.get("b").await.unwrap().is_none());
+    }
+}
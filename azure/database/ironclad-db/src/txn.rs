@@ -0,0 +1,286 @@
+//! Transaction: Atomic Multi-Key Write Batches
+//!
+//! A transaction buffers sets and deletes in memory and commits them as a
+//! single WAL batch (Begin, entries, Commit) written with one append. Recovery
+//! applies a batch only if its Commit marker made it to the log, so a crash
+//! mid-commit never leaves half a transaction behind.
+//!
+//! Reads inside the transaction see its own uncommitted writes first.
+//!
+//! # Isolation levels
+//!
+//! Every write bumps a per-key version taken from a store-wide commit
+//! sequence; a transaction remembers the sequence at `begin`.
+//!
+//! | Anomaly             | ReadCommitted | Snapshot |
+//! |---------------------|---------------|----------|
+//! | Dirty read          | no            | no       |
+//! | Non-repeatable read | possible      | no       |
+//! | Lost update         | possible      | no       |
+//! | Write skew          | possible      | possible |
+//!
+//! - `ReadCommitted` (the default for `begin()`): reads return the latest
+//!   committed value and commit never conflicts — last writer wins. No
+//!   tracking overhead.
+//! - `Snapshot` (`begin_with(Isolation::Snapshot)`): reads only ever return
+//!   values committed before the snapshot and are repeatable. The store
+//!   keeps no old versions, so reading a key that changed after the snapshot
+//!   fails with `SnapshotTooOld` instead of returning the newer value. Commit
+//!   fails with `WriteConflict` if another writer changed a key this
+//!   transaction writes (first committer wins). Callers retry on either error.
+//!   Write skew is still possible: two transactions that each read both
+//!   keys and write a different one can both commit.
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::wal::WalEntry;
+
+/// Transaction isolation level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Isolation {
+    /// Read the latest committed value; last writer wins on commit
+    #[default]
+    ReadCommitted,
+    /// Read a consistent snapshot; first committer wins on write conflicts
+    Snapshot,
+}
+
+/// A batch of writes applied atomically on commit
+pub struct Transaction<'a> {
+    store: &'a KVStore,
+    txn_id: u64,
+    isolation: Isolation,
+    /// Store commit sequence when the transaction began
+    snapshot_seq: u64,
+    writes: Vec<WalEntry>,
+    /// Values already read under snapshot isolation, kept for repeatable reads
+    reads: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl<'a> Transaction<'a> {
+    pub(crate) fn new(store: &'a KVStore, txn_id: u64, isolation: Isolation, snapshot_seq: u64) -> Self {
+        Self {
+            store,
+            txn_id,
+            isolation,
+            snapshot_seq,
+            writes: Vec::new(),
+            reads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Isolation level the transaction runs at
+    pub fn isolation(&self) -> Isolation {
+        self.isolation
+    }
+
+    /// Transaction ID, recorded on the WAL Begin/Commit markers
+    pub fn id(&self) -> u64 {
+        self.txn_id
+    }
+
+    /// Buffer a set
+    pub fn set(&mut self, key: &str, value: &str) {
+        self.writes.push(WalEntry::set(key, value));
+    }
+
+    /// Buffer a delete
+    pub fn delete(&mut self, key: &str) {
+        self.writes.push(WalEntry::Delete { key: key.to_string() });
+    }
+
+    /// Read a key, seeing this transaction's own writes first
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        if let Some(write) = self.pending_write(key) {
+            return Ok(write);
+        }
+
+        match self.isolation {
+            Isolation::ReadCommitted => self.store.get(key).await,
+            Isolation::Snapshot => self.snapshot_get(key).await,
+        }
+    }
+
+    /// Read as of the snapshot, failing if the key changed since
+    async fn snapshot_get(&self, key: &str) -> Result<Option<String>> {
+        if let Some(value) = self.reads.lock().get(key) {
+            return Ok(value.clone());
+        }
+
+        // The version must be unchanged across the read, so the value
+        // can't be one committed concurrently with it
+        let before = self.store.key_version(key);
+        let value = self.store.get(key).await?;
+        let after = self.store.key_version(key);
+
+        if !is_visible(before, self.snapshot_seq) || before != after {
+            return Err(IronCladError::SnapshotTooOld { key: key.to_string() }.into());
+        }
+
+        self.reads.lock().insert(key.to_string(), value.clone());
+        Ok(value)
+    }
+
+    /// Number of buffered writes
+    pub fn len(&self) -> usize {
+        self.writes.len()
+    }
+
+    /// Has nothing been written yet?
+    pub fn is_empty(&self) -> bool {
+        self.writes.is_empty()
+    }
+
+    /// Log the batch and apply it; returns the LSN of the Commit marker
+    pub async fn commit(self) -> Result<u64> {
+        let (store, txn_id, writes, snapshot_seq) = self.into_parts();
+        store.commit_batch(txn_id, writes, snapshot_seq).await
+    }
+
+    /// Store, ID, writes and the snapshot to validate against (snapshot isolation only)
+    pub(crate) fn into_parts(self) -> (&'a KVStore, u64, Vec<WalEntry>, Option<u64>) {
+        let snapshot_seq = match self.isolation {
+            Isolation::ReadCommitted => None,
+            Isolation::Snapshot => Some(self.snapshot_seq),
+        };
+        (self.store, self.txn_id, self.writes, snapshot_seq)
+    }
+
+    /// Discard all buffered writes
+    pub fn rollback(self) {}
+
+    /// Latest buffered write for `key`: Some(Some(v)) for a set, Some(None) for a delete
+    fn pending_write(&self, key: &str) -> Option<Option<String>> {
+        self.writes.iter().rev().find_map(|entry| match entry {
+            WalEntry::Set { key: k, value, .. } if k == key => Some(Some(value.clone())),
+            WalEntry::Delete { key: k } if k == key => Some(None),
+            _ => None,
+        })
+    }
+}
+
+/// Was a key last written at `version` visible to a snapshot taken at `snapshot_seq`?
+fn is_visible(version: u64, snapshot_seq: u64) -> bool {
+    version <= snapshot_seq
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::memory_store;
+
+    const LEVELS: [Isolation; 2] = [Isolation::ReadCommitted, Isolation::Snapshot];
+
+    fn is_error(result: Result<impl std::fmt::Debug>, expected: fn(&IronCladError) -> bool) -> bool {
+        result.unwrap_err().downcast_ref::<IronCladError>().is_some_and(expected)
+    }
+
+    #[test]
+    fn test_default_isolation() {
+        assert_eq!(Isolation::default(), Isolation::ReadCommitted);
+    }
+
+    #[test]
+    fn test_snapshot_visibility() {
+        // Written before or at the snapshot: visible
+        assert!(is_visible(0, 5));
+        assert!(is_visible(5, 5));
+        // Written after the snapshot: not visible (no old versions to fall back on)
+        assert!(!is_visible(6, 5));
+    }
+
+    #[tokio::test]
+    async fn test_no_dirty_reads() {
+        for level in LEVELS {
+            let store = memory_store().await;
+            store.set("x", "1").await.unwrap();
+            let mut writer = store.begin_with(level);
+            writer.set("x", "2");
+            let reader = store.begin_with(level);
+            assert_eq!(reader.get("x").await.unwrap().as_deref(), Some("1"), "{:?}", level);
+            assert_eq!(store.get("x").await.unwrap().as_deref(), Some("1"));
+            writer.rollback();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_non_repeatable_read() {
+        // Read committed: a second read sees the concurrent commit
+        let store = memory_store().await;
+        store.set("x", "1").await.unwrap();
+        let txn = store.begin();
+        assert_eq!(txn.get("x").await.unwrap().as_deref(), Some("1"));
+        store.set("x", "2").await.unwrap();
+        assert_eq!(txn.get("x").await.unwrap().as_deref(), Some("2"));
+
+        // Snapshot: the second read repeats the first, and a key first read
+        // after it changed fails rather than show the newer value
+        let store = memory_store().await;
+        store.set("x", "1").await.unwrap();
+        store.set("y", "1").await.unwrap();
+        let txn = store.begin_with(Isolation::Snapshot);
+        assert_eq!(txn.get("x").await.unwrap().as_deref(), Some("1"));
+        store.set("x", "2").await.unwrap();
+        store.set("y", "2").await.unwrap();
+        assert_eq!(txn.get("x").await.unwrap().as_deref(), Some("1"));
+        assert!(is_error(txn.get("y").await, |e| matches!(e, IronCladError::SnapshotTooOld { .. })));
+    }
+
+    /// Two transactions each read `counter` and write it back plus one
+    async fn race_increments(store: &KVStore, level: Isolation) -> (Result<u64>, Result<u64>) {
+        let (mut first, mut second) = (store.begin_with(level), store.begin_with(level));
+        for txn in [&mut first, &mut second] {
+            let counter: u64 = txn.get("counter").await.unwrap().unwrap().parse().unwrap();
+            txn.set("counter", &(counter + 1).to_string());
+        }
+        (first.commit().await, second.commit().await)
+    }
+
+    #[tokio::test]
+    async fn test_lost_update() {
+        // Read committed: both commit, and one increment is lost
+        let store = memory_store().await;
+        store.set("counter", "0").await.unwrap();
+        let (first, second) = race_increments(&store, Isolation::ReadCommitted).await;
+        assert!(first.is_ok() && second.is_ok());
+        assert_eq!(store.get("counter").await.unwrap().as_deref(), Some("1"));
+
+        // Snapshot: the second committer conflicts and its retry counts
+        let store = memory_store().await;
+        store.set("counter", "0").await.unwrap();
+        let (first, second) = race_increments(&store, Isolation::Snapshot).await;
+        assert!(first.is_ok());
+        assert!(is_error(second, |e| matches!(e, IronCladError::WriteConflict { .. })));
+        let mut retry = store.begin_with(Isolation::Snapshot);
+        let counter: u64 = retry.get("counter").await.unwrap().unwrap().parse().unwrap();
+        retry.set("counter", &(counter + 1).to_string());
+        retry.commit().await.unwrap();
+        assert_eq!(store.get("counter").await.unwrap().as_deref(), Some("2"));
+    }
+
+    #[tokio::test]
+    async fn test_write_skew_is_possible_at_every_level() {
+        // At least one of a and b must stay on call; each transaction
+        // checks that, then takes a different one off
+        for level in LEVELS {
+            let store = memory_store().await;
+            store.set("oncall:a", "true").await.unwrap();
+            store.set("oncall:b", "true").await.unwrap();
+            let (mut first, mut second) = (store.begin_with(level), store.begin_with(level));
+            for (txn, leaving) in [(&mut first, "oncall:a"), (&mut second, "oncall:b")] {
+                let a = txn.get("oncall:a").await.unwrap();
+                let b = txn.get("oncall:b").await.unwrap();
+                assert_eq!((a.as_deref(), b.as_deref()), (Some("true"), Some("true")));
+                txn.set(leaving, "false");
+            }
+            first.commit().await.unwrap();
+            second.commit().await.unwrap();
+            assert_eq!(store.get("oncall:a").await.unwrap().as_deref(), Some("false"), "{:?}", level);
+            assert_eq!(store.get("oncall:b").await.unwrap().as_deref(), Some("false"), "{:?}", level);
+        }
+    }
+}
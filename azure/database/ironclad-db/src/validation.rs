@@ -0,0 +1,263 @@
+//! Validation: Rules Values Must Meet Before They Are Written
+//!
+//! A bad value that reaches the WAL is there for good: replayed at every
+//! restart, copied into backups and change feeds. Validators check values
+//! per bucket (key prefix) before anything is logged, and refuse a write
+//! that breaks a rule with `IronCladError::ValidationFailed`:
+//!
+//! ```ignore
+//! let options = StoreOptions {
+//!     validators: vec![
+//!         BucketValidator::new("orders/")
+//!             .max_bytes(16 * 1024)
+//!             .json_schema(json!({"type": "object", "required": ["id", "total"],
+//!                                 "properties": {"total": {"type": "number", "minimum": 0}}})),
+//!         BucketValidator::new("").custom("no-nul", |value| {
+//!             if value.contains('\0') { Err("contains NUL".into()) } else { Ok(()) }
+//!         }),
+//!     ],
+//!     ..Default::default()
+//! };
+//! ```
+//!
+//! Rules check the value as the caller wrote it, before middleware (see
+//! middleware.rs), and the new value of an `append` or `patch_json`. Every
+//! validator whose prefix matches applies, rules in the order added. Values
+//! are UTF-8 by type, so that needs no rule. Reserved (`__`) keys are the
+//! store's own and aren't validated. Values already in the store when a
+//! rule is added aren't checked.
+//!
+//! `json_schema` supports a subset of JSON Schema: `type`, `enum`,
+//! `required`, `properties`, `items`, `minLength`, `maxLength`, `minimum`
+//! and `maximum`. Other keywords are ignored.
+
+use anyhow::Result;
+use serde_json::Value;
+use std::sync::Arc;
+use tracing::warn;
+
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::trash::is_reserved;
+
+/// Checks a value; Err holds why it was refused
+type Check = Arc<dyn Fn(&str) -> std::result::Result<(), String> + Send + Sync>;
+
+/// One rule of a validator
+#[derive(Clone)]
+enum Rule {
+    MaxBytes(usize),
+    Json,
+    JsonSchema(Arc<Value>),
+    Custom { name: String, check: Check },
+}
+
+impl Rule {
+    fn name(&self) -> &str {
+        match self {
+            Rule::MaxBytes(_) => "max_bytes",
+            Rule::Json => "json",
+            Rule::JsonSchema(_) => "json_schema",
+            Rule::Custom { name, .. } => name,
+        }
+    }
+
+    fn check(&self, value: &str) -> std::result::Result<(), String> {
+        match self {
+            Rule::MaxBytes(max) if value.len() > *max => Err(format!("{} bytes, over the {} allowed", value.len(), max)),
+            Rule::MaxBytes(_) => Ok(()),
+            Rule::Json => serde_json::from_str::<Value>(value).map(|_| ()).map_err(|e| format!("not JSON: {}", e)),
+            Rule::JsonSchema(schema) => {
+                let value = serde_json::from_str::<Value>(value).map_err(|e| format!("not JSON: {}", e))?;
+                check_schema(schema, &value, "$")
+            }
+            Rule::Custom { check, .. } => check(value),
+        }
+    }
+}
+
+impl PartialEq for Rule {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Rule::MaxBytes(a), Rule::MaxBytes(b)) => a == b,
+            (Rule::Json, Rule::Json) => true,
+            (Rule::JsonSchema(a), Rule::JsonSchema(b)) => a == b,
+            (Rule::Custom { name: a, check: x }, Rule::Custom { name: b, check: y }) => a == b && Arc::ptr_eq(x, y),
+            _ => false,
+        }
+    }
+}
+
+/// Rules for the values of keys starting with `prefix`
+#[derive(Clone, PartialEq)]
+pub struct BucketValidator {
+    pub prefix: String,
+    rules: Vec<Rule>,
+}
+
+impl BucketValidator {
+    pub fn new(prefix: &str) -> Self {
+        Self { prefix: prefix.to_string(), rules: Vec::new() }
+    }
+
+    /// Values may be at most `max` bytes
+    pub fn max_bytes(mut self, max: usize) -> Self {
+        self.rules.push(Rule::MaxBytes(max));
+        self
+    }
+
+    /// Values must be JSON
+    pub fn json(mut self) -> Self {
+        self.rules.push(Rule::Json);
+        self
+    }
+
+    /// Values must be JSON matching `schema` (see the module docs for the
+    /// keywords checked)
+    pub fn json_schema(mut self, schema: Value) -> Self {
+        self.rules.push(Rule::JsonSchema(Arc::new(schema)));
+        self
+    }
+
+    /// Values must pass `check`, which returns why it refuses one
+    pub fn custom<F>(mut self, name: &str, check: F) -> Self
+    where
+        F: Fn(&str) -> std::result::Result<(), String> + Send + Sync + 'static,
+    {
+        self.rules.push(Rule::Custom { name: name.to_string(), check: Arc::new(check) });
+        self
+    }
+}
+
+impl std::fmt::Debug for BucketValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BucketValidator")
+            .field("prefix", &self.prefix)
+            .field("rules", &self.rules.iter().map(Rule::name).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+/// Schemas never hold NaN, so equality is reflexive
+impl Eq for BucketValidator {}
+
+/// Check `value` (at `path`) against the supported subset of JSON Schema
+fn check_schema(schema: &Value, value: &Value, path: &str) -> std::result::Result<(), String> {
+    if let Some(expected) = schema.get("type").and_then(Value::as_str) {
+        let matches = match expected {
+            "object" => value.is_object(),
+            "array" => value.is_array(),
+            "string" => value.is_string(),
+            "number" => value.is_number(),
+            "integer" => value.is_i64() || value.is_u64(),
+            "boolean" => value.is_boolean(),
+            "null" => value.is_null(),
+            other => return Err(format!("{}: unknown type {:?} in the schema", path, other)),
+        };
+        if !matches {
+            return Err(format!("{}: expected {}", path, expected));
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            return Err(format!("{}: not one of the allowed values", path));
+        }
+    }
+    if let Some(object) = value.as_object() {
+        for field in schema.get("required").and_then(Value::as_array).into_iter().flatten().filter_map(Value::as_str) {
+            if !object.contains_key(field) {
+                return Err(format!("{}: missing {:?}", path, field));
+            }
+        }
+        for (name, field_schema) in schema.get("properties").and_then(Value::as_object).into_iter().flatten() {
+            if let Some(field) = object.get(name) {
+                check_schema(field_schema, field, &format!("{}.{}", path, name))?;
+            }
+        }
+    }
+    if let (Some(items), Some(array)) = (schema.get("items"), value.as_array()) {
+        for (i, item) in array.iter().enumerate() {
+            check_schema(items, item, &format!("{}[{}]", path, i))?;
+        }
+    }
+    if let Some(text) = value.as_str() {
+        let len = text.chars().count() as u64;
+        if schema.get("minLength").and_then(Value::as_u64).is_some_and(|min| len < min) {
+            return Err(format!("{}: shorter than minLength", path));
+        }
+        if schema.get("maxLength").and_then(Value::as_u64).is_some_and(|max| len > max) {
+            return Err(format!("{}: longer than maxLength", path));
+        }
+    }
+    if let Some(number) = value.as_f64() {
+        if schema.get("minimum").and_then(Value::as_f64).is_some_and(|min| number < min) {
+            return Err(format!("{}: below the minimum", path));
+        }
+        if schema.get("maximum").and_then(Value::as_f64).is_some_and(|max| number > max) {
+            return Err(format!("{}: above the maximum", path));
+        }
+    }
+    Ok(())
+}
+
+impl KVStore {
+    /// Fail with `ValidationFailed` if `value` breaks a rule of a validator
+    /// covering `key`
+    pub(crate) fn validate(&self, key: &str, value: &str) -> Result<()> {
+        if is_reserved(key) {
+            return Ok(());
+        }
+        for validator in self.options().validators.iter().filter(|validator| key.starts_with(&validator.prefix)) {
+            for rule in &validator.rules {
+                if let Err(reason) = rule.check(value) {
+                    warn!("VALIDATION: write to {} refused by {}: {}", key, rule.name(), reason);
+                    return Err(IronCladError::ValidationFailed {
+                        key: key.to_string(),
+                        rule: rule.name().to_string(),
+                        reason,
+                    }
+                    .into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rules_refuse_bad_values() {
+        let validator = BucketValidator::new("orders/").max_bytes(40).json().custom("no-test", |value| {
+            if value.contains("test") { Err("test data".into()) } else { Ok(()) }
+        });
+        let failure = |value: &str| validator.rules.iter().find_map(|rule| rule.check(value).err().map(|_| rule.name().to_string()));
+        assert_eq!(failure(r#"{"id": 1}"#), None);
+        assert_eq!(failure(r#"{"id": 1, "note": "much too long for the limit"}"#).as_deref(), Some("max_bytes"));
+        assert_eq!(failure("{oops").as_deref(), Some("json"));
+        assert_eq!(failure(r#"{"test": true}"#).as_deref(), Some("no-test"));
+    }
+
+    #[test]
+    fn test_json_schema_subset() {
+        let schema = json!({
+            "type": "object",
+            "required": ["id", "total"],
+            "properties": {
+                "total": {"type": "number", "minimum": 0},
+                "status": {"enum": ["open", "paid"]},
+                "lines": {"type": "array", "items": {"type": "string", "maxLength": 3}}
+            }
+        });
+        let check = |value: Value| check_schema(&schema, &value, "$");
+        assert!(check(json!({"id": 1, "total": 9.5, "status": "paid", "lines": ["a", "bc"]})).is_ok());
+        assert_eq!(check(json!([1])).unwrap_err(), "$: expected object");
+        assert_eq!(check(json!({"id": 1})).unwrap_err(), "$: missing \"total\"");
+        assert_eq!(check(json!({"id": 1, "total": -1})).unwrap_err(), "$.total: below the minimum");
+        assert_eq!(check(json!({"id": 1, "total": 1, "status": "lost"})).unwrap_err(), "$.status: not one of the allowed values");
+        assert_eq!(check(json!({"id": 1, "total": 1, "lines": ["abcd"]})).unwrap_err(), "$.lines[0]: longer than maxLength");
+    }
+}
@@ -0,0 +1,260 @@
+//! Verify: Store Integrity Checks
+//!
+//! `KVStore::open_with_verification` opens a store and refuses it with
+//! `IronCladError::VerificationFailed` unless it passes these checks, as a
+//! gate before putting a node into rotation. `KVStore::verify` runs them on
+//! an open store.
+//!
+//! `Quick` reads page 0 and the end of the WAL:
+//!
+//! - superblock: page 0 decodes (magic, checksum), the store is fully
+//!   created, its format is known, and no other writer has taken over.
+//! - index: every page-backed key has a page of its own, allocated and
+//!   clear of the superblock, the inline value pages and the index
+//!   snapshot. This checks the index as loaded and replayed, not the
+//!   snapshot the last checkpoint stored.
+//! - WAL tail: the log ends on a complete, parseable record.
+//!
+//! `Full` adds a read of everything: every key's page decodes and holds
+//! that key, the inline value and index snapshot pages decode, and the
+//! whole WAL parses.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::ops::Range;
+use tracing::{info, warn};
+
+use crate::buffer_pool::CachePriority;
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::options::StoreOptions;
+use crate::page;
+use crate::superblock::{StoreState, Superblock, FIRST_DATA_PAGE, FORMAT_VERSION, SUPERBLOCK_PAGE};
+use crate::wal::decode_log;
+use crate::wal_record;
+
+/// Bytes read from the end of the WAL by a quick check
+const WAL_TAIL_LEN: u64 = 64 * 1024;
+
+/// How thoroughly to verify a store
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VerifyMode {
+    /// Superblock, index layout and WAL tail
+    #[default]
+    Quick,
+    /// Quick, plus every page and the whole WAL
+    Full,
+}
+
+/// What a verification checked and found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerificationReport {
+    pub mode: VerifyMode,
+    pub keys_checked: usize,
+    /// Pages read (Full only)
+    pub pages_checked: usize,
+    /// WAL bytes read
+    pub wal_bytes_checked: u64,
+    pub problems: Vec<String>,
+}
+
+impl VerificationReport {
+    pub fn is_ok(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl KVStore {
+    /// Open a store, failing with `IronCladError::VerificationFailed`
+    /// unless it passes a `mode` verification
+    pub async fn open_with_verification(connection_string: &str, options: StoreOptions, mode: VerifyMode) -> Result<Self> {
+        let store = Self::open(connection_string, options).await?;
+        let report = store.verify(mode).await?;
+        if !report.is_ok() {
+            return Err(IronCladError::VerificationFailed { problems: report.problems }.into());
+        }
+        info!(
+            "VERIFY: {:?} check passed ({} keys, {} pages, {} WAL bytes)",
+            mode, report.keys_checked, report.pages_checked, report.wal_bytes_checked
+        );
+        Ok(store)
+    }
+
+    /// Check the store's integrity; problems are reported, not raised
+    pub async fn verify(&self, mode: VerifyMode) -> Result<VerificationReport> {
+        let mut report = VerificationReport {
+            mode,
+            keys_checked: 0,
+            pages_checked: 0,
+            wal_bytes_checked: 0,
+            problems: Vec::new(),
+        };
+
+        let superblock = self.verify_superblock(&mut report.problems).await?;
+        let inline_pages = superblock
+            .as_ref()
+            .map_or(0..0, |superblock| superblock.inline_start..superblock.inline_start + superblock.inline_count);
+        let snapshot_pages = superblock
+            .as_ref()
+            .and_then(|superblock| superblock.index_snapshot)
+            .map_or(0..0, |snapshot| snapshot.start..snapshot.start + snapshot.count);
+        let packed_pages = [("inline value", inline_pages), ("index snapshot", snapshot_pages)];
+
+        let pages = self.index().pages();
+        report.keys_checked = pages.len();
+        report.problems.extend(check_page_layout(&pages, FIRST_DATA_PAGE..self.next_page_id(), &packed_pages));
+
+        let wal = match mode {
+            VerifyMode::Quick => self.wal().read_tail(WAL_TAIL_LEN).await?,
+            VerifyMode::Full => self.wal().read_raw().await?.1,
+        };
+        report.wal_bytes_checked = wal.len() as u64;
+        report.problems.extend(check_wal_tail(&wal));
+
+        if mode == VerifyMode::Full {
+            if let Err(e) = decode_log(&wal) {
+                report.problems.push(format!("WAL doesn't parse: {}", e));
+            }
+            for (key, page_id) in &pages {
+                report.pages_checked += 1;
+                if let Some(problem) = self.verify_key_page(key.as_deref(), *page_id).await? {
+                    report.problems.push(problem);
+                }
+            }
+            for (kind, range) in packed_pages {
+                for page_id in range {
+                    report.pages_checked += 1;
+                    if let Err(e) = page::decode_packed_page(&self.disk().read_page(page_id).await?) {
+                        report.problems.push(format!("{} page {} doesn't decode: {}", kind, page_id, e));
+                    }
+                }
+            }
+        }
+
+        for problem in &report.problems {
+            warn!("VERIFY: {}", problem);
+        }
+        Ok(report)
+    }
+
+    /// Check page 0 as stored (not the in-memory copy)
+    async fn verify_superblock(&self, problems: &mut Vec<String>) -> Result<Option<Superblock>> {
+        let superblock = match Superblock::decode(&self.disk().read_page(SUPERBLOCK_PAGE).await?) {
+            Ok(Some(superblock)) => superblock,
+            Ok(None) => {
+                problems.push("Superblock is missing".to_string());
+                return Ok(None);
+            }
+            Err(e) => {
+                problems.push(format!("Superblock doesn't decode: {}", e));
+                return Ok(None);
+            }
+        };
+
+        if superblock.state != StoreState::Ready {
+            problems.push(format!("Store creation never finished ({:?})", superblock.state));
+        }
+        if superblock.format_version > FORMAT_VERSION {
+            problems.push(format!("Unknown format version {}", superblock.format_version));
+        }
+        if superblock.epoch != self.fencing_token() {
+            problems.push(format!(
+                "Another writer took over (epoch {}, this instance {})",
+                superblock.epoch,
+                self.fencing_token()
+            ));
+        }
+        Ok(Some(superblock))
+    }
+
+    /// Check that `key`'s page decodes and holds it
+    async fn verify_key_page(&self, key: Option<&str>, page_id: u64) -> Result<Option<String>> {
+        let Some(data) = self.load_page(page_id, CachePriority::Low).await? else {
+            return Ok(Some(format!("Page {} can't be read", page_id)));
+        };
+        Ok(match (page::decode_kv_page(&data), key) {
+            (Err(e), _) => Some(format!("Page {} doesn't decode: {}", page_id, e)),
+            (Ok((page_key, _)), Some(key)) if page_key != key => {
+                Some(format!("Page {} holds {:?}, indexed as {:?}", page_id, page_key, key))
+            }
+            _ => None,
+        })
+    }
+}
+
+/// Problems with where the index puts keys: pages shared, unallocated, or
+/// reserved for the superblock or one of the `packed` runs (kind, pages)
+fn check_page_layout(pages: &[(Option<String>, u64)], allocated: Range<u64>, packed: &[(&str, Range<u64>)]) -> Vec<String> {
+    let mut problems = Vec::new();
+    let mut owners: HashMap<u64, &str> = HashMap::new();
+
+    for (key, page_id) in pages {
+        let key = key.as_deref().unwrap_or("<hashed key>");
+        if !allocated.contains(page_id) {
+            problems.push(format!("{:?} is on unallocated page {}", key, page_id));
+        } else if let Some((kind, _)) = packed.iter().find(|(_, range)| range.contains(page_id)) {
+            problems.push(format!("{:?} is on {} page {}", key, kind, page_id));
+        }
+        if let Some(other) = owners.insert(*page_id, key) {
+            problems.push(format!("{:?} and {:?} share page {}", other, key, page_id));
+        }
+    }
+    problems
+}
+
+/// Problem with the end of the log, if it doesn't end on a complete record
+fn check_wal_tail(tail: &[u8]) -> Option<String> {
+    let Some(body) = tail.strip_suffix(b"\n") else {
+        return (!tail.is_empty()).then(|| "WAL ends mid-record".to_string());
+    };
+    let last_record = body.rsplit(|byte| *byte == b'\n').next().unwrap_or(body);
+    serde_json::from_slice(last_record)
+        .map_err(anyhow::Error::from)
+        .and_then(wal_record::decode)
+        .err()
+        .map(|e| format!("Last WAL record doesn't parse: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wal::WalEntry;
+    use crate::wal_record::RecordFormat;
+
+    #[test]
+    fn test_page_layout_problems() {
+        let pages = vec![
+            (Some("a".to_string()), 1),
+            (Some("b".to_string()), 1),
+            (Some("c".to_string()), 9),
+            (None, 5),
+        ];
+        let packed = [("inline value", 5..6), ("index snapshot", 6..7)];
+        let problems = check_page_layout(&pages, 1..8, &packed);
+        assert_eq!(problems.len(), 3, "{:?}", problems);
+        assert!(check_page_layout(&pages[..1], 1..8, &packed).is_empty());
+        assert_eq!(check_page_layout(&[(None, 6)], 1..8, &packed), vec!["\"<hashed key>\" is on index snapshot page 6".to_string()]);
+    }
+
+    #[test]
+    fn test_wal_tail() {
+        let mut log = Vec::new();
+        for entry in [WalEntry::set("a", "1"), WalEntry::Delete { key: "a".to_string() }] {
+            log.extend(serde_json::to_vec(&entry).unwrap());
+            log.push(b'\n');
+        }
+        assert_eq!(check_wal_tail(&log), None);
+        assert_eq!(check_wal_tail(b""), None);
+        // A tail read can start mid-record; only the last one matters
+        assert_eq!(check_wal_tail(&log[5..]), None);
+
+        assert!(check_wal_tail(&log[..log.len() - 1]).is_some());
+        let mut enveloped = wal_record::encode(&WalEntry::set("a", "1"), RecordFormat::Envelope).unwrap();
+        enveloped.push(b'\n');
+        assert_eq!(check_wal_tail(&enveloped), None);
+        let mut garbled = log.clone();
+        let len = garbled.len();
+        garbled[len - 4] = b'#';
+        assert!(check_wal_tail(&garbled).is_some());
+    }
+}
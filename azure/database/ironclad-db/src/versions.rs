@@ -0,0 +1,252 @@
+//! Versions: Prior Values of a Key, Kept for Audit and Undo
+//!
+//! With `StoreOptions::version_retention` set, overwriting or deleting a
+//! key keeps the value it replaced: one WAL batch writes the new value and
+//! records the old one under `__versions/<key>/<lsn>`, where `lsn` is the
+//! LSN that wrote it. `history` lists them, newest first:
+//!
+//! ```ignore
+//! for version in store.history("user:1", 5).await? {
+//!     println!("LSN {} at {}: {}", version.lsn, version.at_ms, version.value);
+//! }
+//! ```
+//!
+//! Each write prunes the key's versions to the policy: at most
+//! `max_versions`, none older than `max_age`. Versions of a deleted key
+//! stay until they age out and `enforce_retention` reclaims them (see
+//! retention.rs), so it can be brought back.
+//!
+//! As with the trash, only `set` and `delete` (and what is built on them)
+//! keep versions; patches, appends, transactions and writes to the reserved
+//! `__` namespace don't. Two writers overwriting a key at once can each
+//! record the value they both replaced, so the first one's value is never
+//! kept. A version too large for a page is skipped.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::{info, warn};
+
+use crate::kvstore::KVStore;
+use crate::metadata::now_ms;
+use crate::page;
+use crate::trash::is_reserved;
+use crate::wal::WalEntry;
+
+/// Key prefix for prior versions
+pub(crate) const VERSIONS_PREFIX: &str = "__versions/";
+
+/// Digits in a version key's LSN, so keys sort by LSN
+const LSN_DIGITS: usize = 20;
+
+/// Which prior versions of a key are kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionRetention {
+    /// Most versions kept per key
+    pub max_versions: usize,
+    /// Versions older than this are dropped (None = kept until pruned by count)
+    pub max_age: Option<Duration>,
+}
+
+impl Default for VersionRetention {
+    fn default() -> Self {
+        Self { max_versions: 10, max_age: None }
+    }
+}
+
+impl VersionRetention {
+    fn is_expired(&self, version: &KeyVersion, now_ms: u64) -> bool {
+        self.max_age.is_some_and(|max_age| now_ms.saturating_sub(version.at_ms) >= max_age.as_millis() as u64)
+    }
+}
+
+/// A value a key held before it was overwritten or deleted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyVersion {
+    pub value: String,
+    /// LSN of the write that set it (0 if unknown, e.g. loaded at startup)
+    pub lsn: u64,
+    /// When it was written (ms since the epoch; 0 if unknown)
+    pub at_ms: u64,
+}
+
+/// A version as stored under its key
+#[derive(Serialize, Deserialize)]
+struct StoredVersion {
+    value: String,
+    at_ms: u64,
+}
+
+fn versions_prefix(key: &str) -> String {
+    format!("{}{}/", VERSIONS_PREFIX, key)
+}
+
+fn version_key(key: &str, lsn: u64) -> String {
+    format!("{}{:0width$}", versions_prefix(key), lsn, width = LSN_DIGITS)
+}
+
+/// LSN of a version key under `prefix` (None for another key's versions,
+/// e.g. `a/b`'s under `a`'s prefix)
+fn version_lsn(prefix: &str, version_key: &str) -> Option<u64> {
+    let lsn = version_key.strip_prefix(prefix)?;
+    if lsn.len() != LSN_DIGITS {
+        return None;
+    }
+    lsn.parse().ok()
+}
+
+/// The key and LSN a version key is for
+fn version_owner(version_key: &str) -> Option<(&str, u64)> {
+    let (key, _) = version_key.strip_prefix(VERSIONS_PREFIX)?.rsplit_once('/')?;
+    Some((key, version_lsn(&versions_prefix(key), version_key)?))
+}
+
+/// LSNs to drop, oldest first, so at most `max_versions` of `lsns`
+/// (ascending) remain
+fn over_count(lsns: &[u64], max_versions: usize) -> &[u64] {
+    &lsns[..lsns.len().saturating_sub(max_versions)]
+}
+
+impl KVStore {
+    /// Up to `limit` prior versions of `key`, newest first (see versions.rs)
+    pub async fn history(&self, key: &str, limit: usize) -> Result<Vec<KeyVersion>> {
+        let retention = self.options().version_retention.unwrap_or_default();
+        let now = now_ms();
+        let mut versions = Vec::new();
+        for lsn in self.version_lsns(key).into_iter().rev() {
+            if versions.len() == limit {
+                break;
+            }
+            match self.version(key, lsn).await? {
+                Some(version) if !retention.is_expired(&version, now) => versions.push(version),
+                _ => {}
+            }
+        }
+        Ok(versions)
+    }
+
+    /// Does a write to `key` keep the value it replaces?
+    pub(crate) fn keeps_versions(&self, key: &str) -> bool {
+        self.options().version_retention.is_some() && !is_reserved(key)
+    }
+
+    /// Set `key`, keeping its current value as a version
+    pub(crate) async fn set_versioned(&self, key: &str, value: &str) -> Result<()> {
+        let current = self.get(key).await?;
+        if current.as_deref() == Some(value) {
+            return Ok(());
+        }
+        let mut writes = vec![WalEntry::set(key, value)];
+        if let Some(current) = current {
+            writes.extend(self.version_writes(key, current).await?);
+        }
+        self.commit_batch_locked(self.new_txn_id(), writes, None).await?;
+        Ok(())
+    }
+
+    /// Delete `key`, keeping its value as a version
+    pub(crate) async fn delete_versioned(&self, key: &str) -> Result<bool> {
+        let Some(current) = self.get(key).await? else {
+            return Ok(false);
+        };
+        let mut writes = vec![WalEntry::Delete { key: key.to_string() }];
+        writes.extend(self.version_writes(key, current).await?);
+        self.commit_batch_locked(self.new_txn_id(), writes, None).await?;
+
+        info!("DELETE: {} (kept as a version)", key);
+        Ok(true)
+    }
+
+    /// Writes that record `current` (the value of `key` about to be
+    /// replaced) as a version and prune the key's versions to the policy
+    pub(crate) async fn version_writes(&self, key: &str, current: String) -> Result<Vec<WalEntry>> {
+        let Some(retention) = self.options().version_retention else {
+            return Ok(Vec::new());
+        };
+        let (lsn, at_ms) = match self.metadata(key).await? {
+            Some(metadata) => (metadata.last_lsn, metadata.updated_ms),
+            None => (0, 0),
+        };
+
+        let mut writes = Vec::new();
+        let mut lsns = self.version_lsns(key);
+        if !lsns.contains(&lsn) {
+            let stored = serde_json::to_string(&StoredVersion { value: current, at_ms })?;
+            let version_key = version_key(key, lsn);
+            if page::encode_kv_page(&version_key, &stored).is_ok() {
+                writes.push(WalEntry::set(&version_key, &stored));
+                lsns.push(lsn);
+                lsns.sort_unstable();
+            } else {
+                warn!("VERSIONS: {} at LSN {} is too large to keep", key, lsn);
+            }
+        }
+
+        let dropped = over_count(&lsns, retention.max_versions);
+        let mut pruned: Vec<u64> = dropped.to_vec();
+        // Versions are in LSN order, so the expired ones come first
+        let now = now_ms();
+        for &kept in &lsns[dropped.len()..] {
+            match self.version(key, kept).await? {
+                Some(version) if retention.is_expired(&version, now) => pruned.push(kept),
+                _ => break,
+            }
+        }
+        writes.extend(pruned.into_iter().map(|lsn| WalEntry::Delete { key: version_key(key, lsn) }));
+        Ok(writes)
+    }
+
+    /// Does the policy no longer keep the version stored as `value` under
+    /// `version_key`? With no `version_retention`, none are kept
+    pub(crate) fn version_stale(&self, version_key: &str, value: &str, now_ms: u64) -> bool {
+        let Some((key, lsn)) = version_owner(version_key) else {
+            return false;
+        };
+        let Some(retention) = self.options().version_retention else {
+            return true;
+        };
+        let at_ms = serde_json::from_str::<StoredVersion>(value).map_or(0, |stored| stored.at_ms);
+        let version = KeyVersion { value: String::new(), lsn, at_ms };
+        over_count(&self.version_lsns(key), retention.max_versions).contains(&lsn) || retention.is_expired(&version, now_ms)
+    }
+
+    /// LSNs of `key`'s stored versions, ascending
+    fn version_lsns(&self, key: &str) -> Vec<u64> {
+        let prefix = versions_prefix(key);
+        let mut lsns: Vec<u64> =
+            self.keys_with_prefix(&prefix).iter().filter_map(|version_key| version_lsn(&prefix, version_key)).collect();
+        lsns.sort_unstable();
+        lsns
+    }
+
+    async fn version(&self, key: &str, lsn: u64) -> Result<Option<KeyVersion>> {
+        let Some(json) = self.get(&version_key(key, lsn)).await? else {
+            return Ok(None);
+        };
+        let stored: StoredVersion = serde_json::from_str(&json)?;
+        Ok(Some(KeyVersion { value: stored.value, lsn, at_ms: stored.at_ms }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_keys_sort_by_lsn_and_stay_per_key() {
+        assert!(version_key("a", 9) < version_key("a", 10));
+        let prefix = versions_prefix("a");
+        assert_eq!(version_lsn(&prefix, &version_key("a", 42)), Some(42));
+        assert_eq!(version_lsn(&prefix, &version_key("a/b", 42)), None);
+        assert_eq!(version_owner(&version_key("a/b", 42)), Some(("a/b", 42)));
+
+        assert_eq!(over_count(&[1, 2, 3, 4], 2), &[1, 2]);
+        assert!(over_count(&[1, 2], 5).is_empty());
+
+        let retention = VersionRetention { max_versions: 2, max_age: Some(Duration::from_secs(10)) };
+        let version = KeyVersion { value: "v".into(), lsn: 1, at_ms: 5_000 };
+        assert!(!retention.is_expired(&version, 14_000));
+        assert!(retention.is_expired(&version, 15_000));
+        assert!(!VersionRetention::default().is_expired(&version, u64::MAX));
+    }
+}
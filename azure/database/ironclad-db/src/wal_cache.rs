@@ -0,0 +1,174 @@
+//! WAL Tail Cache: Local Copy of the Log for Faster Restarts
+//!
+//! Recovery downloads the whole WAL blob. When a process restarts often
+//! (dev loops, rolling deploys) it fetches the same bytes every time. The
+//! tail cache keeps the sealed part of the log (everything up to the last
+//! complete record) in a local file, so the next read only fetches the
+//! suffix appended since.
+//!
+//! The log is an append blob, so a cached prefix stays valid for as long as
+//! the blob isn't recreated. `WAL::clear` recreates it, which changes the
+//! blob's creation time; since that has one-second resolution, readers also
+//! compare the start of the cached bytes with the blob before trusting it.
+//!
+//! Cache file layout:
+//!
+//! ```text
+//! ["ICWALTC1"][identity_len: u32 LE][identity][data_len: u64 LE][checksum: u64 LE][data]
+//! ```
+//!
+//! A file that fails any check is ignored (and overwritten by the next read).
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use tracing::{debug, warn};
+
+use crate::superblock::checksum;
+
+const MAGIC: &[u8; 8] = b"ICWALTC1";
+
+/// Bytes from the start of the log compared against the blob before use
+pub const PROBE_LEN: usize = 4096;
+
+/// A cached log prefix
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachedTail {
+    /// Identifies the blob instance the bytes came from
+    pub identity: String,
+    /// Log bytes, ending on a record boundary
+    pub data: Vec<u8>,
+}
+
+impl CachedTail {
+    /// Cache `data` up to its last complete record
+    pub fn sealed(identity: &str, data: &[u8]) -> Self {
+        let sealed_len = data.iter().rposition(|b| *b == b'\n').map(|i| i + 1).unwrap_or(0);
+        Self {
+            identity: identity.to_string(),
+            data: data[..sealed_len].to_vec(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut file = Vec::with_capacity(8 + 4 + self.identity.len() + 16 + self.data.len());
+        file.extend_from_slice(MAGIC);
+        file.extend_from_slice(&(self.identity.len() as u32).to_le_bytes());
+        file.extend_from_slice(self.identity.as_bytes());
+        file.extend_from_slice(&(self.data.len() as u64).to_le_bytes());
+        file.extend_from_slice(&checksum(&self.data).to_le_bytes());
+        file.extend_from_slice(&self.data);
+        file
+    }
+
+    /// Parse a cache file; None if it is truncated, foreign or corrupt
+    pub fn decode(file: &[u8]) -> Option<Self> {
+        let rest = file.strip_prefix(MAGIC.as_slice())?;
+        let (identity_len, rest) = split_u32(rest)?;
+        if rest.len() < identity_len as usize {
+            return None;
+        }
+        let (identity, rest) = rest.split_at(identity_len as usize);
+        let (data_len, rest) = split_u64(rest)?;
+        let (expected, data) = split_u64(rest)?;
+
+        if data.len() as u64 != data_len || checksum(data) != expected {
+            return None;
+        }
+        Some(Self {
+            identity: String::from_utf8(identity.to_vec()).ok()?,
+            data: data.to_vec(),
+        })
+    }
+}
+
+fn split_u32(data: &[u8]) -> Option<(u32, &[u8])> {
+    let (head, rest) = data.split_at_checked(4)?;
+    Some((u32::from_le_bytes(head.try_into().ok()?), rest))
+}
+
+fn split_u64(data: &[u8]) -> Option<(u64, &[u8])> {
+    let (head, rest) = data.split_at_checked(8)?;
+    Some((u64::from_le_bytes(head.try_into().ok()?), rest))
+}
+
+/// Cache file for one WAL blob
+#[derive(Debug, Clone)]
+pub struct WalTailCache {
+    path: PathBuf,
+}
+
+impl WalTailCache {
+    /// Cache for `container/blob` inside `dir`
+    pub fn new(dir: &Path, container: &str, blob: &str) -> Self {
+        let name = format!("{}-{}.waltail", container, blob).replace(['/', '\\'], "_");
+        Self { path: dir.join(name) }
+    }
+
+    /// The cached prefix of the blob instance `identity`, if any
+    pub async fn load(&self, identity: &str) -> Option<Vec<u8>> {
+        let file = tokio::fs::read(&self.path).await.ok()?;
+        match CachedTail::decode(&file) {
+            Some(cached) if cached.identity == identity => Some(cached.data),
+            Some(_) => {
+                debug!("WAL cache: {} is for an older log, ignoring", self.path.display());
+                None
+            }
+            None => {
+                warn!("WAL cache: {} is corrupt, ignoring", self.path.display());
+                None
+            }
+        }
+    }
+
+    /// Replace the cached prefix (written to a temp file, then renamed in)
+    pub async fn store(&self, identity: &str, data: &[u8]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            tokio::fs::create_dir_all(dir).await?;
+        }
+        let temp = self.path.with_extension("waltail.tmp");
+        tokio::fs::write(&temp, CachedTail::sealed(identity, data).encode()).await?;
+        tokio::fs::rename(&temp, &self.path).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_file_round_trip() {
+        let cached = CachedTail::sealed("created-at", b"{\"a\":1}\n{\"b\":2}\n");
+        assert_eq!(CachedTail::decode(&cached.encode()), Some(cached));
+    }
+
+    #[test]
+    fn test_only_complete_records_are_cached() {
+        let cached = CachedTail::sealed("id", b"{\"a\":1}\n{\"b\":");
+        assert_eq!(cached.data, b"{\"a\":1}\n");
+        assert!(CachedTail::sealed("id", b"partial").data.is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_cache_file_is_rejected() {
+        let encoded = CachedTail::sealed("id", b"record\n").encode();
+
+        let mut flipped = encoded.clone();
+        *flipped.last_mut().unwrap() ^= 0xff;
+        assert_eq!(CachedTail::decode(&flipped), None);
+        assert_eq!(CachedTail::decode(&encoded[..encoded.len() - 1]), None);
+        assert_eq!(CachedTail::decode(b"ICWALTC1\xff\xff\xff\xff"), None);
+    }
+
+    #[tokio::test]
+    async fn test_load_checks_identity() {
+        let dir = std::env::temp_dir().join(format!("ironclad-waltail-{}", rand::random::<u64>()));
+        let cache = WalTailCache::new(&dir, "container", "db-wal");
+
+        cache.store("first", b"one\ntwo\n").await.unwrap();
+        assert_eq!(cache.load("first").await, Some(b"one\ntwo\n".to_vec()));
+        assert_eq!(cache.load("second").await, None);
+
+        tokio::fs::remove_dir_all(&dir).await.unwrap();
+    }
+}
@@ -0,0 +1,152 @@
+//! WAL Record: Versioned Envelope for Log Records
+//!
+//! Each WAL record is one line of JSON. Records are written in an envelope
+//! naming their type, so a reader can recognize a record it doesn't know
+//! instead of failing to parse the log:
+//!
+//! ```text
+//! {"v":1,"type":"Set","data":{"key":"k","value":"v","at_ms":1700000000000}}
+//! {"v":1,"type":"Annotation","required":false,"data":{...}}
+//! ```
+//!
+//! A reader that meets an unknown type (or a newer envelope version)
+//! checks `required` (true when absent):
+//!
+//! - not required: the record is skipped, with a warning. It still takes
+//!   its LSN, so numbering matches the writer's. New record types that
+//!   older readers can safely ignore (hints, annotations) are written this
+//!   way.
+//! - required: reading fails with `IronCladError::UnsupportedWalRecord`,
+//!   since replaying without it would lose writes. Upgrade the reader.
+//!
+//! Unknown fields inside a known record's `data` are ignored, so a new
+//! optional field needs no new type (give it `#[serde(default)]`).
+//!
+//! Logs written before the envelope hold bare records (`{"Set":{...}}`);
+//! those are still read. During a rolling upgrade from such a version,
+//! writers keep writing bare records (`RecordFormat::Bare`) until every
+//! reader understands envelopes.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::warn;
+
+use crate::error::IronCladError;
+use crate::wal::WalEntry;
+
+/// Envelope version this crate writes and understands
+pub const ENVELOPE_VERSION: u32 = 1;
+
+/// How WAL records are written (all formats are always read)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum RecordFormat {
+    /// Versioned envelope
+    #[default]
+    Envelope,
+    /// Bare records, for readers older than the envelope
+    Bare,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    v: u32,
+    #[serde(rename = "type")]
+    record_type: String,
+    #[serde(default = "required_by_default", skip_serializing_if = "is_required")]
+    required: bool,
+    data: Value,
+}
+
+fn required_by_default() -> bool {
+    true
+}
+
+fn is_required(required: &bool) -> bool {
+    *required
+}
+
+/// Encode `entry` as one record (without the newline)
+pub(crate) fn encode(entry: &WalEntry, format: RecordFormat) -> Result<Vec<u8>> {
+    if let WalEntry::Unknown { record_type } = entry {
+        anyhow::bail!("Can't write a WAL record of unknown type {}", record_type);
+    }
+    match format {
+        RecordFormat::Bare => Ok(serde_json::to_vec(entry)?),
+        RecordFormat::Envelope => {
+            // Externally tagged: {"<type>": <data>}
+            let Value::Object(tagged) = serde_json::to_value(entry)? else {
+                anyhow::bail!("WAL entry didn't serialize to an object");
+            };
+            let (record_type, data) = tagged.into_iter().next().ok_or_else(|| anyhow::anyhow!("empty WAL entry"))?;
+            Ok(serde_json::to_vec(&Envelope { v: ENVELOPE_VERSION, record_type, required: true, data })?)
+        }
+    }
+}
+
+/// Decode one record, enveloped or bare
+pub(crate) fn decode(record: Value) -> Result<WalEntry> {
+    let is_envelope = record.as_object().is_some_and(|object| object.contains_key("v") && object.contains_key("type"));
+    if !is_envelope {
+        return Ok(serde_json::from_value(record)?);
+    }
+
+    let envelope: Envelope = serde_json::from_value(record)?;
+    let known = envelope.v <= ENVELOPE_VERSION;
+    let tagged = Value::Object([(envelope.record_type.clone(), envelope.data)].into_iter().collect());
+    match serde_json::from_value::<WalEntry>(tagged) {
+        Ok(entry) if known => Ok(entry),
+        _ if !envelope.required => {
+            warn!("WAL: skipping record of unknown type {} (envelope v{})", envelope.record_type, envelope.v);
+            Ok(WalEntry::Unknown { record_type: envelope.record_type })
+        }
+        Err(_) if known && is_known_type(&envelope.record_type) => {
+            anyhow::bail!("Malformed WAL record of type {}", envelope.record_type)
+        }
+        _ => Err(IronCladError::UnsupportedWalRecord { record_type: envelope.record_type, version: envelope.v }.into()),
+    }
+}
+
+/// Record types this crate writes
+fn is_known_type(record_type: &str) -> bool {
+    matches!(record_type, "Set" | "Delete" | "Patch" | "Checkpoint" | "Begin" | "Commit")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(entry: &WalEntry, format: RecordFormat) -> WalEntry {
+        decode(serde_json::from_slice(&encode(entry, format).unwrap()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_both_formats_round_trip() {
+        let entry = WalEntry::set("k", "v");
+        assert_eq!(round_trip(&entry, RecordFormat::Envelope), entry);
+        assert_eq!(round_trip(&entry, RecordFormat::Bare), entry);
+
+        let encoded = String::from_utf8(encode(&WalEntry::Delete { key: "k".to_string() }, RecordFormat::Envelope).unwrap()).unwrap();
+        assert_eq!(encoded, r#"{"v":1,"type":"Delete","data":{"key":"k"}}"#);
+    }
+
+    #[test]
+    fn test_unknown_records_skip_unless_required() {
+        let optional = serde_json::json!({"v": 1, "type": "Hint", "required": false, "data": {}});
+        assert_eq!(decode(optional).unwrap(), WalEntry::Unknown { record_type: "Hint".to_string() });
+
+        let required = serde_json::json!({"v": 1, "type": "Rename", "data": {"from": "a", "to": "b"}});
+        let error = decode(required).unwrap_err();
+        assert!(matches!(error.downcast_ref(), Some(IronCladError::UnsupportedWalRecord { .. })));
+
+        // A newer envelope of a known type is only read if it can be skipped
+        let newer = serde_json::json!({"v": 2, "type": "Delete", "data": {"key": "k"}});
+        assert!(decode(newer).is_err());
+    }
+
+    #[test]
+    fn test_new_fields_are_ignored() {
+        let record = serde_json::json!({"v": 1, "type": "Delete", "data": {"key": "k", "reason": "expired"}});
+        assert_eq!(decode(record).unwrap(), WalEntry::Delete { key: "k".to_string() });
+    }
+}
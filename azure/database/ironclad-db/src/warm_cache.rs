@@ -0,0 +1,219 @@
+//! Warm Cache: Buffer Pool Snapshots for Fast Restarts on the Same Node
+//!
+//! A restarted process normally starts with a cold buffer pool and pays an
+//! Azure round trip for every page until it warms up again. With
+//! `StoreOptions::warm_cache_dir` set, `KVStore::save_warm_cache` (called at
+//! shutdown, after the last write) saves the pool's clean pages there, and
+//! the next open on the same VM loads them straight back into the pool:
+//!
+//! ```ignore
+//! store.checkpoint().await?; // dirty pages aren't saved: flush them first
+//! store.save_warm_cache()?;
+//! ```
+//!
+//! The snapshot is two files:
+//!
+//! ```text
+//! pages.bin:  [page: 4096 bytes] x N, page-aligned, so it can be mmap'ed
+//! table.json: {"epoch": E, "pages": [[page_id, checksum], ...]}
+//! ```
+//!
+//! Entry i of the page table is the page in slot i of pages.bin, least
+//! recently used first, so the restored pool evicts in the same order.
+//!
+//! Only clean pages are saved, so each one matches the data blob. That
+//! stays true only while nothing writes pages: the snapshot is deleted on
+//! the first page write after it was saved, and loaded only if the store
+//! is opened at the writer epoch it was saved at (see superblock.rs).
+//! Otherwise another instance may have written since, and the pool starts
+//! cold. A page failing its checksum is skipped. A snapshot is loaded
+//! once, then deleted.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tracing::{info, warn};
+
+use crate::buffer_pool::{BufferPool, CachePriority};
+use crate::kvstore::KVStore;
+use crate::page::PAGE_SIZE;
+use crate::superblock::checksum;
+
+const PAGES_FILE: &str = "pages.bin";
+const TABLE_FILE: &str = "table.json";
+
+/// Page table of a snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct SnapshotTable {
+    /// Writer epoch the pages are valid at
+    epoch: u64,
+    /// (page ID, checksum) of each slot in pages.bin
+    pages: Vec<(u64, u64)>,
+}
+
+/// Where a store keeps its buffer pool snapshot
+#[derive(Debug)]
+pub(crate) struct WarmCache {
+    dir: PathBuf,
+    /// A snapshot was saved and nothing has written a page since
+    saved: AtomicBool,
+}
+
+impl WarmCache {
+    pub fn new(dir: &Path) -> Self {
+        Self { dir: dir.to_path_buf(), saved: AtomicBool::new(false) }
+    }
+
+    /// Save `pool`'s clean pages as valid at `epoch`; returns how many
+    pub fn save(&self, pool: &BufferPool, epoch: u64) -> Result<usize> {
+        fs::create_dir_all(&self.dir)?;
+        let pages = pool.clean_pages();
+
+        // Pages first, then the table naming them: a crash leaves either
+        // the old snapshot or none
+        let _ = fs::remove_file(self.dir.join(TABLE_FILE));
+        let pages_tmp = self.dir.join(format!("{}.tmp", PAGES_FILE));
+        let mut file = BufWriter::new(File::create(&pages_tmp)?);
+        for (_, data) in &pages {
+            file.write_all(data)?;
+        }
+        file.into_inner()?.sync_all()?;
+        fs::rename(&pages_tmp, self.dir.join(PAGES_FILE))?;
+
+        let table = SnapshotTable { epoch, pages: pages.iter().map(|(id, data)| (*id, checksum(data))).collect() };
+        let table_tmp = self.dir.join(format!("{}.tmp", TABLE_FILE));
+        let mut file = File::create(&table_tmp)?;
+        file.write_all(&serde_json::to_vec(&table)?)?;
+        file.sync_all()?;
+        fs::rename(&table_tmp, self.dir.join(TABLE_FILE))?;
+
+        self.saved.store(true, Ordering::SeqCst);
+        info!("Warm cache: saved {} pages to {}", pages.len(), self.dir.display());
+        Ok(pages.len())
+    }
+
+    /// Load a snapshot saved at `epoch` into `pool`, then delete it;
+    /// returns the pages loaded
+    pub fn load(&self, pool: &BufferPool, epoch: u64) -> usize {
+        let loaded = match self.read_into(pool, epoch) {
+            Ok(loaded) => loaded,
+            Err(e) => {
+                warn!("Warm cache: not loaded from {}: {:#}", self.dir.display(), e);
+                0
+            }
+        };
+        self.discard();
+        loaded
+    }
+
+    fn read_into(&self, pool: &BufferPool, epoch: u64) -> Result<usize> {
+        let table = match fs::read(self.dir.join(TABLE_FILE)) {
+            Ok(json) => serde_json::from_slice::<SnapshotTable>(&json).context("unreadable page table")?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+        if table.epoch != epoch {
+            anyhow::bail!("saved at epoch {}, store is at {}: it may have been written since", table.epoch, epoch);
+        }
+
+        // Pages the pool couldn't hold would only be evicted again
+        let skip = table.pages.len().saturating_sub(pool.stats().total_frames);
+        let mut file = File::open(self.dir.join(PAGES_FILE))?;
+        let mut data = vec![0u8; PAGE_SIZE];
+        let mut loaded = 0;
+        for (slot, &(page_id, sum)) in table.pages.iter().enumerate() {
+            file.read_exact(&mut data)?;
+            if slot < skip {
+                continue;
+            }
+            if checksum(&data) != sum {
+                warn!("Warm cache: page {} failed its checksum, skipping it", page_id);
+                continue;
+            }
+            if pool.admit_page_with(page_id, data.clone(), CachePriority::High)? {
+                loaded += 1;
+            }
+        }
+        info!("Warm cache: loaded {} pages from {}", loaded, self.dir.display());
+        Ok(loaded)
+    }
+
+    /// A page is about to be written: the snapshot may no longer match
+    pub fn invalidate(&self) {
+        if self.saved.swap(false, Ordering::SeqCst) {
+            self.discard();
+        }
+    }
+
+    fn discard(&self) {
+        for file in [TABLE_FILE, PAGES_FILE] {
+            match fs::remove_file(self.dir.join(file)) {
+                Ok(()) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Warm cache: failed to delete {}: {}", file, e),
+            }
+        }
+    }
+}
+
+impl KVStore {
+    /// Save the buffer pool's clean pages for the next open on this node
+    /// (see warm_cache.rs); returns how many were saved
+    ///
+    /// Call at shutdown, after a checkpoint and the last write.
+    pub fn save_warm_cache(&self) -> Result<usize> {
+        let Some(warm_cache) = self.warm_cache() else {
+            anyhow::bail!("No warm cache directory configured (StoreOptions::warm_cache_dir)");
+        };
+        warm_cache.save(self.buffer_pool(), self.fencing_token())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("ironclad-warm-{}", rand::random::<u64>()))
+    }
+
+    #[test]
+    fn test_snapshot_restores_clean_pages_at_same_epoch() {
+        let dir = temp_dir();
+        let pool = BufferPool::with_admission(4, false);
+        pool.admit_page(1, vec![1u8; PAGE_SIZE]).unwrap();
+        pool.admit_page(2, vec![2u8; PAGE_SIZE]).unwrap();
+        pool.put_page(3, vec![3u8; PAGE_SIZE]).unwrap(); // dirty: not saved
+        pool.get_page(1);
+        assert_eq!(WarmCache::new(&dir).save(&pool, 7).unwrap(), 2);
+
+        // Least recently used first: a one-frame pool keeps page 1
+        let restored = BufferPool::with_admission(1, false);
+        assert_eq!(WarmCache::new(&dir).load(&restored, 7), 1);
+        assert_eq!(restored.get_page(1), Some(vec![1u8; PAGE_SIZE]));
+        assert!(restored.get_dirty_pages().is_empty());
+
+        // Loaded once
+        assert_eq!(WarmCache::new(&dir).load(&BufferPool::with_admission(4, false), 7), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_is_dropped_at_other_epoch_or_after_a_write() {
+        let dir = temp_dir();
+        let pool = BufferPool::with_admission(4, false);
+        pool.admit_page(1, vec![1u8; PAGE_SIZE]).unwrap();
+
+        WarmCache::new(&dir).save(&pool, 7).unwrap();
+        assert_eq!(WarmCache::new(&dir).load(&BufferPool::with_admission(4, false), 8), 0);
+
+        let warm_cache = WarmCache::new(&dir);
+        warm_cache.save(&pool, 7).unwrap();
+        warm_cache.invalidate();
+        assert_eq!(WarmCache::new(&dir).load(&BufferPool::with_admission(4, false), 7), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,407 @@
+//! Watch: Filtered, Buffered Subscriptions to the Change Feed
+//!
+//! `watch` follows the change feed (see changefeed.rs) from the current
+//! head of the WAL and hands the subscriber the changes it asked for:
+//!
+//! ```ignore
+//! let watch = store.watch(
+//!     WatchOptions::new()
+//!         .with_key_glob("orders/*")
+//!         .with_ops(&[ChangeOp::Set])
+//!         .with_value_matching("/status", |status| status == "shipped")
+//!         .with_buffer(1_000, OverflowPolicy::DropOldest),
+//! )?;
+//! while let Ok(event) = watch.recv().await {
+//!     notify_customer(&event).await;
+//! }
+//! ```
+//!
+//! Filters run in the store's poller, so changes nobody asked for are
+//! never buffered. An event must pass every filter set:
+//!
+//! - key glob: `*` matches any run of characters, `?` any one
+//! - operation types: set, delete, patch
+//! - value predicates: the value at a JSON Pointer in a set's new value.
+//!   Only sets carry a whole value, so other operations never match one
+//!
+//! Each watch buffers up to `capacity` events for its subscriber. When a
+//! slow subscriber lets it fill, the overflow policy decides:
+//!
+//! - `DropOldest`: the oldest buffered event makes room (counted in
+//!   `WatchStats::dropped`)
+//! - `Block`: the poller stops reading the feed until there is room. The
+//!   WAL keeps the changes, but only until the next checkpoint
+//! - `Error`: the watch ends; `recv` returns what was buffered, then
+//!   `IronCladError::WatchOverflow`
+//!
+//! Like the change feed, a watch only sees what the WAL still holds:
+//! changes a checkpoint clears before the poller reads them are missed and
+//! reported in `WatchStats::missed`. Dropping the `Watch` stops its poller.
+
+use anyhow::Result;
+use parking_lot::Mutex;
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+use tracing::{debug, warn};
+
+use crate::changefeed::{Change, ChangeEvent};
+use crate::error::IronCladError;
+use crate::kvstore::KVStore;
+use crate::runtime::TaskHandle;
+
+/// The kind of write an event is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeOp {
+    Set,
+    Delete,
+    Patch,
+}
+
+impl ChangeOp {
+    pub fn of(change: &Change) -> Self {
+        match change {
+            Change::Set { .. } => ChangeOp::Set,
+            Change::Delete => ChangeOp::Delete,
+            Change::Patch { .. } => ChangeOp::Patch,
+        }
+    }
+}
+
+/// What happens when a watch's buffer is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    #[default]
+    DropOldest,
+    Block,
+    Error,
+}
+
+/// Tests the value at a JSON Pointer
+type ValueCheck = Arc<dyn Fn(&Value) -> bool + Send + Sync>;
+
+/// Watch settings (see watch.rs)
+#[derive(Clone)]
+pub struct WatchOptions {
+    key_glob: Option<String>,
+    ops: Option<Vec<ChangeOp>>,
+    predicates: Vec<(String, ValueCheck)>,
+    /// Most events buffered for the subscriber (default: 1024)
+    pub capacity: usize,
+    pub overflow: OverflowPolicy,
+    /// How often the WAL is checked for new changes (default: 100ms)
+    pub poll_interval: Duration,
+}
+
+impl fmt::Debug for WatchOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WatchOptions")
+            .field("key_glob", &self.key_glob)
+            .field("ops", &self.ops)
+            .field("predicates", &self.predicates.iter().map(|(pointer, _)| pointer).collect::<Vec<_>>())
+            .field("capacity", &self.capacity)
+            .field("overflow", &self.overflow)
+            .field("poll_interval", &self.poll_interval)
+            .finish()
+    }
+}
+
+impl Default for WatchOptions {
+    fn default() -> Self {
+        Self {
+            key_glob: None,
+            ops: None,
+            predicates: Vec::new(),
+            capacity: 1024,
+            overflow: OverflowPolicy::DropOldest,
+            poll_interval: Duration::from_millis(100),
+        }
+    }
+}
+
+impl WatchOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only keys matching `glob` (`*` = any run, `?` = any one character)
+    pub fn with_key_glob(mut self, glob: &str) -> Self {
+        self.key_glob = Some(glob.to_string());
+        self
+    }
+
+    /// Only these kinds of writes
+    pub fn with_ops(mut self, ops: &[ChangeOp]) -> Self {
+        self.ops = Some(ops.to_vec());
+        self
+    }
+
+    /// Only sets whose new value is JSON with a value at `pointer` that
+    /// passes `check`
+    pub fn with_value_matching(mut self, pointer: &str, check: impl Fn(&Value) -> bool + Send + Sync + 'static) -> Self {
+        self.predicates.push((pointer.to_string(), Arc::new(check)));
+        self
+    }
+
+    pub fn with_buffer(mut self, capacity: usize, overflow: OverflowPolicy) -> Self {
+        self.capacity = capacity.max(1);
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Does `event` pass every filter?
+    fn matches(&self, event: &ChangeEvent) -> bool {
+        if self.key_glob.as_deref().is_some_and(|glob| !glob_matches(glob, &event.key)) {
+            return false;
+        }
+        if self.ops.as_ref().is_some_and(|ops| !ops.contains(&ChangeOp::of(&event.change))) {
+            return false;
+        }
+        if self.predicates.is_empty() {
+            return true;
+        }
+        let Change::Set { value } = &event.change else {
+            return false;
+        };
+        let Ok(document) = serde_json::from_str::<Value>(value) else {
+            return false;
+        };
+        self.predicates.iter().all(|(pointer, check)| document.pointer(pointer).is_some_and(|value| check(value)))
+    }
+}
+
+/// Does `text` match `glob`?
+fn glob_matches(glob: &str, text: &str) -> bool {
+    let (glob, text): (Vec<char>, Vec<char>) = (glob.chars().collect(), text.chars().collect());
+    let (mut g, mut t) = (0, 0);
+    // Last `*` seen, and the text position it is matched up to
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, t));
+                g += 1;
+            }
+            Some('?') => (g, t) = (g + 1, t + 1),
+            Some(&c) if c == text[t] => (g, t) = (g + 1, t + 1),
+            _ => match star {
+                // Let the last `*` take one more character
+                Some((star_g, star_t)) => {
+                    star = Some((star_g, star_t + 1));
+                    (g, t) = (star_g + 1, star_t + 1);
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+/// Watch counters
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WatchStats {
+    /// Events handed to the subscriber
+    pub delivered: u64,
+    /// Events the filters let through but `DropOldest` discarded
+    pub dropped: u64,
+    /// Events the filters left out
+    pub filtered: u64,
+    /// LSN the poller has read up to
+    pub lsn: u64,
+    /// LSN ranges cleared by checkpoints before the poller read them
+    pub missed: Vec<RangeInclusive<u64>>,
+}
+
+/// State shared by a watch's poller and its subscriber
+#[derive(Default)]
+struct Shared {
+    buffer: Mutex<VecDeque<ChangeEvent>>,
+    stats: Mutex<WatchStats>,
+    /// Set when the watch ended on an overflow
+    overflowed: Mutex<Option<usize>>,
+    /// An event was buffered or the watch ended
+    ready: Notify,
+    /// An event was taken from the buffer
+    space: Notify,
+}
+
+impl Shared {
+    /// Buffer `event` under `policy`; false if the watch must end
+    async fn push(&self, event: ChangeEvent, capacity: usize, policy: OverflowPolicy) -> bool {
+        loop {
+            {
+                let mut buffer = self.buffer.lock();
+                if buffer.len() < capacity {
+                    buffer.push_back(event);
+                    break;
+                }
+                match policy {
+                    OverflowPolicy::DropOldest => {
+                        buffer.pop_front();
+                        buffer.push_back(event);
+                        self.stats.lock().dropped += 1;
+                        break;
+                    }
+                    OverflowPolicy::Error => {
+                        *self.overflowed.lock() = Some(capacity);
+                        self.ready.notify_one();
+                        return false;
+                    }
+                    OverflowPolicy::Block => {}
+                }
+            }
+            self.space.notified().await;
+        }
+        self.ready.notify_one();
+        true
+    }
+
+    fn pop(&self) -> Option<ChangeEvent> {
+        let event = self.buffer.lock().pop_front()?;
+        self.stats.lock().delivered += 1;
+        self.space.notify_one();
+        Some(event)
+    }
+}
+
+/// A running watch; dropping it stops the poller
+pub struct Watch {
+    task: TaskHandle,
+    shared: Arc<Shared>,
+}
+
+impl Watch {
+    /// The next matching change, waiting for one if none is buffered
+    ///
+    /// Fails with `WatchOverflow` once an `Error`-policy watch has
+    /// overflowed and its buffer is drained.
+    pub async fn recv(&self) -> Result<ChangeEvent> {
+        loop {
+            if let Some(event) = self.try_recv()? {
+                return Ok(event);
+            }
+            self.shared.ready.notified().await;
+        }
+    }
+
+    /// The next buffered change, without waiting
+    pub fn try_recv(&self) -> Result<Option<ChangeEvent>> {
+        if let Some(event) = self.shared.pop() {
+            return Ok(Some(event));
+        }
+        match *self.shared.overflowed.lock() {
+            Some(capacity) => Err(IronCladError::WatchOverflow { capacity }.into()),
+            None => Ok(None),
+        }
+    }
+
+    pub fn stats(&self) -> WatchStats {
+        self.shared.stats.lock().clone()
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl KVStore {
+    /// Subscribe to the changes committed from now on that pass `options`'
+    /// filters (see watch.rs)
+    pub fn watch(self: &Arc<Self>, options: WatchOptions) -> Result<Watch> {
+        if options.poll_interval.is_zero() {
+            anyhow::bail!("A watch needs a non-zero poll interval");
+        }
+        let shared = Arc::new(Shared::default());
+        let store = Arc::clone(self);
+        let task_shared = shared.clone();
+        let runtime = self.options().runtime.clone();
+        let mut lsn = self.wal().current_lsn();
+        task_shared.stats.lock().lsn = lsn;
+        let task = runtime.clone().spawn(async move {
+            loop {
+                match store.changes_since(lsn).await {
+                    Ok(batch) => {
+                        if let Some(missed) = batch.missed {
+                            warn!("WATCH: changes at LSN {}..={} were cleared before they were read", missed.start(), missed.end());
+                            task_shared.stats.lock().missed.push(missed);
+                        }
+                        for event in batch.events {
+                            if !options.matches(&event) {
+                                task_shared.stats.lock().filtered += 1;
+                                continue;
+                            }
+                            if !task_shared.push(event, options.capacity, options.overflow).await {
+                                warn!("WATCH: buffer of {} events overflowed, watch ended", options.capacity);
+                                return;
+                            }
+                        }
+                        lsn = batch.last_lsn;
+                        task_shared.stats.lock().lsn = lsn;
+                    }
+                    Err(e) => {
+                        debug!("WATCH: reading changes after LSN {} failed: {:#}", lsn, e);
+                        store.options().listeners.error("watch", &e);
+                    }
+                }
+                runtime.sleep(options.poll_interval).await;
+            }
+        });
+        Ok(Watch { task, shared })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(key: &str, change: Change) -> ChangeEvent {
+        ChangeEvent { lsn: 1, key: key.to_string(), change, at_ms: 0 }
+    }
+
+    #[test]
+    fn test_filters() {
+        assert!(glob_matches("orders/*", "orders/1"));
+        assert!(glob_matches("*/1?", "orders/12"));
+        assert!(glob_matches("a*b*c", "axxbyyc"));
+        assert!(!glob_matches("orders/*", "users/1"));
+        assert!(!glob_matches("a?", "a"));
+
+        let options = WatchOptions::new()
+            .with_key_glob("orders/*")
+            .with_ops(&[ChangeOp::Set, ChangeOp::Delete])
+            .with_value_matching("/status", |status| status == "shipped");
+        let set = |value: &str| Change::Set { value: value.to_string() };
+        assert!(options.matches(&event("orders/1", set(r#"{"status":"shipped"}"#))));
+        assert!(!options.matches(&event("orders/1", set(r#"{"status":"new"}"#))));
+        assert!(!options.matches(&event("orders/1", set("not json"))));
+        assert!(!options.matches(&event("users/1", set(r#"{"status":"shipped"}"#))));
+        // A predicate needs a whole value, which a delete doesn't carry
+        assert!(!options.matches(&event("orders/1", Change::Delete)));
+        assert!(WatchOptions::new().with_ops(&[ChangeOp::Delete]).matches(&event("orders/1", Change::Delete)));
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policies() {
+        let shared = Shared::default();
+        for i in 0..3 {
+            assert!(shared.push(event(&i.to_string(), Change::Delete), 2, OverflowPolicy::DropOldest).await);
+        }
+        assert_eq!(shared.pop().unwrap().key, "1");
+        assert_eq!(shared.stats.lock().dropped, 1);
+
+        assert!(shared.push(event("3", Change::Delete), 2, OverflowPolicy::Error).await);
+        assert!(!shared.push(event("4", Change::Delete), 2, OverflowPolicy::Error).await);
+        assert_eq!(*shared.overflowed.lock(), Some(2));
+    }
+}
@@ -1,7 +1,7 @@
-/// Integration tests for the complete IronClad-DB system
-/// 
-/// These tests verify end-to-end functionality across all layers:
-/// AzureDisk, BufferPool, WAL, and KVStore
+//! Integration tests for the complete IronClad-DB system
+//! 
+//! These tests verify end-to-end functionality across all layers:
+//! AzureDisk, BufferPool, WAL, and KVStore
 
 use ironclad_db::{KVStore, BufferPool, WAL, WalEntry};
 
@@ -81,15 +81,9 @@ async fn test_wal_integration() {
     let wal = WAL::new("test-conn", "test-container", "test-wal").await.unwrap();
     
     // Test logging operations
-    wal.append_entry(WalEntry::Set {
-        key: "k1".to_string(),
-        value: "v1".to_string(),
-    }).await.unwrap();
+    wal.append_entry(WalEntry::set("k1", "v1")).await.unwrap();
     
-    wal.append_entry(WalEntry::Set {
-        key: "k2".to_string(),
-        value: "v2".to_string(),
-    }).await.unwrap();
+    wal.append_entry(WalEntry::set("k2", "v2")).await.unwrap();
     
     wal.append_entry(WalEntry::Delete {
         key: "k1".to_string(),